@@ -0,0 +1,103 @@
+//! The lookup and serialization work behind `/getCountry` and `/countries`, run against
+//! the built-in dataset without going through HTTP.
+//!
+//! Run with `cargo bench --bench handlers`, adding `--features mimalloc` or
+//! `--features jemalloc` to measure with that allocator instead. Before timing, it
+//! prints how many heap allocations each `/getCountry` case makes.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rusty_currency::{dataset, lookup, SelectedAllocator};
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        SelectedAllocator.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        SelectedAllocator.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn get_country(dataset: &dataset::Dataset, based: &str) -> bytes::Bytes {
+    lookup::to_json(&lookup::lookup_countries(
+        dataset,
+        based,
+        dataset::DEFAULT_LANGUAGE,
+    ))
+}
+
+fn handlers(c: &mut Criterion) {
+    let dataset = dataset::Dataset::builtin();
+    let mut names: Vec<_> = dataset
+        .records()
+        .map(|record| record.name.clone())
+        .collect();
+    names.sort();
+    let hundred = names
+        .iter()
+        .cycle()
+        .take(100)
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(",");
+    let unknown_heavy = (0..100)
+        .map(|i| match i % 10 {
+            0 => names[i].clone(),
+            _ => format!("Atlantis {}", i),
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    // Build every record up front so the first iteration is not an outlier
+    get_country(&dataset, &hundred);
+    for (case, based) in [
+        ("single", "Japan"),
+        ("3 countries", "Japan,usa,France"),
+        ("100 countries", hundred.as_str()),
+    ] {
+        let before = ALLOCATIONS.load(Ordering::Relaxed);
+        black_box(get_country(&dataset, based));
+        eprintln!(
+            "getCountry {}: {} allocations",
+            case,
+            ALLOCATIONS.load(Ordering::Relaxed) - before
+        );
+    }
+
+    c.bench_function("getCountry single", |b| {
+        b.iter(|| get_country(&dataset, black_box("Japan")))
+    });
+    c.bench_function("getCountry 3 countries", |b| {
+        b.iter(|| get_country(&dataset, black_box("Japan,usa,France")))
+    });
+    c.bench_function("getCountry 100 countries", |b| {
+        b.iter(|| get_country(&dataset, black_box(&hundred)))
+    });
+    c.bench_function("getCountry 90% unknown", |b| {
+        b.iter(|| get_country(&dataset, black_box(&unknown_heavy)))
+    });
+    c.bench_function("countries full catalog", |b| {
+        b.iter(|| {
+            let results = lookup::canonical_countries(&dataset, dataset::DEFAULT_LANGUAGE);
+            lookup::to_json(&lookup::CountryResponse {
+                results,
+                unmatched: Vec::new(),
+                truncated: false,
+            })
+        })
+    });
+}
+
+criterion_group!(benches, handlers);
+criterion_main!(benches);
@@ -0,0 +1,156 @@
+//! Built-in name lookups through the generated perfect hash map versus the runtime
+//! `HashMap` the dataset used to build at startup, and a 50-country `/getCountry` query
+//! answered with copied strings versus borrowed ones.
+//!
+//! Run with `cargo bench --bench lookup`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rusty_currency::{
+    bounding_box::BoundingBox,
+    codes::{Alpha2, CurrencyCode},
+    dataset::{AliasKind, Continent},
+};
+use serde::Serialize;
+use std::{borrow::Cow, collections::HashMap};
+
+#[allow(dead_code)]
+pub struct BuiltinCountry {
+    name: &'static str,
+    aliases: &'static [(&'static str, AliasKind)],
+    localized_names: &'static [(&'static str, &'static str)],
+    alpha2: Alpha2,
+    alpha3: &'static str,
+    numeric: &'static str,
+    flag: &'static str,
+    currency_code: CurrencyCode,
+    phone_code: &'static str,
+    bounding_box: Option<BoundingBox>,
+    tld: Option<&'static str>,
+    continent: Option<Continent>,
+    languages: &'static [&'static str],
+    timezones: &'static [&'static str],
+    latlng: Option<[f64; 2]>,
+    borders: &'static [Alpha2],
+}
+
+include!(concat!(env!("OUT_DIR"), "/builtin_countries.rs"));
+
+static BUILTIN_INDEX: phf::Map<&'static str, usize> =
+    include!(concat!(env!("OUT_DIR"), "/builtin_index.rs"));
+
+const NAMES: [&str; 4] = ["japan", "united states", "côte d'ivoire", "atlantis"];
+
+fn lookup(c: &mut Criterion) {
+    let runtime: HashMap<String, usize> = BUILTIN_INDEX
+        .entries()
+        .map(|(name, &position)| (name.to_string(), position))
+        .collect();
+
+    c.bench_function("phf lookup", |b| {
+        b.iter(|| {
+            for name in NAMES {
+                black_box(BUILTIN_INDEX.get(black_box(name)));
+            }
+        })
+    });
+    c.bench_function("runtime HashMap lookup", |b| {
+        b.iter(|| {
+            for name in NAMES {
+                black_box(runtime.get(black_box(name)));
+            }
+        })
+    });
+    c.bench_function("runtime HashMap build", |b| {
+        b.iter(|| {
+            black_box(
+                BUILTIN_INDEX
+                    .entries()
+                    .map(|(name, &position)| (name.to_string(), position))
+                    .collect::<HashMap<_, _>>(),
+            )
+        })
+    });
+}
+
+// The response entry as it was before lookups stopped copying strings
+#[derive(Serialize)]
+struct OwnedInfo {
+    country: String,
+    #[serde(rename = "localizedName")]
+    localized_name: String,
+    flag: String,
+    #[serde(rename = "currencyCode")]
+    currency_code: String,
+    #[serde(rename = "phoneCode")]
+    phone_code: String,
+}
+
+#[derive(Serialize)]
+struct BorrowedInfo<'a> {
+    country: Cow<'a, str>,
+    #[serde(rename = "localizedName")]
+    localized_name: Cow<'a, str>,
+    flag: Cow<'a, str>,
+    #[serde(rename = "currencyCode")]
+    currency_code: Cow<'a, str>,
+    #[serde(rename = "phoneCode")]
+    phone_code: Cow<'a, str>,
+}
+
+fn lookup_key(name: &str) -> Cow<'_, str> {
+    if name.bytes().any(|byte| byte.is_ascii_uppercase()) || !name.is_ascii() {
+        Cow::Owned(name.to_lowercase())
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
+fn query(c: &mut Criterion) {
+    let based = BUILTIN_COUNTRIES[..50]
+        .iter()
+        .map(|country| country.name)
+        .collect::<Vec<_>>()
+        .join(",");
+
+    c.bench_function("50-country query, copied strings", |b| {
+        b.iter(|| {
+            let results: Vec<_> = black_box(&based)
+                .split(',')
+                .map(str::trim)
+                .filter_map(|name| {
+                    let country = &BUILTIN_COUNTRIES[*BUILTIN_INDEX.get(&name.to_lowercase())?];
+                    Some(OwnedInfo {
+                        country: name.to_string(),
+                        localized_name: country.localized_names[0].1.to_string(),
+                        flag: country.flag.to_string(),
+                        currency_code: country.currency_code.to_string(),
+                        phone_code: country.phone_code.to_string(),
+                    })
+                })
+                .collect();
+            serde_json::to_vec(&results).unwrap()
+        })
+    });
+    c.bench_function("50-country query, borrowed strings", |b| {
+        b.iter(|| {
+            let results: Vec<_> = black_box(&based)
+                .split(',')
+                .map(str::trim)
+                .filter_map(|name| {
+                    let country = &BUILTIN_COUNTRIES[*BUILTIN_INDEX.get(&*lookup_key(name))?];
+                    Some(BorrowedInfo {
+                        country: Cow::Borrowed(name),
+                        localized_name: Cow::Borrowed(country.localized_names[0].1),
+                        flag: Cow::Borrowed(country.flag),
+                        currency_code: Cow::Borrowed(country.currency_code.as_str()),
+                        phone_code: Cow::Borrowed(country.phone_code),
+                    })
+                })
+                .collect();
+            serde_json::to_vec(&results).unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, lookup, query);
+criterion_main!(benches);
@@ -0,0 +1,709 @@
+//! Generates the built-in country dataset from the vendored ISO 3166-1 list in
+//! `data/iso3166.csv`, so the binary needs no data files at runtime.
+//!
+//! Each row is `alpha2,alpha3,numeric,name,currencyCode,phoneCode,tld,continent,aliases`
+//! where `tld` is the country-code top-level domain, empty for territories without one,
+//! `continent` is named as it is served, such as `northAmerica`, and `aliases` is a
+//! `|`-separated list. An alias may end in `:historical` for a former name
+//! or `:colloquial` for an informal one; others are current. The flag emoji is derived
+//! from the alpha-2 code.
+//! Names and aliases are normalized with `src/normalize.rs`, the same code lookups use,
+//! and deduplicated per entry; a name or alias claimed by two entries fails the build.
+//!
+//! Besides the table itself this writes compile-time perfect hash maps from every name
+//! and alias, and from every alpha-2 code, to the country's position in the table, so
+//! built-in lookups need no runtime index.
+//!
+//! Display names come from `data/country_names.csv`, one row per alpha-2 code with a
+//! column per language named in its header. Every country needs a row with every name.
+//!
+//! Bounding boxes come from `data/bounding_boxes.csv`, one
+//! `alpha2,minLat,minLng,maxLat,maxLng` row per country, in degrees. They were computed
+//! from OpenStreetMap boundaries (© OpenStreetMap contributors, ODbL), with overseas
+//! territories that have their own code left out of their sovereign's box. A country
+//! without a row has no box.
+//!
+//! Official languages come from `data/languages.csv`, one `alpha2,languages` row per
+//! country with the ISO 639 codes `|`-separated, most used first. Every country needs a
+//! row, which is empty for the uninhabited ones, and every code a name in
+//! `src/language.rs`.
+//!
+//! Time zones come from `data/timezones.csv`, one `alpha2,timezones` row per country with
+//! the IANA zone names `|`-separated, as the tz database's `zone.tab` lists them. Every
+//! country needs a row, which is empty for the uninhabited ones.
+//!
+//! A representative point inside each country comes from `data/latlng.csv`, one
+//! `alpha2,lat,lng` row per country in degrees; a country without a row has none. Land
+//! borders come from `data/borders.csv`, one `alpha2,borders` row per country with its
+//! neighbours' alpha-2 codes `|`-separated, empty for islands. Every country needs a row,
+//! and a border must be listed from both sides.
+//!
+//! Flag images are the vendored SVGs in `assets/flags`, one `<alpha2>.svg` per country;
+//! a country without one fails the build. They are compiled in through a map from
+//! alpha-2 code to file contents.
+//!
+//! The offline exchange rates come from `data/rates.csv`, one
+//! `currency,rateToUsd,asOf` row per currency with the date the rate was taken. Rows are
+//! compiled in as they are written; `src/rate_snapshot.rs` parses and validates them the
+//! way it does live rates.
+
+use std::{collections::HashMap, env, fmt::Write as _, fs, path::Path};
+
+#[path = "src/language.rs"]
+#[allow(dead_code)]
+mod language;
+#[path = "src/normalize.rs"]
+mod normalize;
+
+const DATASET_PATH: &str = "data/iso3166.csv";
+const FIELD_COUNT: usize = 9;
+// Continent column value -> `Continent` variant
+const CONTINENTS: [(&str, &str); 7] = [
+    ("africa", "Africa"),
+    ("antarctica", "Antarctica"),
+    ("asia", "Asia"),
+    ("europe", "Europe"),
+    ("northAmerica", "NorthAmerica"),
+    ("oceania", "Oceania"),
+    ("southAmerica", "SouthAmerica"),
+];
+const NAMES_PATH: &str = "data/country_names.csv";
+const BOXES_PATH: &str = "data/bounding_boxes.csv";
+const LANGUAGES_PATH: &str = "data/languages.csv";
+const TIMEZONES_PATH: &str = "data/timezones.csv";
+const LATLNG_PATH: &str = "data/latlng.csv";
+const BORDERS_PATH: &str = "data/borders.csv";
+const FLAGS_DIR: &str = "assets/flags";
+const RATES_PATH: &str = "data/rates.csv";
+const RATES_HEADER: &str = "currency,rateToUsd,asOf";
+#[cfg(feature = "ffi")]
+const FFI_HEADER_PATH: &str = "include/rusty_currency.h";
+#[cfg(feature = "grpc")]
+const PROTO_PATH: &str = "proto/country.proto";
+
+struct Row {
+    alpha2: String,
+    alpha3: String,
+    numeric: String,
+    name: String,
+    currency_code: String,
+    phone_code: String,
+    tld: String,
+    // `Continent` variant
+    continent: &'static str,
+    // (alias, AliasKind variant)
+    aliases: Vec<(String, &'static str)>,
+    localized_names: Vec<(String, String)>,
+    // (minLat, minLng, maxLat, maxLng)
+    bounding_box: Option<[f64; 4]>,
+    // ISO 639 codes
+    languages: Vec<String>,
+    // IANA time zone names
+    timezones: Vec<String>,
+    // [lat, lng]
+    latlng: Option<[f64; 2]>,
+    // Alpha-2 codes, sorted
+    borders: Vec<String>,
+}
+
+fn is_upper_ascii(value: &str, length: usize) -> bool {
+    value.len() == length && value.bytes().all(|byte| byte.is_ascii_uppercase())
+}
+
+fn flag_from_alpha2(alpha2: &str) -> String {
+    alpha2
+        .chars()
+        .map(|letter| {
+            char::from_u32(0x1F1E6 + (letter as u32 - 'A' as u32))
+                .expect("alpha-2 letters map to regional indicator symbols")
+        })
+        .collect()
+}
+
+fn parse_row(line_number: usize, line: &str) -> Row {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    if fields.len() != FIELD_COUNT {
+        panic!(
+            "{}:{}: expected {} fields, found {}",
+            DATASET_PATH,
+            line_number,
+            FIELD_COUNT,
+            fields.len()
+        );
+    }
+
+    let row = Row {
+        alpha2: fields[0].to_string(),
+        alpha3: fields[1].to_string(),
+        numeric: fields[2].to_string(),
+        name: normalize::normalize(fields[3]).into_owned(),
+        currency_code: fields[4].to_string(),
+        phone_code: fields[5].to_string(),
+        tld: fields[6].to_string(),
+        continent: CONTINENTS
+            .iter()
+            .find(|(value, _)| *value == fields[7])
+            .map_or("", |(_, variant)| variant),
+        aliases: fields[8]
+            .split('|')
+            .filter(|alias| !alias.trim().is_empty())
+            .map(|alias| parse_alias(line_number, alias))
+            .collect(),
+        localized_names: Vec::new(),
+        bounding_box: None,
+        languages: Vec::new(),
+        timezones: Vec::new(),
+        latlng: None,
+        borders: Vec::new(),
+    };
+
+    let checks = [
+        (is_upper_ascii(&row.alpha2, 2), "alpha-2 code"),
+        (is_upper_ascii(&row.alpha3, 3), "alpha-3 code"),
+        (
+            row.numeric.len() == 3 && row.numeric.bytes().all(|byte| byte.is_ascii_digit()),
+            "numeric code",
+        ),
+        (!row.name.is_empty(), "name"),
+        (is_upper_ascii(&row.currency_code, 3), "currency code"),
+        (
+            row.phone_code.starts_with('+') && row.phone_code.len() > 1,
+            "phone code",
+        ),
+        (
+            row.tld.is_empty()
+                || row.tld.strip_prefix('.').is_some_and(|label| {
+                    !label.is_empty() && label.bytes().all(|byte| byte.is_ascii_lowercase())
+                }),
+            "TLD",
+        ),
+        (!row.continent.is_empty(), "continent"),
+    ];
+    for (valid, field) in checks {
+        if !valid {
+            panic!(
+                "{}:{}: invalid {} in {:?}",
+                DATASET_PATH, line_number, field, line
+            );
+        }
+    }
+
+    row
+}
+
+// "burma:historical" -> ("burma", "Historical")
+fn parse_alias(line_number: usize, alias: &str) -> (String, &'static str) {
+    let (name, kind) = match alias.rsplit_once(':') {
+        Some((name, "historical")) => (name, "Historical"),
+        Some((name, "colloquial")) => (name, "Colloquial"),
+        Some((_, kind)) => panic!(
+            "{}:{}: unknown alias kind {:?} in {:?}",
+            DATASET_PATH, line_number, kind, alias
+        ),
+        None => (alias, "Current"),
+    };
+    (normalize::normalize(name).into_owned(), kind)
+}
+
+// Alpha-2 code -> (language, name) pairs in header order
+fn parse_names(source: &str) -> HashMap<String, Vec<(String, String)>> {
+    let mut lines = source.lines().enumerate();
+    let (_, header) = lines
+        .next()
+        .unwrap_or_else(|| panic!("{}: missing header", NAMES_PATH));
+    let languages: Vec<&str> = header.split(',').map(str::trim).skip(1).collect();
+    if languages.is_empty()
+        || languages.iter().any(|language| {
+            language.is_empty() || !language.bytes().all(|byte| byte.is_ascii_lowercase())
+        })
+    {
+        panic!("{}:1: invalid language header {:?}", NAMES_PATH, header);
+    }
+
+    let mut names = HashMap::new();
+    for (line_index, line) in lines {
+        let line_number = line_index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if fields.len() != languages.len() + 1 || fields.iter().any(|field| field.is_empty()) {
+            panic!(
+                "{}:{}: expected an alpha-2 code and {} non-empty names in {:?}",
+                NAMES_PATH,
+                line_number,
+                languages.len(),
+                line
+            );
+        }
+        let localized = languages
+            .iter()
+            .zip(&fields[1..])
+            .map(|(language, name)| (language.to_string(), name.to_string()))
+            .collect();
+        if names.insert(fields[0].to_string(), localized).is_some() {
+            panic!(
+                "{}:{}: alpha-2 code {} listed twice",
+                NAMES_PATH, line_number, fields[0]
+            );
+        }
+    }
+    names
+}
+
+// Alpha-2 code -> (minLat, minLng, maxLat, maxLng)
+fn parse_boxes(source: &str) -> HashMap<String, [f64; 4]> {
+    let mut boxes = HashMap::new();
+    for (line_index, line) in source.lines().enumerate().skip(1) {
+        let line_number = line_index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let invalid = || -> ! {
+            panic!(
+                "{}:{}: expected an alpha-2 code and four coordinates in {:?}",
+                BOXES_PATH, line_number, line
+            )
+        };
+        if fields.len() != 5 || !is_upper_ascii(fields[0], 2) {
+            invalid();
+        }
+        let mut coordinates = [0.0; 4];
+        for (coordinate, field) in coordinates.iter_mut().zip(&fields[1..]) {
+            *coordinate = field.parse().unwrap_or_else(|_| invalid());
+        }
+        let [min_lat, min_lng, max_lat, max_lng] = coordinates;
+        let latitudes = -90.0..=90.0;
+        let longitudes = -180.0..=180.0;
+        if !latitudes.contains(&min_lat)
+            || !latitudes.contains(&max_lat)
+            || !longitudes.contains(&min_lng)
+            || !longitudes.contains(&max_lng)
+            || min_lat > max_lat
+        {
+            panic!(
+                "{}:{}: coordinates out of range in {:?}",
+                BOXES_PATH, line_number, line
+            );
+        }
+        if boxes.insert(fields[0].to_string(), coordinates).is_some() {
+            panic!(
+                "{}:{}: alpha-2 code {} listed twice",
+                BOXES_PATH, line_number, fields[0]
+            );
+        }
+    }
+    boxes
+}
+
+fn parse_points(source: &str) -> HashMap<String, [f64; 2]> {
+    let mut points = HashMap::new();
+    for (line_index, line) in source.lines().enumerate().skip(1) {
+        let line_number = line_index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let invalid = || -> ! {
+            panic!(
+                "{}:{}: expected an alpha-2 code, a latitude and a longitude in {:?}",
+                LATLNG_PATH, line_number, line
+            )
+        };
+        if fields.len() != 3 || !is_upper_ascii(fields[0], 2) {
+            invalid();
+        }
+        let lat: f64 = fields[1].parse().unwrap_or_else(|_| invalid());
+        let lng: f64 = fields[2].parse().unwrap_or_else(|_| invalid());
+        if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lng) {
+            panic!(
+                "{}:{}: coordinates out of range in {:?}",
+                LATLNG_PATH, line_number, line
+            );
+        }
+        if points.insert(fields[0].to_string(), [lat, lng]).is_some() {
+            panic!(
+                "{}:{}: alpha-2 code {} listed twice",
+                LATLNG_PATH, line_number, fields[0]
+            );
+        }
+    }
+    points
+}
+
+// Alpha-2 code -> the `|`-separated values of its `alpha2,values` row, each of which
+// `check` returns a complaint about if it is invalid
+fn parse_lists(
+    path: &str,
+    source: &str,
+    check: impl Fn(&str) -> Option<String>,
+) -> HashMap<String, Vec<String>> {
+    let mut lists = HashMap::new();
+    for (line_index, line) in source.lines().enumerate().skip(1) {
+        let line_number = line_index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some((alpha2, values)) = line.split_once(',') else {
+            panic!(
+                "{}:{}: expected an alpha-2 code and values in {:?}",
+                path, line_number, line
+            );
+        };
+        let values: Vec<String> = values
+            .split('|')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+            .collect();
+        for value in &values {
+            if let Some(complaint) = check(value) {
+                panic!("{}:{}: {}", path, line_number, complaint);
+            }
+        }
+        if lists.insert(alpha2.trim().to_string(), values).is_some() {
+            panic!(
+                "{}:{}: alpha-2 code {} listed twice",
+                path, line_number, alpha2
+            );
+        }
+    }
+    lists
+}
+
+fn check_language(code: &str) -> Option<String> {
+    language::name(code)
+        .is_none()
+        .then(|| format!("language {:?} has no name in src/language.rs", code))
+}
+
+fn check_alpha2(code: &str) -> Option<String> {
+    (!is_upper_ascii(code, 2)).then(|| format!("{:?} is not an alpha-2 code", code))
+}
+
+// The shape of a tz database name such as `America/Argentina/Buenos_Aires`; whether the
+// zone exists is left to the tests, which have chrono-tz
+fn check_timezone(zone: &str) -> Option<String> {
+    let valid = zone.contains('/')
+        && zone.split('/').all(|part| {
+            !part.is_empty()
+                && part
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || b"_-+".contains(&byte))
+        });
+    (!valid).then(|| format!("{:?} is not a time zone name", zone))
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", DATASET_PATH);
+    println!("cargo:rerun-if-changed={}", NAMES_PATH);
+    println!("cargo:rerun-if-changed={}", BOXES_PATH);
+    println!("cargo:rerun-if-changed={}", LANGUAGES_PATH);
+    println!("cargo:rerun-if-changed=src/language.rs");
+    println!("cargo:rerun-if-changed={}", TIMEZONES_PATH);
+    println!("cargo:rerun-if-changed={}", LATLNG_PATH);
+    println!("cargo:rerun-if-changed={}", BORDERS_PATH);
+    println!("cargo:rerun-if-changed={}", FLAGS_DIR);
+    println!("cargo:rerun-if-changed={}", RATES_PATH);
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/normalize.rs");
+
+    #[cfg(feature = "ffi")]
+    write_ffi_header();
+    #[cfg(feature = "grpc")]
+    compile_protos();
+
+    let source = fs::read_to_string(DATASET_PATH)
+        .unwrap_or_else(|error| panic!("Failed to read {}: {}", DATASET_PATH, error));
+
+    let names_source = fs::read_to_string(NAMES_PATH)
+        .unwrap_or_else(|error| panic!("Failed to read {}: {}", NAMES_PATH, error));
+    let mut localized_names = parse_names(&names_source);
+
+    let boxes_source = fs::read_to_string(BOXES_PATH)
+        .unwrap_or_else(|error| panic!("Failed to read {}: {}", BOXES_PATH, error));
+    let mut bounding_boxes = parse_boxes(&boxes_source);
+
+    let languages_source = fs::read_to_string(LANGUAGES_PATH)
+        .unwrap_or_else(|error| panic!("Failed to read {}: {}", LANGUAGES_PATH, error));
+    let mut languages = parse_lists(LANGUAGES_PATH, &languages_source, check_language);
+
+    let timezones_source = fs::read_to_string(TIMEZONES_PATH)
+        .unwrap_or_else(|error| panic!("Failed to read {}: {}", TIMEZONES_PATH, error));
+    let mut timezones = parse_lists(TIMEZONES_PATH, &timezones_source, check_timezone);
+
+    let latlng_source = fs::read_to_string(LATLNG_PATH)
+        .unwrap_or_else(|error| panic!("Failed to read {}: {}", LATLNG_PATH, error));
+    let mut points = parse_points(&latlng_source);
+
+    let borders_source = fs::read_to_string(BORDERS_PATH)
+        .unwrap_or_else(|error| panic!("Failed to read {}: {}", BORDERS_PATH, error));
+    let mut borders = parse_lists(BORDERS_PATH, &borders_source, check_alpha2);
+
+    let mut rows: Vec<Row> = Vec::new();
+    // Lookup name or alpha-2 code -> line that claimed it
+    let mut claimed_names: HashMap<String, usize> = HashMap::new();
+    let mut claimed_codes: HashMap<String, usize> = HashMap::new();
+
+    for (line_index, line) in source.lines().enumerate().skip(1) {
+        let line_number = line_index + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut row = parse_row(line_number, line);
+
+        if let Some(previous) = claimed_codes.insert(row.alpha2.clone(), line_number) {
+            panic!(
+                "{}:{}: alpha-2 code {} already used on line {}",
+                DATASET_PATH, line_number, row.alpha2, previous
+            );
+        }
+
+        let mut names = vec![row.name.clone()];
+        let mut aliases = Vec::new();
+        for (alias, kind) in std::mem::take(&mut row.aliases) {
+            if !alias.is_empty() && !names.contains(&alias) {
+                names.push(alias.clone());
+                aliases.push((alias, kind));
+            }
+        }
+        for name in &names {
+            if let Some(previous) = claimed_names.insert(name.clone(), line_number) {
+                panic!(
+                    "{}:{}: name or alias {:?} already used on line {}",
+                    DATASET_PATH, line_number, name, previous
+                );
+            }
+        }
+        row.aliases = aliases;
+        row.localized_names = localized_names.remove(&row.alpha2).unwrap_or_else(|| {
+            panic!(
+                "{}: no names for {} (line {} of {})",
+                NAMES_PATH, row.alpha2, line_number, DATASET_PATH
+            )
+        });
+        row.bounding_box = bounding_boxes.remove(&row.alpha2);
+        row.languages = languages.remove(&row.alpha2).unwrap_or_else(|| {
+            panic!(
+                "{}: no languages for {} (line {} of {})",
+                LANGUAGES_PATH, row.alpha2, line_number, DATASET_PATH
+            )
+        });
+        row.timezones = timezones.remove(&row.alpha2).unwrap_or_else(|| {
+            panic!(
+                "{}: no time zones for {} (line {} of {})",
+                TIMEZONES_PATH, row.alpha2, line_number, DATASET_PATH
+            )
+        });
+        row.latlng = points.remove(&row.alpha2);
+        row.borders = borders.remove(&row.alpha2).unwrap_or_else(|| {
+            panic!(
+                "{}: no borders for {} (line {} of {})",
+                BORDERS_PATH, row.alpha2, line_number, DATASET_PATH
+            )
+        });
+        row.borders.sort();
+
+        rows.push(row);
+    }
+    if let Some(alpha2) = localized_names.keys().next() {
+        panic!("{}: names for unknown alpha-2 code {}", NAMES_PATH, alpha2);
+    }
+    if let Some(alpha2) = bounding_boxes.keys().next() {
+        panic!("{}: box for unknown alpha-2 code {}", BOXES_PATH, alpha2);
+    }
+    if let Some(alpha2) = languages.keys().next() {
+        panic!(
+            "{}: languages for unknown alpha-2 code {}",
+            LANGUAGES_PATH, alpha2
+        );
+    }
+    if let Some(alpha2) = timezones.keys().next() {
+        panic!(
+            "{}: time zones for unknown alpha-2 code {}",
+            TIMEZONES_PATH, alpha2
+        );
+    }
+    if let Some(alpha2) = points.keys().next() {
+        panic!("{}: point for unknown alpha-2 code {}", LATLNG_PATH, alpha2);
+    }
+    if let Some(alpha2) = borders.keys().next() {
+        panic!(
+            "{}: borders for unknown alpha-2 code {}",
+            BORDERS_PATH, alpha2
+        );
+    }
+    // A border is listed from both sides
+    for row in &rows {
+        for neighbour in &row.borders {
+            let listed_back = rows
+                .iter()
+                .find(|other| other.alpha2 == *neighbour)
+                .map(|other| other.borders.contains(&row.alpha2));
+            match listed_back {
+                Some(true) => {}
+                Some(false) => panic!(
+                    "{}: {} borders {}, but not the other way round",
+                    BORDERS_PATH, row.alpha2, neighbour
+                ),
+                None => panic!(
+                    "{}: {} borders unknown alpha-2 code {}",
+                    BORDERS_PATH, row.alpha2, neighbour
+                ),
+            }
+        }
+    }
+
+    let mut generated = format!(
+        "pub const BUILTIN_COUNT: usize = {};\n\npub static BUILTIN_COUNTRIES: [BuiltinCountry; BUILTIN_COUNT] = [\n",
+        rows.len()
+    );
+    for row in &rows {
+        writeln!(
+            generated,
+            "    BuiltinCountry {{ name: {:?}, aliases: &[{}], localized_names: &{:?}, alpha2: Alpha2::from_static({:?}), alpha3: {:?}, numeric: {:?}, flag: {:?}, currency_code: CurrencyCode::from_static({:?}), phone_code: {:?}, bounding_box: {}, tld: {:?}, continent: Some(Continent::{}), languages: &{:?}, timezones: &{:?}, latlng: {:?}, borders: &[{}] }},",
+            row.name,
+            row.aliases
+                .iter()
+                .map(|(alias, kind)| format!("({:?}, AliasKind::{})", alias, kind))
+                .collect::<Vec<_>>()
+                .join(", "),
+            row.localized_names,
+            row.alpha2,
+            row.alpha3,
+            row.numeric,
+            flag_from_alpha2(&row.alpha2),
+            row.currency_code,
+            row.phone_code,
+            match row.bounding_box {
+                Some([min_lat, min_lng, max_lat, max_lng]) => format!(
+                    "Some(BoundingBox {{ min_lat: {:?}, min_lng: {:?}, max_lat: {:?}, max_lng: {:?} }})",
+                    min_lat, min_lng, max_lat, max_lng
+                ),
+                None => String::from("None"),
+            },
+            (!row.tld.is_empty()).then_some(&row.tld),
+            row.continent,
+            row.languages,
+            row.timezones,
+            row.latlng,
+            row.borders
+                .iter()
+                .map(|code| format!("Alpha2::from_static({:?})", code))
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+        .unwrap();
+    }
+    generated.push_str("];\n");
+
+    // Names are unique across rows (checked above), so every key is inserted once
+    let mut index = phf_codegen::Map::new();
+    for (position, row) in rows.iter().enumerate() {
+        for name in std::iter::once(&row.name).chain(row.aliases.iter().map(|(alias, _)| alias)) {
+            index.entry(name.as_str(), &position.to_string());
+        }
+    }
+
+    // Alpha-2 codes are unique across rows (checked above) too
+    let mut codes = phf_codegen::Map::new();
+    for (position, row) in rows.iter().enumerate() {
+        codes.entry(row.alpha2.as_str(), &position.to_string());
+    }
+
+    // Included rather than read here, so the images are only compiled into the builds
+    // whose code refers to them
+    let mut flag_images = phf_codegen::Map::new();
+    for row in &rows {
+        let path = format!("{}/{}.svg", FLAGS_DIR, row.alpha2);
+        if !Path::new(&path).is_file() {
+            panic!("{}: no flag image for {}", path, row.alpha2);
+        }
+        flag_images.entry(
+            row.alpha2.as_str(),
+            &format!(
+                "include_str!(concat!(env!(\"CARGO_MANIFEST_DIR\"), {:?}))",
+                format!("/{}", path)
+            ),
+        );
+    }
+
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR is set by cargo");
+    fs::write(Path::new(&out_dir).join("builtin_countries.rs"), generated)
+        .expect("Failed to write generated country dataset");
+    fs::write(
+        Path::new(&out_dir).join("builtin_index.rs"),
+        index.build().to_string(),
+    )
+    .expect("Failed to write generated country index");
+    fs::write(
+        Path::new(&out_dir).join("builtin_codes.rs"),
+        codes.build().to_string(),
+    )
+    .expect("Failed to write generated code index");
+    fs::write(
+        Path::new(&out_dir).join("flag_images.rs"),
+        flag_images.build().to_string(),
+    )
+    .expect("Failed to write generated flag image index");
+    fs::write(
+        Path::new(&out_dir).join("rate_snapshot.rs"),
+        rate_snapshot(),
+    )
+    .expect("Failed to write generated rate snapshot");
+}
+
+// `data/rates.csv` as a slice of (currency, rateToUsd, asOf) rows, unparsed
+fn rate_snapshot() -> String {
+    let source = fs::read_to_string(RATES_PATH)
+        .unwrap_or_else(|error| panic!("Failed to read {}: {}", RATES_PATH, error));
+    let mut lines = source.lines().enumerate();
+    match lines.next() {
+        Some((_, header)) if header.trim() == RATES_HEADER => {}
+        _ => panic!("{}: expected the header {:?}", RATES_PATH, RATES_HEADER),
+    }
+    let mut generated = String::from("&[\n");
+    for (index, line) in lines {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [currency, rate, as_of] = fields[..] else {
+            panic!(
+                "{}:{}: expected 3 fields, found {}",
+                RATES_PATH,
+                index + 1,
+                fields.len()
+            );
+        };
+        writeln!(generated, "    ({:?}, {:?}, {:?}),", currency, rate, as_of).unwrap();
+    }
+    generated.push(']');
+    generated
+}
+
+// The C header for `src/ffi.rs`, kept in the repository so C callers need no Rust
+// toolchain to read it; configured by `cbindgen.toml`
+#[cfg(feature = "ffi")]
+fn write_ffi_header() {
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    let crate_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is set by cargo");
+    cbindgen::generate(&crate_dir)
+        .expect("Failed to generate the C header")
+        .write_to_file(Path::new(&crate_dir).join(FFI_HEADER_PATH));
+}
+
+// The gRPC service and messages, compiled with a vendored protoc so building needs no
+// system protobuf install
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    println!("cargo:rerun-if-changed={}", PROTO_PATH);
+
+    let protoc = protoc_bin_vendored::protoc_bin_path().expect("No vendored protoc for this host");
+    env::set_var("PROTOC", protoc);
+    tonic_build::compile_protos(PROTO_PATH)
+        .unwrap_or_else(|error| panic!("Failed to compile {}: {}", PROTO_PATH, error));
+}
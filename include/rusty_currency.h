@@ -0,0 +1,33 @@
+#ifndef RUSTY_CURRENCY_H
+#define RUSTY_CURRENCY_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Looks up `name` and returns its `/getCountry` result as a NUL-terminated JSON object,
+ * or null when `name` is null, is not valid UTF-8 or matches no country.
+ *
+ * # Safety
+ *
+ * `name` must be null or point to a NUL-terminated string that stays valid for the
+ * duration of the call. A non-null result must be passed to [`ric_free`] exactly once.
+ */
+char *ric_lookup_json(const char *name);
+
+/**
+ * Releases a string returned by [`ric_lookup_json`]; does nothing when `json` is null.
+ *
+ * # Safety
+ *
+ * `json` must be null or a pointer returned by [`ric_lookup_json`] that has not been
+ * freed yet.
+ */
+void ric_free(char *json);
+
+#endif  /* RUSTY_CURRENCY_H */
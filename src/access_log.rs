@@ -0,0 +1,112 @@
+//! One log line per request, with enough of them left out to keep busy servers quiet.
+//!
+//! Requests are logged as `tracing` events with the target [`TARGET`]. Failed requests,
+//! answered with anything but a 2xx status, and those slower than the slow threshold are
+//! always logged. Of the rest, requests to an excluded path, by default the health checks
+//! load balancers send every few seconds, are left out, and one in `sample_rate` of the
+//! others is picked at random to be logged. Every request left out counts towards
+//! `rustincountry_access_log_skipped_total` on `/metrics`, by reason.
+
+use axum::{extract::FromRef, http::StatusCode};
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use std::{
+    num::NonZeroU32,
+    sync::{atomic::Ordering, Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use crate::{client_ip::TrustedProxies, metrics::Metrics, AppState};
+
+/// The `tracing` target of the events, for filtering them in or out, as in
+/// `RUST_LOG=info,rustincountry::access=off`.
+pub const TARGET: &str = "rustincountry::access";
+
+/// How long a request may take before it is logged whatever the sample rate.
+pub const DEFAULT_SLOW_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// The paths whose successful requests are not logged by default.
+pub const DEFAULT_EXCLUDED_PATHS: [&str; 1] = ["/healthz"];
+
+#[derive(Debug, Clone)]
+pub struct AccessLogConfig {
+    // Log one in this many successful requests; 1 logs them all
+    pub sample_rate: NonZeroU32,
+    // Requests that take longer are logged however they are sampled
+    pub slow_threshold: Duration,
+    // Paths, matched exactly, whose successful requests are never logged
+    pub excluded_paths: Vec<String>,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        AccessLogConfig {
+            sample_rate: NonZeroU32::MIN,
+            slow_threshold: DEFAULT_SLOW_THRESHOLD,
+            excluded_paths: DEFAULT_EXCLUDED_PATHS.map(String::from).to_vec(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AccessLog {
+    config: AccessLogConfig,
+    rng: Mutex<SmallRng>,
+    metrics: Arc<Metrics>,
+    // For the client address of each line
+    trusted_proxies: TrustedProxies,
+}
+
+impl AccessLog {
+    // Counts skipped requests in the metrics of `state`, and logs the client `state`'s
+    // trusted proxies forward for
+    pub fn new(config: AccessLogConfig, state: &AppState) -> Self {
+        AccessLog {
+            config,
+            rng: Mutex::new(SmallRng::from_entropy()),
+            metrics: state.metrics.clone(),
+            trusted_proxies: state.trusted_proxies.clone(),
+        }
+    }
+
+    // Samples with `rng` instead of one seeded at random
+    pub fn with_rng(mut self, rng: SmallRng) -> Self {
+        self.rng = Mutex::new(rng);
+        self
+    }
+
+    // Whether the request to `path` answered with `status` after `elapsed` is logged;
+    // counts it as skipped when not
+    pub fn should_log(&self, path: &str, status: StatusCode, elapsed: Duration) -> bool {
+        if !status.is_success() || elapsed > self.config.slow_threshold {
+            return true;
+        }
+        if self
+            .config
+            .excluded_paths
+            .iter()
+            .any(|excluded| excluded == path)
+        {
+            self.metrics
+                .access_log_excluded
+                .fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        let sampled = self
+            .rng
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .gen_ratio(1, self.config.sample_rate.get());
+        if !sampled {
+            self.metrics
+                .access_log_sampled_out
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        sampled
+    }
+}
+
+impl FromRef<Arc<AccessLog>> for TrustedProxies {
+    fn from_ref(log: &Arc<AccessLog>) -> Self {
+        log.trusted_proxies.clone()
+    }
+}
@@ -0,0 +1,228 @@
+//! Credentials for the `/admin` endpoints, checked apart from any API key.
+//!
+//! The endpoints accept `Authorization: Bearer <token>` with `--admin-token`, HTTP Basic
+//! auth with `--admin-user` and `--admin-password-hash`, or either when both are set. The
+//! password is kept only as an Argon2 hash in PHC form, as `rusty_currency hash-password`
+//! prints it, and a presented password is checked against it off the async runtime, as
+//! hashing is slow on purpose. At most one password per available CPU is checked at
+//! once; a Basic request beyond that is turned away rather than queued, so a flood of
+//! guesses cannot tie up the blocking pool. Scheme names match in any case, as RFC 7235
+//! has them. Requests without valid credentials are answered `401` with a
+//! `WWW-Authenticate` challenge for each accepted scheme.
+
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::http::{header::AUTHORIZATION, HeaderMap, HeaderValue};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::{fmt, num::NonZeroUsize, sync::Arc, thread};
+use tokio::sync::Semaphore;
+
+use crate::audit::ADMIN_PRINCIPAL;
+
+// Names what the credentials protect in the Basic challenge
+const BASIC_CHALLENGE: &str = "Basic realm=\"rustincountry admin\", charset=\"UTF-8\"";
+const BEARER_CHALLENGE: &str = "Bearer";
+const SALT_LENGTH: usize = 16;
+
+/// Who an admin request authenticated as: the Basic username, or
+/// [`ADMIN_PRINCIPAL`] for the bearer token. Put in the request's extensions for the
+/// audit log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminPrincipal(pub String);
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct BasicAuth {
+    username: String,
+    // An Argon2 hash in PHC form, checked to parse when made
+    password_hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminAuthError {
+    // Not a PHC string, as in `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`
+    InvalidHash(String),
+    // A PHC string of another algorithm, such as bcrypt or scrypt
+    NotArgon2(String),
+}
+
+impl fmt::Display for AdminAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdminAuthError::InvalidHash(reason) => {
+                write!(f, "The admin password hash is not a PHC string: {}", reason)
+            }
+            AdminAuthError::NotArgon2(algorithm) => write!(
+                f,
+                "The admin password hash uses {}; expected argon2id, argon2i or argon2d",
+                algorithm
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AdminAuthError {}
+
+/// A Basic password left unchecked because as many as there are CPUs are already being
+/// checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifierBusy;
+
+// Leaves the hash out, as it can be attacked offline
+impl fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BasicAuth")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BasicAuth {
+    pub fn new(username: String, password_hash: String) -> Result<BasicAuth, AdminAuthError> {
+        check_hash(&password_hash)?;
+        Ok(BasicAuth {
+            username,
+            password_hash,
+        })
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    // Whether `username` and `password` are these credentials; slow, by design
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        let hash = PasswordHash::new(&self.password_hash).expect("checked when made");
+        let password_matches = Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok();
+        // Both are checked whatever the username, so timing does not reveal it
+        tokens_match(username, &self.username) & password_matches
+    }
+}
+
+// A `--admin-password-hash`, checked to be an Argon2 hash in PHC form
+pub fn parse_password_hash(value: &str) -> Result<String, String> {
+    check_hash(value)
+        .map(|()| value.to_string())
+        .map_err(|error| error.to_string())
+}
+
+fn check_hash(password_hash: &str) -> Result<(), AdminAuthError> {
+    let hash = PasswordHash::new(password_hash)
+        .map_err(|error| AdminAuthError::InvalidHash(error.to_string()))?;
+    if !hash.algorithm.as_str().starts_with("argon2") {
+        return Err(AdminAuthError::NotArgon2(hash.algorithm.to_string()));
+    }
+    Ok(())
+}
+
+/// The Argon2id hash of `password`, with a random salt, for `--admin-password-hash`.
+pub fn hash_password(password: &str) -> String {
+    let salt = SaltString::encode_b64(&rand::random::<[u8; SALT_LENGTH]>())
+        .expect("the salt is a valid length");
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("default parameters hash any password")
+        .to_string()
+}
+
+/// What the `/admin` endpoints accept; at least one of the two is set.
+#[derive(Debug, Clone)]
+pub struct AdminCredentials {
+    pub token: Option<String>,
+    pub basic: Option<BasicAuth>,
+    // One permit per password checked at once
+    pub(crate) verifiers: Arc<Semaphore>,
+}
+
+impl AdminCredentials {
+    pub fn new(token: Option<String>, basic: Option<BasicAuth>) -> AdminCredentials {
+        let verifiers = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        AdminCredentials {
+            token,
+            basic,
+            verifiers: Arc::new(Semaphore::new(verifiers)),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.token.is_none() && self.basic.is_none()
+    }
+
+    // Who `headers` authenticate as, or None without credentials these accept. Fails
+    // when every permit to check a Basic password is taken.
+    pub async fn check(
+        self: Arc<Self>,
+        headers: &HeaderMap,
+    ) -> Result<Option<AdminPrincipal>, VerifierBusy> {
+        let Some(authorization) = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return Ok(None);
+        };
+        if let (Some(token), Some(presented)) = (&self.token, strip_scheme(authorization, "Bearer"))
+        {
+            return Ok(
+                tokens_match(presented, token).then(|| AdminPrincipal(ADMIN_PRINCIPAL.to_string()))
+            );
+        }
+        let (Some((username, password)), Some(basic)) = (
+            strip_scheme(authorization, "Basic").and_then(decode_basic),
+            self.basic.clone(),
+        ) else {
+            return Ok(None);
+        };
+        let permit = Arc::clone(&self.verifiers)
+            .try_acquire_owned()
+            .map_err(|_| VerifierBusy)?;
+        Ok(tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            basic
+                .verify(&username, &password)
+                .then_some(AdminPrincipal(basic.username))
+        })
+        .await
+        .unwrap_or(None))
+    }
+
+    // The `WWW-Authenticate` challenges of a `401`, one per accepted scheme
+    pub fn challenges(&self) -> Vec<HeaderValue> {
+        let mut challenges = Vec::new();
+        if self.basic.is_some() {
+            challenges.push(HeaderValue::from_static(BASIC_CHALLENGE));
+        }
+        if self.token.is_some() {
+            challenges.push(HeaderValue::from_static(BEARER_CHALLENGE));
+        }
+        challenges
+    }
+}
+
+// The credentials of `authorization` if its scheme is `scheme`, in any case
+fn strip_scheme<'a>(authorization: &'a str, scheme: &str) -> Option<&'a str> {
+    let (presented, credentials) = authorization.split_once(' ')?;
+    presented
+        .eq_ignore_ascii_case(scheme)
+        .then(|| credentials.trim())
+}
+
+// The username and password of Basic credentials, `base64(username:password)`
+fn decode_basic(credentials: &str) -> Option<(String, String)> {
+    let decoded = STANDARD.decode(credentials.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+// Compares every byte whatever the first mismatch, so timing does not reveal the token
+fn tokens_match(presented: &str, token: &str) -> bool {
+    presented.len() == token.len()
+        && presented
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
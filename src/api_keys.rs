@@ -0,0 +1,391 @@
+//! Named API keys, each with a tier's per-minute rate limit and daily request quota.
+//!
+//! The keys and tiers are read from a `--api-keys keys.toml` file:
+//!
+//! ```toml
+//! [tiers.anonymous]
+//! requests-per-minute = 30
+//!
+//! [tiers.free]
+//! requests-per-minute = 60
+//! daily-quota = 10000
+//!
+//! [tiers.partner]
+//! requests-per-minute = 6000
+//!
+//! [[keys]]
+//! name = "acme"
+//! key = "k_3f9a1c27d4e8"
+//! tier = "partner"
+//! ```
+//!
+//! A request sends its key as `X-API-Key` and is counted against the key; a key the file
+//! does not list is refused with `INVALID_API_KEY`. A request without one is counted
+//! against its client address under the `anonymous` tier, and not limited here when the
+//! file has no such tier. Requests are counted in fixed one-minute windows that start
+//! with a client's first request, and every counted response says how the window stands
+//! in `X-RateLimit-Limit`, `X-RateLimit-Remaining` and `X-RateLimit-Reset`, the seconds
+//! until it ends. Quotas start over at midnight UTC.
+
+use axum::extract::FromRef;
+use chrono::{DateTime, Days, NaiveDate, TimeDelta, Utc};
+use serde::Deserialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs, io,
+    net::IpAddr,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cache::{Clock, SystemClock},
+    client_ip::TrustedProxies,
+    AppState,
+};
+
+/// The request header a key is sent in.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// The tier requests without a key are limited by, when the file has it.
+pub const ANONYMOUS_TIER: &str = "anonymous";
+
+const WINDOW: Duration = Duration::from_secs(60);
+// Clients past this many are forgotten once they have nothing left to count
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Tier {
+    pub requests_per_minute: NonZeroU32,
+    // Most requests a client is served per UTC day; unlimited when not set
+    #[serde(default)]
+    pub daily_quota: Option<NonZeroU32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    // Names the key in logs without giving it away
+    pub name: String,
+    pub tier: Tier,
+}
+
+/// The name of the key a request was admitted with, as a request extension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyName(pub String);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApiKeys {
+    // By the key itself
+    keys: HashMap<String, ApiKey>,
+    anonymous: Option<Tier>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeysFile {
+    #[serde(default)]
+    tiers: HashMap<String, Tier>,
+    #[serde(default)]
+    keys: Vec<KeyEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyEntry {
+    name: String,
+    key: String,
+    tier: String,
+}
+
+#[derive(Debug)]
+pub enum ApiKeysError {
+    // The file could not be read
+    Read {
+        path: PathBuf,
+        error: io::Error,
+    },
+    // The file is not TOML, or not shaped as a keys file
+    Parse {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    UnknownTier {
+        name: String,
+        tier: String,
+    },
+    EmptyKey(String),
+    DuplicateKey(String),
+    DuplicateName(String),
+}
+
+impl fmt::Display for ApiKeysError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiKeysError::Read { path, error } => {
+                write!(f, "Failed to read {}: {}", path.display(), error)
+            }
+            ApiKeysError::Parse { path, error } => {
+                write!(
+                    f,
+                    "{} is not a valid API keys file: {}",
+                    path.display(),
+                    error
+                )
+            }
+            ApiKeysError::UnknownTier { name, tier } => {
+                write!(
+                    f,
+                    "The key {:?} has the tier {:?}, which is not defined",
+                    name, tier
+                )
+            }
+            ApiKeysError::EmptyKey(name) => write!(f, "The key {:?} is empty", name),
+            ApiKeysError::DuplicateKey(name) => {
+                write!(f, "The key {:?} is the same as another key", name)
+            }
+            ApiKeysError::DuplicateName(name) => {
+                write!(f, "More than one key is named {:?}", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ApiKeysError {}
+
+impl ApiKeys {
+    pub fn load(path: &Path) -> Result<ApiKeys, ApiKeysError> {
+        let text = fs::read_to_string(path).map_err(|error| ApiKeysError::Read {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        let file = toml::from_str::<KeysFile>(&text).map_err(|error| ApiKeysError::Parse {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        ApiKeys::new(
+            file.tiers,
+            file.keys
+                .into_iter()
+                .map(|entry| (entry.name, entry.key, entry.tier)),
+        )
+    }
+
+    // The keys of (name, key, tier name) entries, each tier one of `tiers`
+    pub fn new(
+        tiers: HashMap<String, Tier>,
+        entries: impl IntoIterator<Item = (String, String, String)>,
+    ) -> Result<ApiKeys, ApiKeysError> {
+        let mut keys = HashMap::new();
+        let mut names = HashSet::new();
+        for (name, key, tier) in entries {
+            let Some(&tier) = tiers.get(&tier) else {
+                return Err(ApiKeysError::UnknownTier { name, tier });
+            };
+            if key.is_empty() {
+                return Err(ApiKeysError::EmptyKey(name));
+            }
+            if !names.insert(name.clone()) {
+                return Err(ApiKeysError::DuplicateName(name));
+            }
+            if keys.contains_key(&key) {
+                return Err(ApiKeysError::DuplicateKey(name));
+            }
+            keys.insert(key, ApiKey { name, tier });
+        }
+        Ok(ApiKeys {
+            keys,
+            anonymous: tiers.get(ANONYMOUS_TIER).copied(),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&ApiKey> {
+        self.keys.get(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+// Whose requests are counted together
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Client {
+    // By name, so the keys themselves are not kept twice
+    Key(String),
+    Address(IpAddr),
+    // Requests without a key whose address is not known
+    Unknown,
+}
+
+#[derive(Debug)]
+struct Usage {
+    window_started: Instant,
+    served_in_window: u32,
+    day: NaiveDate,
+    served_today: u32,
+    // Whether `served_today` counts towards a quota, and so must be kept all day
+    has_quota: bool,
+}
+
+/// Where a client's current window stands, for the `X-RateLimit-*` headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Allowance {
+    pub limit: u32,
+    pub remaining: u32,
+    // Until the window ends
+    pub reset: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Allowed(Allowance),
+    // The window is spent
+    RateLimited(Allowance),
+    // The day's quota is spent; it starts over after `reset`
+    QuotaExhausted { reset: Duration },
+    // The key is not one of the file's
+    UnknownKey,
+    // No key was sent and there is no anonymous tier
+    Unlimited,
+}
+
+pub struct ApiKeyLimiter {
+    keys: ApiKeys,
+    // For the client address of requests without a key
+    trusted_proxies: TrustedProxies,
+    clock: Arc<dyn Clock>,
+    // An instant of `clock` and the time it stood for, to tell which day it is
+    epoch: (Instant, DateTime<Utc>),
+    clients: Mutex<HashMap<Client, Usage>>,
+}
+
+impl fmt::Debug for ApiKeyLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApiKeyLimiter")
+            .field("keys", &self.keys.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ApiKeyLimiter {
+    // Tells clients apart by the addresses `state`'s trusted proxies forward for
+    pub fn new(keys: ApiKeys, state: &AppState) -> Self {
+        let clock = Arc::new(SystemClock);
+        ApiKeyLimiter {
+            keys,
+            trusted_proxies: state.trusted_proxies.clone(),
+            epoch: (clock.now(), Utc::now()),
+            clock,
+            clients: Mutex::default(),
+        }
+    }
+
+    // Counts with `clock`, whose current instant stands for `now`
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>, now: DateTime<Utc>) -> Self {
+        self.epoch = (clock.now(), now);
+        self.clock = clock;
+        self
+    }
+
+    pub fn key(&self, key: &str) -> Option<&ApiKey> {
+        self.keys.get(key)
+    }
+
+    // Counts one request that sent `key`, or came from `address` without one
+    pub fn admit(&self, key: Option<&str>, address: Option<IpAddr>) -> Admission {
+        let (client, tier) = match key {
+            Some(key) => match self.keys.get(key) {
+                Some(key) => (Client::Key(key.name.clone()), key.tier),
+                None => return Admission::UnknownKey,
+            },
+            None => match self.keys.anonymous {
+                Some(tier) => (address.map_or(Client::Unknown, Client::Address), tier),
+                None => return Admission::Unlimited,
+            },
+        };
+        let now = self.clock.now();
+        let (today, until_tomorrow) = self.day(now);
+        let mut clients = self.clients.lock().unwrap_or_else(PoisonError::into_inner);
+        if clients.len() >= MAX_TRACKED_CLIENTS && !clients.contains_key(&client) {
+            forget_idle(&mut clients, now, today);
+        }
+        let usage = clients.entry(client).or_insert(Usage {
+            window_started: now,
+            served_in_window: 0,
+            day: today,
+            served_today: 0,
+            has_quota: tier.daily_quota.is_some(),
+        });
+        if now.duration_since(usage.window_started) >= WINDOW {
+            usage.window_started = now;
+            usage.served_in_window = 0;
+        }
+        if usage.day != today {
+            usage.day = today;
+            usage.served_today = 0;
+        }
+        if tier
+            .daily_quota
+            .is_some_and(|quota| usage.served_today >= quota.get())
+        {
+            return Admission::QuotaExhausted {
+                reset: until_tomorrow,
+            };
+        }
+        let limit = tier.requests_per_minute.get();
+        let reset = WINDOW.saturating_sub(now.duration_since(usage.window_started));
+        if usage.served_in_window >= limit {
+            return Admission::RateLimited(Allowance {
+                limit,
+                remaining: 0,
+                reset,
+            });
+        }
+        usage.served_in_window += 1;
+        usage.served_today += 1;
+        Admission::Allowed(Allowance {
+            limit,
+            remaining: limit - usage.served_in_window,
+            reset,
+        })
+    }
+
+    // The UTC day `now` falls on, and how long until the next one
+    fn day(&self, now: Instant) -> (NaiveDate, Duration) {
+        let (epoch, epoch_time) = self.epoch;
+        let elapsed =
+            TimeDelta::from_std(now.saturating_duration_since(epoch)).unwrap_or(TimeDelta::MAX);
+        let time = epoch_time
+            .checked_add_signed(elapsed)
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        let today = time.date_naive();
+        let tomorrow = today
+            .checked_add_days(Days::new(1))
+            .and_then(|tomorrow| tomorrow.and_hms_opt(0, 0, 0))
+            .map(|midnight| midnight.and_utc());
+        let until_tomorrow = tomorrow
+            .and_then(|midnight| (midnight - time).to_std().ok())
+            .unwrap_or_default();
+        (today, until_tomorrow)
+    }
+}
+
+impl FromRef<Arc<ApiKeyLimiter>> for TrustedProxies {
+    fn from_ref(limiter: &Arc<ApiKeyLimiter>) -> Self {
+        limiter.trusted_proxies.clone()
+    }
+}
+
+// Drops clients whose window is over and who have no quota to keep count of today
+fn forget_idle(clients: &mut HashMap<Client, Usage>, now: Instant, today: NaiveDate) {
+    clients.retain(|_, usage| {
+        now.duration_since(usage.window_started) < WINDOW || (usage.has_quota && usage.day == today)
+    });
+}
@@ -0,0 +1,245 @@
+//! An append-only record of every change made to the served dataset, for `--audit-log`.
+//!
+//! Each admin add, update and delete, and each reload of the data file, database or
+//! remote document, appends one JSON line to the file: when it happened, the request and
+//! principal behind it, what was done, and a summary of the country before and after, or
+//! of what a reload changed. The line is written before the change is made and flushed
+//! straight away; if it cannot be written, the change is not made, so history is never
+//! lost silently. The same record is logged as a `tracing` event with the target
+//! [`TARGET`].
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
+
+use crate::{dataset::DatasetDiff, CountryRecord};
+
+/// The `tracing` target of the events, for filtering them in or out.
+pub const TARGET: &str = "rustincountry::audit";
+
+/// The principal of changes made with the admin token; Basic auth records the username.
+pub const ADMIN_PRINCIPAL: &str = "admin";
+
+/// The principal of reloads, which the server makes on its own.
+pub const SERVER_PRINCIPAL: &str = "server";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Add,
+    Update,
+    Delete,
+    Reload,
+}
+
+impl fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AuditAction::Add => "add",
+            AuditAction::Update => "update",
+            AuditAction::Delete => "delete",
+            AuditAction::Reload => "reload",
+        })
+    }
+}
+
+// Enough of a country to tell what an edit did, without the whole record
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CountrySummary {
+    pub name: String,
+    pub flag: String,
+    pub currency_code: String,
+    pub phone_code: String,
+    pub aliases: usize,
+}
+
+impl From<&CountryRecord> for CountrySummary {
+    fn from(record: &CountryRecord) -> Self {
+        CountrySummary {
+            name: record.name.clone(),
+            flag: record.flag.clone(),
+            currency_code: record.currency_code.to_string(),
+            phone_code: record.phone_code.clone(),
+            aliases: record.aliases.len(),
+        }
+    }
+}
+
+// The canonical names a reload added, removed and changed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Changes {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl From<&DatasetDiff> for Changes {
+    fn from(diff: &DatasetDiff) -> Self {
+        Changes {
+            added: diff.added.clone(),
+            removed: diff.removed.clone(),
+            changed: diff.changed.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    // The `X-Request-Id` of the admin request; reloads have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    // Who made the change: the admin's Basic username, `ADMIN_PRINCIPAL` for the admin
+    // token, or `SERVER_PRINCIPAL`
+    pub principal: String,
+    // The name of the API key the admin request also sent, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    pub action: AuditAction,
+    // The canonical name of the country an admin request changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<CountrySummary>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<CountrySummary>,
+    // Where a reload read the data from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changes: Option<Changes>,
+}
+
+impl AuditRecord {
+    // An admin change to one country by `principal`, `before` and `after` it
+    pub fn admin(
+        principal: String,
+        action: AuditAction,
+        before: Option<&CountryRecord>,
+        after: Option<&CountryRecord>,
+    ) -> Self {
+        AuditRecord {
+            timestamp: Utc::now(),
+            request_id: None,
+            principal,
+            api_key: None,
+            action,
+            country: after.or(before).map(|record| record.name.clone()),
+            before: before.map(CountrySummary::from),
+            after: after.map(CountrySummary::from),
+            source: None,
+            changes: None,
+        }
+    }
+
+    // A reload from `source` that changed `diff`
+    pub fn reload(source: String, diff: &DatasetDiff) -> Self {
+        AuditRecord {
+            timestamp: Utc::now(),
+            request_id: None,
+            principal: SERVER_PRINCIPAL.to_string(),
+            api_key: None,
+            action: AuditAction::Reload,
+            country: None,
+            before: None,
+            after: None,
+            source: Some(source),
+            changes: Some(Changes::from(diff)),
+        }
+    }
+
+    pub fn with_request_id(mut self, request_id: Option<String>) -> Self {
+        self.request_id = request_id;
+        self
+    }
+
+    pub fn with_api_key(mut self, api_key: Option<String>) -> Self {
+        self.api_key = api_key;
+        self
+    }
+}
+
+#[derive(Debug)]
+pub struct AuditError {
+    pub path: PathBuf,
+    pub error: io::Error,
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to write the audit log {}: {}",
+            self.path.display(),
+            self.error
+        )
+    }
+}
+
+impl std::error::Error for AuditError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[derive(Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+    // Held for the whole write, so lines from concurrent changes never interleave
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    // Appends to the file at `path`, creating it if it does not exist
+    pub fn open(path: &Path) -> Result<AuditLog, AuditError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|error| AuditError {
+                path: path.to_path_buf(),
+                error,
+            })?;
+        Ok(AuditLog {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Writes `record` as one line and flushes it, then logs it
+    pub fn append(&self, record: &AuditRecord) -> Result<(), AuditError> {
+        let mut line = serde_json::to_string(record).expect("audit records serialize");
+        line.push('\n');
+        {
+            let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+            file.write_all(line.as_bytes())
+                .and_then(|()| file.flush())
+                .map_err(|error| AuditError {
+                    path: self.path.clone(),
+                    error,
+                })?;
+        }
+        tracing::info!(
+            target: TARGET,
+            action = %record.action,
+            principal = %record.principal,
+            country = record.country.as_deref().unwrap_or_default(),
+            request_id = record.request_id.as_deref().unwrap_or_default(),
+            record = line.trim_end(),
+            "Dataset changed"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+//! Per-country bounding boxes, for `/byCoordinates`.
+//!
+//! A box is the smallest latitude/longitude rectangle around a country's territory, so
+//! neighbouring boxes overlap wherever borders are not straight lines and a point can
+//! fall in several of them. The smallest box containing a point is the likeliest
+//! match. A box whose `minLng` is greater than its `maxLng` crosses the antimeridian,
+//! as those of Russia and Fiji do.
+
+use serde::{Deserialize, Serialize};
+
+// Mean radius of the Earth
+const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(deny_unknown_fields)]
+pub struct BoundingBox {
+    #[serde(rename = "minLat")]
+    pub min_lat: f64,
+    #[serde(rename = "minLng")]
+    pub min_lng: f64,
+    #[serde(rename = "maxLat")]
+    pub max_lat: f64,
+    #[serde(rename = "maxLng")]
+    pub max_lng: f64,
+}
+
+impl BoundingBox {
+    pub fn crosses_antimeridian(&self) -> bool {
+        self.min_lng > self.max_lng
+    }
+
+    pub fn contains(&self, latitude: f64, longitude: f64) -> bool {
+        let within_longitudes = if self.crosses_antimeridian() {
+            longitude >= self.min_lng || longitude <= self.max_lng
+        } else {
+            (self.min_lng..=self.max_lng).contains(&longitude)
+        };
+        (self.min_lat..=self.max_lat).contains(&latitude) && within_longitudes
+    }
+
+    // In square kilometres on a spherical Earth, so a box near a pole is not ranked as
+    // large as one of the same span in degrees at the equator
+    pub fn area(&self) -> f64 {
+        let mut width = self.max_lng - self.min_lng;
+        if self.crosses_antimeridian() {
+            width += 360.0;
+        }
+        EARTH_RADIUS_KM.powi(2)
+            * width.to_radians()
+            * (self.max_lat.to_radians().sin() - self.min_lat.to_radians().sin())
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if !is_latitude(self.min_lat) || !is_latitude(self.max_lat) {
+            return Err("latitudes must be between -90 and 90");
+        }
+        if !is_longitude(self.min_lng) || !is_longitude(self.max_lng) {
+            return Err("longitudes must be between -180 and 180");
+        }
+        if self.min_lat > self.max_lat {
+            return Err("`minLat` must not be greater than `maxLat`");
+        }
+        Ok(())
+    }
+}
+
+// False for NaN, like every comparison with it
+pub fn is_latitude(value: f64) -> bool {
+    (-90.0..=90.0).contains(&value)
+}
+
+pub fn is_longitude(value: f64) -> bool {
+    (-180.0..=180.0).contains(&value)
+}
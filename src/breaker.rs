@@ -0,0 +1,258 @@
+//! A circuit breaker for the rate provider, so an outage costs a few timeouts rather than
+//! one per currency asked for.
+//!
+//! The breaker starts closed and lets every call through. After `failure_threshold`
+//! calls in a row fail it opens: calls are refused at once, without asking the provider,
+//! until `cooldown` has passed. It is then half-open and lets a single probe call
+//! through. If the probe succeeds the breaker closes again; if it fails the breaker opens
+//! for another cooldown.
+
+use std::{
+    fmt,
+    fmt::Write,
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::cache::{Clock, SystemClock};
+
+/// Failures in a row that open the breaker when `--rates-breaker-failures` is not given.
+pub const DEFAULT_FAILURE_THRESHOLD: NonZeroU32 = NonZeroU32::new(5).unwrap();
+
+/// How long the breaker stays open when `--rates-breaker-cooldown` is not given.
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed,
+    // Refusing calls until the cooldown passes
+    Open,
+    // Letting one probe call through
+    HalfOpen,
+}
+
+impl BreakerState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "halfOpen",
+        }
+    }
+}
+
+impl fmt::Display for BreakerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    consecutive_failures: u32,
+    // When the breaker last opened; None while closed
+    opened_at: Option<Instant>,
+    // Whether the half-open probe has been let through and not yet answered
+    probing: bool,
+}
+
+pub struct CircuitBreaker {
+    failure_threshold: NonZeroU32,
+    cooldown: Duration,
+    clock: Arc<dyn Clock>,
+    circuit: Mutex<Circuit>,
+    opened: AtomicU64,
+    short_circuited: AtomicU64,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_COOLDOWN)
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: NonZeroU32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            failure_threshold,
+            cooldown,
+            clock: Arc::new(SystemClock),
+            circuit: Mutex::new(Circuit::default()),
+            opened: AtomicU64::new(0),
+            short_circuited: AtomicU64::new(0),
+        }
+    }
+
+    // Reads the time from `clock` to tell when the cooldown is over
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn state(&self) -> BreakerState {
+        let circuit = self.circuit.lock().unwrap_or_else(PoisonError::into_inner);
+        self.state_of(&circuit)
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.circuit
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .consecutive_failures
+    }
+
+    // How long until an open breaker lets a probe through; None unless it is open
+    pub fn retry_in(&self) -> Option<Duration> {
+        let circuit = self.circuit.lock().unwrap_or_else(PoisonError::into_inner);
+        let elapsed = self.clock.now().duration_since(circuit.opened_at?);
+        self.cooldown
+            .checked_sub(elapsed)
+            .filter(|left| !left.is_zero())
+    }
+
+    // A permit to make a call, whose outcome it records, or None when the breaker is
+    // open or its half-open probe is already out
+    pub fn try_call(&self) -> Option<BreakerPermit<'_>> {
+        let mut circuit = self.circuit.lock().unwrap_or_else(PoisonError::into_inner);
+        let allowed = match self.state_of(&circuit) {
+            BreakerState::Closed => true,
+            BreakerState::Open => false,
+            BreakerState::HalfOpen => !std::mem::replace(&mut circuit.probing, true),
+        };
+        if !allowed {
+            self.short_circuited.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(BreakerPermit {
+            breaker: self,
+            recorded: false,
+        })
+    }
+
+    fn state_of(&self, circuit: &Circuit) -> BreakerState {
+        match circuit.opened_at {
+            None => BreakerState::Closed,
+            Some(opened_at) if self.clock.now().duration_since(opened_at) < self.cooldown => {
+                BreakerState::Open
+            }
+            Some(_) => BreakerState::HalfOpen,
+        }
+    }
+
+    fn record(&self, succeeded: bool) {
+        let mut circuit = self.circuit.lock().unwrap_or_else(PoisonError::into_inner);
+        circuit.probing = false;
+        if succeeded {
+            circuit.consecutive_failures = 0;
+            circuit.opened_at = None;
+            return;
+        }
+        circuit.consecutive_failures = circuit.consecutive_failures.saturating_add(1);
+        // A failed probe opens it for another cooldown
+        if circuit.opened_at.is_some()
+            || circuit.consecutive_failures >= self.failure_threshold.get()
+        {
+            circuit.opened_at = Some(self.clock.now());
+            self.opened.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // The breaker's state and counters in the Prometheus text format, for `/metrics`
+    pub fn render_metrics(&self) -> String {
+        let state = self.state();
+        let mut output = String::new();
+        writeln!(
+            output,
+            "# HELP rustincountry_rate_breaker_state Whether the rate provider's circuit breaker is in each state"
+        )
+        .unwrap();
+        writeln!(output, "# TYPE rustincountry_rate_breaker_state gauge").unwrap();
+        for each in [
+            BreakerState::Closed,
+            BreakerState::Open,
+            BreakerState::HalfOpen,
+        ] {
+            writeln!(
+                output,
+                "rustincountry_rate_breaker_state{{state=\"{}\"}} {}",
+                each,
+                u8::from(each == state)
+            )
+            .unwrap();
+        }
+        writeln!(
+            output,
+            "# HELP rustincountry_rate_breaker_opened_total Times the rate provider's circuit breaker opened"
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# TYPE rustincountry_rate_breaker_opened_total counter"
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "rustincountry_rate_breaker_opened_total {}",
+            self.opened.load(Ordering::Relaxed)
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# HELP rustincountry_rate_breaker_short_circuited_total Rate lookups answered without asking the provider, as the breaker was open"
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# TYPE rustincountry_rate_breaker_short_circuited_total counter"
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "rustincountry_rate_breaker_short_circuited_total {}",
+            self.short_circuited.load(Ordering::Relaxed)
+        )
+        .unwrap();
+        output
+    }
+}
+
+impl fmt::Debug for CircuitBreaker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("failure_threshold", &self.failure_threshold)
+            .field("cooldown", &self.cooldown)
+            .field("state", &self.state())
+            .finish_non_exhaustive()
+    }
+}
+
+// Records how the call it was given for went. Dropped without a record, say because the
+// call was cancelled, it lets the next call probe instead.
+#[derive(Debug)]
+pub struct BreakerPermit<'a> {
+    breaker: &'a CircuitBreaker,
+    recorded: bool,
+}
+
+impl BreakerPermit<'_> {
+    pub fn record(mut self, succeeded: bool) {
+        self.recorded = true;
+        self.breaker.record(succeeded);
+    }
+}
+
+impl Drop for BreakerPermit<'_> {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.breaker
+                .circuit
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .probing = false;
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Built-in country dataset generated by `build.rs` from `data/iso3166.csv`.
+//!
+//! Lookups go through a compile-time perfect hash map, and each country's
+//! `CountryRecord` is only built the first time it is needed, so serving the built-in
+//! data costs nothing at startup.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+};
+
+use crate::{
+    bounding_box::BoundingBox,
+    calling_code::CallingCodes,
+    codes::{Alpha2, CurrencyCode},
+    dataset::{Alias, AliasKind, Continent, CountryRecord, Dataset},
+};
+
+// The alpha-3 and numeric codes are only served through GraphQL's `geo`
+#[allow(dead_code)]
+#[derive(Debug)]
+pub struct BuiltinCountry {
+    pub name: &'static str,
+    pub aliases: &'static [(&'static str, AliasKind)],
+    // (language, display name) pairs
+    pub localized_names: &'static [(&'static str, &'static str)],
+    pub alpha2: Alpha2,
+    pub alpha3: &'static str,
+    pub numeric: &'static str,
+    pub flag: &'static str,
+    pub currency_code: CurrencyCode,
+    pub phone_code: &'static str,
+    pub bounding_box: Option<BoundingBox>,
+    pub tld: Option<&'static str>,
+    pub continent: Option<Continent>,
+    // ISO 639 codes, most used first
+    pub languages: &'static [&'static str],
+    // IANA time zones, as `zone.tab` lists them
+    pub timezones: &'static [&'static str],
+    // [latitude, longitude]
+    pub latlng: Option<[f64; 2]>,
+    pub borders: &'static [Alpha2],
+}
+
+include!(concat!(env!("OUT_DIR"), "/builtin_countries.rs"));
+
+// Every lowercase name and alias -> position in BUILTIN_COUNTRIES
+pub static BUILTIN_INDEX: phf::Map<&'static str, usize> =
+    include!(concat!(env!("OUT_DIR"), "/builtin_index.rs"));
+
+// Every alpha-2 code -> position in BUILTIN_COUNTRIES
+pub static BUILTIN_CODES: phf::Map<&'static str, usize> =
+    include!(concat!(env!("OUT_DIR"), "/builtin_codes.rs"));
+
+static RECORDS: [OnceLock<Arc<CountryRecord>>; BUILTIN_COUNT] =
+    [const { OnceLock::new() }; BUILTIN_COUNT];
+
+impl BuiltinCountry {
+    fn to_record(&self) -> CountryRecord {
+        CountryRecord {
+            name: self.name.to_string(),
+            aliases: self
+                .aliases
+                .iter()
+                .map(|&(name, kind)| Alias {
+                    name: name.to_string(),
+                    kind,
+                })
+                .collect(),
+            localized_names: self
+                .localized_names
+                .iter()
+                .map(|(language, name)| (language.to_string(), name.to_string()))
+                .collect(),
+            flag: self.flag.to_string(),
+            currency_code: self.currency_code,
+            phone_code: self.phone_code.to_string(),
+            bounding_box: self.bounding_box,
+            tld: self.tld.map(str::to_string),
+            continent: self.continent,
+            languages: self.languages.iter().map(|code| code.to_string()).collect(),
+            timezones: self.timezones.iter().map(|zone| zone.to_string()).collect(),
+            latlng: self.latlng,
+            borders: self.borders.to_vec(),
+        }
+    }
+}
+
+pub fn record(position: usize) -> &'static Arc<CountryRecord> {
+    RECORDS[position].get_or_init(|| Arc::new(BUILTIN_COUNTRIES[position].to_record()))
+}
+
+// Built from every record the first time a built-in dataset is searched by number
+pub fn calling_codes() -> &'static CallingCodes {
+    static CALLING_CODES: OnceLock<CallingCodes> = OnceLock::new();
+    CALLING_CODES.get_or_init(|| (0..BUILTIN_COUNT).map(record).collect())
+}
+
+// Built from every record the first time a built-in dataset is searched by TLD
+pub fn tlds() -> &'static HashMap<String, Vec<Arc<CountryRecord>>> {
+    static TLDS: OnceLock<HashMap<String, Vec<Arc<CountryRecord>>>> = OnceLock::new();
+    TLDS.get_or_init(|| {
+        let mut tlds: HashMap<String, Vec<_>> = HashMap::new();
+        for record in (0..BUILTIN_COUNT).map(record) {
+            if let Some(tld) = &record.tld {
+                tlds.entry(tld.clone())
+                    .or_default()
+                    .push(Arc::clone(record));
+            }
+        }
+        tlds
+    })
+}
+
+pub fn lookup(name: &str) -> Option<&'static Arc<CountryRecord>> {
+    BUILTIN_INDEX.get(name).map(|&position| record(position))
+}
+
+pub fn by_alpha2(alpha2: Alpha2) -> Option<&'static BuiltinCountry> {
+    BUILTIN_CODES
+        .get(alpha2.as_str())
+        .map(|&position| &BUILTIN_COUNTRIES[position])
+}
+
+// A linear scan; alpha-3 codes are only looked up one input at a time
+pub fn by_alpha3(alpha3: &str) -> Option<&'static BuiltinCountry> {
+    BUILTIN_COUNTRIES
+        .iter()
+        .find(|country| country.alpha3 == alpha3)
+}
+
+pub fn record_by_alpha2(alpha2: Alpha2) -> Option<&'static Arc<CountryRecord>> {
+    BUILTIN_CODES
+        .get(alpha2.as_str())
+        .map(|&position| record(position))
+}
+
+pub fn dataset() -> Dataset {
+    Dataset::builtin()
+}
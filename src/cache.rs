@@ -0,0 +1,275 @@
+//! Caches shared between the replicas of a deployment: exchange rates and, optionally,
+//! serialized `/getCountry` bodies.
+//!
+//! Both go through the `Cache` trait. `MemoryCache` keeps entries in this process and is
+//! the default; with the `redis` feature and `--redis-url`, `RedisCache` keeps them in
+//! Redis so every replica sees them. A cache never fails a request: an unreachable Redis
+//! is answered from memory, with a warning logged when it goes away and a note when it
+//! comes back.
+
+use axum::body::Bytes;
+use lru::LruCache;
+use std::{
+    fmt,
+    future::Future,
+    num::NonZeroUsize,
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+pub type CacheFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// Byte values under string keys, each kept for the TTL it was set with
+pub trait Cache: Send + Sync {
+    // The value under `key`, unless it is missing or expired
+    fn get<'a>(&'a self, key: &'a str) -> CacheFuture<'a, Option<Bytes>>;
+
+    fn set<'a>(&'a self, key: &'a str, value: Bytes, ttl: Duration) -> CacheFuture<'a, ()>;
+}
+
+impl fmt::Debug for dyn Cache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cache").finish_non_exhaustive()
+    }
+}
+
+// Where caches read the time from, so tests can move it forward
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Entries in this process, least recently used first out once `capacity` is reached.
+pub struct MemoryCache {
+    // None for a capacity of zero, which keeps nothing
+    entries: Option<Mutex<LruCache<String, (Instant, Bytes)>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: usize) -> Self {
+        MemoryCache {
+            entries: NonZeroUsize::new(capacity)
+                .map(|capacity| Mutex::new(LruCache::new(capacity))),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn lookup(&self, key: &str) -> Option<Bytes> {
+        let mut entries = self
+            .entries
+            .as_ref()?
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match entries.get(key) {
+            Some((expires, value)) if self.clock.now() < *expires => Some(value.clone()),
+            Some(_) => {
+                entries.pop(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn store(&self, key: &str, value: Bytes, ttl: Duration) {
+        if let Some(entries) = &self.entries {
+            let expires = self.clock.now() + ttl;
+            entries
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .put(key.to_string(), (expires, value));
+        }
+    }
+}
+
+impl Cache for MemoryCache {
+    fn get<'a>(&'a self, key: &'a str) -> CacheFuture<'a, Option<Bytes>> {
+        let value = self.lookup(key);
+        Box::pin(async move { value })
+    }
+
+    fn set<'a>(&'a self, key: &'a str, value: Bytes, ttl: Duration) -> CacheFuture<'a, ()> {
+        self.store(key, value, ttl);
+        Box::pin(async {})
+    }
+}
+
+impl fmt::Debug for MemoryCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let capacity = self.entries.as_ref().map(|entries| {
+            entries
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .cap()
+                .get()
+        });
+        f.debug_struct("MemoryCache")
+            .field("capacity", &capacity)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use self::redis_cache::RedisCache;
+
+// Namespaces this server's keys from anything else in the same Redis
+pub const REDIS_PREFIX: &str = "rustincountry:";
+
+#[cfg(feature = "redis")]
+mod redis_cache {
+    use redis::{aio::ConnectionManager, AsyncCommands};
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::health::{CheckFuture, DependencyCheck};
+
+    // Short, so requests are not held up for long while Redis is away
+    const CONNECTION_TIMEOUT: Duration = Duration::from_millis(500);
+    const RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+    /// Entries in Redis under `prefix`, answered from `fallback` while Redis cannot be
+    /// reached.
+    pub struct RedisCache {
+        connection: ConnectionManager,
+        // Put before every key, e.g. REDIS_PREFIX
+        prefix: String,
+        fallback: Arc<dyn Cache>,
+        // Whether the last command reached Redis, so each outage is logged once; shared
+        // by the caches made `with_fallback`, which use the same connection
+        available: Arc<AtomicBool>,
+    }
+
+    impl RedisCache {
+        // Connects lazily, so a Redis that is down at startup only fails the URL check
+        pub fn connect(
+            url: &str,
+            prefix: &str,
+            fallback: Arc<dyn Cache>,
+        ) -> redis::RedisResult<Self> {
+            let config = redis::aio::ConnectionManagerConfig::new()
+                .set_connection_timeout(Some(CONNECTION_TIMEOUT))
+                .set_response_timeout(Some(RESPONSE_TIMEOUT))
+                .set_number_of_retries(1);
+            let connection =
+                ConnectionManager::new_lazy_with_config(redis::Client::open(url)?, config)?;
+            Ok(RedisCache {
+                connection,
+                prefix: prefix.to_string(),
+                fallback,
+                available: Arc::new(AtomicBool::new(true)),
+            })
+        }
+
+        // The same connection and keys, falling back to `fallback` instead
+        pub fn with_fallback(&self, fallback: Arc<dyn Cache>) -> Self {
+            RedisCache {
+                connection: self.connection.clone(),
+                prefix: self.prefix.clone(),
+                fallback,
+                available: Arc::clone(&self.available),
+            }
+        }
+
+        // Whether Redis answers right now
+        pub async fn ping(&self) -> bool {
+            let mut connection = self.connection.clone();
+            let pong: redis::RedisResult<String> =
+                redis::cmd("PING").query_async(&mut connection).await;
+            self.record(pong.map(drop))
+        }
+
+        // Logs when Redis goes away or comes back; true while it is reachable
+        fn record(&self, outcome: redis::RedisResult<()>) -> bool {
+            match outcome {
+                Ok(()) => {
+                    if !self.available.swap(true, Ordering::Relaxed) {
+                        tracing::info!("Redis is reachable again, sharing the cache");
+                    }
+                    true
+                }
+                Err(error) => {
+                    if self.available.swap(false, Ordering::Relaxed) {
+                        tracing::warn!("Redis is unavailable, caching in memory: {}", error);
+                    }
+                    false
+                }
+            }
+        }
+    }
+
+    impl Cache for RedisCache {
+        fn get<'a>(&'a self, key: &'a str) -> CacheFuture<'a, Option<Bytes>> {
+            Box::pin(async move {
+                let mut connection = self.connection.clone();
+                let value: redis::RedisResult<Option<Vec<u8>>> =
+                    connection.get(format!("{}{}", self.prefix, key)).await;
+                match value {
+                    Ok(value) => {
+                        self.record(Ok(()));
+                        value.map(Bytes::from)
+                    }
+                    Err(error) => {
+                        self.record(Err(error));
+                        self.fallback.get(key).await
+                    }
+                }
+            })
+        }
+
+        fn set<'a>(&'a self, key: &'a str, value: Bytes, ttl: Duration) -> CacheFuture<'a, ()> {
+            Box::pin(async move {
+                let mut connection = self.connection.clone();
+                // Redis expires whole milliseconds, and never after zero
+                let milliseconds = ttl.as_millis().clamp(1, u64::MAX.into()) as u64;
+                let stored: redis::RedisResult<()> = connection
+                    .pset_ex(
+                        format!("{}{}", self.prefix, key),
+                        value.as_ref(),
+                        milliseconds,
+                    )
+                    .await;
+                if !self.record(stored) {
+                    self.fallback.set(key, value, ttl).await;
+                }
+            })
+        }
+    }
+
+    impl DependencyCheck for RedisCache {
+        fn check(&self) -> CheckFuture<'_> {
+            Box::pin(async move {
+                if self.ping().await {
+                    Ok(())
+                } else {
+                    Err(String::from("Redis does not answer; caching in memory"))
+                }
+            })
+        }
+    }
+
+    impl fmt::Debug for RedisCache {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("RedisCache")
+                .field("prefix", &self.prefix)
+                .field("available", &self.available.load(Ordering::Relaxed))
+                .finish_non_exhaustive()
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Reverse lookup of international calling codes, for `/byCallingCode`.
+//!
+//! Each dataset keeps a trie of its countries' `phoneCode` digits, updated as countries
+//! are added and removed. A number resolves by walking its digits once and keeping the
+//! deepest node that has countries, so `+1 242 …` reaches the Bahamas' `+1242` rather
+//! than the `+1` the United States and Canada share.
+
+use std::{fmt, sync::Arc};
+
+use crate::dataset::CountryRecord;
+
+// E.164 numbers have at most 15 digits
+pub const MAX_DIGITS: usize = 15;
+
+#[derive(Debug, Clone, Default)]
+pub struct CallingCodes {
+    // Boxed, so an empty trie is one pointer in the dataset rather than eleven
+    root: Box<Node>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    // Indexed by the next digit
+    children: [Option<Box<Node>>; 10],
+    // The countries whose code ends here
+    countries: Vec<Arc<CountryRecord>>,
+}
+
+impl CallingCodes {
+    // Records without a digit in their `phoneCode` are left out
+    pub fn insert(&mut self, record: &Arc<CountryRecord>) {
+        let digits = code_digits(&record.phone_code);
+        if digits.is_empty() {
+            return;
+        }
+        let mut node: &mut Node = &mut self.root;
+        for digit in digits {
+            node = node.children[digit].get_or_insert_with(Box::default);
+        }
+        node.countries.push(Arc::clone(record));
+    }
+
+    // Emptied nodes are kept; they have no countries, so lookups pass over them
+    pub fn remove(&mut self, record: &Arc<CountryRecord>) {
+        let mut node: &mut Node = &mut self.root;
+        for digit in code_digits(&record.phone_code) {
+            match node.children[digit].as_deref_mut() {
+                Some(child) => node = child,
+                None => return,
+            }
+        }
+        node.countries.retain(|other| !Arc::ptr_eq(other, record));
+    }
+
+    // The countries of the longest calling code `digits` starts with, in the order they
+    // were added; empty if no code matches
+    pub fn resolve(&self, digits: &str) -> &[Arc<CountryRecord>] {
+        let mut node: &Node = &self.root;
+        let mut longest: &[Arc<CountryRecord>] = &[];
+        for digit in code_digits(digits) {
+            match node.children[digit].as_deref() {
+                Some(child) => node = child,
+                None => break,
+            }
+            if !node.countries.is_empty() {
+                longest = &node.countries;
+            }
+        }
+        longest
+    }
+}
+
+impl<'a> FromIterator<&'a Arc<CountryRecord>> for CallingCodes {
+    fn from_iter<T: IntoIterator<Item = &'a Arc<CountryRecord>>>(records: T) -> Self {
+        let mut codes = CallingCodes::default();
+        for record in records {
+            codes.insert(record);
+        }
+        codes
+    }
+}
+
+// "+1-684" -> [1, 6, 8, 4]
+fn code_digits(code: &str) -> Vec<usize> {
+    code.chars()
+        .filter_map(|c| c.to_digit(10))
+        .map(|digit| digit as usize)
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NumberError {
+    Empty,
+    InvalidCharacter(char),
+    TooLong,
+}
+
+impl fmt::Display for NumberError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NumberError::Empty => f.write_str("no digits"),
+            NumberError::InvalidCharacter(c) => write!(f, "{:?} is not a digit", c),
+            NumberError::TooLong => write!(f, "more than {} digits", MAX_DIGITS),
+        }
+    }
+}
+
+impl std::error::Error for NumberError {}
+
+// The digits of a phone number such as "+81-90-1234-5678", "+1" or "(81) 90.1234":
+// an optional leading `+`, then digits separated by any spaces, dashes, dots and
+// parentheses. The `+` is optional because a raw `+` in a query string reads as a space.
+pub fn number_digits(number: &str) -> Result<String, NumberError> {
+    let number = number.trim();
+    let number = number.strip_prefix('+').unwrap_or(number);
+    let mut digits = String::new();
+    for c in number.chars() {
+        match c {
+            '0'..='9' => digits.push(c),
+            ' ' | '-' | '.' | '(' | ')' => {}
+            c => return Err(NumberError::InvalidCharacter(c)),
+        }
+    }
+
+    match digits.len() {
+        0 => Err(NumberError::Empty),
+        length if length > MAX_DIGITS => Err(NumberError::TooLong),
+        _ => Ok(digits),
+    }
+}
@@ -0,0 +1,224 @@
+//! Memoized `/countries` and `/codes` bodies.
+//!
+//! The lists only change with the dataset, so each serialized body, one per format and
+//! language, is kept until the dataset version moves on and is then rendered again on
+//! first use. Its ETag is a SHA-256 of the body itself: it differs between languages and
+//! formats, and replicas or restarted servers serving the same data agree on it.
+//!
+//! Both are sent with a [`ListCaching`] `Cache-Control`, so CDNs keep serving a copy that
+//! went stale while they revalidate it, which the ETag makes a `304` without a body.
+
+use axum::{
+    body::Bytes,
+    http::{header::IF_NONE_MATCH, HeaderMap},
+};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    hash::Hash,
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use crate::{
+    export::csv_field,
+    lookup::{CountryCodes, CountryInfo, FlagFormat},
+};
+
+// `lang` is free-form, so only this many bodies are kept per version
+const MAX_CACHED_BODIES: usize = 48;
+
+const CSV_HEADER: &str = "country,localizedName,flag,currencyCode,phoneCode";
+const CODES_CSV_HEADER: &str = "country,alpha2,alpha3,numeric";
+
+// Hex digits of the body's SHA-256 kept in the ETag; plenty to tell bodies apart
+const ETAG_DIGITS: usize = 32;
+
+/// How long caches may serve a list without asking again when `--list-max-age` is not given.
+pub const DEFAULT_LIST_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// How long past that caches may serve a stale list while they revalidate it when
+/// `--list-stale-while-revalidate` is not given.
+pub const DEFAULT_LIST_STALE_WHILE_REVALIDATE: Duration = Duration::from_secs(300);
+
+// The `Cache-Control` of the list endpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListCaching {
+    // Fresh for this long after it is fetched
+    pub max_age: Duration,
+    // Then served stale for up to this long more while a cache revalidates it
+    pub stale_while_revalidate: Duration,
+}
+
+impl Default for ListCaching {
+    fn default() -> Self {
+        ListCaching {
+            max_age: DEFAULT_LIST_MAX_AGE,
+            stale_while_revalidate: DEFAULT_LIST_STALE_WHILE_REVALIDATE,
+        }
+    }
+}
+
+impl ListCaching {
+    pub fn header_value(&self) -> String {
+        format!(
+            "public, max-age={}, stale-while-revalidate={}",
+            self.max_age.as_secs(),
+            self.stale_while_revalidate.as_secs()
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CatalogFormat {
+    Json,
+    Csv,
+    // One JSON object per line, for streaming parsers
+    Ndjson,
+}
+
+impl CatalogFormat {
+    // The `format` query parameter; JSON when absent
+    pub fn from_param(format: Option<&str>) -> Result<Self, String> {
+        match format.unwrap_or("json") {
+            "json" => Ok(CatalogFormat::Json),
+            "csv" => Ok(CatalogFormat::Csv),
+            "ndjson" => Ok(CatalogFormat::Ndjson),
+            other => Err(format!("{:?} is not `json`, `csv` or `ndjson`", other)),
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            CatalogFormat::Json => "application/json",
+            CatalogFormat::Csv => "text/csv; charset=utf-8",
+            CatalogFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+// A rendered body and its ETag
+#[derive(Debug, Clone)]
+pub struct CatalogBody {
+    pub body: Bytes,
+    pub etag: String,
+}
+
+impl CatalogBody {
+    pub fn new(body: Bytes) -> Self {
+        let digest = format!("{:x}", Sha256::digest(&body));
+        CatalogBody {
+            etag: format!("\"{}\"", &digest[..ETAG_DIGITS]),
+            body,
+        }
+    }
+
+    // Whether `If-None-Match` already names this body, so `304 Not Modified` will do
+    pub fn is_cached_by(&self, headers: &HeaderMap) -> bool {
+        headers
+            .get_all(IF_NONE_MATCH)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            // Weak comparison, as `If-None-Match` calls for
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == self.etag)
+    }
+}
+
+// What a `/countries` body depends on: its format, flag format, `maxResults` and language
+pub type CountriesKey = (CatalogFormat, FlagFormat, Option<usize>, String);
+
+// Bodies keyed by everything besides the dataset that they depend on
+#[derive(Debug)]
+pub struct CatalogCache<K> {
+    state: Mutex<CatalogState<K>>,
+}
+
+#[derive(Debug)]
+struct CatalogState<K> {
+    // Dataset version the bodies were rendered from
+    version: u64,
+    bodies: HashMap<K, CatalogBody>,
+}
+
+impl<K> Default for CatalogCache<K> {
+    fn default() -> Self {
+        CatalogCache {
+            state: Mutex::new(CatalogState {
+                version: 0,
+                bodies: HashMap::new(),
+            }),
+        }
+    }
+}
+
+impl<K: Hash + Eq> CatalogCache<K> {
+    // The cached body for `key`, rendering and keeping it if there is none yet
+    pub fn get_or_render(
+        &self,
+        version: u64,
+        key: K,
+        render: impl FnOnce() -> Bytes,
+    ) -> CatalogBody {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if state.version != version {
+            state.bodies.clear();
+            state.version = version;
+        }
+        if let Some(body) = state.bodies.get(&key) {
+            return body.clone();
+        }
+
+        let body = CatalogBody::new(render());
+        if state.bodies.len() < MAX_CACHED_BODIES {
+            state.bodies.insert(key, body.clone());
+        }
+        body
+    }
+}
+
+pub fn to_csv(results: &[CountryInfo]) -> Bytes {
+    let mut csv = String::from(CSV_HEADER);
+    csv.push('\n');
+    for info in results {
+        let fields = [
+            csv_field(&info.country),
+            csv_field(&info.localized_name),
+            csv_field(&info.flag),
+            csv_field(info.currency_code.as_str()),
+            csv_field(&info.phone_code),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    Bytes::from(csv)
+}
+
+// `/codes` as CSV; codes a country does not have are left empty
+pub fn codes_to_csv(codes: &[CountryCodes]) -> Bytes {
+    let mut csv = String::from(CODES_CSV_HEADER);
+    csv.push('\n');
+    for entry in codes {
+        let fields = [
+            csv_field(&entry.country),
+            csv_field(entry.alpha2.as_str()),
+            csv_field(entry.alpha3.as_deref().unwrap_or_default()),
+            csv_field(entry.numeric.as_deref().unwrap_or_default()),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    Bytes::from(csv)
+}
+
+pub fn to_ndjson<T: Serialize>(results: &[T]) -> Bytes {
+    let mut ndjson = String::new();
+    for info in results {
+        let line = serde_json::to_string(info).expect("results serialize to JSON");
+        writeln!(ndjson, "{}", line).expect("writing to a String cannot fail");
+    }
+    Bytes::from(ndjson)
+}
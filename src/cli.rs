@@ -0,0 +1,187 @@
+//! Commands of the `rusty_currency` binary that answer offline, without starting the
+//! server. Output goes to the writers passed in, so tests can capture it.
+
+use clap::builder::{PossibleValue, PossibleValuesParser};
+use clap_complete::Shell;
+use std::io::{self, Write};
+
+use crate::{
+    builtin,
+    dataset::Dataset,
+    export,
+    lookup::{self, CountryInfo, CountryResponse},
+    source::DatasetLoader,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+// Formats of `dump`, the same ones `/admin/export` serves
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DumpFormat {
+    Json,
+    Csv,
+    Yaml,
+}
+
+// Prints the whole dataset `source` loads, or on `err` why it failed to load or
+// validate. Returns whether it loaded.
+pub fn dump(
+    source: &dyn DatasetLoader,
+    format: DumpFormat,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> io::Result<bool> {
+    let dataset = match source.load() {
+        Ok(dataset) => dataset,
+        Err(error) => {
+            writeln!(err, "{}", error)?;
+            return Ok(false);
+        }
+    };
+
+    match format {
+        DumpFormat::Json => writeln!(out, "{}", export::to_json(&dataset))?,
+        DumpFormat::Csv => out.write_all(export::to_csv(&dataset).as_bytes())?,
+        DumpFormat::Yaml => out.write_all(export::to_yaml(&dataset).as_bytes())?,
+    }
+    Ok(true)
+}
+
+// Prints the countries `names` resolve to, in the order given, and lists the names that
+// matched nothing on `err`. Returns whether every name matched.
+pub fn lookup(
+    dataset: &Dataset,
+    names: &[String],
+    language: &str,
+    format: OutputFormat,
+    out: &mut impl Write,
+    err: &mut impl Write,
+) -> io::Result<bool> {
+    let (results, unknown) =
+        lookup::resolve_names(dataset, names.iter().map(|name| name.trim()), language);
+
+    match format {
+        OutputFormat::Table => write_table(&results, out)?,
+        OutputFormat::Json => {
+            out.write_all(&lookup::to_json(&CountryResponse {
+                results,
+                unmatched: Vec::new(),
+                truncated: false,
+            }))?;
+            writeln!(out)?;
+        }
+    }
+    for name in &unknown {
+        writeln!(err, "Unknown country: {}", name)?;
+    }
+
+    Ok(unknown.is_empty())
+}
+
+const TABLE_HEADER: [&str; 5] = ["COUNTRY", "NAME", "CURRENCY", "PHONE", "FLAG"];
+
+// Columns are padded to their widest value; the flag comes last because terminals
+// disagree on how wide an emoji is
+fn write_table(results: &[CountryInfo], out: &mut impl Write) -> io::Result<()> {
+    let rows: Vec<[&str; 5]> = results
+        .iter()
+        .map(|info| {
+            [
+                &*info.country,
+                &*info.localized_name,
+                info.currency_code.as_str(),
+                &*info.phone_code,
+                &*info.flag,
+            ]
+        })
+        .collect();
+    let mut widths = TABLE_HEADER.map(|heading| heading.chars().count());
+    for row in &rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+
+    for row in std::iter::once(&TABLE_HEADER).chain(&rows) {
+        let mut line = String::new();
+        for (column, value) in row.iter().enumerate() {
+            if column + 1 == row.len() {
+                line.push_str(value);
+            } else {
+                let padding = widths[column] - value.chars().count();
+                line.push_str(value);
+                line.extend(std::iter::repeat_n(' ', padding + 2));
+            }
+        }
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+// Writes the completion script of `command` for `shell`. zsh and fish also complete
+// `lookup` arguments with the names of the built-in countries; bash splits completion
+// words on spaces, so names like "united states" cannot be offered there.
+pub fn completions(
+    mut command: clap::Command,
+    shell: Shell,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let name = command.get_name().to_string();
+    // zsh reads the names from the argument's possible values. They only exist in the
+    // generated script: `lookup` still accepts any name. With a description each, the
+    // spaces in them are escaped.
+    if shell == Shell::Zsh {
+        let names = builtin::BUILTIN_COUNTRIES
+            .iter()
+            .map(|country| PossibleValue::new(country.name).help(country_description(country)));
+        command = command.mut_subcommand("lookup", |lookup| {
+            lookup.mut_arg("names", |arg| {
+                arg.value_parser(PossibleValuesParser::new(names))
+            })
+        });
+    }
+    clap_complete::generate(shell, &mut command, &name, out);
+
+    // clap's fish script does not complete positional arguments, so the names are
+    // added as one more completion
+    if shell == Shell::Fish {
+        let names: Vec<String> = builtin::BUILTIN_COUNTRIES
+            .iter()
+            .map(|country| {
+                format!(
+                    "{}\\t'{}'",
+                    fish_escape(country.name),
+                    country_description(country)
+                )
+            })
+            .collect();
+        writeln!(
+            out,
+            "complete -c {} -n \"__fish_seen_subcommand_from lookup\" -f -a \"{}\"",
+            name,
+            names.join("\n")
+        )?;
+    }
+    Ok(())
+}
+
+fn country_description(country: &builtin::BuiltinCountry) -> String {
+    format!("{} {}", country.flag, country.currency_code)
+}
+
+// For the candidate list of `complete -a`, which fish splits on spaces and expands
+// like a command line, so "cocos (keeling) islands" would otherwise run a command
+fn fish_escape(name: &str) -> String {
+    let mut escaped = String::with_capacity(name.len());
+    for c in name.chars() {
+        if matches!(c, '\\' | '\'' | '(' | ')' | ' ') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,121 @@
+//! Typed async client for the HTTP API, built on reqwest (`--features client`).
+//!
+//! Responses are parsed into the same types the server serializes, so the client cannot
+//! drift from the API it calls.
+
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use std::fmt;
+
+use crate::{
+    lookup::CountryResponse,
+    responses::{ErrorEnvelope, HealthResponse},
+};
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    // Where the API is mounted, without a trailing slash, e.g. `https://example.com/geo`
+    base_url: String,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    // No response arrived, or its body could not be read or parsed
+    Transport(reqwest::Error),
+    // The server answered with a non-2xx status; `error` is the JSON error envelope it
+    // sent, if the body held one
+    Status {
+        status: StatusCode,
+        error: Option<ErrorEnvelope>,
+    },
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(error: reqwest::Error) -> Self {
+        ClientError::Transport(error)
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Transport(error) => write!(f, "Country API request failed: {}", error),
+            ClientError::Status {
+                status,
+                error: Some(envelope),
+            } => write!(
+                f,
+                "Country API answered {}: {} ({})",
+                status, envelope.error.message, envelope.error.code
+            ),
+            ClientError::Status {
+                status,
+                error: None,
+            } => {
+                write!(f, "Country API answered {}", status)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Transport(error) => Some(error),
+            ClientError::Status { .. } => None,
+        }
+    }
+}
+
+impl Client {
+    pub fn new(base_url: &str) -> Self {
+        Client::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    // For callers that configure timeouts, proxies or TLS on their own reqwest client
+    pub fn with_http_client(base_url: &str, http: reqwest::Client) -> Self {
+        Client {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    // `/getCountry` for `names`; names matching no country are left out of the results
+    pub async fn get_countries(
+        &self,
+        names: &[&str],
+    ) -> Result<CountryResponse<'static>, ClientError> {
+        self.get("/getCountry", &[("based", names.join(","))]).await
+    }
+
+    // `/countries`: every country once, under its canonical name
+    pub async fn list_all(&self) -> Result<CountryResponse<'static>, ClientError> {
+        self.get("/countries", &[]).await
+    }
+
+    pub async fn health(&self) -> Result<HealthResponse, ClientError> {
+        self.get("/healthz", &[]).await
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> Result<T, ClientError> {
+        let response = self
+            .http
+            .get(format!("{}{}", self.base_url, path))
+            .query(query)
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            // A proxy in front of the API may answer with a body that is not ours
+            let error = response.json().await.ok();
+            return Err(ClientError::Status { status, error });
+        }
+        Ok(response.json().await?)
+    }
+}
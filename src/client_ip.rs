@@ -0,0 +1,167 @@
+//! The address of the client a request came from, for `/whoami`, the access log and the
+//! unmatched-name log.
+//!
+//! Behind a load balancer the socket peer is the proxy rather than the client. When the
+//! peer is one of `--trusted-proxies`, the client is read from `X-Forwarded-For`, or
+//! `Forwarded` when that is absent: the rightmost address that is not itself a trusted
+//! proxy, as every address to its left was written by someone no proxy vouches for. A
+//! malformed entry ends the search at the last address before it. Requests from any other
+//! peer are answered as coming from the peer, whatever headers they send, since anyone
+//! can write them.
+
+use axum::{
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::{header::FORWARDED, request::Parts, HeaderMap},
+};
+use ipnet::IpNet;
+use std::{
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+
+use crate::{ApiError, AppState};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+// The proxies whose forwarding headers are believed; none by default
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies(Arc<[IpNet]>);
+
+impl TrustedProxies {
+    pub fn new(networks: impl IntoIterator<Item = IpNet>) -> Self {
+        TrustedProxies(networks.into_iter().collect())
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let address = address.to_canonical();
+        self.0.iter().any(|network| network.contains(&address))
+    }
+
+    // The client a request from `peer` with `headers` was sent for
+    pub fn client(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer.to_canonical();
+        if !self.contains(client) {
+            return client;
+        }
+        let hops = if headers.contains_key(X_FORWARDED_FOR) {
+            x_forwarded_for(headers)
+        } else {
+            forwarded(headers)
+        };
+        for hop in hops.into_iter().rev() {
+            let Some(address) = hop else {
+                break;
+            };
+            client = address;
+            if !self.contains(address) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+/// The address of the client that sent the request, as [`TrustedProxies`] works it out.
+///
+/// Fails with a 500 when the app is not served with
+/// `into_make_service_with_connect_info::<SocketAddr>()`; extract `Option<ClientIp>` where
+/// the address is not needed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+    TrustedProxies: FromRef<S>,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, ApiError> {
+        let Ok(ConnectInfo(peer)) =
+            ConnectInfo::<SocketAddr>::from_request_parts(parts, state).await
+        else {
+            return Err(anyhow::anyhow!(
+                "{} needs the client address; serve the router with `into_make_service_with_connect_info`",
+                parts.uri.path()
+            )
+            .into());
+        };
+        Ok(ClientIp(
+            TrustedProxies::from_ref(state).client(peer.ip(), &parts.headers),
+        ))
+    }
+}
+
+impl FromRef<AppState> for TrustedProxies {
+    fn from_ref(state: &AppState) -> Self {
+        state.trusted_proxies.clone()
+    }
+}
+
+// A `--trusted-proxies` entry: a CIDR range, or a single address
+pub fn parse_network(value: &str) -> Result<IpNet, String> {
+    value
+        .parse::<IpNet>()
+        .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+        .map_err(|_| format!("{:?} is not an address or CIDR range", value))
+}
+
+// Each address of every `X-Forwarded-For` header in order, None for the malformed ones
+fn x_forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .flat_map(|value| match value.to_str() {
+            Ok(value) => value.split(',').map(node).collect(),
+            Err(_) => vec![None],
+        })
+        .collect()
+}
+
+// The `for=` address of each element of every `Forwarded` header in order, None for the
+// elements without one or with an obfuscated or malformed one
+fn forwarded(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all(FORWARDED)
+        .iter()
+        .flat_map(|value| match value.to_str() {
+            Ok(value) => value
+                .split(',')
+                .map(|element| {
+                    element.split(';').find_map(|pair| {
+                        let (name, value) = pair.split_once('=')?;
+                        name.trim().eq_ignore_ascii_case("for").then(|| {
+                            let value = value.trim();
+                            node(
+                                value
+                                    .strip_prefix('"')
+                                    .and_then(|value| value.strip_suffix('"'))
+                                    .unwrap_or(value),
+                            )
+                        })
+                    })?
+                })
+                .collect(),
+            Err(_) => vec![None],
+        })
+        .collect()
+}
+
+// An address as proxies write it: bare, with a port, or an IPv6 address in brackets
+fn node(value: &str) -> Option<IpAddr> {
+    let value = value.trim();
+    let address = value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|address| address.ip()))
+        .or_else(|| {
+            value
+                .strip_prefix('[')?
+                .strip_suffix(']')?
+                .parse::<Ipv6Addr>()
+                .ok()
+                .map(IpAddr::V6)
+        })?;
+    Some(address.to_canonical())
+}
@@ -0,0 +1,197 @@
+//! Validated ISO codes: `CurrencyCode` (ISO 4217, three uppercase letters) and `Alpha2`
+//! (ISO 3166-1 alpha-2, two uppercase letters).
+//!
+//! Only the format is checked here; whether a currency code is actually assigned is left
+//! to `--check`. Codes are kept as their ASCII bytes, so they are `Copy`, never allocate,
+//! and serialize as plain strings.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CurrencyCode([u8; 3]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Alpha2([u8; 2]);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeError {
+    // What was being parsed, e.g. "currency code"
+    pub kind: &'static str,
+    pub value: String,
+    // Number of uppercase letters the code needs
+    pub length: usize,
+}
+
+impl fmt::Display for CodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} is not a valid {}: expected {} uppercase ASCII letters",
+            self.value, self.kind, self.length
+        )
+    }
+}
+
+impl std::error::Error for CodeError {}
+
+// `code` as exactly N uppercase ASCII letters; const so the built-in table is checked
+// while compiling
+const fn uppercase_letters<const N: usize>(code: &str) -> Option<[u8; N]> {
+    let bytes = code.as_bytes();
+    if bytes.len() != N {
+        return None;
+    }
+    let mut letters = [0; N];
+    let mut index = 0;
+    while index < N {
+        if !bytes[index].is_ascii_uppercase() {
+            return None;
+        }
+        letters[index] = bytes[index];
+        index += 1;
+    }
+    Some(letters)
+}
+
+fn as_str(letters: &[u8]) -> &str {
+    std::str::from_utf8(letters).expect("codes only hold ASCII letters")
+}
+
+impl CurrencyCode {
+    pub fn new(code: &str) -> Result<Self, CodeError> {
+        uppercase_letters(code)
+            .map(CurrencyCode)
+            .ok_or_else(|| CodeError {
+                kind: "currency code",
+                value: code.to_string(),
+                length: 3,
+            })
+    }
+
+    // For the generated built-in table: a malformed code fails the build
+    pub const fn from_static(code: &'static str) -> Self {
+        match uppercase_letters(code) {
+            Some(letters) => CurrencyCode(letters),
+            None => panic!("malformed currency code in the built-in dataset"),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        as_str(&self.0)
+    }
+}
+
+impl Alpha2 {
+    pub fn new(code: &str) -> Result<Self, CodeError> {
+        uppercase_letters(code)
+            .map(Alpha2)
+            .ok_or_else(|| CodeError {
+                kind: "alpha-2 code",
+                value: code.to_string(),
+                length: 2,
+            })
+    }
+
+    // For the generated built-in table: a malformed code fails the build
+    pub const fn from_static(code: &'static str) -> Self {
+        match uppercase_letters(code) {
+            Some(letters) => Alpha2(letters),
+            None => panic!("malformed alpha-2 code in the built-in dataset"),
+        }
+    }
+
+    // The code a flag emoji spells, one regional indicator symbol per letter
+    pub fn from_flag(flag: &str) -> Option<Self> {
+        let letters: String = flag
+            .chars()
+            .map(|indicator| {
+                let offset = u32::from(indicator).checked_sub(0x1F1E6)?;
+                char::from_u32(u32::from('A') + offset).filter(char::is_ascii_uppercase)
+            })
+            .collect::<Option<_>>()?;
+        Alpha2::new(&letters).ok()
+    }
+
+    pub fn as_str(&self) -> &str {
+        as_str(&self.0)
+    }
+}
+
+impl FromStr for CurrencyCode {
+    type Err = CodeError;
+
+    fn from_str(code: &str) -> Result<Self, CodeError> {
+        CurrencyCode::new(code)
+    }
+}
+
+impl FromStr for Alpha2 {
+    type Err = CodeError;
+
+    fn from_str(code: &str) -> Result<Self, CodeError> {
+        Alpha2::new(code)
+    }
+}
+
+impl fmt::Display for CurrencyCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for Alpha2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl PartialEq<str> for CurrencyCode {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for CurrencyCode {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<str> for Alpha2 {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Alpha2 {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Serialize for CurrencyCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl Serialize for Alpha2 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for CurrencyCode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        CurrencyCode::new(&code).map_err(de::Error::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Alpha2 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = String::deserialize(deserializer)?;
+        Alpha2::new(&code).map_err(de::Error::custom)
+    }
+}
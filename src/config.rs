@@ -0,0 +1,323 @@
+//! Server options from the environment and from a `--config rustincountry.toml` file, as
+//! well as from flags.
+//!
+//! Every `serve` option can be set with a variable named after it, `RUSTINCOUNTRY_` and
+//! its long name in upper case with underscores: `RUSTINCOUNTRY_RATE_LIMIT=100` for
+//! `--rate-limit 100`. Options that can be passed more than once take a comma-separated
+//! list, as in `RUSTINCOUNTRY_CORS_ORIGIN=https://a.example,https://b.example`, and
+//! switches take `1`, `true` or `yes` (and `0`, `false` or `no`).
+//!
+//! In the file each key is an option's long name, with the value the flag would take:
+//!
+//! ```toml
+//! listen = ["0.0.0.0:3000", "[::]:3000"]
+//! cors-origin = ["https://example.com"]
+//! rate-limit = 100
+//! response-cache = 1000
+//! data-file = "countries.json"
+//! watch = true
+//! log = "info,rustincountry::unmatched=off"
+//! ```
+//!
+//! A value on the command line wins over its variable, which wins over the file, which
+//! wins over the default; [`resolve`] applies all four at once. Each variable and file
+//! value is checked against its option on its own, so a bad one is reported by name.
+//! File keys naming no option are returned, for the caller to warn about, rather than
+//! failing startup.
+
+use clap::{parser::ValueSource, Arg, ArgAction, ArgMatches, Command};
+use std::{
+    collections::{BTreeMap, HashSet},
+    ffi::OsString,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+use toml::{Table, Value};
+
+pub const ENV_PREFIX: &str = "RUSTINCOUNTRY_";
+
+// The option that names the file, which the file itself cannot set
+const CONFIG_KEY: &str = "config";
+
+const TRUE_VALUES: [&str; 4] = ["1", "true", "yes", "on"];
+const FALSE_VALUES: [&str; 4] = ["0", "false", "no", "off"];
+
+#[derive(Debug)]
+pub enum ConfigError {
+    // The file could not be read
+    Read {
+        path: PathBuf,
+        error: io::Error,
+    },
+    // The file is not TOML
+    Parse {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    // A key holds a table or date, which no option takes
+    UnsupportedValue {
+        key: String,
+    },
+    // A variable or file key holds a value its option does not take
+    InvalidValue {
+        // `RUSTINCOUNTRY_RATE_LIMIT`, or "config file key `rate-limit`"
+        setting: String,
+        value: String,
+        reason: String,
+        expected: String,
+    },
+    // The options set are invalid together, or the command line is invalid on its own
+    Rejected(clap::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read { path, error } => {
+                write!(f, "Failed to read {}: {}", path.display(), error)
+            }
+            ConfigError::Parse { path, error } => {
+                write!(f, "{} is not valid TOML: {}", path.display(), error)
+            }
+            ConfigError::UnsupportedValue { key } => write!(
+                f,
+                "`{}` must be a string, number, boolean or an array of them",
+                key
+            ),
+            ConfigError::InvalidValue {
+                setting,
+                value,
+                reason,
+                expected,
+            } => write!(
+                f,
+                "Invalid {} '{}': {}; expected {}",
+                setting, value, reason, expected
+            ),
+            ConfigError::Rejected(error) => write!(f, "{}", first_line(error)),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+pub fn load(path: &Path) -> Result<Table, ConfigError> {
+    let text = fs::read_to_string(path).map_err(|error| ConfigError::Read {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    text.parse().map_err(|error| ConfigError::Parse {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+// The variable that sets the option with the long name `long`
+pub fn variable(long: &str) -> String {
+    format!("{}{}", ENV_PREFIX, long.to_uppercase().replace('-', "_"))
+}
+
+// `command` with each option's variable at the end of its help, as clap shows its own
+pub fn document_variables(command: Command) -> Command {
+    command.mut_args(|arg| match (arg.get_long(), settable(&arg)) {
+        (Some(long), true) => {
+            let help = arg.get_help().map(ToString::to_string).unwrap_or_default();
+            let variable = variable(long);
+            arg.help(format!("{} [env: {}]", help, variable))
+        }
+        _ => arg,
+    })
+}
+
+// `args` parsed by `command` with the environment and the file filled in, and the keys of
+// the file that name no option, sorted
+#[derive(Debug)]
+pub struct Resolved {
+    pub matches: ArgMatches,
+    pub unknown_keys: Vec<String>,
+}
+
+// Parses `args` as `command` would, then gives each option of the innermost subcommand
+// that `args` left unset the value of its variable in `env`, or failing that of its key
+// in `file`. Options conflicting with one set by a higher layer are left out, as that
+// layer overrides them. The values are appended to `args` as flags and everything parsed
+// again, so they are validated exactly as flags are.
+pub fn resolve(
+    command: Command,
+    args: &[OsString],
+    env: &BTreeMap<String, String>,
+    file: Option<&Table>,
+) -> Result<Resolved, ConfigError> {
+    // Lenient, as a flag may need another the environment or file provides
+    let matches = command
+        .clone()
+        .ignore_errors(true)
+        .try_get_matches_from(args)
+        .map_err(ConfigError::Rejected)?;
+    let (target, target_matches) = innermost(&command, &matches);
+    let mut given: HashSet<String> = target
+        .get_arguments()
+        .map(|arg| arg.get_id().to_string())
+        .filter(|id| target_matches.value_source(id) == Some(ValueSource::CommandLine))
+        .collect();
+    let overridden = |arg: &Arg, given: &HashSet<String>| {
+        given.contains(arg.get_id().as_str())
+            || target
+                .get_arg_conflicts_with(arg)
+                .iter()
+                .any(|conflict| given.contains(conflict.get_id().as_str()))
+    };
+
+    let mut extra = Vec::new();
+    let mut from_env = Vec::new();
+    for arg in target.get_arguments().filter(|arg| settable(arg)) {
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+        let variable = variable(long);
+        let Some(value) = env.get(&variable) else {
+            continue;
+        };
+        if overridden(arg, &given) {
+            continue;
+        }
+        let values: Vec<_> = if takes_many(arg) {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .collect()
+        } else {
+            vec![value.as_str()]
+        };
+        extra.extend(flags(arg, &variable, &values)?);
+        from_env.push(arg.get_id().to_string());
+    }
+    given.extend(from_env);
+
+    let mut unknown_keys = Vec::new();
+    for (key, value) in file.into_iter().flatten() {
+        let arg = target
+            .get_arguments()
+            .filter(|arg| settable(arg) && key != CONFIG_KEY)
+            .find(|arg| arg.get_long() == Some(key.as_str()));
+        let Some(arg) = arg else {
+            unknown_keys.push(key.clone());
+            continue;
+        };
+        if overridden(arg, &given) {
+            continue;
+        }
+        let values = match value {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        };
+        let values = values
+            .into_iter()
+            .map(|value| match value {
+                Value::String(value) => Ok(value.clone()),
+                Value::Integer(_) | Value::Float(_) | Value::Boolean(_) => Ok(value.to_string()),
+                Value::Datetime(_) | Value::Array(_) | Value::Table(_) => {
+                    Err(ConfigError::UnsupportedValue { key: key.clone() })
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let values: Vec<_> = values.iter().map(String::as_str).collect();
+        let setting = format!("config file key `{}`", key);
+        extra.extend(flags(arg, &setting, &values)?);
+    }
+
+    let matches = command
+        .try_get_matches_from(args.iter().cloned().chain(extra))
+        .map_err(ConfigError::Rejected)?;
+    Ok(Resolved {
+        matches,
+        unknown_keys,
+    })
+}
+
+// Options a variable or the file can set: not `--help` or `--version`
+fn settable(arg: &Arg) -> bool {
+    !matches!(
+        arg.get_action(),
+        ArgAction::Help | ArgAction::HelpShort | ArgAction::HelpLong | ArgAction::Version
+    )
+}
+
+fn takes_many(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::Append)
+}
+
+// The flags giving `arg` `values`, each checked first so a bad one is reported as the
+// value of `setting`: switches take a boolean, other options what their flag takes
+fn flags(arg: &Arg, setting: &str, values: &[&str]) -> Result<Vec<OsString>, ConfigError> {
+    let long = arg.get_long().expect("settable options have a long name");
+    let invalid = |value: &str, reason: String, expected: String| ConfigError::InvalidValue {
+        setting: setting.to_string(),
+        value: value.to_string(),
+        reason,
+        expected,
+    };
+    let mut flags = Vec::new();
+    for &value in values {
+        if !arg.get_action().takes_values() {
+            let lowered = value.to_ascii_lowercase();
+            if TRUE_VALUES.contains(&lowered.as_str()) {
+                flags.push(OsString::from(format!("--{}", long)));
+            } else if !FALSE_VALUES.contains(&lowered.as_str()) {
+                return Err(invalid(
+                    value,
+                    String::from("not a boolean"),
+                    String::from("1, true, yes, 0, false or no"),
+                ));
+            }
+            continue;
+        }
+        // The option's parser alone, so nothing but the value can fail
+        let alone = Command::new("value").no_binary_name(true).arg(
+            Arg::new("value")
+                .allow_hyphen_values(true)
+                .value_parser(arg.get_value_parser().clone()),
+        );
+        if let Err(error) = alone.try_get_matches_from([value]) {
+            let reason = std::error::Error::source(&error)
+                .map_or_else(|| first_line(&error), ToString::to_string);
+            let value_name = arg
+                .get_value_names()
+                .and_then(|names| names.first())
+                .map_or_else(
+                    || arg.get_id().to_string().to_uppercase(),
+                    ToString::to_string,
+                );
+            let expected = if takes_many(arg) && setting.starts_with(ENV_PREFIX) {
+                format!(
+                    "comma-separated <{}> values, as --{} takes",
+                    value_name, long
+                )
+            } else {
+                format!("<{}>, as --{} takes", value_name, long)
+            };
+            return Err(invalid(value, reason, expected));
+        }
+        flags.push(OsString::from(format!("--{}={}", long, value)));
+    }
+    Ok(flags)
+}
+
+// clap's first line, without its `error: ` prefix or the `--help` hint after it
+fn first_line(error: &clap::Error) -> String {
+    let message = error.to_string();
+    let first = message.lines().next().unwrap_or_default();
+    first.strip_prefix("error: ").unwrap_or(first).to_string()
+}
+
+// The subcommand `matches` ends in, with its matches; `command` itself without one
+fn innermost<'a>(command: &'a Command, matches: &'a ArgMatches) -> (&'a Command, &'a ArgMatches) {
+    match matches.subcommand() {
+        Some((name, sub_matches)) => match command.find_subcommand(name) {
+            Some(subcommand) => innermost(subcommand, sub_matches),
+            None => (command, matches),
+        },
+        None => (command, matches),
+    }
+}
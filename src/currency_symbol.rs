@@ -0,0 +1,88 @@
+//! Currency symbols, for `/byCurrency?symbol=`.
+//!
+//! Many currencies share a symbol: `$` is written for the US, Canadian, Australian and
+//! a dozen more dollars, and `¥` for both the yen and the yuan. A symbol therefore maps
+//! to every code it is written for, and callers tell the user when that is more than
+//! one. Prefixed forms such as `US$` or `HK$` name a single currency.
+
+use crate::codes::CurrencyCode;
+
+// Symbol -> the codes it is written for, sorted; letters match in any case
+const SYMBOLS: &[(&str, &[&str])] = &[
+    (
+        "$",
+        &[
+            "ARS", "AUD", "BBD", "BMD", "BND", "BSD", "BZD", "CAD", "CLP", "COP", "CUP", "DOP",
+            "FJD", "GYD", "HKD", "JMD", "KYD", "LRD", "MXN", "NAD", "NZD", "SBD", "SGD", "SRD",
+            "TTD", "TWD", "USD", "UYU", "XCD",
+        ],
+    ),
+    ("A$", &["AUD"]),
+    ("C$", &["CAD"]),
+    ("HK$", &["HKD"]),
+    ("MX$", &["MXN"]),
+    ("NT$", &["TWD"]),
+    ("NZ$", &["NZD"]),
+    ("R$", &["BRL"]),
+    ("S$", &["SGD"]),
+    ("US$", &["USD"]),
+    ("¥", &["CNY", "JPY"]),
+    ("€", &["EUR"]),
+    ("£", &["EGP", "FKP", "GBP", "GIP", "SHP", "SSP", "SYP"]),
+    ("₩", &["KPW", "KRW"]),
+    ("₹", &["INR"]),
+    ("₨", &["LKR", "MUR", "NPR", "PKR", "SCR"]),
+    ("₽", &["RUB"]),
+    ("₺", &["TRY"]),
+    ("₪", &["ILS"]),
+    ("₫", &["VND"]),
+    ("₱", &["PHP"]),
+    ("₦", &["NGN"]),
+    ("₴", &["UAH"]),
+    ("₸", &["KZT"]),
+    ("₮", &["MNT"]),
+    ("₭", &["LAK"]),
+    ("₡", &["CRC"]),
+    ("₲", &["PYG"]),
+    ("₵", &["GHS"]),
+    ("₼", &["AZN"]),
+    ("₾", &["GEL"]),
+    ("฿", &["THB"]),
+    ("৳", &["BDT"]),
+    ("៛", &["KHR"]),
+    ("﷼", &["IRR", "OMR", "QAR", "SAR", "YER"]),
+    ("CHF", &["CHF"]),
+    ("Ft", &["HUF"]),
+    ("Kč", &["CZK"]),
+    ("kr", &["DKK", "ISK", "NOK", "SEK"]),
+    ("lei", &["RON"]),
+    ("R", &["ZAR"]),
+    ("RM", &["MYR"]),
+    ("Rp", &["IDR"]),
+    ("S/", &["PEN"]),
+    ("zł", &["PLN"]),
+];
+
+// The currencies `symbol` is written for, sorted by code; empty for an unknown symbol
+pub fn currencies(symbol: &str) -> Vec<CurrencyCode> {
+    let symbol = symbol.trim();
+    SYMBOLS
+        .iter()
+        .find(|(known, _)| known.to_lowercase() == symbol.to_lowercase())
+        .map(|(_, codes)| {
+            codes
+                .iter()
+                .map(|&code| CurrencyCode::from_static(code))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// The symbols written for `code`, in table order; empty when the table has none
+pub fn symbols(code: CurrencyCode) -> Vec<&'static str> {
+    SYMBOLS
+        .iter()
+        .filter(|(_, codes)| codes.contains(&code.as_str()))
+        .map(|(symbol, _)| *symbol)
+        .collect()
+}
@@ -0,0 +1,206 @@
+//! Loader for country data supplied as a JSON file via `--data-file`.
+//!
+//! The file holds a single object with a `countries` array. Each entry names the
+//! canonical country, any aliases it should also resolve under, and its lookup data:
+//!
+//! ```json
+//! {
+//!   "countries": [
+//!     {
+//!       "name": "japan",
+//!       "aliases": ["nippon"],
+//!       "flag": "🇯🇵",
+//!       "currencyCode": "JPY",
+//!       "phoneCode": "+81"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Names and aliases are matched case-insensitively and must be unique across the file,
+//! and flags must be exactly two regional indicator symbols.
+
+use serde::{
+    de::{self, value::MapAccessDeserializer, DeserializeSeed, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::dataset::{CountryRecord, Dataset};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DataFile {
+    countries: CountryEntries,
+}
+
+// The file as written, without any validation; `--check` reports every problem at once
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UncheckedDataFile {
+    countries: Vec<CountryRecord>,
+}
+
+// Entries are validated while deserializing so errors carry serde_json's line and column
+#[derive(Debug)]
+struct CountryEntries(Dataset);
+
+// Deserializes one entry and adds it to the dataset built so far. The checks run
+// inside the entry's map visitor so errors point at the entry itself.
+struct EntrySeed<'a> {
+    dataset: &'a mut Dataset,
+}
+
+impl<'de> DeserializeSeed<'de> for EntrySeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntrySeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a country entry object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        let entry = CountryRecord::deserialize(MapAccessDeserializer::new(map))?;
+        let entry_name = entry.name.clone();
+        let record = entry.normalized();
+
+        record
+            .validate()
+            .and_then(|()| self.dataset.insert(record))
+            .map_err(|error| {
+                de::Error::custom(format!("country entry {:?}: {}", entry_name, error))
+            })
+    }
+}
+
+impl<'de> Deserialize<'de> for CountryEntries {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor;
+
+        impl<'de> Visitor<'de> for EntriesVisitor {
+            type Value = CountryEntries;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an array of country entries")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut dataset = Dataset::default();
+                while seq
+                    .next_element_seed(EntrySeed {
+                        dataset: &mut dataset,
+                    })?
+                    .is_some()
+                {}
+                Ok(CountryEntries(dataset))
+            }
+        }
+
+        deserializer.deserialize_seq(EntriesVisitor)
+    }
+}
+
+#[derive(Debug)]
+pub enum DataFileError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Invalid {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for DataFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataFileError::Io { path, source } => {
+                write!(
+                    f,
+                    "Failed to read country data file {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+            DataFileError::Invalid {
+                path,
+                line,
+                column,
+                message,
+            } => write!(
+                f,
+                "Invalid country data file {} at line {}, column {}: {}",
+                path.display(),
+                line,
+                column,
+                message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DataFileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DataFileError::Io { source, .. } => Some(source),
+            DataFileError::Invalid { .. } => None,
+        }
+    }
+}
+
+pub fn invalid(path: &Path, error: serde_json::Error) -> DataFileError {
+    DataFileError::Invalid {
+        path: path.to_path_buf(),
+        line: error.line(),
+        column: error.column(),
+        // serde_json appends the position itself; keep only the description
+        message: error
+            .to_string()
+            .split(" at line ")
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+pub fn open(path: &Path) -> Result<BufReader<File>, DataFileError> {
+    let file = File::open(path).map_err(|source| DataFileError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    Ok(BufReader::new(file))
+}
+
+pub fn parse_data_file<R: Read>(reader: R, path: &Path) -> Result<Dataset, DataFileError> {
+    let data_file: DataFile =
+        serde_json::from_reader(reader).map_err(|error| invalid(path, error))?;
+
+    Ok(data_file.countries.0)
+}
+
+pub fn load_data_file(path: &Path) -> Result<Dataset, DataFileError> {
+    parse_data_file(open(path)?, path)
+}
+
+// Entries in file order, checked only for JSON syntax and shape
+pub fn read_records(path: &Path) -> Result<Vec<CountryRecord>, DataFileError> {
+    let data_file: UncheckedDataFile =
+        serde_json::from_reader(open(path)?).map_err(|error| invalid(path, error))?;
+
+    Ok(data_file.countries)
+}
@@ -0,0 +1,477 @@
+//! SQLite-backed country data for `--database`.
+//!
+//! Each country is a row in `countries` keyed by its canonical name, every extra name
+//! it resolves under is a row in `aliases`, each display name is a row in
+//! `localized_names`, its bounding box, if it has one, is a row in `bounding_boxes`, each
+//! official language is a row in `languages`, each time zone is a row in `timezones` and
+//! each country it shares a land border with is a row in `borders`, all pointing back at
+//! it:
+//!
+//! ```sql
+//! CREATE TABLE countries (name TEXT PRIMARY KEY, flag TEXT, currency_code TEXT, phone_code TEXT, tld TEXT, continent TEXT, lat REAL, lng REAL);
+//! CREATE TABLE aliases (alias TEXT PRIMARY KEY, country TEXT REFERENCES countries (name), kind TEXT);
+//! CREATE TABLE localized_names (country TEXT REFERENCES countries (name), language TEXT, name TEXT);
+//! CREATE TABLE bounding_boxes (country TEXT REFERENCES countries (name), min_lat REAL, min_lng REAL, max_lat REAL, max_lng REAL);
+//! CREATE TABLE languages (country TEXT REFERENCES countries (name), language TEXT);
+//! CREATE TABLE timezones (country TEXT REFERENCES countries (name), zone TEXT);
+//! CREATE TABLE borders (country TEXT REFERENCES countries (name), neighbour TEXT);
+//! ```
+//!
+//! `rustincountry migrate --database <path>` creates the tables and seeds them with the
+//! built-in dataset. An alias's `kind` is `current`, `historical` or `colloquial`, and a
+//! country's `tld`, `continent`, `lat` and `lng` are NULL when it has none; `neighbour` is
+//! an alpha-2 code. In databases created before those columns existed every alias reads
+//! as current and no country has a TLD, continent or coordinates, and without the
+//! `languages`, `timezones` or `borders` table no country has official languages, time
+//! zones or borders; `migrate` adds the columns and tables to them. A country's languages
+//! and time zones are listed in the order their rows were inserted. Rows go through the
+//! same normalization and validation as the admin API, so a database that would not load
+//! as a data file does not load either.
+
+use rusqlite::{params, Connection, OpenFlags};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    bounding_box::BoundingBox,
+    builtin,
+    codes::{Alpha2, CurrencyCode},
+    dataset::{Alias, Continent, CountryRecord, Dataset, RecordError},
+    source::{self, DatasetLoader, LoadError},
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS countries (
+    name TEXT PRIMARY KEY NOT NULL,
+    flag TEXT NOT NULL,
+    currency_code TEXT NOT NULL,
+    phone_code TEXT NOT NULL,
+    tld TEXT,
+    continent TEXT,
+    lat REAL,
+    lng REAL
+);
+CREATE TABLE IF NOT EXISTS aliases (
+    alias TEXT PRIMARY KEY NOT NULL,
+    country TEXT NOT NULL REFERENCES countries (name) ON DELETE CASCADE,
+    kind TEXT NOT NULL DEFAULT 'current'
+);
+CREATE TABLE IF NOT EXISTS localized_names (
+    country TEXT NOT NULL REFERENCES countries (name) ON DELETE CASCADE,
+    language TEXT NOT NULL,
+    name TEXT NOT NULL,
+    PRIMARY KEY (country, language)
+);
+CREATE TABLE IF NOT EXISTS bounding_boxes (
+    country TEXT PRIMARY KEY NOT NULL REFERENCES countries (name) ON DELETE CASCADE,
+    min_lat REAL NOT NULL,
+    min_lng REAL NOT NULL,
+    max_lat REAL NOT NULL,
+    max_lng REAL NOT NULL
+);
+CREATE TABLE IF NOT EXISTS languages (
+    country TEXT NOT NULL REFERENCES countries (name) ON DELETE CASCADE,
+    language TEXT NOT NULL,
+    PRIMARY KEY (country, language)
+);
+CREATE TABLE IF NOT EXISTS timezones (
+    country TEXT NOT NULL REFERENCES countries (name) ON DELETE CASCADE,
+    zone TEXT NOT NULL,
+    PRIMARY KEY (country, zone)
+);
+CREATE TABLE IF NOT EXISTS borders (
+    country TEXT NOT NULL REFERENCES countries (name) ON DELETE CASCADE,
+    neighbour TEXT NOT NULL,
+    PRIMARY KEY (country, neighbour)
+);
+";
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    Sqlite(rusqlite::Error),
+    InvalidCountry { name: String, error: RecordError },
+    UnknownCountry { alias: String, country: String },
+}
+
+impl From<rusqlite::Error> for DatabaseError {
+    fn from(error: rusqlite::Error) -> Self {
+        DatabaseError::Sqlite(error)
+    }
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Sqlite(error) => error.fmt(f),
+            DatabaseError::InvalidCountry { name, error } => {
+                write!(f, "country {:?}: {}", name, error)
+            }
+            DatabaseError::UnknownCountry { alias, country } => write!(
+                f,
+                "alias {:?} refers to unknown country {:?}",
+                alias, country
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DatabaseError::Sqlite(error) => Some(error),
+            DatabaseError::InvalidCountry { .. } | DatabaseError::UnknownCountry { .. } => None,
+        }
+    }
+}
+
+pub fn create_schema(connection: &Connection) -> rusqlite::Result<()> {
+    connection.execute_batch(SCHEMA)?;
+
+    // `CREATE TABLE IF NOT EXISTS` leaves older tables as they were
+    if !has_column(connection, "aliases", "kind")? {
+        connection
+            .execute_batch("ALTER TABLE aliases ADD COLUMN kind TEXT NOT NULL DEFAULT 'current'")?;
+    }
+    for (column, column_type) in [
+        ("tld", "TEXT"),
+        ("continent", "TEXT"),
+        ("lat", "REAL"),
+        ("lng", "REAL"),
+    ] {
+        if !has_column(connection, "countries", column)? {
+            connection.execute_batch(&format!(
+                "ALTER TABLE countries ADD COLUMN {} {}",
+                column, column_type
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+// Creates the schema and, if the database has no countries yet, seeds it with the
+// built-in dataset. Returns how many countries were seeded.
+pub fn migrate(connection: &mut Connection) -> rusqlite::Result<usize> {
+    let transaction = connection.transaction()?;
+    create_schema(&transaction)?;
+
+    let existing: usize =
+        transaction.query_row("SELECT COUNT(*) FROM countries", [], |row| row.get(0))?;
+    if existing > 0 {
+        // Keeps any tables and columns the schema gained
+        transaction.commit()?;
+        return Ok(0);
+    }
+
+    let dataset = builtin::dataset();
+    {
+        let mut insert_country = transaction.prepare(
+            "INSERT INTO countries (name, flag, currency_code, phone_code, tld, continent, lat, lng) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        let mut insert_alias = transaction
+            .prepare("INSERT INTO aliases (alias, country, kind) VALUES (?1, ?2, ?3)")?;
+        let mut insert_name = transaction
+            .prepare("INSERT INTO localized_names (country, language, name) VALUES (?1, ?2, ?3)")?;
+        let mut insert_language =
+            transaction.prepare("INSERT INTO languages (country, language) VALUES (?1, ?2)")?;
+        let mut insert_timezone =
+            transaction.prepare("INSERT INTO timezones (country, zone) VALUES (?1, ?2)")?;
+        let mut insert_border =
+            transaction.prepare("INSERT INTO borders (country, neighbour) VALUES (?1, ?2)")?;
+        let mut insert_box = transaction.prepare(
+            "INSERT INTO bounding_boxes (country, min_lat, min_lng, max_lat, max_lng) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for record in dataset.records() {
+            insert_country.execute(params![
+                record.name,
+                record.flag,
+                record.currency_code.as_str(),
+                record.phone_code,
+                record.tld,
+                record.continent.as_ref().map(Continent::as_str),
+                record.latlng.map(|[lat, _]| lat),
+                record.latlng.map(|[_, lng]| lng)
+            ])?;
+            for alias in &record.aliases {
+                insert_alias.execute(params![alias.name, record.name, alias.kind.as_str()])?;
+            }
+            for (language, name) in &record.localized_names {
+                insert_name.execute(params![record.name, language, name])?;
+            }
+            for language in &record.languages {
+                insert_language.execute(params![record.name, language])?;
+            }
+            for zone in &record.timezones {
+                insert_timezone.execute(params![record.name, zone])?;
+            }
+            for neighbour in &record.borders {
+                insert_border.execute(params![record.name, neighbour.as_str()])?;
+            }
+            if let Some(bounding_box) = &record.bounding_box {
+                insert_box.execute(params![
+                    record.name,
+                    bounding_box.min_lat,
+                    bounding_box.min_lng,
+                    bounding_box.max_lat,
+                    bounding_box.max_lng
+                ])?;
+            }
+        }
+    }
+    transaction.commit()?;
+
+    Ok(dataset.len())
+}
+
+fn has_table(connection: &Connection, table: &str) -> rusqlite::Result<bool> {
+    connection
+        .prepare("SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1")?
+        .exists(params![table])
+}
+
+// Country -> `column` of its rows in `table`, in insertion order. Read-only databases
+// from before the table existed have none.
+fn read_lists(
+    connection: &Connection,
+    table: &str,
+    column: &str,
+) -> rusqlite::Result<HashMap<String, Vec<String>>> {
+    let mut lists: HashMap<String, Vec<String>> = HashMap::new();
+    if !has_table(connection, table)? {
+        return Ok(lists);
+    }
+    let mut statement = connection.prepare(&format!(
+        "SELECT country, {} FROM {} ORDER BY rowid",
+        column, table
+    ))?;
+    let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    for row in rows {
+        let (country, value): (String, String) = row?;
+        lists.entry(country).or_default().push(value);
+    }
+    Ok(lists)
+}
+
+fn has_column(connection: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    connection
+        .prepare("SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2")?
+        .exists(params![table, column])
+}
+
+// A `countries` row: name, flag, currency code, phone code, TLD, continent and
+// [lat, lng]
+type CountryRow = (
+    String,
+    String,
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+    Option<[f64; 2]>,
+);
+
+// Rows in name order with their aliases attached, before any normalization or validation
+pub fn read_records(connection: &Connection) -> Result<Vec<CountryRecord>, DatabaseError> {
+    let mut aliases: HashMap<String, Vec<(String, String)>> = HashMap::new();
+    let mut statement = connection.prepare(if has_column(connection, "aliases", "kind")? {
+        "SELECT alias, country, kind FROM aliases ORDER BY alias"
+    } else {
+        "SELECT alias, country, 'current' FROM aliases ORDER BY alias"
+    })?;
+    let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    for row in rows {
+        let (alias, country, kind): (String, String, String) = row?;
+        aliases.entry(country).or_default().push((alias, kind));
+    }
+
+    let mut localized_names: HashMap<String, BTreeMap<String, String>> = HashMap::new();
+    let mut statement =
+        connection.prepare("SELECT country, language, name FROM localized_names")?;
+    let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    for row in rows {
+        let (country, language, name): (String, String, String) = row?;
+        localized_names
+            .entry(country)
+            .or_default()
+            .insert(language, name);
+    }
+
+    let mut bounding_boxes: HashMap<String, BoundingBox> = HashMap::new();
+    let mut statement = connection
+        .prepare("SELECT country, min_lat, min_lng, max_lat, max_lng FROM bounding_boxes")?;
+    let rows = statement.query_map([], |row| {
+        Ok((
+            row.get(0)?,
+            BoundingBox {
+                min_lat: row.get(1)?,
+                min_lng: row.get(2)?,
+                max_lat: row.get(3)?,
+                max_lng: row.get(4)?,
+            },
+        ))
+    })?;
+    for row in rows {
+        let (country, bounding_box): (String, BoundingBox) = row?;
+        bounding_boxes.insert(country, bounding_box);
+    }
+
+    let mut languages = read_lists(connection, "languages", "language")?;
+    let mut timezones = read_lists(connection, "timezones", "zone")?;
+    let mut borders = read_lists(connection, "borders", "neighbour")?;
+
+    // Columns older databases lack read as NULL
+    let mut optional_columns = Vec::new();
+    for column in ["tld", "continent", "lat", "lng"] {
+        optional_columns.push(if has_column(connection, "countries", column)? {
+            column
+        } else {
+            "NULL"
+        });
+    }
+    let mut statement = connection.prepare(&format!(
+        "SELECT name, flag, currency_code, phone_code, {} FROM countries ORDER BY name",
+        optional_columns.join(", ")
+    ))?;
+    let rows = statement.query_map([], |row| {
+        Ok((
+            row.get(0)?,
+            row.get(1)?,
+            row.get(2)?,
+            row.get(3)?,
+            row.get(4)?,
+            row.get(5)?,
+            row.get::<_, Option<f64>>(6)?
+                .zip(row.get::<_, Option<f64>>(7)?)
+                .map(|(lat, lng)| [lat, lng]),
+        ))
+    })?;
+    let mut records = Vec::new();
+    for row in rows {
+        let (name, flag, currency_code, phone_code, tld, continent, latlng): CountryRow = row?;
+        let currency_code =
+            CurrencyCode::new(&currency_code).map_err(|error| DatabaseError::InvalidCountry {
+                name: name.clone(),
+                error: RecordError::invalid("currencyCode", &error.to_string()),
+            })?;
+        let aliases = aliases
+            .remove(&name)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(alias, kind)| {
+                let kind =
+                    kind.parse()
+                        .map_err(|reason: String| DatabaseError::InvalidCountry {
+                            name: name.clone(),
+                            error: RecordError::invalid("aliases", &reason),
+                        })?;
+                Ok(Alias { name: alias, kind })
+            })
+            .collect::<Result<_, DatabaseError>>()?;
+        let continent = continent
+            .map(|continent| continent.parse())
+            .transpose()
+            .map_err(|reason: String| DatabaseError::InvalidCountry {
+                name: name.clone(),
+                error: RecordError::invalid("continent", &reason),
+            })?;
+        let borders = borders
+            .remove(&name)
+            .unwrap_or_default()
+            .iter()
+            .map(|code| Alpha2::new(code))
+            .collect::<Result<_, _>>()
+            .map_err(|error| DatabaseError::InvalidCountry {
+                name: name.clone(),
+                error: RecordError::invalid("borders", &error.to_string()),
+            })?;
+        records.push(CountryRecord {
+            aliases,
+            localized_names: localized_names.remove(&name).unwrap_or_default(),
+            bounding_box: bounding_boxes.remove(&name),
+            languages: languages.remove(&name).unwrap_or_default(),
+            timezones: timezones.remove(&name).unwrap_or_default(),
+            latlng,
+            borders,
+            name,
+            flag,
+            currency_code,
+            phone_code,
+            tld,
+            continent,
+        });
+    }
+
+    // Without foreign key enforcement an alias can outlive its country. Leftover
+    // localized names, bounding boxes, languages, time zones and borders are harmless, so
+    // only aliases are reported.
+    if let Some((country, mut names)) = aliases.into_iter().next() {
+        return Err(DatabaseError::UnknownCountry {
+            alias: names.swap_remove(0).0,
+            country,
+        });
+    }
+
+    Ok(records)
+}
+
+pub fn load_countries(connection: &Connection) -> Result<Dataset, DatabaseError> {
+    let mut dataset = Dataset::default();
+    for record in read_records(connection)? {
+        let name = record.name.clone();
+        let record = record.normalized();
+
+        record
+            .validate()
+            .and_then(|()| dataset.insert(record))
+            .map_err(|error| DatabaseError::InvalidCountry { name, error })?;
+    }
+
+    Ok(dataset)
+}
+
+// A SQLite database passed with `--database`, opened read-only on every load
+#[derive(Debug, Clone)]
+pub struct SqliteLoader {
+    path: PathBuf,
+}
+
+impl SqliteLoader {
+    pub fn new(path: &Path) -> Self {
+        SqliteLoader {
+            path: path.to_path_buf(),
+        }
+    }
+
+    fn open(&self) -> Result<Connection, LoadError> {
+        Connection::open_with_flags(&self.path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|error| self.error(DatabaseError::Sqlite(error)))
+    }
+
+    fn error(&self, source: DatabaseError) -> LoadError {
+        LoadError::Database {
+            path: self.path.clone(),
+            source,
+        }
+    }
+}
+
+impl DatasetLoader for SqliteLoader {
+    fn describe(&self) -> String {
+        format!("database {}", self.path.display())
+    }
+
+    fn load(&self) -> Result<Dataset, LoadError> {
+        load_countries(&self.open()?).map_err(|source| self.error(source))
+    }
+
+    fn records(&self) -> Result<Vec<CountryRecord>, LoadError> {
+        read_records(&self.open()?).map_err(|source| self.error(source))
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        source::file_modified(&self.path)
+    }
+}
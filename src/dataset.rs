@@ -0,0 +1,759 @@
+//! In-memory country dataset shared by the lookup handlers and the admin endpoints.
+//!
+//! Every country is stored once as a shared record; its canonical name and aliases all
+//! point at that same record through a lookup index, so the names of a country can
+//! never drift apart and adding or removing a country keeps all of them consistent.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::Arc,
+};
+
+use crate::{
+    bounding_box::BoundingBox,
+    builtin,
+    calling_code::CallingCodes,
+    codes::{Alpha2, CurrencyCode},
+    language,
+    normalize::normalize,
+    timezone, tld, CountryData,
+};
+
+const REGIONAL_INDICATORS: std::ops::RangeInclusive<char> = '\u{1F1E6}'..='\u{1F1FF}';
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(deny_unknown_fields)]
+pub struct CountryRecord {
+    pub name: String,
+    #[serde(default)]
+    #[cfg_attr(feature = "server", schema(value_type = Vec<AliasEntry>))]
+    pub aliases: Vec<Alias>,
+    // Display names keyed by language code, e.g. {"en": "Japan", "ja": "日本"}
+    #[serde(
+        default,
+        rename = "localizedNames",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub localized_names: BTreeMap<String, String>,
+    pub flag: String,
+    #[serde(rename = "currencyCode")]
+    #[cfg_attr(feature = "server", schema(value_type = String, example = "JPY"))]
+    pub currency_code: CurrencyCode,
+    #[serde(rename = "phoneCode")]
+    pub phone_code: String,
+    // Left out by sources without geography, which `/byCoordinates` then never returns
+    #[serde(
+        default,
+        rename = "boundingBox",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub bounding_box: Option<BoundingBox>,
+    // Country-code top-level domain such as ".jp"; left out for countries without one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "server", schema(example = ".jp"))]
+    pub tld: Option<String>,
+    // Left out by sources without geography, whose countries `/stats` counts as unknown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continent: Option<Continent>,
+    // Official languages as lowercase ISO 639 codes, most used first, e.g. ["de", "fr"]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[cfg_attr(feature = "server", schema(example = json!(["ja"])))]
+    pub languages: Vec<String>,
+    // IANA time zones in use, under the tz database's spelling, e.g. ["Asia/Tokyo"]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[cfg_attr(feature = "server", schema(example = json!(["Asia/Tokyo"])))]
+    pub timezones: Vec<String>,
+    // A point inside the country as [latitude, longitude] in degrees, for maps; left out
+    // by sources without geography
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "server", schema(example = json!([36.0, 138.0])))]
+    pub latlng: Option<[f64; 2]>,
+    // Alpha-2 codes of the countries sharing a land border, sorted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[cfg_attr(feature = "server", schema(value_type = Vec<String>, example = json!(["KR", "RU"])))]
+    pub borders: Vec<Alpha2>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum Continent {
+    Africa,
+    // The subantarctic islands, which have no permanent population
+    Antarctica,
+    Asia,
+    Europe,
+    // Central America and the Caribbean included
+    NorthAmerica,
+    Oceania,
+    SouthAmerica,
+}
+
+impl Continent {
+    pub const ALL: [Continent; 7] = [
+        Continent::Africa,
+        Continent::Antarctica,
+        Continent::Asia,
+        Continent::Europe,
+        Continent::NorthAmerica,
+        Continent::Oceania,
+        Continent::SouthAmerica,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Continent::Africa => "africa",
+            Continent::Antarctica => "antarctica",
+            Continent::Asia => "asia",
+            Continent::Europe => "europe",
+            Continent::NorthAmerica => "northAmerica",
+            Continent::Oceania => "oceania",
+            Continent::SouthAmerica => "southAmerica",
+        }
+    }
+}
+
+impl std::str::FromStr for Continent {
+    type Err = String;
+
+    fn from_str(continent: &str) -> Result<Self, String> {
+        Continent::ALL
+            .into_iter()
+            .find(|known| known.as_str() == continent)
+            .ok_or_else(|| format!("{:?} is not a continent", continent))
+    }
+}
+
+// How current a name of a country is, served as `nameStatus`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum AliasKind {
+    // The canonical name, and alternatives in current use such as "usa"
+    #[default]
+    Current,
+    // A former name, such as "burma" for Myanmar
+    Historical,
+    // An informal name, such as "holland" for the Netherlands
+    Colloquial,
+}
+
+impl AliasKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AliasKind::Current => "current",
+            AliasKind::Historical => "historical",
+            AliasKind::Colloquial => "colloquial",
+        }
+    }
+}
+
+impl std::str::FromStr for AliasKind {
+    type Err = String;
+
+    fn from_str(kind: &str) -> Result<Self, String> {
+        match kind {
+            "current" => Ok(AliasKind::Current),
+            "historical" => Ok(AliasKind::Historical),
+            "colloquial" => Ok(AliasKind::Colloquial),
+            other => Err(format!(
+                "{:?} is not `current`, `historical` or `colloquial`",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "AliasEntry", into = "AliasEntry")]
+pub struct Alias {
+    pub name: String,
+    pub kind: AliasKind,
+}
+
+impl Alias {
+    pub fn current(name: impl Into<String>) -> Self {
+        Alias {
+            name: name.into(),
+            kind: AliasKind::Current,
+        }
+    }
+}
+
+/// An alias as data files write it: a plain string for a current name, so files
+/// without alias kinds keep working, or the name with its kind.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(untagged)]
+pub enum AliasEntry {
+    Current(String),
+    Kind { name: String, kind: AliasKind },
+}
+
+impl From<AliasEntry> for Alias {
+    fn from(entry: AliasEntry) -> Self {
+        match entry {
+            AliasEntry::Current(name) => Alias::current(name),
+            AliasEntry::Kind { name, kind } => Alias { name, kind },
+        }
+    }
+}
+
+impl From<Alias> for AliasEntry {
+    fn from(alias: Alias) -> Self {
+        match alias.kind {
+            AliasKind::Current => AliasEntry::Current(alias.name),
+            kind => AliasEntry::Kind {
+                name: alias.name,
+                kind,
+            },
+        }
+    }
+}
+
+impl CountryRecord {
+    // Normalizes names the way lookups do, dropping aliases that repeat a name; of two
+    // aliases with the same name the first is kept
+    pub fn normalized(self) -> Self {
+        let name = normalize(&self.name).into_owned();
+        let mut aliases: Vec<Alias> = Vec::new();
+        for alias in self.aliases {
+            let alias = Alias {
+                name: normalize(&alias.name).into_owned(),
+                kind: alias.kind,
+            };
+            if alias.name != name && !aliases.iter().any(|other| other.name == alias.name) {
+                aliases.push(alias);
+            }
+        }
+
+        CountryRecord {
+            name,
+            aliases,
+            localized_names: self
+                .localized_names
+                .into_iter()
+                .map(|(language, name)| (language_code(&language), name.trim().to_string()))
+                .collect(),
+            flag: self.flag.trim().to_string(),
+            currency_code: self.currency_code,
+            phone_code: self.phone_code.trim().to_string(),
+            bounding_box: self.bounding_box,
+            tld: self.tld.as_deref().and_then(tld::normalize),
+            continent: self.continent,
+            languages: normalized_languages(self.languages),
+            timezones: normalized_timezones(self.timezones),
+            latlng: self.latlng,
+            borders: {
+                let mut borders = self.borders;
+                borders.sort();
+                borders.dedup();
+                borders
+            },
+        }
+    }
+
+    pub fn lookup_names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.name.as_str())
+            .chain(self.aliases.iter().map(|alias| alias.name.as_str()))
+    }
+
+    // How current `name`, one of the lookup names, is; the canonical name is current
+    pub fn name_status(&self, name: &str) -> AliasKind {
+        self.aliases
+            .iter()
+            .find(|alias| alias.name == name)
+            .map_or(AliasKind::Current, |alias| alias.kind)
+    }
+
+    pub fn validate(&self) -> Result<(), RecordError> {
+        for (field, value) in [
+            ("name", &self.name),
+            ("flag", &self.flag),
+            ("phoneCode", &self.phone_code),
+        ] {
+            if value.trim().is_empty() {
+                return Err(RecordError::invalid(field, "must not be empty"));
+            }
+        }
+        if self
+            .aliases
+            .iter()
+            .any(|alias| alias.name.trim().is_empty())
+        {
+            return Err(RecordError::invalid(
+                "aliases",
+                "must not contain empty names",
+            ));
+        }
+        if self
+            .localized_names
+            .iter()
+            .any(|(language, name)| language.trim().is_empty() || name.trim().is_empty())
+        {
+            return Err(RecordError::invalid(
+                "localizedNames",
+                "must not contain empty languages or names",
+            ));
+        }
+        if !is_flag_emoji(&self.flag) {
+            return Err(RecordError::invalid(
+                "flag",
+                "must be exactly two regional indicator symbols",
+            ));
+        }
+        if let Some(bounding_box) = &self.bounding_box {
+            bounding_box
+                .validate()
+                .map_err(|reason| RecordError::invalid("boundingBox", reason))?;
+        }
+        if self.tld.as_deref().is_some_and(|tld| !tld::is_tld(tld)) {
+            return Err(RecordError::invalid(
+                "tld",
+                "must be a dot followed by letters, such as `.jp`",
+            ));
+        }
+        if !self.languages.iter().all(|code| language::is_code(code)) {
+            return Err(RecordError::invalid(
+                "languages",
+                "must be ISO 639 codes, such as `fr`",
+            ));
+        }
+        if !self
+            .timezones
+            .iter()
+            .all(|zone| timezone::canonical(zone).is_some())
+        {
+            return Err(RecordError::invalid(
+                "timezones",
+                "must be IANA time zones, such as `Europe/Paris`",
+            ));
+        }
+        if let Some(latlng) = self.latlng {
+            validate_latlng(latlng).map_err(|reason| RecordError::invalid("latlng", reason))?;
+        }
+
+        Ok(())
+    }
+
+    // Falls back to English, then to the canonical name, when a translation is missing
+    pub fn localized_name(&self, language: &str) -> &str {
+        self.localized_names
+            .get(language)
+            .or_else(|| self.localized_names.get(DEFAULT_LANGUAGE))
+            .unwrap_or(&self.name)
+    }
+}
+
+// Lowercased and trimmed, without blanks or repeats, in the order given
+fn normalized_languages(languages: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(languages.len());
+    for code in languages {
+        let code = code.trim().to_lowercase();
+        if !code.is_empty() && !normalized.contains(&code) {
+            normalized.push(code);
+        }
+    }
+    normalized
+}
+
+// [latitude, longitude] within -90 to 90 and -180 to 180 degrees
+pub fn validate_latlng([lat, lng]: [f64; 2]) -> Result<(), &'static str> {
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lng) {
+        return Err("must be [latitude, longitude] within -90 to 90 and -180 to 180");
+    }
+    Ok(())
+}
+
+// Spelled as the tz database does where it knows the zone, otherwise trimmed for
+// `validate` to reject, without blanks or repeats, in the order given
+fn normalized_timezones(timezones: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(timezones.len());
+    for zone in timezones {
+        let zone = timezone::canonical(&zone).map_or_else(|| zone.trim().to_string(), String::from);
+        if !zone.is_empty() && !normalized.contains(&zone) {
+            normalized.push(zone);
+        }
+    }
+    normalized
+}
+
+// Lowercased primary subtag of a language tag, so `ja-JP` and `JA` both select `ja`
+pub fn language_code(tag: &str) -> String {
+    tag.split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase()
+}
+
+pub fn is_regional_indicator(c: char) -> bool {
+    REGIONAL_INDICATORS.contains(&c)
+}
+
+pub fn is_flag_emoji(flag: &str) -> bool {
+    let mut chars = flag.chars();
+    matches!(
+        (chars.next(), chars.next(), chars.next()),
+        (Some(first), Some(second), None)
+            if REGIONAL_INDICATORS.contains(&first) && REGIONAL_INDICATORS.contains(&second)
+    )
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordError {
+    Invalid { field: &'static str, reason: String },
+    Conflict { name: String },
+    NotFound { name: String },
+}
+
+impl RecordError {
+    pub fn invalid(field: &'static str, reason: &str) -> Self {
+        RecordError::Invalid {
+            field,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::Invalid { field, reason } => write!(f, "`{}` {}", field, reason),
+            RecordError::Conflict { name } => {
+                write!(f, "country name or alias {:?} is already in use", name)
+            }
+            RecordError::NotFound { name } => write!(f, "no country is named {:?}", name),
+        }
+    }
+}
+
+// Canonical names that differ between two datasets, each list sorted
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DatasetDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Dataset {
+    storage: Storage,
+}
+
+#[derive(Debug, Clone)]
+enum Storage {
+    // The generated built-in table, read through its compile-time index until the
+    // first change copies it into owned collections
+    Builtin,
+    Owned {
+        records: Vec<Arc<CountryRecord>>,
+        // Every lookup name (canonical names and aliases) -> the record's shared copy
+        index: HashMap<String, Arc<CountryRecord>>,
+        calling_codes: CallingCodes,
+        // The alpha-2 code each flag spells -> the records with that flag, in the order
+        // they were added
+        codes: HashMap<Alpha2, Vec<Arc<CountryRecord>>>,
+        // Each TLD -> the records with it, in the order they were added
+        tlds: HashMap<String, Vec<Arc<CountryRecord>>>,
+    },
+}
+
+type OwnedStorage<'a> = (
+    &'a mut Vec<Arc<CountryRecord>>,
+    &'a mut HashMap<String, Arc<CountryRecord>>,
+    &'a mut CallingCodes,
+    &'a mut HashMap<Alpha2, Vec<Arc<CountryRecord>>>,
+    &'a mut HashMap<String, Vec<Arc<CountryRecord>>>,
+);
+
+impl Default for Dataset {
+    fn default() -> Self {
+        Dataset {
+            storage: Storage::Owned {
+                records: Vec::new(),
+                index: HashMap::new(),
+                calling_codes: CallingCodes::default(),
+                codes: HashMap::new(),
+                tlds: HashMap::new(),
+            },
+        }
+    }
+}
+
+// Aliases are part of each record, so equal records mean equal lookups
+impl PartialEq for Dataset {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .records()
+                .all(|record| other.canonical(&record.name) == Some(record))
+    }
+}
+
+impl Dataset {
+    pub fn builtin() -> Self {
+        Dataset {
+            storage: Storage::Builtin,
+        }
+    }
+
+    // Legacy flat data has no alias information, so every name becomes its own record.
+    // Names that differ only in case or apostrophes normalize to the same one and conflict.
+    pub fn from_flat(data: CountryData) -> Result<Self, RecordError> {
+        let mut entries: Vec<_> = data.into_iter().collect();
+        // Sorted, so the same file always reports the same conflict
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut dataset = Dataset::default();
+        for (name, (flag, currency_code, phone_code)) in entries {
+            dataset.insert(CountryRecord {
+                name: normalize(&name).into_owned(),
+                aliases: Vec::new(),
+                localized_names: BTreeMap::new(),
+                flag,
+                currency_code,
+                phone_code,
+                bounding_box: None,
+                tld: None,
+                continent: None,
+                languages: Vec::new(),
+                timezones: Vec::new(),
+                latlng: None,
+                borders: Vec::new(),
+            })?;
+        }
+        Ok(dataset)
+    }
+
+    // The collections to edit in place, copying the built-in table into them first if
+    // needed; the records themselves are shared, not copied
+    fn owned(&mut self) -> OwnedStorage<'_> {
+        if let Storage::Builtin = self.storage {
+            let records: Vec<_> = (0..builtin::BUILTIN_COUNT)
+                .map(|position| Arc::clone(builtin::record(position)))
+                .collect();
+            let mut index = HashMap::new();
+            for record in &records {
+                for name in record.lookup_names() {
+                    index.insert(name.to_string(), Arc::clone(record));
+                }
+            }
+            let calling_codes = builtin::calling_codes().clone();
+            let mut codes: HashMap<Alpha2, Vec<_>> = HashMap::new();
+            for record in &records {
+                if let Some(alpha2) = Alpha2::from_flag(&record.flag) {
+                    codes.entry(alpha2).or_default().push(Arc::clone(record));
+                }
+            }
+            let tlds = builtin::tlds().clone();
+            self.storage = Storage::Owned {
+                records,
+                index,
+                calling_codes,
+                codes,
+                tlds,
+            };
+        }
+
+        match &mut self.storage {
+            Storage::Owned {
+                records,
+                index,
+                calling_codes,
+                codes,
+                tlds,
+            } => (records, index, calling_codes, codes, tlds),
+            Storage::Builtin => unreachable!("the built-in table was just copied"),
+        }
+    }
+
+    // Expects a normalized record; fails without modifying the dataset if any name is taken
+    pub fn insert(&mut self, record: CountryRecord) -> Result<(), RecordError> {
+        if let Some(name) = record.lookup_names().find(|name| self.get(name).is_some()) {
+            return Err(RecordError::Conflict {
+                name: name.to_string(),
+            });
+        }
+
+        let record = Arc::new(record);
+        let (records, index, calling_codes, codes, tlds) = self.owned();
+        for name in record.lookup_names() {
+            index.insert(name.to_string(), Arc::clone(&record));
+        }
+        calling_codes.insert(&record);
+        if let Some(alpha2) = Alpha2::from_flag(&record.flag) {
+            codes.entry(alpha2).or_default().push(Arc::clone(&record));
+        }
+        if let Some(tld) = &record.tld {
+            tlds.entry(tld.clone())
+                .or_default()
+                .push(Arc::clone(&record));
+        }
+        records.push(record);
+        Ok(())
+    }
+
+    // Removes the country any of whose names matches, together with all of its aliases
+    pub fn remove(&mut self, name: &str) -> Option<CountryRecord> {
+        let record = Arc::clone(self.shared(name)?);
+        let (records, index, calling_codes, codes, tlds) = self.owned();
+        records.retain(|other| !Arc::ptr_eq(other, &record));
+        for name in record.lookup_names() {
+            index.remove(name);
+        }
+        calling_codes.remove(&record);
+        if let Some(alpha2) = Alpha2::from_flag(&record.flag) {
+            if let Some(flagged) = codes.get_mut(&alpha2) {
+                flagged.retain(|other| !Arc::ptr_eq(other, &record));
+                if flagged.is_empty() {
+                    codes.remove(&alpha2);
+                }
+            }
+        }
+        if let Some(tld) = &record.tld {
+            if let Some(with_tld) = tlds.get_mut(tld) {
+                with_tld.retain(|other| !Arc::ptr_eq(other, &record));
+                if with_tld.is_empty() {
+                    tlds.remove(tld);
+                }
+            }
+        }
+        Some(Arc::unwrap_or_clone(record))
+    }
+
+    // Swaps the country named `name` for `record`, returning the old record. On a
+    // conflict with another country the dataset is left unchanged.
+    pub fn replace(
+        &mut self,
+        name: &str,
+        record: CountryRecord,
+    ) -> Result<CountryRecord, RecordError> {
+        let previous = self.remove(name).ok_or_else(|| RecordError::NotFound {
+            name: name.to_string(),
+        })?;
+        if let Err(error) = self.insert(record) {
+            self.insert(previous)
+                .expect("restoring a just-removed record cannot conflict");
+            return Err(error);
+        }
+        Ok(previous)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CountryRecord> {
+        self.shared(name).map(Arc::as_ref)
+    }
+
+    // The record `name` resolves to; every name of a country resolves to the same one
+    pub fn shared(&self, name: &str) -> Option<&Arc<CountryRecord>> {
+        match &self.storage {
+            Storage::Builtin => builtin::lookup(name),
+            Storage::Owned { index, .. } => index.get(name),
+        }
+    }
+
+    // The countries dialed with the longest calling code `digits` starts with
+    pub fn by_calling_code(&self, digits: &str) -> &[Arc<CountryRecord>] {
+        match &self.storage {
+            Storage::Builtin => builtin::calling_codes().resolve(digits),
+            Storage::Owned { calling_codes, .. } => calling_codes.resolve(digits),
+        }
+    }
+
+    // The first country added whose flag spells `alpha2`
+    pub fn by_alpha2(&self, alpha2: Alpha2) -> Option<&CountryRecord> {
+        match &self.storage {
+            Storage::Builtin => builtin::record_by_alpha2(alpha2).map(Arc::as_ref),
+            Storage::Owned { codes, .. } => codes
+                .get(&alpha2)
+                .and_then(|flagged| flagged.first())
+                .map(Arc::as_ref),
+        }
+    }
+
+    // The countries whose TLD is `tld`, normalized as `tld::normalize` does, in the
+    // order they were added
+    pub fn by_tld(&self, tld: &str) -> &[Arc<CountryRecord>] {
+        let tlds = match &self.storage {
+            Storage::Builtin => builtin::tlds(),
+            Storage::Owned { tlds, .. } => tlds,
+        };
+        tlds.get(tld).map_or(&[], Vec::as_slice)
+    }
+
+    // The record whose canonical name is `name`, ignoring aliases
+    fn canonical(&self, name: &str) -> Option<&CountryRecord> {
+        self.get(name).filter(|record| record.name == name)
+    }
+
+    // Every lookup name with the record it resolves to, in no particular order
+    pub fn names(&self) -> Box<dyn Iterator<Item = (&str, &CountryRecord)> + '_> {
+        match &self.storage {
+            Storage::Builtin => Box::new(
+                builtin::BUILTIN_INDEX
+                    .entries()
+                    .map(|(name, &position)| (*name, builtin::record(position).as_ref())),
+            ),
+            Storage::Owned { index, .. } => Box::new(
+                index
+                    .iter()
+                    .map(|(name, record)| (name.as_str(), record.as_ref())),
+            ),
+        }
+    }
+
+    pub fn records(&self) -> Box<dyn Iterator<Item = &CountryRecord> + '_> {
+        match &self.storage {
+            Storage::Builtin => Box::new(
+                (0..builtin::BUILTIN_COUNT)
+                    .map(|position| -> &CountryRecord { builtin::record(position) }),
+            ),
+            Storage::Owned { records, .. } => Box::new(records.iter().map(Arc::as_ref)),
+        }
+    }
+
+    pub fn diff(&self, newer: &Dataset) -> DatasetDiff {
+        let mut diff = DatasetDiff::default();
+        for record in newer.records() {
+            match self.canonical(&record.name) {
+                None => diff.added.push(record.name.clone()),
+                Some(previous) if previous != record => diff.changed.push(record.name.clone()),
+                Some(_) => {}
+            }
+        }
+        diff.removed = self
+            .records()
+            .filter(|record| newer.canonical(&record.name).is_none())
+            .map(|record| record.name.clone())
+            .collect();
+
+        diff.added.sort();
+        diff.removed.sort();
+        diff.changed.sort();
+        diff
+    }
+
+    // Whether any country has a display name in `language`
+    pub fn has_language(&self, language: &str) -> bool {
+        language == DEFAULT_LANGUAGE
+            || self
+                .records()
+                .any(|record| record.localized_names.contains_key(language))
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.storage {
+            Storage::Builtin => builtin::BUILTIN_COUNT,
+            Storage::Owned { records, .. } => records.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
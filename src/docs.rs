@@ -0,0 +1,108 @@
+//! Swagger UI at `/docs` and ReDoc at `/redoc`, both rendering `/openapi.json`.
+//!
+//! Their scripts and stylesheets are vendored in `assets/docs` and compiled in, so the
+//! pages make no requests to other hosts. Every URL in them is relative, which keeps
+//! them working when the routes are nested under a prefix.
+
+use axum::{
+    http::header::{CACHE_CONTROL, CONTENT_TYPE},
+    response::{Html, IntoResponse},
+    routing::get,
+    Router,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha2::{Digest, Sha256};
+
+use crate::AppState;
+
+const SWAGGER_UI_JS: &[u8] = include_bytes!("../assets/docs/swagger-ui-bundle.js");
+const SWAGGER_UI_CSS: &[u8] = include_bytes!("../assets/docs/swagger-ui.css");
+const REDOC_JS: &[u8] = include_bytes!("../assets/docs/redoc.standalone.js");
+
+const JAVASCRIPT: &str = "text/javascript; charset=utf-8";
+const CSS: &str = "text/css; charset=utf-8";
+// The assets only change with the binary
+const ASSET_CACHE_CONTROL: &str = "public, max-age=86400";
+
+// `validatorUrl: null` keeps Swagger UI from sending the spec to validator.swagger.io
+const SWAGGER_UI_PAGE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta charset="utf-8" />
+    <meta name="viewport" content="width=device-width, initial-scale=1" />
+    <title>RustyCurrency API</title>
+    <link rel="stylesheet" href="docs/swagger-ui.css" />
+  </head>
+  <body>
+    <div id="swagger-ui"></div>
+    <script src="docs/swagger-ui-bundle.js"></script>
+    <script>
+      window.ui = SwaggerUIBundle({
+        url: "openapi.json",
+        dom_id: "#swagger-ui",
+        validatorUrl: null,
+      });
+    </script>
+  </body>
+</html>
+"##;
+
+const REDOC_PAGE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta charset="utf-8" />
+    <meta name="viewport" content="width=device-width, initial-scale=1" />
+    <title>RustyCurrency API</title>
+    <style>
+      body {
+        margin: 0;
+      }
+    </style>
+  </head>
+  <body>
+    <redoc spec-url="openapi.json"></redoc>
+    <script src="redoc/redoc.standalone.js"></script>
+  </body>
+</html>
+"##;
+
+pub(crate) fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/docs", get(|| async { Html(SWAGGER_UI_PAGE) }))
+        .route(
+            "/docs/swagger-ui-bundle.js",
+            get(|| async { asset(JAVASCRIPT, SWAGGER_UI_JS) }),
+        )
+        .route(
+            "/docs/swagger-ui.css",
+            get(|| async { asset(CSS, SWAGGER_UI_CSS) }),
+        )
+        .route("/redoc", get(|| async { Html(REDOC_PAGE) }))
+        .route(
+            "/redoc/redoc.standalone.js",
+            get(|| async { asset(JAVASCRIPT, REDOC_JS) }),
+        )
+}
+
+// The `script-src` source that lets the Swagger UI page's inline script run, and no
+// other inline script: its SHA-256 hash
+pub(crate) fn swagger_ui_script_source() -> String {
+    let (_, script) = SWAGGER_UI_PAGE
+        .rsplit_once("<script>")
+        .expect("the Swagger UI page has an inline script");
+    let (script, _) = script
+        .split_once("</script>")
+        .expect("the inline script is closed");
+    format!("'sha256-{}'", STANDARD.encode(Sha256::digest(script)))
+}
+
+fn asset(content_type: &'static str, body: &'static [u8]) -> impl IntoResponse {
+    (
+        [
+            (CONTENT_TYPE, content_type),
+            (CACHE_CONTROL, ASSET_CACHE_CONTROL),
+        ],
+        body,
+    )
+}
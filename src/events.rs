@@ -0,0 +1,88 @@
+//! `/events`: a Server-Sent Events stream with one `dataset` event per change to the
+//! served data, whether by an admin edit or a reload.
+//!
+//! Changes are published on a broadcast channel, and only computed while someone is
+//! subscribed. A subscriber that falls more than `CHANNEL_CAPACITY` events behind gets
+//! a `lagged` event in place of the ones it missed, and should refetch what it shows.
+
+use axum::response::sse::{Event, KeepAlive, Sse};
+use std::{convert::Infallible, time::Duration};
+use tokio::sync::{broadcast, watch};
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, WatchStream},
+    Stream, StreamExt,
+};
+
+use crate::{dataset::DatasetDiff, responses::DatasetEvent, revision::RevisionInfo};
+
+const CHANNEL_CAPACITY: usize = 64;
+// Idle streams get a comment this often, so proxies do not time them out
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug)]
+pub struct DatasetEvents {
+    sender: broadcast::Sender<DatasetEvent>,
+}
+
+impl Default for DatasetEvents {
+    fn default() -> Self {
+        DatasetEvents {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+impl DatasetEvents {
+    // Whether publishing would reach anyone, so callers can skip working out the diff
+    pub fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    pub fn publish(&self, revision: RevisionInfo, diff: DatasetDiff) {
+        // Fails only when nobody is subscribed, which is fine
+        let _ = self.sender.send(DatasetEvent {
+            dataset_version: revision.version,
+            dataset_modified: revision.modified_http_date(),
+            added: diff.added,
+            removed: diff.removed,
+            changed: diff.changed,
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DatasetEvent> {
+        self.sender.subscribe()
+    }
+
+    // The `/events` response, subscribed from the moment it is made. It ends once
+    // `stopping` turns true, so a shutdown need not wait for subscribers to leave.
+    pub(crate) fn stream(
+        &self,
+        stopping: Option<watch::Receiver<bool>>,
+    ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+        let stopping = stopping.unwrap_or_else(|| watch::channel(false).1);
+        let stop = WatchStream::new(stopping)
+            .filter(|stopping| *stopping)
+            .map(|_| None);
+        let events = BroadcastStream::new(self.subscribe()).map(|event| {
+            Some(match event {
+                Ok(event) => Event::default()
+                    .event("dataset")
+                    .id(event.dataset_version.to_string())
+                    .json_data(&event)
+                    .expect("events serialize to JSON"),
+                Err(BroadcastStreamRecvError::Lagged(missed)) => Event::default()
+                    .event("lagged")
+                    .data(format!(r#"{{"missed":{}}}"#, missed)),
+            })
+        });
+        let events = events
+            .merge(stop)
+            .take_while(Option::is_some)
+            .filter_map(|event| event.map(Ok));
+        Sse::new(events).keep_alive(
+            KeepAlive::new()
+                .interval(KEEP_ALIVE_INTERVAL)
+                .text("keep-alive"),
+        )
+    }
+}
@@ -0,0 +1,77 @@
+//! Serializes the live dataset for `GET /admin/export`.
+//!
+//! The JSON form is the `--data-file` schema, so an export can be committed and served
+//! again as is. The YAML form has the same shape. The CSV form has one row per country
+//! with aliases joined by `|`.
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::dataset::{CountryRecord, Dataset};
+
+const CSV_HEADER: &str = "name,aliases,flag,currencyCode,phoneCode";
+const CSV_ALIAS_SEPARATOR: &str = "|";
+
+#[derive(Debug, Serialize)]
+struct ExportFile<'a> {
+    countries: Vec<&'a CountryRecord>,
+}
+
+// Canonical records sorted by name so exports of the same data are byte-identical
+fn sorted_records(dataset: &Dataset) -> Vec<&CountryRecord> {
+    let mut records: Vec<_> = dataset.records().collect();
+    records.sort_by_key(|record| &record.name);
+    records
+}
+
+pub fn to_json(dataset: &Dataset) -> String {
+    let export = ExportFile {
+        countries: sorted_records(dataset),
+    };
+    serde_json::to_string_pretty(&export).expect("country records serialize to JSON")
+}
+
+pub fn to_yaml(dataset: &Dataset) -> String {
+    let export = ExportFile {
+        countries: sorted_records(dataset),
+    };
+    serde_yaml::to_string(&export).expect("country records serialize to YAML")
+}
+
+// Hex SHA-256 of the JSON export, which is the same for the same data on any server
+pub fn checksum(dataset: &Dataset) -> String {
+    format!("{:x}", Sha256::digest(to_json(dataset)))
+}
+
+// Quotes a field only when it holds a delimiter, a quote or a line break
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn to_csv(dataset: &Dataset) -> String {
+    let mut csv = String::from(CSV_HEADER);
+    csv.push('\n');
+    for record in sorted_records(dataset) {
+        let fields = [
+            csv_field(&record.name),
+            csv_field(
+                &record
+                    .aliases
+                    .iter()
+                    .map(|alias| alias.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(CSV_ALIAS_SEPARATOR),
+            ),
+            csv_field(&record.flag),
+            csv_field(record.currency_code.as_str()),
+            csv_field(&record.phone_code),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
@@ -0,0 +1,53 @@
+//! C functions for looking up countries in the built-in dataset from other languages.
+//!
+//! Built with `--features ffi`, which also regenerates `include/rusty_currency.h`. Strings
+//! returned by this module are allocated by Rust and must be released with [`ric_free`],
+//! never with `free()`. No panic crosses the boundary: one is reported as a miss.
+
+use std::{
+    ffi::{c_char, CStr, CString},
+    panic, ptr,
+};
+
+use crate::{dataset::DEFAULT_LANGUAGE, lookup::CountryInfo};
+
+/// Looks up `name` and returns its `/getCountry` result as a NUL-terminated JSON object,
+/// or null when `name` is null, is not valid UTF-8 or matches no country.
+///
+/// # Safety
+///
+/// `name` must be null or point to a NUL-terminated string that stays valid for the
+/// duration of the call. A non-null result must be passed to [`ric_free`] exactly once.
+#[no_mangle]
+pub unsafe extern "C" fn ric_lookup_json(name: *const c_char) -> *mut c_char {
+    if name.is_null() {
+        return ptr::null_mut();
+    }
+    let name = CStr::from_ptr(name);
+    match panic::catch_unwind(|| lookup_json(name)) {
+        Ok(Some(json)) => json.into_raw(),
+        Ok(None) | Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Releases a string returned by [`ric_lookup_json`]; does nothing when `json` is null.
+///
+/// # Safety
+///
+/// `json` must be null or a pointer returned by [`ric_lookup_json`] that has not been
+/// freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn ric_free(json: *mut c_char) {
+    if !json.is_null() {
+        drop(CString::from_raw(json));
+    }
+}
+
+fn lookup_json(name: &CStr) -> Option<CString> {
+    let name = name.to_str().ok()?;
+    let record = crate::lookup_country(name)?;
+    let json = serde_json::to_string(&CountryInfo::new(name.trim(), record, DEFAULT_LANGUAGE))
+        .expect("lookup results serialize to JSON");
+    // serde_json escapes control characters, so the JSON never holds a NUL byte
+    Some(CString::new(json).expect("JSON contains no NUL bytes"))
+}
@@ -0,0 +1,94 @@
+//! Flag images, for `/flag/{name}.svg` and `/flag/{name}.png`.
+//!
+//! Emoji flags look different on every platform, and some show two letters instead. The
+//! vendored SVGs in `assets/flags` are compiled in, keyed by alpha-2 code, so every
+//! built-in country has an image; custom countries have one when their flag is that of a
+//! built-in code. With the `flag-png` feature the SVGs are also rasterized with resvg.
+
+use std::fmt;
+
+use crate::codes::Alpha2;
+
+// Alpha-2 code -> SVG document
+static FLAG_IMAGES: phf::Map<&'static str, &'static str> =
+    include!(concat!(env!("OUT_DIR"), "/flag_images.rs"));
+
+// PNG widths `size` may ask for, in pixels
+pub const MIN_PNG_SIZE: u32 = 16;
+pub const MAX_PNG_SIZE: u32 = 1024;
+pub const DEFAULT_PNG_SIZE: u32 = 64;
+
+// Flags are the same for as long as the binary is, but a name may come to stand for
+// another country through an admin edit or reload, so the cache lifetime is a day
+pub const CACHE_CONTROL: &str = "public, max-age=86400";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Svg,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ImageFormat::Svg => "image/svg+xml",
+            ImageFormat::Png => "image/png",
+        }
+    }
+}
+
+// "japan.svg" -> ("japan", Svg); `None` without a known extension, which is matched in
+// any case
+pub fn parse_file_name(file: &str) -> Option<(&str, ImageFormat)> {
+    let (name, extension) = file.rsplit_once('.')?;
+    let format = match extension.to_ascii_lowercase().as_str() {
+        "svg" => ImageFormat::Svg,
+        "png" => ImageFormat::Png,
+        _ => return None,
+    };
+    Some((name, format))
+}
+
+pub fn svg(alpha2: Alpha2) -> Option<&'static str> {
+    FLAG_IMAGES.get(alpha2.as_str()).copied()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenderError {
+    // The SVG could not be parsed; only possible if a vendored file is broken
+    Svg(String),
+    Encode(String),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Svg(reason) => write!(f, "invalid flag SVG: {}", reason),
+            RenderError::Encode(reason) => write!(f, "PNG encoding failed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+// `width` pixels wide, and as tall as the SVG's aspect ratio makes it
+#[cfg(feature = "flag-png")]
+pub fn render_png(svg: &str, width: u32) -> Result<Vec<u8>, RenderError> {
+    use resvg::{tiny_skia, usvg};
+
+    let tree = usvg::Tree::from_str(svg, &usvg::Options::default())
+        .map_err(|error| RenderError::Svg(error.to_string()))?;
+    let size = tree.size();
+    let scale = width as f32 / size.width();
+    let height = (size.height() * scale).round().max(1.0) as u32;
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| RenderError::Encode(format!("no {}x{} image", width, height)))?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+    pixmap
+        .encode_png()
+        .map_err(|error| RenderError::Encode(error.to_string()))
+}
@@ -0,0 +1,211 @@
+//! Country lookups by IP address, for `/whoami` and the addresses in `based`, written
+//! bare or as `ip:<address>` entries.
+//!
+//! Addresses are resolved to an ISO 3166-1 alpha-2 code by an `IpResolver`, normally a
+//! MaxMind database loaded with `--geoip-db`, and the code to the served country whose
+//! flag spells it. Private and reserved addresses are refused before the resolver is
+//! asked, since no database places them in a country.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+};
+
+use crate::{
+    codes::Alpha2,
+    dataset::{CountryRecord, Dataset},
+    lookup::AddressLocator,
+};
+
+pub trait IpResolver: Send + Sync {
+    // The country `address` is in, or None when the resolver does not know it
+    fn country(&self, address: IpAddr) -> Result<Option<Alpha2>, GeoIpError>;
+}
+
+impl fmt::Debug for dyn IpResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IpResolver").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoIpError {
+    // The server has no database to ask (`--geoip-db` is not set)
+    NotConfigured,
+    // The text after `ip:` is not an IPv4 or IPv6 address
+    InvalidAddress(String),
+    // A private, loopback or otherwise reserved address
+    PrivateAddress(IpAddr),
+    // The database has no country for the address
+    NotFound(IpAddr),
+    // The database could not be opened or read
+    Database(String),
+}
+
+impl fmt::Display for GeoIpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeoIpError::NotConfigured => write!(f, "No GeoIP database is configured"),
+            GeoIpError::InvalidAddress(address) => {
+                write!(f, "{:?} is not an IP address", address)
+            }
+            GeoIpError::PrivateAddress(address) => write!(
+                f,
+                "{} is a private or reserved address, which has no country",
+                address
+            ),
+            GeoIpError::NotFound(address) => {
+                write!(f, "The GeoIP database has no country for {}", address)
+            }
+            GeoIpError::Database(reason) => write!(f, "The GeoIP database failed: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for GeoIpError {}
+
+impl From<maxminddb::MaxMindDbError> for GeoIpError {
+    fn from(error: maxminddb::MaxMindDbError) -> Self {
+        GeoIpError::Database(error.to_string())
+    }
+}
+
+/// A MaxMind database, such as GeoLite2 Country or City, read into memory.
+pub struct MaxMindResolver {
+    reader: maxminddb::Reader<Vec<u8>>,
+}
+
+impl MaxMindResolver {
+    pub fn open(path: &Path) -> Result<Self, GeoIpError> {
+        let reader = maxminddb::Reader::open_readfile(path).map_err(|error| {
+            GeoIpError::Database(format!("cannot open {}: {}", path.display(), error))
+        })?;
+        Ok(MaxMindResolver { reader })
+    }
+
+    // "GeoLite2-Country", for the startup log
+    pub fn database_type(&self) -> &str {
+        &self.reader.metadata().database_type
+    }
+}
+
+impl fmt::Debug for MaxMindResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MaxMindResolver")
+            .field("database_type", &self.database_type())
+            .finish_non_exhaustive()
+    }
+}
+
+impl IpResolver for MaxMindResolver {
+    fn country(&self, address: IpAddr) -> Result<Option<Alpha2>, GeoIpError> {
+        let result = self.reader.lookup(address)?;
+        // Addresses of anonymous networks may only have the registered country
+        for field in ["country", "registered_country"] {
+            let code: Option<&str> = result.decode_path(&maxminddb::path![field, "iso_code"])?;
+            if let Some(code) = code {
+                return Alpha2::new(code)
+                    .map(Some)
+                    .map_err(|error| GeoIpError::Database(error.to_string()));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Fixed addresses, for tests and offline use. Addresses it has no entry for are not
+/// found.
+#[derive(Debug, Default)]
+pub struct StaticResolver {
+    countries: HashMap<IpAddr, Alpha2>,
+}
+
+impl StaticResolver {
+    pub fn new(countries: impl IntoIterator<Item = (IpAddr, Alpha2)>) -> Self {
+        StaticResolver {
+            countries: countries.into_iter().collect(),
+        }
+    }
+}
+
+impl IpResolver for StaticResolver {
+    fn country(&self, address: IpAddr) -> Result<Option<Alpha2>, GeoIpError> {
+        Ok(self.countries.get(&address).copied())
+    }
+}
+
+// Addresses no GeoIP database places in a country
+pub fn is_private(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => is_private_v4(address),
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(mapped) => is_private_v4(mapped),
+            None => is_private_v6(address),
+        },
+    }
+}
+
+fn is_private_v4(address: Ipv4Addr) -> bool {
+    let [first, second, ..] = address.octets();
+    address.is_private()
+        || address.is_loopback()
+        || address.is_link_local()
+        || address.is_unspecified()
+        || address.is_broadcast()
+        || address.is_documentation()
+        || address.is_multicast()
+        // Shared address space for carrier-grade NAT, 100.64.0.0/10
+        || (first == 100 && second & 0b1100_0000 == 64)
+}
+
+fn is_private_v6(address: Ipv6Addr) -> bool {
+    let first = address.segments()[0];
+    address.is_loopback()
+        || address.is_unspecified()
+        || address.is_multicast()
+        // Unique local, fc00::/7
+        || first & 0xfe00 == 0xfc00
+        // Link-local, fe80::/10
+        || first & 0xffc0 == 0xfe80
+}
+
+// The country code of `address`, which is the text of an `ip:` entry or a client address
+pub fn locate(resolver: Option<&dyn IpResolver>, address: &str) -> Result<Alpha2, GeoIpError> {
+    let parsed: IpAddr = address
+        .parse()
+        .map_err(|_| GeoIpError::InvalidAddress(address.to_string()))?;
+    locate_address(resolver, parsed)
+}
+
+pub fn locate_address(
+    resolver: Option<&dyn IpResolver>,
+    address: IpAddr,
+) -> Result<Alpha2, GeoIpError> {
+    if is_private(address) {
+        return Err(GeoIpError::PrivateAddress(address));
+    }
+    resolver
+        .ok_or(GeoIpError::NotConfigured)?
+        .country(address)?
+        .ok_or(GeoIpError::NotFound(address))
+}
+
+// The served country whose flag spells `alpha2`
+pub fn find_country(dataset: &Dataset, alpha2: Alpha2) -> Option<&CountryRecord> {
+    dataset.by_alpha2(alpha2)
+}
+
+// Locates the addresses of `based` for `lookup::resolve_query_with`; without a resolver
+// every public address fails as `NotConfigured`
+#[derive(Clone, Copy)]
+pub struct GeoIpLocator<'r>(pub Option<&'r dyn IpResolver>);
+
+impl AddressLocator for GeoIpLocator<'_> {
+    type Error = GeoIpError;
+
+    fn locate(&self, address: &str) -> Result<Alpha2, GeoIpError> {
+        locate(self.0, address)
+    }
+}
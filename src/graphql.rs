@@ -0,0 +1,315 @@
+//! The lookups as a GraphQL schema (`--features graphql`), served at `/graphql`.
+//!
+//! Clients select the fields they want, including the nested `currency` and `geo`
+//! objects, instead of getting every field of every result. The schema reads the
+//! [`AppState`] each request carries, so it serves the same data as the HTTP API, reloads
+//! and admin edits included. As in `/getCountry`, names that match no country are left
+//! out of `countries`, and `country` answers null for them.
+//!
+//! ```
+//! use rusty_currency::{graphql, AppState, Dataset};
+//!
+//! # tokio::runtime::Runtime::new().unwrap().block_on(async {
+//! let request = async_graphql::Request::new(r#"{ country(name: "japan") { flag } }"#)
+//!     .data(AppState::new(Dataset::builtin()));
+//! let response = graphql::schema().execute(request).await;
+//! assert_eq!(response.data.to_string(), r#"{country: {flag: "🇯🇵"}}"#);
+//! # });
+//! ```
+
+use async_graphql::{
+    http::GraphiQLSource, Context, EmptyMutation, EmptySubscription, InputObject, Object, Schema,
+    SimpleObject,
+};
+use async_graphql_axum::{rejection::GraphQLRejection, GraphQLRequest, GraphQLResponse};
+use axum::{
+    extract::{Extension, OriginalUri, State},
+    http::header::CONTENT_SECURITY_POLICY,
+    response::{Html, IntoResponse},
+    routing::get,
+    Router,
+};
+use std::sync::Arc;
+use utoipa::OpenApi;
+
+use crate::{
+    builtin,
+    codes::{Alpha2, CurrencyCode},
+    dataset::{CountryRecord, Dataset},
+    lookup::requested_language,
+    normalize::normalize,
+    ApiError, AppState, ErrorEnvelope, MAX_COUNTRIES_PER_QUERY,
+};
+
+pub const ENDPOINT: &str = "/graphql";
+pub const PLAYGROUND: &str = "/graphql/playground";
+
+// GraphiQL loads its scripts and styles from unpkg.com and runs inline scripts, which
+// the policy of the server's own pages does not allow
+const PLAYGROUND_CONTENT_SECURITY_POLICY: &str = "default-src 'none'; \
+     script-src 'self' 'unsafe-inline' https://unpkg.com; \
+     style-src 'self' 'unsafe-inline' https://unpkg.com; img-src 'self' data: https://graphql.org; \
+     font-src 'self' data: https://unpkg.com; connect-src 'self'; base-uri 'none'; \
+     frame-ancestors 'none'";
+
+// Deep enough for `allCountries { currency { countries { name } } }`, shallow enough
+// that nesting `currency` and `countries` cannot multiply a response without bound
+const MAX_QUERY_DEPTH: usize = 5;
+
+pub type CountrySchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// The schema `/graphql` executes. Requests need the [`AppState`] to read as data.
+pub fn schema() -> CountrySchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .finish()
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Looks up a name or alias; null when it matches no country.
+    async fn country(
+        &self,
+        ctx: &Context<'_>,
+        name: String,
+    ) -> async_graphql::Result<Option<Country>> {
+        let dataset = current(ctx)?;
+        Ok(Country::find(&dataset, name.trim()))
+    }
+
+    /// Looks up each name or alias, in the order given, leaving out the ones that match
+    /// no country.
+    async fn countries(
+        &self,
+        ctx: &Context<'_>,
+        names: Vec<String>,
+    ) -> async_graphql::Result<Vec<Country>> {
+        let names: Vec<&str> = names
+            .iter()
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty())
+            .collect();
+        if names.is_empty() {
+            return Err("`names` must name at least one country".into());
+        }
+        if names.len() > MAX_COUNTRIES_PER_QUERY {
+            return Err(format!(
+                "At most {} countries can be looked up per request, {} were given",
+                MAX_COUNTRIES_PER_QUERY,
+                names.len()
+            )
+            .into());
+        }
+
+        let dataset = current(ctx)?;
+        Ok(names
+            .into_iter()
+            .filter_map(|name| Country::find(&dataset, name))
+            .collect())
+    }
+
+    /// Every country once, under its canonical name, sorted by name.
+    async fn all_countries(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<CountryFilter>,
+    ) -> async_graphql::Result<Vec<Country>> {
+        let filter = filter.unwrap_or_default();
+        let currency_code = filter
+            .currency_code
+            .as_deref()
+            .map(|code| CurrencyCode::new(code.trim()))
+            .transpose()?;
+        let phone_code = filter.phone_code.as_deref().map(str::trim);
+
+        let dataset = current(ctx)?;
+        Ok(Country::all(&dataset, |record| {
+            currency_code.is_none_or(|code| record.currency_code == code)
+                && phone_code.is_none_or(|code| record.phone_code == code)
+        }))
+    }
+}
+
+/// Narrows `allCountries`; every field given has to match.
+#[derive(Debug, Default, InputObject)]
+pub struct CountryFilter {
+    /// ISO 4217 code, such as `EUR`.
+    pub currency_code: Option<String>,
+    /// International dialling code, such as `+44`.
+    pub phone_code: Option<String>,
+}
+
+// Results hold the dataset they came from, so nested fields read the same snapshot
+pub struct Country {
+    name: String,
+    record: Arc<CountryRecord>,
+    dataset: Arc<Dataset>,
+}
+
+impl Country {
+    fn find(dataset: &Arc<Dataset>, name: &str) -> Option<Self> {
+        let record = dataset.shared(&normalize(name))?;
+        Some(Country {
+            name: name.to_string(),
+            record: Arc::clone(record),
+            dataset: Arc::clone(dataset),
+        })
+    }
+
+    // The countries `keep` accepts, under their canonical names, sorted by name
+    fn all(dataset: &Arc<Dataset>, keep: impl Fn(&CountryRecord) -> bool) -> Vec<Self> {
+        let mut countries: Vec<_> = dataset
+            .records()
+            .filter(|record| keep(record))
+            .filter_map(|record| Country::find(dataset, &record.name))
+            .collect();
+        countries.sort_by(|a, b| a.name.cmp(&b.name));
+        countries
+    }
+}
+
+#[Object]
+impl Country {
+    /// The name as it was looked up, or the canonical name when listing.
+    async fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn canonical_name(&self) -> &str {
+        &self.record.name
+    }
+
+    async fn aliases(&self) -> Vec<&str> {
+        self.record
+            .aliases
+            .iter()
+            .map(|alias| alias.name.as_str())
+            .collect()
+    }
+
+    /// The display name in `lang`, such as `ja`; English when not given.
+    async fn localized_name(&self, lang: Option<String>) -> &str {
+        self.record
+            .localized_name(&requested_language(lang.as_deref()))
+    }
+
+    async fn flag(&self) -> &str {
+        &self.record.flag
+    }
+
+    async fn phone_code(&self) -> &str {
+        &self.record.phone_code
+    }
+
+    async fn currency(&self) -> Currency {
+        Currency {
+            code: self.record.currency_code,
+            dataset: Arc::clone(&self.dataset),
+        }
+    }
+
+    /// The ISO 3166-1 codes, found through the alpha-2 code the flag spells; null when
+    /// the flag spells none.
+    async fn geo(&self) -> Option<Geo> {
+        let alpha2 = Alpha2::from_flag(&self.record.flag)?;
+        let builtin = builtin::by_alpha2(alpha2);
+        Some(Geo {
+            alpha2: alpha2.to_string(),
+            alpha3: builtin.map(|country| country.alpha3.to_string()),
+            numeric: builtin.map(|country| country.numeric.to_string()),
+        })
+    }
+}
+
+pub struct Currency {
+    code: CurrencyCode,
+    dataset: Arc<Dataset>,
+}
+
+#[Object]
+impl Currency {
+    /// ISO 4217 code, such as `EUR`.
+    async fn code(&self) -> &str {
+        self.code.as_str()
+    }
+
+    /// Every country using this currency, under its canonical name, sorted by name.
+    async fn countries(&self) -> Vec<Country> {
+        Country::all(&self.dataset, |record| record.currency_code == self.code)
+    }
+}
+
+/// ISO 3166-1 codes. Only countries of the built-in dataset have `alpha3` and `numeric`.
+#[derive(Debug, SimpleObject)]
+pub struct Geo {
+    pub alpha2: String,
+    pub alpha3: Option<String>,
+    pub numeric: Option<String>,
+}
+
+fn current(ctx: &Context<'_>) -> async_graphql::Result<Arc<Dataset>> {
+    Ok(ctx.data::<AppState>()?.source.all())
+}
+
+// `/graphql` in the OpenAPI document. Only the endpoint is described there; clients
+// learn the schema itself through GraphQL introspection.
+#[derive(OpenApi)]
+#[openapi(
+    paths(execute),
+    tags((name = "graphql", description = "The lookups as a GraphQL schema"))
+)]
+pub(crate) struct GraphqlDoc;
+
+// `/graphql` over GET and POST
+pub(crate) fn routes() -> Router<AppState> {
+    Router::new()
+        .route(ENDPOINT, get(execute).post(execute))
+        .layer(Extension(schema()))
+}
+
+pub(crate) fn playground_routes() -> Router<AppState> {
+    Router::new().route(PLAYGROUND, get(serve_playground))
+}
+
+#[utoipa::path(
+    method(get, post),
+    path = "/graphql",
+    tag = "graphql",
+    summary = "Run a GraphQL query",
+    params(("query" = Option<String>, Query, description = "The query, when sent with GET")),
+    request_body(content = Object, description = "`{\"query\", \"variables\", \"operationName\"}`, when sent with POST"),
+    responses(
+        (status = 200, description = "`{\"data\", \"errors\"}` as GraphQL over HTTP defines it", body = Object),
+        (status = 400, description = "Not a GraphQL request (`INVALID_PARAMETER`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+    )
+)]
+async fn execute(
+    State(state): State<AppState>,
+    Extension(schema): Extension<CountrySchema>,
+    request: Result<GraphQLRequest, GraphQLRejection>,
+) -> Result<GraphQLResponse, ApiError> {
+    let request = request.map_err(|rejection| ApiError::InvalidParameter {
+        name: "query",
+        reason: rejection.0.to_string(),
+    })?;
+    Ok(schema
+        .execute(request.into_inner().data(state))
+        .await
+        .into())
+}
+
+// Points at the endpoint next to it, so it also works when the routes are nested
+async fn serve_playground(OriginalUri(uri): OriginalUri) -> impl IntoResponse {
+    let prefix = uri.path().strip_suffix(PLAYGROUND).unwrap_or_default();
+    (
+        [(CONTENT_SECURITY_POLICY, PLAYGROUND_CONTENT_SECURITY_POLICY)],
+        Html(
+            GraphiQLSource::build()
+                .endpoint(&format!("{}{}", prefix, ENDPOINT))
+                .finish(),
+        ),
+    )
+}
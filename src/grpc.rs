@@ -0,0 +1,141 @@
+//! The lookups of the HTTP API as a gRPC service (`--features grpc`), defined in
+//! `proto/country.proto`.
+//!
+//! The service reads the same [`AppState`] as the HTTP router, so it serves whatever its
+//! `CountryDataSource` does, reloads and admin edits included. Names that match no
+//! country are left out of the results, as in `/getCountry`.
+
+use std::{future::Future, io, sync::Arc};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{Request, Response, Status};
+
+use crate::{
+    codes::CurrencyCode,
+    dataset::Dataset,
+    lookup::{self, requested_language, CountryInfo, Results},
+    AppState, MAX_COUNTRIES_PER_QUERY,
+};
+
+pub mod proto {
+    tonic::include_proto!("rustincountry.v1");
+}
+
+use proto::{
+    country_service_server::{CountryService, CountryServiceServer},
+    Country, CountryList, GetByCurrencyRequest, GetCountryRequest, ListCountriesRequest,
+};
+
+#[derive(Debug, Clone)]
+pub struct GrpcService {
+    state: AppState,
+}
+
+impl GrpcService {
+    pub fn new(state: AppState) -> Self {
+        GrpcService { state }
+    }
+
+    pub fn into_server(self) -> CountryServiceServer<Self> {
+        CountryServiceServer::new(self)
+    }
+
+    // Results borrow from the dataset, so they are copied into messages while it is held
+    fn dataset(&self) -> Arc<Dataset> {
+        self.state.source.all()
+    }
+}
+
+#[tonic::async_trait]
+impl CountryService for GrpcService {
+    async fn get_country(
+        &self,
+        request: Request<GetCountryRequest>,
+    ) -> Result<Response<CountryList>, Status> {
+        let request = request.into_inner();
+        let names: Vec<&str> = request
+            .names
+            .iter()
+            .map(|name| name.trim())
+            .filter(|name| !name.is_empty())
+            .collect();
+        if names.is_empty() {
+            return Err(Status::invalid_argument(
+                "`names` must name at least one country",
+            ));
+        }
+        if names.len() > MAX_COUNTRIES_PER_QUERY {
+            return Err(Status::invalid_argument(format!(
+                "At most {} countries can be looked up per request, {} were given",
+                MAX_COUNTRIES_PER_QUERY,
+                names.len()
+            )));
+        }
+
+        let dataset = self.dataset();
+        let (results, _) = lookup::resolve_names(&dataset, names, &language(&request.lang));
+        Ok(Response::new(to_list(&results)))
+    }
+
+    async fn list_countries(
+        &self,
+        request: Request<ListCountriesRequest>,
+    ) -> Result<Response<CountryList>, Status> {
+        let dataset = self.dataset();
+        let results = lookup::canonical_countries(&dataset, &language(&request.get_ref().lang));
+        Ok(Response::new(to_list(&results)))
+    }
+
+    async fn get_by_currency(
+        &self,
+        request: Request<GetByCurrencyRequest>,
+    ) -> Result<Response<CountryList>, Status> {
+        let request = request.into_inner();
+        let currency_code = CurrencyCode::new(request.currency_code.trim())
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+
+        let dataset = self.dataset();
+        let results =
+            lookup::countries_by_currency(&dataset, currency_code, &language(&request.lang));
+        Ok(Response::new(to_list(&results)))
+    }
+}
+
+// proto3 strings cannot be absent, so an empty `lang` means the default
+fn language(lang: &str) -> String {
+    requested_language(Some(lang))
+}
+
+fn to_list(results: &Results) -> CountryList {
+    CountryList {
+        results: results.iter().map(to_country).collect(),
+    }
+}
+
+fn to_country(info: &CountryInfo) -> Country {
+    Country {
+        country: info.country.to_string(),
+        localized_name: info.localized_name.to_string(),
+        flag: info.flag.to_string(),
+        currency_code: info.currency_code.to_string(),
+        phone_code: info.phone_code.to_string(),
+    }
+}
+
+/// Serves the gRPC service over `state` on `listener` until it fails.
+pub async fn serve(listener: TcpListener, state: AppState) -> io::Result<()> {
+    serve_until(listener, state, std::future::pending()).await
+}
+
+/// Serves as [`serve`] does until `shutdown` completes, then finishes the calls in progress.
+pub async fn serve_until(
+    listener: TcpListener,
+    state: AppState,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    tonic::transport::Server::builder()
+        .add_service(GrpcService::new(state).into_server())
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown)
+        .await
+        .map_err(io::Error::other)
+}
@@ -0,0 +1,172 @@
+//! The checks behind `/healthz?verbose=true`.
+//!
+//! Required components (the served data and its reloads) decide the overall status. The
+//! optional ones, the TLS certificate, the GeoIP database, the rate provider and Redis,
+//! are only reported when configured, and a failing one marks the server degraded
+//! rather than down, since lookups are still answered without it. Each check that goes over the network is
+//! given `CHECK_TIMEOUT`, except the rate provider's while its circuit breaker is open,
+//! which is reported degraded without asking it.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    responses::{
+        ComponentHealth, HealthDetails, HealthStatus, RatesHealth, ReloadHealth, TlsHealth,
+    },
+    AppState,
+};
+
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub type CheckFuture<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+
+// A service the server uses, checked on every verbose health request
+pub trait DependencyCheck: Send + Sync {
+    // Err with the reason when the service cannot be used right now
+    fn check(&self) -> CheckFuture<'_>;
+}
+
+impl fmt::Debug for dyn DependencyCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DependencyCheck").finish_non_exhaustive()
+    }
+}
+
+fn component(status: HealthStatus, required: bool, message: Option<String>) -> ComponentHealth {
+    ComponentHealth {
+        status,
+        required,
+        message,
+    }
+}
+
+// An optional dependency's check, with failures and timeouts marked degraded
+async fn check_optional(check: CheckFuture<'_>) -> ComponentHealth {
+    let outcome = tokio::time::timeout(CHECK_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| Err(format!("no answer within {:?}", CHECK_TIMEOUT)));
+    match outcome {
+        Ok(()) => component(HealthStatus::Ok, false, None),
+        Err(reason) => component(HealthStatus::Degraded, false, Some(reason)),
+    }
+}
+
+// The details and the overall status, the worst of all components
+pub async fn report(state: &AppState) -> (HealthStatus, HealthDetails) {
+    let (dataset, _, checksum) = state.current_checksum();
+    let mut components = BTreeMap::new();
+
+    let served = if dataset.is_empty() {
+        component(
+            HealthStatus::Down,
+            true,
+            Some(String::from("No countries are served")),
+        )
+    } else {
+        component(HealthStatus::Ok, true, None)
+    };
+    components.insert(String::from("dataset"), served);
+
+    let last_reload = state.metrics.last_reload();
+    if let Some(reload) = last_reload {
+        let reloaded = if reload.succeeded {
+            component(HealthStatus::Ok, true, None)
+        } else {
+            component(
+                HealthStatus::Degraded,
+                true,
+                Some(String::from(
+                    "The last reload failed, so the previous data is served",
+                )),
+            )
+        };
+        components.insert(String::from("reload"), reloaded);
+    }
+
+    if let Some(tls) = &state.tls {
+        let certificate = if tls.not_after() <= SystemTime::now() {
+            component(
+                HealthStatus::Degraded,
+                false,
+                Some(String::from("The TLS certificate has expired")),
+            )
+        } else if let Some(error) = tls.last_error() {
+            component(
+                HealthStatus::Degraded,
+                false,
+                Some(format!(
+                    "The last certificate reload failed, so the previous one is served: {}",
+                    error
+                )),
+            )
+        } else {
+            component(HealthStatus::Ok, false, None)
+        };
+        components.insert(String::from("tls"), certificate);
+    }
+
+    // Opened at startup, or the server would not have started
+    if state.geoip.is_some() {
+        components.insert(
+            String::from("geoip"),
+            component(HealthStatus::Ok, false, None),
+        );
+    }
+    if let Some(rates) = &state.rates {
+        let breaker = rates.breaker();
+        let health = match breaker.retry_in() {
+            Some(retry_in) => component(
+                HealthStatus::Degraded,
+                false,
+                Some(format!(
+                    "The rate provider failed {} time(s) in a row, so it is not asked for another {}s",
+                    breaker.consecutive_failures(),
+                    retry_in.as_secs_f64().ceil()
+                )),
+            ),
+            None => {
+                let check =
+                    Box::pin(async move { rates.probe().await.map_err(|error| error.to_string()) });
+                check_optional(check).await
+            }
+        };
+        components.insert(String::from("rates"), health);
+    }
+    for (name, dependency) in &state.dependencies {
+        components.insert(name.to_string(), check_optional(dependency.check()).await);
+    }
+
+    let status = components
+        .values()
+        .map(|component| component.status)
+        .max()
+        .unwrap_or(HealthStatus::Ok);
+    let details = HealthDetails {
+        uptime_seconds: state.started.elapsed().as_secs(),
+        dataset_entries: dataset.len(),
+        dataset_checksum: checksum,
+        last_reload: last_reload.map(|reload| ReloadHealth {
+            time: httpdate::fmt_http_date(reload.at),
+            outcome: String::from(if reload.succeeded {
+                "success"
+            } else {
+                "failure"
+            }),
+        }),
+        tls: state.tls.as_ref().map(|tls| TlsHealth {
+            not_after: httpdate::fmt_http_date(tls.not_after()),
+        }),
+        rates: state.rates.as_ref().map(|rates| RatesHealth {
+            breaker: rates.breaker().state().to_string(),
+            consecutive_failures: rates.breaker().consecutive_failures(),
+        }),
+        components,
+    };
+    (status, details)
+}
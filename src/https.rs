@@ -0,0 +1,58 @@
+//! HTTPS-only behaviour for servers with `--tls-cert`: HSTS, and redirecting plain HTTP.
+//!
+//! With [`Hsts`] set, every response to a request made over TLS carries a
+//! `Strict-Transport-Security` header, so browsers use HTTPS for the host from then on.
+//! It is never sent over plain HTTP, where browsers ignore it. With [`HttpsRedirect`] set,
+//! requests made without TLS, on the listeners [`ServeOptions::plain_addresses`] names,
+//! are answered with a `301` to the same path and query on the HTTPS port. Both are off
+//! unless configured.
+//!
+//! [`ServeOptions::plain_addresses`]: crate::ServeOptions::plain_addresses
+
+use axum::http::{uri::Authority, HeaderValue, Uri};
+use std::time::Duration;
+
+const HTTPS_PORT: u16 = 443;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hsts {
+    // How long browsers keep to HTTPS after the latest response
+    pub max_age: Duration,
+    // Whether subdomains of the host are held to HTTPS too
+    pub include_subdomains: bool,
+}
+
+impl Hsts {
+    pub fn header_value(&self) -> HeaderValue {
+        let mut value = format!("max-age={}", self.max_age.as_secs());
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        HeaderValue::from_str(&value).expect("HSTS directives are valid header values")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HttpsRedirect {
+    // The port HTTPS is served on as clients see it, which a port mapping may make
+    // differ from the one listened on
+    pub port: u16,
+}
+
+impl HttpsRedirect {
+    // Where a request for `uri` sent to `host`, a `Host` header value, is redirected;
+    // None when `host` is not a host
+    pub fn location(&self, host: &str, uri: &Uri) -> Option<HeaderValue> {
+        let host = host.parse::<Authority>().ok()?;
+        let path = uri.path_and_query().map_or("/", |path| path.as_str());
+        let location = match self.port {
+            HTTPS_PORT => format!("https://{}{}", host.host(), path),
+            port => format!("https://{}:{}{}", host.host(), port, path),
+        };
+        HeaderValue::from_str(&location).ok()
+    }
+}
+
+// Marks requests that arrived over TLS, as the server that decrypted them tells
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OverTls;
@@ -0,0 +1,112 @@
+//! Optional field groups, for `/getCountry?include=`.
+//!
+//! Results carry the fields every caller needs by default. Heavier groups are asked for
+//! by name, as in `include=geo,translations`, and each adds its fields to every result;
+//! a token that names no group is an error listing the ones there are.
+
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IncludeGroup {
+    // `latlng`, `borders` and `timezones`
+    Geo,
+    // `currencies`, each currency with the symbols written for it
+    Currencies,
+    // `translations`, the country's name in every language it has one in
+    Translations,
+    // `exchangeRateToUsd`, as `rates=true` adds
+    Rates,
+}
+
+impl IncludeGroup {
+    pub const ALL: [IncludeGroup; 4] = [
+        IncludeGroup::Geo,
+        IncludeGroup::Currencies,
+        IncludeGroup::Translations,
+        IncludeGroup::Rates,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IncludeGroup::Geo => "geo",
+            IncludeGroup::Currencies => "currencies",
+            IncludeGroup::Translations => "translations",
+            IncludeGroup::Rates => "rates",
+        }
+    }
+}
+
+impl FromStr for IncludeGroup {
+    type Err = UnknownInclude;
+
+    // Matched in any case
+    fn from_str(token: &str) -> Result<Self, Self::Err> {
+        let token = token.trim();
+        IncludeGroup::ALL
+            .into_iter()
+            .find(|group| group.as_str().eq_ignore_ascii_case(token))
+            .ok_or_else(|| UnknownInclude(token.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownInclude(pub String);
+
+impl fmt::Display for UnknownInclude {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let valid: Vec<_> = IncludeGroup::ALL
+            .iter()
+            .map(|group| group.as_str())
+            .collect();
+        write!(
+            f,
+            "{:?} is not a field group; valid ones are {}",
+            self.0,
+            valid.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnknownInclude {}
+
+// The groups an `include` parameter asks for, each once
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Includes {
+    groups: [bool; IncludeGroup::ALL.len()],
+}
+
+impl Includes {
+    // Comma-separated group names; blank tokens are skipped, so an empty parameter asks
+    // for nothing
+    pub fn parse(include: &str) -> Result<Includes, UnknownInclude> {
+        let mut includes = Includes::default();
+        for token in include.split(',').filter(|token| !token.trim().is_empty()) {
+            includes.insert(token.parse()?);
+        }
+        Ok(includes)
+    }
+
+    pub fn insert(&mut self, group: IncludeGroup) {
+        self.groups[group as usize] = true;
+    }
+
+    pub fn contains(&self, group: IncludeGroup) -> bool {
+        self.groups[group as usize]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.groups.contains(&true)
+    }
+}
+
+// The groups in a fixed order, comma-separated, so equal sets print the same
+impl fmt::Display for Includes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let groups: Vec<_> = IncludeGroup::ALL
+            .into_iter()
+            .filter(|&group| self.contains(group))
+            .map(IncludeGroup::as_str)
+            .collect();
+        f.write_str(&groups.join(","))
+    }
+}
@@ -0,0 +1,122 @@
+//! Limits on the entries a lookup is given, and the cleanup they get before it.
+//!
+//! `based` is split on commas into entries, each a name, alias, flag or address. Before
+//! any is looked up the whole value and every entry are checked, so a query that could
+//! only be a mistake or an attack, such as 50 KB of text or a name with a NUL byte in it,
+//! is refused with the rule it broke instead of being matched against nothing.
+//!
+//! Entries pasted from elsewhere often come wrapped in quotes or brackets, or end a
+//! sentence: `"Japan"`, `(Japan)`, `Japan.`. [`clean`] strips those before the entry is
+//! normalized and looked up, and the cleaned entry is what results echo as `country`.
+
+use std::fmt;
+
+/// Most bytes `based` may hold, commas included.
+pub const MAX_QUERY_BYTES: usize = 4096;
+
+/// Most characters one entry may hold once trimmed. The longest official names, such as
+/// "United Kingdom of Great Britain and Northern Ireland", are about half of it.
+pub const MAX_ENTRY_CHARS: usize = 100;
+
+// Opening and closing characters an entry may be wrapped in
+const WRAPPERS: [(char, char); 9] = [
+    ('"', '"'),
+    ('\'', '\''),
+    ('(', ')'),
+    ('[', ']'),
+    ('{', '}'),
+    ('\u{201C}', '\u{201D}'),
+    ('\u{2018}', '\u{2019}'),
+    ('\u{AB}', '\u{BB}'),
+    ('\u{300C}', '\u{300D}'),
+];
+
+// Sentence punctuation an entry may end with
+const TRAILING_PUNCTUATION: [char; 3] = ['.', '!', '?'];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputError {
+    // The whole value is over `MAX_QUERY_BYTES`
+    QueryTooLong { length: usize },
+    // An entry is over `MAX_ENTRY_CHARS`
+    EntryTooLong { entry: String, length: usize },
+    // An entry holds a C0 or C1 control character, such as NUL or a newline
+    ControlCharacter { entry: String, character: char },
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::QueryTooLong { length } => write!(
+                f,
+                "`based` is {} bytes long, over the limit of {}",
+                length, MAX_QUERY_BYTES
+            ),
+            InputError::EntryTooLong { length, .. } => write!(
+                f,
+                "An entry is {} characters long, over the limit of {}",
+                length, MAX_ENTRY_CHARS
+            ),
+            InputError::ControlCharacter { entry, character } => write!(
+                f,
+                "{:?} holds the control character U+{:04X}",
+                entry, *character as u32
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InputError {}
+
+// Checks the length of `based`, then each entry in order, so the first offending entry
+// is the one reported
+pub fn check(based: &str) -> Result<(), InputError> {
+    if based.len() > MAX_QUERY_BYTES {
+        return Err(InputError::QueryTooLong {
+            length: based.len(),
+        });
+    }
+    based.split(',').try_for_each(check_entry)
+}
+
+// One entry, as `based` or a `/resolve` body holds it
+pub fn check_entry(entry: &str) -> Result<(), InputError> {
+    if let Some(character) = entry.chars().find(|c| c.is_control()) {
+        return Err(InputError::ControlCharacter {
+            entry: entry.to_string(),
+            character,
+        });
+    }
+    let entry = entry.trim();
+    let length = entry.chars().count();
+    if length > MAX_ENTRY_CHARS {
+        return Err(InputError::EntryTooLong {
+            entry: entry.to_string(),
+            length,
+        });
+    }
+    Ok(())
+}
+
+// `entry` trimmed, without the matching quotes or brackets around it and the `.`, `!` or
+// `?` after it, as often as they nest: `("Japan.")` is `Japan`. Only the ends are
+// touched, so `côte d'ivoire` and `st. lucia` keep their punctuation. A slice of
+// `entry`, so results can echo it.
+pub fn clean(entry: &str) -> &str {
+    let mut entry = entry.trim();
+    loop {
+        let before = entry;
+        entry = entry.trim_end_matches(TRAILING_PUNCTUATION).trim_end();
+        for (open, close) in WRAPPERS {
+            if let Some(inner) = entry
+                .strip_prefix(open)
+                .and_then(|inner| inner.strip_suffix(close))
+            {
+                entry = inner.trim();
+            }
+        }
+        if entry == before {
+            return entry;
+        }
+    }
+}
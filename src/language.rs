@@ -0,0 +1,171 @@
+//! Official languages, for `/byLanguage`.
+//!
+//! Records list their official languages as lowercase ISO 639 codes: the two-letter
+//! 639-1 code where there is one, such as `fr`, otherwise the three-letter 639-2 or
+//! 639-3 code, such as `fil` for Filipino. Queries may name a language by its code, its
+//! English name or one of the names below, in any case, so `french`, `Français` and
+//! `FR` all find the same countries.
+//!
+//! `build.rs` includes this file too and fails if the built-in data lists a code the
+//! table does not name, so it must not depend on the rest of the crate.
+
+// Code -> English name and the other names a language is looked up by, mostly what its
+// speakers call it; sorted by code
+const LANGUAGES: &[(&str, &str, &[&str])] = &[
+    ("af", "Afrikaans", &[]),
+    ("am", "Amharic", &["አማርኛ"]),
+    ("ar", "Arabic", &["العربية"]),
+    ("ay", "Aymara", &["aymar aru"]),
+    ("az", "Azerbaijani", &["azərbaycan", "azeri"]),
+    ("be", "Belarusian", &["беларуская"]),
+    ("ber", "Berber", &["tamazight", "amazigh"]),
+    ("bg", "Bulgarian", &["български"]),
+    ("bi", "Bislama", &[]),
+    ("bn", "Bengali", &["বাংলা", "bangla"]),
+    ("bs", "Bosnian", &["bosanski"]),
+    ("ca", "Catalan", &["català"]),
+    ("ch", "Chamorro", &["chamoru"]),
+    ("cnr", "Montenegrin", &["crnogorski"]),
+    ("crs", "Seychellois Creole", &["kreol seselwa"]),
+    ("cs", "Czech", &["čeština", "cestina"]),
+    ("da", "Danish", &["dansk"]),
+    ("de", "German", &["deutsch"]),
+    ("dv", "Dhivehi", &["divehi", "maldivian"]),
+    ("dz", "Dzongkha", &[]),
+    ("el", "Greek", &["ελληνικά"]),
+    ("en", "English", &[]),
+    (
+        "es",
+        "Spanish",
+        &["español", "espanol", "castellano", "castilian"],
+    ),
+    ("et", "Estonian", &["eesti"]),
+    ("fa", "Persian", &["فارسی", "farsi", "dari"]),
+    ("fi", "Finnish", &["suomi"]),
+    ("fil", "Filipino", &["wikang filipino"]),
+    ("fj", "Fijian", &["vosa vakaviti"]),
+    ("fo", "Faroese", &["føroyskt"]),
+    ("fr", "French", &["français", "francais"]),
+    ("ga", "Irish", &["gaeilge", "irish gaelic"]),
+    ("gil", "Gilbertese", &["kiribati", "taetae ni kiribati"]),
+    ("gn", "Guarani", &["guaraní", "avañe'ẽ"]),
+    ("gv", "Manx", &["gaelg"]),
+    ("he", "Hebrew", &["עברית"]),
+    ("hi", "Hindi", &["हिन्दी"]),
+    ("hif", "Fiji Hindi", &[]),
+    ("ho", "Hiri Motu", &[]),
+    ("hr", "Croatian", &["hrvatski"]),
+    ("ht", "Haitian Creole", &["kreyòl ayisyen", "kreyol"]),
+    ("hu", "Hungarian", &["magyar"]),
+    ("hy", "Armenian", &["հայերեն"]),
+    ("id", "Indonesian", &["bahasa indonesia"]),
+    ("is", "Icelandic", &["íslenska"]),
+    ("it", "Italian", &["italiano"]),
+    ("ja", "Japanese", &["日本語", "nihongo"]),
+    ("ka", "Georgian", &["ქართული"]),
+    ("kk", "Kazakh", &["қазақ тілі"]),
+    ("kl", "Greenlandic", &["kalaallisut"]),
+    ("km", "Khmer", &["ខ្មែរ", "cambodian"]),
+    ("ko", "Korean", &["한국어", "조선말"]),
+    ("ku", "Kurdish", &["kurdî", "کوردی"]),
+    ("ky", "Kyrgyz", &["кыргызча", "kirghiz"]),
+    ("la", "Latin", &["latina"]),
+    ("lb", "Luxembourgish", &["lëtzebuergesch"]),
+    ("lo", "Lao", &["ລາວ", "laotian"]),
+    ("lt", "Lithuanian", &["lietuvių"]),
+    ("lv", "Latvian", &["latviešu"]),
+    ("mg", "Malagasy", &[]),
+    ("mh", "Marshallese", &["kajin m̧ajeļ"]),
+    ("mi", "Māori", &["maori", "te reo māori", "te reo maori"]),
+    ("mk", "Macedonian", &["македонски"]),
+    ("mn", "Mongolian", &["монгол"]),
+    ("ms", "Malay", &["bahasa melayu", "melayu"]),
+    ("mt", "Maltese", &["malti"]),
+    ("my", "Burmese", &["မြန်မာ", "myanmar"]),
+    ("na", "Nauruan", &["dorerin naoero"]),
+    ("nd", "Northern Ndebele", &["isindebele"]),
+    ("ne", "Nepali", &["नेपाली"]),
+    ("niu", "Niuean", &["vagahau niuē"]),
+    ("nl", "Dutch", &["nederlands", "flemish", "vlaams"]),
+    ("no", "Norwegian", &["norsk", "bokmål", "nynorsk"]),
+    ("nr", "Southern Ndebele", &[]),
+    ("nso", "Northern Sotho", &["sesotho sa leboa", "sepedi"]),
+    ("ny", "Chichewa", &["chewa", "nyanja"]),
+    ("pap", "Papiamento", &["papiamentu"]),
+    ("pau", "Palauan", &["tekoi er a belau"]),
+    ("pl", "Polish", &["polski"]),
+    ("ps", "Pashto", &["پښتو", "pushto"]),
+    ("pt", "Portuguese", &["português", "portugues"]),
+    ("qu", "Quechua", &["runa simi"]),
+    (
+        "rar",
+        "Cook Islands Māori",
+        &["cook islands maori", "rarotongan"],
+    ),
+    ("rm", "Romansh", &["rumantsch"]),
+    ("rn", "Kirundi", &["ikirundi", "rundi"]),
+    ("ro", "Romanian", &["română", "romana", "moldovan"]),
+    ("ru", "Russian", &["русский"]),
+    ("rw", "Kinyarwanda", &["ikinyarwanda"]),
+    ("sg", "Sango", &["yângâ tî sängö"]),
+    ("si", "Sinhala", &["සිංහල", "sinhalese"]),
+    ("sk", "Slovak", &["slovenčina"]),
+    ("sl", "Slovenian", &["slovenščina", "slovene"]),
+    ("sm", "Samoan", &["gagana samoa"]),
+    ("sn", "Shona", &["chishona"]),
+    ("so", "Somali", &["soomaali"]),
+    ("sq", "Albanian", &["shqip"]),
+    ("sr", "Serbian", &["српски", "srpski"]),
+    ("ss", "Swazi", &["siswati"]),
+    ("st", "Sesotho", &["southern sotho", "sotho"]),
+    ("sv", "Swedish", &["svenska"]),
+    ("sw", "Swahili", &["kiswahili"]),
+    ("ta", "Tamil", &["தமிழ்"]),
+    ("tet", "Tetum", &["tetun"]),
+    ("tg", "Tajik", &["тоҷикӣ"]),
+    ("th", "Thai", &["ไทย"]),
+    ("ti", "Tigrinya", &["ትግርኛ"]),
+    ("tk", "Turkmen", &["türkmençe"]),
+    ("tkl", "Tokelauan", &[]),
+    ("tn", "Tswana", &["setswana"]),
+    ("to", "Tongan", &["lea faka-tonga"]),
+    ("tpi", "Tok Pisin", &[]),
+    ("tr", "Turkish", &["türkçe", "turkce"]),
+    ("ts", "Tsonga", &["xitsonga"]),
+    ("tvl", "Tuvaluan", &["te gana tuvalu"]),
+    ("uk", "Ukrainian", &["українська"]),
+    ("ur", "Urdu", &["اردو"]),
+    ("uz", "Uzbek", &["oʻzbek", "o'zbek"]),
+    ("ve", "Venda", &["tshivenḓa"]),
+    ("vi", "Vietnamese", &["tiếng việt"]),
+    ("xh", "Xhosa", &["isixhosa"]),
+    ("zh", "Chinese", &["中文", "mandarin"]),
+    ("zu", "Zulu", &["isizulu"]),
+];
+
+// The English name of a code in the table
+pub fn name(code: &str) -> Option<&'static str> {
+    LANGUAGES
+        .binary_search_by(|(known, _, _)| (*known).cmp(code))
+        .ok()
+        .map(|index| LANGUAGES[index].1)
+}
+
+// Two or three lowercase ASCII letters, the shape of the codes records list
+pub fn is_code(code: &str) -> bool {
+    matches!(code.len(), 2 | 3) && code.bytes().all(|b| b.is_ascii_lowercase())
+}
+
+// The code `query` names, matched against codes, English names and the other names in
+// any case. A code the table does not know is still a code, as custom data may list
+// languages the table lacks; anything else is `None`.
+pub fn resolve(query: &str) -> Option<String> {
+    let query = query.trim().to_lowercase();
+    let known = LANGUAGES.iter().find(|(code, name, others)| {
+        *code == query || name.to_lowercase() == query || others.iter().any(|other| *other == query)
+    });
+    match known {
+        Some((code, _, _)) => Some(code.to_string()),
+        None => is_code(&query).then_some(query),
+    }
+}
@@ -0,0 +1,213 @@
+//! Country flag, currency and phone code lookups, and the HTTP API that serves them.
+//!
+//! The built-in dataset can be queried directly, without a runtime or a server:
+//!
+//! ```
+//! let japan = rusty_currency::lookup_country("japan").unwrap();
+//! assert_eq!(japan.currency_code, "JPY");
+//! ```
+//!
+//! With the default `server` feature, [`create_app`] builds the axum router the
+//! `rusty_currency` binary serves. Without it only the lookup core is built, which also
+//! compiles to `wasm32-unknown-unknown` (see the `wasm` module). The `ffi` feature adds C
+//! functions for calling lookups from other languages (see the `ffi` module), `grpc`
+//! serves the lookups over gRPC next to the HTTP API (see the `grpc` module),
+//! `graphql` adds a `/graphql` endpoint to it (see the `graphql` module) and `test-util`
+//! starts real servers for integration tests (see the `test_util` module).
+
+use codes::CurrencyCode;
+use normalize::normalize;
+use std::{collections::HashMap, sync::Arc};
+
+#[cfg(feature = "server")]
+pub mod access_log;
+#[cfg(feature = "server")]
+pub mod admin_auth;
+#[cfg(feature = "server")]
+pub mod api_keys;
+#[cfg(feature = "server")]
+pub mod audit;
+pub mod bounding_box;
+#[cfg(feature = "server")]
+pub mod breaker;
+pub mod builtin;
+#[cfg(feature = "server")]
+pub mod cache;
+pub mod calling_code;
+#[cfg(feature = "server")]
+pub mod catalog;
+#[cfg(feature = "server")]
+pub mod cli;
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "server")]
+pub mod client_ip;
+pub mod codes;
+#[cfg(feature = "server")]
+pub mod config;
+pub mod currency_symbol;
+#[cfg(feature = "server")]
+pub mod data_file;
+#[cfg(feature = "server")]
+pub mod database;
+pub mod dataset;
+#[cfg(feature = "server")]
+mod docs;
+#[cfg(feature = "server")]
+pub mod events;
+#[cfg(feature = "server")]
+pub mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "server")]
+pub mod flag_image;
+#[cfg(feature = "server")]
+pub mod geoip;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "server")]
+pub mod health;
+#[cfg(feature = "server")]
+pub mod https;
+pub mod include;
+pub mod input;
+pub mod language;
+#[cfg(feature = "server")]
+pub mod log_level;
+pub mod lookup;
+#[cfg(feature = "server")]
+pub mod merge;
+#[cfg(feature = "server")]
+pub mod metrics;
+#[cfg(feature = "server")]
+pub mod miss_log;
+pub mod normalize;
+#[cfg(feature = "server")]
+pub mod openapi;
+#[cfg(feature = "server")]
+pub mod overrides;
+#[cfg(feature = "server")]
+pub mod rate_limit;
+#[cfg(feature = "server")]
+pub mod rate_snapshot;
+#[cfg(feature = "server")]
+pub mod rates;
+#[cfg(feature = "server")]
+pub mod reload;
+#[cfg(feature = "server")]
+pub mod remote;
+#[cfg(feature = "server")]
+pub mod resolve;
+#[cfg(feature = "server")]
+pub mod response_cache;
+pub mod responses;
+#[cfg(feature = "server")]
+pub mod retry;
+#[cfg(feature = "server")]
+pub mod revision;
+#[cfg(feature = "server")]
+pub mod security_headers;
+#[cfg(feature = "server")]
+pub mod self_test;
+#[cfg(feature = "server")]
+pub mod shutdown;
+#[cfg(feature = "server")]
+pub mod snapshot;
+#[cfg(feature = "server")]
+pub mod source;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod timezone;
+pub mod tld;
+#[cfg(feature = "server")]
+pub mod tls;
+#[cfg(feature = "server")]
+pub mod validation;
+#[cfg(feature = "server")]
+pub mod webhooks;
+
+#[cfg(feature = "server")]
+mod server;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
+
+pub use dataset::{Alias, AliasKind, CountryRecord, Dataset};
+pub use lookup::{
+    CodesResponse, CountryCodes, CountryInfo, CountryResponse, CurrencyCountries, CurrencyResponse,
+    LanguageCount, LanguageResponse, LanguagesResponse, ResolvedFrom, SymbolResponse, Unmatched,
+    ValidateResponse, Validation,
+};
+pub use responses::{
+    ChecksumResponse, ComponentHealth, DatasetEvent, ErrorBody, ErrorEnvelope, HealthDetails,
+    HealthResponse, HealthStatus, ReloadHealth, StatsResponse, VersionResponse, WhoamiResponse,
+};
+#[cfg(feature = "server")]
+pub use server::{
+    bind_listeners, bind_listeners_with_retry, create_app, create_app_with, create_app_with_state,
+    routes, run_server, run_server_until, ApiError, AppConfig, AppState, ServeOptions,
+    DEFAULT_LISTEN_ADDRESS, DEFAULT_MAX_RESULTS, DEFAULT_SLOW_REQUEST_THRESHOLD,
+    MAX_COUNTRIES_PER_QUERY,
+};
+#[cfg(feature = "grpc")]
+pub use server::{run_server_with_grpc, run_server_with_grpc_until};
+
+// What the binary needs from the server besides the embedding API; not part of that API
+#[cfg(feature = "server")]
+#[doc(hidden)]
+pub mod binary {
+    pub use crate::server::{
+        adopt_listeners, check_source, install_panic_hook, take_inherited_listeners,
+    };
+}
+
+#[cfg(all(feature = "mimalloc", feature = "jemalloc"))]
+compile_error!("the `mimalloc` and `jemalloc` features are mutually exclusive");
+
+#[cfg(feature = "mimalloc")]
+pub use mimalloc::MiMalloc as SelectedAllocator;
+#[cfg(not(any(feature = "mimalloc", feature = "jemalloc")))]
+pub use std::alloc::System as SelectedAllocator;
+#[cfg(all(feature = "jemalloc", not(feature = "mimalloc")))]
+pub use tikv_jemallocator::Jemalloc as SelectedAllocator;
+
+// Name of the selected allocator, for the startup log
+pub const ALLOCATOR_NAME: &str = if cfg!(feature = "mimalloc") {
+    "mimalloc"
+} else if cfg!(feature = "jemalloc") {
+    "jemalloc"
+} else {
+    "system"
+};
+
+/// Looks a country up in the built-in dataset by name or alias.
+///
+/// Names are matched the way `/getCountry` matches them: case, surrounding whitespace
+/// and typographic apostrophes don't matter.
+///
+/// ```
+/// let record = rusty_currency::lookup_country("  Côte d’Ivoire ").unwrap();
+/// assert_eq!(record.currency_code, "XOF");
+/// assert!(rusty_currency::lookup_country("atlantis").is_none());
+/// ```
+pub fn lookup_country(name: &str) -> Option<&'static CountryRecord> {
+    builtin::lookup(&normalize(name)).map(Arc::as_ref)
+}
+
+/// Looks up each of `names` with [`lookup_country`], skipping the ones that match nothing.
+///
+/// ```
+/// let records = rusty_currency::lookup_many(["japan", "atlantis", "usa"].into_iter());
+/// let codes: Vec<_> = records.iter().map(|record| record.currency_code.as_str()).collect();
+/// assert_eq!(codes, ["JPY", "USD"]);
+/// ```
+pub fn lookup_many<'a>(names: impl Iterator<Item = &'a str>) -> Vec<&'static CountryRecord> {
+    names.filter_map(lookup_country).collect()
+}
+
+// Lowercase country name or alias -> (flag, currency code, phone code)
+type CountryData = HashMap<String, (String, CurrencyCode, String)>;
+
+#[cfg(all(test, feature = "server"))]
+mod tests;
@@ -0,0 +1,104 @@
+//! The log level, changeable while the server runs through `/admin/loglevel`.
+//!
+//! [`LogLevel::new`] returns the filter layer to install at startup along with the
+//! handle that changes it. Changing the level keeps the rest of the startup filter, so
+//! with `--log info,rustincountry::unmatched=off` and the level set to `debug` unknown
+//! names stay unlogged while everything else logs at debug.
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Mutex, PoisonError};
+use tracing_subscriber::{
+    filter::{LevelFilter, ParseError},
+    reload, EnvFilter, Registry,
+};
+use utoipa::ToSchema;
+
+/// The filter layer [`LogLevel::new`] returns, to put first on a `Registry`.
+pub type FilterLayer = reload::Layer<EnvFilter, Registry>;
+
+#[derive(Debug)]
+pub struct LogLevel {
+    handle: reload::Handle<EnvFilter, Registry>,
+    // The filter given at startup, which every new level is added to
+    directives: String,
+    level: Mutex<LevelFilter>,
+}
+
+/// A level `PUT /admin/loglevel` can set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<Level> for LevelFilter {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Error => LevelFilter::ERROR,
+            Level::Warn => LevelFilter::WARN,
+            Level::Info => LevelFilter::INFO,
+            Level::Debug => LevelFilter::DEBUG,
+            Level::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct LogLevelRequest {
+    pub level: Level,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LogLevelResponse {
+    // `off` when the startup filter only names targets
+    #[schema(example = "info")]
+    pub level: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+pub struct LogLevelChange {
+    #[schema(example = "info")]
+    pub previous: String,
+    #[schema(example = "debug")]
+    pub level: String,
+}
+
+impl LogLevel {
+    // The handle and layer for the `RUST_LOG`-style filter `directives`
+    pub fn new(directives: &str) -> Result<(Self, FilterLayer), ParseError> {
+        let filter = EnvFilter::try_new(directives)?;
+        let (layer, handle) = reload::Layer::new(filter);
+        let log_level = LogLevel {
+            handle,
+            directives: directives.to_string(),
+            level: Mutex::new(default_level(directives)),
+        };
+        Ok((log_level, layer))
+    }
+
+    pub fn current(&self) -> LevelFilter {
+        *self.level.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Logs everything the startup filter does not name at `level`; returns the level before
+    pub fn set(&self, level: Level) -> Result<LevelFilter, reload::Error> {
+        let mut current = self.level.lock().unwrap_or_else(PoisonError::into_inner);
+        let filter = EnvFilter::try_new(&self.directives)
+            .unwrap_or_default()
+            .add_directive(LevelFilter::from(level).into());
+        self.handle.reload(filter)?;
+        Ok(std::mem::replace(&mut *current, level.into()))
+    }
+}
+
+// The last directive naming only a level, which is the level of targets no other names
+fn default_level(directives: &str) -> LevelFilter {
+    directives
+        .rsplit(',')
+        .find_map(|directive| directive.trim().parse::<LevelFilter>().ok())
+        .unwrap_or(LevelFilter::OFF)
+}
@@ -0,0 +1,1303 @@
+//! Resolving `/getCountry` and `/countries` queries against a dataset.
+//!
+//! Results borrow from the dataset and the query, so answering a lookup copies no
+//! strings; callers serialize them while they hold the dataset.
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
+use std::{borrow::Cow, collections::BTreeMap, fmt, net::IpAddr};
+
+use crate::{
+    builtin,
+    codes::{Alpha2, CurrencyCode},
+    currency_symbol,
+    dataset::{self, AliasKind, Continent, CountryRecord, Dataset},
+    include::{IncludeGroup, Includes},
+    input, language, normalize,
+    responses::StatsResponse,
+    timezone, tld,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct CountryInfo<'a> {
+    pub country: Cow<'a, str>,
+    // The name of the country answered, rather than the entry that found it
+    #[serde(rename = "canonicalName")]
+    pub canonical_name: Cow<'a, str>,
+    #[serde(rename = "localizedName")]
+    pub localized_name: Cow<'a, str>,
+    // In the form `flagFormat` asks for; empty, and absent from the JSON, for `none`
+    #[serde(default, skip_serializing_if = "str::is_empty")]
+    pub flag: Cow<'a, str>,
+    #[serde(rename = "currencyCode")]
+    #[cfg_attr(feature = "server", schema(value_type = String, example = "JPY"))]
+    pub currency_code: CurrencyCode,
+    #[serde(rename = "phoneCode")]
+    pub phone_code: Cow<'a, str>,
+    // Set when the entry was not a name; absent from the JSON otherwise
+    #[serde(
+        rename = "resolvedFrom",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub resolved_from: Option<ResolvedFrom>,
+    // How current the name looked up is; `current` for canonical names and for entries
+    // that were not names
+    #[serde(rename = "nameStatus", default)]
+    pub name_status: AliasKind,
+    // Field groups added by `include`, absent from the JSON unless asked for
+    #[serde(flatten, default, skip_serializing_if = "Option::is_none")]
+    pub geo: Option<GeoFields<'a>>,
+    #[serde(flatten, default, skip_serializing_if = "Option::is_none")]
+    pub currencies: Option<CurrencyFields<'a>>,
+    #[serde(flatten, default, skip_serializing_if = "Option::is_none")]
+    pub translations: Option<TranslationFields<'a>>,
+    // The record answered, which `expand` reads the groups from
+    #[serde(skip)]
+    #[cfg_attr(feature = "server", schema(ignore))]
+    pub(crate) record: Option<&'a CountryRecord>,
+}
+
+/// Added by `include=geo`.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct GeoFields<'a> {
+    // [latitude, longitude] of a point inside the country; null when it has none
+    #[cfg_attr(feature = "server", schema(example = json!([36.0, 138.0])))]
+    pub latlng: Option<[f64; 2]>,
+    // Alpha-2 codes of the countries it shares a land border with, sorted
+    #[cfg_attr(feature = "server", schema(value_type = Vec<String>, example = json!(["AD", "BE"])))]
+    pub borders: Cow<'a, [Alpha2]>,
+    pub timezones: Cow<'a, [String]>,
+}
+
+/// Added by `include=currencies`.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct CurrencyFields<'a> {
+    #[cfg_attr(feature = "server", schema(value_type = Vec<CurrencyDetails>))]
+    pub currencies: Vec<CurrencyDetails<'a>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct CurrencyDetails<'a> {
+    #[cfg_attr(feature = "server", schema(value_type = String, example = "JPY"))]
+    pub code: CurrencyCode,
+    // Every symbol written for it, shared ones such as `$` included
+    #[cfg_attr(feature = "server", schema(value_type = Vec<String>))]
+    pub symbols: Vec<Cow<'a, str>>,
+}
+
+/// Added by `include=translations`.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct TranslationFields<'a> {
+    // Language -> the country's name in it
+    #[cfg_attr(feature = "server", schema(value_type = BTreeMap<String, String>))]
+    pub translations: Cow<'a, BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ResolvedFrom {
+    // An IP address located through GeoIP
+    Ip,
+    // A flag emoji, decoded to the alpha-2 code it spells
+    Flag,
+}
+
+impl ResolvedFrom {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ResolvedFrom::Ip => "ip",
+            ResolvedFrom::Flag => "flag",
+        }
+    }
+}
+
+// The single-valued fields of a result, which `only` can list on their own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnlyField {
+    Country,
+    CanonicalName,
+    LocalizedName,
+    Flag,
+    CurrencyCode,
+    PhoneCode,
+    NameStatus,
+    ResolvedFrom,
+}
+
+// Result fields that hold more than one value, which `only` refuses
+const NON_SCALAR_FIELDS: &[&str] = &[
+    "latlng",
+    "borders",
+    "timezones",
+    "currencies",
+    "translations",
+];
+
+impl OnlyField {
+    pub const ALL: [OnlyField; 8] = [
+        OnlyField::Country,
+        OnlyField::CanonicalName,
+        OnlyField::LocalizedName,
+        OnlyField::Flag,
+        OnlyField::CurrencyCode,
+        OnlyField::PhoneCode,
+        OnlyField::NameStatus,
+        OnlyField::ResolvedFrom,
+    ];
+
+    // The `only` query parameter, a field name as the JSON spells it; `None`, for whole
+    // results, when absent
+    pub fn from_param(only: Option<&str>) -> Result<Option<Self>, String> {
+        let Some(only) = only else {
+            return Ok(None);
+        };
+        if let Some(field) = OnlyField::ALL
+            .into_iter()
+            .find(|field| field.as_str() == only)
+        {
+            return Ok(Some(field));
+        }
+        let scalar: Vec<_> = OnlyField::ALL.iter().map(|field| field.as_str()).collect();
+        if NON_SCALAR_FIELDS.contains(&only) {
+            return Err(format!(
+                "{:?} is not a single value; `only` takes {}",
+                only,
+                scalar.join(", ")
+            ));
+        }
+        Err(format!(
+            "{:?} is not a field; `only` takes {}",
+            only,
+            scalar.join(", ")
+        ))
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OnlyField::Country => "country",
+            OnlyField::CanonicalName => "canonicalName",
+            OnlyField::LocalizedName => "localizedName",
+            OnlyField::Flag => "flag",
+            OnlyField::CurrencyCode => "currencyCode",
+            OnlyField::PhoneCode => "phoneCode",
+            OnlyField::NameStatus => "nameStatus",
+            OnlyField::ResolvedFrom => "resolvedFrom",
+        }
+    }
+
+    // `None` only for `resolvedFrom` of a result found by name
+    fn value<'a>(self, info: &CountryInfo<'a>) -> Option<Cow<'a, str>> {
+        match self {
+            OnlyField::Country => Some(info.country.clone()),
+            OnlyField::CanonicalName => Some(info.canonical_name.clone()),
+            OnlyField::LocalizedName => Some(info.localized_name.clone()),
+            OnlyField::Flag => Some(info.flag.clone()),
+            OnlyField::CurrencyCode => Some(Cow::Owned(info.currency_code.as_str().to_string())),
+            OnlyField::PhoneCode => Some(info.phone_code.clone()),
+            OnlyField::NameStatus => Some(Cow::Borrowed(info.name_status.as_str())),
+            OnlyField::ResolvedFrom => info.resolved_from.map(|from| Cow::Borrowed(from.as_str())),
+        }
+    }
+}
+
+// `field` of each result, in their order; without repeats, keeping the first of each,
+// when `distinct`
+pub fn values<'a>(
+    results: &[CountryInfo<'a>],
+    field: OnlyField,
+    distinct: bool,
+) -> Vec<Option<Cow<'a, str>>> {
+    let mut values = Vec::with_capacity(results.len());
+    for value in results.iter().map(|info| field.value(info)) {
+        if !distinct || !values.contains(&value) {
+            values.push(value);
+        }
+    }
+    values
+}
+
+// How results spell `flag`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum FlagFormat {
+    // The emoji itself, as stored
+    #[default]
+    Emoji,
+    // Its code points, as in `U+1F1EF U+1F1F5`, for clients that mangle emoji
+    Codepoints,
+    // Left out
+    None,
+}
+
+impl FlagFormat {
+    // The `flagFormat` query parameter; emoji when absent
+    pub fn from_param(format: Option<&str>) -> Result<Self, String> {
+        match format.unwrap_or("emoji") {
+            "emoji" => Ok(FlagFormat::Emoji),
+            "codepoints" => Ok(FlagFormat::Codepoints),
+            "none" => Ok(FlagFormat::None),
+            other => Err(format!(
+                "{:?} is not `emoji`, `codepoints` or `none`",
+                other
+            )),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FlagFormat::Emoji => "emoji",
+            FlagFormat::Codepoints => "codepoints",
+            FlagFormat::None => "none",
+        }
+    }
+}
+
+// "🇯🇵" -> "U+1F1EF U+1F1F5"
+pub fn flag_codepoints(flag: &str) -> String {
+    flag.chars()
+        .map(|c| format!("U+{:04X}", c as u32))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Rewrites each result's `flag` into `format`. Every endpoint that answers with
+// countries passes its results through here before serializing them.
+pub fn format_flags<'a, 'b: 'a>(
+    results: impl IntoIterator<Item = &'a mut CountryInfo<'b>>,
+    format: FlagFormat,
+) {
+    for info in results {
+        match format {
+            FlagFormat::Emoji => {}
+            FlagFormat::Codepoints => info.flag = Cow::Owned(flag_codepoints(&info.flag)),
+            FlagFormat::None => info.flag = Cow::Borrowed(""),
+        }
+    }
+}
+
+// The `maxResults` query parameter, between 1 and `ceiling`; `None` when absent
+pub fn max_results_param(
+    max_results: Option<&str>,
+    ceiling: usize,
+) -> Result<Option<usize>, String> {
+    let Some(max_results) = max_results else {
+        return Ok(None);
+    };
+    let max = max_results
+        .trim()
+        .parse::<i64>()
+        .map_err(|_| format!("{:?} is not a whole number", max_results))?;
+    if max < 1 {
+        return Err(format!("{} is below 1", max));
+    }
+    match usize::try_from(max) {
+        Ok(max) if max <= ceiling => Ok(Some(max)),
+        _ => Err(format!(
+            "{} is above this server's limit of {}",
+            max, ceiling
+        )),
+    }
+}
+
+// Cuts `results` down to `max`, saying whether any were left out
+pub fn truncate(results: &mut Results, max: Option<usize>) -> bool {
+    match max {
+        Some(max) if results.len() > max => {
+            results.truncate(max);
+            true
+        }
+        _ => false,
+    }
+}
+
+// The order of `/getCountry` results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    // As the entries were given
+    #[default]
+    Input,
+    // By `canonicalName`, whatever spelling an entry used
+    Alpha,
+}
+
+impl Order {
+    // The `order` query parameter; input order when absent
+    pub fn from_param(order: Option<&str>) -> Result<Self, String> {
+        match order.unwrap_or("input") {
+            "input" => Ok(Order::Input),
+            "alpha" => Ok(Order::Alpha),
+            other => Err(format!("{:?} is not `input` or `alpha`", other)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Order::Input => "input",
+            Order::Alpha => "alpha",
+        }
+    }
+}
+
+// What `/getCountry` results are grouped by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    Currency,
+    Continent,
+}
+
+impl GroupBy {
+    // The `groupBy` query parameter; `None`, for flat results, when absent
+    pub fn from_param(group_by: Option<&str>) -> Result<Option<Self>, String> {
+        match group_by {
+            None => Ok(None),
+            Some("currency") => Ok(Some(GroupBy::Currency)),
+            Some("continent") => Ok(Some(GroupBy::Continent)),
+            Some(other) => Err(format!("{:?} is not `currency` or `continent`", other)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GroupBy::Currency => "currency",
+            GroupBy::Continent => "continent",
+        }
+    }
+
+    fn key<'a>(self, info: &CountryInfo<'a>) -> Option<Cow<'a, str>> {
+        match self {
+            GroupBy::Currency => Some(Cow::Owned(info.currency_code.as_str().to_string())),
+            GroupBy::Continent => info
+                .record
+                .and_then(|record| record.continent.as_ref())
+                .map(|continent| Cow::Borrowed(continent.as_str())),
+        }
+    }
+}
+
+// Everything but the language that decides a `/getCountry` response
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResultOptions {
+    pub includes: Includes,
+    pub flag_format: FlagFormat,
+    pub order: Order,
+    pub group_by: Option<GroupBy>,
+    pub only: Option<OnlyField>,
+    pub distinct: bool,
+    // Fail with every unanswered entry when there is one
+    pub strict: bool,
+    // Answer no results, only `unmatched`, when an entry goes unanswered
+    pub atomic: bool,
+    // Most results to answer with; every one when `None`
+    pub max_results: Option<usize>,
+}
+
+impl ResultOptions {
+    // Adds the groups asked for, orders the results and writes their flags. Grouping is
+    // left to `group`, as it changes the response's type.
+    pub fn apply(&self, results: &mut [CountryInfo]) {
+        expand(results, self.includes);
+        if self.order == Order::Alpha {
+            results.sort_by(|a, b| a.canonical_name.cmp(&b.canonical_name));
+        }
+        format_flags(results, self.flag_format);
+    }
+}
+
+// One line, so equal options print the same; part of the response cache key
+impl fmt::Display for ResultOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "include={};flags={};order={};group={};only={};distinct={};strict={};atomic={};max={}",
+            self.includes,
+            self.flag_format.as_str(),
+            self.order.as_str(),
+            self.group_by.map_or("", GroupBy::as_str),
+            self.only.map_or("", OnlyField::as_str),
+            self.distinct,
+            self.strict,
+            self.atomic,
+            self.max_results
+                .map_or(String::new(), |max| max.to_string())
+        )
+    }
+}
+
+// Splits `items` by `by`, groups in the order of their first item and items in the order
+// given; `info` finds each item's country
+pub fn group<'a, T>(
+    items: impl IntoIterator<Item = T>,
+    by: GroupBy,
+    info: impl Fn(&T) -> &CountryInfo<'a>,
+) -> Vec<Group<'a, T>> {
+    let mut groups: Vec<Group<'a, T>> = Vec::new();
+    for item in items {
+        let key = by.key(info(&item));
+        match groups.iter_mut().find(|group| group.key == key) {
+            Some(group) => group.results.push(item),
+            None => groups.push(Group {
+                key,
+                results: vec![item],
+            }),
+        }
+    }
+    groups
+}
+
+impl<'a> CountryInfo<'a> {
+    pub fn new(country: &'a str, record: &'a CountryRecord, language: &str) -> Self {
+        CountryInfo {
+            country: Cow::Borrowed(country),
+            canonical_name: Cow::Borrowed(&record.name),
+            localized_name: Cow::Borrowed(record.localized_name(language)),
+            flag: Cow::Borrowed(&record.flag),
+            currency_code: record.currency_code,
+            phone_code: Cow::Borrowed(&record.phone_code),
+            resolved_from: None,
+            name_status: AliasKind::Current,
+            geo: None,
+            currencies: None,
+            translations: None,
+            record: Some(record),
+        }
+    }
+}
+
+// Typical queries name a handful of countries, which fit without a heap allocation
+pub type Results<'a> = SmallVec<[CountryInfo<'a>; 8]>;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct CountryResponse<'a> {
+    #[cfg_attr(feature = "server", schema(value_type = Vec<CountryInfo>))]
+    pub results: Results<'a>,
+    // Entries that were recognized but could not be answered; unknown names are left
+    // out silently instead
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unmatched: Vec<Unmatched<'a>>,
+    // Set when `maxResults` left results out
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+// `/getCountry` with `groupBy`: the results split into groups, in place of `results`.
+// `T` is `CountryInfo`, or a result with its exchange rate.
+#[derive(Debug, Serialize)]
+pub struct GroupedResponse<'a, T> {
+    pub groups: Vec<Group<'a, T>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unmatched: Vec<Unmatched<'a>>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+// `/getCountry` with `only`: one field of each result, in place of `results`
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct ValuesResponse<'a> {
+    #[cfg_attr(feature = "server", schema(value_type = Vec<Option<String>>, example = json!(["JPY", "KRW"])))]
+    pub values: Vec<Option<Cow<'a, str>>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unmatched: Vec<Unmatched<'a>>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Group<'a, T> {
+    // The currency code or continent the results share; null for countries without a
+    // continent
+    pub key: Option<Cow<'a, str>>,
+    pub results: Vec<T>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct Unmatched<'a> {
+    // The entry as written
+    pub input: Cow<'a, str>,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct ValidateResponse<'a> {
+    // One per non-empty entry, in the order given
+    pub results: Vec<Validation<'a>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct Validation<'a> {
+    // The entry as written, trimmed
+    pub input: Cow<'a, str>,
+    pub valid: bool,
+    // The display name of the country the entry names, such as `Japan`, in the response
+    // language; only for valid entries
+    #[serde(
+        rename = "canonicalName",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub canonical_name: Option<Cow<'a, str>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct CodesResponse<'a> {
+    // One per country, sorted by alpha-2 code
+    pub codes: Vec<CountryCodes<'a>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct CountryCodes<'a> {
+    // The canonical name
+    pub country: Cow<'a, str>,
+    #[cfg_attr(feature = "server", schema(value_type = String, example = "JP"))]
+    pub alpha2: Alpha2,
+    // The alpha-3 and numeric codes are only known for ISO 3166-1 countries, so a custom
+    // country whose flag spells no such code has neither
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "server", schema(example = "JPN"))]
+    pub alpha3: Option<Cow<'a, str>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "server", schema(example = "392"))]
+    pub numeric: Option<Cow<'a, str>>,
+}
+
+// `/byCurrency` answers a code with a plain list and a symbol with its currencies. The
+// list keeps its results inline, so it is boxed to keep the enum small.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(untagged)]
+pub enum CurrencyResponse<'a> {
+    Code(Box<CountryResponse<'a>>),
+    Symbol(SymbolResponse<'a>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct SymbolResponse<'a> {
+    // The symbol as given, trimmed
+    pub symbol: Cow<'a, str>,
+    // Whether more than one currency used by a served country is written with it
+    pub ambiguous: bool,
+    pub currencies: Vec<CurrencyCountries<'a>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct CurrencyCountries<'a> {
+    #[serde(rename = "currencyCode")]
+    #[cfg_attr(feature = "server", schema(value_type = String, example = "JPY"))]
+    pub currency_code: CurrencyCode,
+    #[cfg_attr(feature = "server", schema(value_type = Vec<CountryInfo>))]
+    pub countries: Results<'a>,
+}
+
+// `/byLanguage` answers a language with a plain list and `list=true` with the languages
+// themselves
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(untagged)]
+pub enum LanguageResponse<'a> {
+    Countries(Box<CountryResponse<'a>>),
+    Languages(LanguagesResponse<'a>),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct LanguagesResponse<'a> {
+    // Every language a served country lists, sorted by code
+    pub languages: Vec<LanguageCount<'a>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct LanguageCount<'a> {
+    // ISO 639 code
+    #[cfg_attr(feature = "server", schema(example = "fr"))]
+    pub code: Cow<'a, str>,
+    // English name; only missing for a code custom data lists that the table lacks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "server", schema(example = "French"))]
+    pub name: Option<Cow<'a, str>>,
+    // Countries listing it as official
+    pub countries: usize,
+}
+
+// U+FE0F, asking for the emoji presentation of the character before it
+const VARIATION_SELECTOR: char = '\u{FE0F}';
+
+// Generous serialized size of one result, so the body buffer rarely has to grow
+const ESTIMATED_RESULT_BYTES: usize = 200;
+
+pub fn to_json(response: &CountryResponse) -> Bytes {
+    let mut body = Vec::with_capacity(
+        r#"{"results":[]}"#.len() + response.results.len() * ESTIMATED_RESULT_BYTES,
+    );
+    serde_json::to_writer(&mut body, response).expect("responses serialize to JSON");
+    Bytes::from(body)
+}
+
+// `lang` as a language code, English when absent or blank
+pub fn requested_language(lang: Option<&str>) -> String {
+    lang.map(dataset::language_code)
+        .filter(|language| !language.is_empty())
+        .unwrap_or_else(|| dataset::DEFAULT_LANGUAGE.to_string())
+}
+
+// The language of `localizedName`: `lang` when given, otherwise the most preferred
+// language of the `Accept-Language` header that `supported` accepts, otherwise English
+pub fn negotiated_language(
+    lang: Option<&str>,
+    accept_language: Option<&str>,
+    supported: impl Fn(&str) -> bool,
+) -> String {
+    if lang.is_some_and(|lang| !lang.trim().is_empty()) {
+        return requested_language(lang);
+    }
+    accept_language
+        .map(accepted_languages)
+        .unwrap_or_default()
+        .into_iter()
+        .find(|language| language == "*" || supported(language))
+        .filter(|language| language != "*")
+        .unwrap_or_else(|| dataset::DEFAULT_LANGUAGE.to_string())
+}
+
+// The language codes of an `Accept-Language` header, most preferred first and each
+// once. Entries refused with `q=0` are left out, as are ones that do not parse; `*`
+// stands for any language.
+pub fn accepted_languages(header: &str) -> Vec<String> {
+    let mut weighted: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            let tag = parts.next().filter(|tag| is_language_range(tag))?;
+            let mut quality = 1.0;
+            for parameter in parts {
+                let (name, value) = parameter.split_once('=')?;
+                if name.trim().eq_ignore_ascii_case("q") {
+                    quality = value
+                        .trim()
+                        .parse()
+                        .ok()
+                        .filter(|quality| (0.0..=1.0).contains(quality))?;
+                }
+            }
+            (quality > 0.0).then(|| (dataset::language_code(tag), quality))
+        })
+        .collect();
+    // Stable, so equally weighted languages keep the order they were sent in
+    weighted.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+    let mut languages: Vec<String> = Vec::with_capacity(weighted.len());
+    for (language, _) in weighted {
+        if !languages.contains(&language) {
+            languages.push(language);
+        }
+    }
+    languages
+}
+
+// `*`, or subtags of one to eight letters and digits joined by `-`, the first letters only
+fn is_language_range(tag: &str) -> bool {
+    tag == "*"
+        || tag.split('-').enumerate().all(|(position, subtag)| {
+            (1..=8).contains(&subtag.len())
+                && subtag
+                    .chars()
+                    .all(|c| c.is_ascii_alphabetic() || (position > 0 && c.is_ascii_digit()))
+        })
+}
+
+// Every entry of `based` that `response` has no result for, in the order given: those
+// already in `unmatched` with their reason, and unknown names, which lookups leave out
+pub fn unanswered<'a>(based: &'a str, response: &CountryResponse<'a>) -> Vec<Unmatched<'a>> {
+    if based.trim().eq_ignore_ascii_case("all") {
+        return Vec::new();
+    }
+    based
+        .split(',')
+        .map(input::clean)
+        .filter(|entry| !entry.is_empty())
+        .filter(|entry| !response.results.iter().any(|info| info.country == *entry))
+        .map(|entry| Unmatched {
+            input: Cow::Borrowed(entry),
+            reason: response
+                .unmatched
+                .iter()
+                .find(|unmatched| unmatched.input == entry)
+                .map_or_else(
+                    || String::from("No served country has this name"),
+                    |unmatched| unmatched.reason.clone(),
+                ),
+        })
+        .collect()
+}
+
+// What one `based` entry names
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resolution<'a> {
+    // A name or alias, with how current it is
+    Name(&'a CountryRecord, AliasKind),
+    // A flag emoji
+    Flag(&'a CountryRecord),
+    // A name no served country has
+    UnknownName,
+    // Regional indicator symbols that are not two, so not a flag
+    NotAFlag,
+    // A flag whose code no served country has
+    UnservedFlag(Alpha2),
+    // An address, bare or in an `ip:` entry, located in a served country
+    Ip(&'a CountryRecord),
+    // An address located in a country no served one has the code of
+    UnservedIp(Alpha2),
+    // A bare address that could not be located, with why
+    UnlocatedIp(String),
+}
+
+// One `based` entry, cleaned, and what it names
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedEntry<'a> {
+    pub input: &'a str,
+    pub resolution: Resolution<'a>,
+}
+
+impl<'a> ResolvedEntry<'a> {
+    // The entry as a result echoing it as written, or as `unmatched` with the reason.
+    // `Ok(None)` is an unknown name, which lookups leave out.
+    pub fn to_result(&self, language: &str) -> Result<Option<CountryInfo<'a>>, Unmatched<'a>> {
+        let unmatched = |reason| Unmatched {
+            input: Cow::Borrowed(self.input),
+            reason,
+        };
+        match self.resolution {
+            Resolution::Name(record, name_status) => Ok(Some(CountryInfo {
+                name_status,
+                ..CountryInfo::new(self.input, record, language)
+            })),
+            Resolution::Flag(record) => Ok(Some(CountryInfo {
+                resolved_from: Some(ResolvedFrom::Flag),
+                ..CountryInfo::new(self.input, record, language)
+            })),
+            Resolution::UnknownName => Ok(None),
+            Resolution::NotAFlag => Err(unmatched(String::from(
+                "Not a flag: a flag is two regional indicator symbols",
+            ))),
+            Resolution::UnservedFlag(alpha2) => Err(unmatched(format!(
+                "No served country has the code {}",
+                alpha2
+            ))),
+            Resolution::Ip(record) => Ok(Some(CountryInfo {
+                resolved_from: Some(ResolvedFrom::Ip),
+                ..CountryInfo::new(self.input, record, language)
+            })),
+            // An `ip:` entry asked for an address, which is left out like an unknown name;
+            // a bare one is mixed in with names, so it is listed
+            Resolution::UnservedIp(_) if ip_entry(self.input).is_some() => Ok(None),
+            Resolution::UnservedIp(alpha2) => Err(unmatched(format!(
+                "No served country has the code {}",
+                alpha2
+            ))),
+            Resolution::UnlocatedIp(ref reason) => Err(unmatched(reason.clone())),
+        }
+    }
+}
+
+// `based` resolved entry by entry, in the order given
+#[derive(Debug, Default)]
+pub struct ResolvedQuery<'a> {
+    pub entries: SmallVec<[ResolvedEntry<'a>; 8]>,
+}
+
+impl<'a> ResolvedQuery<'a> {
+    // The results and unmatched flags a lookup answers with
+    pub fn response(&self, language: &str) -> CountryResponse<'a> {
+        // Sized for every name being known, which is the common case
+        let mut results = SmallVec::with_capacity(self.entries.len());
+        let mut unmatched = Vec::new();
+        for entry in &self.entries {
+            match entry.to_result(language) {
+                Ok(Some(info)) => results.push(info),
+                Ok(None) => {}
+                Err(entry) => unmatched.push(entry),
+            }
+        }
+        CountryResponse {
+            results,
+            unmatched,
+            truncated: false,
+        }
+    }
+
+    // The entries that are names no served country has, in the order given
+    pub fn unknown_names(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.entries
+            .iter()
+            .filter(|entry| entry.resolution == Resolution::UnknownName)
+            .map(|entry| entry.input)
+    }
+}
+
+// The one pipeline every `based` goes through: split on commas, each entry cleaned, and
+// resolved as a flag or, failing that, as a name. Blank entries are left out, so there
+// are never more entries than commas plus one. Any string resolves; without a locator
+// addresses resolve as unknown names, see `resolve_query_with`.
+pub fn resolve_query<'a>(based: &'a str, dataset: &'a Dataset) -> ResolvedQuery<'a> {
+    let entries = split_entries(based)
+        .map(|entry| ResolvedEntry {
+            input: entry,
+            resolution: resolve(dataset, entry),
+        })
+        .collect();
+    ResolvedQuery { entries }
+}
+
+// `resolve_query`, with `ip:` entries and bare addresses placed in a country by
+// `locator`. An `ip:` entry that cannot be located fails the whole query with its
+// error; a bare address is mixed in with names, so it resolves as `UnlocatedIp` instead.
+pub fn resolve_query_with<'a, L: AddressLocator>(
+    based: &'a str,
+    dataset: &'a Dataset,
+    locator: &L,
+) -> Result<ResolvedQuery<'a>, (&'a str, L::Error)> {
+    let located = |alpha2| {
+        dataset
+            .by_alpha2(alpha2)
+            .map_or(Resolution::UnservedIp(alpha2), Resolution::Ip)
+    };
+    let mut entries = SmallVec::new();
+    for entry in split_entries(based) {
+        let resolution = if let Some(address) = ip_entry(entry) {
+            located(locator.locate(address).map_err(|error| (entry, error))?)
+        } else if entry.parse::<IpAddr>().is_ok() {
+            match locator.locate(entry) {
+                Ok(alpha2) => located(alpha2),
+                Err(error) => Resolution::UnlocatedIp(error.to_string()),
+            }
+        } else {
+            resolve(dataset, entry)
+        };
+        entries.push(ResolvedEntry {
+            input: entry,
+            resolution,
+        });
+    }
+    Ok(ResolvedQuery { entries })
+}
+
+// The cleaned, non-blank entries of `based`
+fn split_entries(based: &str) -> impl Iterator<Item = &str> {
+    based
+        .split(',')
+        .map(input::clean)
+        .filter(|entry| !entry.is_empty())
+}
+
+// Marks a `based` entry as an IP address rather than a name
+pub const IP_PREFIX: &str = "ip:";
+
+// Places the addresses in `based` in countries, for `resolve_query_with`; the server's
+// GeoIP database
+pub trait AddressLocator {
+    type Error: fmt::Display;
+
+    // The country of `address`, the text of an `ip:` entry or a bare address
+    fn locate(&self, address: &str) -> Result<Alpha2, Self::Error>;
+}
+
+// The address of an `ip:` entry, or None for a name
+pub fn ip_entry(entry: &str) -> Option<&str> {
+    let prefix = entry.get(..IP_PREFIX.len())?;
+    prefix
+        .eq_ignore_ascii_case(IP_PREFIX)
+        .then(|| entry[IP_PREFIX.len()..].trim())
+}
+
+// An `ip:` entry or a bare address
+pub fn is_address_entry(entry: &str) -> bool {
+    ip_entry(entry).is_some() || entry.parse::<IpAddr>().is_ok()
+}
+
+// What `entry`, already cleaned, names
+pub fn resolve<'a>(dataset: &'a Dataset, entry: &str) -> Resolution<'a> {
+    match flag_entry(entry) {
+        None => find(dataset, entry).map_or(Resolution::UnknownName, |(record, name_status)| {
+            Resolution::Name(record, name_status)
+        }),
+        Some(None) => Resolution::NotAFlag,
+        Some(Some(alpha2)) => dataset
+            .by_alpha2(alpha2)
+            .map_or(Resolution::UnservedFlag(alpha2), Resolution::Flag),
+    }
+}
+
+// Resolves each comma-separated name or flag in `based`, skipping unknown names and
+// listing flags no served country has in `unmatched`
+pub fn lookup_countries<'a>(
+    dataset: &'a Dataset,
+    based: &'a str,
+    language: &str,
+) -> CountryResponse<'a> {
+    resolve_query(based, dataset).response(language)
+}
+
+// `/validate`: whether each entry of `based` is one `/getCountry` answers, decided by
+// the same `resolve_query`
+pub fn validate_names<'a>(
+    dataset: &'a Dataset,
+    based: &'a str,
+    language: &str,
+) -> ValidateResponse<'a> {
+    let results = resolve_query(based, dataset)
+        .entries
+        .into_iter()
+        .map(|entry| match entry.to_result(language) {
+            Ok(Some(info)) => Validation {
+                input: Cow::Borrowed(entry.input),
+                valid: true,
+                canonical_name: Some(info.localized_name),
+            },
+            Ok(None) | Err(_) => Validation {
+                input: Cow::Borrowed(entry.input),
+                valid: false,
+                canonical_name: None,
+            },
+        })
+        .collect();
+    ValidateResponse { results }
+}
+
+// One `based` entry: a name or alias, or a flag emoji answered with the country whose
+// flag it is and echoed as written. `Ok(None)` is an unknown name.
+pub fn resolve_entry<'a>(
+    dataset: &'a Dataset,
+    entry: &'a str,
+    language: &str,
+) -> Result<Option<CountryInfo<'a>>, Unmatched<'a>> {
+    ResolvedEntry {
+        input: entry,
+        resolution: resolve(dataset, entry),
+    }
+    .to_result(language)
+}
+
+// Adds the field groups `includes` asks for to each result. Rates are left to the
+// caller, which has to fetch them.
+pub fn expand(results: &mut [CountryInfo], includes: Includes) {
+    for info in results {
+        let Some(record) = info.record else {
+            continue;
+        };
+        if includes.contains(IncludeGroup::Geo) {
+            info.geo = Some(GeoFields {
+                latlng: record.latlng,
+                borders: Cow::Borrowed(&record.borders),
+                timezones: Cow::Borrowed(&record.timezones),
+            });
+        }
+        if includes.contains(IncludeGroup::Currencies) {
+            let code = record.currency_code;
+            info.currencies = Some(CurrencyFields {
+                currencies: vec![CurrencyDetails {
+                    code,
+                    symbols: currency_symbol::symbols(code)
+                        .into_iter()
+                        .map(Cow::Borrowed)
+                        .collect(),
+                }],
+            });
+        }
+        if includes.contains(IncludeGroup::Translations) {
+            info.translations = Some(TranslationFields {
+                translations: Cow::Borrowed(&record.localized_names),
+            });
+        }
+    }
+}
+
+// `None` unless `entry` is made of regional indicator symbols, as flag emoji are, then
+// the code they spell if there are two. A trailing variation selector, which some
+// keyboards append to emoji, is ignored.
+pub fn flag_entry(entry: &str) -> Option<Option<Alpha2>> {
+    let indicators = entry.trim_end_matches(VARIATION_SELECTOR);
+    if indicators.is_empty() || !indicators.chars().all(dataset::is_regional_indicator) {
+        return None;
+    }
+    Some(Alpha2::from_flag(indicators))
+}
+
+// Resolves each of `names`, returning the matches and, separately, the names that
+// matched nothing, both in the order given
+pub fn resolve_names<'a>(
+    dataset: &'a Dataset,
+    names: impl IntoIterator<Item = &'a str>,
+    language: &str,
+) -> (Results<'a>, Vec<&'a str>) {
+    let mut results = Results::new();
+    let mut unknown = Vec::new();
+    for name in names {
+        match find(dataset, name) {
+            Some((record, name_status)) => results.push(CountryInfo {
+                name_status,
+                ..CountryInfo::new(name, record, language)
+            }),
+            None => unknown.push(name),
+        }
+    }
+    (results, unknown)
+}
+
+// Normalizes `name` once and looks it up, returning the record with how current the
+// name is; short ASCII names are normalized on the stack
+pub fn find<'a>(dataset: &'a Dataset, name: &str) -> Option<(&'a CountryRecord, AliasKind)> {
+    let with_status = |name: &str| {
+        dataset
+            .get(name)
+            .map(|record| (record, record.name_status(name)))
+    };
+    let mut buffer = [0; 64];
+    if name.is_ascii() && name.len() <= buffer.len() {
+        let length = normalize::normalized_chars(name)
+            .zip(&mut buffer)
+            .map(|(c, byte)| *byte = c as u8)
+            .count();
+        return with_status(std::str::from_utf8(&buffer[..length]).expect("ASCII is valid UTF-8"));
+    }
+    with_status(&normalize::normalize(name))
+}
+
+// `based=all`: one entry per name and alias, sorted by name
+pub fn all_names<'a>(dataset: &'a Dataset, language: &str) -> Results<'a> {
+    let mut countries: Vec<_> = dataset.names().collect();
+    countries.sort_by_key(|(country_name, _)| *country_name);
+    countries
+        .into_iter()
+        .map(|(country_name, record)| CountryInfo {
+            name_status: record.name_status(country_name),
+            ..CountryInfo::new(country_name, record, language)
+        })
+        .collect()
+}
+
+// One entry per country under its canonical name, sorted by name
+pub fn canonical_countries<'a>(dataset: &'a Dataset, language: &str) -> Results<'a> {
+    sorted_by_name(dataset.records().collect(), language)
+}
+
+// `/codes`: every country's codes under its canonical name, sorted by alpha-2 code and
+// then by name, as custom countries may share a flag
+pub fn code_table(dataset: &Dataset) -> CodesResponse<'_> {
+    let mut codes: Vec<_> = dataset
+        .records()
+        .filter_map(|record| {
+            let alpha2 = Alpha2::from_flag(&record.flag)?;
+            let builtin = builtin::by_alpha2(alpha2);
+            Some(CountryCodes {
+                country: Cow::Borrowed(&record.name),
+                alpha2,
+                alpha3: builtin.map(|country| Cow::Borrowed(country.alpha3)),
+                numeric: builtin.map(|country| Cow::Borrowed(country.numeric)),
+            })
+        })
+        .collect();
+    codes.sort_by(|a, b| (a.alpha2, &a.country).cmp(&(b.alpha2, &b.country)));
+    CodesResponse { codes }
+}
+
+pub fn stats(dataset: &Dataset) -> StatsResponse {
+    let mut stats = StatsResponse {
+        countries: 0,
+        aliases: 0,
+        continents: BTreeMap::new(),
+        currencies: BTreeMap::new(),
+    };
+    for record in dataset.records() {
+        stats.countries += 1;
+        stats.aliases += record.aliases.len();
+        let continent = record
+            .continent
+            .as_ref()
+            .map_or("unknown", Continent::as_str);
+        *stats.continents.entry(continent.to_string()).or_default() += 1;
+        *stats
+            .currencies
+            .entry(record.currency_code.to_string())
+            .or_default() += 1;
+    }
+    stats
+}
+
+// The countries using `currency_code`, under their canonical names, sorted by name
+pub fn countries_by_currency<'a>(
+    dataset: &'a Dataset,
+    currency_code: CurrencyCode,
+    language: &str,
+) -> Results<'a> {
+    let records = dataset
+        .records()
+        .filter(|record| record.currency_code == currency_code)
+        .collect();
+    sorted_by_name(records, language)
+}
+
+// The currencies written with `symbol` that served countries use, sorted by code, each
+// with its countries as `countries_by_currency` lists them
+pub fn countries_by_symbol<'a>(
+    dataset: &'a Dataset,
+    symbol: &'a str,
+    language: &str,
+) -> SymbolResponse<'a> {
+    let currencies: Vec<_> = currency_symbol::currencies(symbol)
+        .into_iter()
+        .map(|currency_code| CurrencyCountries {
+            currency_code,
+            countries: countries_by_currency(dataset, currency_code, language),
+        })
+        .filter(|currency| !currency.countries.is_empty())
+        .collect();
+    SymbolResponse {
+        symbol: Cow::Borrowed(symbol.trim()),
+        ambiguous: currencies.len() > 1,
+        currencies,
+    }
+}
+
+// The countries of the longest calling code `digits` starts with, under their canonical
+// names, sorted by name
+pub fn countries_by_calling_code<'a>(
+    dataset: &'a Dataset,
+    digits: &str,
+    language: &str,
+) -> Results<'a> {
+    let records = dataset
+        .by_calling_code(digits)
+        .iter()
+        .map(|record| record.as_ref())
+        .collect();
+    sorted_by_name(records, language)
+}
+
+// The countries whose TLD is `tld`, under their canonical names, sorted by name; empty
+// for generic TLDs such as `.com`
+pub fn countries_by_tld<'a>(dataset: &'a Dataset, tld: &str, language: &str) -> Results<'a> {
+    let records = tld::normalize(tld)
+        .map(|tld| {
+            dataset
+                .by_tld(&tld)
+                .iter()
+                .map(|record| record.as_ref())
+                .collect()
+        })
+        .unwrap_or_default();
+    sorted_by_name(records, language)
+}
+
+// The countries listing `query` as an official language, under their canonical names,
+// sorted by name; `query` is a code or name as `language::resolve` takes it, and one it
+// does not know has no countries
+pub fn countries_by_language<'a>(dataset: &'a Dataset, query: &str, language: &str) -> Results<'a> {
+    let Some(code) = language::resolve(query) else {
+        return Results::new();
+    };
+    let records = dataset
+        .records()
+        .filter(|record| record.languages.contains(&code))
+        .collect();
+    sorted_by_name(records, language)
+}
+
+// The countries using `zone`, which is spelled as `timezone::canonical` returns it, under
+// their canonical names, sorted by name
+pub fn countries_by_timezone<'a>(dataset: &'a Dataset, zone: &str, language: &str) -> Results<'a> {
+    let records = dataset
+        .records()
+        .filter(|record| record.timezones.iter().any(|known| known == zone))
+        .collect();
+    sorted_by_name(records, language)
+}
+
+// The countries with a zone `offset` seconds east of UTC at `at`, under their canonical
+// names, sorted by name; a country whose zones only reach the offset in another season
+// does not match
+pub fn countries_by_offset<'a>(
+    dataset: &'a Dataset,
+    offset: i32,
+    at: DateTime<Utc>,
+    language: &str,
+) -> Results<'a> {
+    let records = dataset
+        .records()
+        .filter(|record| {
+            record
+                .timezones
+                .iter()
+                .any(|zone| timezone::offset_at(zone, at) == Some(offset))
+        })
+        .collect();
+    sorted_by_name(records, language)
+}
+
+pub fn language_counts(dataset: &Dataset) -> LanguagesResponse<'_> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for record in dataset.records() {
+        for code in &record.languages {
+            *counts.entry(code).or_default() += 1;
+        }
+    }
+    LanguagesResponse {
+        languages: counts
+            .into_iter()
+            .map(|(code, countries)| LanguageCount {
+                code: Cow::Borrowed(code),
+                name: language::name(code).map(Cow::Borrowed),
+                countries,
+            })
+            .collect(),
+    }
+}
+
+// The countries whose bounding box contains the point, under their canonical names,
+// the smallest box first
+pub fn countries_by_coordinates<'a>(
+    dataset: &'a Dataset,
+    latitude: f64,
+    longitude: f64,
+    language: &str,
+) -> Results<'a> {
+    let mut matches: Vec<(&CountryRecord, f64)> = dataset
+        .records()
+        .filter_map(|record| {
+            let bounding_box = record.bounding_box.as_ref()?;
+            bounding_box
+                .contains(latitude, longitude)
+                .then(|| (record, bounding_box.area()))
+        })
+        .collect();
+    matches.sort_by(|(a, a_area), (b, b_area)| {
+        a_area.total_cmp(b_area).then_with(|| a.name.cmp(&b.name))
+    });
+    matches
+        .into_iter()
+        .map(|(record, _)| CountryInfo::new(&record.name, record, language))
+        .collect()
+}
+
+fn sorted_by_name<'a>(mut records: Vec<&'a CountryRecord>, language: &str) -> Results<'a> {
+    records.sort_by_key(|record| &record.name);
+    records
+        .into_iter()
+        .map(|record| CountryInfo::new(&record.name, record, language))
+        .collect()
+}
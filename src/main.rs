@@ -1,157 +1,889 @@
-use axum::{
-    extract::Query,
-    response::Json,
-    routing::get,
-    Router,
+use anyhow::Context;
+use axum::http::HeaderValue;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use ipnet::IpNet;
+use listenfd::ListenFd;
+#[cfg(feature = "redis")]
+use rusty_currency::cache;
+use rusty_currency::{
+    access_log::{self, AccessLogConfig},
+    admin_auth::{self, BasicAuth},
+    api_keys::ApiKeys,
+    audit::AuditLog,
+    binary::{adopt_listeners, check_source, install_panic_hook, take_inherited_listeners},
+    bind_listeners, bind_listeners_with_retry, breaker,
+    catalog::{self, ListCaching},
+    cli::{self, DumpFormat, OutputFormat},
+    client_ip::{self, TrustedProxies},
+    config, create_app_with_state, database, geoip,
+    https::{Hsts, HttpsRedirect},
+    log_level::LogLevel,
+    lookup::requested_language,
+    merge, overrides,
+    rate_snapshot::RateSnapshot,
+    rates, reload, remote,
+    retry::{self, RetryPolicy},
+    run_server_until,
+    security_headers::{self, HeaderSetting, SecurityHeaders},
+    self_test,
+    shutdown::{self, DrainOutcome},
+    snapshot,
+    source::{BuiltinLoader, DataFileLoader, DatasetLoader},
+    tls, webhooks, AppConfig, AppState, SelectedAllocator, ServeOptions, ALLOCATOR_NAME,
+    DEFAULT_LISTEN_ADDRESS, DEFAULT_MAX_RESULTS, DEFAULT_SLOW_REQUEST_THRESHOLD,
 };
-use once_cell::sync::Lazy;
-use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    fs::File,
-    io::{BufRead, BufReader},
+    collections::BTreeMap,
+    ffi::OsString,
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
+    path::PathBuf,
+    process::ExitCode,
+    sync::Arc,
+    time::Duration,
 };
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
-#[derive(Debug, Deserialize)]
-struct CountryQuery {
-    based: String,
+#[derive(Debug, Parser)]
+#[command(version, about = "Country flag, currency and phone code lookup API")]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // Running without a subcommand serves, as `serve` does
+    #[command(flatten)]
+    serve: ServeArgs,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct CountryInfo {
-    country: String,
-    flag: String,
-    #[serde(rename = "currencyCode")]
-    currency_code: String,
-    #[serde(rename = "phoneCode")]
-    phone_code: String,
+// `Args`' command, with the variable of each `serve` option in its help
+fn command() -> clap::Command {
+    config::document_variables(Args::command()).mut_subcommand("serve", config::document_variables)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct CountryResponse {
-    results: Vec<CountryInfo>,
+impl Args {
+    // The options serving uses, given to `serve` or to no subcommand
+    fn serve_args(&self) -> &ServeArgs {
+        match &self.command {
+            Some(Command::Serve(serve)) => serve,
+            _ => &self.serve,
+        }
+    }
 }
 
-const DEFAULT_COUNTRY_DATA_PATH: &str = "data/countries.csv";
-const CSV_FIELD_COUNT: usize = 4;
+#[derive(Debug, clap::Args)]
+#[command(group = clap::ArgGroup::new("admin_credentials").args(["admin_token", "admin_user"]).multiple(true))]
+struct ServeArgs {
+    /// TOML file of server options, keyed by their long names (`rate-limit = 100`);
+    /// flags and environment variables override it
+    #[arg(long)]
+    config: Option<PathBuf>,
 
-fn parse_country_data<R: BufRead>(reader: R) -> HashMap<String, (String, String, String)> {
-    let mut data = HashMap::new();
+    /// Address to listen on; pass multiple times to serve on several addresses
+    #[arg(long = "listen", default_value = DEFAULT_LISTEN_ADDRESS)]
+    listen: Vec<SocketAddr>,
 
-    for (line_index, line) in reader.lines().skip(1).enumerate() {
-        let file_line_number = line_index + 2;
-        let line = line.unwrap_or_else(|error| {
-            panic!(
-                "Failed to read country data line {}: {}",
-                file_line_number, error
-            );
-        });
-        let line = line.trim();
-        if line.is_empty() {
-            continue;
-        }
-        let mut parts = line.splitn(CSV_FIELD_COUNT, ',');
-        let country = parts.next().unwrap_or("").trim();
-        let flag = parts.next().unwrap_or("").trim();
-        let currency_code = parts.next().unwrap_or("").trim();
-        let phone_code = parts.next().unwrap_or("").trim();
-        if country.is_empty() || flag.is_empty() || currency_code.is_empty() || phone_code.is_empty() {
-            tracing::warn!(
-                "Skipping malformed country data line {}: {}",
-                file_line_number,
-                line
-            );
-            continue;
-        }
-        data.insert(
-            country.to_string(),
-            (
-                flag.to_string(),
-                currency_code.to_string(),
-                phone_code.to_string(),
-            ),
-        );
+    /// What to log, as a filter like `info` or `warn,rustincountry::unmatched=off`;
+    /// `RUST_LOG`, or `info`, when not set
+    #[arg(long)]
+    log: Option<String>,
+
+    /// Seconds to keep retrying an address that is in use, as while the instance being
+    /// replaced drains; fails right away when not set
+    #[arg(long)]
+    bind_retry: Option<NonZeroU64>,
+
+    /// PEM certificate chain to serve HTTPS with, leaf first; reloaded whenever it or
+    /// --tls-key changes
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key of --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Also listen on this address for plain HTTP, answering every request with a 301 to
+    /// the same URL over HTTPS
+    #[arg(long, requires = "tls_cert")]
+    https_redirect_listen: Option<SocketAddr>,
+
+    /// Port the HTTPS redirects point at, when clients reach HTTPS on another port than
+    /// the first --listen address's, as behind a port mapping
+    #[arg(long, requires = "https_redirect_listen")]
+    https_redirect_port: Option<u16>,
+
+    /// Send Strict-Transport-Security with this max-age, in seconds, on every HTTPS
+    /// response
+    #[arg(long, requires = "tls_cert")]
+    hsts_max_age: Option<u64>,
+
+    /// Hold subdomains to HTTPS as well, with includeSubDomains in
+    /// Strict-Transport-Security
+    #[arg(long, requires = "hsts_max_age")]
+    hsts_include_subdomains: bool,
+
+    /// X-Content-Type-Options for every response, or `off`; `nosniff` when not set
+    #[arg(long, value_parser = security_headers::parse_setting)]
+    content_type_options: Option<HeaderSetting>,
+
+    /// Referrer-Policy for every response, or `off`; `no-referrer` when not set
+    #[arg(long, value_parser = security_headers::parse_setting)]
+    referrer_policy: Option<HeaderSetting>,
+
+    /// Content-Security-Policy for HTML pages, or `off`; when not set, one that only
+    /// allows what the server itself serves
+    #[arg(long, value_parser = security_headers::parse_setting)]
+    content_security_policy: Option<HeaderSetting>,
+
+    /// Permissions-Policy for HTML pages, or `off`; when not set, one that denies the
+    /// camera, microphone, geolocation, payment and USB
+    #[arg(long, value_parser = security_headers::parse_setting)]
+    permissions_policy: Option<HeaderSetting>,
+
+    /// JSON country data file to serve instead of the built-in dataset
+    #[arg(long)]
+    data_file: Option<PathBuf>,
+
+    /// SQLite database to serve instead of the built-in dataset (see `migrate`)
+    #[arg(long, conflicts_with = "data_file")]
+    database: Option<PathBuf>,
+
+    /// URL of a JSON country data file to serve and refresh periodically; the built-in
+    /// dataset is served if the first download fails
+    #[arg(
+        long,
+        conflicts_with_all = ["data_file", "database"]
+    )]
+    data_url: Option<String>,
+
+    /// How often to download --data-url again, e.g. 90s, 15m or 1h
+    #[arg(
+        long,
+        requires = "data_url",
+        value_parser = remote::parse_interval,
+        default_value = "15m"
+    )]
+    refresh_interval: Duration,
+
+    /// Serve the /admin endpoints, which change the served data, to requests bearing
+    /// --admin-token or the --admin-user credentials
+    #[arg(long, requires = "admin_credentials")]
+    enable_admin: bool,
+
+    /// Token the /admin endpoints accept as `Authorization: Bearer <token>`
+    #[arg(long)]
+    admin_token: Option<String>,
+
+    /// Username the /admin endpoints accept with HTTP Basic auth
+    #[arg(long, requires = "admin_password_hash")]
+    admin_user: Option<String>,
+
+    /// Argon2 hash of the --admin-user password, as `rusty_currency hash-password` prints it
+    #[arg(long, requires = "admin_user", value_parser = admin_auth::parse_password_hash)]
+    admin_password_hash: Option<String>,
+
+    /// Layer the data file over the built-in dataset instead of replacing it
+    #[arg(long, requires = "data_file")]
+    merge: bool,
+
+    /// Reload the data file automatically whenever it changes
+    #[arg(long, requires = "data_file")]
+    watch: bool,
+
+    /// Directory to save admin changes in, which are restored from it on restart
+    #[arg(long)]
+    state_dir: Option<PathBuf>,
+
+    /// File to append a JSON line to for every admin change and reload; a change whose
+    /// line cannot be written is not made
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+
+    /// Validate the configured country data, print a report and exit without serving
+    #[arg(long, conflicts_with_all = ["watch", "data_url"])]
+    check: bool,
+
+    /// Serve on a loopback port, check that each endpoint answers as expected with this
+    /// configuration, print a report and exit; status 1 if any check fails
+    #[arg(long, conflicts_with = "check")]
+    self_test: bool,
+
+    /// Number of serialized /getCountry responses to cache; 0 disables the cache
+    #[arg(long, default_value_t = 0)]
+    response_cache: usize,
+
+    /// Origin allowed to call the API from a browser, or `*` for any; pass multiple times
+    #[arg(long = "cors-origin", value_parser = HeaderValue::from_str)]
+    cors_origins: Vec<HeaderValue>,
+
+    /// Load balancers and proxies whose X-Forwarded-For and Forwarded headers name the
+    /// client, as addresses or CIDR ranges such as 10.0.0.0/8; comma-separated or passed
+    /// multiple times. Those headers are ignored from any other peer
+    #[arg(long, value_delimiter = ',', value_parser = client_ip::parse_network)]
+    trusted_proxies: Vec<IpNet>,
+
+    /// Most lookup and admin requests to serve per second; unlimited when not set
+    #[arg(long)]
+    rate_limit: Option<NonZeroU32>,
+
+    /// TOML file of named API keys and the tiers that set each one's requests per minute
+    /// and per day; requests without a key are limited by its `anonymous` tier, if any
+    #[arg(long)]
+    api_keys: Option<PathBuf>,
+
+    /// Seconds a lookup or admin request may take before it fails with 503; no limit when
+    /// not set
+    #[arg(long)]
+    request_timeout: Option<NonZeroU64>,
+
+    /// Most lookup and admin requests to handle at once; more fail with 503 right away
+    #[arg(long)]
+    max_concurrent_requests: Option<NonZeroUsize>,
+
+    /// Seconds to wait on SIGTERM or Ctrl-C for requests in flight; those still running
+    /// after it are dropped and the server exits with status 1
+    #[arg(long, default_value_t = shutdown::DEFAULT_DRAIN_TIMEOUT.as_secs())]
+    drain_timeout: u64,
+
+    /// Seconds caches and CDNs may serve /countries and /codes without revalidating
+    #[arg(long, default_value_t = catalog::DEFAULT_LIST_MAX_AGE.as_secs())]
+    list_max_age: u64,
+
+    /// Seconds past --list-max-age that caches may serve a stale /countries or /codes
+    /// while they revalidate it in the background
+    #[arg(long, default_value_t = catalog::DEFAULT_LIST_STALE_WHILE_REVALIDATE.as_secs())]
+    list_stale_while_revalidate: u64,
+
+    /// Highest `maxResults` a /getCountry or /countries request may ask for
+    #[arg(long, default_value_t = DEFAULT_MAX_RESULTS)]
+    max_results: usize,
+
+    /// Times per minute each unknown name /getCountry is asked for is logged under the
+    /// `rustincountry::unmatched` target; the rest are only counted
+    #[arg(long, default_value_t = NonZeroU32::MIN)]
+    unmatched_log_limit: NonZeroU32,
+
+    /// Log one in N successful requests under the `rustincountry::access` target; failed
+    /// and slow requests are always logged
+    #[arg(long, default_value_t = NonZeroU32::MIN)]
+    access_log_sample_rate: NonZeroU32,
+
+    /// Milliseconds after which a request is logged whatever --access-log-sample-rate says
+    #[arg(long, default_value_t = access_log::DEFAULT_SLOW_THRESHOLD.as_millis() as u64)]
+    access_log_slow_ms: u64,
+
+    /// Path whose successful requests are never logged; pass multiple times for several
+    #[arg(long = "access-log-exclude", default_values = access_log::DEFAULT_EXCLUDED_PATHS)]
+    access_log_excluded_paths: Vec<String>,
+
+    /// Milliseconds after which a request is logged as a warning, with its normalized query
+    /// and how many results it had
+    #[arg(long, default_value_t = DEFAULT_SLOW_REQUEST_THRESHOLD.as_millis() as u64)]
+    slow_request_ms: u64,
+
+    /// Exchange rate API for `/getCountry?rates=true`, with `{currency}` standing for the
+    /// currency code, e.g. https://api.frankfurter.app/latest?from={currency}&to=USD
+    #[arg(long)]
+    rates_url: Option<String>,
+
+    /// Seconds to reuse an exchange rate before fetching it again
+    #[arg(long, default_value_t = rates::DEFAULT_TTL.as_secs())]
+    rates_ttl: u64,
+
+    /// Failed calls in a row after which the rate provider is not asked again until
+    /// --rates-breaker-cooldown passes; rates are answered as null meanwhile
+    #[arg(long, default_value_t = breaker::DEFAULT_FAILURE_THRESHOLD)]
+    rates_breaker_failures: NonZeroU32,
+
+    /// Seconds to stop asking a failing rate provider before trying it with one request
+    #[arg(long, default_value_t = breaker::DEFAULT_COOLDOWN.as_secs())]
+    rates_breaker_cooldown: u64,
+
+    /// Answer exchange rates from the snapshot bundled into the binary when --rates-url is
+    /// not set or its provider is failing
+    #[arg(long)]
+    rates_snapshot: bool,
+
+    /// Times to retry an exchange rate or --data-url fetch that timed out, could not
+    /// connect or got a 5xx answer; 4xx answers are never retried
+    #[arg(long, default_value_t = retry::DEFAULT_MAX_RETRIES)]
+    upstream_retries: u32,
+
+    /// Milliseconds to wait before the first retry of an upstream fetch, doubling after
+    /// each, less a random amount of up to half
+    #[arg(long, default_value_t = retry::DEFAULT_INITIAL_BACKOFF.as_millis() as u64)]
+    upstream_retry_backoff_ms: u64,
+
+    /// URL to POST every change to the served data to; pass multiple times for several
+    #[arg(long = "webhook-url", requires = "webhook_secret")]
+    webhook_urls: Vec<String>,
+
+    /// Key of the HMAC-SHA256 signature sent with each webhook
+    #[arg(long)]
+    webhook_secret: Option<String>,
+
+    /// MaxMind database (e.g. GeoLite2-Country.mmdb) for `/whoami` and `ip:` lookups
+    #[arg(long)]
+    geoip_db: Option<PathBuf>,
+
+    /// Don't serve the Swagger UI and ReDoc pages at /docs and /redoc
+    #[arg(long)]
+    disable_docs: bool,
+
+    /// Redis server to keep cached exchange rates in, shared by every replica using it,
+    /// e.g. redis://127.0.0.1/; rates are cached in memory while it is unreachable
+    #[cfg(feature = "redis")]
+    #[arg(long)]
+    redis_url: Option<String>,
+
+    /// Also share serialized /getCountry responses through --redis-url
+    #[cfg(feature = "redis")]
+    #[arg(long, requires = "redis_url")]
+    redis_response_cache: bool,
+
+    /// Also serve the gRPC API on this address, e.g. 0.0.0.0:50051
+    #[cfg(feature = "grpc")]
+    #[arg(long)]
+    grpc_listen: Option<SocketAddr>,
+
+    /// Serve the GraphiQL playground at /graphql/playground, for debugging queries
+    #[cfg(feature = "graphql")]
+    #[arg(long)]
+    graphql_playground: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Serve the API (the default when no subcommand is given)
+    Serve(Box<ServeArgs>),
+    /// Look countries up in the built-in dataset and print them, without starting the server
+    ///
+    /// Exits with status 1, listing the names on stderr, if any name matches no country.
+    Lookup {
+        /// Country names or aliases; quote names that contain spaces
+        #[arg(required = true)]
+        names: Vec<String>,
+        /// Print the same JSON body `/getCountry` returns instead of a table
+        #[arg(long)]
+        json: bool,
+        /// Language of the localized names
+        #[arg(long)]
+        lang: Option<String>,
+    },
+    /// Print the whole configured dataset, validated, in the same format as /admin/export
+    ///
+    /// Exits with status 1, with the reason on stderr, if the data fails to load.
+    Dump {
+        /// Output format
+        #[arg(long, value_enum, default_value = "json")]
+        format: DumpFormat,
+        /// JSON country data file to dump instead of the built-in dataset
+        #[arg(long, env = "RUSTINCOUNTRY_DATA_FILE")]
+        data_file: Option<PathBuf>,
+        /// SQLite database to dump instead of the built-in dataset
+        #[arg(long, conflicts_with = "data_file")]
+        database: Option<PathBuf>,
+        /// Layer the data file over the built-in dataset instead of replacing it
+        #[arg(long, requires = "data_file")]
+        merge: bool,
+    },
+    /// Print a completion script for a shell, e.g.
+    /// `rusty_currency completions bash > /etc/bash_completion.d/rusty_currency`
+    Completions {
+        /// Shell to complete in
+        shell: clap_complete::Shell,
+    },
+    /// Read a password from stdin and print its Argon2 hash, for --admin-password-hash
+    HashPassword,
+    /// Create the SQLite schema and seed it with the built-in dataset if it is empty
+    Migrate {
+        /// SQLite database to create or update
+        #[arg(long)]
+        database: PathBuf,
+    },
+}
+
+#[global_allocator]
+static GLOBAL: SelectedAllocator = SelectedAllocator;
+
+// The data `--database` or `--data-file` name, or the built-in dataset, with the
+// `RUSTINCOUNTRY_OVERRIDE_*` overrides applied
+fn configured_source(
+    database: Option<&PathBuf>,
+    data_file: Option<&PathBuf>,
+    merge: bool,
+) -> anyhow::Result<Arc<dyn DatasetLoader>> {
+    let source: Arc<dyn DatasetLoader> = match (database, data_file) {
+        (Some(path), _) => Arc::new(database::SqliteLoader::new(path)),
+        (None, Some(path)) if merge => Arc::new(merge::MergedLoader::new(path)),
+        (None, Some(path)) => Arc::new(DataFileLoader::new(path)),
+        (None, None) => Arc::new(BuiltinLoader),
+    };
+    let overrides = overrides::Overrides::from_env()?;
+    Ok(if overrides.is_empty() {
+        source
+    } else {
+        Arc::new(overrides::OverriddenLoader::new(source, overrides))
+    })
+}
+
+// Connects to `url`, which may be down now and come up later
+#[cfg(feature = "redis")]
+async fn connect_redis(url: &str) -> anyhow::Result<cache::RedisCache> {
+    let redis = cache::RedisCache::connect(
+        url,
+        cache::REDIS_PREFIX,
+        Arc::new(cache::MemoryCache::new(0)),
+    )
+    .context("Invalid --redis-url")?;
+    // An unreachable server is logged as a warning by the ping itself
+    if redis.ping().await {
+        tracing::info!("Sharing cached data through Redis");
     }
+    Ok(redis)
+}
 
-    data
+#[tokio::main]
+async fn main() -> ExitCode {
+    // The error and its causes on one line, without the backtrace `RUST_BACKTRACE` would
+    // add to returning it from `main`
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {:#}", error);
+            ExitCode::FAILURE
+        }
+    }
 }
 
-// Global country data initialized once - All 195 UN-recognized countries
-static COUNTRY_DATA: Lazy<HashMap<String, (String, String, String)>> = Lazy::new(|| {
-    let (path, path_source) = match std::env::var("COUNTRY_DATA_PATH") {
-        Ok(path) => (path, "COUNTRY_DATA_PATH"),
-        Err(_) => (DEFAULT_COUNTRY_DATA_PATH.to_string(), "default path"),
+async fn run() -> anyhow::Result<()> {
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    // Variables that are not Unicode name no option
+    let env: BTreeMap<String, String> = std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .collect();
+    let parser = command();
+    let lenient = parser.clone().ignore_errors(true).get_matches_from(&argv);
+    let mut config = None;
+    let mut unknown_keys = Vec::new();
+    let matches = match lenient.subcommand() {
+        None | Some(("serve", _)) => {
+            let serve = lenient.subcommand_matches("serve").unwrap_or(&lenient);
+            config = serve
+                .get_one::<PathBuf>("config")
+                .cloned()
+                .or_else(|| env.get(&config::variable("config")).map(PathBuf::from));
+            let file = config.as_deref().map(config::load).transpose()?;
+            let resolved = match config::resolve(parser, &argv, &env, file.as_ref()) {
+                Err(config::ConfigError::Rejected(error)) => error.exit(),
+                resolved => resolved?,
+            };
+            unknown_keys = resolved.unknown_keys;
+            resolved.matches
+        }
+        Some(_) => parser.get_matches_from(&argv),
     };
-    let file = File::open(&path).unwrap_or_else(|error| {
-        panic!(
-            "Failed to open country data file at {} (source: {}): {}",
-            path, path_source, error
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+
+    // Runs before logging is set up so nothing but the results reaches stdout
+    if let Some(Command::Lookup { names, json, lang }) = &args.command {
+        let dataset = BuiltinLoader.load()?;
+        let format = if *json {
+            OutputFormat::Json
+        } else {
+            OutputFormat::Table
+        };
+        let all_matched = cli::lookup(
+            &dataset,
+            names,
+            &requested_language(lang.as_deref()),
+            format,
+            &mut io::stdout().lock(),
+            &mut io::stderr().lock(),
         )
-    });
-    let reader = BufReader::new(file);
-    parse_country_data(reader)
-});
-
-async fn get_country(Query(params): Query<CountryQuery>) -> Json<CountryResponse> {
-    let mut results = Vec::new();
-
-    if params.based.trim().eq_ignore_ascii_case("all") {
-        let mut countries: Vec<_> = COUNTRY_DATA.iter().collect();
-        countries.sort_by(|(left, _), (right, _)| left.cmp(right));
-        for (country_name, (flag, currency_code, phone_code)) in countries {
-            results.push(CountryInfo {
-                country: country_name.clone(),
-                flag: flag.clone(),
-                currency_code: currency_code.clone(),
-                phone_code: phone_code.clone(),
-            });
-        }
-        return Json(CountryResponse { results });
+        .context("Failed to print lookup results")?;
+        std::process::exit(if all_matched { 0 } else { 1 });
+    }
+    if let Some(Command::Dump {
+        format,
+        data_file,
+        database,
+        merge,
+    }) = &args.command
+    {
+        let source = configured_source(database.as_ref(), data_file.as_ref(), *merge)?;
+        let loaded = cli::dump(
+            source.as_ref(),
+            *format,
+            &mut io::stdout().lock(),
+            &mut io::stderr().lock(),
+        )
+        .context("Failed to print the dataset")?;
+        std::process::exit(if loaded { 0 } else { 1 });
+    }
+    if let Some(Command::Completions { shell }) = &args.command {
+        cli::completions(command(), *shell, &mut io::stdout().lock())
+            .context("Failed to print the completion script")?;
+        return Ok(());
+    }
+    if let Some(Command::HashPassword) = &args.command {
+        let mut password = String::new();
+        io::stdin()
+            .read_line(&mut password)
+            .context("Failed to read the password")?;
+        let password = password.trim_end_matches(['\r', '\n']);
+        anyhow::ensure!(!password.is_empty(), "The password is empty");
+        println!("{}", admin_auth::hash_password(password));
+        return Ok(());
     }
 
-    // Split the based parameter by comma and process each country
-    let countries: Vec<&str> = params.based.split(',').map(|s| s.trim()).collect();
+    // Initialize tracing, logging at info level unless RUST_LOG says otherwise, through a
+    // filter `/admin/loglevel` can change
+    let directives = match &args.serve_args().log {
+        Some(filter) => Some(filter.clone()),
+        None => std::env::var(EnvFilter::DEFAULT_ENV).ok(),
+    };
+    let (log_level, filter) = match directives.and_then(|d| LogLevel::new(&d).ok()) {
+        Some(parsed) => parsed,
+        None => LogLevel::new("info").context("Failed to set up logging")?,
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .init();
+    install_panic_hook();
+    tracing::info!("Using the {} allocator", ALLOCATOR_NAME);
+    if let (Some(path), false) = (&config, unknown_keys.is_empty()) {
+        tracing::warn!(
+            "Ignoring keys in {} that name no option: {}",
+            path.display(),
+            unknown_keys.join(", ")
+        );
+    }
 
-    for country_name in countries {
-        let country_lower = country_name.to_lowercase();
+    if let Some(Command::Migrate { database }) = &args.command {
+        let mut connection = rusqlite::Connection::open(database)
+            .with_context(|| format!("Failed to open database {}", database.display()))?;
+        let seeded = database::migrate(&mut connection)
+            .with_context(|| format!("Failed to migrate database {}", database.display()))?;
+        tracing::info!(
+            "Migrated database {}, seeded {} countries",
+            database.display(),
+            seeded
+        );
+        return Ok(());
+    }
 
-        if let Some((flag, currency_code, phone_code)) = COUNTRY_DATA.get(&country_lower) {
-            results.push(CountryInfo {
-                country: country_name.to_string(),
-                flag: flag.clone(),
-                currency_code: currency_code.clone(),
-                phone_code: phone_code.clone(),
-            });
+    let args = match args.command {
+        Some(Command::Serve(serve)) => *serve,
+        _ => args.serve,
+    };
+    let source = configured_source(args.database.as_ref(), args.data_file.as_ref(), args.merge)?;
+    if args.check {
+        let valid = check_source(source.as_ref());
+        std::process::exit(if valid { 0 } else { 1 });
+    }
+
+    let retry = RetryPolicy {
+        max_retries: args.upstream_retries,
+        initial_backoff: Duration::from_millis(args.upstream_retry_backoff_ms),
+    };
+    let remote = args
+        .data_url
+        .map(|url| remote::RemoteDataset::new(url).with_retry(retry));
+    let downloaded = match &remote {
+        Some(remote) => match remote.fetch().await {
+            Ok(dataset) => dataset,
+            Err(error) => {
+                tracing::warn!("Serving {} instead: {}", source.describe(), error);
+                None
+            }
+        },
+        None => None,
+    };
+    let refreshed = downloaded.is_some();
+    let dataset = match (downloaded, &remote) {
+        (Some(dataset), Some(remote)) => {
+            tracing::info!(
+                "Downloaded {} countries from {}",
+                dataset.len(),
+                remote.url()
+            );
+            dataset
+        }
+        _ => {
+            let dataset = source.load()?;
+            tracing::info!(
+                "Loaded {} countries from {}",
+                dataset.len(),
+                source.describe()
+            );
+            dataset
+        }
+    };
+    let snapshots = args
+        .state_dir
+        .as_deref()
+        .map(snapshot::SnapshotStore::new)
+        .transpose()?;
+    let dataset = match &snapshots {
+        Some(snapshots) => snapshots.restore(dataset, source.modified()),
+        None => dataset,
+    };
+    let mut security_headers = SecurityHeaders::default();
+    if let Some(HeaderSetting(value)) = args.content_type_options {
+        security_headers.content_type_options = value;
+    }
+    if let Some(HeaderSetting(value)) = args.referrer_policy {
+        security_headers.referrer_policy = value;
+    }
+    if let Some(HeaderSetting(value)) = args.content_security_policy {
+        security_headers.content_security_policy = value;
+    }
+    if let Some(HeaderSetting(value)) = args.permissions_policy {
+        security_headers.permissions_policy = value;
+    }
+    let api_keys = args.api_keys.as_deref().map(ApiKeys::load).transpose()?;
+    if let Some(keys) = &api_keys {
+        tracing::info!("Limiting requests by {} API keys", keys.len());
+    }
+    let config = AppConfig {
+        admin_token: args.admin_token.filter(|_| args.enable_admin),
+        admin_basic: match (args.admin_user, args.admin_password_hash) {
+            (Some(username), Some(hash)) if args.enable_admin => {
+                Some(BasicAuth::new(username, hash)?)
+            }
+            _ => None,
+        },
+        response_cache: args.response_cache,
+        cors_origins: args.cors_origins,
+        rate_limit: args.rate_limit,
+        api_keys,
+        request_timeout: args
+            .request_timeout
+            .map(|seconds| Duration::from_secs(seconds.get())),
+        max_concurrent_requests: args.max_concurrent_requests,
+        #[cfg(feature = "graphql")]
+        graphql_playground: args.graphql_playground,
+        docs: !args.disable_docs,
+        slow_request_threshold: Some(Duration::from_millis(args.slow_request_ms)),
+        access_log: Some(AccessLogConfig {
+            sample_rate: args.access_log_sample_rate,
+            slow_threshold: Duration::from_millis(args.access_log_slow_ms),
+            excluded_paths: args.access_log_excluded_paths,
+        }),
+        hsts: args.hsts_max_age.map(|max_age| Hsts {
+            max_age: Duration::from_secs(max_age),
+            include_subdomains: args.hsts_include_subdomains,
+        }),
+        https_redirect: args.https_redirect_listen.map(|_| HttpsRedirect {
+            port: args
+                .https_redirect_port
+                .unwrap_or_else(|| args.listen[0].port()),
+        }),
+        security_headers,
+    };
+    let mut state = AppState::new(dataset)
+        .with_response_cache(config.response_cache)
+        .with_max_results(args.max_results)
+        .with_list_caching(ListCaching {
+            max_age: Duration::from_secs(args.list_max_age),
+            stale_while_revalidate: Duration::from_secs(args.list_stale_while_revalidate),
+        })
+        .with_unmatched_log_limit(args.unmatched_log_limit)
+        .with_log_level(Arc::new(log_level))
+        .with_trusted_proxies(TrustedProxies::new(args.trusted_proxies));
+    if let Some(snapshots) = snapshots {
+        state = state.with_snapshots(snapshots);
+    }
+    if let Some(path) = &args.audit_log {
+        state = state.with_audit_log(AuditLog::open(path)?);
+        tracing::info!("Recording dataset changes in {}", path.display());
+    }
+    #[cfg(feature = "redis")]
+    let redis = match &args.redis_url {
+        Some(url) => Some(connect_redis(url).await?),
+        None => None,
+    };
+    #[cfg(feature = "redis")]
+    if let Some(redis) = &redis {
+        let check = redis.with_fallback(Arc::new(cache::MemoryCache::new(0)));
+        state = state.with_dependency("redis", Arc::new(check));
+    }
+    #[cfg(feature = "redis")]
+    if let (Some(redis), true) = (&redis, args.redis_response_cache) {
+        // The local LRU still answers first, so the fallback keeps nothing more
+        let shared = redis.with_fallback(Arc::new(cache::MemoryCache::new(0)));
+        state = state.with_shared_response_cache(config.response_cache, Arc::new(shared));
+    }
+    if let Some(url) = args.rates_url {
+        tracing::info!("Fetching exchange rates from {}", url);
+        let provider = Arc::new(rates::HttpRateProvider::new(url).with_retry(retry));
+        let rates = rates::RateCache::new(provider, Duration::from_secs(args.rates_ttl))
+            .with_breaker(breaker::CircuitBreaker::new(
+                args.rates_breaker_failures,
+                Duration::from_secs(args.rates_breaker_cooldown),
+            ));
+        let rates = if args.rates_snapshot {
+            rates.with_snapshot(RateSnapshot::builtin())
+        } else {
+            rates
+        };
+        #[cfg(feature = "redis")]
+        let rates = match &redis {
+            Some(redis) => {
+                let fallback = cache::MemoryCache::new(rates::MEMORY_CAPACITY);
+                rates.with_store(Arc::new(redis.with_fallback(Arc::new(fallback))))
+            }
+            None => rates,
+        };
+        state = state.with_rates(rates);
+    } else if args.rates_snapshot {
+        let snapshot = RateSnapshot::builtin();
+        tracing::info!(
+            "Answering exchange rates from the bundled snapshot of {} currencies",
+            snapshot.len()
+        );
+        state = state.with_rates(rates::RateCache::offline(snapshot));
+    }
+    if let Some(path) = &args.geoip_db {
+        let resolver = geoip::MaxMindResolver::open(path)?;
+        tracing::info!(
+            "Locating IP addresses with {} from {}",
+            resolver.database_type(),
+            path.display()
+        );
+        state = state.with_geoip(Arc::new(resolver));
+    }
+    let tls = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => {
+            let certificates = Arc::new(
+                tls::TlsCertificates::load(cert, key)
+                    .context("Failed to load the TLS certificate")?,
+            );
+            tracing::info!(
+                "Serving HTTPS with the certificate in {}, valid until {}",
+                cert.display(),
+                httpdate::fmt_http_date(certificates.not_after())
+            );
+            tls::spawn_watch(certificates.clone())
+                .context("Failed to watch the TLS certificate files")?;
+            state = state.with_tls(certificates.clone());
+            Some(certificates)
         }
+        _ => None,
+    };
+    if !args.webhook_urls.is_empty() {
+        let secret = args
+            .webhook_secret
+            .expect("clap requires --webhook-secret with --webhook-url");
+        tracing::info!(
+            "Sending data changes to {} webhook(s)",
+            args.webhook_urls.len()
+        );
+        webhooks::spawn(
+            &state,
+            webhooks::WebhookConfig::new(args.webhook_urls, secret),
+        );
+    }
+    if let Some(remote) = remote {
+        if refreshed {
+            remote::record_success(&state);
+        }
+        tracing::info!(
+            "Refreshing country data from {} every {:?}",
+            remote.url(),
+            args.refresh_interval
+        );
+        remote::spawn_refresh(state.clone(), remote, args.refresh_interval);
+    }
+    // The built-in data cannot change while running, so only external sources reload
+    if args.database.is_some() || args.data_file.is_some() {
+        reload::spawn_reload_on_sighup(state.clone(), source.clone());
     }
+    if let (true, Some(path)) = (args.watch, &args.data_file) {
+        reload::spawn_watch(state.clone(), path.clone(), source)
+            .with_context(|| format!("Failed to watch {}", path.display()))?;
+    }
+    let app = create_app_with_state(state.clone(), &config);
 
-    Json(CountryResponse { results })
-}
+    if args.self_test {
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .context("Failed to bind a loopback port for the self-test")?;
+        let country = self_test::served_country(&state)
+            .context("The self-test needs country data with at least one country")?;
+        let options = ServeOptions {
+            drain_timeout: Duration::from_secs(args.drain_timeout),
+            tls,
+            ..ServeOptions::default()
+        };
+        let report = self_test::run(listener, app, options, &self_test::cases(), &country)
+            .await
+            .context("Self-test failed to run")?;
+        println!("{}", report);
+        std::process::exit(if report.passed() { 0 } else { 1 });
+    }
 
-// Separate function to create the app router for testing
-fn create_app() -> Router {
-    Router::new().route("/getCountry", get(get_country))
-}
+    let inherited = take_inherited_listeners(&mut ListenFd::from_env())
+        .context("Failed to take socket-activated listeners")?;
+    let bind_retry = args
+        .bind_retry
+        .map(|seconds| Duration::from_secs(seconds.get()));
+    let bind = |addresses: Vec<SocketAddr>| async move {
+        match bind_retry {
+            Some(retry_for) => bind_listeners_with_retry(&addresses, retry_for).await,
+            None => bind_listeners(&addresses),
+        }
+    };
+    let mut listeners = if inherited.is_empty() {
+        tracing::info!("Binding listeners from --listen");
+        bind(args.listen).await?
+    } else {
+        tracing::info!(
+            "Using {} socket-activated listener(s) from systemd, ignoring --listen",
+            inherited.len()
+        );
+        adopt_listeners(inherited).context("Failed to adopt socket-activated listeners")?
+    };
 
-#[tokio::main]
-async fn main() {
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
-
-    // Build our application with a route
-    let app = create_app();
-
-    // Run the server
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
-        .await
-        .expect("Failed to bind to 0.0.0.0:3000");
-    
-    println!("Server running on http://0.0.0.0:3000");
-    
-    axum::serve(listener, app)
-        .await
-        .expect("Failed to start server");
-}
+    let mut plain_addresses = Vec::new();
+    if let Some(address) = args.https_redirect_listen {
+        let listener = bind(vec![address]).await?.remove(0);
+        plain_addresses.push(listener.local_addr()?);
+        listeners.push(listener);
+    }
+
+    let options = ServeOptions {
+        drain_timeout: Duration::from_secs(args.drain_timeout),
+        tls,
+        plain_addresses,
+    };
+    #[cfg(feature = "grpc")]
+    let outcome = match args.grpc_listen {
+        Some(address) => {
+            let grpc_listener = bind(vec![address]).await?.remove(0);
+            rusty_currency::run_server_with_grpc_until(
+                listeners,
+                app,
+                grpc_listener,
+                state,
+                shutdown::signal(),
+                options,
+            )
+            .await
+        }
+        None => run_server_until(listeners, app, shutdown::signal(), options).await,
+    };
+    #[cfg(not(feature = "grpc"))]
+    let outcome = run_server_until(listeners, app, shutdown::signal(), options).await;
 
-#[cfg(test)]
-mod tests;
+    match outcome.context("Server failed")? {
+        DrainOutcome::Drained => Ok(()),
+        DrainOutcome::TimedOut { in_flight } => Err(anyhow::anyhow!(
+            "Shut down with {} request(s) still in flight after the {}s --drain-timeout",
+            in_flight,
+            args.drain_timeout
+        )),
+    }
+}
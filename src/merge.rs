@@ -0,0 +1,304 @@
+//! `--merge`: layering a `--data-file` over the built-in dataset instead of replacing it.
+//!
+//! The file uses the `--data-file` schema, applied entry by entry in file order:
+//!
+//! - an entry whose `name` is not a built-in country adds it
+//! - an entry whose `name` is a built-in canonical name replaces that record
+//! - `{"name": "...", "remove": true}` deletes the built-in country of that canonical name
+//!
+//! Each country may appear only once, so the result does not depend on entry order.
+
+use serde::{
+    de::{self, value::MapAccessDeserializer, DeserializeSeed, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    io::Read,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    bounding_box::BoundingBox,
+    codes::{Alpha2, CurrencyCode},
+    data_file::{self, DataFileError},
+    dataset::{Alias, Continent, CountryRecord, Dataset},
+    normalize::normalize,
+    source::{self, BuiltinLoader, DatasetLoader, LoadError},
+};
+
+// Canonical names affected by a merge, each list sorted
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MergeReport {
+    pub added: Vec<String>,
+    pub replaced: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MergeEntry {
+    name: String,
+    #[serde(default)]
+    remove: bool,
+    #[serde(default)]
+    aliases: Vec<Alias>,
+    #[serde(default, rename = "localizedNames")]
+    localized_names: BTreeMap<String, String>,
+    flag: Option<String>,
+    #[serde(rename = "currencyCode")]
+    currency_code: Option<CurrencyCode>,
+    #[serde(rename = "phoneCode")]
+    phone_code: Option<String>,
+    #[serde(rename = "boundingBox")]
+    bounding_box: Option<BoundingBox>,
+    tld: Option<String>,
+    continent: Option<Continent>,
+    #[serde(default)]
+    languages: Vec<String>,
+    #[serde(default)]
+    timezones: Vec<String>,
+    latlng: Option<[f64; 2]>,
+    #[serde(default)]
+    borders: Vec<Alpha2>,
+}
+
+struct Merge {
+    dataset: Dataset,
+    report: MergeReport,
+    // Canonical names of the entries applied so far
+    touched: HashSet<String>,
+}
+
+impl Merge {
+    fn apply(&mut self, entry: MergeEntry) -> Result<(), String> {
+        let name = normalize(&entry.name).into_owned();
+        if !self.touched.insert(name.clone()) {
+            return Err(String::from(
+                "the country appears more than once in the file",
+            ));
+        }
+        let is_builtin = self
+            .dataset
+            .get(&name)
+            .is_some_and(|record| record.name == name);
+
+        if entry.remove {
+            if !entry.aliases.is_empty()
+                || !entry.localized_names.is_empty()
+                || entry.flag.is_some()
+                || entry.currency_code.is_some()
+                || entry.phone_code.is_some()
+                || entry.bounding_box.is_some()
+                || entry.tld.is_some()
+                || entry.continent.is_some()
+                || !entry.languages.is_empty()
+                || !entry.timezones.is_empty()
+                || entry.latlng.is_some()
+                || !entry.borders.is_empty()
+            {
+                return Err(String::from(
+                    "a removal entry takes only `name` and `remove`",
+                ));
+            }
+            if !is_builtin {
+                return Err(String::from("no built-in country has this canonical name"));
+            }
+            self.dataset.remove(&name);
+            self.report.removed.push(name);
+            return Ok(());
+        }
+
+        let record = CountryRecord {
+            name: entry.name,
+            aliases: entry.aliases,
+            localized_names: entry.localized_names,
+            flag: entry.flag.ok_or("missing field `flag`")?,
+            currency_code: entry.currency_code.ok_or("missing field `currencyCode`")?,
+            phone_code: entry.phone_code.ok_or("missing field `phoneCode`")?,
+            bounding_box: entry.bounding_box,
+            tld: entry.tld,
+            continent: entry.continent,
+            languages: entry.languages,
+            timezones: entry.timezones,
+            latlng: entry.latlng,
+            borders: entry.borders,
+        }
+        .normalized();
+        record.validate().map_err(|error| error.to_string())?;
+
+        if is_builtin {
+            self.dataset
+                .replace(&name, record)
+                .map_err(|error| error.to_string())?;
+            self.report.replaced.push(name);
+        } else {
+            self.dataset
+                .insert(record)
+                .map_err(|error| error.to_string())?;
+            self.report.added.push(name);
+        }
+        Ok(())
+    }
+}
+
+// Applies each entry while deserializing so errors carry serde_json's line and column,
+// like the plain `--data-file` loader
+struct FileSeed<'a>(&'a mut Merge);
+struct EntriesSeed<'a>(&'a mut Merge);
+struct EntrySeed<'a>(&'a mut Merge);
+
+impl<'de> DeserializeSeed<'de> for FileSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for FileSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an object with a `countries` array")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut seen_countries = false;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "countries" if seen_countries => {
+                    return Err(de::Error::duplicate_field("countries"))
+                }
+                "countries" => {
+                    map.next_value_seed(EntriesSeed(&mut *self.0))?;
+                    seen_countries = true;
+                }
+                other => return Err(de::Error::unknown_field(other, &["countries"])),
+            }
+        }
+        if !seen_countries {
+            return Err(de::Error::missing_field("countries"));
+        }
+        Ok(())
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for EntriesSeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntriesSeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of country entries")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        while seq.next_element_seed(EntrySeed(&mut *self.0))?.is_some() {}
+        Ok(())
+    }
+}
+
+impl<'de> DeserializeSeed<'de> for EntrySeed<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for EntrySeed<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a country entry object")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        let entry = MergeEntry::deserialize(MapAccessDeserializer::new(map))?;
+        let entry_name = entry.name.clone();
+        self.0.apply(entry).map_err(|reason| {
+            de::Error::custom(format!("country entry {:?}: {}", entry_name, reason))
+        })
+    }
+}
+
+pub fn merge_data_file<R: Read>(
+    base: Dataset,
+    reader: R,
+    path: &Path,
+) -> Result<(Dataset, MergeReport), DataFileError> {
+    let mut merge = Merge {
+        dataset: base,
+        report: MergeReport::default(),
+        touched: HashSet::new(),
+    };
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    FileSeed(&mut merge)
+        .deserialize(&mut deserializer)
+        .and_then(|()| deserializer.end())
+        .map_err(|error| data_file::invalid(path, error))?;
+
+    let mut report = merge.report;
+    report.added.sort();
+    report.replaced.sort();
+    report.removed.sort();
+    Ok((merge.dataset, report))
+}
+
+// A `--data-file` passed with `--merge`, applied over a fresh built-in dataset on every load
+#[derive(Debug, Clone)]
+pub struct MergedLoader {
+    path: PathBuf,
+}
+
+impl MergedLoader {
+    pub fn new(path: &Path) -> Self {
+        MergedLoader {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl DatasetLoader for MergedLoader {
+    fn describe(&self) -> String {
+        format!("{} merged over the built-in dataset", self.path.display())
+    }
+
+    fn load(&self) -> Result<Dataset, LoadError> {
+        let base = BuiltinLoader.load()?;
+        let (dataset, report) = merge_data_file(base, data_file::open(&self.path)?, &self.path)?;
+        tracing::info!(
+            "Merged {} over the built-in dataset: {} added, {} replaced, {} removed (added: {:?}, replaced: {:?}, removed: {:?})",
+            self.path.display(),
+            report.added.len(),
+            report.replaced.len(),
+            report.removed.len(),
+            report.added,
+            report.replaced,
+            report.removed
+        );
+        Ok(dataset)
+    }
+
+    // Merge errors only make sense against the base dataset, so `--check` sees the
+    // merged result or the first error
+    fn records(&self) -> Result<Vec<CountryRecord>, LoadError> {
+        let dataset = self.load()?;
+        let mut records: Vec<_> = dataset.records().cloned().collect();
+        records.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(records)
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        source::file_modified(&self.path)
+    }
+}
@@ -0,0 +1,251 @@
+//! Process-wide counters rendered in the Prometheus text format on `/metrics`.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, PoisonError,
+    },
+    time::{Duration, SystemTime},
+};
+
+/// Upper bounds of the request latency buckets, in seconds. Lookups usually take well
+/// under a millisecond, so the low end is finer than Prometheus' defaults.
+pub const LATENCY_BUCKETS: [f64; 14] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub reloads_succeeded: AtomicU64,
+    pub reloads_failed: AtomicU64,
+    // Only counted while the response cache is enabled
+    pub cache_hits: AtomicU64,
+    pub cache_misses: AtomicU64,
+    // Only counted with `--data-url`
+    pub refreshes_updated: AtomicU64,
+    pub refreshes_unchanged: AtomicU64,
+    pub refreshes_failed: AtomicU64,
+    // Unix time of the last successful `--data-url` download; zero before the first
+    pub last_refresh: AtomicU64,
+    // `/getCountry` entries that named no country, logged or not
+    pub unmatched_names: AtomicU64,
+    // Successful requests the access log left out, as sampled out or on an excluded path
+    pub access_log_sampled_out: AtomicU64,
+    pub access_log_excluded: AtomicU64,
+    // The last reload or `--data-url` refresh, for `/healthz?verbose=true`
+    last_reload: Mutex<Option<ReloadRecord>>,
+    // Route template -> how long its requests took; templates rather than paths, so
+    // there are as many series as routes whatever paths are requested
+    latencies: Mutex<BTreeMap<String, Histogram>>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    // Requests in each bucket of `LATENCY_BUCKETS` alone, and past the last one
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    count: u64,
+    sum: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReloadRecord {
+    pub at: SystemTime,
+    pub succeeded: bool,
+}
+
+impl Metrics {
+    pub fn record_reload(&self, succeeded: bool) {
+        *self
+            .last_reload
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(ReloadRecord {
+            at: SystemTime::now(),
+            succeeded,
+        });
+    }
+
+    pub fn last_reload(&self) -> Option<ReloadRecord> {
+        *self
+            .last_reload
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn record_latency(&self, route: &str, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let mut latencies = self
+            .latencies
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let histogram = match latencies.get_mut(route) {
+            Some(histogram) => histogram,
+            None => latencies.entry(route.to_string()).or_default(),
+        };
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        histogram.buckets[bucket] += 1;
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+        writeln!(
+            output,
+            "# HELP rustincountry_dataset_reloads_total Dataset reloads by outcome"
+        )
+        .unwrap();
+        writeln!(output, "# TYPE rustincountry_dataset_reloads_total counter").unwrap();
+        for (outcome, counter) in [
+            ("success", &self.reloads_succeeded),
+            ("failure", &self.reloads_failed),
+        ] {
+            writeln!(
+                output,
+                "rustincountry_dataset_reloads_total{{outcome=\"{}\"}} {}",
+                outcome,
+                counter.load(Ordering::Relaxed)
+            )
+            .unwrap();
+        }
+        writeln!(
+            output,
+            "# HELP rustincountry_response_cache_requests_total Response cache lookups by result"
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# TYPE rustincountry_response_cache_requests_total counter"
+        )
+        .unwrap();
+        for (result, counter) in [("hit", &self.cache_hits), ("miss", &self.cache_misses)] {
+            writeln!(
+                output,
+                "rustincountry_response_cache_requests_total{{result=\"{}\"}} {}",
+                result,
+                counter.load(Ordering::Relaxed)
+            )
+            .unwrap();
+        }
+        writeln!(
+            output,
+            "# HELP rustincountry_dataset_refreshes_total Downloads from --data-url by outcome"
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# TYPE rustincountry_dataset_refreshes_total counter"
+        )
+        .unwrap();
+        for (outcome, counter) in [
+            ("updated", &self.refreshes_updated),
+            ("unchanged", &self.refreshes_unchanged),
+            ("failure", &self.refreshes_failed),
+        ] {
+            writeln!(
+                output,
+                "rustincountry_dataset_refreshes_total{{outcome=\"{}\"}} {}",
+                outcome,
+                counter.load(Ordering::Relaxed)
+            )
+            .unwrap();
+        }
+        writeln!(
+            output,
+            "# HELP rustincountry_dataset_last_refresh_timestamp_seconds Unix time of the last successful download from --data-url"
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# TYPE rustincountry_dataset_last_refresh_timestamp_seconds gauge"
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "rustincountry_dataset_last_refresh_timestamp_seconds {}",
+            self.last_refresh.load(Ordering::Relaxed)
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# HELP rustincountry_unmatched_names_total /getCountry entries that named no country"
+        )
+        .unwrap();
+        writeln!(output, "# TYPE rustincountry_unmatched_names_total counter").unwrap();
+        writeln!(
+            output,
+            "rustincountry_unmatched_names_total {}",
+            self.unmatched_names.load(Ordering::Relaxed)
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# HELP rustincountry_access_log_skipped_total Successful requests left out of the access log by reason"
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# TYPE rustincountry_access_log_skipped_total counter"
+        )
+        .unwrap();
+        for (reason, counter) in [
+            ("sampled", &self.access_log_sampled_out),
+            ("excluded", &self.access_log_excluded),
+        ] {
+            writeln!(
+                output,
+                "rustincountry_access_log_skipped_total{{reason=\"{}\"}} {}",
+                reason,
+                counter.load(Ordering::Relaxed)
+            )
+            .unwrap();
+        }
+        writeln!(
+            output,
+            "# HELP rustincountry_request_duration_seconds Time taken to answer requests by route"
+        )
+        .unwrap();
+        writeln!(
+            output,
+            "# TYPE rustincountry_request_duration_seconds histogram"
+        )
+        .unwrap();
+        let latencies = self
+            .latencies
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (route, histogram) in latencies.iter() {
+            let bounds = LATENCY_BUCKETS
+                .iter()
+                .map(f64::to_string)
+                .chain([String::from("+Inf")]);
+            let mut cumulative = 0;
+            for (bound, count) in bounds.zip(histogram.buckets) {
+                cumulative += count;
+                writeln!(
+                    output,
+                    "rustincountry_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    route, bound, cumulative
+                )
+                .unwrap();
+            }
+            writeln!(
+                output,
+                "rustincountry_request_duration_seconds_sum{{route=\"{}\"}} {}",
+                route, histogram.sum
+            )
+            .unwrap();
+            writeln!(
+                output,
+                "rustincountry_request_duration_seconds_count{{route=\"{}\"}} {}",
+                route, histogram.count
+            )
+            .unwrap();
+        }
+        output
+    }
+}
@@ -0,0 +1,84 @@
+//! Logging of the names lookups could not resolve, to find out what people type.
+//!
+//! Every name `/getCountry` finds no country for is logged as a `tracing` event with the
+//! target [`TARGET`], holding the normalized name and a salted hash of the caller's
+//! address. A name that keeps coming back would flood the log, so each name is logged
+//! at most `per_minute` times in each one-minute window; the rest only count towards
+//! `rustincountry_unmatched_names_total` on `/metrics`.
+
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    num::NonZeroU32,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// The `tracing` target of the events, for filtering them in or out, as in
+/// `RUST_LOG=rustincountry::unmatched=info`.
+pub const TARGET: &str = "rustincountry::unmatched";
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+// Names tracked at once; past it, names not yet tracked are only counted until old
+// windows expire, so a flood of distinct names cannot grow the table without bound
+const MAX_TRACKED_NAMES: usize = 10_000;
+
+// Hex digits of the address hash kept; enough to tell callers apart
+const CLIENT_HASH_DIGITS: usize = 16;
+
+#[derive(Debug)]
+pub struct MissLog {
+    per_minute: NonZeroU32,
+    // Random per process, so hashes cannot be reversed by hashing every IPv4 address
+    salt: [u8; 16],
+    // Normalized name -> start of its window and events logged in it
+    windows: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl MissLog {
+    pub fn new(per_minute: NonZeroU32) -> Self {
+        MissLog {
+            per_minute,
+            salt: *uuid::Uuid::new_v4().as_bytes(),
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Counts one miss of `name` at `now`; false once its window has had `per_minute`
+    pub fn should_log(&self, name: &str, now: Instant) -> bool {
+        let mut windows = self.windows.lock().unwrap_or_else(PoisonError::into_inner);
+        if !windows.contains_key(name) && windows.len() >= MAX_TRACKED_NAMES {
+            windows.retain(|_, (started, _)| now.duration_since(*started) < WINDOW);
+            if windows.len() >= MAX_TRACKED_NAMES {
+                return false;
+            }
+        }
+        let (started, logged) = windows.entry(name.to_string()).or_insert((now, 0));
+        if now.duration_since(*started) >= WINDOW {
+            *started = now;
+            *logged = 0;
+        }
+        if *logged >= self.per_minute.get() {
+            return false;
+        }
+        *logged += 1;
+        true
+    }
+
+    // The first hex digits of the salted SHA-256 of `address`
+    pub fn client_hash(&self, address: IpAddr) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt);
+        hasher.update(address.to_string());
+        let digest = format!("{:x}", hasher.finalize());
+        digest[..CLIENT_HASH_DIGITS].to_string()
+    }
+}
+
+impl Default for MissLog {
+    fn default() -> Self {
+        MissLog::new(NonZeroU32::MIN)
+    }
+}
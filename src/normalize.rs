@@ -0,0 +1,74 @@
+//! The one normalization applied to country names, both when lookup indexes are built
+//! (at runtime and by `build.rs`, which compiles this same file) and to every name a
+//! request asks for, so the two can never disagree.
+//!
+//! A normalized name is NFC-composed and lowercased, has its typographic apostrophes
+//! replaced with `'`, and has its whitespace, no-break spaces included, trimmed and
+//! collapsed to single spaces. Accents are kept: "curaçao" and "curacao" are different
+//! names, matched through aliases.
+
+use std::{borrow::Cow, char::ToLowercase, iter::FlatMap, str::Chars};
+use unicode_normalization::{Recompositions, UnicodeNormalization};
+
+const APOSTROPHES: [char; 4] = ['\u{2018}', '\u{2019}', '\u{02BC}', '\u{FF07}'];
+
+pub fn normalize(name: &str) -> Cow<'_, str> {
+    if normalized_chars(name).eq(name.chars()) {
+        Cow::Borrowed(name)
+    } else {
+        let mut normalized = String::with_capacity(name.len());
+        normalized.extend(normalized_chars(name));
+        Cow::Owned(normalized)
+    }
+}
+
+// The characters of `name` once normalized; ASCII input gives ASCII output no longer
+// than the input, so callers can normalize short ASCII names into a stack buffer
+pub fn normalized_chars(name: &str) -> impl Iterator<Item = char> + '_ {
+    let name = name.trim();
+    // ASCII is already NFC and lowercases without case tables, which keeps the common
+    // case fast
+    let lowered = if name.is_ascii() {
+        Lowered::Ascii(name.chars())
+    } else {
+        Lowered::Unicode(
+            name.nfc()
+                .flat_map(char::to_lowercase as fn(char) -> ToLowercase)
+                .nfc(),
+        )
+    };
+    let mut after_space = false;
+    lowered
+        .map(|c| match c {
+            c if APOSTROPHES.contains(&c) => '\'',
+            c if c.is_whitespace() => ' ',
+            c => c,
+        })
+        .filter(move |&c| {
+            let repeated = after_space && c == ' ';
+            after_space = c == ' ';
+            !repeated
+        })
+}
+
+// Composed and lowercased characters, composed again since lowering can leave marks a
+// composed character takes, as "Ώ\u{345}" lowers to "ώ\u{345}", which composes to "ῴ";
+// only ever lives on the stack for one name
+#[allow(clippy::large_enum_variant)]
+enum Lowered<'a> {
+    Ascii(Chars<'a>),
+    Unicode(Recompositions<LoweredComposed<'a>>),
+}
+
+type LoweredComposed<'a> = FlatMap<Recompositions<Chars<'a>>, ToLowercase, fn(char) -> ToLowercase>;
+
+impl Iterator for Lowered<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        match self {
+            Lowered::Ascii(chars) => chars.next().map(|c| c.to_ascii_lowercase()),
+            Lowered::Unicode(chars) => chars.next(),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! The OpenAPI document served at `/openapi.json`, for generating clients.
+//!
+//! It is assembled from the `#[utoipa::path]` annotations on the handlers and the
+//! `ToSchema` derives on the bodies they answer with, so it changes with them. The admin
+//! endpoints are listed even though they are only routed with an admin token.
+
+use utoipa::{
+    openapi::{
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+        OpenApi as Document,
+    },
+    Modify, OpenApi,
+};
+
+use crate::server;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(description = "Country flag, currency and phone code lookup API"),
+    paths(
+        server::get_country,
+        server::list_countries,
+        server::validate_names,
+        server::codes,
+        server::by_currency,
+        server::by_calling_code,
+        server::by_coordinates,
+        server::by_tld,
+        server::by_language,
+        server::by_timezone,
+        server::stats,
+        server::flag,
+        server::resolve_batch,
+        server::whoami,
+        server::events,
+        server::openapi_json,
+        server::healthz,
+        server::version,
+        server::checksum,
+        server::metrics,
+        server::add_country,
+        server::update_country,
+        server::delete_country,
+        server::export_dataset,
+        server::get_log_level,
+        server::set_log_level,
+    ),
+    modifiers(&AdminToken),
+    tags(
+        (name = "lookup", description = "Country lookups"),
+        (name = "status", description = "Health, version, checksum and metrics; never rate limited"),
+        (name = "admin", description = "Edits of the served data and the log level; only served with `--enable-admin`"),
+    )
+)]
+struct ApiDoc;
+
+// The bearer token or Basic credentials the admin endpoints require
+struct AdminToken;
+
+impl Modify for AdminToken {
+    fn modify(&self, document: &mut Document) {
+        let components = document.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "admin_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "admin_basic",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Basic).build()),
+        );
+    }
+}
+
+/// The OpenAPI document of every route the enabled features can serve.
+pub fn document() -> Document {
+    #[allow(unused_mut)]
+    let mut document = ApiDoc::openapi();
+    #[cfg(feature = "graphql")]
+    document.merge(crate::graphql::GraphqlDoc::openapi());
+    document
+}
@@ -0,0 +1,253 @@
+//! Emergency per-country patches supplied through environment variables.
+//!
+//! `RUSTINCOUNTRY_OVERRIDE_<COUNTRY>` holds a JSON object with any of the record fields
+//! except `name`; underscores in `<COUNTRY>` stand for spaces, so
+//! `RUSTINCOUNTRY_OVERRIDE_UNITED_KINGDOM='{"currencyCode":"GBP"}'` patches the United
+//! Kingdom. The country may be named by any alias. Overrides for unknown countries add
+//! new entries, which then need `flag`, `currencyCode` and `phoneCode`.
+//!
+//! Overrides are read once at startup and applied on top of every load, reloads included.
+
+use serde::Deserialize;
+use std::{collections::BTreeMap, fmt, sync::Arc, time::SystemTime};
+
+use crate::{
+    bounding_box::BoundingBox,
+    codes::{Alpha2, CurrencyCode},
+    dataset::{Alias, Continent, CountryRecord, Dataset},
+    normalize::normalize,
+    source::{DatasetLoader, LoadError},
+};
+
+const OVERRIDE_PREFIX: &str = "RUSTINCOUNTRY_OVERRIDE_";
+
+// Fields left out keep the overridden record's value
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CountryPatch {
+    aliases: Option<Vec<Alias>>,
+    #[serde(rename = "localizedNames")]
+    localized_names: Option<BTreeMap<String, String>>,
+    flag: Option<String>,
+    #[serde(rename = "currencyCode")]
+    currency_code: Option<CurrencyCode>,
+    #[serde(rename = "phoneCode")]
+    phone_code: Option<String>,
+    #[serde(rename = "boundingBox")]
+    bounding_box: Option<BoundingBox>,
+    tld: Option<String>,
+    continent: Option<Continent>,
+    languages: Option<Vec<String>>,
+    timezones: Option<Vec<String>>,
+    latlng: Option<[f64; 2]>,
+    borders: Option<Vec<Alpha2>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Override {
+    variable: String,
+    // Lowercased name the variable refers to
+    country: String,
+    patch: CountryPatch,
+}
+
+impl Override {
+    fn apply_to(&self, record: CountryRecord) -> CountryRecord {
+        let patch = self.patch.clone();
+        CountryRecord {
+            name: record.name,
+            aliases: patch.aliases.unwrap_or(record.aliases),
+            localized_names: patch.localized_names.unwrap_or(record.localized_names),
+            flag: patch.flag.unwrap_or(record.flag),
+            currency_code: patch.currency_code.unwrap_or(record.currency_code),
+            phone_code: patch.phone_code.unwrap_or(record.phone_code),
+            bounding_box: patch.bounding_box.or(record.bounding_box),
+            tld: patch.tld.or(record.tld),
+            continent: patch.continent.or(record.continent),
+            languages: patch.languages.unwrap_or(record.languages),
+            timezones: patch.timezones.unwrap_or(record.timezones),
+            latlng: patch.latlng.or(record.latlng),
+            borders: patch.borders.unwrap_or(record.borders),
+        }
+    }
+
+    // Missing text fields stay empty so validation reports them; a currency code has no
+    // empty value, so a missing one is reported here
+    fn new_record(&self) -> Result<CountryRecord, OverrideError> {
+        let currency_code = self
+            .patch
+            .currency_code
+            .ok_or_else(|| self.error("missing field `currencyCode`"))?;
+        Ok(self.apply_to(CountryRecord {
+            name: self.country.clone(),
+            aliases: Vec::new(),
+            localized_names: BTreeMap::new(),
+            flag: String::new(),
+            currency_code,
+            phone_code: String::new(),
+            bounding_box: None,
+            tld: None,
+            continent: None,
+            languages: Vec::new(),
+            timezones: Vec::new(),
+            latlng: None,
+            borders: Vec::new(),
+        }))
+    }
+
+    fn error(&self, reason: impl fmt::Display) -> OverrideError {
+        OverrideError {
+            variable: self.variable.clone(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverrideError {
+    pub variable: String,
+    pub reason: String,
+}
+
+impl fmt::Display for OverrideError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid country override {}: {}",
+            self.variable, self.reason
+        )
+    }
+}
+
+impl std::error::Error for OverrideError {}
+
+// Applied in variable name order so the result does not depend on the environment's order
+#[derive(Debug, Clone, Default)]
+pub struct Overrides(Vec<Override>);
+
+impl Overrides {
+    pub fn from_env() -> Result<Self, OverrideError> {
+        Self::parse(std::env::vars())
+    }
+
+    // Picks the override variables out of `vars`, ignoring everything else
+    pub fn parse<I: IntoIterator<Item = (String, String)>>(vars: I) -> Result<Self, OverrideError> {
+        let mut overrides = Vec::new();
+        for (variable, value) in vars {
+            let Some(country) = variable.strip_prefix(OVERRIDE_PREFIX) else {
+                continue;
+            };
+            let country = normalize(&country.replace('_', " ")).into_owned();
+            if country.is_empty() {
+                return Err(OverrideError {
+                    variable,
+                    reason: String::from("the variable name does not name a country"),
+                });
+            }
+            let patch = serde_json::from_str(&value).map_err(|error| OverrideError {
+                variable: variable.clone(),
+                reason: format!("malformed JSON: {}", error),
+            })?;
+            overrides.push(Override {
+                variable,
+                country,
+                patch,
+            });
+        }
+        overrides.sort_by(|a, b| a.variable.cmp(&b.variable));
+
+        Ok(Overrides(overrides))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn apply(&self, dataset: &mut Dataset) -> Result<(), OverrideError> {
+        for item in &self.0 {
+            match dataset.get(&item.country).cloned() {
+                Some(existing) => {
+                    let record = item.apply_to(existing).normalized();
+                    record
+                        .validate()
+                        .and_then(|()| dataset.replace(&item.country, record.clone()))
+                        .map_err(|error| item.error(error))?;
+                    tracing::info!(
+                        "Applied override {} to country {:?}",
+                        item.variable,
+                        record.name
+                    );
+                }
+                None => {
+                    let record = item.new_record()?.normalized();
+                    record
+                        .validate()
+                        .and_then(|()| dataset.insert(record.clone()))
+                        .map_err(|error| item.error(error))?;
+                    tracing::info!(
+                        "Added country {:?} from override {}",
+                        record.name,
+                        item.variable
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Same merge over unvalidated entries, for `--check`
+    pub fn apply_to_records(&self, records: &mut Vec<CountryRecord>) -> Result<(), OverrideError> {
+        for item in &self.0 {
+            let existing = records.iter().position(|record| {
+                record
+                    .clone()
+                    .normalized()
+                    .lookup_names()
+                    .any(|name| name == item.country)
+            });
+            match existing {
+                Some(index) => records[index] = item.apply_to(records[index].clone()),
+                None => records.push(item.new_record()?),
+            }
+        }
+        Ok(())
+    }
+}
+
+// Any other source with the environment overrides layered on top
+pub struct OverriddenLoader {
+    inner: Arc<dyn DatasetLoader>,
+    overrides: Overrides,
+}
+
+impl OverriddenLoader {
+    pub fn new(inner: Arc<dyn DatasetLoader>, overrides: Overrides) -> Self {
+        OverriddenLoader { inner, overrides }
+    }
+}
+
+impl DatasetLoader for OverriddenLoader {
+    fn describe(&self) -> String {
+        format!(
+            "{} with {} environment override(s)",
+            self.inner.describe(),
+            self.overrides.0.len()
+        )
+    }
+
+    fn load(&self) -> Result<Dataset, LoadError> {
+        let mut dataset = self.inner.load()?;
+        self.overrides.apply(&mut dataset)?;
+        Ok(dataset)
+    }
+
+    fn records(&self) -> Result<Vec<CountryRecord>, LoadError> {
+        let mut records = self.inner.records()?;
+        self.overrides.apply_to_records(&mut records)?;
+        Ok(records)
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        self.inner.modified()
+    }
+}
@@ -0,0 +1,51 @@
+//! Optional limit on how many API requests are served per second, across all clients.
+//!
+//! Requests are counted in fixed one-second windows; once a window's budget is spent,
+//! further requests are turned away until the next window starts.
+
+use std::{
+    num::NonZeroU32,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_second: NonZeroU32,
+    window: Mutex<Window>,
+}
+
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    served: u32,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: NonZeroU32) -> Self {
+        RateLimiter {
+            per_second,
+            window: Mutex::new(Window {
+                started: Instant::now(),
+                served: 0,
+            }),
+        }
+    }
+
+    // Counts one request against the current window; false once the window is full
+    pub fn try_acquire(&self) -> bool {
+        let mut window = self.window.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        if now.duration_since(window.started) >= WINDOW {
+            window.started = now;
+            window.served = 0;
+        }
+        if window.served >= self.per_second.get() {
+            return false;
+        }
+        window.served += 1;
+        true
+    }
+}
@@ -0,0 +1,122 @@
+//! Exchange rates bundled into the binary, for servers that cannot reach a rate provider.
+//!
+//! `build.rs` compiles in the rows of the vendored `data/rates.csv` as they are written.
+//! They are parsed the first time they are needed, each rate checked as a live one is, and
+//! answered with the date it was taken so consumers can tell how stale it is. With
+//! `--rates-snapshot`, a [`RateCache`](crate::rates::RateCache) answers from it whenever
+//! the provider is not configured or cannot be reached.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use std::{collections::HashMap, fmt, sync::OnceLock};
+
+use crate::{
+    codes::CurrencyCode,
+    rates::{self, Rate, RateError, RateSource},
+};
+
+// (currency, rateToUsd, asOf) as `data/rates.csv` has them
+static ROWS: &[(&str, &str, &str)] = include!(concat!(env!("OUT_DIR"), "/rate_snapshot.rs"));
+
+static BUILTIN: OnceLock<RateSnapshot> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateSnapshot {
+    rates: HashMap<CurrencyCode, Rate>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    InvalidCurrency(String),
+    InvalidNumber {
+        currency: CurrencyCode,
+        value: String,
+    },
+    InvalidRate(RateError),
+    InvalidDate {
+        currency: CurrencyCode,
+        value: String,
+    },
+    Duplicate(CurrencyCode),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::InvalidCurrency(value) => {
+                write!(f, "{:?} is not a currency code", value)
+            }
+            SnapshotError::InvalidNumber { currency, value } => {
+                write!(f, "The rate for {}, {:?}, is not a number", currency, value)
+            }
+            SnapshotError::InvalidRate(error) => error.fmt(f),
+            SnapshotError::InvalidDate { currency, value } => write!(
+                f,
+                "The date of the rate for {}, {:?}, is not a YYYY-MM-DD date",
+                currency, value
+            ),
+            SnapshotError::Duplicate(currency) => {
+                write!(f, "{} has more than one rate", currency)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl RateSnapshot {
+    // The rates bundled from `data/rates.csv`
+    pub fn builtin() -> &'static RateSnapshot {
+        BUILTIN.get_or_init(|| {
+            RateSnapshot::parse(ROWS.iter().copied())
+                .unwrap_or_else(|error| panic!("The bundled rate snapshot is invalid: {}", error))
+        })
+    }
+
+    // Rates from (currency, rateToUsd, asOf) rows, each taken at the start of its day
+    pub fn parse<'a>(
+        rows: impl IntoIterator<Item = (&'a str, &'a str, &'a str)>,
+    ) -> Result<RateSnapshot, SnapshotError> {
+        let mut rates = HashMap::new();
+        for (currency, to_usd, as_of) in rows {
+            let currency = CurrencyCode::new(currency)
+                .map_err(|_| SnapshotError::InvalidCurrency(currency.to_string()))?;
+            let to_usd = to_usd
+                .parse()
+                .map_err(|_| SnapshotError::InvalidNumber {
+                    currency,
+                    value: to_usd.to_string(),
+                })
+                .and_then(|to_usd| {
+                    rates::validate(currency, to_usd).map_err(SnapshotError::InvalidRate)
+                })?;
+            let as_of = NaiveDate::parse_from_str(as_of, "%Y-%m-%d")
+                .map_err(|_| SnapshotError::InvalidDate {
+                    currency,
+                    value: as_of.to_string(),
+                })?
+                .and_hms_opt(0, 0, 0)
+                .expect("midnight is a valid time");
+            let rate = Rate {
+                to_usd,
+                as_of: DateTime::<Utc>::from_naive_utc_and_offset(as_of, Utc),
+                source: RateSource::Snapshot,
+            };
+            if rates.insert(currency, rate).is_some() {
+                return Err(SnapshotError::Duplicate(currency));
+            }
+        }
+        Ok(RateSnapshot { rates })
+    }
+
+    pub fn rate(&self, currency: CurrencyCode) -> Option<Rate> {
+        self.rates.get(&currency).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+}
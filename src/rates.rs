@@ -0,0 +1,543 @@
+//! Exchange rates for `/getCountry?rates=true`, fetched from a `RateProvider`.
+//!
+//! Rates are cached per currency for a TTL (`--rates-ttl`). A burst of requests for a
+//! currency that is not cached shares a single upstream call rather than making one each:
+//! the first starts it, and the rest wait for its answer, success or failure. Failures
+//! are cached the same way, so an outage costs one call per currency per TTL. They never
+//! fail a request: the rate is answered as null, with a `rateError` saying why.
+//!
+//! When the provider keeps failing, a [`CircuitBreaker`] stops asking it for a while:
+//! lookups are answered at once with a null rate and a `rateError` saying so, instead of
+//! each waiting for the provider to time out.
+//!
+//! A request for countries with several currencies looks them all up at once, each
+//! currency once however many countries use it. At most [`MAX_CONCURRENT_FETCHES`]
+//! upstream calls are made at a time across all requests; the rest wait their turn.
+//!
+//! The cached rates are kept in a `Cache`: in memory by default, or in Redis with
+//! `--redis-url`, so that replicas share them and one upstream call per TTL serves all
+//! of them.
+//!
+//! Every rate is answered with when it was taken and whether it came from the provider
+//! or from the [`RateSnapshot`] bundled into the binary. With a snapshot, a cache answers
+//! from it while the provider is failing or when there is none; a provider answering that
+//! it has no rate for a currency is believed.
+
+use chrono::{DateTime, SubsecRound, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    future::Future,
+    hash::Hash,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{watch, Semaphore},
+    task::JoinSet,
+};
+
+use crate::{
+    breaker::CircuitBreaker,
+    cache::{Cache, Clock, MemoryCache},
+    codes::CurrencyCode,
+    lookup::{CountryInfo, CountryResponse, Unmatched},
+    rate_snapshot::RateSnapshot,
+    retry::{retry_async, RetryPolicy},
+};
+
+// `--rates-url` stands for the currency code with this
+pub const CURRENCY_PLACEHOLDER: &str = "{currency}";
+pub const DEFAULT_TTL: Duration = Duration::from_secs(300);
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+// Rates kept in memory, far more than there are currencies
+pub const MEMORY_CAPACITY: usize = 1024;
+/// Upstream calls a [`RateCache`] makes at the same time.
+pub const MAX_CONCURRENT_FETCHES: usize = 4;
+
+pub type RateFuture<'a> = Pin<Box<dyn Future<Output = Result<f64, RateError>> + Send + 'a>>;
+
+pub trait RateProvider: Send + Sync {
+    // How many US dollars one unit of `currency` is worth
+    fn rate_to_usd(&self, currency: CurrencyCode) -> RateFuture<'_>;
+}
+
+impl fmt::Debug for dyn RateProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateProvider").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RateError {
+    // The provider could not be reached, or answered with an error
+    Upstream(String),
+    // The provider answered without a rate for the currency
+    Missing(CurrencyCode),
+    // The server has no provider to ask (`--rates-url` is not set)
+    NotConfigured,
+    // The provider kept failing, so it is not being asked until the breaker lets a probe
+    // through
+    CircuitOpen,
+    // The rate is not a finite number above zero
+    Invalid { currency: CurrencyCode, rate: f64 },
+}
+
+impl fmt::Display for RateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RateError::Upstream(reason) => write!(f, "The rate provider failed: {}", reason),
+            RateError::Missing(currency) => {
+                write!(f, "The rate provider has no rate for {}", currency)
+            }
+            RateError::NotConfigured => write!(f, "No exchange rate provider is configured"),
+            RateError::CircuitOpen => write!(
+                f,
+                "The rate provider is failing, so it is not being asked for now"
+            ),
+            RateError::Invalid { currency, rate } => {
+                write!(f, "{} is not a valid rate for {}", rate, currency)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RateError {}
+
+// Where a rate came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RateSource {
+    // The rate provider
+    Live,
+    // The rates bundled into the binary
+    Snapshot,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rate {
+    // How many US dollars one unit of the currency is worth
+    pub to_usd: f64,
+    // When the provider answered, or the day the snapshot was taken
+    pub as_of: DateTime<Utc>,
+    pub source: RateSource,
+}
+
+// `rate` if it can be the worth of one unit of `currency`, whoever answered it
+pub fn validate(currency: CurrencyCode, rate: f64) -> Result<f64, RateError> {
+    if rate.is_finite() && rate > 0.0 {
+        Ok(rate)
+    } else {
+        Err(RateError::Invalid { currency, rate })
+    }
+}
+
+/// Rates from an HTTP API that answers `{"rates": {"USD": <rate>}}`, such as
+/// `https://api.frankfurter.app/latest?from={currency}&to=USD`.
+#[derive(Debug, Clone)]
+pub struct HttpRateProvider {
+    client: reqwest::Client,
+    // Contains CURRENCY_PLACEHOLDER wherever the currency code goes
+    url: String,
+    // For timeouts and `5xx` answers, before the call counts as failed
+    retry: RetryPolicy,
+}
+
+#[derive(Debug, Deserialize)]
+struct UpstreamRates {
+    rates: HashMap<String, f64>,
+}
+
+impl HttpRateProvider {
+    pub fn new(url: impl Into<String>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(UPSTREAM_TIMEOUT)
+            .build()
+            .expect("the HTTP client has a valid configuration");
+        HttpRateProvider {
+            client,
+            url: url.into(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+}
+
+impl RateProvider for HttpRateProvider {
+    fn rate_to_usd(&self, currency: CurrencyCode) -> RateFuture<'_> {
+        Box::pin(async move {
+            let url = self.url.replace(CURRENCY_PLACEHOLDER, currency.as_str());
+            let what = format!("Fetching the {} rate", currency);
+            let upstream: UpstreamRates = retry_async(&self.retry, &what, || async {
+                self.client
+                    .get(&url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await
+            })
+            .await
+            .map_err(|error| RateError::Upstream(error.to_string()))?;
+            upstream
+                .rates
+                .get("USD")
+                .copied()
+                .ok_or(RateError::Missing(currency))
+        })
+    }
+}
+
+/// Fixed rates, for tests and offline use. Currencies it has no rate for are `Missing`,
+/// and it counts the calls it answers.
+#[derive(Debug, Default)]
+pub struct StaticRateProvider {
+    rates: HashMap<CurrencyCode, f64>,
+    // Answered to every call instead, when set
+    failure: Option<String>,
+    calls: AtomicUsize,
+}
+
+impl StaticRateProvider {
+    pub fn new(rates: impl IntoIterator<Item = (CurrencyCode, f64)>) -> Self {
+        StaticRateProvider {
+            rates: rates.into_iter().collect(),
+            ..StaticRateProvider::default()
+        }
+    }
+
+    // Fails every call as an unreachable upstream would
+    pub fn failing(reason: &str) -> Self {
+        StaticRateProvider {
+            failure: Some(reason.to_string()),
+            ..StaticRateProvider::default()
+        }
+    }
+
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::Relaxed)
+    }
+}
+
+impl RateProvider for StaticRateProvider {
+    fn rate_to_usd(&self, currency: CurrencyCode) -> RateFuture<'_> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        let rate = match &self.failure {
+            Some(reason) => Err(RateError::Upstream(reason.clone())),
+            None => self
+                .rates
+                .get(&currency)
+                .copied()
+                .ok_or(RateError::Missing(currency)),
+        };
+        Box::pin(async move { rate })
+    }
+}
+
+// Shares one run of a future among every caller that asks for the same key while it runs
+struct SingleFlight<K, V> {
+    // The flights in progress, answering None until they land
+    flights: Mutex<HashMap<K, watch::Receiver<Option<V>>>>,
+}
+
+// Ends its flight when dropped, whether it landed, was cancelled or panicked, so a
+// later call starts another
+struct Landing<'a, K: Hash + Eq, V> {
+    flights: &'a SingleFlight<K, V>,
+    key: K,
+}
+
+impl<K: Hash + Eq, V> Drop for Landing<'_, K, V> {
+    fn drop(&mut self) {
+        self.flights
+            .flights
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.key);
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> SingleFlight<K, V> {
+    fn new() -> Self {
+        SingleFlight {
+            flights: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // What `fetch` answers, or what the flight already in progress for `key` does, in
+    // which case `fetch` is never run
+    async fn run(&self, key: K, fetch: impl Future<Output = V>) -> V {
+        let sender = loop {
+            let mut receiver = {
+                let mut flights = self.flights.lock().unwrap_or_else(PoisonError::into_inner);
+                match flights.get(&key) {
+                    Some(receiver) => receiver.clone(),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        flights.insert(key.clone(), receiver);
+                        break sender;
+                    }
+                }
+            };
+            let landed = match receiver.wait_for(Option::is_some).await {
+                Ok(landed) => landed.clone(),
+                Err(_) => None,
+            };
+            if let Some(value) = landed {
+                return value;
+            }
+            // The caller running the flight went away before it landed; start another
+        };
+
+        let _landing = Landing { flights: self, key };
+        let value = fetch.await;
+        sender.send_replace(Some(value.clone()));
+        value
+    }
+}
+
+pub struct RateCache {
+    // None when only the snapshot answers
+    provider: Option<Arc<dyn RateProvider>>,
+    // Answers for the provider while it fails
+    snapshot: Option<&'static RateSnapshot>,
+    ttl: Duration,
+    // Rates and failures as JSON, under `rate:<currency>`
+    store: Arc<dyn Cache>,
+    // Stops calls to the provider while it keeps failing
+    breaker: CircuitBreaker,
+    // Upstream calls in progress, by currency, with the callers waiting on them
+    flights: SingleFlight<CurrencyCode, Result<Rate, RateError>>,
+    // A permit per upstream call in progress, so a request for many currencies does not
+    // flood the provider
+    fetches: Semaphore,
+}
+
+impl RateCache {
+    pub fn new(provider: Arc<dyn RateProvider>, ttl: Duration) -> Self {
+        RateCache {
+            provider: Some(provider),
+            snapshot: None,
+            ttl,
+            store: Arc::new(MemoryCache::new(MEMORY_CAPACITY)),
+            breaker: CircuitBreaker::default(),
+            flights: SingleFlight::new(),
+            fetches: Semaphore::new(MAX_CONCURRENT_FETCHES),
+        }
+    }
+
+    // Answers from `snapshot` alone, for servers with no provider to ask
+    pub fn offline(snapshot: &'static RateSnapshot) -> Self {
+        RateCache {
+            provider: None,
+            ..RateCache::new(Arc::new(StaticRateProvider::default()), DEFAULT_TTL)
+        }
+        .with_snapshot(snapshot)
+    }
+
+    // Answers from `snapshot` when the provider cannot
+    pub fn with_snapshot(mut self, snapshot: &'static RateSnapshot) -> Self {
+        self.snapshot = Some(snapshot);
+        self
+    }
+
+    // Keeps the rates in memory, reading the time from `clock`
+    pub fn with_clock(self, clock: Arc<dyn Clock>) -> Self {
+        self.with_store(Arc::new(
+            MemoryCache::new(MEMORY_CAPACITY).with_clock(clock),
+        ))
+    }
+
+    // Guards the provider with `breaker` instead of one with the default settings
+    pub fn with_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = breaker;
+        self
+    }
+
+    pub fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    // Keeps the rates in `store`, which other caches may share
+    pub fn with_store(mut self, store: Arc<dyn Cache>) -> Self {
+        self.store = store;
+        self
+    }
+
+    // Asks the provider directly, bypassing the cache. Any answer will do, including
+    // that it has no rate for the currency asked about; with no provider there is
+    // nothing to reach.
+    pub async fn probe(&self) -> Result<(), RateError> {
+        let Some(provider) = &self.provider else {
+            return Ok(());
+        };
+        let euro = CurrencyCode::new("EUR").expect("EUR is a currency code");
+        match provider.rate_to_usd(euro).await {
+            Ok(_) | Err(RateError::Missing(_)) => Ok(()),
+            Err(error) => Err(error),
+        }
+    }
+
+    // How many US dollars one unit of `currency` is worth, as `rate` has it
+    pub async fn rate_to_usd(&self, currency: CurrencyCode) -> Result<f64, RateError> {
+        self.rate(currency).await.map(|rate| rate.to_usd)
+    }
+
+    // The live rate, or the snapshot's when the provider cannot answer and there is one
+    pub async fn rate(&self, currency: CurrencyCode) -> Result<Rate, RateError> {
+        match (self.live_rate(currency).await, self.snapshot) {
+            (Err(error), Some(snapshot)) if !matches!(error, RateError::Missing(_)) => {
+                snapshot.rate(currency).ok_or(error)
+            }
+            (live, _) => live,
+        }
+    }
+
+    // The cached rate while it is younger than the TTL, otherwise a fresh one
+    async fn live_rate(&self, currency: CurrencyCode) -> Result<Rate, RateError> {
+        let Some(provider) = &self.provider else {
+            return Err(RateError::NotConfigured);
+        };
+        if let Some(cached) = self.cached(currency).await {
+            return cached;
+        }
+        self.flights
+            .run(currency, async {
+                // A flight that landed since the lookup above has cached its answer
+                if let Some(cached) = self.cached(currency).await {
+                    return cached;
+                }
+                // Not cached, so the provider is asked again as soon as the breaker allows
+                let Some(call) = self.breaker.try_call() else {
+                    return Err(RateError::CircuitOpen);
+                };
+                let rate = {
+                    let _permit = self
+                        .fetches
+                        .acquire()
+                        .await
+                        .expect("the fetch semaphore is never closed");
+                    provider.rate_to_usd(currency).await
+                };
+                let rate = rate
+                    .and_then(|rate| validate(currency, rate))
+                    .map(|to_usd| Rate {
+                        to_usd,
+                        as_of: Utc::now().trunc_subsecs(0),
+                        source: RateSource::Live,
+                    });
+                // Having no rate for the currency is an answer, not an outage
+                call.record(!matches!(
+                    rate,
+                    Err(RateError::Upstream(_) | RateError::Invalid { .. })
+                ));
+                let cached = serde_json::to_vec(&rate).expect("rates serialize to JSON");
+                self.store
+                    .set(&key(currency), cached.into(), self.ttl)
+                    .await;
+                rate
+            })
+            .await
+    }
+
+    // Entries that do not parse, say from another release, count as missing
+    async fn cached(&self, currency: CurrencyCode) -> Option<Result<Rate, RateError>> {
+        let cached = self.store.get(&key(currency)).await?;
+        serde_json::from_slice(&cached).ok()
+    }
+}
+
+fn key(currency: CurrencyCode) -> String {
+    format!("rate:{}", currency)
+}
+
+impl fmt::Debug for RateCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateCache")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A `/getCountry` result with `rates=true`.
+#[derive(Debug, Serialize)]
+pub struct RatedCountry<'a> {
+    #[serde(flatten)]
+    pub info: CountryInfo<'a>,
+    // Null when the rate could not be had, with `rate_error` saying why
+    #[serde(rename = "exchangeRateToUsd")]
+    pub exchange_rate_to_usd: Option<f64>,
+    // When the rate was taken; absent with no rate
+    #[serde(rename = "rateAsOf", skip_serializing_if = "Option::is_none")]
+    pub rate_as_of: Option<DateTime<Utc>>,
+    #[serde(rename = "rateSource", skip_serializing_if = "Option::is_none")]
+    pub rate_source: Option<RateSource>,
+    #[serde(rename = "rateError", skip_serializing_if = "Option::is_none")]
+    pub rate_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RatedResponse<'a> {
+    pub results: Vec<RatedCountry<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unmatched: Vec<Unmatched<'a>>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+// Adds the rate of each result's currency. Every distinct currency is looked up once,
+// all of them at the same time as far as `MAX_CONCURRENT_FETCHES` allows.
+pub async fn enrich<'a>(
+    rates: Option<Arc<RateCache>>,
+    response: CountryResponse<'a>,
+) -> RatedResponse<'a> {
+    let CountryResponse {
+        results,
+        unmatched,
+        truncated,
+    } = response;
+    let mut found = HashMap::new();
+    if let Some(rates) = rates {
+        let currencies: HashSet<_> = results.iter().map(|info| info.currency_code).collect();
+        let mut lookups = JoinSet::new();
+        for currency in currencies {
+            let rates = Arc::clone(&rates);
+            lookups.spawn(async move { (currency, rates.rate(currency).await) });
+        }
+        while let Some(lookup) = lookups.join_next().await {
+            let (currency, rate) = lookup.expect("rate lookups run to completion");
+            found.insert(currency, rate);
+        }
+    }
+
+    let results = results
+        .into_iter()
+        .map(|info| {
+            let rate = found
+                .get(&info.currency_code)
+                .cloned()
+                .unwrap_or(Err(RateError::NotConfigured));
+            RatedCountry {
+                exchange_rate_to_usd: rate.as_ref().ok().map(|rate| rate.to_usd),
+                rate_as_of: rate.as_ref().ok().map(|rate| rate.as_of),
+                rate_source: rate.as_ref().ok().map(|rate| rate.source),
+                rate_error: rate.err().map(|error| error.to_string()),
+                info,
+            }
+        })
+        .collect();
+    RatedResponse {
+        results,
+        unmatched,
+        truncated,
+    }
+}
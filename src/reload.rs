@@ -0,0 +1,136 @@
+//! Re-reading the `--data-file` or `--database` dataset while the server is running.
+
+use notify::{RecursiveMode, Watcher};
+use std::{
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+use crate::{
+    audit::AuditRecord,
+    dataset::DatasetDiff,
+    source::{DatasetLoader, LoadError},
+    AppState,
+};
+
+// Loads and validates the new data before touching the shared dataset, so a bad file
+// or database leaves the current data in place. Replaces runtime admin edits on success.
+pub fn reload_dataset(
+    state: &AppState,
+    source: &dyn DatasetLoader,
+) -> Result<DatasetDiff, LoadError> {
+    let dataset = source.load()?;
+
+    // Fails when the served data is read-only, or the audit record cannot be written
+    let (diff, _) = state.update_dataset(|current| -> Result<_, LoadError> {
+        let diff = current.diff(&dataset);
+        state.audit(AuditRecord::reload(source.describe(), &diff))?;
+        *current = dataset;
+        Ok(diff)
+    })?;
+    Ok(diff)
+}
+
+// Writes arriving within this window of each other trigger a single reload
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(250);
+
+pub fn reload_and_log(state: &AppState, source: &dyn DatasetLoader) {
+    match reload_dataset(state, source) {
+        Ok(diff) => {
+            state
+                .metrics
+                .reloads_succeeded
+                .fetch_add(1, Ordering::Relaxed);
+            state.metrics.record_reload(true);
+            tracing::info!(
+                "Reloaded country data from {}: {} added, {} removed, {} changed \
+                 (added: {:?}, removed: {:?}, changed: {:?})",
+                source.describe(),
+                diff.added.len(),
+                diff.removed.len(),
+                diff.changed.len(),
+                diff.added,
+                diff.removed,
+                diff.changed
+            )
+        }
+        Err(error) => {
+            state.metrics.reloads_failed.fetch_add(1, Ordering::Relaxed);
+            state.metrics.record_reload(false);
+            tracing::error!("Keeping the current country data: {}", error)
+        }
+    }
+}
+
+#[cfg(unix)]
+pub fn spawn_reload_on_sighup(state: AppState, source: Arc<dyn DatasetLoader>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            tracing::info!("Received SIGHUP, reloading {}", source.describe());
+            reload_and_log(&state, source.as_ref());
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn spawn_reload_on_sighup(_state: AppState, source: Arc<dyn DatasetLoader>) {
+    tracing::warn!(
+        "SIGHUP reloading is not supported on this platform; {} will not be reloaded",
+        source.describe()
+    );
+}
+
+// Watches the file's directory rather than the file itself so editors that replace the
+// file through a rename are noticed too. `source` is what gets reloaded when it changes.
+pub fn spawn_watch(
+    state: AppState,
+    path: PathBuf,
+    source: Arc<dyn DatasetLoader>,
+) -> notify::Result<()> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let file_name = path.file_name().map(|name| name.to_os_string());
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if event.kind.is_access() => {}
+            Ok(event) => {
+                if event
+                    .paths
+                    .iter()
+                    .any(|changed| changed.file_name() == file_name.as_deref())
+                {
+                    let _ = sender.send(());
+                }
+            }
+            Err(error) => tracing::warn!("Error watching country data file: {}", error),
+        })?;
+    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+    tracing::info!("Watching {} for changes", path.display());
+
+    tokio::spawn(async move {
+        // Keep the watcher alive for as long as the task runs
+        let _watcher = watcher;
+        while receiver.recv().await.is_some() {
+            loop {
+                match tokio::time::timeout(WATCH_DEBOUNCE, receiver.recv()).await {
+                    Ok(Some(())) => continue,
+                    Ok(None) => return,
+                    Err(_) => break,
+                }
+            }
+            tracing::info!("Detected change to {}, reloading", path.display());
+            reload_and_log(&state, source.as_ref());
+        }
+    });
+
+    Ok(())
+}
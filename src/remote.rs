@@ -0,0 +1,260 @@
+//! Country data downloaded from `--data-url` and refreshed every `--refresh-interval`.
+//!
+//! The URL serves a `--data-file` JSON document. Each refresh sends the ETag of the last
+//! download as `If-None-Match`, so an unchanged document costs a `304` rather than a
+//! download. A new document is parsed and validated in full before it replaces the
+//! served data; a failed download or an invalid document leaves the current data in
+//! place until the next refresh. Outcomes are counted on `/metrics`, and `/healthz`
+//! reports when the last download succeeded.
+
+use axum::http::{
+    header::{ETAG, IF_NONE_MATCH},
+    StatusCode,
+};
+use std::{
+    fmt,
+    path::Path,
+    sync::{atomic::Ordering, Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::task::JoinHandle;
+
+use crate::{
+    audit::{AuditError, AuditRecord},
+    data_file::{self, DataFileError},
+    dataset::{Dataset, DatasetDiff},
+    retry::{retry_async, RetryPolicy},
+    source::ReadOnlyError,
+    AppState,
+};
+
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(15 * 60);
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum RemoteError {
+    // The server could not be reached or the download broke off
+    Http(String),
+    // The server answered with an error status
+    Status(StatusCode),
+    // The document is not valid country data
+    Invalid(DataFileError),
+    ReadOnly(ReadOnlyError),
+    // The refresh's audit record could not be written, so it was not made
+    Audit(AuditError),
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemoteError::Http(reason) => write!(f, "Failed to download country data: {}", reason),
+            RemoteError::Status(status) => {
+                write!(f, "The country data server answered {}", status)
+            }
+            RemoteError::Invalid(error) => error.fmt(f),
+            RemoteError::ReadOnly(error) => error.fmt(f),
+            RemoteError::Audit(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RemoteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RemoteError::Invalid(error) => Some(error),
+            RemoteError::ReadOnly(error) => Some(error),
+            RemoteError::Audit(error) => Some(error),
+            RemoteError::Http(_) | RemoteError::Status(_) => None,
+        }
+    }
+}
+
+impl From<AuditError> for RemoteError {
+    fn from(error: AuditError) -> Self {
+        RemoteError::Audit(error)
+    }
+}
+
+impl From<ReadOnlyError> for RemoteError {
+    fn from(error: ReadOnlyError) -> Self {
+        RemoteError::ReadOnly(error)
+    }
+}
+
+/// What a refresh did to the served data.
+#[derive(Debug)]
+pub enum Refreshed {
+    // A new document was swapped in
+    Updated(DatasetDiff),
+    // The server answered `304 Not Modified`
+    Unchanged,
+}
+
+/// The document at a `--data-url`, and the ETag of its last download.
+#[derive(Debug)]
+pub struct RemoteDataset {
+    client: reqwest::Client,
+    url: String,
+    etag: Mutex<Option<String>>,
+    // For timeouts and `5xx` answers, before the refresh counts as failed
+    retry: RetryPolicy,
+}
+
+impl RemoteDataset {
+    pub fn new(url: impl Into<String>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(DOWNLOAD_TIMEOUT)
+            .build()
+            .expect("the HTTP client has a valid configuration");
+        RemoteDataset {
+            client,
+            url: url.into(),
+            etag: Mutex::new(None),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    // The document if it changed since the last successful fetch, otherwise None
+    pub async fn fetch(&self) -> Result<Option<Dataset>, RemoteError> {
+        let etag = self.lock_etag().clone();
+        let downloaded = retry_async(&self.retry, "Downloading country data", || async {
+            let mut request = self.client.get(&self.url);
+            if let Some(etag) = &etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            let response = request.send().await?;
+            if response.status() == StatusCode::NOT_MODIFIED {
+                return Ok(None);
+            }
+            let response = response.error_for_status()?;
+            let etag = response
+                .headers()
+                .get(ETAG)
+                .and_then(|etag| etag.to_str().ok())
+                .map(String::from);
+            Ok(Some((etag, response.bytes().await?)))
+        })
+        .await
+        .map_err(|error: reqwest::Error| match error.status() {
+            Some(status) => RemoteError::Status(status),
+            None => RemoteError::Http(error.to_string()),
+        })?;
+        let Some((etag, body)) = downloaded else {
+            return Ok(None);
+        };
+        let dataset = data_file::parse_data_file(body.as_ref(), Path::new(&self.url))
+            .map_err(RemoteError::Invalid)?;
+        // Only remembered once the document is known to be good, so a bad one is
+        // downloaded again rather than skipped as unchanged
+        *self.lock_etag() = etag;
+        Ok(Some(dataset))
+    }
+
+    fn lock_etag(&self) -> std::sync::MutexGuard<'_, Option<String>> {
+        self.etag.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// Downloads the document again and swaps it in if it changed
+pub async fn refresh(state: &AppState, remote: &RemoteDataset) -> Result<Refreshed, RemoteError> {
+    let result = match remote.fetch().await {
+        Ok(Some(dataset)) => state
+            .update_dataset(|current| -> Result<_, RemoteError> {
+                let diff = current.diff(&dataset);
+                state.audit(AuditRecord::reload(remote.url().to_string(), &diff))?;
+                *current = dataset;
+                Ok(diff)
+            })
+            .map(|(diff, _)| Refreshed::Updated(diff)),
+        Ok(None) => Ok(Refreshed::Unchanged),
+        Err(error) => Err(error),
+    };
+
+    let metrics = &state.metrics;
+    match &result {
+        Ok(refreshed) => {
+            let counter = match refreshed {
+                Refreshed::Updated(_) => &metrics.refreshes_updated,
+                Refreshed::Unchanged => &metrics.refreshes_unchanged,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+            metrics.record_reload(true);
+            record_success(state);
+        }
+        Err(_) => {
+            metrics.refreshes_failed.fetch_add(1, Ordering::Relaxed);
+            metrics.record_reload(false);
+        }
+    }
+    result
+}
+
+// Notes the time of a successful download for `/healthz` and `/metrics`
+pub fn record_success(state: &AppState) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    state.metrics.last_refresh.store(now, Ordering::Relaxed);
+}
+
+pub async fn refresh_and_log(state: &AppState, remote: &RemoteDataset) {
+    match refresh(state, remote).await {
+        Ok(Refreshed::Updated(diff)) => tracing::info!(
+            "Refreshed country data from {}: {} added, {} removed, {} changed",
+            remote.url(),
+            diff.added.len(),
+            diff.removed.len(),
+            diff.changed.len()
+        ),
+        Ok(Refreshed::Unchanged) => {
+            tracing::debug!("Country data at {} is unchanged", remote.url())
+        }
+        Err(error) => tracing::warn!("Keeping the current country data: {}", error),
+    }
+}
+
+// Refreshes every `interval`, starting one interval from now
+pub fn spawn_refresh(state: AppState, remote: RemoteDataset, interval: Duration) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+        ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            refresh_and_log(&state, &remote).await;
+        }
+    })
+}
+
+// "90s", "15m", "1h" or "1d"; a bare number is seconds
+pub fn parse_interval(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("{:?} does not start with a number", text))?;
+    let seconds = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("unknown unit {:?}, expected s, m, h or d", unit)),
+    };
+    match number.checked_mul(seconds) {
+        Some(0) => Err(String::from("the interval must be longer than zero")),
+        Some(seconds) => Ok(Duration::from_secs(seconds)),
+        None => Err(format!("{:?} is too long", text)),
+    }
+}
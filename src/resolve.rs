@@ -0,0 +1,176 @@
+//! Resolving mixed identifiers, for `POST /resolve`.
+//!
+//! Each input is classified by its shape and looked up through the matching index: a
+//! flag emoji through the alpha-2 codes it spells, an IP address through GeoIP, `.jp`
+//! through the TLDs, `+81` through the calling codes, and anything else as a name. Two
+//! or three letters may be an ISO code or a name, such as `JP` or `uk`; in capitals
+//! the code is tried first, otherwise the name.
+
+use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, net::IpAddr};
+
+use crate::{
+    builtin, calling_code,
+    codes::Alpha2,
+    dataset::{CountryRecord, Dataset},
+    geoip::{self, IpResolver},
+    input,
+    lookup::{self, CountryInfo, Results},
+};
+
+#[derive(Debug, Deserialize, utoipa::ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ResolveRequest {
+    #[schema(example = json!(["JP", "united kingdom", ".fr", "+82", "🇩🇪", "8.8.8.8"]))]
+    pub inputs: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct ResolveResponse<'a> {
+    // One per input, in the order they were sent
+    pub results: Vec<ResolvedInput<'a>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct ResolvedInput<'a> {
+    pub input: Cow<'a, str>,
+    pub kind: InputKind,
+    // Under their canonical names; every country an ambiguous input matches, such as
+    // each country dialed with `+1`
+    #[schema(value_type = Vec<CountryInfo>)]
+    pub results: Results<'a>,
+    // Why an input of a recognized kind has no results, when that is not simply that
+    // no country matches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum InputKind {
+    // An ISO 3166-1 alpha-2 or alpha-3 code, such as `JP` or `JPN`
+    AlphaCode,
+    Name,
+    Tld,
+    CallingCode,
+    Flag,
+    Ip,
+    // Nothing matched the input's shape, or no country has it as a name or code
+    Unknown,
+}
+
+pub fn resolve_inputs<'a>(
+    dataset: &'a Dataset,
+    inputs: &'a [String],
+    language: &str,
+    resolver: Option<&dyn IpResolver>,
+) -> ResolveResponse<'a> {
+    ResolveResponse {
+        results: inputs
+            .iter()
+            .map(|input| resolve_input(dataset, input, language, resolver))
+            .collect(),
+    }
+}
+
+pub fn resolve_input<'a>(
+    dataset: &'a Dataset,
+    input: &'a str,
+    language: &str,
+    resolver: Option<&dyn IpResolver>,
+) -> ResolvedInput<'a> {
+    let entry = input::clean(input);
+    let resolved = |kind, results| ResolvedInput {
+        input: Cow::Borrowed(input),
+        kind,
+        results,
+        reason: None,
+    };
+    let failed = |kind, reason: String| ResolvedInput {
+        reason: Some(reason),
+        ..resolved(kind, Results::new())
+    };
+    let country = |record| Results::from_iter([canonical(record, language)]);
+
+    if let Some(Some(alpha2)) = lookup::flag_entry(entry) {
+        return match dataset.by_alpha2(alpha2) {
+            Some(record) => resolved(InputKind::Flag, country(record)),
+            None => failed(InputKind::Flag, not_served(alpha2)),
+        };
+    }
+
+    let address = match lookup::ip_entry(entry) {
+        Some(address) => Some(address.parse::<IpAddr>().map_err(|_| address)),
+        None => entry.parse::<IpAddr>().ok().map(Ok),
+    };
+    if let Some(address) = address {
+        let located = address
+            .map_err(|address| geoip::GeoIpError::InvalidAddress(address.to_string()))
+            .and_then(|address| geoip::locate_address(resolver, address));
+        return match located {
+            Ok(alpha2) => match dataset.by_alpha2(alpha2) {
+                Some(record) => resolved(InputKind::Ip, country(record)),
+                None => failed(InputKind::Ip, not_served(alpha2)),
+            },
+            Err(error) => failed(InputKind::Ip, error.to_string()),
+        };
+    }
+
+    if entry.starts_with('.') {
+        return resolved(
+            InputKind::Tld,
+            lookup::countries_by_tld(dataset, entry, language),
+        );
+    }
+
+    if entry.starts_with('+') {
+        return match calling_code::number_digits(entry) {
+            Ok(digits) => resolved(
+                InputKind::CallingCode,
+                lookup::countries_by_calling_code(dataset, &digits, language),
+            ),
+            Err(error) => failed(InputKind::CallingCode, error.to_string()),
+        };
+    }
+
+    let by_name = || {
+        lookup::find(dataset, entry).map(|(record, name_status)| {
+            let info = CountryInfo {
+                name_status,
+                ..canonical(record, language)
+            };
+            resolved(InputKind::Name, Results::from_iter([info]))
+        })
+    };
+    let by_code = || {
+        by_alpha_code(dataset, entry).map(|record| resolved(InputKind::AlphaCode, country(record)))
+    };
+    let is_code = matches!(entry.len(), 2 | 3) && entry.bytes().all(|b| b.is_ascii_alphabetic());
+    let found = if is_code && entry.bytes().all(|b| b.is_ascii_uppercase()) {
+        by_code().or_else(by_name)
+    } else if is_code {
+        by_name().or_else(by_code)
+    } else {
+        by_name()
+    };
+    found.unwrap_or_else(|| resolved(InputKind::Unknown, Results::new()))
+}
+
+fn canonical<'a>(record: &'a CountryRecord, language: &str) -> CountryInfo<'a> {
+    CountryInfo::new(&record.name, record, language)
+}
+
+fn not_served(alpha2: Alpha2) -> String {
+    format!("No served country has the code {}", alpha2)
+}
+
+// Alpha-3 codes are only known for the built-in countries, so they are translated to
+// the alpha-2 code the served data is indexed by
+fn by_alpha_code<'a>(dataset: &'a Dataset, code: &str) -> Option<&'a CountryRecord> {
+    let code = code.to_ascii_uppercase();
+    let alpha2 = match code.len() {
+        2 => Alpha2::new(&code).ok()?,
+        _ => builtin::by_alpha3(&code)?.alpha2,
+    };
+    dataset.by_alpha2(alpha2)
+}
@@ -0,0 +1,218 @@
+//! Optional LRU cache of serialized `/getCountry` bodies, enabled with `--response-cache`.
+//!
+//! Entries are keyed by the response format, the language and the query's names. The
+//! body echoes every name as written and in request order, so only whitespace around
+//! names is canonicalized away: `japan,korea` and ` japan , korea` share an entry, while
+//! `korea,japan` and `Japan,korea` do not. Entries are dropped as soon as the dataset
+//! version changes.
+//!
+//! With `--redis-response-cache`, bodies are also shared with the other replicas through
+//! a `Cache`. Replicas number their versions independently, so shared entries are keyed
+//! by a hash of the dataset's contents instead, and simply expire.
+//!
+//! Each body is kept with how many results and unmatched entries it holds, so a cached
+//! answer is logged like a fresh one. Shared entries put the two counts on a line of
+//! their own before the body.
+
+use axum::body::Bytes;
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use crate::{cache::Cache, dataset::Dataset, export, input, lookup::ResultOptions};
+
+pub const JSON_FORMAT: &str = "json";
+// How long shared bodies are kept; changed data is keyed apart, so this only bounds
+// the memory bodies of old datasets take up
+pub const SHARED_TTL: Duration = Duration::from_secs(3600);
+
+// A serialized body, and the results and unmatched entries it answered with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedResponse {
+    pub body: Bytes,
+    pub results: usize,
+    pub unmatched: usize,
+}
+
+pub struct ResponseCache {
+    // None when caching in this process is disabled
+    state: Option<Mutex<CacheState>>,
+    // None unless bodies are shared with other replicas
+    shared: Option<SharedResponses>,
+}
+
+struct SharedResponses {
+    cache: Arc<dyn Cache>,
+    // The dataset version last fingerprinted, and its fingerprint
+    fingerprint: Mutex<Option<(u64, String)>>,
+}
+
+struct CacheState {
+    // Dataset version the entries were rendered from
+    version: u64,
+    entries: LruCache<String, CachedResponse>,
+}
+
+impl ResponseCache {
+    // A capacity of zero disables the cache
+    pub fn new(capacity: usize) -> Self {
+        ResponseCache {
+            state: NonZeroUsize::new(capacity).map(|capacity| {
+                Mutex::new(CacheState {
+                    version: 0,
+                    entries: LruCache::new(capacity),
+                })
+            }),
+            shared: None,
+        }
+    }
+
+    // Also shares bodies with every replica that uses `cache`
+    pub fn with_shared(mut self, cache: Arc<dyn Cache>) -> Self {
+        self.shared = Some(SharedResponses {
+            cache,
+            fingerprint: Mutex::new(None),
+        });
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state.is_some() || self.shared.is_some()
+    }
+
+    // The response for `key` rendered from `dataset`, which is at `version`
+    pub async fn get(&self, version: u64, dataset: &Dataset, key: &str) -> Option<CachedResponse> {
+        if let Some(mut state) = self.lock() {
+            state.invalidate_unless(version);
+            if let Some(response) = state.entries.get(key) {
+                return Some(response.clone());
+            }
+        }
+        let shared = self.shared.as_ref()?;
+        let entry = shared.cache.get(&shared.key(version, dataset, key)).await?;
+        let response = CachedResponse::decode(&entry)?;
+        self.insert_local(version, key.to_string(), response.clone());
+        Some(response)
+    }
+
+    pub async fn insert(
+        &self,
+        version: u64,
+        dataset: &Dataset,
+        key: String,
+        response: CachedResponse,
+    ) {
+        if let Some(shared) = &self.shared {
+            let shared_key = shared.key(version, dataset, &key);
+            shared
+                .cache
+                .set(&shared_key, response.encode(), SHARED_TTL)
+                .await;
+        }
+        self.insert_local(version, key, response);
+    }
+
+    fn insert_local(&self, version: u64, key: String, response: CachedResponse) {
+        if let Some(mut state) = self.lock() {
+            state.invalidate_unless(version);
+            state.entries.put(key, response);
+        }
+    }
+
+    fn lock(&self) -> Option<std::sync::MutexGuard<'_, CacheState>> {
+        self.state
+            .as_ref()
+            .map(|state| state.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+impl CachedResponse {
+    // `<results> <unmatched>\n<body>`, as shared entries are stored
+    fn encode(&self) -> Bytes {
+        let mut entry = format!("{} {}\n", self.results, self.unmatched).into_bytes();
+        entry.extend_from_slice(&self.body);
+        Bytes::from(entry)
+    }
+
+    // None for an entry not in `encode`'s form, which is then treated as a miss
+    fn decode(entry: &Bytes) -> Option<Self> {
+        let newline = entry.iter().position(|&byte| byte == b'\n')?;
+        let counts = std::str::from_utf8(&entry[..newline]).ok()?;
+        let (results, unmatched) = counts.split_once(' ')?;
+        Some(CachedResponse {
+            body: entry.slice(newline + 1..),
+            results: results.parse().ok()?,
+            unmatched: unmatched.parse().ok()?,
+        })
+    }
+}
+
+impl SharedResponses {
+    // `response:<dataset hash>:<key hash>`; keys hold raw names, so they are hashed too
+    fn key(&self, version: u64, dataset: &Dataset, key: &str) -> String {
+        format!(
+            "response:{}:{:x}",
+            self.fingerprint(version, dataset),
+            Sha256::digest(key)
+        )
+    }
+
+    fn fingerprint(&self, version: u64, dataset: &Dataset) -> String {
+        let mut fingerprint = self
+            .fingerprint
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match &*fingerprint {
+            Some((fingerprinted, hash)) if *fingerprinted == version => hash.clone(),
+            _ => {
+                let hash = export::checksum(dataset);
+                *fingerprint = Some((version, hash.clone()));
+                hash
+            }
+        }
+    }
+}
+
+impl CacheState {
+    fn invalidate_unless(&mut self, version: u64) {
+        if self.version != version {
+            self.entries.clear();
+            self.version = version;
+        }
+    }
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        ResponseCache::new(0)
+    }
+}
+
+impl fmt::Debug for ResponseCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let capacity = self.lock().map(|state| state.entries.cap().get());
+        f.debug_struct("ResponseCache")
+            .field("capacity", &capacity)
+            .field("shared", &self.shared.is_some())
+            .finish()
+    }
+}
+
+// `based` with the whitespace around each name removed; "all" in any case is one key
+pub fn cache_key(format: &str, language: &str, options: &ResultOptions, based: &str) -> String {
+    let names = if based.trim().eq_ignore_ascii_case("all") {
+        String::from("all")
+    } else {
+        based
+            .split(',')
+            .map(input::clean)
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    format!("{}\n{}\n{}\n{}", format, language, options, names)
+}
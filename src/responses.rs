@@ -0,0 +1,201 @@
+//! JSON bodies the API answers with besides country results, shared by the server and
+//! the client so the two agree on every field name.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+};
+
+use crate::lookup::CountryInfo;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct HealthResponse {
+    pub status: String,
+    #[serde(rename = "datasetVersion")]
+    pub dataset_version: u64,
+    #[serde(rename = "datasetModified")]
+    pub dataset_modified: String,
+    // When the data was last downloaded from `--data-url`, as an HTTP date; absent
+    // without it, or before the first successful download
+    #[serde(
+        rename = "lastRefresh",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub last_refresh: Option<String>,
+    // Only with `?verbose=true`
+    #[serde(flatten, default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<HealthDetails>,
+}
+
+// Ordered from best to worst, so the overall status is the maximum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    Degraded,
+    Down,
+}
+
+impl HealthStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HealthStatus::Ok => "ok",
+            HealthStatus::Degraded => "degraded",
+            HealthStatus::Down => "down",
+        }
+    }
+}
+
+/// What `/healthz?verbose=true` adds to the plain health check.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct HealthDetails {
+    #[serde(rename = "uptimeSeconds")]
+    pub uptime_seconds: u64,
+    #[serde(rename = "datasetEntries")]
+    pub dataset_entries: usize,
+    // Hex SHA-256 of the `/admin/export` JSON, equal across servers with the same data
+    #[serde(rename = "datasetChecksum")]
+    pub dataset_checksum: String,
+    #[serde(
+        rename = "lastReload",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub last_reload: Option<ReloadHealth>,
+    // Only when serving HTTPS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsHealth>,
+    // Only with a rate provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rates: Option<RatesHealth>,
+    // "dataset", plus "reload", "tls", "geoip", "rates" and "redis" when they apply
+    pub components: BTreeMap<String, ComponentHealth>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct ReloadHealth {
+    // HTTP date
+    pub time: String,
+    // "success" or "failure"
+    pub outcome: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct TlsHealth {
+    // When the certificate presented to new connections expires, as an HTTP date
+    #[serde(rename = "notAfter")]
+    pub not_after: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct RatesHealth {
+    // The circuit breaker in front of the rate provider: "closed", "open" or "halfOpen"
+    pub breaker: String,
+    // Calls to the provider in a row that failed
+    #[serde(rename = "consecutiveFailures")]
+    pub consecutive_failures: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct ComponentHealth {
+    pub status: HealthStatus,
+    // Whether the server is down without it; optional components only degrade it
+    pub required: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct VersionResponse {
+    pub name: String,
+    pub version: String,
+    #[serde(rename = "listenAddresses")]
+    #[cfg_attr(feature = "server", schema(value_type = Vec<String>))]
+    pub listen_addresses: Vec<SocketAddr>,
+    #[serde(rename = "datasetVersion")]
+    pub dataset_version: u64,
+    #[serde(rename = "datasetModified")]
+    pub dataset_modified: String,
+}
+
+/// A fingerprint of the served data, equal on every instance serving the same countries.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct ChecksumResponse {
+    // Always "sha256"
+    #[cfg_attr(feature = "server", schema(example = "sha256"))]
+    pub algorithm: String,
+    // Hex digest of the JSON `/admin/export`, which sorts the countries by name
+    pub checksum: String,
+    pub countries: usize,
+    #[serde(rename = "datasetVersion")]
+    pub dataset_version: u64,
+}
+
+/// Aggregates over the served data, as `/stats` computes them on each request.
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct StatsResponse {
+    pub countries: usize,
+    // Aliases across all countries, canonical names not included
+    pub aliases: usize,
+    // Countries per continent, such as `northAmerica`; countries without one are counted
+    // under `unknown`, so the counts always add up to `countries`
+    pub continents: BTreeMap<String, usize>,
+    // Countries per ISO 4217 code
+    pub currencies: BTreeMap<String, usize>,
+}
+
+// Shared JSON error body: {"error": {"code", "message", "details", "requestId"}}
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct ErrorEnvelope {
+    pub error: ErrorBody,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "server", schema(value_type = Option<Object>))]
+    pub details: Option<serde_json::Value>,
+    #[serde(rename = "requestId", skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+// `/whoami`: the caller's address and the served country it is in, if any
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct WhoamiResponse<'a> {
+    #[cfg_attr(feature = "server", schema(value_type = String, example = "8.8.8.8"))]
+    pub ip: IpAddr,
+    // Null when the address is in a country the served dataset lacks
+    #[serde(borrow)]
+    pub country: Option<CountryInfo<'a>>,
+}
+
+// Data of each `dataset` event on `/events`: the new revision and the canonical names
+// that changed to reach it, each list sorted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "server", derive(utoipa::ToSchema))]
+pub struct DatasetEvent {
+    #[serde(rename = "datasetVersion")]
+    pub dataset_version: u64,
+    #[serde(rename = "datasetModified")]
+    pub dataset_modified: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
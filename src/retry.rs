@@ -0,0 +1,122 @@
+//! Retries of upstream fetches that failed in a way that may not happen again: exchange
+//! rates and `--data-url` downloads, which are both safe to repeat.
+//!
+//! A fetch is tried again after timeouts, connection failures and `5xx` answers, up to
+//! the policy's `max_retries` times, but never after a `4xx`, which a second attempt
+//! would get too. Waits double from `initial_backoff` up to 5 seconds, each shortened
+//! by a random amount of up to half, so replicas that failed together do not retry
+//! together. Webhook deliveries are not idempotent and keep their own policy.
+
+use rand::Rng;
+use std::{fmt, future::Future, time::Duration};
+
+/// Retries after the first attempt when `--upstream-retries` is not given.
+pub const DEFAULT_MAX_RETRIES: u32 = 2;
+
+/// Wait before the first retry when `--upstream-retry-backoff-ms` is not given.
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(200);
+
+// Longest wait between attempts, however many there are
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    // Attempts after the first one; 0 never retries
+    pub max_retries: u32,
+    // Wait before the first retry; it doubles for each retry after that
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: DEFAULT_MAX_RETRIES,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Tries once only
+    pub fn never() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            initial_backoff: Duration::ZERO,
+        }
+    }
+
+    // The longest wait before retry number `retry`, counting from 1, before jitter
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let doublings = retry.saturating_sub(1).min(31);
+        self.initial_backoff
+            .saturating_mul(1 << doublings)
+            .min(MAX_BACKOFF)
+    }
+}
+
+// Whether a failed attempt may succeed if made again
+pub trait Transient {
+    fn is_transient(&self) -> bool;
+}
+
+impl Transient for reqwest::Error {
+    fn is_transient(&self) -> bool {
+        match self.status() {
+            Some(status) => status.is_server_error(),
+            // Timeouts, refused or reset connections and bodies cut off, but not bodies
+            // that arrived whole and do not parse
+            None => !self.is_decode() && !self.is_builder() && !self.is_redirect(),
+        }
+    }
+}
+
+// `operation`'s result, making it again after transient failures as `policy` allows.
+// `what` names the fetch in the warnings logged before each retry.
+pub async fn retry_async<T, E, F>(
+    policy: &RetryPolicy,
+    what: &str,
+    operation: impl FnMut() -> F,
+) -> Result<T, E>
+where
+    E: Transient + fmt::Display,
+    F: Future<Output = Result<T, E>>,
+{
+    retry_async_with(policy, what, tokio::time::sleep, operation).await
+}
+
+// `retry_async`, waiting between attempts with `sleep`
+pub async fn retry_async_with<T, E, F, S>(
+    policy: &RetryPolicy,
+    what: &str,
+    mut sleep: impl FnMut(Duration) -> S,
+    mut operation: impl FnMut() -> F,
+) -> Result<T, E>
+where
+    E: Transient + fmt::Display,
+    F: Future<Output = Result<T, E>>,
+    S: Future<Output = ()>,
+{
+    let mut retry = 0;
+    loop {
+        let error = match operation().await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+        if retry >= policy.max_retries || !error.is_transient() {
+            return Err(error);
+        }
+        retry += 1;
+        let backoff = policy
+            .backoff(retry)
+            .mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+        tracing::warn!(
+            "{} failed (attempt {} of {}), retrying in {:?}: {}",
+            what,
+            retry,
+            policy.max_retries + 1,
+            backoff,
+            error
+        );
+        sleep(backoff).await;
+    }
+}
@@ -0,0 +1,75 @@
+//! Revision of the served dataset, so clients can tell when cached country data is stale.
+//!
+//! The version is the served `CountryDataSource`'s: 1 to start with, one more on every
+//! reload and admin change. Responses read it together with the data they are built
+//! from, so they always carry the revision of that data. When the data was modified is
+//! not the source's to say: it is when this server first saw the version.
+
+use std::{
+    sync::{Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub const DATASET_VERSION_HEADER: &str = "x-dataset-version";
+pub const DATASET_MODIFIED_HEADER: &str = "x-dataset-modified";
+
+// Response headers identifying the revision a data response was built from
+pub type RevisionHeaders = [(&'static str, String); 2];
+
+#[derive(Debug)]
+pub struct Revision {
+    latest: Mutex<RevisionInfo>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RevisionInfo {
+    pub version: u64,
+    pub modified: SystemTime,
+}
+
+// Whole seconds; HTTP dates carry no finer precision
+fn now() -> SystemTime {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    UNIX_EPOCH + Duration::from_secs(elapsed)
+}
+
+impl Revision {
+    // Starts at `version`, modified now
+    pub fn new(version: u64) -> Self {
+        Revision {
+            latest: Mutex::new(RevisionInfo {
+                version,
+                modified: now(),
+            }),
+        }
+    }
+
+    // The revision of data at `version`; a version not seen before was modified now
+    pub fn observe(&self, version: u64) -> RevisionInfo {
+        let mut latest = self.latest.lock().unwrap_or_else(PoisonError::into_inner);
+        if version > latest.version {
+            *latest = RevisionInfo {
+                version,
+                modified: now(),
+            };
+        }
+        RevisionInfo { version, ..*latest }
+    }
+}
+
+impl RevisionInfo {
+    // Formatted like `Last-Modified`, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+    pub fn modified_http_date(&self) -> String {
+        httpdate::fmt_http_date(self.modified)
+    }
+
+    pub fn headers(&self) -> RevisionHeaders {
+        [
+            (DATASET_VERSION_HEADER, self.version.to_string()),
+            (DATASET_MODIFIED_HEADER, self.modified_http_date()),
+        ]
+    }
+}
@@ -0,0 +1,103 @@
+//! Response headers that tell browsers to treat what the server sends cautiously.
+//!
+//! Every response gets `X-Content-Type-Options` and `Referrer-Policy`. HTML pages, such
+//! as `/docs` and `/redoc`, also get `Content-Security-Policy` and `Permissions-Policy`,
+//! which only mean something for documents. The default policy lets the pages load the
+//! scripts, styles and spec the server itself serves and nothing from elsewhere. Each
+//! header can be given another value or turned off, and a header a handler set itself is
+//! left alone, as the GraphQL playground does with its own policy.
+
+use axum::http::{
+    header::{CONTENT_SECURITY_POLICY, CONTENT_TYPE, REFERRER_POLICY, X_CONTENT_TYPE_OPTIONS},
+    HeaderMap, HeaderName, HeaderValue,
+};
+
+use crate::docs;
+
+pub const DEFAULT_CONTENT_TYPE_OPTIONS: &str = "nosniff";
+pub const DEFAULT_REFERRER_POLICY: &str = "no-referrer";
+pub const DEFAULT_PERMISSIONS_POLICY: &str =
+    "camera=(), geolocation=(), microphone=(), payment=(), usb=()";
+
+/// Turns a header off when given as its value.
+pub const OFF: &str = "off";
+
+const PERMISSIONS_POLICY: HeaderName = HeaderName::from_static("permissions-policy");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityHeaders {
+    // Each None is left out
+    pub content_type_options: Option<HeaderValue>,
+    pub referrer_policy: Option<HeaderValue>,
+    // Sent with HTML only
+    pub content_security_policy: Option<HeaderValue>,
+    // Sent with HTML only
+    pub permissions_policy: Option<HeaderValue>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        SecurityHeaders {
+            content_type_options: Some(HeaderValue::from_static(DEFAULT_CONTENT_TYPE_OPTIONS)),
+            referrer_policy: Some(HeaderValue::from_static(DEFAULT_REFERRER_POLICY)),
+            content_security_policy: Some(default_content_security_policy()),
+            permissions_policy: Some(HeaderValue::from_static(DEFAULT_PERMISSIONS_POLICY)),
+        }
+    }
+}
+
+impl SecurityHeaders {
+    // None of the headers
+    pub fn off() -> Self {
+        SecurityHeaders {
+            content_type_options: None,
+            referrer_policy: None,
+            content_security_policy: None,
+            permissions_policy: None,
+        }
+    }
+
+    // Adds the headers that apply to a response with `headers`, but none it already has
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        let html = headers
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .is_some_and(|content_type| content_type.starts_with("text/html"));
+        let mut set = |name: HeaderName, value: &Option<HeaderValue>| {
+            if let Some(value) = value {
+                headers.entry(name).or_insert_with(|| value.clone());
+            }
+        };
+        set(X_CONTENT_TYPE_OPTIONS, &self.content_type_options);
+        set(REFERRER_POLICY, &self.referrer_policy);
+        if html {
+            set(CONTENT_SECURITY_POLICY, &self.content_security_policy);
+            set(PERMISSIONS_POLICY, &self.permissions_policy);
+        }
+    }
+}
+
+/// A header's configured value: `off` for none, anything else as it is written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderSetting(pub Option<HeaderValue>);
+
+pub fn parse_setting(value: &str) -> Result<HeaderSetting, String> {
+    if value.eq_ignore_ascii_case(OFF) {
+        return Ok(HeaderSetting(None));
+    }
+    HeaderValue::from_str(value)
+        .map(|value| HeaderSetting(Some(value)))
+        .map_err(|_| format!("{:?} is not a valid header value", value))
+}
+
+// Same-origin everything, plus the one inline script the Swagger UI page runs and the
+// inline styles and blob workers Swagger UI and ReDoc use
+fn default_content_security_policy() -> HeaderValue {
+    let policy = format!(
+        "default-src 'none'; script-src 'self' {}; style-src 'self' 'unsafe-inline'; \
+         img-src 'self' data:; font-src 'self' data:; connect-src 'self'; \
+         worker-src 'self' blob:; base-uri 'none'; form-action 'none'; frame-ancestors 'none'",
+        docs::swagger_ui_script_source()
+    );
+    HeaderValue::from_str(&policy).expect("the policy is a valid header value")
+}
@@ -0,0 +1,313 @@
+//! `serve --self-test`: a smoke test of the binary as configured, for deployment pipelines.
+//!
+//! [`run`] serves the app on a listener of its own, with the same [`ServeOptions`] as the
+//! real server, so TLS and every other option are in play. It then sends one request per
+//! [`SelfTestCase`] and reports which were answered as expected. Cases are rows of
+//! [`cases`]; an endpoint gets a self-test by adding one. Lookups ask for a country the
+//! served data has, so the checks pass whatever data file is configured.
+
+use axum::{http::Method, Router};
+use serde_json::Value;
+use std::{fmt, io, time::Duration};
+use tokio::{net::TcpListener, sync::oneshot};
+
+use crate::{run_server_until, shutdown::DrainOutcome, AppState, ServeOptions};
+
+// Stands for the served country in paths, query values and bodies; in bodies it is
+// replaced by the name as a JSON string
+const COUNTRY: &str = "{country}";
+
+// A name no dataset is expected to have
+const UNKNOWN_NAME: &str = "no such country";
+
+// Longest any one request may take before its case fails
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One request and what its response must look like.
+#[derive(Debug, Clone)]
+pub struct SelfTestCase {
+    pub name: &'static str,
+    pub method: Method,
+    pub path: &'static str,
+    // Query parameters, with `{country}` in values standing for the served country
+    pub query: &'static [(&'static str, &'static str)],
+    // A JSON body, with `{country}` standing for the served country as a JSON string
+    pub body: Option<&'static str>,
+    pub status: u16,
+    // Given the response body and the served country; says what is wrong, if anything
+    pub check: fn(&str, &str) -> Result<(), String>,
+}
+
+/// The outcome of every case, in order.
+#[derive(Debug)]
+pub struct SelfTestReport {
+    pub results: Vec<CaseResult>,
+}
+
+#[derive(Debug)]
+pub struct CaseResult {
+    pub name: &'static str,
+    pub request: String,
+    // Why the case failed; None when it passed
+    pub failure: Option<String>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|result| result.failure.is_none())
+    }
+
+    pub fn failures(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|result| result.failure.is_some())
+            .count()
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .results
+            .iter()
+            .map(|result| result.name.len())
+            .max()
+            .unwrap_or(0);
+        for result in &self.results {
+            match &result.failure {
+                None => writeln!(
+                    f,
+                    "PASS  {:width$}  {}",
+                    result.name,
+                    result.request,
+                    width = width
+                )?,
+                Some(failure) => writeln!(
+                    f,
+                    "FAIL  {:width$}  {}: {}",
+                    result.name,
+                    result.request,
+                    failure,
+                    width = width
+                )?,
+            }
+        }
+        write!(
+            f,
+            "{} checks, {} passed, {} failed",
+            self.results.len(),
+            self.results.len() - self.failures(),
+            self.failures()
+        )
+    }
+}
+
+/// The checks `serve --self-test` runs.
+pub fn cases() -> Vec<SelfTestCase> {
+    vec![
+        SelfTestCase {
+            name: "single lookup",
+            method: Method::GET,
+            path: "/getCountry",
+            query: &[("based", COUNTRY)],
+            body: None,
+            status: 200,
+            check: |body, country| {
+                let body = json(body)?;
+                let found = &body["results"][0];
+                expect(
+                    found["country"] == country,
+                    "the country is not in `results`",
+                )?;
+                expect(
+                    found["currencyCode"].is_string(),
+                    "the result has no `currencyCode`",
+                )
+            },
+        },
+        SelfTestCase {
+            name: "batch lookup",
+            method: Method::POST,
+            path: "/resolve",
+            query: &[],
+            body: Some(r#"{"inputs": [{country}, "no such country"]}"#),
+            status: 200,
+            check: |body, _| {
+                let body = json(body)?;
+                let resolved = |index: usize| {
+                    body["results"][index]["results"]
+                        .as_array()
+                        .map(|results| !results.is_empty())
+                };
+                expect(resolved(0) == Some(true), "the country was not resolved")?;
+                expect(resolved(1) == Some(false), "the unknown name was resolved")
+            },
+        },
+        SelfTestCase {
+            name: "unknown name",
+            method: Method::GET,
+            path: "/getCountry",
+            query: &[("based", UNKNOWN_NAME), ("strict", "true")],
+            body: None,
+            status: 422,
+            check: |body, _| {
+                let body = json(body)?;
+                expect(
+                    body["error"]["code"] == "UNMATCHED_ENTRIES",
+                    "the error code is not UNMATCHED_ENTRIES",
+                )
+            },
+        },
+        SelfTestCase {
+            name: "country list",
+            method: Method::GET,
+            path: "/countries",
+            query: &[],
+            body: None,
+            status: 200,
+            check: |body, _| {
+                let body = json(body)?;
+                let listed = body["results"].as_array().is_some_and(|results| {
+                    !results.is_empty() && results.iter().all(|found| found["country"].is_string())
+                });
+                expect(listed, "no countries are listed")
+            },
+        },
+        SelfTestCase {
+            name: "health",
+            method: Method::GET,
+            path: "/healthz",
+            query: &[("verbose", "true")],
+            body: None,
+            status: 200,
+            check: |body, _| {
+                let body = json(body)?;
+                expect(body["status"] == "ok", "the status is not ok")
+            },
+        },
+        // After the lookups above, which it expects to have been timed
+        SelfTestCase {
+            name: "metrics",
+            method: Method::GET,
+            path: "/metrics",
+            query: &[],
+            body: None,
+            status: 200,
+            check: |body, _| {
+                expect(
+                    body.contains(
+                        "rustincountry_request_duration_seconds_count{route=\"/getCountry\"}",
+                    ),
+                    "the lookups are not in the latency histogram",
+                )
+            },
+        },
+    ]
+}
+
+/// A country `state` serves, to look up; None when it serves none.
+pub fn served_country(state: &AppState) -> Option<String> {
+    let (dataset, _) = state.current();
+    let country = dataset.records().next()?.name.clone();
+    Some(country)
+}
+
+/// Serves `app` on `listener` with `options`, runs `cases` against it asking for
+/// `country`, then shuts the server down.
+pub async fn run(
+    listener: TcpListener,
+    app: Router,
+    options: ServeOptions,
+    cases: &[SelfTestCase],
+    country: &str,
+) -> io::Result<SelfTestReport> {
+    let address = listener.local_addr()?;
+    let scheme = if options.tls.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    let base = format!("{}://{}", scheme, address);
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = tokio::spawn(run_server_until(
+        vec![listener],
+        app,
+        async {
+            let _ = stopped.await;
+        },
+        options,
+    ));
+
+    // The certificate is the server's own, for a name other than the loopback address
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .danger_accept_invalid_certs(true)
+        .build()
+        .map_err(io::Error::other)?;
+    let mut results = Vec::with_capacity(cases.len());
+    for case in cases {
+        results.push(run_case(&client, &base, case, country).await);
+    }
+
+    let _ = stop.send(());
+    match server.await.map_err(io::Error::other)?? {
+        DrainOutcome::Drained => Ok(SelfTestReport { results }),
+        DrainOutcome::TimedOut { in_flight } => Err(io::Error::other(format!(
+            "{} self-test request(s) were still in flight at shutdown",
+            in_flight
+        ))),
+    }
+}
+
+async fn run_case(
+    client: &reqwest::Client,
+    base: &str,
+    case: &SelfTestCase,
+    country: &str,
+) -> CaseResult {
+    let query: Vec<(&str, &str)> = case
+        .query
+        .iter()
+        .map(|&(name, value)| (name, if value == COUNTRY { country } else { value }))
+        .collect();
+    let mut request = client
+        .request(case.method.clone(), format!("{}{}", base, case.path))
+        .query(&query);
+    if let Some(body) = case.body {
+        let country = serde_json::to_string(country).expect("strings serialize to JSON");
+        request = request
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.replace(COUNTRY, &country));
+    }
+    let described = match serde_urlencoded::to_string(&query) {
+        Ok(query) if !query.is_empty() => format!("{} {}?{}", case.method, case.path, query),
+        _ => format!("{} {}", case.method, case.path),
+    };
+
+    let outcome = async {
+        let response = request.send().await.map_err(|error| error.to_string())?;
+        let status = response.status().as_u16();
+        let body = response.text().await.map_err(|error| error.to_string())?;
+        if status != case.status {
+            return Err(format!("expected status {}, got {}", case.status, status));
+        }
+        (case.check)(&body, country)
+    };
+    CaseResult {
+        name: case.name,
+        request: described,
+        failure: outcome.await.err(),
+    }
+}
+
+fn json(body: &str) -> Result<Value, String> {
+    serde_json::from_str(body).map_err(|error| format!("the body is not JSON: {}", error))
+}
+
+fn expect(condition: bool, failure: &str) -> Result<(), String> {
+    if condition {
+        Ok(())
+    } else {
+        Err(failure.to_string())
+    }
+}
@@ -0,0 +1,3462 @@
+//! The HTTP API: request handlers, the router and the listeners it is served on.
+
+use crate::{
+    access_log::{self, AccessLog, AccessLogConfig},
+    admin_auth::{AdminCredentials, AdminPrincipal, BasicAuth, VerifierBusy},
+    api_keys::{self, Admission, Allowance, ApiKeyLimiter, ApiKeys, KeyName},
+    audit::{AuditAction, AuditError, AuditLog, AuditRecord},
+    bounding_box, builtin,
+    cache::Cache,
+    calling_code,
+    catalog::{self, CatalogBody, CatalogCache, CatalogFormat, CountriesKey, ListCaching},
+    client_ip::{ClientIp, TrustedProxies},
+    codes::{Alpha2, CurrencyCode},
+    dataset::{Dataset, RecordError},
+    docs,
+    events::DatasetEvents,
+    export,
+    flag_image::{self, ImageFormat},
+    geoip::{self, GeoIpError, IpResolver},
+    health::{self, DependencyCheck},
+    https::{Hsts, HttpsRedirect, OverTls},
+    include::{IncludeGroup, Includes},
+    input::{self, InputError},
+    log_level::{LogLevel, LogLevelChange, LogLevelRequest, LogLevelResponse},
+    lookup::{
+        self, CountryInfo, FlagFormat, GroupBy, GroupedResponse, OnlyField, Order, ResolvedQuery,
+        ResultOptions, ValuesResponse,
+    },
+    metrics::Metrics,
+    miss_log::{self, MissLog},
+    normalize::normalize,
+    openapi,
+    rate_limit::RateLimiter,
+    rates::{self, RateCache},
+    resolve::{self, ResolveRequest, ResolveResponse},
+    response_cache::{self, CachedResponse, ResponseCache},
+    responses::{
+        ChecksumResponse, DatasetEvent, ErrorBody, ErrorEnvelope, HealthResponse, HealthStatus,
+        StatsResponse, VersionResponse, WhoamiResponse,
+    },
+    revision::{self, Revision, RevisionHeaders, RevisionInfo},
+    security_headers::SecurityHeaders,
+    shutdown::{self, count_in_flight, DrainOutcome, InFlight, Stopping},
+    snapshot::SnapshotStore,
+    source::{CountryDataSource, DatasetLoader, LoadError, MutableSource, ReadOnlyError},
+    timezone, tld,
+    tls::TlsCertificates,
+    validation, CodesResponse, CountryData, CountryRecord, CountryResponse, CurrencyResponse,
+    LanguageResponse, ValidateResponse,
+};
+use axum::{
+    body::Bytes,
+    error_handling::HandleErrorLayer,
+    extract::{
+        rejection::JsonRejection, ConnectInfo, Extension, FromRequestParts, MatchedPath,
+        OriginalUri, Path, Request, State,
+    },
+    http::{
+        header::{
+            ACCEPT_LANGUAGE, CACHE_CONTROL, CONNECTION, CONTENT_TYPE, HOST, LOCATION, RETRY_AFTER,
+            STRICT_TRANSPORT_SECURITY, VARY, WWW_AUTHENTICATE,
+        },
+        request::Parts,
+        uri::Authority,
+        HeaderMap, HeaderName, HeaderValue, Method, StatusCode,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
+    routing::{get, post, put},
+    Router,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::graceful::GracefulShutdown,
+    service::TowerToHyperService,
+};
+use listenfd::ListenFd;
+use serde::{de::DeserializeOwned, Deserialize};
+use socket2::{Domain, Socket, Type};
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::RefCell,
+    collections::HashMap,
+    fs::File,
+    future::Future,
+    io::{self, BufRead, BufReader},
+    net::{IpAddr, SocketAddr},
+    num::{NonZeroU32, NonZeroUsize},
+    sync::{atomic::Ordering, Arc, Mutex, PoisonError},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+use tokio::{net::TcpListener, sync::watch, task::JoinSet};
+use tokio_rustls::TlsAcceptor;
+use tower::{
+    limit::GlobalConcurrencyLimitLayer, load_shed::error::Overloaded, timeout::error::Elapsed,
+    BoxError, Service, ServiceBuilder,
+};
+use tower_http::{
+    catch_panic::CatchPanicLayer,
+    cors::{AllowOrigin, CorsLayer},
+};
+
+#[derive(Debug, Deserialize)]
+struct CountryQuery {
+    // Missing is answered like empty, with `EMPTY_QUERY`
+    based: Option<String>,
+    // Language of `localizedName`; lookups by `based` work the same in every language
+    lang: Option<String>,
+    // Add each currency's exchange rate to US dollars, as `include=rates` does
+    rates: Option<bool>,
+    // Comma-separated field groups to add to each result
+    include: Option<String>,
+    // `input` or `alpha`
+    order: Option<String>,
+    // `currency` or `continent`; flat results without it
+    #[serde(rename = "groupBy")]
+    group_by: Option<String>,
+    // A single-valued field to list on its own, in place of whole results
+    only: Option<String>,
+    // Leave repeats out of `only`'s values
+    distinct: Option<bool>,
+    // Refuse the lookup with a 422 when an entry goes unanswered
+    strict: Option<bool>,
+    // `false` answers no results when an entry goes unanswered, rather than the rest
+    #[serde(rename = "allowPartial")]
+    allow_partial: Option<bool>,
+    // Most results to answer with, up to the server's `--max-results`
+    #[serde(rename = "maxResults")]
+    max_results: Option<String>,
+}
+
+// Taken by every endpoint that answers with countries, alongside its own parameters
+#[derive(Debug, Deserialize)]
+struct FlagFormatQuery {
+    #[serde(rename = "flagFormat")]
+    flag_format: Option<String>,
+}
+
+// Parameters that hold comma-separated lists, so repeating one adds to the list:
+// `?based=japan&based=korea` looks up both
+const LIST_PARAMETERS: [&str; 2] = ["based", "include"];
+
+// The query string, deserialized into `T` like `Query` does, except that a parameter
+// given twice is only accepted with the same value both times, or when it is one of
+// `LIST_PARAMETERS`. Anything else would leave it to the order of the parameters which
+// value wins.
+#[derive(Debug)]
+struct ApiQuery<T>(T);
+
+#[axum::async_trait]
+impl<T: DeserializeOwned, S: Send + Sync> FromRequestParts<S> for ApiQuery<T> {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, ApiError> {
+        let invalid = |error: serde_urlencoded::de::Error| ApiError::InvalidParameter {
+            name: "query",
+            reason: format!("Failed to deserialize query string: {}", error),
+        };
+        let given: Vec<(String, String)> =
+            serde_urlencoded::from_str(parts.uri.query().unwrap_or_default()).map_err(invalid)?;
+        let mut merged: Vec<(String, String)> = Vec::with_capacity(given.len());
+        for (name, value) in given {
+            match merged.iter_mut().find(|(seen, _)| *seen == name) {
+                None => merged.push((name, value)),
+                Some((_, list)) if LIST_PARAMETERS.contains(&name.as_str()) => {
+                    list.push(',');
+                    list.push_str(&value);
+                }
+                Some((_, first)) if *first == value => {}
+                Some(_) => return Err(ApiError::ConflictingParameter(name)),
+            }
+        }
+        let query = serde_urlencoded::to_string(&merged).expect("pairs of strings encode");
+        serde_urlencoded::from_str(&query)
+            .map(ApiQuery)
+            .map_err(invalid)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ValidateQuery {
+    based: Option<String>,
+    lang: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FlagQuery {
+    // Width of a PNG in pixels; SVGs scale by themselves and ignore it
+    size: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HealthQuery {
+    verbose: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WhoamiQuery {
+    lang: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CallingCodeQuery {
+    number: Option<String>,
+    lang: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CurrencyQuery {
+    code: Option<String>,
+    symbol: Option<String>,
+    lang: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TldQuery {
+    tld: Option<String>,
+    lang: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LanguageQuery {
+    name: Option<String>,
+    // List every language instead, with how many countries list it
+    list: Option<bool>,
+    lang: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TimezoneQuery {
+    zone: Option<String>,
+    // Such as `+09:00`; the sign may be left out, as a raw `+` reads as a space
+    offset: Option<String>,
+    lang: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResolveQuery {
+    lang: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CoordinatesQuery {
+    lat: Option<String>,
+    lng: Option<String>,
+    lang: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CatalogQuery {
+    lang: Option<String>,
+    format: Option<String>,
+    #[serde(rename = "maxResults")]
+    max_results: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CodesQuery {
+    format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportQuery {
+    format: Option<String>,
+}
+
+// Identifier of the request being served, taken from `x-request-id` or generated
+#[derive(Debug, Clone)]
+struct RequestId(String);
+
+tokio::task_local! {
+    static CURRENT_REQUEST_ID: RequestId;
+}
+
+thread_local! {
+    static PANIC_BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+// State shared by all handlers, which read the served data from `source`
+#[derive(Debug, Clone)]
+pub struct AppState {
+    pub(crate) source: Arc<dyn CountryDataSource>,
+    pub(crate) revision: Arc<Revision>,
+    pub(crate) metrics: Arc<Metrics>,
+    pub(crate) cache: Arc<ResponseCache>,
+    pub(crate) catalog: Arc<CatalogCache<CountriesKey>>,
+    pub(crate) codes: Arc<CatalogCache<CatalogFormat>>,
+    // The `Cache-Control` of `/countries` and `/codes`
+    pub(crate) list_caching: ListCaching,
+    // None when no rate provider is configured
+    pub(crate) rates: Option<Arc<RateCache>>,
+    // Locates `/whoami` callers and `ip:` entries; None without `--geoip-db`
+    pub(crate) geoip: Option<Arc<dyn IpResolver>>,
+    // Tells `/events` subscribers about every admin edit and reload
+    pub(crate) events: Arc<DatasetEvents>,
+    // Keeps every admin edit and reload across restarts; None without `--state-dir`
+    pub(crate) snapshots: Option<Arc<SnapshotStore>>,
+    // Records every admin edit and reload before it is made; None without `--audit-log`
+    pub(crate) audit_log: Option<Arc<AuditLog>>,
+    // Services besides GeoIP and the rate provider that `/healthz?verbose=true` checks
+    pub(crate) dependencies: Vec<(&'static str, Arc<dyn DependencyCheck>)>,
+    // For the uptime on `/healthz?verbose=true`
+    pub(crate) started: Instant,
+    // Highest `maxResults` a request may ask for
+    pub(crate) max_results: usize,
+    // Logs the names `/getCountry` finds no country for
+    pub(crate) misses: Arc<MissLog>,
+    // Changes the log level for `/admin/loglevel`; None when the caller set up logging
+    pub(crate) log_level: Option<Arc<LogLevel>>,
+    // The certificates served over HTTPS, for `/healthz?verbose=true`; None over HTTP
+    pub(crate) tls: Option<Arc<TlsCertificates>>,
+    // Proxies whose forwarding headers say which client a request is for
+    pub(crate) trusted_proxies: TrustedProxies,
+    // The dataset version last hashed for `/checksum`, and its checksum
+    pub(crate) checksum: Arc<Mutex<Option<(u64, String)>>>,
+}
+
+impl AppState {
+    // Serves `dataset` from a `MutableSource`, so the admin endpoints and reloads can change it
+    pub fn new(dataset: Dataset) -> Self {
+        Self::from_source(Arc::new(MutableSource::new(dataset)))
+    }
+
+    pub fn from_source(source: Arc<dyn CountryDataSource>) -> Self {
+        AppState {
+            revision: Arc::new(Revision::new(source.version())),
+            source,
+            metrics: Arc::new(Metrics::default()),
+            cache: Arc::new(ResponseCache::default()),
+            catalog: Arc::new(CatalogCache::default()),
+            codes: Arc::new(CatalogCache::default()),
+            list_caching: ListCaching::default(),
+            rates: None,
+            geoip: None,
+            events: Arc::new(DatasetEvents::default()),
+            snapshots: None,
+            audit_log: None,
+            dependencies: Vec::new(),
+            started: Instant::now(),
+            max_results: DEFAULT_MAX_RESULTS,
+            misses: Arc::new(MissLog::default()),
+            log_level: None,
+            tls: None,
+            trusted_proxies: TrustedProxies::default(),
+            checksum: Arc::default(),
+        }
+    }
+
+    // Caches up to `capacity` serialized /getCountry bodies; zero leaves caching off
+    pub fn with_response_cache(mut self, capacity: usize) -> Self {
+        self.cache = Arc::new(ResponseCache::new(capacity));
+        self
+    }
+
+    // Caches /getCountry bodies up to `capacity` locally and shares them through `shared`
+    pub fn with_shared_response_cache(mut self, capacity: usize, shared: Arc<dyn Cache>) -> Self {
+        self.cache = Arc::new(ResponseCache::new(capacity).with_shared(shared));
+        self
+    }
+
+    // Answers `/getCountry?rates=true` from `rates`; without it every rate is null
+    pub fn with_rates(mut self, rates: RateCache) -> Self {
+        self.rates = Some(Arc::new(rates));
+        self
+    }
+
+    // Answers `/whoami` and `ip:` entries from `resolver`; without it they fail with
+    // `GEOIP_UNAVAILABLE`
+    pub fn with_geoip(mut self, resolver: Arc<dyn IpResolver>) -> Self {
+        self.geoip = Some(resolver);
+        self
+    }
+
+    // Writes the served data to `snapshots` after every admin edit and reload
+    pub fn with_snapshots(mut self, snapshots: SnapshotStore) -> Self {
+        self.snapshots = Some(Arc::new(snapshots));
+        self
+    }
+
+    // Appends a record to `audit_log` before every admin edit and reload, which fails
+    // if it cannot be written
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(Arc::new(audit_log));
+        self
+    }
+
+    // Lets requests ask for up to `ceiling` results with `maxResults`
+    pub fn with_max_results(mut self, ceiling: usize) -> Self {
+        self.max_results = ceiling;
+        self
+    }
+
+    // Lets caches keep `/countries` and `/codes` as `list_caching` says
+    pub fn with_list_caching(mut self, list_caching: ListCaching) -> Self {
+        self.list_caching = list_caching;
+        self
+    }
+
+    // Logs each name `/getCountry` finds no country for at most `per_minute` times a minute
+    pub fn with_unmatched_log_limit(mut self, per_minute: NonZeroU32) -> Self {
+        self.misses = Arc::new(MissLog::new(per_minute));
+        self
+    }
+
+    // Lets `/admin/loglevel` read and change the level through `log_level`
+    pub fn with_log_level(mut self, log_level: Arc<LogLevel>) -> Self {
+        self.log_level = Some(log_level);
+        self
+    }
+
+    // Reports the certificate `tls` presents on `/healthz?verbose=true`
+    pub fn with_tls(mut self, tls: Arc<TlsCertificates>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    // Believes the `X-Forwarded-For` and `Forwarded` headers of requests from `proxies`
+    pub fn with_trusted_proxies(mut self, proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = proxies;
+        self
+    }
+
+    // Reports `check` as the optional component `name` on `/healthz?verbose=true`
+    pub fn with_dependency(mut self, name: &'static str, check: Arc<dyn DependencyCheck>) -> Self {
+        self.dependencies.push((name, check));
+        self
+    }
+
+    // The data currently served and its revision
+    pub(crate) fn current(&self) -> (Arc<Dataset>, RevisionInfo) {
+        let (version, dataset) = self.source.versioned();
+        (dataset, self.revision.observe(version))
+    }
+
+    pub(crate) fn current_revision(&self) -> RevisionInfo {
+        self.revision.observe(self.source.version())
+    }
+
+    // The data currently served, its revision and its checksum, hashed once per version
+    pub(crate) fn current_checksum(&self) -> (Arc<Dataset>, RevisionInfo, String) {
+        let (version, dataset) = self.source.versioned();
+        let checksum = {
+            let mut memo = self.checksum.lock().unwrap_or_else(PoisonError::into_inner);
+            match &*memo {
+                Some((hashed, checksum)) if *hashed == version => checksum.clone(),
+                _ => {
+                    let checksum = export::checksum(&dataset);
+                    *memo = Some((version, checksum.clone()));
+                    checksum
+                }
+            }
+        };
+        (dataset, self.revision.observe(version), checksum)
+    }
+
+    // Appends `record` to the audit log, with the ID of the request being served; call it
+    // from within `update_dataset`, so a record that cannot be written stops the change
+    pub(crate) fn audit(&self, record: AuditRecord) -> Result<(), AuditError> {
+        let Some(audit_log) = &self.audit_log else {
+            return Ok(());
+        };
+        let request_id = CURRENT_REQUEST_ID
+            .try_with(|RequestId(request_id)| request_id.clone())
+            .ok();
+        audit_log.append(&record.with_request_id(request_id))
+    }
+
+    // Makes an admin edit or reload with `change`, publishes what it changed to `/events`
+    // and saves the snapshot. Fails without calling `change` if the source is read-only.
+    pub(crate) fn update_dataset<T, E: From<ReadOnlyError>>(
+        &self,
+        change: impl FnOnce(&mut Dataset) -> Result<T, E>,
+    ) -> Result<(T, RevisionInfo), E> {
+        let source = self.source.as_mutable().ok_or(ReadOnlyError)?;
+        let watched = self.events.has_subscribers();
+        let ((value, diff), version) = source.update(|dataset| -> Result<_, E> {
+            let before = watched.then(|| dataset.clone());
+            let value = change(dataset)?;
+            Ok((value, before.map(|before| before.diff(dataset))))
+        })?;
+        let revision = self.revision.observe(version);
+        if let Some(diff) = diff {
+            self.events.publish(revision, diff);
+        }
+        // The change is made either way, so a failed write only loses it on restart
+        if let Some(snapshots) = &self.snapshots {
+            let (version, dataset) = source.versioned();
+            if let Err(error) = snapshots.save(version, &dataset) {
+                tracing::error!("Failed to save admin changes: {}", error);
+            }
+        }
+        Ok((value, revision))
+    }
+}
+
+/// Options for the router [`create_app_with`] builds. The default serves the lookup API
+/// only: admin endpoints, the response cache, CORS, rate limiting, request timeouts,
+/// the concurrency limit, the API docs and the access log are all off.
+#[derive(Debug, Clone, Default)]
+pub struct AppConfig {
+    // Serve the /admin endpoints, which edit the served dataset, to requests that send
+    // this token as `Authorization: Bearer <token>`; without it they are left unrouted
+    pub admin_token: Option<String>,
+    // Serve the /admin endpoints to requests that send these credentials with HTTP Basic
+    // auth too, or instead when there is no `admin_token`
+    pub admin_basic: Option<BasicAuth>,
+    // Number of serialized /getCountry responses to cache; zero disables the cache
+    pub response_cache: usize,
+    // Origins browsers may call the API from; `*` allows any, none disables CORS
+    pub cors_origins: Vec<HeaderValue>,
+    // Most lookup and admin requests served per second across all clients
+    pub rate_limit: Option<NonZeroU32>,
+    // Limit lookup and admin requests per key, and per client address without one, as
+    // each key's tier says
+    pub api_keys: Option<ApiKeys>,
+    // Lookup and admin requests still unanswered after this long fail with `TIMEOUT`
+    pub request_timeout: Option<Duration>,
+    // Most lookup and admin requests handled at once; more fail with `OVERLOADED`
+    pub max_concurrent_requests: Option<NonZeroUsize>,
+    // Serve the GraphiQL playground at /graphql/playground, for debugging queries
+    #[cfg(feature = "graphql")]
+    pub graphql_playground: bool,
+    // Serve Swagger UI at /docs and ReDoc at /redoc, both rendering /openapi.json
+    pub docs: bool,
+    // Log requests under `access_log::TARGET`, sampled as configured; none are logged
+    // without it
+    pub access_log: Option<AccessLogConfig>,
+    // Warn about every request that takes longer, with its normalized query
+    pub slow_request_threshold: Option<Duration>,
+    // Send `Strict-Transport-Security` with every response to a request over TLS
+    pub hsts: Option<Hsts>,
+    // Answer every request made without TLS with a 301 to the same URL over HTTPS
+    pub https_redirect: Option<HttpsRedirect>,
+    // `X-Content-Type-Options` and the like, on by default
+    pub security_headers: SecurityHeaders,
+}
+
+/// How [`run_server_until`] serves connections and shuts down.
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    // Longest wait for requests in flight once shutdown starts
+    pub drain_timeout: Duration,
+    // Serve HTTPS with these certificates instead of plain HTTP
+    pub tls: Option<Arc<TlsCertificates>>,
+    // Listeners on these addresses serve plain HTTP even with `tls`, for
+    // `AppConfig::https_redirect` to answer
+    pub plain_addresses: Vec<SocketAddr>,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        ServeOptions {
+            drain_timeout: shutdown::DEFAULT_DRAIN_TIMEOUT,
+            tls: None,
+            plain_addresses: Vec::new(),
+        }
+    }
+}
+
+// Addresses the server actually bound, shared with handlers via an extension
+#[derive(Debug, Clone, Default)]
+struct ListenAddresses(Vec<SocketAddr>);
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+// Lookup bodies differ by `Accept-Language` when `lang` is not given
+const VARY_LANGUAGE: &str = "accept-language";
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+pub const DEFAULT_LISTEN_ADDRESS: &str = "0.0.0.0:3000";
+const LISTEN_BACKLOG: i32 = 1024;
+// Connections that have not finished the TLS handshake after this long are closed
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// Waits between `bind_listeners_with_retry` attempts, doubling from the first to the last
+const FIRST_BIND_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BIND_BACKOFF: Duration = Duration::from_secs(2);
+// Ports below this one need privileges to bind on Unix
+const PRIVILEGED_PORTS_END: u16 = 1024;
+const CSV_FIELD_COUNT: usize = 4;
+// The `route` of requests no route matched, in the latency histograms
+const UNMATCHED_ROUTE: &str = "unmatched";
+// Longest query logged with a slow request, in characters
+const MAX_LOGGED_QUERY_LENGTH: usize = 512;
+
+// How many results and unmatched entries a lookup answered with, for the slow-request
+// warning
+#[derive(Debug, Clone, Copy)]
+struct ResultCounts {
+    results: usize,
+    unmatched: usize,
+}
+
+pub(crate) fn parse_country_data<R: BufRead>(reader: R) -> CountryData {
+    let mut data = HashMap::new();
+
+    for (line_index, line) in reader.lines().skip(1).enumerate() {
+        let file_line_number = line_index + 2;
+        let line = line.unwrap_or_else(|error| {
+            panic!(
+                "Failed to read country data line {}: {}",
+                file_line_number, error
+            );
+        });
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut parts = line.splitn(CSV_FIELD_COUNT, ',');
+        let country = parts.next().unwrap_or("").trim();
+        let flag = parts.next().unwrap_or("").trim();
+        let currency_code = parts.next().unwrap_or("").trim();
+        let phone_code = parts.next().unwrap_or("").trim();
+        if country.is_empty()
+            || flag.is_empty()
+            || currency_code.is_empty()
+            || phone_code.is_empty()
+        {
+            tracing::warn!(
+                "Skipping malformed country data line {}: {}",
+                file_line_number,
+                line
+            );
+            continue;
+        }
+        let currency_code = match CurrencyCode::new(currency_code) {
+            Ok(code) => code,
+            Err(error) => {
+                tracing::warn!("Skipping country data line {}: {}", file_line_number, error);
+                continue;
+            }
+        };
+        data.insert(
+            country.to_string(),
+            (flag.to_string(), currency_code, phone_code.to_string()),
+        );
+    }
+
+    data
+}
+
+// The generated ISO 3166-1 dataset, unless COUNTRY_DATA_PATH points at a CSV file
+pub(crate) fn load_builtin_country_data() -> Result<Dataset, LoadError> {
+    let Ok(path) = std::env::var("COUNTRY_DATA_PATH") else {
+        return Ok(builtin::dataset());
+    };
+    let file = File::open(&path).unwrap_or_else(|error| {
+        panic!(
+            "Failed to open country data file at {} (source: COUNTRY_DATA_PATH): {}",
+            path, error
+        )
+    });
+    let reader = BufReader::new(file);
+    Dataset::from_flat(parse_country_data(reader)).map_err(|source| LoadError::Csv {
+        path: path.into(),
+        source,
+    })
+}
+
+// Responses borrow from the dataset snapshot, so they are serialized while it is held
+#[utoipa::path(
+    get,
+    path = "/getCountry",
+    tag = "lookup",
+    summary = "Look up countries by name or alias",
+    params(
+        ("based" = String, Query, description = "Comma-separated names or aliases, or `all` for every name and alias. IPv4 and IPv6 addresses, bare or as `ip:<address>` entries, stand for the country of that address; bare ones that cannot be located are listed in `unmatched`. Flag emoji stand for the country whose flag they are; flags no served country has are listed in `unmatched`"),
+        ("lang" = Option<String>, Query, description = "Language of `localizedName`, such as `ja`; overrides `Accept-Language`"),
+        ("Accept-Language" = Option<String>, Header, description = "Languages for `localizedName` when `lang` is not given; English by default"),
+        ("flagFormat" = Option<String>, Query, description = "How `flag` is written: `emoji` (the default), `codepoints`, such as `U+1F1EF U+1F1F5`, or `none` to leave it out"),
+        ("rates" = Option<bool>, Query, description = "Add `exchangeRateToUsd` to each result; when a rate cannot be had it is null and `rateError` says why"),
+        ("include" = Option<String>, Query, description = "Comma-separated field groups to add to each result: `geo` (`latlng`, `borders`, `timezones`), `currencies`, `translations` and `rates`, which is `rates=true`"),
+        ("order" = Option<String>, Query, description = "`input` (the default) keeps the order entries were given in; `alpha` sorts by each result's `canonicalName`, not by the spelling an entry used"),
+        ("groupBy" = Option<String>, Query, description = "`currency` or `continent`: answer `groups`, each with a `key` and its `results` in `order`, instead of `results`"),
+        ("only" = Option<String>, Query, description = "A single-valued field, such as `currencyCode`: answer `values`, that field of each result in `order`, instead of `results`; cannot be combined with `groupBy`"),
+        ("distinct" = Option<bool>, Query, description = "With `only`, leave out repeated values, keeping the first of each"),
+        ("strict" = Option<bool>, Query, description = "Fail with 422 when an entry matches no served country, rather than leaving it out"),
+        ("allowPartial" = Option<bool>, Query, description = "`false` answers no `results` and every unanswered entry in `unmatched` when any entry matches no served country; `strict` wins over it"),
+        ("maxResults" = Option<u32>, Query, description = "Answer at most this many results, from 1 to the server's `--max-results`; `truncated` is true when some were left out"),
+    ),
+    responses(
+        (status = 200, description = "The names, flags and addresses that matched a country, in the order given; `groups` with `groupBy`, and `values` with `only`", body = CountryResponse),
+        (status = 400, description = "`based` names no country or too many, is over a length limit, holds a control character or a malformed `ip:` entry, or `include`, `order`, `groupBy`, `only` or `maxResults` has an invalid value (`EMPTY_QUERY`, `TOO_MANY_COUNTRIES`, `QUERY_TOO_LONG`, `ENTRY_TOO_LONG`, `CONTROL_CHARACTER`, `INVALID_PARAMETER`, `INVALID_IP`)", body = ErrorEnvelope),
+        (status = 404, description = "An `ip:` address has no country in the GeoIP database (`IP_NOT_LOCATED`)", body = ErrorEnvelope),
+        (status = 422, description = "An `ip:` address is private or reserved (`PRIVATE_IP`), or with `strict` an entry matches no served country (`UNMATCHED_ENTRIES`)", body = ErrorEnvelope),
+        (status = 503, description = "An `ip:` entry was given without `--geoip-db` (`GEOIP_UNAVAILABLE`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+    )
+)]
+async fn get_country(
+    State(state): State<AppState>,
+    client: Option<ClientIp>,
+    headers: HeaderMap,
+    ApiQuery(params): ApiQuery<CountryQuery>,
+    ApiQuery(flags): ApiQuery<FlagFormatQuery>,
+) -> Result<Response, ApiError> {
+    let based = params.based.unwrap_or_default();
+    let all = based.trim().eq_ignore_ascii_case("all");
+    if !all {
+        check_requested_names(&based)?;
+    }
+    let mut includes =
+        Includes::parse(params.include.as_deref().unwrap_or_default()).map_err(|error| {
+            ApiError::InvalidParameter {
+                name: "include",
+                reason: error.to_string(),
+            }
+        })?;
+    if params.rates.unwrap_or(false) {
+        includes.insert(IncludeGroup::Rates);
+    }
+    let options = ResultOptions {
+        includes,
+        flag_format: flag_format(flags)?,
+        order: Order::from_param(params.order.as_deref()).map_err(|reason| {
+            ApiError::InvalidParameter {
+                name: "order",
+                reason,
+            }
+        })?,
+        group_by: GroupBy::from_param(params.group_by.as_deref()).map_err(|reason| {
+            ApiError::InvalidParameter {
+                name: "groupBy",
+                reason,
+            }
+        })?,
+        only: OnlyField::from_param(params.only.as_deref()).map_err(|reason| {
+            ApiError::InvalidParameter {
+                name: "only",
+                reason,
+            }
+        })?,
+        distinct: params.distinct.unwrap_or(false),
+        strict: params.strict.unwrap_or(false),
+        atomic: !params.allow_partial.unwrap_or(true),
+        max_results: max_results(params.max_results.as_deref(), state.max_results)?,
+    };
+    if options.only.is_some() && options.group_by.is_some() {
+        return Err(ApiError::InvalidParameter {
+            name: "groupBy",
+            reason: String::from("cannot be combined with `only`"),
+        });
+    }
+
+    let (dataset, revision) = state.current();
+    let query = if all {
+        ResolvedQuery::default()
+    } else {
+        let locator = geoip::GeoIpLocator(state.geoip.as_deref());
+        lookup::resolve_query_with(&based, &dataset, &locator).map_err(|(entry, error)| {
+            ApiError::GeoIp {
+                input: entry.to_string(),
+                error,
+            }
+        })?
+    };
+    let client = client.map(|ClientIp(address)| address);
+    record_misses(&state, &query, client);
+    let language = response_language(params.lang.as_deref(), &headers, &dataset);
+    let resolve = || {
+        if all {
+            CountryResponse {
+                results: lookup::all_names(&dataset, &language),
+                unmatched: Vec::new(),
+                truncated: false,
+            }
+        } else {
+            query.response(&language)
+        }
+    };
+    // `strict` wins over `allowPartial=false`: an unanswered entry is an error, not an
+    // empty answer
+    let lookup = || {
+        let mut response = resolve();
+        if options.strict || options.atomic {
+            let unanswered = lookup::unanswered(&based, &response);
+            if options.strict && !unanswered.is_empty() {
+                return Err(ApiError::UnansweredEntries(
+                    unanswered
+                        .into_iter()
+                        .map(|unmatched| unmatched.input.into_owned())
+                        .collect(),
+                ));
+            }
+            if !unanswered.is_empty() {
+                response = CountryResponse {
+                    results: lookup::Results::new(),
+                    unmatched: unanswered,
+                    truncated: false,
+                };
+            }
+        }
+        options.apply(&mut response.results);
+        response.truncated = lookup::truncate(&mut response.results, options.max_results);
+        Ok(response)
+    };
+
+    // Rates change without the dataset changing, so these responses are never cached.
+    // `only` lists none of the fields rates add, so it never looks them up.
+    if includes.contains(IncludeGroup::Rates) && options.only.is_none() {
+        let response = rates::enrich(state.rates.clone(), lookup()?).await;
+        let counts = ResultCounts {
+            results: response.results.len(),
+            unmatched: response.unmatched.len(),
+        };
+        let body = match options.group_by {
+            Some(by) => serde_json::to_vec(&GroupedResponse {
+                groups: lookup::group(response.results, by, |rated| &rated.info),
+                unmatched: response.unmatched,
+                truncated: response.truncated,
+            }),
+            None => serde_json::to_vec(&response),
+        };
+        let body = body.expect("responses serialize to JSON");
+        return Ok((
+            Extension(counts),
+            json_bytes_response(revision.headers(), Bytes::from(body)),
+        )
+            .into_response());
+    }
+
+    let key = response_cache::cache_key(response_cache::JSON_FORMAT, &language, &options, &based);
+    if state.cache.is_enabled() {
+        if let Some(cached) = state.cache.get(revision.version, &dataset, &key).await {
+            state.metrics.cache_hits.fetch_add(1, Ordering::Relaxed);
+            let counts = ResultCounts {
+                results: cached.results,
+                unmatched: cached.unmatched,
+            };
+            return Ok((
+                Extension(counts),
+                json_bytes_response(revision.headers(), cached.body),
+            )
+                .into_response());
+        }
+        state.metrics.cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let response = lookup()?;
+    let counts = ResultCounts {
+        results: response.results.len(),
+        unmatched: response.unmatched.len(),
+    };
+    let body = match (options.only, options.group_by) {
+        (Some(field), _) => Bytes::from(
+            serde_json::to_vec(&ValuesResponse {
+                values: lookup::values(&response.results, field, options.distinct),
+                unmatched: response.unmatched,
+                truncated: response.truncated,
+            })
+            .expect("responses serialize to JSON"),
+        ),
+        (None, Some(by)) => Bytes::from(
+            serde_json::to_vec(&GroupedResponse {
+                groups: lookup::group(response.results, by, |info| info),
+                unmatched: response.unmatched,
+                truncated: response.truncated,
+            })
+            .expect("responses serialize to JSON"),
+        ),
+        (None, None) => lookup::to_json(&response),
+    };
+    let cached = CachedResponse {
+        body: body.clone(),
+        results: counts.results,
+        unmatched: counts.unmatched,
+    };
+    state
+        .cache
+        .insert(revision.version, &dataset, key, cached)
+        .await;
+    Ok((
+        Extension(counts),
+        json_bytes_response(revision.headers(), body),
+    )
+        .into_response())
+}
+
+// Shares `/getCountry`'s resolver, so an entry is valid exactly when `/getCountry` would
+// answer it; IP addresses are not names and are never valid
+#[utoipa::path(
+    get,
+    path = "/validate",
+    tag = "lookup",
+    summary = "Check whether names are known countries",
+    params(
+        ("based" = String, Query, description = "Comma-separated names, aliases or flag emoji, as in `/getCountry`"),
+        ("lang" = Option<String>, Query, description = "Language of `canonicalName`, such as `ja`; overrides `Accept-Language`"),
+        ("Accept-Language" = Option<String>, Header, description = "Languages for `canonicalName` when `lang` is not given; English by default"),
+    ),
+    responses(
+        (status = 200, description = "Whether each entry names a country, in the order given", body = ValidateResponse),
+        (status = 400, description = "`based` names no country or too many, is over a length limit or holds a control character (`EMPTY_QUERY`, `TOO_MANY_COUNTRIES`, `QUERY_TOO_LONG`, `ENTRY_TOO_LONG`, `CONTROL_CHARACTER`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+    )
+)]
+async fn validate_names(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiQuery(params): ApiQuery<ValidateQuery>,
+) -> Result<Response, ApiError> {
+    let based = params.based.unwrap_or_default();
+    check_requested_names(&based)?;
+
+    let (dataset, revision) = state.current();
+    let language = response_language(params.lang.as_deref(), &headers, &dataset);
+    Ok((
+        revision.headers(),
+        [(VARY, VARY_LANGUAGE)],
+        Json(lookup::validate_names(&dataset, &based, &language)),
+    )
+        .into_response())
+}
+
+// Counts, and within `state.misses`' limit logs, each entry of `query` that names no
+// country. Done before the response cache, so misses answered from it count too.
+fn record_misses(state: &AppState, query: &ResolvedQuery, client: Option<IpAddr>) {
+    let now = Instant::now();
+    for entry in query.unknown_names() {
+        state
+            .metrics
+            .unmatched_names
+            .fetch_add(1, Ordering::Relaxed);
+        let name = normalize(entry);
+        if state.misses.should_log(&name, now) {
+            let client = client.map_or_else(
+                || String::from("unknown"),
+                |address| state.misses.client_hash(address),
+            );
+            tracing::info!(
+                target: miss_log::TARGET,
+                name = %name,
+                client = %client,
+                "No country has this name"
+            );
+        }
+    }
+}
+
+fn max_results(max_results: Option<&str>, ceiling: usize) -> Result<Option<usize>, ApiError> {
+    lookup::max_results_param(max_results, ceiling).map_err(|reason| ApiError::InvalidParameter {
+        name: "maxResults",
+        reason,
+    })
+}
+
+fn flag_format(params: FlagFormatQuery) -> Result<FlagFormat, ApiError> {
+    FlagFormat::from_param(params.flag_format.as_deref()).map_err(|reason| {
+        ApiError::InvalidParameter {
+            name: "flagFormat",
+            reason,
+        }
+    })
+}
+
+// `based` has to name between one and `MAX_COUNTRIES_PER_QUERY` countries; whether they
+// exist is not checked, unknown names are left out of the results
+fn check_requested_names(based: &str) -> Result<(), ApiError> {
+    input::check(based)?;
+    let requested = based
+        .split(',')
+        .filter(|name| !input::clean(name).is_empty())
+        .count();
+    match requested {
+        0 => Err(ApiError::EmptyQuery),
+        requested if requested > MAX_COUNTRIES_PER_QUERY => Err(ApiError::TooManyCountries {
+            requested,
+            limit: MAX_COUNTRIES_PER_QUERY,
+        }),
+        _ => Ok(()),
+    }
+}
+
+// Pre-serialized JSON, answered exactly as `Json` would have
+fn json_bytes_response(headers: RevisionHeaders, body: Bytes) -> Response {
+    (
+        headers,
+        [(CONTENT_TYPE, "application/json"), (VARY, VARY_LANGUAGE)],
+        body,
+    )
+        .into_response()
+}
+
+// `lang` when given, otherwise negotiated from `Accept-Language` among the languages
+// `dataset` has names in
+fn response_language(lang: Option<&str>, headers: &HeaderMap, dataset: &Dataset) -> String {
+    let accept_language = headers
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|value| value.to_str().ok());
+    lookup::negotiated_language(lang, accept_language, |language| {
+        dataset.has_language(language)
+    })
+}
+
+// The caller's country, located by the address the request came from. Proxies in front
+// of the server are not seen through, so behind one this locates the proxy.
+#[utoipa::path(
+    get,
+    path = "/whoami",
+    tag = "lookup",
+    summary = "Locate the caller by IP address",
+    params(
+        ("lang" = Option<String>, Query, description = "Language of `localizedName`, such as `ja`; overrides `Accept-Language`"),
+        ("Accept-Language" = Option<String>, Header, description = "Languages for `localizedName` when `lang` is not given; English by default"),
+        ("flagFormat" = Option<String>, Query, description = "How `flag` is written: `emoji` (the default), `codepoints`, such as `U+1F1EF U+1F1F5`, or `none` to leave it out"),
+    ),
+    responses(
+        (status = 200, description = "The caller's address and country", body = WhoamiResponse),
+        (status = 404, description = "The GeoIP database has no country for the address (`IP_NOT_LOCATED`)", body = ErrorEnvelope),
+        (status = 422, description = "The caller has a private or reserved address (`PRIVATE_IP`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+        (status = 503, description = "No `--geoip-db` is configured (`GEOIP_UNAVAILABLE`)", body = ErrorEnvelope),
+    )
+)]
+async fn whoami(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    ApiQuery(params): ApiQuery<WhoamiQuery>,
+    ApiQuery(flags): ApiQuery<FlagFormatQuery>,
+) -> Result<Response, ApiError> {
+    let flag_format = flag_format(flags)?;
+    let alpha2 =
+        geoip::locate_address(state.geoip.as_deref(), ip).map_err(|error| ApiError::GeoIp {
+            input: ip.to_string(),
+            error,
+        })?;
+
+    let (dataset, revision) = state.current();
+    let language = response_language(params.lang.as_deref(), &headers, &dataset);
+    let mut country = geoip::find_country(&dataset, alpha2)
+        .map(|record| CountryInfo::new(&record.name, record, &language));
+    lookup::format_flags(&mut country, flag_format);
+    Ok((
+        revision.headers(),
+        [(VARY, VARY_LANGUAGE)],
+        Json(WhoamiResponse { ip, country }),
+    )
+        .into_response())
+}
+
+// The countries of the longest calling code the number starts with, sorted by name. A
+// number that no code matches is answered with no results rather than a 404.
+#[utoipa::path(
+    get,
+    path = "/byCallingCode",
+    tag = "lookup",
+    summary = "Find countries by phone number",
+    params(
+        ("number" = String, Query, description = "A number such as `+81-90-1234-5678`, or just a calling code such as `+1`; spaces, dashes, dots and parentheses are ignored, and the `+` must be sent as `%2B` or left out"),
+        ("lang" = Option<String>, Query, description = "Language of `localizedName`, such as `ja`; overrides `Accept-Language`"),
+        ("Accept-Language" = Option<String>, Header, description = "Languages for `localizedName` when `lang` is not given; English by default"),
+        ("flagFormat" = Option<String>, Query, description = "How `flag` is written: `emoji` (the default), `codepoints`, such as `U+1F1EF U+1F1F5`, or `none` to leave it out"),
+    ),
+    responses(
+        (status = 200, description = "The countries dialed with the number's calling code", body = CountryResponse),
+        (status = 400, description = "Missing or malformed `number` (`INVALID_PARAMETER`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+    )
+)]
+async fn by_calling_code(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiQuery(params): ApiQuery<CallingCodeQuery>,
+    ApiQuery(flags): ApiQuery<FlagFormatQuery>,
+) -> Result<Response, ApiError> {
+    let flag_format = flag_format(flags)?;
+    let digits = calling_code::number_digits(params.number.as_deref().unwrap_or_default())
+        .map_err(|error| ApiError::InvalidParameter {
+            name: "number",
+            reason: error.to_string(),
+        })?;
+
+    let (dataset, revision) = state.current();
+    let language = response_language(params.lang.as_deref(), &headers, &dataset);
+    let mut results = lookup::countries_by_calling_code(&dataset, &digits, &language);
+    lookup::format_flags(&mut results, flag_format);
+    Ok((
+        revision.headers(),
+        [(VARY, VARY_LANGUAGE)],
+        Json(CountryResponse {
+            results,
+            unmatched: Vec::new(),
+            truncated: false,
+        }),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/byCurrency",
+    tag = "lookup",
+    summary = "Find countries by currency",
+    description = "With `code`, the countries using that currency, sorted by name. With `symbol`, the currencies written with it that served countries use, each with its countries; `ambiguous` is true when there is more than one, as for `$` and `¥`. An unknown symbol has no currencies.",
+    params(
+        ("code" = Option<String>, Query, description = "ISO 4217 code, such as `JPY`; required unless `symbol` is given"),
+        ("symbol" = Option<String>, Query, description = "Currency symbol, such as `€` or `$`, percent-encoded; required unless `code` is given"),
+        ("lang" = Option<String>, Query, description = "Language of `localizedName`, such as `ja`; overrides `Accept-Language`"),
+        ("Accept-Language" = Option<String>, Header, description = "Languages for `localizedName` when `lang` is not given; English by default"),
+        ("flagFormat" = Option<String>, Query, description = "How `flag` is written: `emoji` (the default), `codepoints`, such as `U+1F1EF U+1F1F5`, or `none` to leave it out"),
+    ),
+    responses(
+        (status = 200, description = "The countries using the currency, or grouped by the currencies the symbol stands for", body = CurrencyResponse),
+        (status = 400, description = "Neither or both of `code` and `symbol`, or a malformed code (`INVALID_PARAMETER`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+    )
+)]
+async fn by_currency(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiQuery(params): ApiQuery<CurrencyQuery>,
+    ApiQuery(flags): ApiQuery<FlagFormatQuery>,
+) -> Result<Response, ApiError> {
+    let flag_format = flag_format(flags)?;
+    let (dataset, revision) = state.current();
+    let language = response_language(params.lang.as_deref(), &headers, &dataset);
+
+    let mut response = match (params.code.as_deref(), params.symbol.as_deref()) {
+        (Some(_), Some(_)) => {
+            return Err(ApiError::InvalidParameter {
+                name: "symbol",
+                reason: String::from("cannot be combined with `code`"),
+            })
+        }
+        (None, None) => {
+            return Err(ApiError::InvalidParameter {
+                name: "code",
+                reason: String::from("is required unless `symbol` is given"),
+            })
+        }
+        (Some(code), None) => {
+            let currency_code =
+                CurrencyCode::new(code.trim()).map_err(|error| ApiError::InvalidParameter {
+                    name: "code",
+                    reason: error.to_string(),
+                })?;
+            CurrencyResponse::Code(Box::new(CountryResponse {
+                results: lookup::countries_by_currency(&dataset, currency_code, &language),
+                unmatched: Vec::new(),
+                truncated: false,
+            }))
+        }
+        (None, Some(symbol)) => {
+            CurrencyResponse::Symbol(lookup::countries_by_symbol(&dataset, symbol, &language))
+        }
+    };
+    match &mut response {
+        CurrencyResponse::Code(response) => {
+            lookup::format_flags(&mut response.results, flag_format)
+        }
+        CurrencyResponse::Symbol(response) => lookup::format_flags(
+            response
+                .currencies
+                .iter_mut()
+                .flat_map(|currency| &mut currency.countries),
+            flag_format,
+        ),
+    }
+    Ok((revision.headers(), [(VARY, VARY_LANGUAGE)], Json(response)).into_response())
+}
+
+// `value` as degrees within `valid`, or the reason it is not
+fn parse_degrees(
+    name: &'static str,
+    value: Option<&str>,
+    valid: fn(f64) -> bool,
+    range: &str,
+) -> Result<f64, ApiError> {
+    value
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|&degrees| valid(degrees))
+        .ok_or_else(|| ApiError::InvalidParameter {
+            name,
+            reason: format!("must be a number between {}", range),
+        })
+}
+
+#[utoipa::path(
+    get,
+    path = "/byCoordinates",
+    tag = "lookup",
+    summary = "Find countries by location",
+    description = "Countries whose bounding box contains the point, the smallest box first, so the likeliest country leads. Boxes are rectangles, so near a border a neighbour may be listed too, or listed first; a point in the open sea has no results.",
+    params(
+        ("lat" = f64, Query, description = "Latitude in degrees, from -90 to 90"),
+        ("lng" = f64, Query, description = "Longitude in degrees, from -180 to 180"),
+        ("lang" = Option<String>, Query, description = "Language of `localizedName`, such as `ja`; overrides `Accept-Language`"),
+        ("Accept-Language" = Option<String>, Header, description = "Languages for `localizedName` when `lang` is not given; English by default"),
+        ("flagFormat" = Option<String>, Query, description = "How `flag` is written: `emoji` (the default), `codepoints`, such as `U+1F1EF U+1F1F5`, or `none` to leave it out"),
+    ),
+    responses(
+        (status = 200, description = "The countries whose bounding box contains the point", body = CountryResponse),
+        (status = 400, description = "Missing or out of range `lat` or `lng` (`INVALID_PARAMETER`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+    )
+)]
+async fn by_coordinates(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiQuery(params): ApiQuery<CoordinatesQuery>,
+    ApiQuery(flags): ApiQuery<FlagFormatQuery>,
+) -> Result<Response, ApiError> {
+    let flag_format = flag_format(flags)?;
+    let latitude = parse_degrees(
+        "lat",
+        params.lat.as_deref(),
+        bounding_box::is_latitude,
+        "-90 and 90",
+    )?;
+    let longitude = parse_degrees(
+        "lng",
+        params.lng.as_deref(),
+        bounding_box::is_longitude,
+        "-180 and 180",
+    )?;
+
+    let (dataset, revision) = state.current();
+    let language = response_language(params.lang.as_deref(), &headers, &dataset);
+    let mut results = lookup::countries_by_coordinates(&dataset, latitude, longitude, &language);
+    lookup::format_flags(&mut results, flag_format);
+    Ok((
+        revision.headers(),
+        [(VARY, VARY_LANGUAGE)],
+        Json(CountryResponse {
+            results,
+            unmatched: Vec::new(),
+            truncated: false,
+        }),
+    )
+        .into_response())
+}
+
+// A TLD no served country has, generic ones such as `.com` included, is answered with no
+// results rather than a 404
+#[utoipa::path(
+    get,
+    path = "/byTld",
+    tag = "lookup",
+    summary = "Find countries by top-level domain",
+    params(
+        ("tld" = String, Query, description = "Country-code top-level domain such as `.jp` or `jp`, in any case"),
+        ("lang" = Option<String>, Query, description = "Language of `localizedName`, such as `ja`; overrides `Accept-Language`"),
+        ("Accept-Language" = Option<String>, Header, description = "Languages for `localizedName` when `lang` is not given; English by default"),
+        ("flagFormat" = Option<String>, Query, description = "How `flag` is written: `emoji` (the default), `codepoints`, such as `U+1F1EF U+1F1F5`, or `none` to leave it out"),
+    ),
+    responses(
+        (status = 200, description = "The countries using the TLD", body = CountryResponse),
+        (status = 400, description = "Missing or empty `tld` (`INVALID_PARAMETER`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+    )
+)]
+async fn by_tld(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiQuery(params): ApiQuery<TldQuery>,
+    ApiQuery(flags): ApiQuery<FlagFormatQuery>,
+) -> Result<Response, ApiError> {
+    let flag_format = flag_format(flags)?;
+    let tld = params
+        .tld
+        .filter(|tld| tld::normalize(tld).is_some())
+        .ok_or_else(|| ApiError::InvalidParameter {
+            name: "tld",
+            reason: String::from("is required"),
+        })?;
+
+    let (dataset, revision) = state.current();
+    let language = response_language(params.lang.as_deref(), &headers, &dataset);
+    let mut results = lookup::countries_by_tld(&dataset, &tld, &language);
+    lookup::format_flags(&mut results, flag_format);
+    Ok((
+        revision.headers(),
+        [(VARY, VARY_LANGUAGE)],
+        Json(CountryResponse {
+            results,
+            unmatched: Vec::new(),
+            truncated: false,
+        }),
+    )
+        .into_response())
+}
+
+// A language no served country lists, or that is not known at all, is answered with no
+// results rather than a 404, like a TLD
+#[utoipa::path(
+    get,
+    path = "/byLanguage",
+    tag = "lookup",
+    summary = "Find countries by official language",
+    params(
+        ("name" = Option<String>, Query, description = "Language as an ISO 639 code, English name or native name, such as `fr`, `french` or `français`, in any case; required unless `list` is true"),
+        ("list" = Option<bool>, Query, description = "List every official language of a served country, with how many list it, instead"),
+        ("lang" = Option<String>, Query, description = "Language of `localizedName`, such as `ja`; overrides `Accept-Language`"),
+        ("Accept-Language" = Option<String>, Header, description = "Languages for `localizedName` when `lang` is not given; English by default"),
+        ("flagFormat" = Option<String>, Query, description = "How `flag` is written: `emoji` (the default), `codepoints`, such as `U+1F1EF U+1F1F5`, or `none` to leave it out"),
+    ),
+    responses(
+        (status = 200, description = "The countries listing the language as official, or with `list=true` the languages", body = LanguageResponse),
+        (status = 400, description = "Missing or empty `name` without `list` (`INVALID_PARAMETER`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+    )
+)]
+async fn by_language(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiQuery(params): ApiQuery<LanguageQuery>,
+    ApiQuery(flags): ApiQuery<FlagFormatQuery>,
+) -> Result<Response, ApiError> {
+    let flag_format = flag_format(flags)?;
+    let (dataset, revision) = state.current();
+    if params.list.unwrap_or(false) {
+        let languages = lookup::language_counts(&dataset);
+        return Ok((
+            revision.headers(),
+            Json(LanguageResponse::Languages(languages)),
+        )
+            .into_response());
+    }
+    let name = params
+        .name
+        .filter(|name| !name.trim().is_empty())
+        .ok_or_else(|| ApiError::InvalidParameter {
+            name: "name",
+            reason: String::from("is required unless `list` is true"),
+        })?;
+
+    let language = response_language(params.lang.as_deref(), &headers, &dataset);
+    let mut results = lookup::countries_by_language(&dataset, &name, &language);
+    lookup::format_flags(&mut results, flag_format);
+    Ok((
+        revision.headers(),
+        [(VARY, VARY_LANGUAGE)],
+        Json(LanguageResponse::Countries(Box::new(CountryResponse {
+            results,
+            unmatched: Vec::new(),
+            truncated: false,
+        }))),
+    )
+        .into_response())
+}
+
+// Offsets are worked out at the time of the request, so the countries at `+01:00` in
+// winter are not those at `+01:00` in summer
+#[utoipa::path(
+    get,
+    path = "/byTimezone",
+    tag = "lookup",
+    summary = "Find countries by time zone or current UTC offset",
+    params(
+        ("zone" = Option<String>, Query, description = "IANA time zone such as `Europe/Paris`, in any case; required unless `offset` is given"),
+        ("offset" = Option<String>, Query, description = "UTC offset such as `+09:00`, `-0330` or `+05`, matched against every zone's offset right now, daylight saving time included; the `+` must be sent as `%2B` or left out"),
+        ("lang" = Option<String>, Query, description = "Language of `localizedName`, such as `ja`; overrides `Accept-Language`"),
+        ("Accept-Language" = Option<String>, Header, description = "Languages for `localizedName` when `lang` is not given; English by default"),
+        ("flagFormat" = Option<String>, Query, description = "How `flag` is written: `emoji` (the default), `codepoints`, such as `U+1F1EF U+1F1F5`, or `none` to leave it out"),
+    ),
+    responses(
+        (status = 200, description = "The countries using the zone, or with a zone at the offset", body = CountryResponse),
+        (status = 400, description = "Neither or both of `zone` and `offset`, an unknown zone or a malformed offset (`INVALID_PARAMETER`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+    )
+)]
+async fn by_timezone(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiQuery(params): ApiQuery<TimezoneQuery>,
+    ApiQuery(flags): ApiQuery<FlagFormatQuery>,
+) -> Result<Response, ApiError> {
+    let flag_format = flag_format(flags)?;
+    let (dataset, revision) = state.current();
+    let language = response_language(params.lang.as_deref(), &headers, &dataset);
+    let mut results = match (params.zone.as_deref(), params.offset.as_deref()) {
+        (Some(_), Some(_)) => {
+            return Err(ApiError::InvalidParameter {
+                name: "offset",
+                reason: String::from("cannot be combined with `zone`"),
+            })
+        }
+        (None, None) => {
+            return Err(ApiError::InvalidParameter {
+                name: "zone",
+                reason: String::from("is required unless `offset` is given"),
+            })
+        }
+        (Some(zone), None) => {
+            let zone = timezone::canonical(zone).ok_or_else(|| ApiError::InvalidParameter {
+                name: "zone",
+                reason: String::from("must be an IANA time zone, such as `Europe/Paris`"),
+            })?;
+            lookup::countries_by_timezone(&dataset, zone, &language)
+        }
+        (None, Some(offset)) => {
+            let offset =
+                timezone::parse_offset(offset).map_err(|error| ApiError::InvalidParameter {
+                    name: "offset",
+                    reason: error.to_string(),
+                })?;
+            lookup::countries_by_offset(&dataset, offset, chrono::Utc::now(), &language)
+        }
+    };
+    lookup::format_flags(&mut results, flag_format);
+    Ok((
+        revision.headers(),
+        [(VARY, VARY_LANGUAGE)],
+        Json(CountryResponse {
+            results,
+            unmatched: Vec::new(),
+            truncated: false,
+        }),
+    )
+        .into_response())
+}
+
+// Codes do not depend on the language, so unlike `/countries` there is no `lang`. The
+// table only changes with the dataset, so it is served from a catalog cache of its own.
+#[utoipa::path(
+    get,
+    path = "/codes",
+    tag = "lookup",
+    summary = "ISO codes of every country",
+    description = "A mapping table of every country's canonical name to its ISO 3166-1 alpha-2, alpha-3 and numeric codes, sorted by alpha-2 code. Custom countries whose flag is not an ISO 3166-1 country's have no alpha-3 or numeric code.",
+    params(
+        ("format" = Option<String>, Query, description = "`json` (the default), `csv` or `ndjson`"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a table already held"),
+    ),
+    responses(
+        (status = 200, description = "Every country's codes, sorted by alpha-2 code, with a `Cache-Control` that lets caches serve a stale copy while they revalidate it", content(
+            (CodesResponse = "application/json"),
+            (String = "text/csv"),
+            (String = "application/x-ndjson"),
+        )),
+        (status = 304, description = "The table still matches `If-None-Match`"),
+        (status = 400, description = "Unknown `format` (`INVALID_PARAMETER`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+    )
+)]
+async fn codes(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiQuery(params): ApiQuery<CodesQuery>,
+) -> Result<Response, ApiError> {
+    let format = CatalogFormat::from_param(params.format.as_deref()).map_err(|reason| {
+        ApiError::InvalidParameter {
+            name: "format",
+            reason,
+        }
+    })?;
+    let (dataset, revision) = state.current();
+    let codes = state.codes.get_or_render(revision.version, format, || {
+        let table = lookup::code_table(&dataset);
+        match format {
+            CatalogFormat::Json => {
+                Bytes::from(serde_json::to_vec(&table).expect("code tables serialize to JSON"))
+            }
+            CatalogFormat::Csv => catalog::codes_to_csv(&table.codes),
+            CatalogFormat::Ndjson => catalog::to_ndjson(&table.codes),
+        }
+    });
+    Ok(list_response(&state, &headers, codes, &revision, format))
+}
+
+// Computed from the current dataset on every request, so admin edits and reloads show up
+// at once; conditional requests against the revision headers avoid the work when
+// nothing changed
+#[utoipa::path(
+    get,
+    path = "/stats",
+    tag = "lookup",
+    summary = "Counts over the served countries",
+    responses(
+        (status = 200, description = "How many countries, aliases, and countries per continent and per currency are served", body = StatsResponse),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+    )
+)]
+async fn stats(State(state): State<AppState>) -> Response {
+    let (dataset, revision) = state.current();
+    (revision.headers(), Json(lookup::stats(&dataset))).into_response()
+}
+
+// `file` is a name or alias with an `.svg` or `.png` extension, such as `japan.svg`
+#[utoipa::path(
+    get,
+    path = "/flag/{file}",
+    tag = "lookup",
+    summary = "Flag image of a country",
+    params(
+        ("file" = String, Path, description = "Name or alias with an `.svg` or `.png` extension, such as `japan.svg` or `united%20kingdom.png`"),
+        ("size" = Option<u32>, Query, description = "Width of a PNG in pixels, 16 to 1024; 64 by default"),
+    ),
+    responses(
+        (status = 200, description = "The flag, as SVG or PNG", content(
+            (String = "image/svg+xml"),
+            (Vec<u8> = "image/png"),
+        )),
+        (status = 400, description = "An extension other than `.svg` or `.png`, `.png` in a build without the `flag-png` feature, or a `size` out of range (`INVALID_PARAMETER`)", body = ErrorEnvelope),
+        (status = 404, description = "No country is named so, or it has no flag image (`COUNTRY_NOT_FOUND`, `FLAG_NOT_FOUND`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+    )
+)]
+async fn flag(
+    State(state): State<AppState>,
+    Path(file): Path<String>,
+    ApiQuery(params): ApiQuery<FlagQuery>,
+) -> Result<Response, ApiError> {
+    let (name, format) =
+        flag_image::parse_file_name(&file).ok_or_else(|| ApiError::InvalidParameter {
+            name: "format",
+            reason: String::from("must be `.svg` or `.png`"),
+        })?;
+
+    let (dataset, _) = state.current();
+    let (record, _) = lookup::find(&dataset, name)
+        .ok_or_else(|| ApiError::UnknownCountry(vec![name.to_string()]))?;
+    let svg = Alpha2::from_flag(&record.flag)
+        .and_then(flag_image::svg)
+        .ok_or_else(|| ApiError::NoFlagImage(record.name.clone()))?;
+    let body = match format {
+        ImageFormat::Svg => Bytes::from_static(svg.as_bytes()),
+        ImageFormat::Png => flag_png(svg, params.size)?,
+    };
+
+    Ok((
+        [
+            (CONTENT_TYPE, format.content_type()),
+            (CACHE_CONTROL, flag_image::CACHE_CONTROL),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+#[cfg(feature = "flag-png")]
+fn flag_png(svg: &str, size: Option<u32>) -> Result<Bytes, ApiError> {
+    let size = size.unwrap_or(flag_image::DEFAULT_PNG_SIZE);
+    if !(flag_image::MIN_PNG_SIZE..=flag_image::MAX_PNG_SIZE).contains(&size) {
+        return Err(ApiError::InvalidParameter {
+            name: "size",
+            reason: format!(
+                "must be between {} and {} pixels",
+                flag_image::MIN_PNG_SIZE,
+                flag_image::MAX_PNG_SIZE
+            ),
+        });
+    }
+    let png = flag_image::render_png(svg, size).map_err(anyhow::Error::from)?;
+    Ok(Bytes::from(png))
+}
+
+#[cfg(not(feature = "flag-png"))]
+fn flag_png(_svg: &str, _size: Option<u32>) -> Result<Bytes, ApiError> {
+    Err(ApiError::InvalidParameter {
+        name: "format",
+        reason: String::from("PNG flags need a build with the `flag-png` feature; use `.svg`"),
+    })
+}
+
+// Never fails for an input that matches nothing; only an empty or oversized batch, or a
+// body that is not a request, is refused
+#[utoipa::path(
+    post,
+    path = "/resolve",
+    tag = "lookup",
+    summary = "Resolve a batch of mixed identifiers",
+    description = "Classifies each input as an ISO alpha-2 or alpha-3 code, a name, a TLD, a calling code, a flag emoji or an IP address, and resolves it through the matching index. Answers one entry per input, in order, with every country it matches; inputs of no recognized kind have `kind: \"unknown\"` and no results.",
+    request_body = ResolveRequest,
+    params(
+        ("lang" = Option<String>, Query, description = "Language of `localizedName`, such as `ja`; overrides `Accept-Language`"),
+        ("Accept-Language" = Option<String>, Header, description = "Languages for `localizedName` when `lang` is not given; English by default"),
+        ("flagFormat" = Option<String>, Query, description = "How `flag` is written: `emoji` (the default), `codepoints`, such as `U+1F1EF U+1F1F5`, or `none` to leave it out"),
+    ),
+    responses(
+        (status = 200, description = "Each input with its kind and the countries it resolves to", body = ResolveResponse),
+        (status = 400, description = "No inputs, too many, an input over the length limit or holding a control character, or a body that is not JSON (`EMPTY_QUERY`, `TOO_MANY_COUNTRIES`, `ENTRY_TOO_LONG`, `CONTROL_CHARACTER`, `INVALID_BODY`)", body = ErrorEnvelope),
+        (status = 422, description = "The body is not an object with an `inputs` array of strings (`INVALID_BODY`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+    )
+)]
+async fn resolve_batch(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiQuery(params): ApiQuery<ResolveQuery>,
+    ApiQuery(flags): ApiQuery<FlagFormatQuery>,
+    body: Result<Json<ResolveRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let flag_format = flag_format(flags)?;
+    let Json(request) = body?;
+    match request.inputs.len() {
+        0 => return Err(ApiError::EmptyQuery),
+        requested if requested > MAX_COUNTRIES_PER_QUERY => {
+            return Err(ApiError::TooManyCountries {
+                requested,
+                limit: MAX_COUNTRIES_PER_QUERY,
+            })
+        }
+        _ => {}
+    }
+    for entry in &request.inputs {
+        input::check_entry(entry)?;
+    }
+
+    let (dataset, revision) = state.current();
+    let language = response_language(params.lang.as_deref(), &headers, &dataset);
+    let mut response =
+        resolve::resolve_inputs(&dataset, &request.inputs, &language, state.geoip.as_deref());
+    lookup::format_flags(
+        response
+            .results
+            .iter_mut()
+            .flat_map(|input| &mut input.results),
+        flag_format,
+    );
+    let resolved = response
+        .results
+        .iter()
+        .filter(|input| !input.results.is_empty())
+        .count();
+    let counts = ResultCounts {
+        results: resolved,
+        unmatched: response.results.len() - resolved,
+    };
+    Ok((
+        Extension(counts),
+        revision.headers(),
+        [(VARY, VARY_LANGUAGE)],
+        Json(response),
+    )
+        .into_response())
+}
+
+// Streams until the client goes away; events are only sent for changes made after it
+// connected
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "lookup",
+    summary = "Stream changes to the served data",
+    responses(
+        (status = 200, description = "Server-Sent Events: a `dataset` event per admin edit or reload, its `id` the new dataset version", content(
+            (DatasetEvent = "text/event-stream"),
+        )),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+    )
+)]
+async fn events(
+    State(state): State<AppState>,
+    stopping: Option<Extension<Stopping>>,
+) -> impl IntoResponse {
+    state
+        .events
+        .stream(stopping.map(|Extension(Stopping(stopping))| stopping))
+}
+
+// One entry per country under its canonical name, sorted by name. The body only changes
+// with the dataset, so it is served from the catalog cache.
+#[utoipa::path(
+    get,
+    path = "/countries",
+    tag = "lookup",
+    summary = "List every country once",
+    params(
+        ("lang" = Option<String>, Query, description = "Language of `localizedName`, such as `ja`; overrides `Accept-Language`"),
+        ("Accept-Language" = Option<String>, Header, description = "Languages for `localizedName` when `lang` is not given; English by default"),
+        ("flagFormat" = Option<String>, Query, description = "How `flag` is written: `emoji` (the default), `codepoints`, such as `U+1F1EF U+1F1F5`, or `none` to leave it out"),
+        ("format" = Option<String>, Query, description = "`json` (the default), `csv` or `ndjson`"),
+        ("maxResults" = Option<u32>, Query, description = "List at most this many countries, from 1 to the server's `--max-results`; the JSON body has `truncated` set when some were left out"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a catalog already held"),
+    ),
+    responses(
+        (status = 200, description = "Every country under its canonical name, sorted by name, with a `Cache-Control` that lets caches serve a stale copy while they revalidate it", content(
+            (CountryResponse = "application/json"),
+            (String = "text/csv"),
+            (String = "application/x-ndjson"),
+        )),
+        (status = 304, description = "The catalog still matches `If-None-Match`"),
+        (status = 400, description = "Unknown `format`, or `maxResults` out of range (`INVALID_PARAMETER`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+    )
+)]
+async fn list_countries(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ApiQuery(params): ApiQuery<CatalogQuery>,
+    ApiQuery(flags): ApiQuery<FlagFormatQuery>,
+) -> Result<Response, ApiError> {
+    let flag_format = flag_format(flags)?;
+    let max_results = max_results(params.max_results.as_deref(), state.max_results)?;
+    let format = CatalogFormat::from_param(params.format.as_deref()).map_err(|reason| {
+        ApiError::InvalidParameter {
+            name: "format",
+            reason,
+        }
+    })?;
+    let (dataset, revision) = state.current();
+    let language = response_language(params.lang.as_deref(), &headers, &dataset);
+    let catalog = state.catalog.get_or_render(
+        revision.version,
+        (format, flag_format, max_results, language.clone()),
+        || {
+            let mut results = lookup::canonical_countries(&dataset, &language);
+            let truncated = lookup::truncate(&mut results, max_results);
+            lookup::format_flags(&mut results, flag_format);
+            match format {
+                CatalogFormat::Json => lookup::to_json(&CountryResponse {
+                    results,
+                    unmatched: Vec::new(),
+                    truncated,
+                }),
+                CatalogFormat::Csv => catalog::to_csv(&results),
+                CatalogFormat::Ndjson => catalog::to_ndjson(&results),
+            }
+        },
+    );
+
+    let mut response = list_response(&state, &headers, catalog, &revision, format);
+    response
+        .headers_mut()
+        .insert(VARY, HeaderValue::from_static(VARY_LANGUAGE));
+    Ok(response)
+}
+
+// A memoized list body with its ETag and `Cache-Control`, or `304 Not Modified` with the
+// same headers and no body when `If-None-Match` already names it
+fn list_response(
+    state: &AppState,
+    headers: &HeaderMap,
+    list: CatalogBody,
+    revision: &RevisionInfo,
+    format: CatalogFormat,
+) -> Response {
+    let cache_headers = [
+        (axum::http::header::ETAG, list.etag.clone()),
+        (CACHE_CONTROL, state.list_caching.header_value()),
+    ];
+    if list.is_cached_by(headers) {
+        return (StatusCode::NOT_MODIFIED, cache_headers, revision.headers()).into_response();
+    }
+    (
+        cache_headers,
+        revision.headers(),
+        [(CONTENT_TYPE, format.content_type())],
+        list.body,
+    )
+        .into_response()
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "status",
+    summary = "Request and cache counters",
+    responses((status = 200, description = "Prometheus text exposition format", body = String, content_type = "text/plain"))
+)]
+async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let mut output = state.metrics.render();
+    if let Some(rates) = &state.rates {
+        output.push_str(&rates.breaker().render_metrics());
+    }
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        output,
+    )
+}
+
+// Built per request; it is only fetched when generating clients
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "status",
+    summary = "This document",
+    responses((status = 200, description = "The OpenAPI document of the API", body = Object))
+)]
+async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(openapi::document())
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "status",
+    summary = "Liveness and the revision of the served data",
+    params(
+        ("verbose" = Option<bool>, Query, description = "Add uptime, the dataset checksum, the last reload and the state of every component"),
+    ),
+    responses(
+        (status = 200, description = "The server is up, possibly degraded", body = HealthResponse),
+        (status = 400, description = "`verbose` is not a boolean (`INVALID_PARAMETER`)", body = ErrorEnvelope),
+        (status = 503, description = "With `verbose=true`, a required component is down", body = HealthResponse),
+    )
+)]
+async fn healthz(
+    State(state): State<AppState>,
+    ApiQuery(params): ApiQuery<HealthQuery>,
+) -> Result<Response, ApiError> {
+    let revision = state.current_revision();
+
+    let last_refresh = match state.metrics.last_refresh.load(Ordering::Relaxed) {
+        0 => None,
+        seconds => Some(httpdate::fmt_http_date(
+            UNIX_EPOCH + Duration::from_secs(seconds),
+        )),
+    };
+
+    let (status, details) = if params.verbose.unwrap_or(false) {
+        let (status, details) = health::report(&state).await;
+        (status, Some(details))
+    } else {
+        (HealthStatus::Ok, None)
+    };
+    let code = match status {
+        HealthStatus::Down => StatusCode::SERVICE_UNAVAILABLE,
+        HealthStatus::Ok | HealthStatus::Degraded => StatusCode::OK,
+    };
+
+    Ok((
+        code,
+        Json(HealthResponse {
+            status: status.as_str().to_string(),
+            dataset_version: revision.version,
+            dataset_modified: revision.modified_http_date(),
+            last_refresh,
+            details,
+        }),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "status",
+    summary = "Build, listeners and the revision of the served data",
+    responses((status = 200, description = "What is running", body = VersionResponse))
+)]
+async fn version(
+    State(state): State<AppState>,
+    listen_addresses: Option<Extension<ListenAddresses>>,
+) -> Json<VersionResponse> {
+    let listen_addresses = listen_addresses
+        .map(|Extension(ListenAddresses(addresses))| addresses)
+        .unwrap_or_default();
+    let revision = state.current_revision();
+
+    Json(VersionResponse {
+        name: env!("CARGO_PKG_NAME").to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        listen_addresses,
+        dataset_version: revision.version,
+        dataset_modified: revision.modified_http_date(),
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/checksum",
+    tag = "status",
+    summary = "SHA-256 of the served data, for comparing instances",
+    description = "Hashes the JSON `/admin/export` of the served countries, sorted by name, so two instances serving the same data answer the same checksum whatever their dataset versions.",
+    responses((status = 200, description = "The checksum of the served data", body = ChecksumResponse))
+)]
+async fn checksum(State(state): State<AppState>) -> Json<ChecksumResponse> {
+    let (dataset, revision, checksum) = state.current_checksum();
+
+    Json(ChecksumResponse {
+        algorithm: String::from("sha256"),
+        checksum,
+        countries: dataset.len(),
+        dataset_version: revision.version,
+    })
+}
+
+fn error_response(
+    status: StatusCode,
+    code: &str,
+    message: &str,
+    details: Option<serde_json::Value>,
+) -> Response {
+    let request_id = CURRENT_REQUEST_ID
+        .try_with(|RequestId(request_id)| request_id.clone())
+        .ok();
+    let envelope = ErrorEnvelope {
+        error: ErrorBody {
+            code: code.to_string(),
+            message: message.to_string(),
+            details,
+            request_id,
+        },
+    };
+
+    (status, Json(envelope)).into_response()
+}
+
+// Reuses a sane incoming `x-request-id`, otherwise generates one, and echoes it on the response
+async fn add_hsts(State(hsts): State<HeaderValue>, request: Request, next: Next) -> Response {
+    let over_tls = request.extensions().get::<OverTls>().is_some();
+    let mut response = next.run(request).await;
+    if over_tls {
+        response
+            .headers_mut()
+            .insert(STRICT_TRANSPORT_SECURITY, hsts);
+    }
+    response
+}
+
+async fn set_security_headers(
+    State(headers): State<Arc<SecurityHeaders>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    headers.apply(response.headers_mut());
+    response
+}
+
+async fn redirect_to_https(
+    State(redirect): State<HttpsRedirect>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.extensions().get::<OverTls>().is_some() {
+        return next.run(request).await;
+    }
+    // HTTP/2 sends the host as the URI's authority instead of a `Host` header
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .or_else(|| request.uri().authority().map(Authority::as_str));
+    match host.and_then(|host| redirect.location(host, request.uri())) {
+        Some(location) => (StatusCode::MOVED_PERMANENTLY, [(LOCATION, location)]).into_response(),
+        None => error_response(
+            StatusCode::BAD_REQUEST,
+            "MISSING_HOST",
+            "The request has no valid Host header to redirect to over HTTPS",
+            None,
+        ),
+    }
+}
+
+async fn assign_request_id(mut request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty() && value.len() <= MAX_REQUEST_ID_LENGTH)
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    request
+        .extensions_mut()
+        .insert(RequestId(request_id.clone()));
+
+    let mut response = CURRENT_REQUEST_ID
+        .scope(RequestId(request_id.clone()), next.run(request))
+        .await;
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+// Keeps the backtrace of the latest panic on this thread so the panic handler can log it
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        PANIC_BACKTRACE
+            .with(|backtrace| *backtrace.borrow_mut() = Some(Backtrace::force_capture()));
+        default_hook(info);
+    }));
+}
+
+fn handle_panic(payload: Box<dyn Any + Send + 'static>) -> Response {
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| String::from("non-string panic payload"));
+    let backtrace = PANIC_BACKTRACE
+        .with(|backtrace| backtrace.borrow_mut().take())
+        .map(|backtrace| backtrace.to_string())
+        .unwrap_or_else(|| String::from("unavailable"));
+    tracing::error!(
+        request_id = CURRENT_REQUEST_ID
+            .try_with(|RequestId(request_id)| request_id.clone())
+            .unwrap_or_default(),
+        "Request handler panicked: {}\nbacktrace:\n{}",
+        message,
+        backtrace
+    );
+
+    error_response(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "INTERNAL_ERROR",
+        "Internal server error",
+        None,
+    )
+}
+
+/// Most names one `/getCountry` request may look up: enough to name every country once.
+pub const MAX_COUNTRIES_PER_QUERY: usize = 300;
+
+/// Highest `maxResults` served when `--max-results` does not set another.
+pub const DEFAULT_MAX_RESULTS: usize = 1000;
+
+/// How long a request may take before the binary logs it as slow, when
+/// `--slow-request-ms` does not set another time.
+pub const DEFAULT_SLOW_REQUEST_THRESHOLD: Duration = Duration::from_secs(1);
+
+/// Every failure a handler answers with, rendered with the shared JSON error envelope.
+#[derive(Debug)]
+pub enum ApiError {
+    // Names an admin request refers to that match no country
+    UnknownCountry(Vec<String>),
+    // `based` entries a `strict` lookup could not answer, as written
+    UnansweredEntries(Vec<String>),
+    // A country, by its canonical name, whose flag has no image
+    NoFlagImage(String),
+    // `based` is missing or names no country, e.g. `?based=` or `?based=,,`
+    EmptyQuery,
+    TooManyCountries { requested: usize, limit: usize },
+    // `based`, or one of its entries, over a length limit or holding a control character
+    InvalidInput(InputError),
+    // A query parameter that could not be used as given
+    InvalidParameter { name: &'static str, reason: String },
+    // A query parameter given more than once with different values
+    ConflictingParameter(String),
+    // An `ip:` entry or `/whoami` caller that could not be located; `input` is the entry
+    // or the caller's address
+    GeoIp { input: String, error: GeoIpError },
+    // An admin request without the admin token
+    Unauthorized,
+    // An admin request whose Basic password was not checked, as every permit to check
+    // one was taken
+    AdminAuthBusy,
+    // An admin request body that is not a country record
+    InvalidBody(JsonRejection),
+    // A record that fails validation or clashes with another country
+    InvalidRecord(RecordError),
+    // An admin edit of data served by a read-only `CountryDataSource`
+    ReadOnly(ReadOnlyError),
+    // `/admin/loglevel` on a state without a `LogLevel`
+    LogLevelUnavailable,
+    // An admin edit not made because its audit record could not be written
+    AuditFailed(AuditError),
+    // A request still running when the drain timeout passed
+    ShuttingDown,
+    // Anything unexpected; logged in full, but clients only see a generic message
+    Internal(anyhow::Error),
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::InvalidBody(rejection)
+    }
+}
+
+impl From<InputError> for ApiError {
+    fn from(error: InputError) -> Self {
+        ApiError::InvalidInput(error)
+    }
+}
+
+impl From<AuditError> for ApiError {
+    fn from(error: AuditError) -> Self {
+        ApiError::AuditFailed(error)
+    }
+}
+
+impl From<VerifierBusy> for ApiError {
+    fn from(VerifierBusy: VerifierBusy) -> Self {
+        ApiError::AdminAuthBusy
+    }
+}
+
+impl From<ReadOnlyError> for ApiError {
+    fn from(error: ReadOnlyError) -> Self {
+        ApiError::ReadOnly(error)
+    }
+}
+
+impl From<RecordError> for ApiError {
+    fn from(error: RecordError) -> Self {
+        match error {
+            RecordError::NotFound { name } => ApiError::UnknownCountry(vec![name]),
+            error => ApiError::InvalidRecord(error),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        ApiError::Internal(error)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match self {
+            ApiError::UnknownCountry(names) => {
+                let quoted: Vec<String> = names.iter().map(|name| format!("{:?}", name)).collect();
+                error_response(
+                    StatusCode::NOT_FOUND,
+                    "COUNTRY_NOT_FOUND",
+                    &format!("No country is named {}", quoted.join(", ")),
+                    Some(serde_json::json!({ "names": names })),
+                )
+            }
+            ApiError::UnansweredEntries(entries) => {
+                let quoted: Vec<String> =
+                    entries.iter().map(|entry| format!("{:?}", entry)).collect();
+                error_response(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "UNMATCHED_ENTRIES",
+                    &format!("No served country matches {}", quoted.join(", ")),
+                    Some(serde_json::json!({ "entries": entries })),
+                )
+            }
+            ApiError::NoFlagImage(name) => error_response(
+                StatusCode::NOT_FOUND,
+                "FLAG_NOT_FOUND",
+                &format!("There is no flag image for {:?}", name),
+                Some(serde_json::json!({ "name": name })),
+            ),
+            ApiError::EmptyQuery => error_response(
+                StatusCode::BAD_REQUEST,
+                "EMPTY_QUERY",
+                "`based` must name at least one country",
+                None,
+            ),
+            ApiError::TooManyCountries { requested, limit } => error_response(
+                StatusCode::BAD_REQUEST,
+                "TOO_MANY_COUNTRIES",
+                &format!(
+                    "At most {} countries can be looked up per request, {} were given",
+                    limit, requested
+                ),
+                Some(serde_json::json!({ "requested": requested, "limit": limit })),
+            ),
+            ApiError::InvalidInput(error) => {
+                let (code, details) = match &error {
+                    InputError::QueryTooLong { length } => (
+                        "QUERY_TOO_LONG",
+                        serde_json::json!({ "length": length, "limit": input::MAX_QUERY_BYTES }),
+                    ),
+                    InputError::EntryTooLong { entry, length } => (
+                        "ENTRY_TOO_LONG",
+                        serde_json::json!({
+                            "entry": entry,
+                            "length": length,
+                            "limit": input::MAX_ENTRY_CHARS,
+                        }),
+                    ),
+                    InputError::ControlCharacter { entry, character } => (
+                        "CONTROL_CHARACTER",
+                        serde_json::json!({
+                            "entry": entry,
+                            "character": format!("U+{:04X}", *character as u32),
+                        }),
+                    ),
+                };
+                error_response(
+                    StatusCode::BAD_REQUEST,
+                    code,
+                    &error.to_string(),
+                    Some(details),
+                )
+            }
+            ApiError::InvalidParameter { name, reason } => error_response(
+                StatusCode::BAD_REQUEST,
+                "INVALID_PARAMETER",
+                &format!("Invalid `{}` parameter: {}", name, reason),
+                Some(serde_json::json!({ "parameter": name })),
+            ),
+            ApiError::ConflictingParameter(name) => error_response(
+                StatusCode::BAD_REQUEST,
+                "CONFLICTING_PARAMETER",
+                &format!("`{}` was given more than once with different values", name),
+                Some(serde_json::json!({ "parameter": name })),
+            ),
+            ApiError::GeoIp { input, error } => {
+                let (status, code) = match &error {
+                    GeoIpError::NotConfigured => {
+                        (StatusCode::SERVICE_UNAVAILABLE, "GEOIP_UNAVAILABLE")
+                    }
+                    GeoIpError::InvalidAddress(_) => (StatusCode::BAD_REQUEST, "INVALID_IP"),
+                    GeoIpError::PrivateAddress(_) => {
+                        (StatusCode::UNPROCESSABLE_ENTITY, "PRIVATE_IP")
+                    }
+                    GeoIpError::NotFound(_) => (StatusCode::NOT_FOUND, "IP_NOT_LOCATED"),
+                    GeoIpError::Database(reason) => {
+                        tracing::error!("GeoIP lookup of {} failed: {}", input, reason);
+                        (StatusCode::INTERNAL_SERVER_ERROR, "GEOIP_FAILED")
+                    }
+                };
+                error_response(
+                    status,
+                    code,
+                    &error.to_string(),
+                    Some(serde_json::json!({ "input": input })),
+                )
+            }
+            ApiError::Unauthorized => {
+                let mut response = error_response(
+                    StatusCode::UNAUTHORIZED,
+                    "UNAUTHORIZED",
+                    "The admin endpoints require the admin token or username and password",
+                    None,
+                );
+                response
+                    .headers_mut()
+                    .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+                response
+            }
+            ApiError::AdminAuthBusy => {
+                let mut response = error_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "ADMIN_AUTH_BUSY",
+                    "Too many admin passwords are being checked; try again shortly",
+                    None,
+                );
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from_static("1"));
+                response
+            }
+            ApiError::InvalidBody(rejection) => error_response(
+                rejection.status(),
+                "INVALID_BODY",
+                &rejection.body_text(),
+                None,
+            ),
+            ApiError::InvalidRecord(error) => match &error {
+                RecordError::Invalid { field, reason } => error_response(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "VALIDATION_FAILED",
+                    &error.to_string(),
+                    Some(serde_json::json!({ "field": field, "reason": reason })),
+                ),
+                RecordError::Conflict { name } => error_response(
+                    StatusCode::CONFLICT,
+                    "COUNTRY_CONFLICT",
+                    &error.to_string(),
+                    Some(serde_json::json!({ "name": name })),
+                ),
+                RecordError::NotFound { name } => {
+                    ApiError::UnknownCountry(vec![name.clone()]).into_response()
+                }
+            },
+            ApiError::ReadOnly(error) => error_response(
+                StatusCode::CONFLICT,
+                "READ_ONLY_DATASET",
+                &error.to_string(),
+                None,
+            ),
+            ApiError::LogLevelUnavailable => error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "LOG_LEVEL_UNAVAILABLE",
+                "The log level cannot be changed from this server",
+                None,
+            ),
+            ApiError::AuditFailed(error) => {
+                tracing::error!(
+                    request_id = CURRENT_REQUEST_ID
+                        .try_with(|RequestId(request_id)| request_id.clone())
+                        .unwrap_or_default(),
+                    "Refused an admin change: {}",
+                    error
+                );
+                error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "AUDIT_LOG_FAILED",
+                    "The change was not made because it could not be recorded in the audit log",
+                    None,
+                )
+            }
+            ApiError::ShuttingDown => {
+                let mut response = error_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "SHUTTING_DOWN",
+                    "The server shut down before the request finished",
+                    None,
+                );
+                response
+                    .headers_mut()
+                    .insert(CONNECTION, HeaderValue::from_static("close"));
+                response
+            }
+            ApiError::Internal(error) => {
+                tracing::error!(
+                    request_id = CURRENT_REQUEST_ID
+                        .try_with(|RequestId(request_id)| request_id.clone())
+                        .unwrap_or_default(),
+                    "Request failed: {:#}",
+                    error
+                );
+                error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "INTERNAL_ERROR",
+                    "Internal server error",
+                    None,
+                )
+            }
+        }
+    }
+}
+
+fn parse_record_body(
+    body: Result<Json<CountryRecord>, JsonRejection>,
+) -> Result<CountryRecord, ApiError> {
+    let Json(record) = body?;
+    let record = record.normalized();
+    record.validate()?;
+    Ok(record)
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/countries",
+    tag = "admin",
+    summary = "Add a country",
+    request_body = CountryRecord,
+    security(("admin_token" = []), ("admin_basic" = [])),
+    responses(
+        (status = 201, description = "The country as stored, names normalized", body = CountryRecord),
+        (status = 400, description = "The body is not a country record (`INVALID_BODY`)", body = ErrorEnvelope),
+        (status = 409, description = "A name is taken, or the data is read-only (`COUNTRY_CONFLICT`, `READ_ONLY_DATASET`)", body = ErrorEnvelope),
+        (status = 422, description = "The record fails validation (`VALIDATION_FAILED`)", body = ErrorEnvelope),
+        (status = 401, description = "Missing or wrong admin token (`UNAUTHORIZED`)", body = ErrorEnvelope),
+        (status = 503, description = "Too many admin passwords being checked at once (`ADMIN_AUTH_BUSY`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+        (status = 500, description = "The audit record could not be written, so nothing changed (`AUDIT_LOG_FAILED`)", body = ErrorEnvelope),
+    )
+)]
+async fn add_country(
+    State(state): State<AppState>,
+    Extension(AdminPrincipal(principal)): Extension<AdminPrincipal>,
+    api_key: Option<Extension<KeyName>>,
+    body: Result<Json<CountryRecord>, JsonRejection>,
+) -> Result<(StatusCode, RevisionHeaders, Json<CountryRecord>), ApiError> {
+    let record = parse_record_body(body)?;
+
+    let ((), revision) = state.update_dataset(|dataset| -> Result<_, ApiError> {
+        dataset.insert(record.clone())?;
+        let audit = AuditRecord::admin(principal, AuditAction::Add, None, Some(&record));
+        Ok(state.audit(audit.with_api_key(key_name(api_key)))?)
+    })?;
+    tracing::info!("Added country {:?} through the admin API", record.name);
+
+    Ok((StatusCode::CREATED, revision.headers(), Json(record)))
+}
+
+// `name` may be the canonical name or any alias; the body replaces the whole record
+#[utoipa::path(
+    put,
+    path = "/admin/countries/{name}",
+    tag = "admin",
+    summary = "Replace a country",
+    params(("name" = String, Path, description = "Canonical name or any alias")),
+    request_body = CountryRecord,
+    security(("admin_token" = []), ("admin_basic" = [])),
+    responses(
+        (status = 200, description = "The country as stored, names normalized", body = CountryRecord),
+        (status = 400, description = "The body is not a country record (`INVALID_BODY`)", body = ErrorEnvelope),
+        (status = 404, description = "No country is named `name` (`COUNTRY_NOT_FOUND`)", body = ErrorEnvelope),
+        (status = 409, description = "A name is taken, or the data is read-only (`COUNTRY_CONFLICT`, `READ_ONLY_DATASET`)", body = ErrorEnvelope),
+        (status = 422, description = "The record fails validation (`VALIDATION_FAILED`)", body = ErrorEnvelope),
+        (status = 401, description = "Missing or wrong admin token (`UNAUTHORIZED`)", body = ErrorEnvelope),
+        (status = 503, description = "Too many admin passwords being checked at once (`ADMIN_AUTH_BUSY`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+        (status = 500, description = "The audit record could not be written, so nothing changed (`AUDIT_LOG_FAILED`)", body = ErrorEnvelope),
+    )
+)]
+async fn update_country(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Extension(AdminPrincipal(principal)): Extension<AdminPrincipal>,
+    api_key: Option<Extension<KeyName>>,
+    body: Result<Json<CountryRecord>, JsonRejection>,
+) -> Result<(RevisionHeaders, Json<CountryRecord>), ApiError> {
+    let record = parse_record_body(body)?;
+
+    let (previous, revision) = state.update_dataset(|dataset| -> Result<_, ApiError> {
+        let previous = dataset.replace(&normalize(&name), record.clone())?;
+        let audit = AuditRecord::admin(
+            principal,
+            AuditAction::Update,
+            Some(&previous),
+            Some(&record),
+        );
+        state.audit(audit.with_api_key(key_name(api_key)))?;
+        Ok(previous)
+    })?;
+    tracing::info!(
+        "Replaced country {:?} with {:?} through the admin API",
+        previous.name,
+        record.name
+    );
+
+    Ok((revision.headers(), Json(record)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/countries/{name}",
+    tag = "admin",
+    summary = "Delete a country",
+    params(("name" = String, Path, description = "Canonical name or any alias")),
+    security(("admin_token" = []), ("admin_basic" = [])),
+    responses(
+        (status = 200, description = "The country that was deleted", body = CountryRecord),
+        (status = 404, description = "No country is named `name` (`COUNTRY_NOT_FOUND`)", body = ErrorEnvelope),
+        (status = 409, description = "The data is read-only (`READ_ONLY_DATASET`)", body = ErrorEnvelope),
+        (status = 401, description = "Missing or wrong admin token (`UNAUTHORIZED`)", body = ErrorEnvelope),
+        (status = 503, description = "Too many admin passwords being checked at once (`ADMIN_AUTH_BUSY`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+        (status = 500, description = "The audit record could not be written, so nothing changed (`AUDIT_LOG_FAILED`)", body = ErrorEnvelope),
+    )
+)]
+async fn delete_country(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Extension(AdminPrincipal(principal)): Extension<AdminPrincipal>,
+    api_key: Option<Extension<KeyName>>,
+) -> Result<(RevisionHeaders, Json<CountryRecord>), ApiError> {
+    let name = normalize(&name).into_owned();
+
+    let (record, revision) = state.update_dataset(|dataset| -> Result<_, ApiError> {
+        let record = dataset
+            .remove(&name)
+            .ok_or(RecordError::NotFound { name })?;
+        let audit = AuditRecord::admin(principal, AuditAction::Delete, Some(&record), None);
+        state.audit(audit.with_api_key(key_name(api_key)))?;
+        Ok(record)
+    })?;
+    tracing::info!("Deleted country {:?} through the admin API", record.name);
+
+    Ok((revision.headers(), Json(record)))
+}
+
+// The name of the API key an admin request sent, for its audit record
+fn key_name(api_key: Option<Extension<KeyName>>) -> Option<String> {
+    api_key.map(|Extension(KeyName(name))| name)
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/loglevel",
+    tag = "admin",
+    summary = "Read the log level",
+    security(("admin_token" = []), ("admin_basic" = [])),
+    responses(
+        (status = 200, description = "The level of targets the `--log` filter does not name", body = LogLevelResponse),
+        (status = 401, description = "Missing or wrong admin token (`UNAUTHORIZED`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+        (status = 503, description = "Logging was not set up by the server, or too many admin passwords are being checked at once (`LOG_LEVEL_UNAVAILABLE`, `ADMIN_AUTH_BUSY`)", body = ErrorEnvelope),
+    )
+)]
+async fn get_log_level(State(state): State<AppState>) -> Result<Json<LogLevelResponse>, ApiError> {
+    let log_level = state.log_level.ok_or(ApiError::LogLevelUnavailable)?;
+    Ok(Json(LogLevelResponse {
+        level: log_level.current().to_string(),
+    }))
+}
+
+// Targets the `--log` filter names keep their own levels
+#[utoipa::path(
+    put,
+    path = "/admin/loglevel",
+    tag = "admin",
+    summary = "Change the log level",
+    request_body = LogLevelRequest,
+    security(("admin_token" = []), ("admin_basic" = [])),
+    responses(
+        (status = 200, description = "The level before and after", body = LogLevelChange),
+        (status = 400, description = "The body is not JSON (`INVALID_BODY`)", body = ErrorEnvelope),
+        (status = 422, description = "`level` is not error, warn, info, debug or trace (`INVALID_BODY`)", body = ErrorEnvelope),
+        (status = 401, description = "Missing or wrong admin token (`UNAUTHORIZED`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+        (status = 503, description = "Logging was not set up by the server, or too many admin passwords are being checked at once (`LOG_LEVEL_UNAVAILABLE`, `ADMIN_AUTH_BUSY`)", body = ErrorEnvelope),
+    )
+)]
+async fn set_log_level(
+    State(state): State<AppState>,
+    body: Result<Json<LogLevelRequest>, JsonRejection>,
+) -> Result<Json<LogLevelChange>, ApiError> {
+    let log_level = state.log_level.ok_or(ApiError::LogLevelUnavailable)?;
+    let Json(LogLevelRequest { level }) = body?;
+    let previous = log_level.set(level).map_err(|error| {
+        ApiError::Internal(anyhow::anyhow!("Failed to set the log level: {}", error))
+    })?;
+    let level = log_level.current();
+    tracing::warn!(
+        "Log level changed from {} to {} through the admin API",
+        previous,
+        level
+    );
+    Ok(Json(LogLevelChange {
+        previous: previous.to_string(),
+        level: level.to_string(),
+    }))
+}
+
+// Everything currently served, admin edits included, as `--data-file` JSON, YAML or CSV
+#[utoipa::path(
+    get,
+    path = "/admin/export",
+    tag = "admin",
+    summary = "Export the served data",
+    params(("format" = Option<String>, Query, description = "`json` (the default), as read by `--data-file`, `yaml` or `csv`")),
+    security(("admin_token" = []), ("admin_basic" = [])),
+    responses(
+        (status = 200, description = "Every country currently served", content(
+            (Vec<CountryRecord> = "application/json"),
+            (String = "application/yaml"),
+            (String = "text/csv"),
+        )),
+        (status = 400, description = "Unknown `format` (`INVALID_PARAMETER`)", body = ErrorEnvelope),
+        (status = 401, description = "Missing or wrong admin token (`UNAUTHORIZED`)", body = ErrorEnvelope),
+        (status = 503, description = "Too many admin passwords being checked at once (`ADMIN_AUTH_BUSY`)", body = ErrorEnvelope),
+        (status = 429, description = "Over `--rate-limit` or an API key's limits (`RATE_LIMITED`, `QUOTA_EXHAUSTED`)", body = ErrorEnvelope),
+    )
+)]
+async fn export_dataset(
+    State(state): State<AppState>,
+    ApiQuery(params): ApiQuery<ExportQuery>,
+) -> Result<Response, ApiError> {
+    let format = params.format.unwrap_or_else(|| String::from("json"));
+    let (dataset, revision) = state.current();
+    let headers = revision.headers();
+
+    let (content_type, body) = match format.as_str() {
+        "json" => ("application/json", export::to_json(&dataset)),
+        "yaml" => ("application/yaml", export::to_yaml(&dataset)),
+        "csv" => ("text/csv; charset=utf-8", export::to_csv(&dataset)),
+        _ => {
+            return Err(ApiError::InvalidParameter {
+                name: "format",
+                reason: format!("{:?} is not `json`, `yaml` or `csv`", format),
+            })
+        }
+    };
+
+    Ok((
+        [(axum::http::header::CONTENT_TYPE, content_type)],
+        headers,
+        body,
+    )
+        .into_response())
+}
+
+// Paths are reported as requested, prefix included when the routes are nested
+async fn not_found(OriginalUri(uri): OriginalUri) -> Response {
+    error_response(
+        StatusCode::NOT_FOUND,
+        "NOT_FOUND",
+        "No route matches the requested path",
+        Some(serde_json::json!({ "path": uri.path() })),
+    )
+}
+
+// Axum adds the `Allow` header listing the route's methods to whatever this returns
+async fn method_not_allowed(method: Method, OriginalUri(uri): OriginalUri) -> Response {
+    error_response(
+        StatusCode::METHOD_NOT_ALLOWED,
+        "METHOD_NOT_ALLOWED",
+        "The requested method is not supported for this path",
+        Some(serde_json::json!({ "method": method.as_str(), "path": uri.path() })),
+    )
+}
+
+#[cfg(test)]
+async fn panic_for_test() -> Json<CountryResponse<'static>> {
+    panic!("panic triggered by test route")
+}
+
+#[cfg(test)]
+async fn slow_for_test() -> StatusCode {
+    tokio::time::sleep(Duration::from_secs(60)).await;
+    StatusCode::NO_CONTENT
+}
+
+#[cfg(test)]
+async fn delayed_for_test() -> (Extension<ResultCounts>, StatusCode) {
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let counts = ResultCounts {
+        results: 2,
+        unmatched: 1,
+    };
+    (Extension(counts), StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+async fn internal_error_for_test() -> Result<Json<CountryResponse<'static>>, ApiError> {
+    Err(anyhow::anyhow!("connection to db.internal:5432 refused").into())
+}
+
+// Prints every integrity problem in the source's data; returns whether there were none
+pub fn check_source(source: &dyn DatasetLoader) -> bool {
+    let records = match source.records() {
+        Ok(records) => records,
+        Err(error) => {
+            println!("{}", error);
+            return false;
+        }
+    };
+
+    let errors = validation::validate_records(&records);
+    for error in &errors {
+        println!("{}", error);
+    }
+    println!(
+        "Checked {} countries from {}: {} problem(s) found",
+        records.len(),
+        source.describe(),
+        errors.len()
+    );
+
+    errors.is_empty()
+}
+
+/// The API router over the built-in dataset, or the CSV file `COUNTRY_DATA_PATH` points at,
+/// with the default [`AppConfig`]: no admin endpoints, response cache, CORS or rate limit.
+///
+/// Panics if the `COUNTRY_DATA_PATH` file cannot be loaded.
+pub fn create_app() -> Router {
+    let dataset = load_builtin_country_data().unwrap_or_else(|error| panic!("{}", error));
+    create_app_with(AppConfig::default(), Arc::new(MutableSource::new(dataset)))
+}
+
+/// The API router over `source`, configured by `config`.
+///
+/// ```
+/// use std::sync::Arc;
+/// use rusty_currency::{create_app_with, source::MutableSource, AppConfig, Dataset};
+///
+/// let config = AppConfig {
+///     admin_token: Some(String::from("s3cret")),
+///     response_cache: 1000,
+///     ..AppConfig::default()
+/// };
+/// let app = create_app_with(config, Arc::new(MutableSource::new(Dataset::builtin())));
+/// ```
+pub fn create_app_with(config: AppConfig, source: Arc<dyn CountryDataSource>) -> Router {
+    let state = AppState::from_source(source).with_response_cache(config.response_cache);
+    create_app_with_state(state, &config)
+}
+
+#[cfg(test)]
+pub(crate) fn create_app_with_dataset(dataset: Dataset) -> Router {
+    create_app_with(
+        AppConfig::default(),
+        Arc::new(crate::source::StaticSource::new(dataset)),
+    )
+}
+
+/// The API router over an existing state, for callers that keep the state to reload it.
+///
+/// The state brings its own response cache, so `config.response_cache` is not used here.
+pub fn create_app_with_state(state: AppState, config: &AppConfig) -> Router {
+    let mut api = api_routes();
+    #[cfg(feature = "graphql")]
+    if config.graphql_playground {
+        api = api.merge(crate::graphql::playground_routes());
+    }
+    let admin = AdminCredentials::new(config.admin_token.clone(), config.admin_basic.clone());
+    if !admin.is_empty() {
+        api = api.nest("/admin", admin_routes(admin));
+    }
+    #[cfg(test)]
+    let mut api = api
+        .route("/__test/panic", get(panic_for_test))
+        .route("/__test/internal", get(internal_error_for_test))
+        .route("/__test/slow", get(slow_for_test))
+        .route("/__test/delayed", get(delayed_for_test));
+    // Health, version, metrics and the docs stay reachable however busy the API is
+    if let Some(per_second) = config.rate_limit {
+        let limiter = Arc::new(RateLimiter::new(per_second));
+        api = api.route_layer(middleware::from_fn_with_state(limiter, enforce_rate_limit));
+    }
+    // Outside the shared limit, so requests one client is refused do not use it up
+    if let Some(keys) = &config.api_keys {
+        let limiter = Arc::new(ApiKeyLimiter::new(keys.clone(), &state));
+        api = api.route_layer(middleware::from_fn_with_state(limiter, enforce_api_keys));
+    }
+    if let Some(timeout) = config.request_timeout {
+        api = api.route_layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(handle_layer_error))
+                .timeout(timeout),
+        );
+    }
+    // One limit shared by every route, with nothing queued: a request over it is refused
+    if let Some(limit) = config.max_concurrent_requests {
+        api = api.route_layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(handle_layer_error))
+                .load_shed()
+                .layer(GlobalConcurrencyLimitLayer::new(limit.get())),
+        );
+    }
+
+    let mut status = status_routes();
+    if config.docs {
+        status = status.merge(docs::routes());
+    }
+
+    let latency = (state.metrics.clone(), config.slow_request_threshold);
+    let access_log = config
+        .access_log
+        .as_ref()
+        .map(|access_log| Arc::new(AccessLog::new(access_log.clone(), &state)));
+    let router = with_fallbacks(status.merge(api)).with_state(state);
+    let router = if config.cors_origins.is_empty() {
+        router
+    } else {
+        router.layer(cors_layer(&config.cors_origins))
+    };
+
+    let router = router
+        .layer(middleware::from_fn_with_state(latency, record_latency))
+        .layer(CatchPanicLayer::custom(handle_panic));
+    // Inside the request ID, so each line has it, and outside the panic handler, so
+    // panics are logged as the 500 they are answered with
+    let router = match access_log {
+        Some(access_log) => router.layer(middleware::from_fn_with_state(access_log, log_access)),
+        None => router,
+    };
+    let router = match &config.hsts {
+        Some(hsts) => router.layer(middleware::from_fn_with_state(
+            hsts.header_value(),
+            add_hsts,
+        )),
+        None => router,
+    };
+    // Ahead of everything but the request ID, so nothing else is served without TLS
+    let router = match config.https_redirect {
+        Some(redirect) => router.layer(middleware::from_fn_with_state(redirect, redirect_to_https)),
+        None => router,
+    };
+    // Outside everything else, so redirects, errors and 404s have them too
+    let security_headers = Arc::new(config.security_headers.clone());
+    router
+        .layer(middleware::from_fn_with_state(
+            security_headers,
+            set_security_headers,
+        ))
+        .layer(middleware::from_fn(assign_request_id))
+}
+
+/// The public routes and JSON 404/405 fallbacks alone, without state, middleware or
+/// admin endpoints, for mounting the API inside another axum app. `/whoami` reads the
+/// caller's address, so it only works when the app is served with
+/// `into_make_service_with_connect_info::<SocketAddr>()`:
+///
+/// ```
+/// use axum::Router;
+/// use rusty_currency::{routes, AppState, Dataset};
+///
+/// let app: Router = Router::new()
+///     .nest("/geo", routes())
+///     .with_state(AppState::new(Dataset::builtin()));
+/// ```
+pub fn routes() -> Router<AppState> {
+    with_fallbacks(status_routes().merge(api_routes()))
+}
+
+fn status_routes() -> Router<AppState> {
+    Router::new()
+        .route("/openapi.json", get(openapi_json))
+        .route("/healthz", get(healthz))
+        .route("/version", get(version))
+        .route("/checksum", get(checksum))
+        .route("/metrics", get(metrics))
+}
+
+fn api_routes() -> Router<AppState> {
+    let router = Router::new()
+        .route("/getCountry", get(get_country))
+        .route("/countries", get(list_countries))
+        .route("/codes", get(codes))
+        .route("/validate", get(validate_names))
+        .route("/byCurrency", get(by_currency))
+        .route("/byCallingCode", get(by_calling_code))
+        .route("/byCoordinates", get(by_coordinates))
+        .route("/byTld", get(by_tld))
+        .route("/byLanguage", get(by_language))
+        .route("/byTimezone", get(by_timezone))
+        .route("/stats", get(stats))
+        .route("/flag/:file", get(flag))
+        .route("/resolve", post(resolve_batch))
+        .route("/whoami", get(whoami))
+        .route("/events", get(events));
+    #[cfg(feature = "graphql")]
+    let router = router.merge(crate::graphql::routes());
+    router
+}
+
+// Nested under /admin, so `credentials` guard that subtree and nothing else
+fn admin_routes(credentials: AdminCredentials) -> Router<AppState> {
+    let credentials = Arc::new(credentials);
+    Router::new()
+        .route("/countries", post(add_country))
+        .route("/export", get(export_dataset))
+        .route("/loglevel", get(get_log_level).put(set_log_level))
+        .route(
+            "/countries/:name",
+            put(update_country).delete(delete_country),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            credentials,
+            require_admin_credentials,
+        ))
+}
+
+// Lets the request through only if it bears credentials the admin endpoints accept,
+// with who they authenticate as for the audit log
+async fn require_admin_credentials(
+    State(credentials): State<Arc<AdminCredentials>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    match Arc::clone(&credentials).check(request.headers()).await {
+        Ok(Some(principal)) => {
+            request.extensions_mut().insert(principal);
+            return next.run(request).await;
+        }
+        Ok(None) => {}
+        Err(busy) => return ApiError::from(busy).into_response(),
+    }
+    let mut response = ApiError::Unauthorized.into_response();
+    let headers = response.headers_mut();
+    headers.remove(WWW_AUTHENTICATE);
+    for challenge in credentials.challenges() {
+        headers.append(WWW_AUTHENTICATE, challenge);
+    }
+    response
+}
+
+fn with_fallbacks(router: Router<AppState>) -> Router<AppState> {
+    router
+        .fallback(not_found)
+        .method_not_allowed_fallback(method_not_allowed)
+}
+
+// `/resolve` batches, and GraphQL queries, are POSTed as JSON
+const CORS_METHODS: [Method; 2] = [Method::GET, Method::POST];
+
+// Browsers may read the API from these origins; `*` allows any
+fn cors_layer(origins: &[HeaderValue]) -> CorsLayer {
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(origins.iter().cloned())
+    };
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(CORS_METHODS)
+        .allow_headers([CONTENT_TYPE])
+        .expose_headers([
+            HeaderName::from_static(REQUEST_ID_HEADER),
+            HeaderName::from_static(revision::DATASET_VERSION_HEADER),
+            HeaderName::from_static(revision::DATASET_MODIFIED_HEADER),
+        ])
+}
+
+// Records how long the request took under its route template, and warns when that is
+// over the slow-request threshold
+async fn record_latency(
+    State((metrics, slow_threshold)): State<(Arc<Metrics>, Option<Duration>)>,
+    matched: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = matched.map_or_else(
+        || String::from(UNMATCHED_ROUTE),
+        |matched| matched.as_str().to_string(),
+    );
+    let query = request.uri().query().map(str::to_string);
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let elapsed = started.elapsed();
+    metrics.record_latency(&route, elapsed);
+    if slow_threshold.is_some_and(|threshold| elapsed > threshold) {
+        let counts = response.extensions().get::<ResultCounts>();
+        tracing::warn!(
+            route = %route,
+            query = ?normalized_query(query.as_deref().unwrap_or_default()),
+            status = response.status().as_u16(),
+            latency_ms = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+            results = counts.map(|counts| counts.results),
+            unmatched = counts.map(|counts| counts.unmatched),
+            request_id = CURRENT_REQUEST_ID
+                .try_with(|RequestId(request_id)| request_id.clone())
+                .unwrap_or_default(),
+            "Slow request"
+        );
+    }
+    response
+}
+
+// `query` with its parameters sorted by name and the entries of `based` normalized, so
+// the same lookup reads the same however it was written; cut to `MAX_LOGGED_QUERY_LENGTH`
+fn normalized_query(query: &str) -> String {
+    let normalized = match serde_urlencoded::from_str::<Vec<(String, String)>>(query) {
+        Ok(mut parameters) => {
+            for (name, value) in &mut parameters {
+                if name == "based" {
+                    *value = value
+                        .split(',')
+                        .map(normalize)
+                        .filter(|entry| !entry.is_empty())
+                        .collect::<Vec<_>>()
+                        .join(",");
+                }
+            }
+            parameters.sort_by(|(first, _), (second, _)| first.cmp(second));
+            parameters
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join("&")
+        }
+        Err(_) => query.to_string(),
+    };
+    normalized.chars().take(MAX_LOGGED_QUERY_LENGTH).collect()
+}
+
+// Logs the request once its response is ready, unless `access_log` leaves it out; the
+// time taken does not include streaming the body
+pub(crate) async fn log_access(
+    State(access_log): State<Arc<AccessLog>>,
+    client: Option<ClientIp>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let started = Instant::now();
+    let response = next.run(request).await;
+    let elapsed = started.elapsed();
+    let status = response.status();
+    if access_log.should_log(&path, status, elapsed) {
+        tracing::info!(
+            target: access_log::TARGET,
+            method = %method,
+            path = %path,
+            status = status.as_u16(),
+            latency_ms = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+            client = %client.map_or_else(|| String::from("unknown"), |ClientIp(address)| address.to_string()),
+            request_id = CURRENT_REQUEST_ID
+                .try_with(|RequestId(request_id)| request_id.clone())
+                .unwrap_or_default(),
+            "Request served"
+        );
+    }
+    response
+}
+
+async fn enforce_rate_limit(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if limiter.try_acquire() {
+        return next.run(request).await;
+    }
+    let mut response = error_response(
+        StatusCode::TOO_MANY_REQUESTS,
+        "RATE_LIMITED",
+        "Too many requests; try again shortly",
+        None,
+    );
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from_static("1"));
+    response
+}
+
+async fn enforce_api_keys(
+    State(limiter): State<Arc<ApiKeyLimiter>>,
+    client: Option<ClientIp>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let key = match request.headers().get(api_keys::API_KEY_HEADER) {
+        Some(key) => match key.to_str() {
+            Ok(key) => Some(key),
+            Err(_) => return invalid_api_key(),
+        },
+        None => None,
+    };
+    let admission = limiter.admit(key, client.map(|ClientIp(address)| address));
+    let name = key
+        .and_then(|key| limiter.key(key))
+        .map(|key| KeyName(key.name.clone()));
+    match admission {
+        Admission::Allowed(allowance) => {
+            let mut request = request;
+            if let Some(name) = name {
+                request.extensions_mut().insert(name);
+            }
+            let mut response = next.run(request).await;
+            insert_rate_limit_headers(response.headers_mut(), allowance);
+            response
+        }
+        Admission::Unlimited => next.run(request).await,
+        Admission::RateLimited(allowance) => {
+            let mut response = error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                "RATE_LIMITED",
+                "Too many requests for this client; try again once the window resets",
+                None,
+            );
+            insert_rate_limit_headers(response.headers_mut(), allowance);
+            response.headers_mut().insert(
+                RETRY_AFTER,
+                HeaderValue::from(whole_seconds(allowance.reset)),
+            );
+            response
+        }
+        Admission::QuotaExhausted { reset } => {
+            let mut response = error_response(
+                StatusCode::TOO_MANY_REQUESTS,
+                "QUOTA_EXHAUSTED",
+                "The daily request quota is used up; it starts over at midnight UTC",
+                None,
+            );
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(whole_seconds(reset)));
+            response
+        }
+        Admission::UnknownKey => invalid_api_key(),
+    }
+}
+
+fn invalid_api_key() -> Response {
+    error_response(
+        StatusCode::UNAUTHORIZED,
+        "INVALID_API_KEY",
+        "The X-API-Key header is not a known API key",
+        None,
+    )
+}
+
+fn insert_rate_limit_headers(headers: &mut HeaderMap, allowance: Allowance) {
+    headers.insert("x-ratelimit-limit", HeaderValue::from(allowance.limit));
+    headers.insert(
+        "x-ratelimit-remaining",
+        HeaderValue::from(allowance.remaining),
+    );
+    headers.insert(
+        "x-ratelimit-reset",
+        HeaderValue::from(whole_seconds(allowance.reset)),
+    );
+}
+
+// `duration` rounded up to whole seconds, so a client that waits that long is let in
+fn whole_seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+// Renders what the timeout and load-shedding layers fail with in the error envelope
+async fn handle_layer_error(error: BoxError) -> Response {
+    if error.is::<Elapsed>() {
+        return error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "TIMEOUT",
+            "The request took too long to answer",
+            None,
+        );
+    }
+    if error.is::<Overloaded>() {
+        let mut response = error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "OVERLOADED",
+            "Too many requests in progress; try again shortly",
+            None,
+        );
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from_static("1"));
+        return response;
+    }
+    tracing::error!("Request failed in a middleware layer: {}", error);
+    error_response(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "INTERNAL_ERROR",
+        "Internal server error",
+        None,
+    )
+}
+
+// IPv6 sockets are bound v6-only so `[::]` can sit next to `0.0.0.0` on the same port
+fn bind_listener(address: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+    if address.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    TcpListener::from_std(socket.into())
+}
+
+pub fn bind_listeners(addresses: &[SocketAddr]) -> io::Result<Vec<TcpListener>> {
+    addresses
+        .iter()
+        .map(|&address| bind_listener(address).map_err(|error| bind_error(address, error)))
+        .collect()
+}
+
+// `bind_listeners`, but an address in use is retried with backoff until `retry_for` has
+// passed, as while the instance being replaced drains its connections
+pub async fn bind_listeners_with_retry(
+    addresses: &[SocketAddr],
+    retry_for: Duration,
+) -> io::Result<Vec<TcpListener>> {
+    let deadline = tokio::time::Instant::now() + retry_for;
+    let mut listeners = Vec::with_capacity(addresses.len());
+    for &address in addresses {
+        let mut backoff = FIRST_BIND_BACKOFF;
+        let listener = loop {
+            let now = tokio::time::Instant::now();
+            match bind_listener(address) {
+                Ok(listener) => break listener,
+                Err(error) if error.kind() == io::ErrorKind::AddrInUse && now < deadline => {
+                    let wait = backoff.min(deadline - now);
+                    tracing::warn!("{} is in use, retrying in {:?}", address, wait);
+                    tokio::time::sleep(wait).await;
+                    backoff = (backoff * 2).min(MAX_BIND_BACKOFF);
+                }
+                Err(error) => return Err(bind_error(address, error)),
+            }
+        };
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+fn bind_error(address: SocketAddr, error: io::Error) -> io::Error {
+    let mut message = format!("Failed to bind to {}: {}", address, error);
+    if let Some(hint) = bind_error_hint(address, error.kind()) {
+        message.push_str(". ");
+        message.push_str(&hint);
+    }
+    io::Error::new(error.kind(), message)
+}
+
+// What to do about failing to bind `address`, for the errors with a usual cause
+pub(crate) fn bind_error_hint(address: SocketAddr, kind: io::ErrorKind) -> Option<String> {
+    let (ip, port) = (address.ip(), address.port());
+    let listen = |port: u16| SocketAddr::new(ip, port);
+    match kind {
+        io::ErrorKind::AddrInUse => Some(format!(
+            "Another process, perhaps an earlier instance, is listening on port {}; stop \
+             it, serve on another port with `--listen {}`, or pass `--bind-retry <SECONDS>` \
+             to wait for it to exit",
+            port,
+            listen(port.checked_add(1).unwrap_or(port - 1)),
+        )),
+        io::ErrorKind::PermissionDenied if port != 0 && port < PRIVILEGED_PORTS_END => {
+            Some(format!(
+                "Ports below {} need privileges; serve on a higher one with `--listen {}`, \
+                 or let the binary bind them with `sudo setcap \
+                 cap_net_bind_service=+ep <path to rusty_currency>`",
+                PRIVILEGED_PORTS_END,
+                listen(8080),
+            ))
+        }
+        io::ErrorKind::AddrNotAvailable => Some(format!(
+            "{} is not an address of this machine; listen on one of its addresses, or on \
+             0.0.0.0 or [::] for all of them",
+            ip
+        )),
+        _ => None,
+    }
+}
+
+// Listeners handed over by systemd socket activation (LISTEN_FDS); empty when not activated
+pub fn take_inherited_listeners(listenfd: &mut ListenFd) -> io::Result<Vec<std::net::TcpListener>> {
+    let mut listeners = Vec::new();
+    for index in 0..listenfd.len() {
+        if let Some(listener) = listenfd.take_tcp_listener(index)? {
+            listeners.push(listener);
+        }
+    }
+    Ok(listeners)
+}
+
+pub fn adopt_listeners(listeners: Vec<std::net::TcpListener>) -> io::Result<Vec<TcpListener>> {
+    listeners
+        .into_iter()
+        .map(|listener| {
+            listener.set_nonblocking(true)?;
+            TcpListener::from_std(listener)
+        })
+        .collect()
+}
+
+// Serves the same router on every listener until one of them fails
+pub async fn run_server(listeners: Vec<TcpListener>, app: Router) -> io::Result<()> {
+    let (_stop, stopping) = watch::channel(false);
+    let mut servers = spawn_http_servers(
+        listeners,
+        app,
+        stopping,
+        Arc::default(),
+        &ServeOptions::default(),
+    )?;
+    join_servers(&mut servers).await
+}
+
+/// Serves `app` as [`run_server`] does, over TLS if `options` say so, until `shutdown`
+/// completes. Then stops accepting connections and waits up to the drain timeout for
+/// the requests in flight. Fails only if a listener does before `shutdown`.
+pub async fn run_server_until(
+    listeners: Vec<TcpListener>,
+    app: Router,
+    shutdown: impl Future<Output = ()>,
+    options: ServeOptions,
+) -> io::Result<DrainOutcome> {
+    let (stop, stopping) = watch::channel(false);
+    let in_flight = Arc::new(InFlight::default());
+    let servers = spawn_http_servers(listeners, app, stopping, in_flight.clone(), &options)?;
+    drain(servers, stop, shutdown, &in_flight, options.drain_timeout).await
+}
+
+// Serves `app` as `run_server` does, plus the gRPC service over `state` on
+// `grpc_listener`, until any of them fails
+#[cfg(feature = "grpc")]
+pub async fn run_server_with_grpc(
+    listeners: Vec<TcpListener>,
+    app: Router,
+    grpc_listener: TcpListener,
+    state: AppState,
+) -> io::Result<()> {
+    let (_stop, stopping) = watch::channel(false);
+    let mut servers = spawn_http_servers(
+        listeners,
+        app,
+        stopping.clone(),
+        Arc::default(),
+        &ServeOptions::default(),
+    )?;
+    spawn_grpc_server(&mut servers, grpc_listener, state, stopping)?;
+    join_servers(&mut servers).await
+}
+
+/// [`run_server_until`] with the gRPC service over `state` on `grpc_listener` as well,
+/// which stops with the HTTP listeners. Only HTTP requests count as in flight, and gRPC
+/// is served without TLS.
+#[cfg(feature = "grpc")]
+pub async fn run_server_with_grpc_until(
+    listeners: Vec<TcpListener>,
+    app: Router,
+    grpc_listener: TcpListener,
+    state: AppState,
+    shutdown: impl Future<Output = ()>,
+    options: ServeOptions,
+) -> io::Result<DrainOutcome> {
+    let (stop, stopping) = watch::channel(false);
+    let in_flight = Arc::new(InFlight::default());
+    let mut servers = spawn_http_servers(
+        listeners,
+        app,
+        stopping.clone(),
+        in_flight.clone(),
+        &options,
+    )?;
+    spawn_grpc_server(&mut servers, grpc_listener, state, stopping)?;
+    drain(servers, stop, shutdown, &in_flight, options.drain_timeout).await
+}
+
+#[cfg(feature = "grpc")]
+fn spawn_grpc_server(
+    servers: &mut JoinSet<io::Result<()>>,
+    grpc_listener: TcpListener,
+    state: AppState,
+    stopping: watch::Receiver<bool>,
+) -> io::Result<()> {
+    tracing::info!(
+        "gRPC server running on http://{}",
+        grpc_listener.local_addr()?
+    );
+    servers.spawn(crate::grpc::serve_until(
+        grpc_listener,
+        state,
+        shutdown::stopped(stopping),
+    ));
+    Ok(())
+}
+
+fn spawn_http_servers(
+    listeners: Vec<TcpListener>,
+    app: Router,
+    stopping: watch::Receiver<bool>,
+    in_flight: Arc<InFlight>,
+    options: &ServeOptions,
+) -> io::Result<JoinSet<io::Result<()>>> {
+    let listen_addresses = listeners
+        .iter()
+        .map(TcpListener::local_addr)
+        .collect::<io::Result<Vec<_>>>()?;
+    let app = app
+        .layer(Extension(ListenAddresses(listen_addresses.clone())))
+        .layer(Extension(Stopping(stopping.clone())))
+        .layer(middleware::from_fn_with_state(in_flight, count_in_flight));
+    let acceptor = options
+        .tls
+        .as_ref()
+        .map(|tls| TlsAcceptor::from(tls.server_config()));
+
+    let mut servers = JoinSet::new();
+    for (listener, address) in listeners.into_iter().zip(listen_addresses) {
+        let app = app.clone();
+        let stopping = stopping.clone();
+        let acceptor = acceptor
+            .clone()
+            .filter(|_| !options.plain_addresses.contains(&address));
+        match acceptor {
+            Some(acceptor) => {
+                tracing::info!("Server running on https://{}", address);
+                servers.spawn(serve_tls(listener, app, acceptor, stopping));
+            }
+            None => {
+                tracing::info!("Server running on http://{}", address);
+                servers.spawn(async move {
+                    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+                    axum::serve(listener, app)
+                        .with_graceful_shutdown(shutdown::stopped(stopping))
+                        .await
+                });
+            }
+        }
+    }
+    Ok(servers)
+}
+
+// What `axum::serve` does, with a TLS handshake first on every connection. Stops
+// accepting once `stopping` turns true, then waits for the open connections to close.
+async fn serve_tls(
+    listener: TcpListener,
+    app: Router,
+    acceptor: TlsAcceptor,
+    stopping: watch::Receiver<bool>,
+) -> io::Result<()> {
+    let connections = GracefulShutdown::new();
+    let stopped = shutdown::stopped(stopping);
+    tokio::pin!(stopped);
+    loop {
+        let (stream, remote) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(error) => {
+                    tracing::warn!("Failed to accept a connection: {}", error);
+                    if !matches!(
+                        error.kind(),
+                        io::ErrorKind::ConnectionRefused
+                            | io::ErrorKind::ConnectionAborted
+                            | io::ErrorKind::ConnectionReset
+                    ) {
+                        // Out of file descriptors, say; give some a chance to close
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                    continue;
+                }
+            },
+            () = &mut stopped => break,
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        let watcher = connections.watcher();
+        tokio::spawn(async move {
+            let stream =
+                match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(error)) => {
+                        tracing::debug!("TLS handshake with {} failed: {}", remote, error);
+                        return;
+                    }
+                    Err(_) => {
+                        tracing::debug!("TLS handshake with {} timed out", remote);
+                        return;
+                    }
+                };
+            let service = TowerToHyperService::new(tower::service_fn(
+                move |mut request: Request<hyper::body::Incoming>| {
+                    request.extensions_mut().insert(ConnectInfo(remote));
+                    request.extensions_mut().insert(OverTls);
+                    app.clone().call(request)
+                },
+            ));
+            let builder = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+            if let Err(error) = watcher.watch(connection.into_owned()).await {
+                tracing::debug!("Connection from {} failed: {}", remote, error);
+            }
+        });
+    }
+    connections.shutdown().await;
+    Ok(())
+}
+
+// Runs `servers` until `shutdown`, then tells them to stop through `stop` and waits for
+// them to close their connections for at most `timeout`, aborting them after it
+async fn drain(
+    mut servers: JoinSet<io::Result<()>>,
+    stop: watch::Sender<bool>,
+    shutdown: impl Future<Output = ()>,
+    in_flight: &InFlight,
+    timeout: Duration,
+) -> io::Result<DrainOutcome> {
+    tokio::select! {
+        result = join_servers(&mut servers) => return result.map(|()| DrainOutcome::Drained),
+        () = shutdown => {}
+    }
+    stop.send_replace(true);
+    tracing::info!(
+        "Shutting down: no longer accepting connections, waiting up to {}s for {} request(s) in flight",
+        timeout.as_secs_f64(),
+        in_flight.count()
+    );
+
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+    let mut reports = tokio::time::interval_at(
+        tokio::time::Instant::now() + shutdown::DRAIN_REPORT_INTERVAL,
+        shutdown::DRAIN_REPORT_INTERVAL,
+    );
+    loop {
+        tokio::select! {
+            result = join_servers(&mut servers) => {
+                result?;
+                tracing::info!("Every request finished, shutting down");
+                return Ok(DrainOutcome::Drained);
+            }
+            _ = reports.tick() => {
+                tracing::info!("Draining: {} request(s) still in flight", in_flight.count());
+            }
+            () = &mut deadline => {
+                let count = in_flight.count();
+                in_flight.cancel();
+                servers.abort_all();
+                tracing::warn!(
+                    "Drain timeout passed, cancelling {} request(s) still in flight",
+                    count
+                );
+                return Ok(DrainOutcome::TimedOut { in_flight: count });
+            }
+        }
+    }
+}
+
+// Cancel-safe, as the servers that finished are taken out of `servers`
+async fn join_servers(servers: &mut JoinSet<io::Result<()>>) -> io::Result<()> {
+    while let Some(result) = servers.join_next().await {
+        result.map_err(io::Error::other)??;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! Graceful shutdown. On SIGTERM or Ctrl-C the server stops accepting connections, ends
+//! `/events` streams and waits for the requests in flight to finish, logging how many are
+//! left every few seconds. Requests still running after the drain timeout are cancelled
+//! and answered with `503 SHUTTING_DOWN`, and the server reports it so the binary can
+//! exit with an error.
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::sync::watch;
+
+use crate::ApiError;
+
+/// How long the binary waits for requests in flight when `--drain-timeout` is not given.
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+// How often the requests still in flight are logged while draining
+pub(crate) const DRAIN_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How a shutdown ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrainOutcome {
+    /// Every connection closed before the drain timeout.
+    Drained,
+    /// The drain timeout passed with `in_flight` requests unanswered, which were cancelled.
+    TimedOut { in_flight: usize },
+}
+
+// HTTP requests being handled, counted by `count_in_flight`
+#[derive(Debug)]
+pub struct InFlight {
+    count: AtomicUsize,
+    // Turns true when the drain timeout passes, cancelling every request still counted
+    cancelled: watch::Sender<bool>,
+}
+
+impl Default for InFlight {
+    fn default() -> Self {
+        InFlight {
+            count: AtomicUsize::new(0),
+            cancelled: watch::channel(false).0,
+        }
+    }
+}
+
+impl InFlight {
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    // Answers every request in flight, and any started later, with `SHUTTING_DOWN`
+    pub(crate) fn cancel(&self) {
+        self.cancelled.send_replace(true);
+    }
+}
+
+// Takes its request off the count when dropped, so cancelled and panicking handlers are too
+struct InFlightGuard(Arc<InFlight>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.count.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Counts the request as in flight until its response is ready, or until `InFlight::cancel`
+// drops its handler. Streamed bodies, such as `/events`, are not counted past that.
+pub(crate) async fn count_in_flight(
+    State(in_flight): State<Arc<InFlight>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    in_flight.count.fetch_add(1, Ordering::Relaxed);
+    let mut cancelled = in_flight.cancelled.subscribe();
+    let _guard = InFlightGuard(in_flight);
+    tokio::select! {
+        response = next.run(request) => response,
+        Ok(_) = cancelled.wait_for(|cancelled| *cancelled) => {
+            ApiError::ShuttingDown.into_response()
+        }
+    }
+}
+
+// Turns true once the server stops accepting connections; handed to handlers as an
+// extension so long-lived responses can end
+#[derive(Debug, Clone)]
+pub(crate) struct Stopping(pub(crate) watch::Receiver<bool>);
+
+// Completes when `stopping` turns true, and never if its sender is dropped first
+pub(crate) async fn stopped(mut stopping: watch::Receiver<bool>) {
+    if stopping.wait_for(|stopping| *stopping).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Completes on SIGTERM or Ctrl-C, for [`run_server_until`](crate::run_server_until).
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminations) => tokio::select! {
+                _ = terminations.recv() => tracing::info!("Received SIGTERM"),
+                _ = tokio::signal::ctrl_c() => tracing::info!("Received Ctrl-C"),
+            },
+            Err(error) => {
+                tracing::warn!("Failed to install SIGTERM handler: {}", error);
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        tracing::info!("Received Ctrl-C");
+    }
+}
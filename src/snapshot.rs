@@ -0,0 +1,212 @@
+//! Runtime changes to the served data kept across restarts, with `--state-dir`.
+//!
+//! After every admin edit or reload, the whole dataset is written to
+//! `countries.snapshot.json` in the state directory: first to a temporary file, which is
+//! then renamed over the snapshot, so a crash mid-write leaves the previous snapshot
+//! intact. At startup a snapshot newer than the configured data is served instead of it.
+//!
+//! The snapshot carries a SHA-256 checksum of its countries. A snapshot that does not
+//! parse, fails its checksum or holds invalid countries is skipped with a warning, and
+//! the configured data is served.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::dataset::{CountryRecord, Dataset, RecordError};
+
+pub const SNAPSHOT_FILE: &str = "countries.snapshot.json";
+// Written in full before it replaces SNAPSHOT_FILE
+const TEMPORARY_FILE: &str = "countries.snapshot.json.tmp";
+
+#[derive(Debug, Serialize)]
+struct SnapshotFile<'a> {
+    // Milliseconds since the Unix epoch
+    #[serde(rename = "savedAt")]
+    saved_at: u64,
+    checksum: String,
+    countries: Vec<&'a CountryRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StoredSnapshot {
+    #[serde(rename = "savedAt")]
+    saved_at: u64,
+    checksum: String,
+    countries: Vec<CountryRecord>,
+}
+
+// `sha256:` and the hex SHA-256 of the countries as compact JSON, which reads back to
+// the same bytes
+fn checksum<T: Serialize>(countries: &[T]) -> String {
+    let json = serde_json::to_vec(countries).expect("country records serialize to JSON");
+    format!("sha256:{:x}", Sha256::digest(json))
+}
+
+/// A dataset read back from a snapshot.
+#[derive(Debug)]
+pub struct Snapshot {
+    pub saved_at: SystemTime,
+    pub dataset: Dataset,
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io { path: PathBuf, source: io::Error },
+    // The file does not parse, fails its checksum or holds invalid countries
+    Corrupt { path: PathBuf, reason: String },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io { path, source } => {
+                write!(
+                    f,
+                    "Failed to access snapshot {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+            SnapshotError::Corrupt { path, reason } => {
+                write!(f, "Corrupt snapshot {}: {}", path.display(), reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapshotError::Io { source, .. } => Some(source),
+            SnapshotError::Corrupt { .. } => None,
+        }
+    }
+}
+
+/// The snapshot in a state directory.
+#[derive(Debug)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+    // Newest dataset version written, so a slow write never replaces a newer snapshot
+    saved: Mutex<u64>,
+}
+
+impl SnapshotStore {
+    // Creates `dir` if it does not exist yet
+    pub fn new(dir: &Path) -> Result<Self, SnapshotError> {
+        fs::create_dir_all(dir).map_err(|source| SnapshotError::Io {
+            path: dir.to_path_buf(),
+            source,
+        })?;
+        Ok(SnapshotStore {
+            dir: dir.to_path_buf(),
+            saved: Mutex::new(0),
+        })
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(SNAPSHOT_FILE)
+    }
+
+    // The snapshot, or None if none has been written
+    pub fn read(&self) -> Result<Option<Snapshot>, SnapshotError> {
+        let path = self.path();
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(source) => return Err(SnapshotError::Io { path, source }),
+        };
+        let corrupt = |reason: String| SnapshotError::Corrupt {
+            path: path.clone(),
+            reason,
+        };
+
+        let stored: StoredSnapshot =
+            serde_json::from_slice(&bytes).map_err(|error| corrupt(error.to_string()))?;
+        if checksum(&stored.countries) != stored.checksum {
+            return Err(corrupt(String::from("the checksum does not match")));
+        }
+        let mut dataset = Dataset::default();
+        for record in stored.countries {
+            let record = record.normalized();
+            record
+                .validate()
+                .and_then(|()| dataset.insert(record))
+                .map_err(|error: RecordError| corrupt(error.to_string()))?;
+        }
+        Ok(Some(Snapshot {
+            saved_at: UNIX_EPOCH + Duration::from_millis(stored.saved_at),
+            dataset,
+        }))
+    }
+
+    // The snapshot's dataset if it is newer than `base`, which was last modified at
+    // `base_modified` (None when unknown); otherwise `base`
+    pub fn restore(&self, base: Dataset, base_modified: Option<SystemTime>) -> Dataset {
+        match self.read() {
+            Ok(Some(snapshot)) if base_modified.is_none_or(|base| snapshot.saved_at > base) => {
+                tracing::info!(
+                    "Restored {} countries from {}",
+                    snapshot.dataset.len(),
+                    self.path().display()
+                );
+                snapshot.dataset
+            }
+            Ok(Some(_)) => {
+                tracing::info!(
+                    "Ignoring {}, which is older than the configured data",
+                    self.path().display()
+                );
+                base
+            }
+            Ok(None) => base,
+            Err(error) => {
+                tracing::warn!("Serving the configured data: {}", error);
+                base
+            }
+        }
+    }
+
+    // Writes `dataset`, which is at `version`, unless a newer version was written already
+    pub fn save(&self, version: u64, dataset: &Dataset) -> Result<(), SnapshotError> {
+        let mut saved = self.saved.lock().unwrap_or_else(PoisonError::into_inner);
+        if version <= *saved {
+            return Ok(());
+        }
+
+        let mut countries: Vec<_> = dataset.records().collect();
+        countries.sort_by_key(|record| &record.name);
+        let saved_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let snapshot = SnapshotFile {
+            saved_at,
+            checksum: checksum(&countries),
+            countries,
+        };
+        let json = serde_json::to_vec_pretty(&snapshot).expect("snapshots serialize to JSON");
+
+        let temporary = self.dir.join(TEMPORARY_FILE);
+        let io_error = |path: &Path| {
+            let path = path.to_path_buf();
+            move |source| SnapshotError::Io { path, source }
+        };
+        let mut file = File::create(&temporary).map_err(io_error(&temporary))?;
+        file.write_all(&json)
+            .and_then(|()| file.sync_all())
+            .map_err(io_error(&temporary))?;
+        fs::rename(&temporary, self.path()).map_err(io_error(&self.path()))?;
+        *saved = version;
+        Ok(())
+    }
+}
@@ -0,0 +1,295 @@
+//! Where the served countries come from.
+//!
+//! Handlers read them through a `CountryDataSource` held by `AppState`: a `StaticSource`
+//! for data that never changes, or the `MutableSource` the admin endpoints and reloads
+//! edit. Any other backend plugs in by implementing `CountryDataSource` and handing it to
+//! `AppState::from_source`.
+//!
+//! A `DatasetLoader` builds the dataset a source starts with, at startup and on every
+//! reload, from the built-in data, a `--data-file` or a `--database`.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock},
+    time::SystemTime,
+};
+
+use crate::{
+    audit::AuditError,
+    data_file::{self, DataFileError},
+    database::DatabaseError,
+    dataset::{CountryRecord, Dataset, RecordError},
+    overrides::OverrideError,
+};
+
+pub trait CountryDataSource: Send + Sync {
+    // Every country as served right now; later changes leave the returned dataset as is
+    fn all(&self) -> Arc<Dataset>;
+
+    // The country a normalized name or alias resolves to
+    fn lookup(&self, normalized: &str) -> Option<Arc<CountryRecord>> {
+        self.all().shared(normalized).cloned()
+    }
+
+    // Goes up whenever the served data changes
+    fn version(&self) -> u64;
+
+    // The data and the version it is at, read together. The default is only right for
+    // sources whose data and version change together; others have to override it.
+    fn versioned(&self) -> (u64, Arc<Dataset>) {
+        (self.version(), self.all())
+    }
+
+    // The source admin edits and reloads change; None when the data is read-only
+    fn as_mutable(&self) -> Option<&MutableSource> {
+        None
+    }
+}
+
+impl fmt::Debug for dyn CountryDataSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CountryDataSource")
+            .field("version", &self.version())
+            .finish_non_exhaustive()
+    }
+}
+
+// Data that is served as loaded, for as long as the server runs
+#[derive(Debug, Clone)]
+pub struct StaticSource {
+    dataset: Arc<Dataset>,
+}
+
+impl StaticSource {
+    pub fn new(dataset: Dataset) -> Self {
+        StaticSource {
+            dataset: Arc::new(dataset),
+        }
+    }
+}
+
+impl CountryDataSource for StaticSource {
+    fn all(&self) -> Arc<Dataset> {
+        Arc::clone(&self.dataset)
+    }
+
+    fn version(&self) -> u64 {
+        1
+    }
+}
+
+// Data the admin endpoints edit and reloads replace. Each change is made to a copy,
+// which then replaces the served data, so readers never wait for a change to finish.
+#[derive(Debug)]
+pub struct MutableSource {
+    current: RwLock<(u64, Arc<Dataset>)>,
+}
+
+impl MutableSource {
+    pub fn new(dataset: Dataset) -> Self {
+        MutableSource {
+            current: RwLock::new((1, Arc::new(dataset))),
+        }
+    }
+
+    // Serves a copy of the data changed by `change`, unless it fails, and returns the
+    // new version with what `change` returned
+    pub fn update<T, E>(
+        &self,
+        change: impl FnOnce(&mut Dataset) -> Result<T, E>,
+    ) -> Result<(T, u64), E> {
+        let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+        let mut dataset = Dataset::clone(&current.1);
+        let value = change(&mut dataset)?;
+        *current = (current.0 + 1, Arc::new(dataset));
+        Ok((value, current.0))
+    }
+}
+
+impl CountryDataSource for MutableSource {
+    fn all(&self) -> Arc<Dataset> {
+        self.versioned().1
+    }
+
+    fn version(&self) -> u64 {
+        self.versioned().0
+    }
+
+    fn versioned(&self) -> (u64, Arc<Dataset>) {
+        let current = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        (current.0, Arc::clone(&current.1))
+    }
+
+    fn as_mutable(&self) -> Option<&MutableSource> {
+        Some(self)
+    }
+}
+
+// An admin edit or reload of data served by a source that cannot be changed
+#[derive(Debug, Clone, Copy)]
+pub struct ReadOnlyError;
+
+impl fmt::Display for ReadOnlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("The served country data is read-only")
+    }
+}
+
+impl std::error::Error for ReadOnlyError {}
+
+// Builds the dataset a source serves, at startup and on every reload
+pub trait DatasetLoader: Send + Sync {
+    // Where the data comes from, for log messages
+    fn describe(&self) -> String;
+
+    // Builds a complete, validated dataset; never returns partially loaded data
+    fn load(&self) -> Result<Dataset, LoadError>;
+
+    // Every entry as stored, unvalidated, so `--check` can report all problems at once
+    fn records(&self) -> Result<Vec<CountryRecord>, LoadError>;
+
+    // When the data last changed, if known; a `--state-dir` snapshot older than this is
+    // not restored over it
+    fn modified(&self) -> Option<SystemTime> {
+        None
+    }
+}
+
+// The modification time of the file at `path`, if it can be read
+pub(crate) fn file_modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// The generated ISO 3166-1 dataset (or the COUNTRY_DATA_PATH CSV override)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BuiltinLoader;
+
+impl DatasetLoader for BuiltinLoader {
+    fn describe(&self) -> String {
+        String::from("the built-in dataset")
+    }
+
+    fn load(&self) -> Result<Dataset, LoadError> {
+        crate::server::load_builtin_country_data()
+    }
+
+    fn records(&self) -> Result<Vec<CountryRecord>, LoadError> {
+        let dataset = self.load()?;
+        let mut records: Vec<_> = dataset.records().cloned().collect();
+        records.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(records)
+    }
+}
+
+// A JSON file passed with `--data-file`
+#[derive(Debug, Clone)]
+pub struct DataFileLoader {
+    path: PathBuf,
+}
+
+impl DataFileLoader {
+    pub fn new(path: &Path) -> Self {
+        DataFileLoader {
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+impl DatasetLoader for DataFileLoader {
+    fn describe(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn load(&self) -> Result<Dataset, LoadError> {
+        Ok(data_file::load_data_file(&self.path)?)
+    }
+
+    fn records(&self) -> Result<Vec<CountryRecord>, LoadError> {
+        Ok(data_file::read_records(&self.path)?)
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        file_modified(&self.path)
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    DataFile(DataFileError),
+    // The `COUNTRY_DATA_PATH` CSV names a country twice
+    Csv {
+        path: PathBuf,
+        source: RecordError,
+    },
+    Database {
+        path: PathBuf,
+        source: DatabaseError,
+    },
+    Override(OverrideError),
+    ReadOnly(ReadOnlyError),
+    // The reload's audit record could not be written, so it was not made
+    Audit(AuditError),
+}
+
+impl From<AuditError> for LoadError {
+    fn from(error: AuditError) -> Self {
+        LoadError::Audit(error)
+    }
+}
+
+impl From<DataFileError> for LoadError {
+    fn from(error: DataFileError) -> Self {
+        LoadError::DataFile(error)
+    }
+}
+
+impl From<ReadOnlyError> for LoadError {
+    fn from(error: ReadOnlyError) -> Self {
+        LoadError::ReadOnly(error)
+    }
+}
+
+impl From<OverrideError> for LoadError {
+    fn from(error: OverrideError) -> Self {
+        LoadError::Override(error)
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::DataFile(error) => error.fmt(f),
+            LoadError::Csv { path, source } => write!(
+                f,
+                "Invalid country data file {} (source: COUNTRY_DATA_PATH): {}",
+                path.display(),
+                source
+            ),
+            LoadError::Database { path, source } => write!(
+                f,
+                "Failed to load country data from database {}: {}",
+                path.display(),
+                source
+            ),
+            LoadError::Override(error) => error.fmt(f),
+            LoadError::ReadOnly(error) => error.fmt(f),
+            LoadError::Audit(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::DataFile(error) => Some(error),
+            LoadError::Csv { source, .. } => Some(source),
+            LoadError::Database { source, .. } => Some(source),
+            LoadError::Override(error) => Some(error),
+            LoadError::ReadOnly(error) => Some(error),
+            LoadError::Audit(error) => Some(error),
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! A real server on an ephemeral local port, for integration tests of code that embeds
+//! the API. Enabled with the `test-util` feature.
+//!
+//! [`spawn_test_server`] serves [`create_app_with`] over `source` on `127.0.0.1:0`, the
+//! way the binary serves it, and returns its base URL to point any HTTP client at:
+//!
+//! ```no_run
+//! # async fn example() {
+//! use rusty_currency::{source::StaticSource, test_util::spawn_test_server, AppConfig, Dataset};
+//!
+//! let server = spawn_test_server(AppConfig::default(), StaticSource::new(Dataset::builtin())).await;
+//! let health = reqwest::get(format!("{}/healthz", server.url())).await.unwrap();
+//! assert!(health.status().is_success());
+//! server.shutdown().await.unwrap();
+//! # }
+//! ```
+//!
+//! Dropping the [`TestServer`] stops it without waiting, which closes its port and
+//! connections; [`TestServer::shutdown`] waits for the requests in flight to finish first.
+
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{sync::oneshot, task::JoinHandle};
+
+use crate::{
+    bind_listeners, create_app_with, run_server_until, shutdown::DrainOutcome,
+    source::CountryDataSource, AppConfig, ServeOptions,
+};
+
+// Longest `shutdown` waits for requests in flight; tests should not hang on one
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A server started by [`spawn_test_server`], stopped when dropped.
+#[derive(Debug)]
+pub struct TestServer {
+    address: SocketAddr,
+    url: String,
+    // Taken by `shutdown`, or by `drop` when the server is dropped
+    stop: Option<oneshot::Sender<()>>,
+    server: Option<JoinHandle<io::Result<DrainOutcome>>>,
+}
+
+/// Serves `source` with `config` on an ephemeral port of `127.0.0.1`, in a task of the
+/// current Tokio runtime. Panics if no port can be bound, as a test could not go on.
+pub async fn spawn_test_server(
+    config: AppConfig,
+    source: impl CountryDataSource + 'static,
+) -> TestServer {
+    let app = create_app_with(config, Arc::new(source));
+    let listeners = bind_listeners(&[SocketAddr::from(([127, 0, 0, 1], 0))])
+        .expect("binding an ephemeral loopback port");
+    let address = listeners[0]
+        .local_addr()
+        .expect("a bound listener has an address");
+    let (stop, stopped) = oneshot::channel();
+    let options = ServeOptions {
+        drain_timeout: DRAIN_TIMEOUT,
+        ..ServeOptions::default()
+    };
+    let shutdown = async move {
+        // Sent by `shutdown` and `drop`; dropping the sender stops the server too
+        let _ = stopped.await;
+    };
+    let server = tokio::spawn(run_server_until(listeners, app, shutdown, options));
+
+    TestServer {
+        address,
+        url: format!("http://{}", address),
+        stop: Some(stop),
+        server: Some(server),
+    }
+}
+
+impl TestServer {
+    /// The base URL, such as `http://127.0.0.1:49152`, without a trailing slash.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Stops accepting connections and waits for the requests in flight, as the binary
+    /// does on SIGTERM, with a five-second drain timeout.
+    pub async fn shutdown(mut self) -> io::Result<DrainOutcome> {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        let server = self.server.take().expect("only shutdown and drop take it");
+        server.await.map_err(io::Error::other)?
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        // Cancels the listeners and connections with it, without waiting for a drain
+        if let Some(server) = self.server.take() {
+            server.abort();
+        }
+    }
+}
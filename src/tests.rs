@@ -1,364 +1,5358 @@
 use super::*;
 use axum::{
     body::Body,
-    http::{Request, StatusCode},
+    body::Bytes,
+    http::{HeaderValue, Request, StatusCode},
+    Router,
 };
+use dataset::{Alias, AliasKind, RecordError};
 use http_body_util::BodyExt;
+use listenfd::ListenFd;
+use proptest::{collection::vec, prelude::*, sample::select};
+use server::*;
+use sha2::{Digest, Sha256};
+use source::{BuiltinLoader, DataFileLoader, DatasetLoader, LoadError};
+use std::{
+    alloc::{GlobalAlloc, Layout},
+    borrow::Cow,
+    cell::Cell,
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    num::{NonZeroU32, NonZeroUsize},
+    path::PathBuf,
+    time::Duration,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tower::ServiceExt;
 
-#[tokio::test]
-async fn test_get_country_single() {
-    let app = create_app();
+// Minimal HTTP/1.1 client for tests that talk to a real listener
+async fn http_get(address: SocketAddr, path: &str) -> (u16, String) {
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, address
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .uri("/getCountry?based=japan")
-                .body(Body::empty())
-                .unwrap(),
-        )
-        .await
-        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let response = String::from_utf8(response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    (status, body.to_string())
+}
 
-    let body = response.into_body().collect().await.unwrap().to_bytes();
-    let body_str = String::from_utf8(body.to_vec()).unwrap();
-    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+#[test]
+fn test_parse_country_data_skips_malformed_lines() {
+    let csv_data =
+        "country,flag,currencyCode,phoneCode\nvalid,🏳️,VAL,+999\nmissing-flag,,MFG,+000\nmissing-code,🏳️,,+000\nmissing-phone,🏳️,VAL,\n";
+    let reader = std::io::BufReader::new(csv_data.as_bytes());
+    let data = parse_country_data(reader);
 
-    assert_eq!(country_response.results.len(), 1);
-    assert_eq!(country_response.results[0].country, "japan");
-    assert_eq!(country_response.results[0].flag, "🇯🇵");
-    assert_eq!(country_response.results[0].currency_code, "JPY");
-    assert_eq!(country_response.results[0].phone_code, "+81");
+    assert_eq!(data.len(), 1);
+    assert_eq!(
+        data.get("valid"),
+        Some(&(
+            String::from("🏳️"),
+            CurrencyCode::new("VAL").unwrap(),
+            String::from("+999")
+        ))
+    );
+}
+
+#[test]
+fn test_flat_names_that_normalize_alike_conflict() {
+    let csv_data = "country,flag,currencyCode,phoneCode\nCôte d'Ivoire,🇨🇮,XOF,+225\ncôte d\u{2019}ivoire,🇨🇮,XOF,+225\n";
+    let data = parse_country_data(std::io::BufReader::new(csv_data.as_bytes()));
+    assert_eq!(data.len(), 2);
+
+    assert_eq!(
+        Dataset::from_flat(data),
+        Err(RecordError::Conflict {
+            name: String::from("côte d'ivoire")
+        })
+    );
+}
+
+// Counts allocations per thread, so tests running in parallel do not disturb each other.
+// It wraps the allocator the `mimalloc`/`jemalloc` features select, so tests exercise it.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        SelectedAllocator.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        SelectedAllocator.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations_during<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let value = f();
+    (value, ALLOCATIONS.with(Cell::get) - before)
 }
 
 #[tokio::test]
-async fn test_get_country_multiple() {
-    let app = create_app();
+async fn test_selected_allocator_serves_get_country() {
+    let expected = if cfg!(feature = "mimalloc") {
+        "mimalloc"
+    } else if cfg!(feature = "jemalloc") {
+        "jemalloc"
+    } else {
+        "system"
+    };
+    assert_eq!(ALLOCATOR_NAME, expected);
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .uri("/getCountry?based=japan,korea")
-                .body(Body::empty())
-                .unwrap(),
-        )
-        .await
-        .unwrap();
+    let listeners = bind_listeners(&["127.0.0.1:0".parse().unwrap()]).unwrap();
+    let address = listeners[0].local_addr().unwrap();
+    let server = tokio::spawn(run_server(listeners, create_app()));
 
-    assert_eq!(response.status(), StatusCode::OK);
+    let (status, body) = http_get(address, "/getCountry?based=japan,usa").await;
+    assert_eq!(status, 200);
+    let response: CountryResponse = serde_json::from_str(&body).unwrap();
+    assert_eq!(response.results.len(), 2);
+    assert_eq!(response.results[1].currency_code, "USD");
 
-    let body = response.into_body().collect().await.unwrap().to_bytes();
-    let body_str = String::from_utf8(body.to_vec()).unwrap();
-    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+    server.abort();
+}
 
-    assert_eq!(country_response.results.len(), 2);
-    assert_eq!(country_response.results[0].country, "japan");
-    assert_eq!(country_response.results[0].flag, "🇯🇵");
-    assert_eq!(country_response.results[0].currency_code, "JPY");
-    assert_eq!(country_response.results[0].phone_code, "+81");
-    assert_eq!(country_response.results[1].country, "korea");
-    assert_eq!(country_response.results[1].flag, "🇰🇷");
-    assert_eq!(country_response.results[1].currency_code, "KRW");
-    assert_eq!(country_response.results[1].phone_code, "+82");
+#[test]
+fn test_lookup_countries_does_not_copy_strings() {
+    let dataset = builtin::dataset();
+    let mut names: Vec<_> = dataset
+        .records()
+        .map(|record| record.name.as_str())
+        .collect();
+    names.sort();
+    let based = names[..50].join(",");
+    // The first lookup of a built-in country builds its record
+    assert_eq!(
+        lookup::lookup_countries(&dataset, &based, "en")
+            .results
+            .len(),
+        50
+    );
+
+    let (response, allocations) =
+        allocations_during(|| lookup::lookup_countries(&dataset, &based, "en"));
+    let results = response.results;
+
+    assert_eq!(results.len(), 50);
+    assert!(results
+        .iter()
+        .all(|info| matches!(info.flag, Cow::Borrowed(_))));
+    // Only the token and result vectors grow; copying strings would take 250+
+    assert!(allocations < 20, "{} allocations", allocations);
+}
+
+#[test]
+fn test_small_queries_stay_inline() {
+    let dataset = builtin::dataset();
+    let based = "Japan,usa,côte d\u{2019}ivoire";
+    assert_eq!(
+        lookup::lookup_countries(&dataset, based, "en")
+            .results
+            .len(),
+        3
+    );
+
+    let (response, allocations) =
+        allocations_during(|| lookup::lookup_countries(&dataset, based, "en"));
+    assert_eq!(response.results.len(), 3);
+    assert!(!response.results.spilled());
+    // Only the non-ASCII name needs a normalized copy
+    assert_eq!(allocations, 1);
+
+    // The pre-sized buffer, plus the shared header `Bytes` adds to it; growing the buffer
+    // from serde_json's default would take more
+    let (body, allocations) = allocations_during(|| lookup::to_json(&response));
+    assert_eq!(allocations, 2);
+    assert_eq!(body, serde_json::to_vec(&response).unwrap());
+}
+
+#[test]
+fn test_normalize_borrows_normalized_names() {
+    for name in [
+        "japan",
+        "côte d'ivoire",
+        "bosnia and herzegovina",
+        "日本",
+        "",
+    ] {
+        assert!(
+            matches!(normalize(name), Cow::Borrowed(borrowed) if borrowed == name),
+            "{:?}",
+            name
+        );
+    }
+}
+
+#[test]
+fn test_normalize_case() {
+    assert_eq!(normalize("JaPan"), "japan");
+    assert_eq!(normalize("UNITED STATES"), "united states");
+    assert_eq!(normalize("CÔTE D'IVOIRE"), "côte d'ivoire");
+    assert_eq!(normalize("ÅLAND ISLANDS"), "åland islands");
+    assert_eq!(normalize("ΕΛΛΆΔΑ"), "ελλάδα");
+}
+
+#[test]
+fn test_normalize_whitespace() {
+    assert_eq!(normalize("  japan  "), "japan");
+    assert_eq!(normalize("united\u{a0}states"), "united states");
+    assert_eq!(
+        normalize("\u{a0}united  \t states\u{2003}"),
+        "united states"
+    );
+    assert_eq!(normalize("south\u{202f}\u{a0}korea"), "south korea");
+    assert_eq!(normalize(" \u{a0}\t"), "");
+}
+
+#[test]
+fn test_normalize_accents() {
+    // Decomposed accents compose, so either spelling matches
+    assert_eq!(normalize("curac\u{327}ao"), "curaçao");
+    assert_eq!(normalize("co\u{302}te d'ivoire"), "côte d'ivoire");
+    assert_eq!(normalize("CURAC\u{327}AO"), "curaçao");
+    // Accents themselves are kept; unaccented spellings match through aliases
+    assert_eq!(normalize("curaçao"), "curaçao");
+    assert_ne!(normalize("curaçao"), normalize("curacao"));
+}
+
+#[test]
+fn test_normalize_apostrophes() {
+    for name in [
+        "côte d'ivoire",
+        "côte d\u{2019}ivoire",
+        "côte d\u{2018}ivoire",
+        "côte d\u{2bc}ivoire",
+        "côte d\u{ff07}ivoire",
+        "Côte D\u{2019}Ivoire",
+    ] {
+        assert_eq!(normalize(name), "côte d'ivoire", "{:?}", name);
+    }
+}
+
+#[test]
+fn test_normalize_is_idempotent() {
+    for name in [
+        "  Côte\u{a0}D\u{2019}Ivoire ",
+        "CURAC\u{327}AO",
+        "united \t states",
+    ] {
+        let once = normalize(name).into_owned();
+        assert!(matches!(normalize(&once), Cow::Borrowed(_)), "{:?}", name);
+    }
+}
+
+#[test]
+fn test_builtin_index_keys_are_normalized() {
+    for name in builtin::BUILTIN_INDEX.keys() {
+        assert!(matches!(normalize(name), Cow::Borrowed(_)), "{:?}", name);
+    }
 }
 
 #[tokio::test]
-async fn test_get_country_case_insensitive() {
-    let app = create_app();
+async fn test_run_server_multiple_listeners() {
+    let loopback: SocketAddr = "127.0.0.1:0".parse().unwrap();
+    let listeners = bind_listeners(&[loopback, loopback]).unwrap();
+    let addresses: Vec<SocketAddr> = listeners
+        .iter()
+        .map(|listener| listener.local_addr().unwrap())
+        .collect();
+    assert_ne!(addresses[0], addresses[1]);
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .uri("/getCountry?based=JAPAN")
-                .body(Body::empty())
-                .unwrap(),
-        )
-        .await
-        .unwrap();
+    let server = tokio::spawn(run_server(listeners, create_app()));
 
-    assert_eq!(response.status(), StatusCode::OK);
+    for address in &addresses {
+        let (status, body) = http_get(*address, "/healthz").await;
+        assert_eq!(status, 200);
+        let health: HealthResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(health.status, "ok");
+    }
 
-    let body = response.into_body().collect().await.unwrap().to_bytes();
-    let body_str = String::from_utf8(body.to_vec()).unwrap();
-    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+    let (status, body) = http_get(addresses[1], "/version").await;
+    assert_eq!(status, 200);
+    let version: VersionResponse = serde_json::from_str(&body).unwrap();
+    assert_eq!(version.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(version.listen_addresses, addresses);
 
-    assert_eq!(country_response.results.len(), 1);
-    assert_eq!(country_response.results[0].country, "JAPAN");
-    assert_eq!(country_response.results[0].flag, "🇯🇵");
-    assert_eq!(country_response.results[0].currency_code, "JPY");
-    assert_eq!(country_response.results[0].phone_code, "+81");
+    server.abort();
 }
 
 #[tokio::test]
-async fn test_get_country_unknown() {
-    let app = create_app();
+async fn test_bind_listeners_reports_failing_address() {
+    let occupied = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let occupied_address = occupied.local_addr().unwrap();
+    let loopback: SocketAddr = "127.0.0.1:0".parse().unwrap();
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .uri("/getCountry?based=unknown")
-                .body(Body::empty())
-                .unwrap(),
-        )
-        .await
-        .unwrap();
+    let error = bind_listeners(&[loopback, occupied_address]).unwrap_err();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+    assert!(error.to_string().contains(&occupied_address.to_string()));
+    assert!(error.to_string().contains("--bind-retry"), "{}", error);
+}
 
-    let body = response.into_body().collect().await.unwrap().to_bytes();
-    let body_str = String::from_utf8(body.to_vec()).unwrap();
-    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+#[test]
+fn test_bind_error_hints() {
+    use std::io::ErrorKind;
+
+    let hint = |address: &str, kind| bind_error_hint(address.parse().unwrap(), kind);
+    let in_use = hint("0.0.0.0:3000", ErrorKind::AddrInUse).unwrap();
+    assert!(in_use.contains("`--listen 0.0.0.0:3001`"), "{}", in_use);
+    assert!(in_use.contains("--bind-retry"), "{}", in_use);
+    let last_port = hint("[::]:65535", ErrorKind::AddrInUse).unwrap();
+    assert!(last_port.contains("`--listen [::]:65534`"), "{}", last_port);
+    let low_port = hint("0.0.0.0:80", ErrorKind::PermissionDenied).unwrap();
+    assert!(low_port.contains("cap_net_bind_service"), "{}", low_port);
+    assert!(low_port.contains("`--listen 0.0.0.0:8080`"), "{}", low_port);
+    let foreign = hint("192.0.2.1:3000", ErrorKind::AddrNotAvailable).unwrap();
+    assert!(
+        foreign.starts_with("192.0.2.1 is not an address"),
+        "{}",
+        foreign
+    );
 
-    assert_eq!(country_response.results.len(), 0);
+    assert_eq!(hint("0.0.0.0:3000", ErrorKind::PermissionDenied), None);
+    assert_eq!(hint("0.0.0.0:3000", ErrorKind::Other), None);
 }
 
 #[tokio::test]
-async fn test_get_country_with_spaces() {
-    let app = create_app();
+async fn test_bind_retry_waits_for_the_port_to_be_released() {
+    let occupied = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = occupied.local_addr().unwrap();
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .uri("/getCountry?based=japan,%20korea,%20usa")
-                .body(Body::empty())
-                .unwrap(),
-        )
+    let error = bind_listeners_with_retry(&[address], Duration::ZERO)
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+
+    let release = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(300));
+        drop(occupied);
+    });
+    let listeners = bind_listeners_with_retry(&[address], Duration::from_secs(10))
         .await
         .unwrap();
+    release.join().unwrap();
+    assert_eq!(listeners[0].local_addr().unwrap(), address);
+}
 
-    assert_eq!(response.status(), StatusCode::OK);
+#[tokio::test]
+async fn test_run_server_adopted_listener() {
+    let std_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = std_listener.local_addr().unwrap();
+    let listeners = adopt_listeners(vec![std_listener]).unwrap();
 
-    let body = response.into_body().collect().await.unwrap().to_bytes();
-    let body_str = String::from_utf8(body.to_vec()).unwrap();
-    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+    let server = tokio::spawn(run_server(listeners, create_app()));
+
+    let (status, body) = http_get(address, "/getCountry?based=japan").await;
+    assert_eq!(status, 200);
+    let country_response: CountryResponse = serde_json::from_str(&body).unwrap();
+    assert_eq!(country_response.results.len(), 1);
+    assert_eq!(country_response.results[0].currency_code, "JPY");
 
-    assert_eq!(country_response.results.len(), 3);
-    assert_eq!(country_response.results[0].country, "japan");
-    assert_eq!(country_response.results[1].country, "korea");
-    assert_eq!(country_response.results[2].country, "usa");
+    server.abort();
 }
 
 #[tokio::test]
-async fn test_get_country_mixed_valid_invalid() {
-    let app = create_app();
+async fn test_drain_timeout_cancels_requests_still_in_flight() {
+    let listeners = bind_listeners(&["127.0.0.1:0".parse().unwrap()]).unwrap();
+    let address = listeners[0].local_addr().unwrap();
+    let (shut_down, shutdown) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(run_server_until(
+        listeners,
+        create_app(),
+        async move {
+            let _ = shutdown.await;
+        },
+        ServeOptions {
+            drain_timeout: Duration::from_millis(300),
+            ..ServeOptions::default()
+        },
+    ));
+    let slow = tokio::spawn(http_get(address, "/__test/slow"));
+    tokio::time::sleep(Duration::from_millis(100)).await;
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .uri("/getCountry?based=japan,unknown,korea")
-                .body(Body::empty())
-                .unwrap(),
-        )
+    let started = std::time::Instant::now();
+    shut_down.send(()).unwrap();
+    let outcome = server.await.unwrap().unwrap();
+    assert_eq!(outcome, shutdown::DrainOutcome::TimedOut { in_flight: 1 });
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    assert!(started.elapsed() < Duration::from_secs(10));
+    let (status, body) = slow.await.unwrap();
+    assert_eq!(status, 503);
+    assert!(body.contains("SHUTTING_DOWN"), "{}", body);
+    assert!(tokio::net::TcpStream::connect(address).await.is_err());
+}
+
+#[tokio::test]
+async fn test_drain_ends_once_requests_finish_and_event_streams_close() {
+    let listeners = bind_listeners(&["127.0.0.1:0".parse().unwrap()]).unwrap();
+    let address = listeners[0].local_addr().unwrap();
+    let (shut_down, shutdown) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(run_server_until(
+        listeners,
+        create_app(),
+        async move {
+            let _ = shutdown.await;
+        },
+        ServeOptions::default(),
+    ));
+    let mut events = tokio::net::TcpStream::connect(address).await.unwrap();
+    events
+        .write_all(b"GET /events HTTP/1.1\r\nHost: test\r\n\r\n")
         .await
         .unwrap();
+    let mut head = [0; 12];
+    events.read_exact(&mut head).await.unwrap();
+    assert_eq!(&head, b"HTTP/1.1 200");
+    let (status, _) = http_get(address, "/getCountry?based=japan").await;
+    assert_eq!(status, 200);
 
-    assert_eq!(response.status(), StatusCode::OK);
+    shut_down.send(()).unwrap();
+    let outcome = tokio::time::timeout(Duration::from_secs(10), server)
+        .await
+        .expect("the open /events stream held up the shutdown")
+        .unwrap()
+        .unwrap();
+    assert_eq!(outcome, shutdown::DrainOutcome::Drained);
+}
 
-    let body = response.into_body().collect().await.unwrap().to_bytes();
-    let body_str = String::from_utf8(body.to_vec()).unwrap();
-    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+#[tokio::test]
+async fn test_in_flight_counts_requests_until_they_are_answered() {
+    let in_flight = std::sync::Arc::new(shutdown::InFlight::default());
+    let release = std::sync::Arc::new(tokio::sync::Notify::new());
+    let waiting = release.clone();
+    let app = Router::new()
+        .route(
+            "/wait",
+            axum::routing::get(move || async move { waiting.notified().await }),
+        )
+        .layer(axum::middleware::from_fn_with_state(
+            in_flight.clone(),
+            shutdown::count_in_flight,
+        ));
 
-    assert_eq!(country_response.results.len(), 2);
-    assert_eq!(country_response.results[0].country, "japan");
-    assert_eq!(country_response.results[1].country, "korea");
+    let first = tokio::spawn(
+        app.clone()
+            .oneshot(Request::get("/wait").body(Body::empty()).unwrap()),
+    );
+    let second = tokio::spawn(app.oneshot(Request::get("/wait").body(Body::empty()).unwrap()));
+    while in_flight.count() < 2 {
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(in_flight.count(), 2);
+
+    // A request whose client went away is no longer in flight
+    second.abort();
+    let _ = second.await;
+    assert_eq!(in_flight.count(), 1);
+    release.notify_one();
+    first.await.unwrap().unwrap();
+    assert_eq!(in_flight.count(), 0);
+}
+
+#[test]
+fn test_take_inherited_listeners_without_activation() {
+    let listeners = take_inherited_listeners(&mut ListenFd::empty()).unwrap();
+
+    assert!(listeners.is_empty());
 }
 
 #[tokio::test]
-async fn test_get_country_all_supported() {
+async fn test_panicking_handler_returns_json_500() {
     let app = create_app();
 
     let response = app
         .oneshot(
             Request::builder()
-                .uri("/getCountry?based=usa,uk,germany")
+                .uri("/__test/panic")
+                .header("x-request-id", "panic-test-id")
                 .body(Body::empty())
                 .unwrap(),
         )
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(response.headers()["x-request-id"], "panic-test-id");
 
     let body = response.into_body().collect().await.unwrap().to_bytes();
-    let body_str = String::from_utf8(body.to_vec()).unwrap();
-    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+    let envelope: ErrorEnvelope = serde_json::from_slice(&body).unwrap();
 
-    assert_eq!(country_response.results.len(), 3);
-    assert_eq!(country_response.results[0].flag, "🇺🇸");
-    assert_eq!(country_response.results[0].currency_code, "USD");
-    assert_eq!(country_response.results[0].phone_code, "+1");
-    assert_eq!(country_response.results[1].flag, "🇬🇧");
-    assert_eq!(country_response.results[1].currency_code, "GBP");
-    assert_eq!(country_response.results[1].phone_code, "+44");
-    assert_eq!(country_response.results[2].flag, "🇩🇪");
-    assert_eq!(country_response.results[2].currency_code, "EUR");
-    assert_eq!(country_response.results[2].phone_code, "+49");
+    assert_eq!(envelope.error.code, "INTERNAL_ERROR");
+    assert_eq!(envelope.error.request_id.as_deref(), Some("panic-test-id"));
+    assert!(!envelope
+        .error
+        .message
+        .contains("panic triggered by test route"));
 }
 
 #[tokio::test]
-async fn test_get_country_comprehensive_coverage() {
+async fn test_internal_error_does_not_leak_its_cause() {
     let app = create_app();
 
     let response = app
         .oneshot(
             Request::builder()
-                .uri("/getCountry?based=afghanistan,argentina,egypt,fiji,iceland,nigeria,thailand")
+                .uri("/__test/internal")
+                .header("x-request-id", "internal-test-id")
                 .body(Body::empty())
                 .unwrap(),
         )
         .await
         .unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
 
     let body = response.into_body().collect().await.unwrap().to_bytes();
-    let body_str = String::from_utf8(body.to_vec()).unwrap();
-    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    let envelope: ErrorEnvelope = serde_json::from_str(&body).unwrap();
+
+    assert_eq!(envelope.error.code, "INTERNAL_ERROR");
+    assert_eq!(envelope.error.message, "Internal server error");
+    assert!(envelope.error.details.is_none());
+    assert_eq!(
+        envelope.error.request_id.as_deref(),
+        Some("internal-test-id")
+    );
+    assert!(!body.contains("db.internal"));
+}
 
-    assert_eq!(country_response.results.len(), 7);
+// Every way a request can fail answers with the same envelope, whichever layer fails it
+#[tokio::test(start_paused = true)]
+async fn test_every_failure_mode_answers_with_the_error_envelope() {
+    let app_with =
+        |config: AppConfig| create_app_with_state(AppState::new(Dataset::builtin()), &config);
+    let plain = create_app();
+    let admin = app_with(AppConfig {
+        admin_token: Some(String::from("s3cret")),
+        ..AppConfig::default()
+    });
+    let rate_limited = app_with(AppConfig {
+        rate_limit: NonZeroU32::new(1),
+        ..AppConfig::default()
+    });
+    let timed = app_with(AppConfig {
+        request_timeout: Some(Duration::from_millis(50)),
+        ..AppConfig::default()
+    });
+    let busy = app_with(AppConfig {
+        max_concurrent_requests: NonZeroUsize::new(1),
+        ..AppConfig::default()
+    });
+    // Holds `busy`'s only slot until the test ends
+    let slow = busy
+        .clone()
+        .oneshot(Request::get("/__test/slow").body(Body::empty()).unwrap());
+    let holder = tokio::spawn(slow);
+    for _ in 0..10 {
+        tokio::task::yield_now().await;
+    }
 
-    assert_eq!(country_response.results[0].country, "afghanistan");
-    assert_eq!(country_response.results[0].flag, "🇦🇫");
-    assert_eq!(country_response.results[0].currency_code, "AFN");
-    assert_eq!(country_response.results[0].phone_code, "+93");
+    // Each request is sent twice, as the first uses up `rate_limited`'s one request a
+    // second; the second answer is checked
+    let cases: [(&Router, &str, u16, &str); 12] = [
+        (
+            &plain,
+            "GET /getCountry?rates=maybe",
+            400,
+            "INVALID_PARAMETER",
+        ),
+        (&plain, "POST /resolve", 400, "INVALID_BODY"),
+        (&plain, "GET /getCountry?based=", 400, "EMPTY_QUERY"),
+        (
+            &plain,
+            "GET /countries?format=xml",
+            400,
+            "INVALID_PARAMETER",
+        ),
+        (&admin, "DELETE /admin/countries/japan", 401, "UNAUTHORIZED"),
+        (
+            &rate_limited,
+            "GET /getCountry?based=japan",
+            429,
+            "RATE_LIMITED",
+        ),
+        (&plain, "GET /__test/panic", 500, "INTERNAL_ERROR"),
+        (&plain, "GET /__test/internal", 500, "INTERNAL_ERROR"),
+        (&timed, "GET /__test/slow", 503, "TIMEOUT"),
+        (&busy, "GET /getCountry?based=japan", 503, "OVERLOADED"),
+        (&plain, "GET /nowhere", 404, "NOT_FOUND"),
+        (&plain, "DELETE /getCountry", 405, "METHOD_NOT_ALLOWED"),
+    ];
+    for (app, request, status, code) in cases {
+        let (method, uri) = request.split_once(' ').unwrap();
+        let mut response = None;
+        for _ in 0..2 {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(Body::from("{not json"))
+                .unwrap();
+            response = Some(app.clone().oneshot(request).await.unwrap());
+        }
+        let response = response.unwrap();
 
-    assert_eq!(country_response.results[1].country, "argentina");
-    assert_eq!(country_response.results[1].flag, "🇦🇷");
-    assert_eq!(country_response.results[1].currency_code, "ARS");
+        assert_eq!(response.status().as_u16(), status, "{}", request);
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let envelope: ErrorEnvelope = serde_json::from_slice(&bytes)
+            .unwrap_or_else(|error| panic!("{}: {} in {:?}", request, error, bytes));
+        assert_eq!(envelope.error.code, code, "{}", request);
+        assert!(!envelope.error.message.is_empty(), "{}", request);
+        assert!(envelope.error.request_id.is_some(), "{}", request);
+    }
+    holder.abort();
+}
 
-    assert_eq!(country_response.results[2].country, "egypt");
-    assert_eq!(country_response.results[2].flag, "🇪🇬");
-    assert_eq!(country_response.results[2].currency_code, "EGP");
-    assert_eq!(country_response.results[2].phone_code, "+20");
+#[tokio::test]
+async fn test_panicking_handler_over_tcp_keeps_connection() {
+    let listeners = bind_listeners(&["127.0.0.1:0".parse().unwrap()]).unwrap();
+    let address = listeners[0].local_addr().unwrap();
+    let server = tokio::spawn(run_server(listeners, create_app()));
 
-    assert_eq!(country_response.results[3].country, "fiji");
-    assert_eq!(country_response.results[3].flag, "🇫🇯");
-    assert_eq!(country_response.results[3].currency_code, "FJD");
-    assert_eq!(country_response.results[3].phone_code, "+679");
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
 
-    assert_eq!(country_response.results[4].country, "iceland");
-    assert_eq!(country_response.results[4].flag, "🇮🇸");
-    assert_eq!(country_response.results[4].currency_code, "ISK");
-    assert_eq!(country_response.results[4].phone_code, "+354");
+    let (status, body) = keep_alive_get(&mut stream, address, "/__test/panic").await;
+    assert_eq!(status, 500);
+    let envelope: ErrorEnvelope = serde_json::from_str(&body).unwrap();
+    assert_eq!(envelope.error.code, "INTERNAL_ERROR");
+    assert!(envelope.error.request_id.is_some());
 
-    assert_eq!(country_response.results[5].country, "nigeria");
-    assert_eq!(country_response.results[5].flag, "🇳🇬");
-    assert_eq!(country_response.results[5].currency_code, "NGN");
-    assert_eq!(country_response.results[5].phone_code, "+234");
+    // The panic did not take the connection down with it
+    let (status, _) = keep_alive_get(&mut stream, address, "/healthz").await;
+    assert_eq!(status, 200);
 
-    assert_eq!(country_response.results[6].country, "thailand");
-    assert_eq!(country_response.results[6].flag, "🇹🇭");
-    assert_eq!(country_response.results[6].currency_code, "THB");
-    assert_eq!(country_response.results[6].phone_code, "+66");
+    server.abort();
 }
 
-#[tokio::test]
-async fn test_get_country_african_countries() {
-    let app = create_app();
+// Sends a GET on an open connection and reads one response, leaving the connection open
+async fn keep_alive_get(
+    stream: &mut tokio::net::TcpStream,
+    address: SocketAddr,
+    path: &str,
+) -> (u16, String) {
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, address);
+    stream.write_all(request.as_bytes()).await.unwrap();
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .uri("/getCountry?based=kenya,morocco,ghana,ethiopia")
-                .body(Body::empty())
-                .unwrap(),
-        )
-        .await
+    let mut response = Vec::new();
+    let head_end = loop {
+        if let Some(at) = response.windows(4).position(|window| window == b"\r\n\r\n") {
+            break at + 4;
+        }
+        let mut chunk = [0; 1024];
+        let read = stream.read(&mut chunk).await.unwrap();
+        assert!(read > 0, "the connection was closed");
+        response.extend_from_slice(&chunk[..read]);
+    };
+    let head = String::from_utf8(response[..head_end].to_vec()).unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    let length: usize = head
+        .lines()
+        .find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.eq_ignore_ascii_case("content-length")
+                .then(|| value.trim().parse().unwrap())
+        })
         .unwrap();
+    while response.len() < head_end + length {
+        let mut chunk = [0; 1024];
+        let read = stream.read(&mut chunk).await.unwrap();
+        assert!(read > 0, "the connection was closed");
+        response.extend_from_slice(&chunk[..read]);
+    }
+    let body = String::from_utf8(response[head_end..head_end + length].to_vec()).unwrap();
+    (status, body)
+}
 
-    assert_eq!(response.status(), StatusCode::OK);
+fn write_temp_data_file(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "rusty_currency_{}_{}.json",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
 
-    let body = response.into_body().collect().await.unwrap().to_bytes();
-    let body_str = String::from_utf8(body.to_vec()).unwrap();
-    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+#[test]
+fn test_load_data_file() {
+    let path = write_temp_data_file(
+        "valid",
+        r#"{
+  "countries": [
+    {
+      "name": "Japan",
+      "aliases": ["Nippon", "nihon"],
+      "flag": "🇯🇵",
+      "currencyCode": "JPY",
+      "phoneCode": "+81"
+    },
+    {
+      "name": "korea",
+      "flag": "🇰🇷",
+      "currencyCode": "KRW",
+      "phoneCode": "+82"
+    }
+  ]
+}"#,
+    );
 
-    assert_eq!(country_response.results.len(), 4);
-    assert_eq!(country_response.results[0].flag, "🇰🇪");
-    assert_eq!(country_response.results[0].currency_code, "KES");
-    assert_eq!(country_response.results[0].phone_code, "+254");
-    assert_eq!(country_response.results[1].flag, "🇲🇦");
-    assert_eq!(country_response.results[1].currency_code, "MAD");
-    assert_eq!(country_response.results[1].phone_code, "+212");
+    let data = data_file::load_data_file(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(data.len(), 2);
+    assert_eq!(data.names().count(), 4);
+    let japan = data.get("japan").unwrap();
+    assert_eq!(japan.flag, "🇯🇵");
+    assert_eq!(japan.currency_code, "JPY");
+    assert_eq!(japan.phone_code, "+81");
+    assert_eq!(
+        japan.aliases,
+        vec![Alias::current("nippon"), Alias::current("nihon")]
+    );
+    assert_eq!(data.get("nippon"), Some(japan));
+    assert_eq!(data.get("nihon"), Some(japan));
+    assert_eq!(data.get("korea").unwrap().currency_code, "KRW");
 }
 
-#[tokio::test]
-async fn test_get_country_aliases() {
-    let app = create_app();
+#[test]
+fn test_load_data_file_duplicate_alias() {
+    let path = write_temp_data_file(
+        "duplicate_alias",
+        r#"{
+  "countries": [
+    {"name": "japan", "aliases": ["nippon"], "flag": "🇯🇵", "currencyCode": "JPY", "phoneCode": "+81"},
+    {"name": "korea", "aliases": ["Nippon"], "flag": "🇰🇷", "currencyCode": "KRW", "phoneCode": "+82"}
+  ]
+}"#,
+    );
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .uri("/getCountry?based=uae,czechia,burma,vatican")
-                .body(Body::empty())
-                .unwrap(),
-        )
-        .await
-        .unwrap();
+    let error = data_file::load_data_file(&path).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
 
-    assert_eq!(response.status(), StatusCode::OK);
+    match &error {
+        data_file::DataFileError::Invalid { line, message, .. } => {
+            assert_eq!(*line, 4);
+            assert!(message.contains("country name or alias \"nippon\" is already in use"));
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+    assert!(error.to_string().contains(&path.display().to_string()));
+}
 
-    let body = response.into_body().collect().await.unwrap().to_bytes();
-    let body_str = String::from_utf8(body.to_vec()).unwrap();
-    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+#[test]
+fn test_load_data_file_missing_field() {
+    let path = write_temp_data_file(
+        "missing_field",
+        r#"{
+  "countries": [
+    {"name": "japan", "flag": "🇯🇵", "currencyCode": "JPY", "phoneCode": "+81"},
+    {"name": "korea", "flag": "🇰🇷", "phoneCode": "+82"}
+  ]
+}"#,
+    );
 
-    assert_eq!(country_response.results.len(), 4);
-    assert_eq!(country_response.results[0].flag, "🇦🇪"); // UAE
-    assert_eq!(country_response.results[0].currency_code, "AED");
-    assert_eq!(country_response.results[0].phone_code, "+971");
-    assert_eq!(country_response.results[1].flag, "🇨🇿"); // Czechia
-    assert_eq!(country_response.results[1].currency_code, "CZK");
-    assert_eq!(country_response.results[1].phone_code, "+420");
-    assert_eq!(country_response.results[2].flag, "🇲🇲"); // Burma/Myanmar
-    assert_eq!(country_response.results[2].currency_code, "MMK");
-    assert_eq!(country_response.results[2].phone_code, "+95");
-    assert_eq!(country_response.results[3].flag, "🇻🇦"); // Vatican
-    assert_eq!(country_response.results[3].currency_code, "EUR");
-    assert_eq!(country_response.results[3].phone_code, "+3906698");
+    let error = data_file::load_data_file(&path).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+
+    match &error {
+        data_file::DataFileError::Invalid { line, message, .. } => {
+            assert_eq!(*line, 4);
+            assert!(message.contains("missing field `currencyCode`"));
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
 }
 
 #[test]
-fn test_parse_country_data_skips_malformed_lines() {
-    let csv_data =
-        "country,flag,currencyCode,phoneCode\nvalid,🏳️,VAL,+999\nmissing-flag,,MFG,+000\nmissing-code,🏳️,,+000\nmissing-phone,🏳️,VAL,\n";
-    let reader = std::io::BufReader::new(csv_data.as_bytes());
-    let data = parse_country_data(reader);
+fn test_load_data_file_malformed_currency_code() {
+    let path = write_temp_data_file(
+        "malformed_currency_code",
+        r#"{
+  "countries": [
+    {"name": "japan", "flag": "🇯🇵", "currencyCode": "jpy", "phoneCode": "+81"}
+  ]
+}"#,
+    );
 
-    assert_eq!(data.len(), 1);
+    let error = data_file::load_data_file(&path).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+
+    match &error {
+        data_file::DataFileError::Invalid { line, message, .. } => {
+            assert_eq!(*line, 3);
+            assert!(message.contains("\"jpy\" is not a valid currency code"));
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[test]
+fn test_currency_code_format() {
+    for valid in ["JPY", "USD", "XOF"] {
+        assert_eq!(CurrencyCode::new(valid).unwrap().as_str(), valid);
+    }
+    for invalid in ["", "JP", "JPYY", "jpy", "Jpy", "JP1", " JPY", "ÉUR"] {
+        let error = CurrencyCode::new(invalid).unwrap_err();
+        assert_eq!(error.value, invalid);
+    }
     assert_eq!(
-        data.get("valid"),
-        Some(&(
-            String::from("🏳️"),
-            String::from("VAL"),
-            String::from("+999")
-        ))
+        CurrencyCode::new("yen").unwrap_err().to_string(),
+        "\"yen\" is not a valid currency code: expected 3 uppercase ASCII letters"
     );
 }
 
-#[tokio::test]
-async fn test_get_country_all_parameter() {
-    let app = create_app();
+#[test]
+fn test_alpha2_format() {
+    for valid in ["JP", "US", "CI"] {
+        assert_eq!(codes::Alpha2::new(valid).unwrap().as_str(), valid);
+    }
+    for invalid in ["", "J", "JPN", "jp", "Jp", "J1", "É"] {
+        assert!(codes::Alpha2::new(invalid).is_err(), "{:?}", invalid);
+    }
+}
 
-    let response = app
-        .oneshot(
-            Request::builder()
-                .uri("/getCountry?based=all")
-                .body(Body::empty())
-                .unwrap(),
-        )
-        .await
-        .unwrap();
+#[test]
+fn test_codes_round_trip_through_serde_as_strings() {
+    let currency = CurrencyCode::new("JPY").unwrap();
+    assert_eq!(serde_json::to_string(&currency).unwrap(), r#""JPY""#);
+    assert_eq!(
+        serde_json::from_str::<CurrencyCode>(r#""JPY""#).unwrap(),
+        currency
+    );
+    assert!(serde_json::from_str::<CurrencyCode>(r#""YENS""#).is_err());
 
-    assert_eq!(response.status(), StatusCode::OK);
+    let alpha2 = codes::Alpha2::new("JP").unwrap();
+    assert_eq!(serde_json::to_string(&alpha2).unwrap(), r#""JP""#);
+    assert_eq!(
+        serde_json::from_str::<codes::Alpha2>(r#""JP""#).unwrap(),
+        alpha2
+    );
+    assert!(serde_json::from_str::<codes::Alpha2>(r#""jp""#).is_err());
+}
 
-    let body = response.into_body().collect().await.unwrap().to_bytes();
-    let body_str = String::from_utf8(body.to_vec()).unwrap();
-    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+#[test]
+fn test_builtin_codes_match_their_rows() {
+    let japan = &builtin::BUILTIN_COUNTRIES[builtin::BUILTIN_INDEX["japan"]];
+    assert_eq!(japan.alpha2, "JP");
+    assert_eq!(japan.currency_code, "JPY");
+}
 
-    assert_eq!(country_response.results.len(), COUNTRY_DATA.len());
-    assert!(country_response
+#[test]
+fn test_load_data_file_missing_file() {
+    let path = std::env::temp_dir().join("rusty_currency_does_not_exist.json");
+
+    let error = data_file::load_data_file(&path).unwrap_err();
+
+    assert!(matches!(error, data_file::DataFileError::Io { .. }));
+}
+
+#[test]
+fn test_builtin_dataset_covers_iso_3166() {
+    assert!(builtin::BUILTIN_COUNTRIES.len() >= 240);
+
+    let mut alpha2_codes = std::collections::HashSet::new();
+    for country in &builtin::BUILTIN_COUNTRIES {
+        assert!(alpha2_codes.insert(country.alpha2), "{}", country.alpha2);
+        assert_eq!(country.alpha3.len(), 3, "{}", country.name);
+        assert_eq!(country.numeric.len(), 3, "{}", country.name);
+    }
+}
+
+#[test]
+fn test_builtin_flags_are_regional_indicator_pairs() {
+    for country in &builtin::BUILTIN_COUNTRIES {
+        let indicators: Vec<char> = country.flag.chars().collect();
+        assert_eq!(indicators.len(), 2, "{}", country.name);
+
+        for (indicator, letter) in indicators.iter().zip(country.alpha2.as_str().chars()) {
+            assert!(
+                ('\u{1F1E6}'..='\u{1F1FF}').contains(indicator),
+                "{}",
+                country.name
+            );
+            assert_eq!(
+                *indicator as u32 - 0x1F1E6,
+                letter as u32 - 'A' as u32,
+                "{}",
+                country.name
+            );
+        }
+    }
+}
+
+// Everything that must hold of the built-in data, as one message per broken rule
+fn dataset_invariant_violations(dataset: &Dataset) -> Vec<String> {
+    let rates = rate_snapshot::RateSnapshot::builtin();
+    let mut violations = Vec::new();
+    let mut names = std::collections::HashSet::new();
+    let mut codes = std::collections::HashMap::new();
+    for record in dataset.records() {
+        if !names.insert(record.name.as_str()) {
+            violations.push(format!("{} is listed twice", record.name));
+        }
+        // Two regional indicators, spelling the country's alpha-2 code
+        match codes::Alpha2::from_flag(&record.flag) {
+            Some(alpha2) => {
+                if let Some(other) = codes.insert(alpha2, record.name.as_str()) {
+                    violations.push(format!("{} and {} share {}", other, record.name, alpha2));
+                }
+            }
+            None => violations.push(format!("{} has the flag {:?}", record.name, record.flag)),
+        }
+        if rates.rate(record.currency_code).is_none() {
+            violations.push(format!(
+                "{} uses the unknown currency {}",
+                record.name, record.currency_code
+            ));
+        }
+    }
+    for record in dataset.records() {
+        let Some(alpha2) = codes::Alpha2::from_flag(&record.flag) else {
+            continue;
+        };
+        for neighbour in &record.borders {
+            let symmetric = dataset
+                .by_alpha2(*neighbour)
+                .is_some_and(|neighbour| neighbour.borders.contains(&alpha2));
+            if !symmetric {
+                violations.push(format!(
+                    "{} borders {} but not back",
+                    record.name, neighbour
+                ));
+            }
+        }
+    }
+    violations
+}
+
+#[test]
+fn test_builtin_dataset_invariants() {
+    assert_eq!(
+        dataset_invariant_violations(&builtin::dataset()),
+        Vec::<String>::new()
+    );
+
+    let mut alpha3_codes = std::collections::HashSet::new();
+    let mut numeric_codes = std::collections::HashSet::new();
+    for country in &builtin::BUILTIN_COUNTRIES {
+        assert!(alpha3_codes.insert(country.alpha3), "{}", country.alpha3);
+        assert!(numeric_codes.insert(country.numeric), "{}", country.numeric);
+    }
+}
+
+#[test]
+fn test_dataset_invariants_catch_broken_records() {
+    let mut dataset = builtin::dataset();
+    let mut japan = dataset.get("japan").unwrap().clone();
+    japan.currency_code = CurrencyCode::new("JPZ").unwrap();
+    dataset.replace("japan", japan).unwrap();
+    let mut france = dataset.get("france").unwrap().clone();
+    france.borders.push(codes::Alpha2::new("JP").unwrap());
+    dataset.replace("france", france).unwrap();
+
+    assert_eq!(
+        dataset_invariant_violations(&dataset),
+        [
+            "japan uses the unknown currency JPZ",
+            "france borders JP but not back"
+        ]
+    );
+}
+
+// The JSON export of the built-in data, as `/checksum` hashes it. Run the tests with
+// `UPDATE_SNAPSHOTS=1` to rewrite it after changing the data on purpose.
+const DATASET_SNAPSHOT: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/snapshots/builtin_dataset.json"
+);
+
+#[test]
+fn test_builtin_dataset_matches_snapshot() {
+    let export = export::to_json(&builtin::dataset());
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::write(DATASET_SNAPSHOT, &export).unwrap();
+    }
+    let snapshot = std::fs::read_to_string(DATASET_SNAPSHOT).unwrap();
+
+    if let Some((line, (expected, actual))) = snapshot
+        .lines()
+        .zip(export.lines())
+        .enumerate()
+        .find(|(_, (expected, actual))| expected != actual)
+    {
+        panic!(
+            "the built-in dataset differs from {} at line {}:\n  snapshot: {}\n  dataset:  {}\n\
+             rerun with UPDATE_SNAPSHOTS=1 if the change is intended",
+            DATASET_SNAPSHOT,
+            line + 1,
+            expected.trim(),
+            actual.trim()
+        );
+    }
+    assert_eq!(
+        snapshot.len(),
+        export.len(),
+        "rerun with UPDATE_SNAPSHOTS=1 if the change is intended"
+    );
+    assert_eq!(
+        export::checksum(&builtin::dataset()),
+        format!("{:x}", Sha256::digest(&snapshot))
+    );
+}
+
+#[test]
+fn test_changed_currency_code_changes_the_checksum() {
+    let builtin = builtin::dataset();
+    let mut dataset = builtin::dataset();
+    let mut japan = dataset.get("japan").unwrap().clone();
+    japan.currency_code = CurrencyCode::new("JPZ").unwrap();
+    dataset.replace("japan", japan).unwrap();
+
+    assert_ne!(export::checksum(&dataset), export::checksum(&builtin));
+    let snapshot = std::fs::read_to_string(DATASET_SNAPSHOT).unwrap();
+    assert_ne!(export::to_json(&dataset), snapshot);
+}
+
+#[test]
+fn test_builtin_dataset_matches_legacy_csv() {
+    let legacy = parse_country_data(std::io::BufReader::new(
+        include_str!("../tests/fixtures/legacy_countries.csv").as_bytes(),
+    ));
+    let builtin = builtin::dataset();
+
+    assert_eq!(legacy.len(), 215);
+    for (name, (flag, currency_code, phone_code)) in &legacy {
+        let record = builtin.get(name).unwrap_or_else(|| panic!("{}", name));
+        assert_eq!(&record.flag, flag, "{}", name);
+        assert_eq!(&record.currency_code, currency_code, "{}", name);
+        assert_eq!(&record.phone_code, phone_code, "{}", name);
+    }
+}
+
+#[test]
+fn test_builtin_dataset_includes_territories() {
+    let data = builtin::dataset();
+
+    assert_eq!(data.get("hong kong").unwrap().currency_code, "HKD");
+    assert_eq!(data.get("taiwan").unwrap().flag, "🇹🇼");
+    assert_eq!(data.get("curacao"), data.get("curaçao"));
+}
+
+// The perfect-hash-backed dataset with every record copied into owned maps
+fn owned_builtin_dataset() -> Dataset {
+    let mut owned = Dataset::default();
+    for record in builtin::dataset().records() {
+        owned.insert(record.clone()).unwrap();
+    }
+    owned
+}
+
+#[test]
+fn test_builtin_index_resolves_every_name() {
+    let builtin = builtin::dataset();
+    let owned = owned_builtin_dataset();
+
+    assert_eq!(builtin::BUILTIN_INDEX.len(), owned.names().count());
+    for (name, record) in builtin.names() {
+        assert_eq!(
+            record.name,
+            builtin::BUILTIN_COUNTRIES[builtin::BUILTIN_INDEX[name]].name
+        );
+        assert_eq!(owned.get(name), Some(record), "{}", name);
+    }
+    assert_eq!(builtin, owned);
+    assert_eq!(builtin.diff(&owned), dataset::DatasetDiff::default());
+}
+
+#[test]
+fn test_aliases_share_one_record() {
+    let builtin = builtin::dataset();
+    let owned = owned_builtin_dataset();
+
+    for dataset in [&builtin, &owned] {
+        let usa = dataset.shared("usa").unwrap();
+        assert!(Arc::ptr_eq(usa, dataset.shared("united states").unwrap()));
+        assert!(!Arc::ptr_eq(usa, dataset.shared("uk").unwrap()));
+        assert!(Arc::ptr_eq(
+            dataset.shared("uk").unwrap(),
+            dataset.shared("united kingdom").unwrap()
+        ));
+    }
+    // Copying the built-in table shares its records rather than duplicating them
+    let mut edited = builtin::dataset();
+    edited.remove("wakanda");
+    edited.remove("côte d'ivoire");
+    assert!(Arc::ptr_eq(
+        edited.shared("usa").unwrap(),
+        builtin.shared("united states").unwrap()
+    ));
+}
+
+#[test]
+fn test_builtin_dataset_copies_on_first_change() {
+    let mut dataset = builtin::dataset();
+    let removed = dataset.remove("ivory coast").unwrap();
+
+    assert_eq!(removed.name, "côte d'ivoire");
+    assert_eq!(dataset.len(), builtin::BUILTIN_COUNT - 1);
+    assert!(dataset.get("côte d'ivoire").is_none());
+    assert!(builtin::dataset().get("ivory coast").is_some());
+}
+
+#[tokio::test]
+async fn test_builtin_dataset_serves_same_responses_as_owned_copy() {
+    let builtin = create_app_with_dataset(builtin::dataset());
+    let owned = create_app_with_dataset(owned_builtin_dataset());
+
+    let all = serde_json::to_value(get_countries(&builtin, "all").await).unwrap();
+    assert_eq!(
+        all,
+        serde_json::to_value(get_countries(&owned, "all").await).unwrap()
+    );
+
+    for name in builtin::BUILTIN_INDEX.keys() {
+        let based: String = name
+            .bytes()
+            .map(|byte| match byte {
+                b'a'..=b'z' | b'0'..=b'9' => (byte as char).to_string(),
+                _ => format!("%{:02X}", byte),
+            })
+            .collect();
+        assert_eq!(
+            serde_json::to_value(get_countries(&builtin, &based).await).unwrap(),
+            serde_json::to_value(get_countries(&owned, &based).await).unwrap(),
+            "{}",
+            name
+        );
+    }
+}
+
+const ADMIN_TOKEN: &str = "test-admin-token";
+const ADMIN_AUTHORIZATION: &str = "Bearer test-admin-token";
+
+fn admin_config() -> AppConfig {
+    AppConfig {
+        admin_token: Some(String::from(ADMIN_TOKEN)),
+        ..AppConfig::default()
+    }
+}
+
+#[tokio::test]
+async fn test_basic_auth_is_turned_away_while_every_verifier_is_busy() {
+    use admin_auth::{AdminCredentials, AdminPrincipal, BasicAuth, VerifierBusy};
+    use axum::{http::header, response::IntoResponse};
+
+    let hash = admin_auth::hash_password("hunter2");
+    let credentials = Arc::new(AdminCredentials::new(
+        Some(String::from(ADMIN_TOKEN)),
+        Some(BasicAuth::new(String::from("ops"), hash).unwrap()),
+    ));
+    let headers = |authorization| {
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static(authorization),
+        );
+        headers
+    };
+    // `ops:hunter2`
+    let basic = headers("Basic b3BzOmh1bnRlcjI=");
+    let permits = credentials.verifiers.available_permits() as u32;
+    assert!(permits >= 1);
+    let taken = Arc::clone(&credentials.verifiers)
+        .try_acquire_many_owned(permits)
+        .unwrap();
+
+    assert_eq!(
+        Arc::clone(&credentials).check(&basic).await,
+        Err(VerifierBusy)
+    );
+    let response = ApiError::from(VerifierBusy).into_response();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+    // The token needs no hashing, so it is let in however busy the verifiers are
+    assert_eq!(
+        Arc::clone(&credentials)
+            .check(&headers(ADMIN_AUTHORIZATION))
+            .await,
+        Ok(Some(AdminPrincipal(String::from(audit::ADMIN_PRINCIPAL))))
+    );
+
+    drop(taken);
+    assert_eq!(
+        Arc::clone(&credentials).check(&basic).await,
+        Ok(Some(AdminPrincipal(String::from("ops"))))
+    );
+    assert_eq!(credentials.verifiers.available_permits() as u32, permits);
+}
+
+async fn send_json(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    body: serde_json::Value,
+) -> (StatusCode, serde_json::Value) {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("authorization", ADMIN_AUTHORIZATION)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+async fn get_countries(app: &Router, based: &str) -> CountryResponse<'static> {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/getCountry?based={}", based))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+
+    serde_json::from_slice(&body).unwrap()
+}
+
+const RELOAD_ORIGINAL: &str = r#"{
+  "countries": [
+    {"name": "japan", "flag": "🇯🇵", "currencyCode": "JPY", "phoneCode": "+81"},
+    {"name": "korea", "flag": "🇰🇷", "currencyCode": "KRW", "phoneCode": "+82"}
+  ]
+}"#;
+
+#[tokio::test]
+async fn test_reload_data_file_swaps_dataset() {
+    let path = write_temp_data_file("reload_changed", RELOAD_ORIGINAL);
+    let state = AppState::new(data_file::load_data_file(&path).unwrap());
+    let app = create_app_with_state(state.clone(), &AppConfig::default());
+    assert!(get_countries(&app, "france").await.results.is_empty());
+
+    std::fs::write(
+        &path,
+        r#"{
+  "countries": [
+    {"name": "japan", "flag": "🇯🇵", "currencyCode": "JPY", "phoneCode": "+81"},
+    {"name": "korea", "flag": "🇰🇷", "currencyCode": "KRW", "phoneCode": "+820"},
+    {"name": "france", "flag": "🇫🇷", "currencyCode": "EUR", "phoneCode": "+33"}
+  ]
+}"#,
+    )
+    .unwrap();
+    let diff = reload::reload_dataset(&state, &DataFileLoader::new(&path)).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(diff.added, vec!["france"]);
+    assert!(diff.removed.is_empty());
+    assert_eq!(diff.changed, vec!["korea"]);
+
+    let country_response = get_countries(&app, "france,korea").await;
+    assert_eq!(country_response.results.len(), 2);
+    assert_eq!(country_response.results[0].currency_code, "EUR");
+    assert_eq!(country_response.results[1].phone_code, "+820");
+}
+
+#[tokio::test]
+async fn test_reload_invalid_data_file_keeps_old_dataset() {
+    let path = write_temp_data_file("reload_invalid", RELOAD_ORIGINAL);
+    let state = AppState::new(data_file::load_data_file(&path).unwrap());
+    let app = create_app_with_state(state.clone(), &AppConfig::default());
+
+    std::fs::write(
+        &path,
+        r#"{"countries": [{"name": "france", "flag": "🇫🇷", "currencyCode": "EUR"}]}"#,
+    )
+    .unwrap();
+    let error = reload::reload_dataset(&state, &DataFileLoader::new(&path)).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(matches!(
+        error,
+        LoadError::DataFile(data_file::DataFileError::Invalid { .. })
+    ));
+    let country_response = get_countries(&app, "japan,korea,france").await;
+    assert_eq!(country_response.results.len(), 2);
+    assert_eq!(country_response.results[0].currency_code, "JPY");
+}
+
+async fn count_countries(app: &Router) -> usize {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/countries")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let country_response: CountryResponse = serde_json::from_slice(&body).unwrap();
+
+    country_response.results.len()
+}
+
+async fn get_metrics(app: &Router) -> String {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/metrics")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn test_reload_outcomes_counted_in_metrics() {
+    let path = write_temp_data_file("reload_metrics", RELOAD_ORIGINAL);
+    let state = AppState::new(data_file::load_data_file(&path).unwrap());
+    let app = create_app_with_state(state.clone(), &AppConfig::default());
+
+    reload::reload_and_log(&state, &DataFileLoader::new(&path));
+    std::fs::write(&path, "{").unwrap();
+    reload::reload_and_log(&state, &DataFileLoader::new(&path));
+    std::fs::remove_file(&path).unwrap();
+
+    let metrics = get_metrics(&app).await;
+    assert!(metrics.contains("rustincountry_dataset_reloads_total{outcome=\"success\"} 1"));
+    assert!(metrics.contains("rustincountry_dataset_reloads_total{outcome=\"failure\"} 1"));
+}
+
+#[tokio::test]
+async fn test_watch_reloads_changed_data_file() {
+    let directory =
+        std::env::temp_dir().join(format!("rusty_currency_watch_{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let path = directory.join("countries.json");
+    std::fs::write(&path, RELOAD_ORIGINAL).unwrap();
+
+    let state = AppState::new(data_file::load_data_file(&path).unwrap());
+    let app = create_app_with_state(state.clone(), &AppConfig::default());
+    reload::spawn_watch(state, path.clone(), Arc::new(DataFileLoader::new(&path))).unwrap();
+
+    std::fs::write(
+        &path,
+        r#"{"countries": [{"name": "chad", "flag": "🇹🇩", "currencyCode": "XAF", "phoneCode": "+235"}]}"#,
+    )
+    .unwrap();
+
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(10);
+    loop {
+        if !get_countries(&app, "chad").await.results.is_empty() {
+            break;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "watched data file was not reloaded in time"
+        );
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert!(get_countries(&app, "japan").await.results.is_empty());
+    assert!(get_metrics(&app)
+        .await
+        .contains("rustincountry_dataset_reloads_total{outcome=\"success\"} 1"));
+}
+
+const TLS_FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls");
+
+fn tls_fixture(name: &str) -> PathBuf {
+    PathBuf::from(TLS_FIXTURES).join(name)
+}
+
+fn utc(rfc3339: &str) -> std::time::SystemTime {
+    chrono::DateTime::parse_from_rfc3339(rfc3339)
+        .unwrap()
+        .into()
+}
+
+#[test]
+fn test_tls_certificates_read_not_after() {
+    // Valid until 2036, written as UTCTime, and until 2126, as GeneralizedTime
+    let first =
+        tls::TlsCertificates::load(&tls_fixture("first.pem"), &tls_fixture("first.key")).unwrap();
+    assert_eq!(first.not_after(), utc("2036-10-13T00:59:08Z"));
+    let second =
+        tls::TlsCertificates::load(&tls_fixture("second.pem"), &tls_fixture("second.key")).unwrap();
+    assert_eq!(second.not_after(), utc("2126-09-22T00:59:08Z"));
+
+    let error = tls::TlsCertificates::load(&tls_fixture("first.pem"), &tls_fixture("second.key"))
+        .unwrap_err();
+    assert!(
+        matches!(error, tls::TlsError::KeyMismatch { .. }),
+        "{}",
+        error
+    );
+    let error = tls::TlsCertificates::load(&tls_fixture("first.key"), &tls_fixture("first.key"))
+        .unwrap_err();
+    assert!(matches!(error, tls::TlsError::Pem { .. }), "{}", error);
+}
+
+#[test]
+fn test_tls_reload_keeps_the_certificate_when_the_new_files_are_invalid() {
+    let directory =
+        std::env::temp_dir().join(format!("rusty_currency_tls_reload_{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let (cert, key) = (directory.join("cert.pem"), directory.join("key.pem"));
+    std::fs::copy(tls_fixture("first.pem"), &cert).unwrap();
+    std::fs::copy(tls_fixture("first.key"), &key).unwrap();
+    let certificates = tls::TlsCertificates::load(&cert, &key).unwrap();
+
+    // A certificate renewed before its key
+    std::fs::copy(tls_fixture("second.pem"), &cert).unwrap();
+    assert!(matches!(
+        certificates.reload(),
+        Err(tls::TlsError::KeyMismatch { .. })
+    ));
+    assert_eq!(certificates.not_after(), utc("2036-10-13T00:59:08Z"));
+    assert!(certificates
+        .last_error()
+        .unwrap()
+        .contains("does not match"));
+
+    std::fs::write(&key, "not a key").unwrap();
+    assert!(certificates.reload().is_err());
+    assert_eq!(certificates.not_after(), utc("2036-10-13T00:59:08Z"));
+
+    std::fs::copy(tls_fixture("second.key"), &key).unwrap();
+    certificates.reload().unwrap();
+    assert_eq!(certificates.not_after(), utc("2126-09-22T00:59:08Z"));
+    assert_eq!(certificates.last_error(), None);
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+// Trusts whatever the server presents, as the fixtures are self-signed
+#[derive(Debug)]
+struct AcceptAnyCertificate(Arc<rustls::crypto::CryptoProvider>);
+
+impl rustls::client::danger::ServerCertVerifier for AcceptAnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _: &rustls::pki_types::CertificateDer<'_>,
+        _: &[rustls::pki_types::CertificateDer<'_>],
+        _: &rustls::pki_types::ServerName<'_>,
+        _: &[u8],
+        _: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+type TlsClientStream = tokio_rustls::client::TlsStream<tokio::net::TcpStream>;
+
+// A TLS connection to `address`, with the DER certificate the server presented
+async fn tls_connect(address: SocketAddr) -> (TlsClientStream, Vec<u8>) {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyCertificate(provider)))
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    let stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    let stream = tokio_rustls::TlsConnector::from(Arc::new(config))
+        .connect("localhost".try_into().unwrap(), stream)
+        .await
+        .unwrap();
+    let presented = stream.get_ref().1.peer_certificates().unwrap()[0].to_vec();
+    (stream, presented)
+}
+
+async fn tls_get(mut stream: TlsClientStream, path: &str) -> (u16, String) {
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        path
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    let response = String::from_utf8(response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    (
+        head.split_whitespace().nth(1).unwrap().parse().unwrap(),
+        body.to_string(),
+    )
+}
+
+fn pem_der(name: &str) -> Vec<u8> {
+    use rustls::pki_types::pem::PemObject;
+    rustls::pki_types::CertificateDer::from_pem_file(tls_fixture(name))
+        .unwrap()
+        .to_vec()
+}
+
+#[tokio::test]
+async fn test_tls_serves_a_replaced_certificate_to_new_connections() {
+    let directory =
+        std::env::temp_dir().join(format!("rusty_currency_tls_watch_{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    let (cert, key) = (directory.join("cert.pem"), directory.join("key.pem"));
+    std::fs::copy(tls_fixture("first.pem"), &cert).unwrap();
+    std::fs::copy(tls_fixture("first.key"), &key).unwrap();
+    let certificates = Arc::new(tls::TlsCertificates::load(&cert, &key).unwrap());
+    tls::spawn_watch(certificates.clone()).unwrap();
+
+    let listeners = bind_listeners(&["127.0.0.1:0".parse().unwrap()]).unwrap();
+    let address = listeners[0].local_addr().unwrap();
+    let state = AppState::new(builtin::dataset()).with_tls(certificates.clone());
+    let app = create_app_with_state(state, &AppConfig::default());
+    let options = ServeOptions {
+        tls: Some(certificates.clone()),
+        ..ServeOptions::default()
+    };
+    let server = tokio::spawn(async move {
+        run_server_until(listeners, app, std::future::pending(), options).await
+    });
+
+    let (open, presented) = tls_connect(address).await;
+    assert_eq!(presented, pem_der("first.pem"));
+
+    // The key first, as a certificate manager might; the pair only matches once both land
+    std::fs::copy(tls_fixture("second.key"), &key).unwrap();
+    std::fs::copy(tls_fixture("second.pem"), &cert).unwrap();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+    while certificates.not_after() != utc("2126-09-22T00:59:08Z") {
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "the replaced certificate was not reloaded in time"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let (stream, presented) = tls_connect(address).await;
+    assert_eq!(presented, pem_der("second.pem"));
+    let (status, body) = tls_get(stream, "/healthz?verbose=true").await;
+    assert_eq!(status, 200);
+    let health: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(health["tls"]["notAfter"], "Sun, 22 Sep 2126 00:59:08 GMT");
+    assert_eq!(health["components"]["tls"]["status"], "ok");
+
+    // The connection made before the swap is left alone
+    let (status, _) = tls_get(open, "/getCountry?based=japan").await;
+    assert_eq!(status, 200);
+
+    server.abort();
+    std::fs::remove_dir_all(&directory).unwrap();
+}
+
+fn seeded_test_database() -> rusqlite::Connection {
+    let connection = rusqlite::Connection::open_in_memory().unwrap();
+    database::create_schema(&connection).unwrap();
+    connection
+        .execute_batch(
+            "INSERT INTO countries (name, flag, currency_code, phone_code) VALUES ('japan', '🇯🇵', 'JPY', '+81');
+             INSERT INTO countries (name, flag, currency_code, phone_code) VALUES ('Korea', '🇰🇷', 'KRW', '+82');
+             INSERT INTO aliases (alias, country) VALUES ('nippon', 'japan');
+             INSERT INTO aliases (alias, country) VALUES ('South Korea', 'Korea');",
+        )
+        .unwrap();
+    connection
+}
+
+#[tokio::test]
+async fn test_database_countries_served_end_to_end() {
+    let connection = seeded_test_database();
+    let app = create_app_with_dataset(database::load_countries(&connection).unwrap());
+
+    let country_response = get_countries(&app, "Nippon,south%20korea,france").await;
+    assert_eq!(country_response.results.len(), 2);
+    assert_eq!(country_response.results[0].country, "Nippon");
+    assert_eq!(country_response.results[0].currency_code, "JPY");
+    assert_eq!(country_response.results[1].phone_code, "+82");
+    assert_eq!(count_countries(&app).await, 2);
+}
+
+#[test]
+fn test_database_rejects_invalid_rows() {
+    let connection = seeded_test_database();
+    connection
+        .execute(
+            "INSERT INTO countries (name, flag, currency_code, phone_code) VALUES ('france', 'FR', 'EUR', '+33')",
+            [],
+        )
+        .unwrap();
+
+    match database::load_countries(&connection).unwrap_err() {
+        database::DatabaseError::InvalidCountry { name, error } => {
+            assert_eq!(name, "france");
+            assert!(matches!(error, RecordError::Invalid { field: "flag", .. }));
+        }
+        other => panic!("unexpected error: {}", other),
+    }
+}
+
+#[test]
+fn test_database_alias_kinds() {
+    let connection = seeded_test_database();
+    connection
+        .execute(
+            "INSERT INTO aliases (alias, country, kind) VALUES ('dai nippon', 'japan', 'historical')",
+            [],
+        )
+        .unwrap();
+    let dataset = database::load_countries(&connection).unwrap();
+    let japan = dataset.get("japan").unwrap();
+    assert_eq!(japan.name_status("dai nippon"), AliasKind::Historical);
+    assert_eq!(japan.name_status("nippon"), AliasKind::Current);
+
+    connection
+        .execute(
+            "INSERT INTO aliases (alias, country, kind) VALUES ('nihon', 'japan', 'poetic')",
+            [],
+        )
+        .unwrap();
+    match database::load_countries(&connection).unwrap_err() {
+        database::DatabaseError::InvalidCountry { name, error } => {
+            assert_eq!(name, "japan");
+            assert!(matches!(
+                error,
+                RecordError::Invalid {
+                    field: "aliases",
+                    ..
+                }
+            ));
+        }
+        other => panic!("unexpected error: {}", other),
+    }
+}
+
+#[test]
+fn test_database_without_alias_kinds_reads_and_migrates() {
+    let mut connection = rusqlite::Connection::open_in_memory().unwrap();
+    connection
+        .execute_batch(
+            "CREATE TABLE countries (name TEXT PRIMARY KEY, flag TEXT, currency_code TEXT, phone_code TEXT);
+             CREATE TABLE aliases (alias TEXT PRIMARY KEY, country TEXT);
+             CREATE TABLE localized_names (country TEXT, language TEXT, name TEXT);
+             CREATE TABLE bounding_boxes (country TEXT, min_lat REAL, min_lng REAL, max_lat REAL, max_lng REAL);
+             INSERT INTO countries VALUES ('japan', '🇯🇵', 'JPY', '+81');
+             INSERT INTO aliases VALUES ('nippon', 'japan');",
+        )
+        .unwrap();
+    let records = database::read_records(&connection).unwrap();
+    assert_eq!(records[0].aliases, vec![Alias::current("nippon")]);
+    assert_eq!(records[0].tld, None);
+
+    // Countries already exist, so nothing is seeded, but the columns are added
+    assert_eq!(database::migrate(&mut connection).unwrap(), 0);
+    connection
+        .execute_batch(
+            "UPDATE aliases SET kind = 'colloquial';
+             UPDATE countries SET tld = '.jp', continent = 'asia';",
+        )
+        .unwrap();
+    let records = database::read_records(&connection).unwrap();
+    assert_eq!(records[0].aliases[0].kind, AliasKind::Colloquial);
+    assert_eq!(records[0].tld.as_deref(), Some(".jp"));
+    assert_eq!(records[0].continent, Some(dataset::Continent::Asia));
+}
+
+#[test]
+fn test_database_timezones() {
+    let mut connection = rusqlite::Connection::open_in_memory().unwrap();
+    database::migrate(&mut connection).unwrap();
+    let dataset = database::load_countries(&connection).unwrap();
+    assert_eq!(dataset.get("japan").unwrap().timezones, ["Asia/Tokyo"]);
+
+    connection
+        .execute("INSERT INTO timezones VALUES ('japan', 'Asia/Edo')", [])
+        .unwrap();
+    match database::load_countries(&connection).unwrap_err() {
+        database::DatabaseError::InvalidCountry { name, error } => {
+            assert_eq!(name, "japan");
+            assert!(matches!(
+                error,
+                RecordError::Invalid {
+                    field: "timezones",
+                    ..
+                }
+            ));
+        }
+        other => panic!("unexpected error: {}", other),
+    }
+
+    connection.execute_batch("DROP TABLE timezones").unwrap();
+    let dataset = database::load_countries(&connection).unwrap();
+    assert!(dataset.get("japan").unwrap().timezones.is_empty());
+}
+
+#[test]
+fn test_database_languages() {
+    let mut connection = rusqlite::Connection::open_in_memory().unwrap();
+    database::migrate(&mut connection).unwrap();
+    let dataset = database::load_countries(&connection).unwrap();
+    assert_eq!(
+        dataset.get("switzerland").unwrap().languages,
+        ["de", "fr", "it", "rm"]
+    );
+    assert_eq!(dataset.get("japan").unwrap().languages, ["ja"]);
+
+    connection
+        .execute("INSERT INTO languages VALUES ('japan', 'Japanese')", [])
+        .unwrap();
+    match database::load_countries(&connection).unwrap_err() {
+        database::DatabaseError::InvalidCountry { name, error } => {
+            assert_eq!(name, "japan");
+            assert!(matches!(
+                error,
+                RecordError::Invalid {
+                    field: "languages",
+                    ..
+                }
+            ));
+        }
+        other => panic!("unexpected error: {}", other),
+    }
+
+    // Before the table existed no country had languages
+    connection.execute_batch("DROP TABLE languages").unwrap();
+    let dataset = database::load_countries(&connection).unwrap();
+    assert!(dataset.get("japan").unwrap().languages.is_empty());
+    database::migrate(&mut connection).unwrap();
+    assert!(database::load_countries(&connection).is_ok());
+}
+
+#[test]
+fn test_database_continents() {
+    let connection = seeded_test_database();
+    connection
+        .execute(
+            "UPDATE countries SET continent = 'asia' WHERE name = 'japan'",
+            [],
+        )
+        .unwrap();
+    let dataset = database::load_countries(&connection).unwrap();
+    assert_eq!(
+        dataset.get("japan").unwrap().continent,
+        Some(dataset::Continent::Asia)
+    );
+    assert_eq!(dataset.get("korea").unwrap().continent, None);
+
+    connection
+        .execute(
+            "UPDATE countries SET continent = 'atlantis' WHERE name = 'japan'",
+            [],
+        )
+        .unwrap();
+    match database::load_countries(&connection).unwrap_err() {
+        database::DatabaseError::InvalidCountry { name, error } => {
+            assert_eq!(name, "japan");
+            assert!(matches!(
+                error,
+                RecordError::Invalid {
+                    field: "continent",
+                    ..
+                }
+            ));
+        }
+        other => panic!("unexpected error: {}", other),
+    }
+}
+
+#[test]
+fn test_aliases_serialize_plainly_unless_they_have_a_kind() {
+    let record: CountryRecord = serde_json::from_str(
+        r#"{"name": "myanmar", "aliases": ["myanma", {"name": "burma", "kind": "historical"}, {"name": "mm", "kind": "current"}],
+            "flag": "🇲🇲", "currencyCode": "MMK", "phoneCode": "+95"}"#,
+    )
+    .unwrap();
+    assert_eq!(record.name_status("burma"), AliasKind::Historical);
+    assert_eq!(record.name_status("myanmar"), AliasKind::Current);
+    assert_eq!(
+        serde_json::to_value(&record.aliases).unwrap(),
+        serde_json::json!(["myanma", {"name": "burma", "kind": "historical"}, "mm"])
+    );
+
+    // Of two spellings that normalize alike, the first and its kind are kept
+    let record = CountryRecord {
+        aliases: vec![
+            Alias {
+                name: String::from("Burma"),
+                kind: AliasKind::Historical,
+            },
+            Alias::current("burma"),
+        ],
+        ..record
+    }
+    .normalized();
+    assert_eq!(record.aliases.len(), 1);
+    assert_eq!(record.name_status("burma"), AliasKind::Historical);
+}
+
+#[test]
+fn test_database_rejects_malformed_currency_code() {
+    let connection = seeded_test_database();
+    connection
+        .execute(
+            "INSERT INTO countries (name, flag, currency_code, phone_code) VALUES ('france', '🇫🇷', 'euro', '+33')",
+            [],
+        )
+        .unwrap();
+
+    match database::load_countries(&connection).unwrap_err() {
+        database::DatabaseError::InvalidCountry { name, error } => {
+            assert_eq!(name, "france");
+            assert!(matches!(
+                error,
+                RecordError::Invalid {
+                    field: "currencyCode",
+                    ..
+                }
+            ));
+        }
+        other => panic!("unexpected error: {}", other),
+    }
+}
+
+#[test]
+fn test_migrate_seeds_builtin_dataset_once() {
+    let mut connection = rusqlite::Connection::open_in_memory().unwrap();
+    let builtin_count = builtin::dataset().len();
+
+    assert_eq!(database::migrate(&mut connection).unwrap(), builtin_count);
+    assert_eq!(database::migrate(&mut connection).unwrap(), 0);
+
+    let dataset = database::load_countries(&connection).unwrap();
+    assert_eq!(dataset.len(), builtin_count);
+    assert_eq!(
+        dataset.names().count(),
+        load_builtin_country_data().unwrap().names().count()
+    );
+    assert_eq!(
+        dataset.get("japan").unwrap().bounding_box,
+        builtin::dataset().get("japan").unwrap().bounding_box
+    );
+    assert_eq!(dataset.get("uk").unwrap().tld.as_deref(), Some(".uk"));
+    assert_eq!(dataset.by_tld(".jp")[0].name, "japan");
+}
+
+#[tokio::test]
+async fn test_reload_from_database_source() {
+    let path =
+        std::env::temp_dir().join(format!("rusty_currency_reload_{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    database::create_schema(&connection).unwrap();
+    connection
+        .execute(
+            "INSERT INTO countries (name, flag, currency_code, phone_code) VALUES ('chad', '🇹🇩', 'XAF', '+235')",
+            [],
+        )
+        .unwrap();
+
+    let source = database::SqliteLoader::new(&path);
+    let state = AppState::new(source.load().unwrap());
+    let app = create_app_with_state(state.clone(), &AppConfig::default());
+    connection
+        .execute(
+            "INSERT INTO countries (name, flag, currency_code, phone_code) VALUES ('peru', '🇵🇪', 'PEN', '+51')",
+            [],
+        )
+        .unwrap();
+    let diff = reload::reload_dataset(&state, &source).unwrap();
+    drop(connection);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(diff.added, vec!["peru"]);
+    assert_eq!(get_countries(&app, "chad,peru").await.results.len(), 2);
+}
+
+fn unchecked_record(
+    name: &str,
+    aliases: &[&str],
+    flag: &str,
+    currency_code: &str,
+) -> CountryRecord {
+    CountryRecord {
+        name: name.to_string(),
+        aliases: aliases.iter().map(|&alias| Alias::current(alias)).collect(),
+        localized_names: BTreeMap::new(),
+        flag: flag.to_string(),
+        currency_code: CurrencyCode::new(currency_code).unwrap(),
+        phone_code: String::from("+1"),
+        bounding_box: None,
+        tld: None,
+        continent: None,
+        languages: Vec::new(),
+        timezones: Vec::new(),
+        latlng: None,
+        borders: Vec::new(),
+    }
+}
+
+#[test]
+fn test_validate_records_reports_every_problem() {
+    use validation::ValidationErrorKind;
+
+    let records = vec![
+        unchecked_record("japan", &["nippon"], "🇯🇵", "JPY"),
+        unchecked_record("France", &[], "FR", "EUR"),
+        unchecked_record("narnia", &[], "🇳🇦", "NRN"),
+        unchecked_record("nihon", &["Nippon"], "🇯🇵", "JPY"),
+        unchecked_record(" ", &[], "🇰🇷", "KRW"),
+    ];
+    let errors = validation::validate_records(&records);
+
+    let kinds: Vec<_> = errors
+        .iter()
+        .map(|error| (error.entry, error.kind.clone()))
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            (
+                2,
+                ValidationErrorKind::InvalidFlag {
+                    flag: String::from("FR")
+                }
+            ),
+            (
+                3,
+                ValidationErrorKind::UnknownCurrencyCode {
+                    code: String::from("NRN")
+                }
+            ),
+            (
+                4,
+                ValidationErrorKind::DuplicateName {
+                    name: String::from("nippon"),
+                    other: String::from("japan"),
+                    other_entry: 1,
+                }
+            ),
+            (5, ValidationErrorKind::EmptyField { field: "name" }),
+        ]
+    );
+    assert_eq!(
+        errors[2].to_string(),
+        "entry 4 (\"nihon\"): name \"nippon\" is already used by entry 1 (\"japan\")"
+    );
+}
+
+#[test]
+fn test_builtin_dataset_passes_check() {
+    let records = BuiltinLoader.records().unwrap();
+
+    assert_eq!(validation::validate_records(&records), Vec::new());
+    assert!(check_source(&BuiltinLoader));
+}
+
+#[test]
+fn test_check_data_file_with_duplicate_alias_fails() {
+    let path = write_temp_data_file(
+        "check_duplicate",
+        r#"{
+  "countries": [
+    {"name": "japan", "aliases": ["nippon"], "flag": "🇯🇵", "currencyCode": "JPY", "phoneCode": "+81"},
+    {"name": "nihon", "aliases": ["nippon"], "flag": "🇯🇵", "currencyCode": "JPY", "phoneCode": "+81"}
+  ]
+}"#,
+    );
+    let source = DataFileLoader::new(&path);
+    let records = source.records().unwrap();
+    let valid = check_source(&source);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(records.len(), 2);
+    assert!(!valid);
+}
+
+async fn get_export(app: &Router, query: &str) -> (StatusCode, String, String) {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri(format!("/admin/export{}", query))
+                .header("authorization", ADMIN_AUTHORIZATION)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get("content-type")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+
+    (
+        status,
+        content_type,
+        String::from_utf8(body.to_vec()).unwrap(),
+    )
+}
+
+#[tokio::test]
+async fn test_export_round_trips_through_data_file_loader() {
+    let state = AppState::new(load_builtin_country_data().unwrap());
+    let app = create_app_with_state(state.clone(), &admin_config());
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "atlantis",
+            "aliases": ["the lost city"],
+            "flag": "🇦🇹",
+            "currencyCode": "ATL",
+            "phoneCode": "+999"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, content_type, body) = get_export(&app, "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type, "application/json");
+
+    let exported =
+        data_file::parse_data_file(body.as_bytes(), std::path::Path::new("export.json")).unwrap();
+    let live = state.source.all();
+    assert_eq!(*live, exported);
+    assert!(exported.get("the lost city").is_some());
+}
+
+#[tokio::test]
+async fn test_reload_bumps_dataset_version() {
+    let path = write_temp_data_file("reload_version", RELOAD_ORIGINAL);
+    let source = DataFileLoader::new(&path);
+    let state = AppState::new(source.load().unwrap());
+
+    reload::reload_dataset(&state, &source).unwrap();
+    std::fs::write(&path, "{").unwrap();
+    reload::reload_dataset(&state, &source).unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(state.current_revision().version, 2);
+}
+
+fn overrides_from(vars: &[(&str, &str)]) -> Result<overrides::Overrides, overrides::OverrideError> {
+    let env: HashMap<String, String> = vars
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    overrides::Overrides::parse(env)
+}
+
+#[tokio::test]
+async fn test_override_replaces_existing_field() {
+    let overrides = overrides_from(&[
+        ("RUSTINCOUNTRY_OVERRIDE_JAPAN", r#"{"currencyCode":"XYZ"}"#),
+        ("PATH", "/usr/bin"),
+    ])
+    .unwrap();
+    let mut dataset = load_builtin_country_data().unwrap();
+    overrides.apply(&mut dataset).unwrap();
+    let app = create_app_with_dataset(dataset);
+
+    let country_response = get_countries(&app, "japan").await;
+    assert_eq!(country_response.results[0].currency_code, "XYZ");
+    assert_eq!(country_response.results[0].flag, "🇯🇵");
+    assert_eq!(country_response.results[0].phone_code, "+81");
+}
+
+#[tokio::test]
+async fn test_override_adds_new_country() {
+    let overrides = overrides_from(&[(
+        "RUSTINCOUNTRY_OVERRIDE_NEW_ATLANTIS",
+        r#"{"flag":"🇦🇹","currencyCode":"ATL","phoneCode":"+999","aliases":["atlantis"]}"#,
+    )])
+    .unwrap();
+    let source = overrides::OverriddenLoader::new(Arc::new(BuiltinLoader), overrides);
+    let app = create_app_with_dataset(source.load().unwrap());
+
+    let country_response = get_countries(&app, "new%20atlantis,Atlantis").await;
+    assert_eq!(country_response.results.len(), 2);
+    assert_eq!(country_response.results[1].currency_code, "ATL");
+    assert_eq!(get_countries(&app, "japan").await.results.len(), 1);
+}
+
+#[test]
+fn test_override_malformed_json_names_variable() {
+    let error =
+        overrides_from(&[("RUSTINCOUNTRY_OVERRIDE_JAPAN", r#"{"currencyCode":"#)]).unwrap_err();
+
+    assert_eq!(error.variable, "RUSTINCOUNTRY_OVERRIDE_JAPAN");
+    assert!(error.to_string().contains("RUSTINCOUNTRY_OVERRIDE_JAPAN"));
+    assert!(error.reason.starts_with("malformed JSON"));
+}
+
+#[test]
+fn test_override_for_new_country_requires_all_fields() {
+    let overrides =
+        overrides_from(&[("RUSTINCOUNTRY_OVERRIDE_ATLANTIS", r#"{"flag":"🇦🇹"}"#)]).unwrap();
+    let mut dataset = load_builtin_country_data().unwrap();
+
+    let error = overrides.apply(&mut dataset).unwrap_err();
+    assert_eq!(error.variable, "RUSTINCOUNTRY_OVERRIDE_ATLANTIS");
+    assert!(dataset.get("atlantis").is_none());
+}
+
+const MERGE_FILE: &str = r#"{
+  "countries": [
+    {"name": "atlantis", "aliases": ["lost city"], "flag": "🇦🇹", "currencyCode": "ATL", "phoneCode": "+999"},
+    {"name": "japan", "flag": "🇯🇵", "currencyCode": "JPY", "phoneCode": "+810"},
+    {"name": "france", "remove": true}
+  ]
+}"#;
+
+fn merge_over_builtin(
+    contents: &str,
+) -> Result<(Dataset, merge::MergeReport), data_file::DataFileError> {
+    merge::merge_data_file(
+        load_builtin_country_data().unwrap(),
+        contents.as_bytes(),
+        std::path::Path::new("merge.json"),
+    )
+}
+
+#[tokio::test]
+async fn test_merge_adds_replaces_and_removes() {
+    let (dataset, report) = merge_over_builtin(MERGE_FILE).unwrap();
+
+    assert_eq!(
+        report,
+        merge::MergeReport {
+            added: vec![String::from("atlantis")],
+            replaced: vec![String::from("japan")],
+            removed: vec![String::from("france")],
+        }
+    );
+    assert_eq!(dataset.len(), load_builtin_country_data().unwrap().len());
+
+    let app = create_app_with_dataset(dataset);
+    let country_response = get_countries(&app, "lost%20city,japan,france").await;
+    assert_eq!(country_response.results.len(), 2);
+    assert_eq!(country_response.results[0].currency_code, "ATL");
+    assert_eq!(country_response.results[1].phone_code, "+810");
+}
+
+#[tokio::test]
+async fn test_merge_keeps_untouched_builtins() {
+    let (dataset, _) = merge_over_builtin(MERGE_FILE).unwrap();
+    let app = create_app_with_dataset(dataset);
+
+    let country_response = get_countries(&app, "germany,usa,united%20kingdom,brazil").await;
+    assert_eq!(country_response.results.len(), 4);
+    assert_eq!(country_response.results[1].currency_code, "USD");
+}
+
+#[test]
+fn test_merge_rejects_unknown_removal_and_repeated_country() {
+    let error =
+        merge_over_builtin(r#"{"countries": [{"name": "narnia", "remove": true}]}"#).unwrap_err();
+    assert!(error.to_string().contains("no built-in country"));
+
+    let error = merge_over_builtin(
+        r#"{"countries": [
+  {"name": "chad", "remove": true},
+  {"name": "chad", "flag": "🇹🇩", "currencyCode": "XAF", "phoneCode": "+235"}
+]}"#,
+    )
+    .unwrap_err();
+    match error {
+        data_file::DataFileError::Invalid { line, message, .. } => {
+            assert_eq!(line, 3);
+            assert!(message.contains("more than once"));
+        }
+        other => panic!("unexpected error: {}", other),
+    }
+}
+
+#[test]
+fn test_number_digits_strips_formatting() {
+    use calling_code::{number_digits, NumberError};
+
+    assert_eq!(number_digits("+81-90-1234-5678").unwrap(), "819012345678");
+    assert_eq!(number_digits(" 1 (242) 555.0100").unwrap(), "12425550100");
+    assert_eq!(number_digits("+1").unwrap(), "1");
+    assert_eq!(number_digits("+"), Err(NumberError::Empty));
+    assert_eq!(
+        number_digits("+81+90"),
+        Err(NumberError::InvalidCharacter('+'))
+    );
+    assert_eq!(
+        number_digits("81x"),
+        Err(NumberError::InvalidCharacter('x'))
+    );
+    assert_eq!(number_digits(&"1".repeat(16)), Err(NumberError::TooLong));
+}
+
+fn calling_code_names(dataset: &Dataset, digits: &str) -> Vec<String> {
+    let mut names: Vec<_> = dataset
+        .by_calling_code(digits)
+        .iter()
+        .map(|record| record.name.clone())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_calling_codes_follow_dataset_changes() {
+    let mut dataset = builtin::dataset();
+    assert_eq!(calling_code_names(&dataset, "8190"), ["japan"]);
+    assert_eq!(calling_code_names(&dataset, "1684"), ["american samoa"]);
+
+    let mut japan = dataset.get("japan").unwrap().clone();
+    japan.phone_code = String::from("+1-684");
+    dataset.replace("japan", japan).unwrap();
+
+    assert!(calling_code_names(&dataset, "8190").is_empty());
+    assert_eq!(
+        calling_code_names(&dataset, "16845550100"),
+        ["american samoa", "japan"]
+    );
+    // An emptied code falls back to the shorter one it extends
+    dataset.remove("american samoa");
+    dataset.remove("japan");
+    assert!(calling_code_names(&dataset, "1684").contains(&String::from("united states")));
+}
+
+#[test]
+fn test_bounding_box_across_antimeridian() {
+    use bounding_box::BoundingBox;
+
+    let fiji = BoundingBox {
+        min_lat: -22.9,
+        min_lng: 174.0,
+        max_lat: -11.5,
+        max_lng: -176.8,
+    };
+    assert!(fiji.crosses_antimeridian());
+    assert!(fiji.contains(-17.7, 178.0));
+    assert!(fiji.contains(-17.7, -179.0));
+    assert!(!fiji.contains(-17.7, 0.0));
+    assert!(!fiji.contains(-30.0, 178.0));
+
+    // 9.2 degrees wide, not the 350.8 between the longitudes going the other way
+    let regular = BoundingBox {
+        min_lng: 0.0,
+        max_lng: 9.2,
+        ..fiji
+    };
+    assert!((fiji.area() - regular.area()).abs() < 1.0);
+}
+
+#[test]
+fn test_bounding_box_validation() {
+    use bounding_box::BoundingBox;
+
+    let valid = BoundingBox {
+        min_lat: 20.0,
+        min_lng: 122.5,
+        max_lat: 46.0,
+        max_lng: 155.2,
+    };
+    assert_eq!(valid.validate(), Ok(()));
+    for invalid in [
+        BoundingBox {
+            min_lat: 47.0,
+            ..valid
+        },
+        BoundingBox {
+            max_lat: 90.1,
+            ..valid
+        },
+        BoundingBox {
+            min_lng: f64::NAN,
+            ..valid
+        },
+        BoundingBox {
+            max_lng: 180.5,
+            ..valid
+        },
+    ] {
+        assert!(invalid.validate().is_err(), "{:?}", invalid);
+    }
+
+    let mut japan = builtin::dataset().get("japan").unwrap().clone();
+    japan.bounding_box = Some(BoundingBox {
+        min_lat: 50.0,
+        ..valid
+    });
+    assert!(matches!(
+        japan.validate(),
+        Err(RecordError::Invalid {
+            field: "boundingBox",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_every_builtin_country_has_a_bounding_box() {
+    for record in builtin::dataset().records() {
+        let bounding_box = record.bounding_box.unwrap();
+        assert_eq!(bounding_box.validate(), Ok(()), "{}", record.name);
+    }
+}
+
+#[test]
+fn test_every_builtin_country_has_a_continent() {
+    let dataset = builtin::dataset();
+    for record in dataset.records() {
+        assert!(record.continent.is_some(), "{}", record.name);
+    }
+    let continent = |name| dataset.get(name).unwrap().continent;
+    assert_eq!(continent("japan"), Some(dataset::Continent::Asia));
+    assert_eq!(continent("mexico"), Some(dataset::Continent::NorthAmerica));
+    assert_eq!(
+        continent("bouvet island"),
+        Some(dataset::Continent::Antarctica)
+    );
+
+    let stats = lookup::stats(&dataset);
+    assert_eq!(stats.continents.values().sum::<usize>(), stats.countries);
+}
+
+#[test]
+fn test_builtin_tlds() {
+    let dataset = builtin::dataset();
+    for record in dataset.records() {
+        let Some(tld) = &record.tld else { continue };
+        assert!(tld::is_tld(tld), "{}: {}", record.name, tld);
+        let alpha2 = codes::Alpha2::from_flag(&record.flag).unwrap();
+        if record.name != "united kingdom" {
+            assert_eq!(*tld, format!(".{}", alpha2.as_str().to_lowercase()));
+        }
+    }
+    assert_eq!(
+        dataset.get("united kingdom").unwrap().tld.as_deref(),
+        Some(".uk")
+    );
+    assert_eq!(dataset.get("western sahara").unwrap().tld, None);
+
+    // The index is rebuilt when the built-in table is first changed
+    let mut changed = dataset.clone();
+    changed.remove("france").unwrap();
+    assert!(changed.by_tld(".fr").is_empty());
+    assert_eq!(changed.by_tld(".uk")[0].name, "united kingdom");
+    assert_eq!(dataset.by_tld(".fr")[0].name, "france");
+}
+
+#[test]
+fn test_flag_images() {
+    for country in builtin::BUILTIN_COUNTRIES.iter() {
+        let svg = flag_image::svg(country.alpha2).unwrap();
+        assert!(svg.starts_with("<svg"), "{}", country.name);
+    }
+
+    use flag_image::ImageFormat;
+    assert_eq!(
+        flag_image::parse_file_name("japan.svg"),
+        Some(("japan", ImageFormat::Svg))
+    );
+    assert_eq!(
+        flag_image::parse_file_name("st. lucia.PNG"),
+        Some(("st. lucia", ImageFormat::Png))
+    );
+    assert_eq!(flag_image::parse_file_name("japan"), None);
+    assert_eq!(flag_image::parse_file_name("japan.gif"), None);
+}
+
+#[test]
+fn test_builtin_languages() {
+    let dataset = builtin::dataset();
+    for record in dataset.records() {
+        for code in &record.languages {
+            assert!(language::name(code).is_some(), "{}: {}", record.name, code);
+        }
+    }
+    let languages = |name| dataset.get(name).unwrap().languages.clone();
+    assert_eq!(languages("canada"), ["en", "fr"]);
+    assert_eq!(languages("philippines"), ["fil", "en"]);
+    assert!(languages("bouvet island").is_empty());
+}
+
+#[test]
+fn test_language_resolution() {
+    let resolve = |query| language::resolve(query);
+    assert_eq!(resolve("french").as_deref(), Some("fr"));
+    assert_eq!(resolve(" French ").as_deref(), Some("fr"));
+    assert_eq!(resolve("FR").as_deref(), Some("fr"));
+    assert_eq!(resolve("Français").as_deref(), Some("fr"));
+    assert_eq!(resolve("francais").as_deref(), Some("fr"));
+    assert_eq!(resolve("Māori").as_deref(), Some("mi"));
+    assert_eq!(resolve("filipino").as_deref(), Some("fil"));
+    // Unknown codes stay codes, for custom data
+    assert_eq!(resolve("tlh").as_deref(), Some("tlh"));
+    assert_eq!(resolve("klingon"), None);
+    assert_eq!(resolve(""), None);
+
+    assert_eq!(language::name("fr"), Some("French"));
+    assert_eq!(language::name("tlh"), None);
+    assert!(language::is_code("fil"));
+    assert!(!language::is_code("FR"));
+    assert!(!language::is_code("f"));
+    assert!(!language::is_code("fren"));
+}
+
+#[test]
+fn test_builtin_timezones() {
+    let dataset = builtin::dataset();
+    for record in dataset.records() {
+        for zone in &record.timezones {
+            assert_eq!(
+                timezone::canonical(zone),
+                Some(zone.as_str()),
+                "{}",
+                record.name
+            );
+        }
+    }
+    assert_eq!(dataset.get("japan").unwrap().timezones, ["Asia/Tokyo"]);
+    assert!(dataset.get("united states").unwrap().timezones.len() > 20);
+    assert!(dataset.get("bouvet island").unwrap().timezones.is_empty());
+
+    assert_eq!(timezone::canonical(" europe/paris "), Some("Europe/Paris"));
+    assert_eq!(timezone::canonical("Mars/Olympus_Mons"), None);
+}
+
+#[test]
+fn test_timezone_normalization() {
+    let record = CountryRecord {
+        timezones: vec![
+            String::from("asia/tokyo"),
+            String::from("Asia/Tokyo"),
+            String::from(" "),
+        ],
+        ..builtin::dataset().get("japan").unwrap().clone()
+    }
+    .normalized();
+    assert_eq!(record.timezones, ["Asia/Tokyo"]);
+    assert!(record.validate().is_ok());
+
+    let record = CountryRecord {
+        timezones: vec![String::from("Asia/Edo")],
+        ..record
+    }
+    .normalized();
+    assert!(matches!(
+        record.validate(),
+        Err(RecordError::Invalid {
+            field: "timezones",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_offset_parsing() {
+    assert_eq!(timezone::parse_offset("+09:00"), Ok(9 * 3600));
+    assert_eq!(timezone::parse_offset(" 09:00"), Ok(9 * 3600));
+    assert_eq!(timezone::parse_offset("+0930"), Ok(9 * 3600 + 30 * 60));
+    assert_eq!(timezone::parse_offset("-03:30"), Ok(-(3 * 3600 + 30 * 60)));
+    assert_eq!(timezone::parse_offset("-05"), Ok(-5 * 3600));
+    assert_eq!(timezone::parse_offset("+14:00"), Ok(14 * 3600));
+    assert_eq!(timezone::parse_offset("00:00"), Ok(0));
+
+    for malformed in [
+        "",
+        "+",
+        "+9",
+        "+9:00",
+        "+09:60",
+        "+09:0",
+        "09h",
+        "UTC",
+        "+09:00:00",
+    ] {
+        assert!(
+            matches!(
+                timezone::parse_offset(malformed),
+                Err(timezone::OffsetError::Malformed(_))
+            ),
+            "{:?}",
+            malformed
+        );
+    }
+    for out_of_range in ["+15:00", "-12:30", "+99"] {
+        assert!(
+            matches!(
+                timezone::parse_offset(out_of_range),
+                Err(timezone::OffsetError::OutOfRange(_))
+            ),
+            "{:?}",
+            out_of_range
+        );
+    }
+}
+
+#[test]
+fn test_countries_by_offset_follow_daylight_saving_time() {
+    use chrono::TimeZone;
+
+    let dataset = builtin::dataset();
+    let winter = chrono::Utc.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap();
+    let summer = chrono::Utc.with_ymd_and_hms(2024, 7, 15, 12, 0, 0).unwrap();
+    let at = |offset, now| -> Vec<String> {
+        lookup::countries_by_offset(&dataset, offset, now, "en")
+            .iter()
+            .map(|info| info.country.to_string())
+            .collect()
+    };
+    let hours = |hours: i32| hours * 3600;
+
+    // Japan and South Korea keep +09:00 all year
+    for now in [winter, summer] {
+        let nine = at(hours(9), now);
+        assert!(nine.contains(&String::from("japan")), "{}", now);
+        assert!(nine.contains(&String::from("south korea")), "{}", now);
+    }
+
+    // France is at +01:00 in winter and +02:00 in summer
+    assert!(at(hours(1), winter).contains(&String::from("france")));
+    assert!(!at(hours(1), summer).contains(&String::from("france")));
+    assert!(at(hours(2), summer).contains(&String::from("france")));
+    assert!(!at(hours(2), winter).contains(&String::from("france")));
+
+    // The United Kingdom moves from UTC to +01:00
+    assert!(at(0, winter).contains(&String::from("united kingdom")));
+    assert!(at(hours(1), summer).contains(&String::from("united kingdom")));
+    assert!(!at(0, summer).contains(&String::from("united kingdom")));
+
+    // In the southern hemisphere the seasons are the other way round: Sydney is at
+    // +11:00 in January and +10:00 in July, Brisbane at +10:00 all year
+    assert!(at(hours(11), winter).contains(&String::from("australia")));
+    assert!(!at(hours(11), summer).contains(&String::from("australia")));
+
+    let sorted = {
+        let mut names = at(hours(9), winter);
+        names.sort();
+        names
+    };
+    assert_eq!(at(hours(9), winter), sorted);
+}
+
+#[test]
+fn test_tld_normalization() {
+    assert_eq!(tld::normalize(".JP").as_deref(), Some(".jp"));
+    assert_eq!(tld::normalize(" jp ").as_deref(), Some(".jp"));
+    assert_eq!(tld::normalize("."), None);
+    assert_eq!(tld::normalize(""), None);
+
+    assert!(tld::is_tld(".jp"));
+    assert!(tld::is_tld(".рф"));
+    assert!(!tld::is_tld("jp"));
+    assert!(!tld::is_tld(".co.jp"));
+    assert!(!tld::is_tld(".xn--p1ai"));
+}
+
+#[test]
+fn test_currency_symbols() {
+    let codes = |symbol| -> Vec<String> {
+        currency_symbol::currencies(symbol)
+            .iter()
+            .map(|code| code.to_string())
+            .collect()
+    };
+
+    assert_eq!(codes("¥"), ["CNY", "JPY"]);
+    assert_eq!(codes(" € "), ["EUR"]);
+    assert_eq!(codes("KR"), codes("kr"));
+    assert!(codes("¤").is_empty());
+    assert!(codes("").is_empty());
+    for symbol in ["$", "£", "₨", "HK$", "zł"] {
+        let codes = codes(symbol);
+        assert!(!codes.is_empty(), "{}", symbol);
+        assert!(codes.windows(2).all(|pair| pair[0] < pair[1]), "{}", symbol);
+        for code in &codes {
+            assert!(validation::is_known_currency_code(code), "{}", code);
+        }
+    }
+}
+
+#[test]
+fn test_code_index_follows_dataset_changes() {
+    let japan_code = codes::Alpha2::new("JP").unwrap();
+    let mut dataset = builtin::dataset();
+    assert_eq!(dataset.by_alpha2(japan_code).unwrap().name, "japan");
+
+    let mut cipangu = unchecked_record("cipangu", &[], "🇯🇵", "JPY");
+    cipangu.phone_code = String::from("+81");
+    dataset.insert(cipangu).unwrap();
+    // The first country added with the flag keeps it
+    assert_eq!(dataset.by_alpha2(japan_code).unwrap().name, "japan");
+
+    dataset.remove("japan");
+    assert_eq!(dataset.by_alpha2(japan_code).unwrap().name, "cipangu");
+    dataset.remove("cipangu");
+    assert!(dataset.by_alpha2(japan_code).is_none());
+}
+
+// (all matched, stdout, stderr) of the offline lookup command
+fn run_lookup(names: &[&str], format: cli::OutputFormat) -> (bool, String, String) {
+    let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+    let (mut out, mut err) = (Vec::new(), Vec::new());
+    let matched = cli::lookup(
+        &builtin::dataset(),
+        &names,
+        dataset::DEFAULT_LANGUAGE,
+        format,
+        &mut out,
+        &mut err,
+    )
+    .unwrap();
+    (
+        matched,
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+    )
+}
+
+#[test]
+fn test_cli_lookup_prints_aligned_table() {
+    let (matched, out, err) = run_lookup(&["Japan", " united states "], cli::OutputFormat::Table);
+
+    assert!(matched);
+    assert_eq!(
+        out,
+        "COUNTRY        NAME           CURRENCY  PHONE  FLAG\n\
+         Japan          Japan          JPY       +81    🇯🇵\n\
+         united states  United States  USD       +1     🇺🇸\n"
+    );
+    assert_eq!(err, "");
+}
+
+#[test]
+fn test_cli_lookup_prints_getcountry_json() {
+    let (matched, out, _) = run_lookup(&["japan"], cli::OutputFormat::Json);
+
+    assert!(matched);
+    assert_eq!(
+        out,
+        "{\"results\":[{\"country\":\"japan\",\"canonicalName\":\"japan\",\"localizedName\":\"Japan\",\"flag\":\"🇯🇵\",\"currencyCode\":\"JPY\",\"phoneCode\":\"+81\",\"nameStatus\":\"current\"}]}\n"
+    );
+}
+
+#[test]
+fn test_cli_lookup_lists_unmatched_names() {
+    for format in [cli::OutputFormat::Table, cli::OutputFormat::Json] {
+        let (matched, out, err) = run_lookup(&["atlantis", "japan", "wakanda"], format);
+
+        assert!(!matched);
+        assert!(out.contains("JPY"));
+        assert_eq!(err, "Unknown country: atlantis\nUnknown country: wakanda\n");
+    }
+}
+
+// (loaded, stdout, stderr) of the offline dump command
+fn run_dump(source: &dyn DatasetLoader, format: cli::DumpFormat) -> (bool, String, String) {
+    let (mut out, mut err) = (Vec::new(), Vec::new());
+    let loaded = cli::dump(source, format, &mut out, &mut err).unwrap();
+    (
+        loaded,
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+    )
+}
+
+#[test]
+fn test_cli_dump_prints_every_entry_in_each_format() {
+    let entries = BuiltinLoader.load().unwrap().len();
+
+    let (loaded, json, err) = run_dump(&BuiltinLoader, cli::DumpFormat::Json);
+    assert!(loaded);
+    assert_eq!(err, "");
+    let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(json["countries"].as_array().unwrap().len(), entries);
+
+    let (loaded, yaml, _) = run_dump(&BuiltinLoader, cli::DumpFormat::Yaml);
+    assert!(loaded);
+    let yaml: serde_json::Value = serde_yaml::from_str(&yaml).unwrap();
+    assert_eq!(yaml, json);
+
+    let (loaded, csv, _) = run_dump(&BuiltinLoader, cli::DumpFormat::Csv);
+    assert!(loaded);
+    let mut rows = csv.lines();
+    assert_eq!(
+        rows.next(),
+        Some("name,aliases,flag,currencyCode,phoneCode")
+    );
+    assert_eq!(rows.count(), entries);
+}
+
+#[test]
+fn test_cli_dump_matches_the_admin_export() {
+    let path = write_temp_data_file(
+        "dump",
+        r#"{"countries": [
+            {"name": "Japan", "aliases": ["Nippon"], "flag": "🇯🇵", "currencyCode": "JPY", "phoneCode": "+81"},
+            {"name": "korea, republic of", "flag": "🇰🇷", "currencyCode": "KRW", "phoneCode": "+82"}
+        ]}"#,
+    );
+    let source = DataFileLoader::new(&path);
+    let dataset = source.load().unwrap();
+
+    let (_, json, _) = run_dump(&source, cli::DumpFormat::Json);
+    assert_eq!(json, format!("{}\n", export::to_json(&dataset)));
+    let reloaded = data_file::parse_data_file(json.as_bytes(), &path).unwrap();
+    assert_eq!(reloaded.len(), 2);
+
+    let (_, csv, _) = run_dump(&source, cli::DumpFormat::Csv);
+    assert_eq!(csv, export::to_csv(&dataset));
+    assert!(csv.contains("\"korea, republic of\""));
+
+    let (_, yaml, _) = run_dump(&source, cli::DumpFormat::Yaml);
+    assert_eq!(yaml, export::to_yaml(&dataset));
+    let yaml: serde_json::Value = serde_yaml::from_str(&yaml).unwrap();
+    assert_eq!(yaml["countries"][0]["aliases"][0], "nippon");
+    assert_eq!(yaml["countries"].as_array().unwrap().len(), 2);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_cli_dump_prints_nothing_for_invalid_data() {
+    let path = write_temp_data_file(
+        "dump_invalid",
+        r#"{"countries": [{"name": "Atlantis", "flag": "🏳", "currencyCode": "ZZZ", "phoneCode": "+0"}]}"#,
+    );
+
+    let (loaded, out, err) = run_dump(&DataFileLoader::new(&path), cli::DumpFormat::Json);
+
+    assert!(!loaded);
+    assert_eq!(out, "");
+    assert!(err.contains("\"Atlantis\""), "{}", err);
+    std::fs::remove_file(path).unwrap();
+}
+
+// A clock that only moves when told to
+struct MockClock {
+    start: std::time::Instant,
+    elapsed: std::sync::Mutex<std::time::Duration>,
+}
+
+impl MockClock {
+    fn new() -> Self {
+        MockClock {
+            start: std::time::Instant::now(),
+            elapsed: std::sync::Mutex::default(),
+        }
+    }
+
+    fn advance(&self, by: std::time::Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+}
+
+impl cache::Clock for MockClock {
+    fn now(&self) -> std::time::Instant {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+}
+
+fn jpy() -> CurrencyCode {
+    CurrencyCode::new("JPY").unwrap()
+}
+
+#[tokio::test]
+async fn test_rate_cache_reuses_rates() {
+    let provider = Arc::new(rates::StaticRateProvider::new([(jpy(), 0.0067)]));
+    let cache = rates::RateCache::new(provider.clone(), rates::DEFAULT_TTL);
+
+    assert_eq!(cache.rate_to_usd(jpy()).await, Ok(0.0067));
+    assert_eq!(cache.rate_to_usd(jpy()).await, Ok(0.0067));
+    assert_eq!(provider.calls(), 1);
+
+    let eur = CurrencyCode::new("EUR").unwrap();
+    assert_eq!(
+        cache.rate_to_usd(eur).await,
+        Err(rates::RateError::Missing(eur))
+    );
+    assert_eq!(provider.calls(), 2);
+}
+
+#[tokio::test]
+async fn test_rate_cache_burst_makes_one_upstream_call() {
+    let provider = Arc::new(rates::StaticRateProvider::new([(jpy(), 0.0067)]));
+    let cache = Arc::new(rates::RateCache::new(provider.clone(), rates::DEFAULT_TTL));
+
+    let mut burst = tokio::task::JoinSet::new();
+    for _ in 0..50 {
+        let cache = cache.clone();
+        burst.spawn(async move { cache.rate_to_usd(jpy()).await });
+    }
+    while let Some(rate) = burst.join_next().await {
+        assert_eq!(rate.unwrap(), Ok(0.0067));
+    }
+
+    assert_eq!(provider.calls(), 1);
+}
+
+#[tokio::test]
+async fn test_rate_cache_refetches_after_ttl() {
+    let ttl = std::time::Duration::from_secs(60);
+    let provider = Arc::new(rates::StaticRateProvider::new([(jpy(), 0.0067)]));
+    let clock = Arc::new(MockClock::new());
+    let cache = rates::RateCache::new(provider.clone(), ttl).with_clock(clock.clone());
+
+    cache.rate_to_usd(jpy()).await.unwrap();
+    clock.advance(ttl - std::time::Duration::from_secs(1));
+    cache.rate_to_usd(jpy()).await.unwrap();
+    assert_eq!(provider.calls(), 1);
+
+    clock.advance(std::time::Duration::from_secs(1));
+    cache.rate_to_usd(jpy()).await.unwrap();
+    assert_eq!(provider.calls(), 2);
+}
+
+#[tokio::test]
+async fn test_rate_cache_caches_failures_for_the_ttl() {
+    let ttl = std::time::Duration::from_secs(60);
+    let provider = Arc::new(rates::StaticRateProvider::failing("connection refused"));
+    let clock = Arc::new(MockClock::new());
+    let cache = rates::RateCache::new(provider.clone(), ttl).with_clock(clock.clone());
+
+    let failure = Err(rates::RateError::Upstream(String::from(
+        "connection refused",
+    )));
+    assert_eq!(cache.rate_to_usd(jpy()).await, failure);
+    assert_eq!(cache.rate_to_usd(jpy()).await, failure);
+    assert_eq!(provider.calls(), 1);
+
+    clock.advance(ttl);
+    assert_eq!(cache.rate_to_usd(jpy()).await, failure);
+    assert_eq!(provider.calls(), 2);
+}
+
+#[tokio::test]
+async fn test_memory_cache_expires_entries() {
+    use cache::Cache;
+
+    let clock = Arc::new(MockClock::new());
+    let store = cache::MemoryCache::new(8).with_clock(clock.clone());
+    let ttl = std::time::Duration::from_secs(60);
+
+    store.set("key", Bytes::from("value"), ttl).await;
+    clock.advance(ttl - std::time::Duration::from_secs(1));
+    assert_eq!(store.get("key").await, Some(Bytes::from("value")));
+    clock.advance(std::time::Duration::from_secs(1));
+    assert_eq!(store.get("key").await, None);
+}
+
+#[tokio::test]
+async fn test_memory_cache_evicts_least_recently_used() {
+    use cache::Cache;
+
+    let store = cache::MemoryCache::new(2);
+    let ttl = std::time::Duration::from_secs(60);
+    store.set("first", Bytes::from("1"), ttl).await;
+    store.set("second", Bytes::from("2"), ttl).await;
+    store.get("first").await;
+    store.set("third", Bytes::from("3"), ttl).await;
+
+    assert_eq!(store.get("first").await, Some(Bytes::from("1")));
+    assert_eq!(store.get("second").await, None);
+    assert_eq!(store.get("third").await, Some(Bytes::from("3")));
+
+    let disabled = cache::MemoryCache::new(0);
+    disabled.set("key", Bytes::from("value"), ttl).await;
+    assert_eq!(disabled.get("key").await, None);
+}
+
+#[tokio::test]
+async fn test_rate_caches_sharing_a_store_make_one_upstream_call() {
+    let provider = Arc::new(rates::StaticRateProvider::new([(jpy(), 0.0067)]));
+    let store: Arc<dyn cache::Cache> = Arc::new(cache::MemoryCache::new(8));
+    let replica =
+        || rates::RateCache::new(provider.clone(), rates::DEFAULT_TTL).with_store(store.clone());
+    let (first, second) = (replica(), replica());
+
+    assert_eq!(first.rate_to_usd(jpy()).await, Ok(0.0067));
+    assert_eq!(second.rate_to_usd(jpy()).await, Ok(0.0067));
+    assert_eq!(provider.calls(), 1);
+}
+
+// Answers every currency with 1.0, or `failure`, after `delay`, recording when each call
+// started and ended and how many were in progress at once
+#[derive(Debug)]
+struct RecordingRateProvider {
+    delay: Duration,
+    failure: Option<String>,
+    calls: std::sync::Mutex<Vec<(CurrencyCode, tokio::time::Instant, tokio::time::Instant)>>,
+    in_flight: std::sync::atomic::AtomicUsize,
+    most_in_flight: std::sync::atomic::AtomicUsize,
+}
+
+impl RecordingRateProvider {
+    fn new(delay: Duration) -> Self {
+        RecordingRateProvider {
+            delay,
+            failure: None,
+            calls: std::sync::Mutex::new(Vec::new()),
+            in_flight: Default::default(),
+            most_in_flight: Default::default(),
+        }
+    }
+
+    fn failing(delay: Duration, reason: &str) -> Self {
+        RecordingRateProvider {
+            failure: Some(reason.to_string()),
+            ..RecordingRateProvider::new(delay)
+        }
+    }
+
+    fn calls(&self) -> usize {
+        self.calls.lock().unwrap().len()
+    }
+}
+
+impl rates::RateProvider for RecordingRateProvider {
+    fn rate_to_usd(&self, currency: CurrencyCode) -> rates::RateFuture<'_> {
+        use std::sync::atomic::Ordering;
+
+        Box::pin(async move {
+            let started = tokio::time::Instant::now();
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            self.calls
+                .lock()
+                .unwrap()
+                .push((currency, started, tokio::time::Instant::now()));
+            match &self.failure {
+                Some(reason) => Err(rates::RateError::Upstream(reason.clone())),
+                None => Ok(1.0),
+            }
+        })
+    }
+}
+
+async fn enrich_with(provider: Arc<RecordingRateProvider>, based: &str) -> usize {
+    let dataset = builtin::dataset();
+    let rates = Arc::new(rates::RateCache::new(provider, rates::DEFAULT_TTL));
+    let response = lookup::lookup_countries(&dataset, based, "en");
+    let rated = rates::enrich(Some(rates), response).await;
+    assert!(rated
+        .results
+        .iter()
+        .all(|result| result.exchange_rate_to_usd == Some(1.0)));
+    rated.results.len()
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_enrich_fetches_each_currency_once_and_all_at_once() {
+    let delay = Duration::from_millis(100);
+    let provider = Arc::new(RecordingRateProvider::new(delay));
+    let started = tokio::time::Instant::now();
+
+    let results = enrich_with(provider.clone(), "germany,france,japan,united states").await;
+
+    assert_eq!(results, 4);
+    let calls = provider.calls.lock().unwrap().clone();
+    let mut currencies: Vec<_> = calls
+        .iter()
+        .map(|(currency, ..)| currency.as_str())
+        .collect();
+    currencies.sort_unstable();
+    // Germany and France share the one EUR call
+    assert_eq!(currencies, ["EUR", "JPY", "USD"]);
+    // Every call started before any other ended, rather than one after another
+    for (_, started, _) in &calls {
+        assert!(calls.iter().all(|(_, _, ended)| started < ended));
+    }
+    assert_eq!(
+        provider
+            .most_in_flight
+            .load(std::sync::atomic::Ordering::SeqCst),
+        3
+    );
+    assert_eq!(started.elapsed(), delay);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_enrich_makes_at_most_the_concurrent_fetch_limit_of_calls_at_once() {
+    let delay = Duration::from_millis(100);
+    let provider = Arc::new(RecordingRateProvider::new(delay));
+    let started = tokio::time::Instant::now();
+
+    // Six currencies: EUR, JPY, USD, GBP, CHF and INR
+    let results = enrich_with(
+        provider.clone(),
+        "germany,japan,united states,united kingdom,switzerland,india",
+    )
+    .await;
+
+    assert_eq!(results, 6);
+    assert_eq!(provider.calls.lock().unwrap().len(), 6);
+    assert_eq!(
+        provider
+            .most_in_flight
+            .load(std::sync::atomic::Ordering::SeqCst),
+        rates::MAX_CONCURRENT_FETCHES
+    );
+    // Four calls, then the other two once permits free up
+    assert_eq!(started.elapsed(), delay * 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_concurrent_enrichments_share_one_upstream_call() {
+    let provider = Arc::new(RecordingRateProvider::new(Duration::from_millis(100)));
+    // Nothing is cached, so only the shared flight can spare the other calls
+    let rates = Arc::new(
+        rates::RateCache::new(provider.clone(), rates::DEFAULT_TTL)
+            .with_store(Arc::new(cache::MemoryCache::new(0))),
+    );
+
+    let mut burst = tokio::task::JoinSet::new();
+    for _ in 0..100 {
+        let rates = rates.clone();
+        burst.spawn(async move {
+            let dataset = builtin::dataset();
+            let response = lookup::lookup_countries(&dataset, "japan", "en");
+            let rated = rates::enrich(Some(rates), response).await;
+            rated.results[0].exchange_rate_to_usd
+        });
+    }
+    while let Some(rate) = burst.join_next().await {
+        assert_eq!(rate.unwrap(), Some(1.0));
+    }
+
+    assert_eq!(provider.calls(), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_a_failed_flight_answers_every_waiter_and_is_not_kept() {
+    let provider = Arc::new(RecordingRateProvider::failing(
+        Duration::from_millis(100),
+        "connection refused",
+    ));
+    let rates = Arc::new(
+        rates::RateCache::new(provider.clone(), rates::DEFAULT_TTL)
+            .with_store(Arc::new(cache::MemoryCache::new(0))),
+    );
+
+    let mut burst = tokio::task::JoinSet::new();
+    for _ in 0..20 {
+        let rates = rates.clone();
+        burst.spawn(async move { rates.rate_to_usd(jpy()).await });
+    }
+    while let Some(rate) = burst.join_next().await {
+        assert_eq!(
+            rate.unwrap(),
+            Err(rates::RateError::Upstream(String::from(
+                "connection refused"
+            )))
+        );
+    }
+    assert_eq!(provider.calls(), 1);
+
+    // The landed flight is gone, so the next uncached lookup asks again
+    assert!(rates.rate_to_usd(jpy()).await.is_err());
+    assert_eq!(provider.calls(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_a_cancelled_flight_hands_over_to_its_waiters() {
+    let delay = Duration::from_millis(100);
+    let provider = Arc::new(RecordingRateProvider::new(delay));
+    let rates = Arc::new(rates::RateCache::new(provider.clone(), rates::DEFAULT_TTL));
+
+    let leader = tokio::spawn({
+        let rates = rates.clone();
+        async move { rates.rate_to_usd(jpy()).await }
+    });
+    tokio::time::sleep(delay / 2).await;
+    let waiter = tokio::spawn({
+        let rates = rates.clone();
+        async move { rates.rate_to_usd(jpy()).await }
+    });
+    tokio::time::sleep(delay / 4).await;
+    leader.abort();
+
+    // The waiter starts a flight of its own rather than waiting forever
+    let rate = tokio::time::timeout(delay * 2, waiter)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(rate, Ok(1.0));
+    assert_eq!(rates.rate_to_usd(jpy()).await, Ok(1.0));
+    // The cancelled call never finished, so it was not recorded
+    assert_eq!(provider.calls(), 1);
+}
+
+// Fails every call while `failing` is set, counting the calls it answers
+#[derive(Debug, Default)]
+struct FlakyRateProvider {
+    failing: std::sync::atomic::AtomicBool,
+    calls: std::sync::atomic::AtomicUsize,
+}
+
+impl rates::RateProvider for FlakyRateProvider {
+    fn rate_to_usd(&self, _currency: CurrencyCode) -> rates::RateFuture<'_> {
+        use std::sync::atomic::Ordering;
+
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        let rate = if self.failing.load(Ordering::SeqCst) {
+            Err(rates::RateError::Upstream(String::from(
+                "connection refused",
+            )))
+        } else {
+            Ok(1.0)
+        };
+        Box::pin(async move { rate })
+    }
+}
+
+#[tokio::test]
+async fn test_rate_breaker_opens_probes_and_closes() {
+    use breaker::BreakerState;
+    use std::sync::atomic::Ordering;
+
+    let cooldown = Duration::from_secs(30);
+    let clock = Arc::new(MockClock::new());
+    let provider = Arc::new(FlakyRateProvider::default());
+    provider.failing.store(true, Ordering::SeqCst);
+    // Nothing is cached, so every lookup reaches the breaker
+    let rates = rates::RateCache::new(provider.clone(), rates::DEFAULT_TTL)
+        .with_store(Arc::new(cache::MemoryCache::new(0)))
+        .with_breaker(
+            breaker::CircuitBreaker::new(NonZeroU32::new(3).unwrap(), cooldown)
+                .with_clock(clock.clone()),
+        );
+    let upstream = Err(rates::RateError::Upstream(String::from(
+        "connection refused",
+    )));
+
+    // Closed: every failure reaches the provider, until three in a row open it
+    for failures in 1..=3 {
+        assert_eq!(rates.breaker().state(), BreakerState::Closed);
+        assert_eq!(rates.rate_to_usd(jpy()).await, upstream);
+        assert_eq!(rates.breaker().consecutive_failures(), failures);
+    }
+    assert_eq!(rates.breaker().state(), BreakerState::Open);
+    assert_eq!(provider.calls.load(Ordering::SeqCst), 3);
+
+    // Open: answered at once without asking
+    assert_eq!(
+        rates.rate_to_usd(jpy()).await,
+        Err(rates::RateError::CircuitOpen)
+    );
+    assert_eq!(provider.calls.load(Ordering::SeqCst), 3);
+    assert_eq!(rates.breaker().retry_in(), Some(cooldown));
+    clock.advance(cooldown - Duration::from_secs(1));
+    assert_eq!(rates.breaker().state(), BreakerState::Open);
+
+    // Half-open: one probe, which fails and opens it for another cooldown
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(rates.breaker().state(), BreakerState::HalfOpen);
+    assert_eq!(rates.rate_to_usd(jpy()).await, upstream);
+    assert_eq!(provider.calls.load(Ordering::SeqCst), 4);
+    assert_eq!(rates.breaker().state(), BreakerState::Open);
+    assert_eq!(
+        rates.rate_to_usd(jpy()).await,
+        Err(rates::RateError::CircuitOpen)
+    );
+
+    // Half-open again: the provider is back, so the probe closes it
+    clock.advance(cooldown);
+    provider.failing.store(false, Ordering::SeqCst);
+    assert_eq!(rates.breaker().state(), BreakerState::HalfOpen);
+    assert_eq!(rates.rate_to_usd(jpy()).await, Ok(1.0));
+    assert_eq!(rates.breaker().state(), BreakerState::Closed);
+    assert_eq!(rates.breaker().consecutive_failures(), 0);
+    assert_eq!(rates.breaker().retry_in(), None);
+    assert_eq!(provider.calls.load(Ordering::SeqCst), 5);
+
+    let metrics = rates.breaker().render_metrics();
+    assert!(metrics.contains("rustincountry_rate_breaker_state{state=\"closed\"} 1\n"));
+    assert!(metrics.contains("rustincountry_rate_breaker_opened_total 2\n"));
+    assert!(metrics.contains("rustincountry_rate_breaker_short_circuited_total 2\n"));
+}
+
+#[test]
+fn test_rate_breaker_lets_one_probe_through_at_a_time() {
+    use breaker::BreakerState;
+
+    let cooldown = Duration::from_secs(30);
+    let clock = Arc::new(MockClock::new());
+    let breaker = breaker::CircuitBreaker::new(NonZeroU32::MIN, cooldown).with_clock(clock.clone());
+    breaker.try_call().unwrap().record(false);
+    assert!(breaker.try_call().is_none());
+    clock.advance(cooldown);
+
+    let probe = breaker.try_call().unwrap();
+    assert!(breaker.try_call().is_none());
+    // A probe that never answers, say because its request was cancelled, hands over
+    drop(probe);
+    assert_eq!(breaker.state(), BreakerState::HalfOpen);
+    let probe = breaker.try_call().unwrap();
+    probe.record(true);
+    assert_eq!(breaker.state(), BreakerState::Closed);
+    assert!(breaker.try_call().is_some());
+}
+
+#[tokio::test]
+async fn test_shared_responses_are_keyed_by_dataset_contents() {
+    let store: Arc<dyn cache::Cache> = Arc::new(cache::MemoryCache::new(8));
+    let first = response_cache::ResponseCache::new(0).with_shared(store.clone());
+    let second = response_cache::ResponseCache::new(0).with_shared(store);
+    let dataset = builtin::dataset();
+    let cached = response_cache::CachedResponse {
+        body: Bytes::from("{\"results\":[]}\n"),
+        results: 0,
+        unmatched: 2,
+    };
+
+    // Replicas number versions on their own, so the same data may have another version
+    first
+        .insert(1, &dataset, String::from("key"), cached.clone())
+        .await;
+    assert_eq!(second.get(7, &dataset, "key").await, Some(cached));
+
+    let mut changed = dataset.clone();
+    changed.remove("japan").unwrap();
+    assert_eq!(second.get(8, &changed, "key").await, None);
+}
+
+#[test]
+fn test_alpha2_from_flag() {
+    assert_eq!(
+        codes::Alpha2::from_flag("🇯🇵"),
+        Some(codes::Alpha2::new("JP").unwrap())
+    );
+    for not_a_flag in ["", "JP", "🇯", "🏳️", "🇯🇵🇯🇵"] {
+        assert_eq!(
+            codes::Alpha2::from_flag(not_a_flag),
+            None,
+            "{:?}",
+            not_a_flag
+        );
+    }
+}
+
+#[test]
+fn test_ip_entries() {
+    assert_eq!(lookup::ip_entry("ip:8.8.8.8"), Some("8.8.8.8"));
+    assert_eq!(lookup::ip_entry("IP: 2001:db8::1"), Some("2001:db8::1"));
+    assert_eq!(lookup::ip_entry("japan"), None);
+    assert_eq!(lookup::ip_entry("i"), None);
+    assert!(lookup::is_address_entry("ip:8.8.8.8"));
+    assert!(!lookup::is_address_entry("japan"));
+    assert!(lookup::is_address_entry("8.8.8.8"));
+    assert!(lookup::is_address_entry("2001:db8::1"));
+    assert!(!lookup::is_address_entry("8.8.8"));
+}
+
+#[test]
+fn test_resolve_query_with_locates_addresses() {
+    let dataset = Dataset::builtin();
+    let us = codes::Alpha2::new("US").unwrap();
+    let zz = codes::Alpha2::new("ZZ").unwrap();
+    let resolver = geoip::StaticResolver::new([
+        ("8.8.8.8".parse().unwrap(), us),
+        ("1.1.1.1".parse().unwrap(), zz),
+    ]);
+    let locator = geoip::GeoIpLocator(Some(&resolver));
+    let query = lookup::resolve_query_with(
+        "japan, ip:8.8.8.8, 8.8.8.8, 1.1.1.1, 9.9.9.9, ip:1.1.1.1",
+        &dataset,
+        &locator,
+    )
+    .unwrap();
+    let resolutions: Vec<_> = query
+        .entries
+        .iter()
+        .map(|entry| &entry.resolution)
+        .collect();
+    let usa = dataset.by_alpha2(us).unwrap();
+    assert!(matches!(resolutions[0], lookup::Resolution::Name(..)));
+    assert_eq!(resolutions[1], &lookup::Resolution::Ip(usa));
+    assert_eq!(resolutions[2], &lookup::Resolution::Ip(usa));
+    assert_eq!(resolutions[3], &lookup::Resolution::UnservedIp(zz));
+    assert!(matches!(resolutions[4], lookup::Resolution::UnlocatedIp(_)));
+    // Addresses are never counted as misses
+    assert_eq!(query.unknown_names().count(), 0);
+
+    let response = query.response("en");
+    let answered: Vec<_> = response
+        .results
+        .iter()
+        .map(|info| (info.country.as_ref(), info.resolved_from))
+        .collect();
+    assert_eq!(
+        answered,
+        [
+            ("japan", None),
+            ("ip:8.8.8.8", Some(lookup::ResolvedFrom::Ip)),
+            ("8.8.8.8", Some(lookup::ResolvedFrom::Ip)),
+        ]
+    );
+    let unmatched: Vec<_> = response
+        .unmatched
+        .iter()
+        .map(|unmatched| unmatched.input.as_ref())
+        .collect();
+    assert_eq!(unmatched, ["1.1.1.1", "9.9.9.9"]);
+
+    let failed = lookup::resolve_query_with("japan, ip:9.9.9.9", &dataset, &locator);
+    assert!(matches!(
+        failed,
+        Err(("ip:9.9.9.9", geoip::GeoIpError::NotFound(_)))
+    ));
+}
+
+#[test]
+fn test_geoip_private_addresses() {
+    for private in [
+        "10.1.2.3",
+        "172.16.0.1",
+        "192.168.1.1",
+        "127.0.0.1",
+        "169.254.0.1",
+        "100.64.0.1",
+        "0.0.0.0",
+        "::1",
+        "fd00::1",
+        "fe80::1",
+        "::ffff:192.168.1.1",
+    ] {
+        assert!(geoip::is_private(private.parse().unwrap()), "{}", private);
+    }
+    for public in ["8.8.8.8", "100.128.0.1", "2001:4860:4860::8888"] {
+        assert!(!geoip::is_private(public.parse().unwrap()), "{}", public);
+    }
+}
+
+#[test]
+fn test_geoip_locate() {
+    let japan = codes::Alpha2::new("JP").unwrap();
+    let resolver = geoip::StaticResolver::new([("1.1.1.1".parse().unwrap(), japan)]);
+    let resolver: Option<&dyn geoip::IpResolver> = Some(&resolver);
+
+    assert_eq!(geoip::locate(resolver, "1.1.1.1"), Ok(japan));
+    assert_eq!(
+        geoip::locate(resolver, "8.8.8.8"),
+        Err(geoip::GeoIpError::NotFound("8.8.8.8".parse().unwrap()))
+    );
+    assert_eq!(
+        geoip::locate(resolver, "1.1.1"),
+        Err(geoip::GeoIpError::InvalidAddress(String::from("1.1.1")))
+    );
+    assert_eq!(
+        geoip::locate(None, "192.168.0.1"),
+        Err(geoip::GeoIpError::PrivateAddress(
+            "192.168.0.1".parse().unwrap()
+        ))
+    );
+    assert_eq!(
+        geoip::locate(None, "1.1.1.1"),
+        Err(geoip::GeoIpError::NotConfigured)
+    );
+}
+
+#[test]
+fn test_geoip_missing_database_fails_to_open() {
+    let error =
+        geoip::MaxMindResolver::open(std::path::Path::new("/nonexistent/geo.mmdb")).unwrap_err();
+    assert!(
+        matches!(error, geoip::GeoIpError::Database(_)),
+        "{:?}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn test_whoami_reads_the_client_address() {
+    let listeners = bind_listeners(&["127.0.0.1:0".parse().unwrap()]).unwrap();
+    let address = listeners[0].local_addr().unwrap();
+    let server = tokio::spawn(run_server(listeners, create_app()));
+
+    // Loopback is refused before the (missing) database would be asked
+    let (status, body) = http_get(address, "/whoami").await;
+    assert_eq!(status, 422);
+    let envelope: ErrorEnvelope = serde_json::from_str(&body).unwrap();
+    assert_eq!(envelope.error.code, "PRIVATE_IP");
+    assert_eq!(envelope.error.details.unwrap()["input"], "127.0.0.1");
+
+    server.abort();
+}
+
+#[test]
+fn test_accepted_languages_weighted() {
+    assert_eq!(
+        lookup::accepted_languages("ja;q=0.8, de;q=0.9"),
+        ["de", "ja"]
+    );
+    assert_eq!(
+        lookup::accepted_languages("fr-CH, fr;q=0.9, en;q=0.8, de;q=0.7, *;q=0.5"),
+        ["fr", "en", "de", "*"]
+    );
+    // Equal weights keep the order they were sent in
+    assert_eq!(
+        lookup::accepted_languages("ja, de;q=1.0, fr"),
+        ["ja", "de", "fr"]
+    );
+    assert_eq!(lookup::accepted_languages("ja;q=0, de"), ["de"]);
+    assert_eq!(lookup::accepted_languages("JA-jp;Q=0.5"), ["ja"]);
+}
+
+#[test]
+fn test_accepted_languages_ignores_garbage() {
+    for garbage in [
+        "",
+        ",,",
+        ";q=0.5",
+        "日本語",
+        "ja;q=high",
+        "ja;q=2",
+        "ja;q",
+        "en_US",
+        "x-",
+    ] {
+        assert!(
+            lookup::accepted_languages(garbage).is_empty(),
+            "{:?}",
+            garbage
+        );
+    }
+    assert_eq!(lookup::accepted_languages("??, de;q=0.4, ;;"), ["de"]);
+}
+
+#[test]
+fn test_negotiated_language() {
+    let dataset = builtin::dataset();
+    let supported = |language: &str| dataset.has_language(language);
+
+    assert_eq!(
+        lookup::negotiated_language(None, Some("ja"), supported),
+        "ja"
+    );
+    // Unsupported languages are passed over for the next preference
+    assert_eq!(
+        lookup::negotiated_language(None, Some("ko, ja;q=0.8, de;q=0.9"), supported),
+        "de"
+    );
+    assert_eq!(
+        lookup::negotiated_language(None, Some("ko"), supported),
+        "en"
+    );
+    assert_eq!(
+        lookup::negotiated_language(None, Some("*"), supported),
+        "en"
+    );
+    assert_eq!(
+        lookup::negotiated_language(None, Some("*, ja"), supported),
+        "en"
+    );
+    assert_eq!(
+        lookup::negotiated_language(None, Some("%%%"), supported),
+        "en"
+    );
+    assert_eq!(lookup::negotiated_language(None, None, supported), "en");
+    // `lang` wins, whether or not the dataset has it
+    assert_eq!(
+        lookup::negotiated_language(Some("fr"), Some("ja"), supported),
+        "fr"
+    );
+    assert_eq!(
+        lookup::negotiated_language(Some("ko"), Some("ja"), supported),
+        "ko"
+    );
+    assert_eq!(
+        lookup::negotiated_language(Some(" "), Some("ja"), supported),
+        "ja"
+    );
+}
+
+#[tokio::test]
+async fn test_reload_publishes_dataset_event() {
+    let path = write_temp_data_file("reload_event", RELOAD_ORIGINAL);
+    let state = AppState::new(data_file::load_data_file(&path).unwrap());
+    let mut events = state.events.subscribe();
+
+    std::fs::write(
+        &path,
+        r#"{
+  "countries": [
+    {"name": "japan", "flag": "🇯🇵", "currencyCode": "JPY", "phoneCode": "+81"},
+    {"name": "france", "flag": "🇫🇷", "currencyCode": "EUR", "phoneCode": "+33"}
+  ]
+}"#,
+    )
+    .unwrap();
+    reload::reload_dataset(&state, &DataFileLoader::new(&path)).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let event = events.try_recv().unwrap();
+    assert_eq!(event.dataset_version, 2);
+    assert_eq!(event.added, ["france"]);
+    assert_eq!(event.removed, ["korea"]);
+    assert!(event.changed.is_empty());
+}
+
+#[test]
+fn test_webhook_signature() {
+    // RFC 4231, test case 2
+    assert_eq!(
+        webhooks::sign(b"Jefe", b"what do ya want for nothing?"),
+        "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+#[test]
+fn test_builtin_geography() {
+    let dataset = builtin::dataset();
+    for record in dataset.records() {
+        if let Some(latlng) = record.latlng {
+            assert!(dataset::validate_latlng(latlng).is_ok(), "{}", record.name);
+        }
+        let alpha2 = codes::Alpha2::from_flag(&record.flag).unwrap();
+        for neighbour in &record.borders {
+            assert!(
+                dataset
+                    .by_alpha2(*neighbour)
+                    .unwrap()
+                    .borders
+                    .contains(&alpha2),
+                "{} borders {}",
+                record.name,
+                neighbour
+            );
+        }
+    }
+    let france = dataset.get("france").unwrap();
+    assert_eq!(france.latlng, Some([46.0, 2.0]));
+    assert!(france.borders.contains(&codes::Alpha2::new("ES").unwrap()));
+    assert!(dataset.get("japan").unwrap().borders.is_empty());
+
+    assert!(dataset::validate_latlng([-90.0, 180.0]).is_ok());
+    assert!(dataset::validate_latlng([91.0, 0.0]).is_err());
+    assert!(dataset::validate_latlng([0.0, -180.5]).is_err());
+    assert!(dataset::validate_latlng([f64::NAN, 0.0]).is_err());
+}
+
+#[test]
+fn test_database_geography() {
+    let mut connection = rusqlite::Connection::open_in_memory().unwrap();
+    database::migrate(&mut connection).unwrap();
+    let dataset = database::load_countries(&connection).unwrap();
+    let france = dataset.get("france").unwrap();
+    assert_eq!(france.latlng, Some([46.0, 2.0]));
+    assert_eq!(
+        france.borders,
+        builtin::dataset().get("france").unwrap().borders
+    );
+
+    connection
+        .execute("UPDATE countries SET lat = NULL WHERE name = 'france'", [])
+        .unwrap();
+    connection
+        .execute("INSERT INTO borders VALUES ('japan', 'korea')", [])
+        .unwrap();
+    match database::load_countries(&connection).unwrap_err() {
+        database::DatabaseError::InvalidCountry { name, error } => {
+            assert_eq!(name, "japan");
+            assert!(matches!(
+                error,
+                RecordError::Invalid {
+                    field: "borders",
+                    ..
+                }
+            ));
+        }
+        other => panic!("unexpected error: {}", other),
+    }
+
+    connection.execute_batch("DROP TABLE borders").unwrap();
+    let dataset = database::load_countries(&connection).unwrap();
+    assert_eq!(dataset.get("france").unwrap().latlng, None);
+    assert!(dataset.get("france").unwrap().borders.is_empty());
+}
+
+#[test]
+fn test_include_parsing() {
+    use include::{IncludeGroup, Includes};
+
+    let includes = Includes::parse("Translations, geo,,geo").unwrap();
+    assert!(includes.contains(IncludeGroup::Geo));
+    assert!(includes.contains(IncludeGroup::Translations));
+    assert!(!includes.contains(IncludeGroup::Rates));
+    assert_eq!(includes.to_string(), "geo,translations");
+
+    assert!(Includes::parse("").unwrap().is_empty());
+    let error = Includes::parse("geo,weather").unwrap_err();
+    assert_eq!(error, include::UnknownInclude(String::from("weather")));
+    assert_eq!(
+        error.to_string(),
+        "\"weather\" is not a field group; valid ones are geo, currencies, translations, rates"
+    );
+}
+
+#[test]
+fn test_flag_formats() {
+    use lookup::FlagFormat;
+
+    assert_eq!(lookup::flag_codepoints("🇯🇵"), "U+1F1EF U+1F1F5");
+    assert_eq!(FlagFormat::from_param(None), Ok(FlagFormat::Emoji));
+    assert_eq!(
+        FlagFormat::from_param(Some("codepoints")),
+        Ok(FlagFormat::Codepoints)
+    );
+    assert_eq!(FlagFormat::from_param(Some("none")), Ok(FlagFormat::None));
+    assert!(FlagFormat::from_param(Some("svg")).is_err());
+
+    let dataset = builtin::dataset();
+    let mut results = lookup::canonical_countries(&dataset, "en");
+    lookup::format_flags(&mut results, FlagFormat::None);
+    assert!(results.iter().all(|info| info.flag.is_empty()));
+    let json = serde_json::to_value(&results[0]).unwrap();
+    assert!(json.get("flag").is_none());
+}
+
+#[test]
+fn test_result_ordering_and_grouping() {
+    use lookup::{GroupBy, Order, ResultOptions};
+
+    let dataset = builtin::dataset();
+    let options = ResultOptions {
+        order: Order::Alpha,
+        ..ResultOptions::default()
+    };
+    let mut response = lookup::lookup_countries(&dataset, "Holland,USA,JAPAN,germany", "en");
+    options.apply(&mut response.results);
+    let canonical: Vec<_> = response
+        .results
+        .iter()
+        .map(|info| info.canonical_name.as_ref())
+        .collect();
+    assert_eq!(
+        canonical,
+        ["germany", "japan", "netherlands", "united states"]
+    );
+
+    let groups = lookup::group(response.results, GroupBy::Currency, |info| info);
+    let keys: Vec<_> = groups.iter().map(|group| group.key.as_deref()).collect();
+    assert_eq!(keys, [Some("EUR"), Some("JPY"), Some("USD")]);
+    assert_eq!(groups[0].results.len(), 2);
+
+    assert_ne!(
+        ResultOptions::default().to_string(),
+        ResultOptions {
+            group_by: Some(GroupBy::Continent),
+            ..ResultOptions::default()
+        }
+        .to_string()
+    );
+    assert_eq!(Order::from_param(None), Ok(Order::Input));
+    assert_eq!(GroupBy::from_param(None), Ok(None));
+    assert!(GroupBy::from_param(Some("currencies")).is_err());
+}
+
+#[test]
+fn test_only_field_values() {
+    use lookup::OnlyField;
+
+    assert_eq!(OnlyField::from_param(None), Ok(None));
+    assert_eq!(
+        OnlyField::from_param(Some("phoneCode")),
+        Ok(Some(OnlyField::PhoneCode))
+    );
+    for refused in ["latlng", "translations", "PhoneCode", "capital"] {
+        assert!(OnlyField::from_param(Some(refused)).is_err(), "{}", refused);
+    }
+
+    let dataset = builtin::dataset();
+    let response = lookup::lookup_countries(&dataset, "japan,🇯🇵,usa", "en");
+    let resolved_from = lookup::values(&response.results, OnlyField::ResolvedFrom, false);
+    assert_eq!(resolved_from, [None, Some(Cow::Borrowed("flag")), None]);
+    let statuses = lookup::values(&response.results, OnlyField::NameStatus, true);
+    assert_eq!(statuses, [Some(Cow::Borrowed("current"))]);
+}
+
+#[test]
+fn test_unanswered_entries() {
+    let dataset = builtin::dataset();
+    let based = "japan, atlantis ,🇦🇦,,Japan";
+    let response = lookup::lookup_countries(&dataset, based, "en");
+    let unanswered = lookup::unanswered(based, &response);
+    let inputs: Vec<_> = unanswered.iter().map(|entry| &*entry.input).collect();
+    assert_eq!(inputs, ["atlantis", "🇦🇦"]);
+    assert_eq!(unanswered[0].reason, "No served country has this name");
+
+    let all = lookup::CountryResponse {
+        results: lookup::all_names(&dataset, "en"),
+        unmatched: Vec::new(),
+        truncated: false,
+    };
+    assert!(lookup::unanswered("ALL", &all).is_empty());
+}
+
+#[test]
+fn test_max_results() {
+    assert_eq!(lookup::max_results_param(None, 10), Ok(None));
+    assert_eq!(lookup::max_results_param(Some("1"), 10), Ok(Some(1)));
+    assert_eq!(lookup::max_results_param(Some(" 10 "), 10), Ok(Some(10)));
+    for param in ["0", "-3", "11", "1.5", "", "99999999999999999999"] {
+        assert!(
+            lookup::max_results_param(Some(param), 10).is_err(),
+            "{}",
+            param
+        );
+    }
+
+    let dataset = builtin::dataset();
+    let mut results = lookup::lookup_countries(&dataset, "japan,germany,france", "en").results;
+    assert!(!lookup::truncate(&mut results, None));
+    assert!(!lookup::truncate(&mut results, Some(3)));
+    assert!(lookup::truncate(&mut results, Some(2)));
+    let countries: Vec<_> = results.iter().map(|info| &*info.country).collect();
+    assert_eq!(countries, ["japan", "germany"]);
+}
+
+#[test]
+fn test_input_limits() {
+    assert_eq!(input::check("japan, united states ,🇫🇷"), Ok(()));
+    assert_eq!(input::check(&"é".repeat(input::MAX_ENTRY_CHARS)), Ok(()));
+    assert_eq!(
+        input::check(&format!("japan,{}", "é".repeat(input::MAX_ENTRY_CHARS + 1))),
+        Err(input::InputError::EntryTooLong {
+            entry: "é".repeat(input::MAX_ENTRY_CHARS + 1),
+            length: input::MAX_ENTRY_CHARS + 1,
+        })
+    );
+    assert_eq!(
+        input::check(&",".repeat(input::MAX_QUERY_BYTES + 1)),
+        Err(input::InputError::QueryTooLong {
+            length: input::MAX_QUERY_BYTES + 1,
+        })
+    );
+    // The first offending entry is the one reported
+    assert_eq!(
+        input::check("japan\r\n,\u{7f}"),
+        Err(input::InputError::ControlCharacter {
+            entry: String::from("japan\r\n"),
+            character: '\r',
+        })
+    );
+}
+
+#[test]
+fn test_entry_cleanup() {
+    for (entry, cleaned) in [
+        ("\"Japan\"", "Japan"),
+        ("'Japan'", "Japan"),
+        ("(Japan)", "Japan"),
+        ("[Japan]", "Japan"),
+        ("“Japan”", "Japan"),
+        ("«Japan»", "Japan"),
+        ("「日本」", "日本"),
+        ("Japan.", "Japan"),
+        ("Japan!", "Japan"),
+        ("Japan?!", "Japan"),
+        (" ( \"Japan.\" ) ", "Japan"),
+        ("\"Japan\".", "Japan"),
+        ("côte d'ivoire", "côte d'ivoire"),
+        ("'côte d'ivoire'", "côte d'ivoire"),
+        ("st. lucia", "st. lucia"),
+        ("korea (south)", "korea (south)"),
+        ("\"Japan)", "\"Japan)"),
+        ("\"\"", ""),
+    ] {
+        assert_eq!(input::clean(entry), cleaned, "{}", entry);
+    }
+}
+
+// The fields of every event logged under one target, by name
+#[derive(Clone)]
+struct CapturedEvents {
+    target: &'static str,
+    events: std::sync::Arc<std::sync::Mutex<Vec<BTreeMap<String, String>>>>,
+}
+
+impl CapturedEvents {
+    fn new(target: &'static str) -> Self {
+        CapturedEvents {
+            target,
+            events: Default::default(),
+        }
+    }
+
+    fn events(&self) -> Vec<BTreeMap<String, String>> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for CapturedEvents {
+    fn on_event(&self, event: &tracing::Event<'_>, _: tracing_subscriber::layer::Context<'_, S>) {
+        if event.metadata().target() != self.target {
+            return;
+        }
+        let mut fields = EventFields::default();
+        event.record(&mut fields);
+        self.events.lock().unwrap().push(fields.0);
+    }
+}
+
+#[derive(Default)]
+struct EventFields(BTreeMap<String, String>);
+
+impl tracing::field::Visit for EventFields {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value));
+    }
+}
+
+#[tokio::test]
+async fn test_unmatched_names_are_logged_once_a_minute_and_counted() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let captured = CapturedEvents::new(miss_log::TARGET);
+    let subscriber = tracing_subscriber::registry().with(captured.clone());
+    let _guard = tracing::subscriber::set_default(subscriber);
+    let app = create_app();
+    let client: SocketAddr = "203.0.113.7:4000".parse().unwrap();
+    for uri in [
+        "/getCountry?based=Japan,Atlantis",
+        "/getCountry?based=%22atlantis.%22",
+    ] {
+        let mut request = Request::get(uri).body(Body::empty()).unwrap();
+        request
+            .extensions_mut()
+            .insert(axum::extract::ConnectInfo(client));
+        app.clone().oneshot(request).await.unwrap();
+    }
+
+    let events = captured.events();
+    assert_eq!(events.len(), 1, "{:?}", events);
+    assert_eq!(events[0]["name"], "atlantis");
+    let hash = &events[0]["client"];
+    assert_eq!(hash.len(), 16);
+    assert!(hash.chars().all(|c| c.is_ascii_hexdigit()), "{}", hash);
+    assert!(!hash.contains("203.0.113.7"));
+    let metrics = get_metrics(&app).await;
+    assert!(
+        metrics.contains("rustincountry_unmatched_names_total 2"),
+        "{}",
+        metrics
+    );
+}
+
+#[test]
+fn test_miss_log_window() {
+    let misses = miss_log::MissLog::new(NonZeroU32::new(2).unwrap());
+    let start = std::time::Instant::now();
+    assert!(misses.should_log("atlantis", start));
+    assert!(misses.should_log("atlantis", start + Duration::from_secs(1)));
+    assert!(!misses.should_log("atlantis", start + Duration::from_secs(59)));
+    assert!(misses.should_log("lemuria", start + Duration::from_secs(59)));
+    assert!(misses.should_log("atlantis", start + Duration::from_secs(60)));
+
+    let address = "203.0.113.7".parse().unwrap();
+    assert_eq!(misses.client_hash(address), misses.client_hash(address));
+    assert_ne!(
+        misses.client_hash(address),
+        misses.client_hash("203.0.113.8".parse().unwrap())
+    );
+}
+
+// Characters the `based` pipeline treats specially: separators, spaces, quotes and
+// brackets, trailing punctuation, apostrophes, combining marks, letters whose case
+// mapping changes their length, regional indicators and the variation selector
+const SPECIAL_CHARS: [char; 40] = [
+    ',',
+    ' ',
+    '\t',
+    '\n',
+    '\u{A0}',
+    '\u{3000}',
+    '"',
+    '\'',
+    '(',
+    ')',
+    '[',
+    ']',
+    '\u{201C}',
+    '\u{201D}',
+    '\u{AB}',
+    '\u{BB}',
+    '\u{300C}',
+    '\u{300D}',
+    '.',
+    '!',
+    '?',
+    '\u{2019}',
+    '\u{FF07}',
+    '\u{301}',
+    '\u{307}',
+    '\u{345}',
+    '\u{130}',
+    '\u{DF}',
+    '\u{1E9E}',
+    '\u{3A3}',
+    '\u{1C5}',
+    '\u{FB03}',
+    '\u{212A}',
+    '\u{2126}',
+    '\u{1F1EF}',
+    '\u{1F1F5}',
+    '\u{1F1FF}',
+    '\u{FE0F}',
+    '\0',
+    ':',
+];
+
+// Strings of special characters, printable ASCII and anything else in Unicode
+fn arbitrary_based() -> impl Strategy<Value = String> {
+    let char = prop_oneof![
+        2 => select(&SPECIAL_CHARS[..]),
+        1 => proptest::char::range(' ', '~'),
+        1 => any::<char>(),
+    ];
+    vec(char, 0..40).prop_map(|chars| chars.into_iter().collect())
+}
+
+// A built-in canonical name with whitespace of every kind around and between its
+// words, and some of its letters upper-cased
+fn respaced_canonical_name() -> impl Strategy<Value = (&'static str, String)> {
+    const SPACES: [char; 4] = [' ', '\t', '\u{A0}', '\u{3000}'];
+    let spaces = |count| vec(select(&SPACES[..]), count);
+    (0..builtin::BUILTIN_COUNTRIES.len())
+        .prop_flat_map(move |index| {
+            let name = builtin::BUILTIN_COUNTRIES[index].name;
+            let words = name.split(' ').count();
+            (
+                Just(name),
+                vec(any::<bool>(), name.chars().count()),
+                // Before the first word, between each two (never none) and after the last
+                spaces(0..3),
+                vec(spaces(1..4), words - 1),
+                spaces(0..3),
+            )
+        })
+        .prop_map(|(name, upper, leading, between, trailing)| {
+            let mut spaced: String = leading.into_iter().collect();
+            let mut upper = upper.into_iter();
+            for (position, word) in name.split(' ').enumerate() {
+                if position > 0 {
+                    spaced.extend(&between[position - 1]);
+                    upper.next();
+                }
+                for c in word.chars() {
+                    // Only where the upper case lowers back, as "ß" to "SS" would not
+                    let upper_case: Vec<_> = c.to_uppercase().collect();
+                    let flips = upper_case.len() == 1 && upper_case[0].to_lowercase().eq([c]);
+                    spaced.push(if flips && upper.next() == Some(true) {
+                        upper_case[0]
+                    } else {
+                        c
+                    });
+                }
+            }
+            spaced.extend(trailing);
+            (name, spaced)
+        })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2_000))]
+
+    #[test]
+    fn test_resolve_query_holds_for_arbitrary_strings(based in arbitrary_based()) {
+        let dataset = builtin::dataset();
+        let query = lookup::resolve_query(&based, &dataset);
+        let commas = based.matches(',').count();
+        prop_assert!(query.entries.len() <= commas + 1);
+        for entry in &query.entries {
+            prop_assert!(!entry.input.is_empty());
+            prop_assert_eq!(input::clean(entry.input), entry.input);
+        }
+        let response = query.response("en");
+        prop_assert!(response.results.len() + response.unmatched.len() <= query.entries.len());
+        lookup::validate_names(&dataset, &based, "ja");
+        let _ = input::check(&based);
+
+        let normalized = normalize(&based);
+        prop_assert_eq!(normalize(&normalized), normalized.clone());
+    }
+
+    #[test]
+    fn test_canonical_names_resolve_whatever_the_spacing_and_case(
+        (name, spaced) in respaced_canonical_name()
+    ) {
+        let dataset = builtin::dataset();
+        let query = lookup::resolve_query(&spaced, &dataset);
+        prop_assert_eq!(query.entries.len(), 1);
+        match &query.entries[0].resolution {
+            lookup::Resolution::Name(found, _) => prop_assert_eq!(found.name.as_str(), name),
+            other => prop_assert!(false, "resolved as {:?}", other),
+        }
+    }
+}
+
+// A `serve`-like command for `config::resolve`
+fn config_test_command() -> clap::Command {
+    use clap::{Arg, ArgAction, Command};
+
+    let option = |name: &'static str| Arg::new(name).long(name).default_value("default");
+    Command::new("rusty_currency").subcommand(
+        Command::new("serve")
+            .arg(option("everywhere"))
+            .arg(option("from-env"))
+            .arg(option("from-file"))
+            .arg(option("unset"))
+            .arg(Arg::new("watch").long("watch").action(ArgAction::SetTrue))
+            .arg(
+                Arg::new("disable-docs")
+                    .long("disable-docs")
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                Arg::new("listen")
+                    .long("listen")
+                    .action(ArgAction::Append)
+                    .value_parser(clap::value_parser!(SocketAddr)),
+            )
+            .arg(
+                Arg::new("limit")
+                    .long("limit")
+                    .value_parser(clap::value_parser!(u32)),
+            )
+            .arg(Arg::new("data-file").long("data-file"))
+            .arg(
+                Arg::new("database")
+                    .long("database")
+                    .conflicts_with("data-file"),
+            )
+            .arg(Arg::new("config").long("config")),
+    )
+}
+
+fn resolve_config(
+    args: &[&str],
+    env: &[(&str, &str)],
+    file: &str,
+) -> Result<config::Resolved, config::ConfigError> {
+    let args: Vec<std::ffi::OsString> = args.iter().map(Into::into).collect();
+    let env = env
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    config::resolve(
+        config_test_command(),
+        &args,
+        &env,
+        Some(&file.parse().unwrap()),
+    )
+}
+
+fn serve_matches(resolved: &config::Resolved) -> &clap::ArgMatches {
+    resolved.matches.subcommand_matches("serve").unwrap()
+}
+
+#[test]
+fn test_config_precedence() {
+    let env = [
+        ("RUSTINCOUNTRY_EVERYWHERE", "env"),
+        ("RUSTINCOUNTRY_FROM_ENV", "env"),
+        ("RUSTINCOUNTRY_DATABASE", "countries.db"),
+    ];
+    let file = r#"
+        everywhere = "file"
+        from-env = "file"
+        from-file = "file"
+        watch = true
+        listen = ["127.0.0.1:1", "127.0.0.1:2"]
+        limit = 7
+    "#;
+
+    let resolved = resolve_config(
+        &[
+            "rusty_currency",
+            "serve",
+            "--everywhere",
+            "cli",
+            "--data-file",
+            "a.json",
+        ],
+        &env,
+        file,
+    )
+    .unwrap();
+
+    let serve = serve_matches(&resolved);
+    let value = |id| serve.get_one::<String>(id).unwrap().as_str();
+    assert_eq!(value("everywhere"), "cli");
+    assert_eq!(value("from-env"), "env");
+    assert_eq!(value("from-file"), "file");
+    assert_eq!(value("unset"), "default");
+    assert!(serve.get_flag("watch"));
+    let listen: Vec<_> = serve.get_many::<SocketAddr>("listen").unwrap().collect();
+    assert_eq!(listen.len(), 2);
+    assert_eq!(serve.get_one::<u32>("limit"), Some(&7));
+    // `--data-file` overrides the variable's conflicting `database`
+    assert_eq!(value("data-file"), "a.json");
+    assert_eq!(serve.get_one::<String>("database"), None);
+    assert!(resolved.unknown_keys.is_empty());
+}
+
+#[test]
+fn test_config_from_environment_variables() {
+    let env = [
+        ("RUSTINCOUNTRY_LISTEN", "127.0.0.1:1, [::1]:2,"),
+        ("RUSTINCOUNTRY_WATCH", "yes"),
+        ("RUSTINCOUNTRY_DISABLE_DOCS", "0"),
+        ("RUSTINCOUNTRY_LIMIT", "42"),
+        ("RUSTINCOUNTRY_UNRELATED", "ignored"),
+        ("LIMIT", "1"),
+    ];
+    // The variable's list replaces the file's rather than adding to it
+    let resolved = resolve_config(
+        &["rusty_currency", "serve"],
+        &env,
+        "listen = [\"127.0.0.1:3\"]\ndisable-docs = true",
+    )
+    .unwrap();
+
+    let serve = serve_matches(&resolved);
+    let listen: Vec<_> = serve.get_many::<SocketAddr>("listen").unwrap().collect();
+    assert_eq!(
+        listen,
+        [
+            &"127.0.0.1:1".parse::<SocketAddr>().unwrap(),
+            &"[::1]:2".parse().unwrap()
+        ]
+    );
+    assert!(serve.get_flag("watch"));
+    assert!(!serve.get_flag("disable-docs"));
+    assert_eq!(serve.get_one::<u32>("limit"), Some(&42));
+
+    for value in ["1", "true", "YES", "on"] {
+        let resolved = resolve_config(
+            &["rusty_currency", "serve"],
+            &[("RUSTINCOUNTRY_WATCH", value)],
+            "",
+        )
+        .unwrap();
+        assert!(serve_matches(&resolved).get_flag("watch"), "{}", value);
+    }
+}
+
+#[test]
+fn test_config_invalid_variables_name_the_variable() {
+    for (variable, value, message) in [
+        (
+            "RUSTINCOUNTRY_LIMIT",
+            "lots",
+            "Invalid RUSTINCOUNTRY_LIMIT 'lots': invalid digit found in string; expected <LIMIT>, as --limit takes",
+        ),
+        (
+            "RUSTINCOUNTRY_WATCH",
+            "maybe",
+            "Invalid RUSTINCOUNTRY_WATCH 'maybe': not a boolean; expected 1, true, yes, 0, false or no",
+        ),
+        (
+            "RUSTINCOUNTRY_LISTEN",
+            "127.0.0.1:1,nowhere",
+            "Invalid RUSTINCOUNTRY_LISTEN 'nowhere': invalid socket address syntax; expected comma-separated <LISTEN> values, as --listen takes",
+        ),
+    ] {
+        let error =
+            resolve_config(&["rusty_currency", "serve"], &[(variable, value)], "").unwrap_err();
+        assert_eq!(error.to_string(), message);
+    }
+
+    // One the command line overrides is never read
+    let resolved = resolve_config(
+        &["rusty_currency", "serve", "--limit", "3"],
+        &[("RUSTINCOUNTRY_LIMIT", "lots")],
+        "",
+    )
+    .unwrap();
+    assert_eq!(serve_matches(&resolved).get_one::<u32>("limit"), Some(&3));
+}
+
+#[test]
+fn test_config_unknown_keys_and_invalid_values() {
+    let resolved = resolve_config(
+        &["rusty_currency", "serve"],
+        &[],
+        "colour = \"blue\"\nconfig = \"other.toml\"\nwatch = false\n[tls]\ncert = \"a.pem\"",
+    )
+    .unwrap();
+    assert_eq!(resolved.unknown_keys, ["colour", "config", "tls"]);
+    assert!(!serve_matches(&resolved).get_flag("watch"));
+
+    let error = resolve_config(&["rusty_currency", "serve"], &[], "limit = \"lots\"").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Invalid config file key `limit` 'lots': invalid digit found in string; expected <LIMIT>, as --limit takes"
+    );
+    let error =
+        resolve_config(&["rusty_currency", "serve"], &[], "limit = { at = 1 }").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "`limit` must be a string, number, boolean or an array of them"
+    );
+}
+
+fn trusted_proxies(networks: &[&str]) -> client_ip::TrustedProxies {
+    client_ip::TrustedProxies::new(
+        networks
+            .iter()
+            .map(|network| client_ip::parse_network(network).unwrap()),
+    )
+}
+
+fn forwarding_headers(headers: &[(&'static str, &str)]) -> axum::http::HeaderMap {
+    let mut map = axum::http::HeaderMap::new();
+    for (name, value) in headers {
+        map.append(*name, value.parse().unwrap());
+    }
+    map
+}
+
+#[test]
+fn test_trusted_proxies_take_the_rightmost_untrusted_forwarded_address() {
+    let proxies = trusted_proxies(&["10.0.0.0/8", "2001:db8::/32", "192.0.2.7"]);
+    let peer: IpAddr = "10.0.0.5".parse().unwrap();
+    let client = |headers: &[(&'static str, &str)]| {
+        proxies
+            .client(peer, &forwarding_headers(headers))
+            .to_string()
+    };
+
+    assert_eq!(client(&[]), "10.0.0.5");
+    assert_eq!(
+        client(&[("x-forwarded-for", "1.1.1.1, 8.8.8.8, 192.0.2.7")]),
+        "8.8.8.8"
+    );
+    // Headers repeated by each proxy read as one list, in order
+    assert_eq!(
+        client(&[
+            ("x-forwarded-for", "1.1.1.1"),
+            ("x-forwarded-for", "8.8.8.8:51234, 10.9.9.9")
+        ]),
+        "8.8.8.8"
+    );
+    // Every hop trusted: the first one is as close to the client as can be known
+    assert_eq!(
+        client(&[("x-forwarded-for", "10.1.1.1, 10.2.2.2")]),
+        "10.1.1.1"
+    );
+    // IPv4-mapped addresses are matched as IPv4
+    assert_eq!(
+        client(&[("x-forwarded-for", "8.8.8.8, ::ffff:10.2.2.2")]),
+        "8.8.8.8"
+    );
+    assert_eq!(
+        client(&[(
+            "forwarded",
+            "for=1.1.1.1, For=\"[2001:4860::8888]:443\";proto=https, for=\"[2001:db8::1]\""
+        )]),
+        "2001:4860::8888"
+    );
+    // X-Forwarded-For wins over Forwarded when both are sent
+    assert_eq!(
+        client(&[("forwarded", "for=1.1.1.1"), ("x-forwarded-for", "8.8.8.8")]),
+        "8.8.8.8"
+    );
+}
+
+#[test]
+fn test_trusted_proxies_ignore_headers_from_other_peers() {
+    let proxies = trusted_proxies(&["10.0.0.0/8"]);
+    let headers =
+        forwarding_headers(&[("x-forwarded-for", "8.8.8.8"), ("forwarded", "for=8.8.8.8")]);
+
+    for peer in ["203.0.113.9", "::ffff:203.0.113.9"] {
+        assert_eq!(
+            proxies.client(peer.parse().unwrap(), &headers).to_string(),
+            "203.0.113.9"
+        );
+    }
+    assert_eq!(
+        client_ip::TrustedProxies::default()
+            .client("10.0.0.5".parse().unwrap(), &headers)
+            .to_string(),
+        "10.0.0.5"
+    );
+}
+
+#[test]
+fn test_trusted_proxies_stop_at_malformed_entries() {
+    let proxies = trusted_proxies(&["10.0.0.0/8"]);
+    let peer: IpAddr = "10.0.0.5".parse().unwrap();
+    let client = |name: &'static str, value: &[u8]| {
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert(name, HeaderValue::from_bytes(value).unwrap());
+        proxies.client(peer, &headers).to_string()
+    };
+
+    assert_eq!(
+        client("x-forwarded-for", b"8.8.8.8, 999.1.1.1, 10.1.1.1"),
+        "10.1.1.1"
+    );
+    assert_eq!(client("x-forwarded-for", b"8.8.8.8 10.1.1.1"), "10.0.0.5");
+    assert_eq!(client("x-forwarded-for", b"8.8.8.8, unknown"), "10.0.0.5");
+    assert_eq!(
+        client("x-forwarded-for", b"8.8.8.8, [10.1.1.1]"),
+        "10.0.0.5"
+    );
+    assert_eq!(client("x-forwarded-for", b"8.8.8.8, \xff"), "10.0.0.5");
+    assert_eq!(client("forwarded", b"for=8.8.8.8, for=unknown"), "10.0.0.5");
+    assert_eq!(
+        client("forwarded", b"for=8.8.8.8;by=10.0.0.5, for"),
+        "10.0.0.5"
+    );
+    assert_eq!(client("forwarded", b"for=\"[2001:4860::8888\""), "10.0.0.5");
+}
+
+#[test]
+fn test_parse_trusted_proxy_networks() {
+    assert_eq!(
+        client_ip::parse_network("10.0.0.0/8").unwrap().to_string(),
+        "10.0.0.0/8"
+    );
+    assert_eq!(
+        client_ip::parse_network("192.0.2.7").unwrap().to_string(),
+        "192.0.2.7/32"
+    );
+    assert_eq!(
+        client_ip::parse_network("2001:db8::1").unwrap().to_string(),
+        "2001:db8::1/128"
+    );
+    for invalid in ["10.0.0.0/33", "example.com", "", "10.0.0.0/8,"] {
+        assert_eq!(
+            client_ip::parse_network(invalid).unwrap_err(),
+            format!("{:?} is not an address or CIDR range", invalid)
+        );
+    }
+}
+
+// A router answering `/ok` and `/healthz` with 200, `/down` with 503 and `/slow` after
+// 50ms, behind the access log sampling with a fixed seed
+fn access_logged_app(config: access_log::AccessLogConfig) -> (Router, AppState) {
+    use axum::routing::get;
+    use rand::SeedableRng;
+
+    let state = AppState::new(builtin::dataset());
+    let access_log = access_log::AccessLog::new(config, &state)
+        .with_rng(rand::rngs::SmallRng::seed_from_u64(185));
+    let app = Router::new()
+        .route("/ok", get(|| async { "ok" }))
+        .route("/healthz", get(|| async { "ok" }))
+        .route("/down", get(|| async { StatusCode::SERVICE_UNAVAILABLE }))
+        .route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                "ok"
+            }),
+        )
+        .layer(axum::middleware::from_fn_with_state(
+            Arc::new(access_log),
+            log_access,
+        ))
+        .layer(axum::extract::connect_info::MockConnectInfo(
+            SocketAddr::from(([203, 0, 113, 7], 4000)),
+        ));
+    (app, state)
+}
+
+async fn send_requests(app: &Router, path: &str, times: usize) {
+    for _ in 0..times {
+        app.clone()
+            .oneshot(Request::get(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+    }
+}
+
+fn logged_paths(captured: &CapturedEvents) -> Vec<String> {
+    captured
+        .events()
+        .into_iter()
+        .map(|fields| fields["path"].clone())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_access_log_samples_one_in_n_successful_requests() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let captured = CapturedEvents::new(access_log::TARGET);
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+    let (app, state) = access_logged_app(access_log::AccessLogConfig {
+        sample_rate: NonZeroU32::new(10).unwrap(),
+        ..Default::default()
+    });
+    send_requests(&app, "/ok", 2000).await;
+
+    let logged = logged_paths(&captured).len();
+    assert!((150..=250).contains(&logged), "{} of 2000 logged", logged);
+    let metrics = state.metrics.render();
+    assert!(
+        metrics.contains(&format!(
+            "rustincountry_access_log_skipped_total{{reason=\"sampled\"}} {}",
+            2000 - logged
+        )),
+        "{}",
+        metrics
+    );
+    assert!(metrics.contains("rustincountry_access_log_skipped_total{reason=\"excluded\"} 0"));
+
+    // The same seed samples the same requests
+    let (again, _) = access_logged_app(access_log::AccessLogConfig {
+        sample_rate: NonZeroU32::new(10).unwrap(),
+        ..Default::default()
+    });
+    send_requests(&again, "/ok", 2000).await;
+    assert_eq!(logged_paths(&captured).len(), logged * 2);
+
+    let fields = &captured.events()[0];
+    assert_eq!(fields["method"], "GET");
+    assert_eq!(fields["status"], "200");
+    assert_eq!(fields["client"], "203.0.113.7");
+}
+
+#[tokio::test]
+async fn test_access_log_always_logs_failed_and_slow_requests() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let captured = CapturedEvents::new(access_log::TARGET);
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+    let (app, state) = access_logged_app(access_log::AccessLogConfig {
+        sample_rate: NonZeroU32::MAX,
+        slow_threshold: Duration::from_millis(20),
+        ..Default::default()
+    });
+    send_requests(&app, "/ok", 20).await;
+    send_requests(&app, "/down", 20).await;
+    send_requests(&app, "/missing", 5).await;
+    send_requests(&app, "/slow", 2).await;
+
+    let logged = logged_paths(&captured);
+    assert_eq!(
+        logged.iter().filter(|path| *path == "/down").count(),
+        20,
+        "{:?}",
+        logged
+    );
+    assert_eq!(logged.iter().filter(|path| *path == "/missing").count(), 5);
+    assert_eq!(logged.iter().filter(|path| *path == "/slow").count(), 2);
+    assert!(!logged.contains(&String::from("/ok")), "{:?}", logged);
+    assert!(state
+        .metrics
+        .render()
+        .contains("rustincountry_access_log_skipped_total{reason=\"sampled\"} 20"));
+}
+
+#[tokio::test]
+async fn test_access_log_leaves_out_successful_requests_to_excluded_paths() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let captured = CapturedEvents::new(access_log::TARGET);
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+    // The health checks are excluded by default
+    let (app, state) = access_logged_app(access_log::AccessLogConfig::default());
+    send_requests(&app, "/healthz", 30).await;
+    send_requests(&app, "/healthz/", 1).await;
+    send_requests(&app, "/ok", 3).await;
+    assert_eq!(logged_paths(&captured), ["/healthz/", "/ok", "/ok", "/ok"]);
+    assert!(state
+        .metrics
+        .render()
+        .contains("rustincountry_access_log_skipped_total{reason=\"excluded\"} 30"));
+
+    // An excluded path is still logged when it fails
+    let (app, _) = access_logged_app(access_log::AccessLogConfig {
+        excluded_paths: vec![String::from("/ok"), String::from("/down")],
+        ..Default::default()
+    });
+    send_requests(&app, "/ok", 2).await;
+    send_requests(&app, "/down", 2).await;
+    send_requests(&app, "/healthz", 1).await;
+    assert_eq!(logged_paths(&captured)[4..], ["/down", "/down", "/healthz"]);
+}
+
+#[tokio::test]
+async fn test_access_log_lines_carry_the_request_id() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let captured = CapturedEvents::new(access_log::TARGET);
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+    let config = AppConfig {
+        access_log: Some(access_log::AccessLogConfig::default()),
+        ..AppConfig::default()
+    };
+    let app = create_app_with_state(AppState::new(builtin::dataset()), &config);
+    let response = app
+        .clone()
+        .oneshot(
+            Request::get("/getCountry?based=japan")
+                .header("x-request-id", "access-185")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    app.oneshot(Request::get("/healthz").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+
+    let events = captured.events();
+    assert_eq!(events.len(), 1, "{:?}", events);
+    assert_eq!(events[0]["path"], "/getCountry");
+    assert_eq!(events[0]["request_id"], "\"access-185\"");
+    assert_eq!(events[0]["client"], "unknown");
+
+    // Without the config nothing is logged
+    send_requests(&create_app(), "/getCountry?based=japan", 1).await;
+    assert_eq!(captured.events().len(), 1);
+}
+
+#[tokio::test]
+async fn test_latency_histograms_are_kept_per_route() {
+    let app = create_app();
+    for uri in [
+        "/getCountry?based=japan",
+        "/getCountry?based=korea",
+        "/flag/jp.svg",
+        "/flag/kr.svg",
+        "/nowhere",
+    ] {
+        app.clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+    }
+
+    let metrics = get_metrics(&app).await;
+    assert!(metrics.contains("# TYPE rustincountry_request_duration_seconds histogram"));
+    for (route, count) in [("/getCountry", 2), ("/flag/:file", 2), ("unmatched", 1)] {
+        for series in [
+            format!(
+                "rustincountry_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}",
+                route, count
+            ),
+            format!(
+                "rustincountry_request_duration_seconds_count{{route=\"{}\"}} {}",
+                route, count
+            ),
+            format!(
+                "rustincountry_request_duration_seconds_sum{{route=\"{}\"}} ",
+                route
+            ),
+        ] {
+            assert!(metrics.contains(&series), "{}\n{}", series, metrics);
+        }
+        let buckets = metrics
+            .lines()
+            .filter(|line| {
+                line.starts_with(&format!(
+                    "rustincountry_request_duration_seconds_bucket{{route=\"{}\",",
+                    route
+                ))
+            })
+            .count();
+        assert_eq!(buckets, metrics::LATENCY_BUCKETS.len() + 1);
+    }
+    // Paths are not labels of their own
+    assert!(!metrics.contains("/flag/jp.svg"), "{}", metrics);
+    assert!(!metrics.contains("/nowhere"), "{}", metrics);
+}
+
+#[test]
+fn test_latency_buckets_are_cumulative() {
+    let metrics = metrics::Metrics::default();
+    for millis in [0, 3, 3, 40, 20_000] {
+        metrics.record_latency("/getCountry", Duration::from_millis(millis));
+    }
+
+    let rendered = metrics.render();
+    for (bound, count) in [
+        ("0.0005", 1),
+        ("0.005", 3),
+        ("0.05", 4),
+        ("10", 4),
+        ("+Inf", 5),
+    ] {
+        let series = format!(
+            "rustincountry_request_duration_seconds_bucket{{route=\"/getCountry\",le=\"{}\"}} {}\n",
+            bound, count
+        );
+        assert!(rendered.contains(&series), "{}\n{}", series, rendered);
+    }
+    assert!(rendered
+        .contains("rustincountry_request_duration_seconds_sum{route=\"/getCountry\"} 20.046\n"));
+}
+
+#[tokio::test]
+async fn test_slow_requests_are_logged_with_their_normalized_query() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let captured = CapturedEvents::new("rusty_currency::server");
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+    let config = AppConfig {
+        slow_request_threshold: Some(Duration::from_millis(20)),
+        ..AppConfig::default()
+    };
+    let app = create_app_with_state(AppState::new(builtin::dataset()), &config);
+    for uri in [
+        "/getCountry?based=japan",
+        "/__test/delayed?lang=ja&based=%20JAPAN%20,,C%C3%B4te%20d%27Ivoire&based=korea",
+    ] {
+        app.clone()
+            .oneshot(
+                Request::get(uri)
+                    .header("x-request-id", "slow-186")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+    }
+
+    let slow: Vec<_> = captured
+        .events()
+        .into_iter()
+        .filter(|fields| fields["message"] == "Slow request")
+        .collect();
+    assert_eq!(slow.len(), 1, "{:?}", slow);
+    let fields = &slow[0];
+    assert_eq!(fields["route"], "/__test/delayed");
+    assert_eq!(
+        fields["query"],
+        "\"based=japan,côte d'ivoire&based=korea&lang=ja\""
+    );
+    assert_eq!(fields["status"], "204");
+    assert_eq!(fields["results"], "2");
+    assert_eq!(fields["unmatched"], "1");
+    assert_eq!(fields["request_id"], "\"slow-186\"");
+    assert!(fields["latency_ms"].parse::<u64>().unwrap() >= 50);
+}
+
+#[tokio::test]
+async fn test_slow_requests_served_from_the_response_cache_keep_their_counts() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let captured = CapturedEvents::new("rusty_currency::server");
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(captured.clone()));
+    let config = AppConfig {
+        slow_request_threshold: Some(Duration::ZERO),
+        ..AppConfig::default()
+    };
+    let state = AppState::new(builtin::dataset()).with_response_cache(8);
+    let app = create_app_with_state(state.clone(), &config);
+    for _ in 0..2 {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/getCountry?based=japan,8.8.8.8,france")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+    assert_eq!(
+        state
+            .metrics
+            .cache_hits
+            .load(std::sync::atomic::Ordering::Relaxed),
+        1
+    );
+
+    let slow: Vec<_> = captured
+        .events()
+        .into_iter()
+        .filter(|fields| fields["message"] == "Slow request")
+        .collect();
+    assert_eq!(slow.len(), 2, "{:?}", slow);
+    for fields in &slow {
+        assert_eq!(fields["results"], "2", "{:?}", fields);
+        assert_eq!(fields["unmatched"], "1", "{:?}", fields);
+    }
+}
+
+#[tokio::test]
+async fn test_self_test_passes_against_the_default_app() {
+    let state = AppState::new(Dataset::builtin());
+    let app = create_app_with_state(state.clone(), &AppConfig::default());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let country = self_test::served_country(&state).unwrap();
+
+    let report = self_test::run(
+        listener,
+        app,
+        ServeOptions::default(),
+        &self_test::cases(),
+        &country,
+    )
+    .await
+    .unwrap();
+
+    assert!(report.passed(), "{}", report);
+    assert_eq!(report.results.len(), self_test::cases().len());
+    assert!(report.to_string().ends_with("6 checks, 6 passed, 0 failed"));
+}
+
+#[tokio::test]
+async fn test_self_test_fails_against_a_broken_route_set() {
+    use axum::routing::get;
+
+    // Healthy, but every lookup comes back empty and there is no /metrics
+    let app = Router::new()
+        .route(
+            "/healthz",
+            get(|| async { axum::Json(serde_json::json!({"status": "ok"})) }),
+        )
+        .route(
+            "/getCountry",
+            get(|| async { axum::Json(serde_json::json!({"results": []})) }),
+        )
+        .route(
+            "/countries",
+            get(|| async { axum::Json(serde_json::json!({"results": []})) }),
+        );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    let report = self_test::run(
+        listener,
+        app,
+        ServeOptions::default(),
+        &self_test::cases(),
+        "japan",
+    )
+    .await
+    .unwrap();
+
+    assert!(!report.passed());
+    let failures: BTreeMap<_, _> = report
         .results
         .iter()
-        .any(|country| country.country == "japan" && country.phone_code == "+81"));
+        .map(|result| (result.name, result.failure.as_deref()))
+        .collect();
+    assert_eq!(
+        failures["single lookup"],
+        Some("the country is not in `results`")
+    );
+    assert_eq!(
+        failures["batch lookup"],
+        Some("expected status 200, got 404")
+    );
+    assert_eq!(
+        failures["unknown name"],
+        Some("expected status 422, got 200")
+    );
+    assert_eq!(failures["country list"], Some("no countries are listed"));
+    assert_eq!(failures["health"], None);
+    assert_eq!(failures["metrics"], Some("expected status 200, got 404"));
+    let report = report.to_string();
+    assert!(report.contains("PASS  health         GET /healthz?verbose=true\n"));
+    assert!(report.ends_with("6 checks, 1 passed, 5 failed"));
+}
+
+// An upstream failure, transient unless it is a 4xx
+#[derive(Debug, Clone, PartialEq)]
+struct UpstreamStatus(u16);
+
+impl std::fmt::Display for UpstreamStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "status {}", self.0)
+    }
+}
+
+impl retry::Transient for UpstreamStatus {
+    fn is_transient(&self) -> bool {
+        self.0 >= 500
+    }
+}
+
+// Runs `retry_async_with` over the `outcomes` in turn, recording sleeps instead of waiting;
+// returns the result, the attempts made and the sleeps
+async fn retry_outcomes(
+    policy: &retry::RetryPolicy,
+    outcomes: Vec<Result<&'static str, UpstreamStatus>>,
+) -> (Result<&'static str, UpstreamStatus>, usize, Vec<Duration>) {
+    let outcomes = std::cell::RefCell::new(outcomes.into_iter());
+    let attempts = Cell::new(0);
+    let slept = std::cell::RefCell::new(Vec::new());
+    let result = retry::retry_async_with(
+        policy,
+        "Fetching",
+        |backoff| {
+            slept.borrow_mut().push(backoff);
+            std::future::ready(())
+        },
+        || {
+            attempts.set(attempts.get() + 1);
+            std::future::ready(outcomes.borrow_mut().next().expect("no attempts left"))
+        },
+    )
+    .await;
+    (result, attempts.get(), slept.into_inner())
+}
+
+#[tokio::test]
+async fn retry_succeeds_after_transient_failures_backing_off_with_jitter() {
+    let policy = retry::RetryPolicy {
+        max_retries: 3,
+        initial_backoff: Duration::from_millis(200),
+    };
+
+    let (result, attempts, slept) = retry_outcomes(
+        &policy,
+        vec![
+            Err(UpstreamStatus(503)),
+            Err(UpstreamStatus(502)),
+            Ok("rates"),
+        ],
+    )
+    .await;
+
+    assert_eq!(result, Ok("rates"));
+    assert_eq!(attempts, 3);
+    assert_eq!(slept.len(), 2);
+    for (backoff, longest) in slept.iter().zip([200, 400]) {
+        let longest = Duration::from_millis(longest);
+        assert!(
+            *backoff >= longest / 2 && *backoff <= longest,
+            "{:?} is not within jitter of {:?}",
+            backoff,
+            longest
+        );
+    }
+}
+
+#[tokio::test]
+async fn retry_never_repeats_a_4xx() {
+    let (result, attempts, slept) = retry_outcomes(
+        &retry::RetryPolicy::default(),
+        vec![Err(UpstreamStatus(404))],
+    )
+    .await;
+
+    assert_eq!(result, Err(UpstreamStatus(404)));
+    assert_eq!(attempts, 1);
+    assert!(slept.is_empty());
+}
+
+#[tokio::test]
+async fn retry_gives_up_after_max_retries() {
+    let policy = retry::RetryPolicy {
+        max_retries: 2,
+        initial_backoff: Duration::from_secs(4),
+    };
+
+    let (result, attempts, slept) =
+        retry_outcomes(&policy, vec![Err(UpstreamStatus(500)); 3]).await;
+
+    assert_eq!(result, Err(UpstreamStatus(500)));
+    assert_eq!(attempts, 3);
+    // The second wait would be 8 seconds but for the cap
+    assert_eq!(policy.backoff(2), Duration::from_secs(5));
+    assert!(slept[1] <= Duration::from_secs(5));
+    assert_eq!(
+        retry_outcomes(&retry::RetryPolicy::never(), vec![Err(UpstreamStatus(500))])
+            .await
+            .1,
+        1
+    );
+}
+
+fn snapshot_date() -> chrono::DateTime<chrono::Utc> {
+    "2025-01-02T00:00:00Z".parse().unwrap()
+}
+
+#[test]
+fn test_rate_snapshot_has_a_valid_rate_for_every_builtin_currency() {
+    let snapshot = rate_snapshot::RateSnapshot::builtin();
+
+    for country in builtin::BUILTIN_COUNTRIES.iter() {
+        let rate = snapshot
+            .rate(country.currency_code)
+            .unwrap_or_else(|| panic!("no snapshot rate for {}", country.currency_code));
+        assert_eq!(
+            rates::validate(country.currency_code, rate.to_usd),
+            Ok(rate.to_usd)
+        );
+        assert_eq!(rate.as_of, snapshot_date());
+        assert_eq!(rate.source, rates::RateSource::Snapshot);
+    }
+    let usd = snapshot.rate(CurrencyCode::new("USD").unwrap()).unwrap();
+    assert_eq!(usd.to_usd, 1.0);
+}
+
+#[tokio::test]
+async fn test_rate_snapshot_rejects_what_a_live_rate_would_fail() {
+    use rate_snapshot::{RateSnapshot, SnapshotError};
+
+    assert_eq!(
+        RateSnapshot::parse([("JPY", "-0.0067", "2025-01-02")]),
+        Err(SnapshotError::InvalidRate(rates::RateError::Invalid {
+            currency: jpy(),
+            rate: -0.0067
+        }))
+    );
+    assert!(matches!(
+        RateSnapshot::parse([("JPY", "inf", "2025-01-02")]),
+        Err(SnapshotError::InvalidRate(_))
+    ));
+    assert!(matches!(
+        RateSnapshot::parse([("JPY", "cheap", "2025-01-02")]),
+        Err(SnapshotError::InvalidNumber { .. })
+    ));
+    assert!(matches!(
+        RateSnapshot::parse([("JPY", "0.0067", "02/01/2025")]),
+        Err(SnapshotError::InvalidDate { .. })
+    ));
+    assert!(matches!(
+        RateSnapshot::parse([("yen", "0.0067", "2025-01-02")]),
+        Err(SnapshotError::InvalidCurrency(_))
+    ));
+    assert_eq!(
+        RateSnapshot::parse([
+            ("JPY", "0.0067", "2025-01-02"),
+            ("JPY", "0.007", "2025-01-03")
+        ]),
+        Err(SnapshotError::Duplicate(jpy()))
+    );
+
+    // A provider answering the same is refused, and counts against it
+    let cache = rates::RateCache::new(
+        Arc::new(rates::StaticRateProvider::new([(jpy(), -0.0067)])),
+        rates::DEFAULT_TTL,
+    );
+    assert_eq!(
+        cache.rate_to_usd(jpy()).await,
+        Err(rates::RateError::Invalid {
+            currency: jpy(),
+            rate: -0.0067
+        })
+    );
+    assert_eq!(cache.breaker().consecutive_failures(), 1);
+}
+
+#[tokio::test]
+async fn test_rate_cache_answers_live_rates_with_when_they_were_fetched() {
+    let cache = rates::RateCache::new(
+        Arc::new(rates::StaticRateProvider::new([(jpy(), 0.0067)])),
+        rates::DEFAULT_TTL,
+    )
+    .with_snapshot(rate_snapshot::RateSnapshot::builtin());
+    let before = chrono::Utc::now() - chrono::Duration::seconds(1);
+
+    let rate = cache.rate(jpy()).await.unwrap();
+
+    assert_eq!(rate.to_usd, 0.0067);
+    assert_eq!(rate.source, rates::RateSource::Live);
+    assert!(rate.as_of >= before && rate.as_of <= chrono::Utc::now());
+    // Cached with its time, not stamped again
+    assert_eq!(cache.rate(jpy()).await.unwrap(), rate);
+}
+
+#[tokio::test]
+async fn test_rate_cache_falls_back_to_the_snapshot_when_the_provider_fails() {
+    let snapshot = rate_snapshot::RateSnapshot::builtin();
+    let failing = rates::RateCache::new(
+        Arc::new(rates::StaticRateProvider::failing("connection refused")),
+        rates::DEFAULT_TTL,
+    )
+    .with_snapshot(snapshot);
+
+    let rate = failing.rate(jpy()).await.unwrap();
+    assert_eq!(rate, snapshot.rate(jpy()).unwrap());
+    assert_eq!(rate.source, rates::RateSource::Snapshot);
+    assert_eq!(rate.as_of, snapshot_date());
+
+    // A provider that has no rate for a currency is believed
+    let eur = CurrencyCode::new("EUR").unwrap();
+    let missing = rates::RateCache::new(
+        Arc::new(rates::StaticRateProvider::new([(jpy(), 0.0067)])),
+        rates::DEFAULT_TTL,
+    )
+    .with_snapshot(snapshot);
+    assert_eq!(missing.rate(eur).await, Err(rates::RateError::Missing(eur)));
+
+    let offline = rates::RateCache::offline(snapshot);
+    assert_eq!(offline.rate(jpy()).await, Ok(rate));
+    assert_eq!(offline.probe().await, Ok(()));
+
+    // Without a snapshot the failure is answered as before
+    let without = rates::RateCache::new(
+        Arc::new(rates::StaticRateProvider::failing("connection refused")),
+        rates::DEFAULT_TTL,
+    );
+    assert_eq!(
+        without.rate(jpy()).await,
+        Err(rates::RateError::Upstream(String::from(
+            "connection refused"
+        )))
+    );
+}
+
+// Serves `config`'s app over TLS on one listener and plain HTTP on another; returns
+// both addresses, TLS first
+async fn serve_https_with_plain(config: AppConfig) -> (SocketAddr, SocketAddr) {
+    let certificates = Arc::new(
+        tls::TlsCertificates::load(&tls_fixture("first.pem"), &tls_fixture("first.key")).unwrap(),
+    );
+    let listeners = bind_listeners(&[
+        "127.0.0.1:0".parse().unwrap(),
+        "127.0.0.1:0".parse().unwrap(),
+    ])
+    .unwrap();
+    let secure = listeners[0].local_addr().unwrap();
+    let plain = listeners[1].local_addr().unwrap();
+    let app = create_app_with_state(AppState::new(builtin::dataset()), &config);
+    let options = ServeOptions {
+        tls: Some(certificates),
+        plain_addresses: vec![plain],
+        ..ServeOptions::default()
+    };
+    tokio::spawn(run_server_until(
+        listeners,
+        app,
+        std::future::pending(),
+        options,
+    ));
+    (secure, plain)
+}
+
+fn https_test_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_plain_listener_redirects_to_https() {
+    let (secure, plain) = serve_https_with_plain(AppConfig {
+        https_redirect: Some(https::HttpsRedirect { port: 8443 }),
+        hsts: Some(https::Hsts {
+            max_age: Duration::from_secs(31_536_000),
+            include_subdomains: false,
+        }),
+        ..AppConfig::default()
+    })
+    .await;
+    let client = https_test_client();
+
+    let response = client
+        .get(format!("http://{}/getCountry?based=japan", plain))
+        .header("host", "countries.example.com:8080")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 301);
+    assert_eq!(
+        response.headers()["location"],
+        "https://countries.example.com:8443/getCountry?based=japan"
+    );
+    assert!(response.headers().contains_key("x-request-id"));
+    assert!(!response.headers().contains_key("strict-transport-security"));
+
+    // HTTPS itself is served as usual
+    let response = client
+        .get(format!(
+            "https://localhost:{}/getCountry?based=japan",
+            secure.port()
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let on_443 = AppConfig {
+        https_redirect: Some(https::HttpsRedirect { port: 443 }),
+        ..AppConfig::default()
+    };
+    let (_, plain) = serve_https_with_plain(on_443).await;
+    let response = client
+        .get(format!("http://{}/healthz", plain))
+        .header("host", "[::1]:8080")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 301);
+    assert_eq!(response.headers()["location"], "https://[::1]/healthz");
+}
+
+#[tokio::test]
+async fn test_hsts_is_sent_over_tls_only_when_configured() {
+    let client = https_test_client();
+    let hsts = |include_subdomains| AppConfig {
+        hsts: Some(https::Hsts {
+            max_age: Duration::from_secs(31_536_000),
+            include_subdomains,
+        }),
+        ..AppConfig::default()
+    };
+
+    for (config, expected) in [
+        (hsts(false), Some("max-age=31536000")),
+        (hsts(true), Some("max-age=31536000; includeSubDomains")),
+        (AppConfig::default(), None),
+    ] {
+        let (secure, plain) = serve_https_with_plain(config).await;
+
+        let response = client
+            .get(format!("https://localhost:{}/healthz", secure.port()))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response
+                .headers()
+                .get("strict-transport-security")
+                .map(|value| value.to_str().unwrap()),
+            expected
+        );
+
+        // Without `https_redirect`, the plain listener serves the API, never with HSTS
+        let response = client
+            .get(format!("http://{}/healthz", plain))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(!response.headers().contains_key("strict-transport-security"));
+    }
+}
+
+fn key_set(tiers: &[(&str, u32, Option<u32>)], keys: &[(&str, &str, &str)]) -> api_keys::ApiKeys {
+    let tiers = tiers
+        .iter()
+        .map(|&(name, per_minute, quota)| {
+            let tier = api_keys::Tier {
+                requests_per_minute: NonZeroU32::new(per_minute).unwrap(),
+                daily_quota: quota.and_then(NonZeroU32::new),
+            };
+            (name.to_string(), tier)
+        })
+        .collect();
+    let keys = keys
+        .iter()
+        .map(|&(name, key, tier)| (name.to_string(), key.to_string(), tier.to_string()));
+    api_keys::ApiKeys::new(tiers, keys).unwrap()
+}
+
+#[test]
+fn test_api_keys_reject_unknown_tiers_and_repeats() {
+    let tiers = || {
+        HashMap::from([(
+            String::from("free"),
+            api_keys::Tier {
+                requests_per_minute: NonZeroU32::MIN,
+                daily_quota: None,
+            },
+        )])
+    };
+    let entry =
+        |name: &str, key: &str, tier: &str| (name.to_string(), key.to_string(), tier.to_string());
+
+    let error = api_keys::ApiKeys::new(tiers(), [entry("acme", "k1", "gold")]).unwrap_err();
+    assert!(matches!(error, api_keys::ApiKeysError::UnknownTier { .. }));
+    assert_eq!(
+        error.to_string(),
+        "The key \"acme\" has the tier \"gold\", which is not defined"
+    );
+    assert!(matches!(
+        api_keys::ApiKeys::new(tiers(), [entry("acme", "", "free")]),
+        Err(api_keys::ApiKeysError::EmptyKey(_))
+    ));
+    assert!(matches!(
+        api_keys::ApiKeys::new(
+            tiers(),
+            [entry("acme", "k1", "free"), entry("acme", "k2", "free")]
+        ),
+        Err(api_keys::ApiKeysError::DuplicateName(_))
+    ));
+    assert!(matches!(
+        api_keys::ApiKeys::new(
+            tiers(),
+            [entry("acme", "k1", "free"), entry("beta", "k1", "free")]
+        ),
+        Err(api_keys::ApiKeysError::DuplicateKey(_))
+    ));
+}
+
+#[test]
+fn test_api_key_window_starts_over_after_a_minute() {
+    let clock = Arc::new(MockClock::new());
+    let keys = key_set(&[("free", 2, None)], &[("acme", "k1", "free")]);
+    let limiter = api_keys::ApiKeyLimiter::new(keys, &AppState::new(Dataset::builtin()))
+        .with_clock(clock.clone(), chrono::Utc::now());
+    let allowance = |remaining, reset| api_keys::Allowance {
+        limit: 2,
+        remaining,
+        reset: Duration::from_secs(reset),
+    };
+
+    assert_eq!(
+        limiter.admit(Some("k1"), None),
+        api_keys::Admission::Allowed(allowance(1, 60))
+    );
+    clock.advance(Duration::from_secs(20));
+    assert_eq!(
+        limiter.admit(Some("k1"), None),
+        api_keys::Admission::Allowed(allowance(0, 40))
+    );
+    assert_eq!(
+        limiter.admit(Some("k1"), None),
+        api_keys::Admission::RateLimited(allowance(0, 40))
+    );
+    clock.advance(Duration::from_secs(40));
+    assert_eq!(
+        limiter.admit(Some("k1"), None),
+        api_keys::Admission::Allowed(allowance(1, 60))
+    );
+    assert_eq!(
+        limiter.admit(Some("k2"), None),
+        api_keys::Admission::UnknownKey
+    );
+    // Without an anonymous tier, requests without a key are left to other limits
+    assert_eq!(limiter.admit(None, None), api_keys::Admission::Unlimited);
+}
+
+#[test]
+fn test_api_key_quota_starts_over_at_midnight_utc() {
+    let clock = Arc::new(MockClock::new());
+    let keys = key_set(
+        &[("free", 100, Some(2)), ("anonymous", 100, Some(1))],
+        &[("acme", "k1", "free")],
+    );
+    let late = "2025-01-02T23:58:30Z"
+        .parse::<chrono::DateTime<chrono::Utc>>()
+        .unwrap();
+    let limiter = api_keys::ApiKeyLimiter::new(keys, &AppState::new(Dataset::builtin()))
+        .with_clock(clock.clone(), late);
+    let address = "203.0.113.7".parse().ok();
+
+    assert!(matches!(
+        limiter.admit(Some("k1"), None),
+        api_keys::Admission::Allowed(_)
+    ));
+    assert!(matches!(
+        limiter.admit(Some("k1"), None),
+        api_keys::Admission::Allowed(_)
+    ));
+    assert!(matches!(
+        limiter.admit(None, address),
+        api_keys::Admission::Allowed(_)
+    ));
+    assert_eq!(
+        limiter.admit(Some("k1"), None),
+        api_keys::Admission::QuotaExhausted {
+            reset: Duration::from_secs(90)
+        }
+    );
+    assert_eq!(
+        limiter.admit(None, address),
+        api_keys::Admission::QuotaExhausted {
+            reset: Duration::from_secs(90)
+        }
+    );
+    // Another address has its own quota
+    assert!(matches!(
+        limiter.admit(None, "203.0.113.8".parse().ok()),
+        api_keys::Admission::Allowed(_)
+    ));
+
+    clock.advance(Duration::from_secs(89));
+    assert!(matches!(
+        limiter.admit(Some("k1"), None),
+        api_keys::Admission::QuotaExhausted { .. }
+    ));
+    clock.advance(Duration::from_secs(1));
+    assert!(matches!(
+        limiter.admit(Some("k1"), None),
+        api_keys::Admission::Allowed(_)
+    ));
+    assert!(matches!(
+        limiter.admit(None, address),
+        api_keys::Admission::Allowed(_)
+    ));
 }
@@ -0,0 +1,86 @@
+//! IANA time zones, for `/byTimezone`.
+//!
+//! Records list the tz database zones used on their territory, such as `Europe/Paris`,
+//! under their canonical spelling. Offsets are not stored, as they change with daylight
+//! saving time: `/byTimezone?offset=` works out every zone's offset at the time of the
+//! request.
+
+use chrono::{DateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::fmt;
+
+// UTC offsets in use range from -12:00 (Baker Island) to +14:00 (Kiribati's Line
+// Islands), in seconds
+const MIN_OFFSET: i32 = -12 * 3600;
+const MAX_OFFSET: i32 = 14 * 3600;
+
+// The spelling the tz database uses for `zone`, which is matched in any case; `None` for
+// a name it does not have
+pub fn canonical(zone: &str) -> Option<&'static str> {
+    Tz::from_str_insensitive(zone.trim())
+        .ok()
+        .map(|tz| tz.name())
+}
+
+// Seconds east of UTC that `zone` is at `at`; `None` for an unknown zone
+pub fn offset_at(zone: &str, at: DateTime<Utc>) -> Option<i32> {
+    let tz: Tz = zone.parse().ok()?;
+    Some(
+        tz.offset_from_utc_datetime(&at.naive_utc())
+            .fix()
+            .local_minus_utc(),
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OffsetError {
+    Malformed(String),
+    OutOfRange(String),
+}
+
+impl fmt::Display for OffsetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OffsetError::Malformed(offset) => write!(
+                f,
+                "{:?} is not a UTC offset such as `+09:00` or `-05:30`",
+                offset
+            ),
+            OffsetError::OutOfRange(offset) => {
+                write!(f, "{:?} is outside -12:00 to +14:00", offset)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OffsetError {}
+
+// `+09:00`, `+0900` or `+09` -> seconds east of UTC. The sign is optional for offsets
+// east of UTC, because a raw `+` in a query string reads as a space.
+pub fn parse_offset(offset: &str) -> Result<i32, OffsetError> {
+    let malformed = || OffsetError::Malformed(offset.to_string());
+    let trimmed = offset.trim();
+    let (sign, digits) = match trimmed.strip_prefix('-') {
+        Some(digits) => (-1, digits),
+        None => (1, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    let (hours, minutes) = match digits.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if digits.len() == 4 => digits.split_at(2),
+        None => (digits, "00"),
+    };
+    let number = |part: &str| {
+        (part.len() == 2 && part.bytes().all(|b| b.is_ascii_digit()))
+            .then(|| part.parse::<i32>().ok())
+            .flatten()
+    };
+    let (hours, minutes) = number(hours).zip(number(minutes)).ok_or_else(malformed)?;
+    if minutes >= 60 {
+        return Err(malformed());
+    }
+    let seconds = sign * (hours * 3600 + minutes * 60);
+    if !(MIN_OFFSET..=MAX_OFFSET).contains(&seconds) {
+        return Err(OffsetError::OutOfRange(offset.to_string()));
+    }
+    Ok(seconds)
+}
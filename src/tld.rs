@@ -0,0 +1,21 @@
+//! Country-code top-level domains, for `/byTld`.
+//!
+//! Most ccTLDs are the country's alpha-2 code in lowercase, but not all: the United
+//! Kingdom uses `.uk` rather than `.gb`, and a few territories have no delegated domain
+//! at all. Records therefore carry their TLD, and lookups go through an index of it
+//! rather than through the alpha-2 code.
+
+// Lowercase with a leading dot, so ".JP", "jp" and " .jp " all become ".jp"; `None` when
+// nothing is left after the dot
+pub fn normalize(tld: &str) -> Option<String> {
+    let tld = tld.trim();
+    let label = tld.strip_prefix('.').unwrap_or(tld);
+    (!label.is_empty()).then(|| format!(".{}", label.to_lowercase()))
+}
+
+// A dot followed by one or more letters, such as `.jp`; internationalized TLDs such as
+// `.рф` count, their punycode forms do not
+pub fn is_tld(tld: &str) -> bool {
+    tld.strip_prefix('.')
+        .is_some_and(|label| !label.is_empty() && label.chars().all(char::is_alphabetic))
+}
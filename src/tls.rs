@@ -0,0 +1,348 @@
+//! HTTPS for `--tls-cert` and `--tls-key`, with a certificate that can be replaced while
+//! the server runs.
+//!
+//! Every handshake asks [`TlsCertificates`] for the certificate to present, so swapping it
+//! changes what new connections get while those already open keep theirs.
+//! [`TlsCertificates::reload`] reads both files again and swaps only if they hold a
+//! certificate chain and the private key that matches it; otherwise the old pair stays
+//! and the error is returned for logging. [`spawn_watch`] reloads whenever either file
+//! changes, so a renewed certificate is picked up without a restart.
+
+use chrono::NaiveDate;
+use notify::{RecursiveMode, Watcher};
+use rustls::{
+    crypto::ring,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig,
+};
+use std::{
+    collections::HashSet,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::mpsc;
+
+// Editors and certificate managers write several events per replacement
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(250);
+
+#[derive(Debug)]
+pub enum TlsError {
+    Read { path: PathBuf, error: io::Error },
+    // Not PEM, or PEM without the expected items
+    Pem { path: PathBuf, reason: String },
+    // A key of a type rustls cannot sign with
+    UnsupportedKey { path: PathBuf, reason: String },
+    // The key is not the one the certificate was issued for
+    KeyMismatch { cert: PathBuf, key: PathBuf },
+    // No validity period could be read from the certificate
+    InvalidCertificate { path: PathBuf },
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Read { path, error } => {
+                write!(f, "Failed to read {}: {}", path.display(), error)
+            }
+            TlsError::Pem { path, reason } => {
+                write!(f, "{} is not usable PEM: {}", path.display(), reason)
+            }
+            TlsError::UnsupportedKey { path, reason } => {
+                write!(
+                    f,
+                    "Unsupported private key in {}: {}",
+                    path.display(),
+                    reason
+                )
+            }
+            TlsError::KeyMismatch { cert, key } => write!(
+                f,
+                "The private key in {} does not match the certificate in {}",
+                key.display(),
+                cert.display()
+            ),
+            TlsError::InvalidCertificate { path } => write!(
+                f,
+                "The certificate in {} has no readable validity period",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+// A certificate chain and key ready to present, with when the certificate expires
+#[derive(Debug)]
+struct Loaded {
+    key: Arc<CertifiedKey>,
+    not_after: SystemTime,
+}
+
+#[derive(Debug)]
+pub struct TlsCertificates {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<Loaded>>,
+    // Why the last reload failed; None once one succeeds
+    last_error: Mutex<Option<String>>,
+}
+
+impl TlsCertificates {
+    // The PEM certificate chain at `cert_path`, leaf first, and its key at `key_path`
+    pub fn load(cert_path: &Path, key_path: &Path) -> Result<Self, TlsError> {
+        let loaded = load_pair(cert_path, key_path)?;
+        Ok(TlsCertificates {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            current: RwLock::new(Arc::new(loaded)),
+            last_error: Mutex::new(None),
+        })
+    }
+
+    // Reads both files again and presents the new pair to new connections; keeps the
+    // current pair if they are not a valid one
+    pub fn reload(&self) -> Result<(), TlsError> {
+        let outcome = load_pair(&self.cert_path, &self.key_path);
+        let mut last_error = self
+            .last_error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match outcome {
+            Ok(loaded) => {
+                *self.current.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(loaded);
+                *last_error = None;
+                Ok(())
+            }
+            Err(error) => {
+                *last_error = Some(error.to_string());
+                Err(error)
+            }
+        }
+    }
+
+    // When the certificate presented now expires
+    pub fn not_after(&self) -> SystemTime {
+        self.loaded().not_after
+    }
+
+    pub fn last_error(&self) -> Option<String> {
+        self.last_error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    // The server config every listener shares, presenting whatever pair is current
+    pub fn server_config(self: &Arc<Self>) -> Arc<ServerConfig> {
+        let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("the ring provider supports the default protocol versions")
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone());
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Arc::new(config)
+    }
+
+    fn loaded(&self) -> Arc<Loaded> {
+        self.current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl ResolvesServerCert for TlsCertificates {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.loaded().key.clone())
+    }
+}
+
+fn load_pair(cert_path: &Path, key_path: &Path) -> Result<Loaded, TlsError> {
+    let read = |path: &Path| {
+        fs::read(path).map_err(|error| TlsError::Read {
+            path: path.to_path_buf(),
+            error,
+        })
+    };
+    let pem_error = |path: &Path, error: rustls::pki_types::pem::Error| TlsError::Pem {
+        path: path.to_path_buf(),
+        reason: error.to_string(),
+    };
+
+    let certs = read(cert_path)?;
+    let chain = CertificateDer::pem_slice_iter(&certs)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|error| pem_error(cert_path, error))?;
+    let Some(leaf) = chain.first() else {
+        return Err(TlsError::Pem {
+            path: cert_path.to_path_buf(),
+            reason: String::from("no certificate found"),
+        });
+    };
+    let not_after = not_after(leaf).ok_or_else(|| TlsError::InvalidCertificate {
+        path: cert_path.to_path_buf(),
+    })?;
+
+    let key = PrivateKeyDer::from_pem_slice(&read(key_path)?)
+        .map_err(|error| pem_error(key_path, error))?;
+    let signing_key =
+        ring::sign::any_supported_type(&key).map_err(|error| TlsError::UnsupportedKey {
+            path: key_path.to_path_buf(),
+            reason: error.to_string(),
+        })?;
+    let key = CertifiedKey::new(chain, signing_key);
+    key.keys_match().map_err(|_| TlsError::KeyMismatch {
+        cert: cert_path.to_path_buf(),
+        key: key_path.to_path_buf(),
+    })?;
+
+    Ok(Loaded {
+        key: Arc::new(key),
+        not_after,
+    })
+}
+
+// The notAfter time of a DER X.509 certificate: the second time in the validity
+// sequence, after the version, serial number, signature algorithm and issuer of the
+// to-be-signed certificate
+fn not_after(certificate: &[u8]) -> Option<SystemTime> {
+    const SEQUENCE: u8 = 0x30;
+    const VERSION: u8 = 0xa0;
+    const UTC_TIME: u8 = 0x17;
+    const GENERALIZED_TIME: u8 = 0x18;
+
+    let (_, certificate, _) = der_item(certificate, SEQUENCE)?;
+    let (_, tbs, _) = der_item(certificate, SEQUENCE)?;
+    let mut rest = tbs;
+    if rest.first() == Some(&VERSION) {
+        rest = der_item(rest, VERSION)?.2;
+    }
+    for _ in 0..3 {
+        // Serial number, signature algorithm, issuer
+        let (_, _, after) = der_item(rest, *rest.first()?)?;
+        rest = after;
+    }
+    let (_, validity, _) = der_item(rest, SEQUENCE)?;
+    let (_, _, validity) = der_item(validity, *validity.first()?)?;
+    let (tag, time, _) = der_item(validity, *validity.first()?)?;
+    let time = std::str::from_utf8(time).ok()?.strip_suffix('Z')?;
+    let (year, time) = match tag {
+        UTC_TIME => {
+            let year: i32 = time.get(..2)?.parse().ok()?;
+            (
+                if year < 50 { 2000 + year } else { 1900 + year },
+                &time[2..],
+            )
+        }
+        GENERALIZED_TIME => (time.get(..4)?.parse().ok()?, &time[4..]),
+        _ => return None,
+    };
+    let field = |range: std::ops::Range<usize>| time.get(range)?.parse::<u32>().ok();
+    let seconds = NaiveDate::from_ymd_opt(year, field(0..2)?, field(2..4)?)?
+        .and_hms_opt(field(4..6)?, field(6..8)?, field(8..10)?)?
+        .and_utc()
+        .timestamp();
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(seconds).ok()?))
+}
+
+// The tag, contents and what follows of the DER item `input` starts with, if its tag is
+// `tag`; lengths past four bytes are not needed for certificates
+fn der_item(input: &[u8], tag: u8) -> Option<(u8, &[u8], &[u8])> {
+    let (&found, input) = input.split_first()?;
+    if found != tag {
+        return None;
+    }
+    let (&first, mut input) = input.split_first()?;
+    let length = if first < 0x80 {
+        usize::from(first)
+    } else {
+        let bytes = usize::from(first & 0x7f);
+        if bytes == 0 || bytes > 4 || input.len() < bytes {
+            return None;
+        }
+        let (length, rest) = input.split_at(bytes);
+        input = rest;
+        length
+            .iter()
+            .fold(0, |length, &byte| (length << 8) | usize::from(byte))
+    };
+    if input.len() < length {
+        return None;
+    }
+    let (contents, rest) = input.split_at(length);
+    Some((tag, contents, rest))
+}
+
+// Reloads `certificates` whenever the certificate or key file changes. Watches their
+// directories rather than the files, as certificate managers replace them by renaming.
+pub fn spawn_watch(certificates: Arc<TlsCertificates>) -> notify::Result<()> {
+    let paths = [
+        certificates.cert_path.clone(),
+        certificates.key_path.clone(),
+    ];
+    let directories: HashSet<PathBuf> = paths
+        .iter()
+        .map(|path| match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        })
+        .collect();
+    let file_names: HashSet<_> = paths
+        .iter()
+        .filter_map(|path| path.file_name().map(|name| name.to_os_string()))
+        .collect();
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if event.kind.is_access() => {}
+            Ok(event) => {
+                if event.paths.iter().any(|changed| {
+                    changed
+                        .file_name()
+                        .is_some_and(|name| file_names.contains(name))
+                }) {
+                    let _ = sender.send(());
+                }
+            }
+            Err(error) => tracing::warn!("Error watching TLS certificate files: {}", error),
+        })?;
+    for directory in &directories {
+        watcher.watch(directory, RecursiveMode::NonRecursive)?;
+    }
+    tracing::info!(
+        "Watching {} and {} for changes",
+        paths[0].display(),
+        paths[1].display()
+    );
+
+    tokio::spawn(async move {
+        // Keep the watcher alive for as long as the task runs
+        let _watcher = watcher;
+        while receiver.recv().await.is_some() {
+            loop {
+                match tokio::time::timeout(WATCH_DEBOUNCE, receiver.recv()).await {
+                    Ok(Some(())) => continue,
+                    Ok(None) => return,
+                    Err(_) => break,
+                }
+            }
+            match certificates.reload() {
+                Ok(()) => tracing::info!(
+                    "Reloaded the TLS certificate, valid until {}",
+                    httpdate::fmt_http_date(certificates.not_after())
+                ),
+                Err(error) => {
+                    tracing::error!("Keeping the current TLS certificate: {}", error)
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
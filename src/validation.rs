@@ -0,0 +1,213 @@
+//! Integrity checks over a whole set of country entries, used by `--check`.
+//!
+//! Unlike the loaders, which stop at the first bad entry, these checks report every
+//! problem they find so a dataset can be fixed in one pass.
+
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    bounding_box::BoundingBox,
+    dataset::{is_flag_emoji, validate_latlng, CountryRecord},
+    language, timezone, tld,
+};
+
+// Active ISO 4217 codes for circulating currencies, sorted
+const CURRENCY_CODES: &[&str] = &[
+    "AED", "AFN", "ALL", "AMD", "ANG", "AOA", "ARS", "AUD", "AWG", "AZN", "BAM", "BBD", "BDT",
+    "BGN", "BHD", "BIF", "BMD", "BND", "BOB", "BOV", "BRL", "BSD", "BTN", "BWP", "BYN", "BZD",
+    "CAD", "CDF", "CHE", "CHF", "CHW", "CLF", "CLP", "CNY", "COP", "COU", "CRC", "CUC", "CUP",
+    "CVE", "CZK", "DJF", "DKK", "DOP", "DZD", "EGP", "ERN", "ETB", "EUR", "FJD", "FKP", "GBP",
+    "GEL", "GHS", "GIP", "GMD", "GNF", "GTQ", "GYD", "HKD", "HNL", "HTG", "HUF", "IDR", "ILS",
+    "INR", "IQD", "IRR", "ISK", "JMD", "JOD", "JPY", "KES", "KGS", "KHR", "KMF", "KPW", "KRW",
+    "KWD", "KYD", "KZT", "LAK", "LBP", "LKR", "LRD", "LSL", "LYD", "MAD", "MDL", "MGA", "MKD",
+    "MMK", "MNT", "MOP", "MRU", "MUR", "MVR", "MWK", "MXN", "MXV", "MYR", "MZN", "NAD", "NGN",
+    "NIO", "NOK", "NPR", "NZD", "OMR", "PAB", "PEN", "PGK", "PHP", "PKR", "PLN", "PYG", "QAR",
+    "RON", "RSD", "RUB", "RWF", "SAR", "SBD", "SCR", "SDG", "SEK", "SGD", "SHP", "SLE", "SLL",
+    "SOS", "SRD", "SSP", "STN", "SVC", "SYP", "SZL", "THB", "TJS", "TMT", "TND", "TOP", "TRY",
+    "TTD", "TWD", "TZS", "UAH", "UGX", "USD", "USN", "UYI", "UYU", "UYW", "UZS", "VED", "VES",
+    "VND", "VUV", "WST", "XAF", "XCD", "XCG", "XOF", "XPF", "YER", "ZAR", "ZMW", "ZWG", "ZWL",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    // 1-based position of the entry in the source
+    pub entry: usize,
+    // The entry's name as written, which may itself be the problem
+    pub country: String,
+    pub kind: ValidationErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationErrorKind {
+    EmptyField {
+        field: &'static str,
+    },
+    InvalidFlag {
+        flag: String,
+    },
+    UnknownCurrencyCode {
+        code: String,
+    },
+    InvalidBoundingBox {
+        reason: &'static str,
+    },
+    InvalidLatLng {
+        reason: &'static str,
+    },
+    InvalidTld {
+        tld: String,
+    },
+    InvalidLanguage {
+        language: String,
+    },
+    InvalidTimezone {
+        zone: String,
+    },
+    // `name` is already used by the entry named `other` at position `other_entry`
+    DuplicateName {
+        name: String,
+        other: String,
+        other_entry: usize,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entry {} ({:?}): ", self.entry, self.country)?;
+        match &self.kind {
+            ValidationErrorKind::EmptyField { field } => write!(f, "`{}` must not be empty", field),
+            ValidationErrorKind::InvalidFlag { flag } => write!(
+                f,
+                "flag {:?} is not exactly two regional indicator symbols",
+                flag
+            ),
+            ValidationErrorKind::UnknownCurrencyCode { code } => {
+                write!(f, "currency code {:?} is not an ISO 4217 code", code)
+            }
+            ValidationErrorKind::InvalidBoundingBox { reason } => {
+                write!(f, "bounding box is invalid: {}", reason)
+            }
+            ValidationErrorKind::InvalidLatLng { reason } => {
+                write!(f, "latlng is invalid: {}", reason)
+            }
+            ValidationErrorKind::InvalidTld { tld } => {
+                write!(f, "TLD {:?} is not a dot followed by letters", tld)
+            }
+            ValidationErrorKind::InvalidLanguage { language } => {
+                write!(f, "language {:?} is not an ISO 639 code", language)
+            }
+            ValidationErrorKind::InvalidTimezone { zone } => {
+                write!(f, "time zone {:?} is not an IANA time zone", zone)
+            }
+            ValidationErrorKind::DuplicateName {
+                name,
+                other,
+                other_entry,
+            } => write!(
+                f,
+                "name {:?} is already used by entry {} ({:?})",
+                name, other_entry, other
+            ),
+        }
+    }
+}
+
+pub fn is_known_currency_code(code: &str) -> bool {
+    CURRENCY_CODES.binary_search(&code).is_ok()
+}
+
+// Names are compared the way lookups match them, after trimming and lowercasing
+pub fn validate_records(records: &[CountryRecord]) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    // Lookup name -> (entry, country) that claimed it first
+    let mut claimed: HashMap<String, (usize, &str)> = HashMap::new();
+
+    for (index, record) in records.iter().enumerate() {
+        let entry = index + 1;
+        let mut report = |kind| {
+            errors.push(ValidationError {
+                entry,
+                country: record.name.clone(),
+                kind,
+            })
+        };
+
+        for (field, value) in [
+            ("name", &record.name),
+            ("flag", &record.flag),
+            ("phoneCode", &record.phone_code),
+        ] {
+            if value.trim().is_empty() {
+                report(ValidationErrorKind::EmptyField { field });
+            }
+        }
+        if record
+            .aliases
+            .iter()
+            .any(|alias| alias.name.trim().is_empty())
+        {
+            report(ValidationErrorKind::EmptyField { field: "aliases" });
+        }
+        if record
+            .localized_names
+            .iter()
+            .any(|(language, name)| language.trim().is_empty() || name.trim().is_empty())
+        {
+            report(ValidationErrorKind::EmptyField {
+                field: "localizedNames",
+            });
+        }
+        let flag = record.flag.trim();
+        if !flag.is_empty() && !is_flag_emoji(flag) {
+            report(ValidationErrorKind::InvalidFlag {
+                flag: flag.to_string(),
+            });
+        }
+        let code = record.currency_code.as_str();
+        if !is_known_currency_code(code) {
+            report(ValidationErrorKind::UnknownCurrencyCode {
+                code: code.to_string(),
+            });
+        }
+        if let Some(Err(reason)) = record.bounding_box.as_ref().map(BoundingBox::validate) {
+            report(ValidationErrorKind::InvalidBoundingBox { reason });
+        }
+        if let Some(Err(reason)) = record.latlng.map(validate_latlng) {
+            report(ValidationErrorKind::InvalidLatLng { reason });
+        }
+        if let Some(raw) = &record.tld {
+            if tld::normalize(raw).is_some_and(|tld| !tld::is_tld(&tld)) {
+                report(ValidationErrorKind::InvalidTld { tld: raw.clone() });
+            }
+        }
+        for raw in &record.languages {
+            let code = raw.trim().to_lowercase();
+            if !code.is_empty() && !language::is_code(&code) {
+                report(ValidationErrorKind::InvalidLanguage {
+                    language: raw.clone(),
+                });
+            }
+        }
+        for raw in &record.timezones {
+            if !raw.trim().is_empty() && timezone::canonical(raw).is_none() {
+                report(ValidationErrorKind::InvalidTimezone { zone: raw.clone() });
+            }
+        }
+
+        let normalized = record.clone().normalized();
+        for name in normalized.lookup_names().filter(|name| !name.is_empty()) {
+            match claimed.get(name) {
+                Some(&(other_entry, other)) => report(ValidationErrorKind::DuplicateName {
+                    name: name.to_string(),
+                    other: other.to_string(),
+                    other_entry,
+                }),
+                None => {
+                    claimed.insert(name.to_string(), (entry, &record.name));
+                }
+            }
+        }
+    }
+
+    errors
+}
@@ -0,0 +1,33 @@
+//! JavaScript bindings to the lookup core, for resolving countries in the browser.
+//!
+//! Build with `wasm-pack build --no-default-features`. Both functions return the same
+//! JSON shapes `/getCountry` does, as plain JavaScript objects.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    dataset::{Dataset, DEFAULT_LANGUAGE},
+    lookup::{self, CountryInfo},
+};
+
+/// One `/getCountry` result for `name`, or `null` when it matches no country.
+#[wasm_bindgen]
+pub fn lookup(name: &str) -> JsValue {
+    match crate::lookup_country(name) {
+        Some(record) => to_js(&CountryInfo::new(name.trim(), record, DEFAULT_LANGUAGE)),
+        None => JsValue::NULL,
+    }
+}
+
+/// The `/getCountry` body for comma-separated names and flags; unknown names are left out.
+#[wasm_bindgen(js_name = lookupMany)]
+pub fn lookup_many(names: &str) -> JsValue {
+    let dataset = Dataset::builtin();
+    to_js(&lookup::lookup_countries(&dataset, names, DEFAULT_LANGUAGE))
+}
+
+// Goes through JSON so the object matches the HTTP response field for field
+fn to_js(value: &impl serde::Serialize) -> JsValue {
+    let json = serde_json::to_string(value).expect("lookup results serialize to JSON");
+    js_sys::JSON::parse(&json).expect("serde_json produces valid JSON")
+}
@@ -0,0 +1,131 @@
+//! Signed POSTs of every change to the served data to the `--webhook-url` targets.
+//!
+//! Changes are taken from the channel `/events` reads, so the body is the same
+//! `DatasetEvent` JSON. Each delivery runs in its own task: admin requests never wait for
+//! one, and a slow or failing target holds up no other. A delivery that fails is retried
+//! with exponential backoff up to `max_attempts` times and then dropped, so targets may
+//! see changes out of order and should go by `datasetVersion`.
+//!
+//! The `x-rustincountry-signature` header is `sha256=` and the hex HMAC-SHA256 of the
+//! body, keyed with `--webhook-secret`.
+
+use axum::body::Bytes;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+
+use crate::AppState;
+
+pub const SIGNATURE_HEADER: &str = "x-rustincountry-signature";
+pub const EVENT_HEADER: &str = "x-rustincountry-event";
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub urls: Vec<String>,
+    // Key of the HMAC-SHA256 signature
+    pub secret: String,
+    // Attempts per target and change, the first one included
+    pub max_attempts: u32,
+    // Wait before the first retry; it doubles for each retry after that
+    pub initial_backoff: Duration,
+}
+
+impl WebhookConfig {
+    pub fn new(urls: Vec<String>, secret: String) -> Self {
+        WebhookConfig {
+            urls,
+            secret,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+        }
+    }
+}
+
+// The signature header's value for `body`
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+// Delivers every later change to `state`'s data to the configured targets, until the
+// state is dropped
+pub fn spawn(state: &AppState, config: WebhookConfig) -> JoinHandle<()> {
+    let mut changes = state.events.subscribe();
+    let client = reqwest::Client::builder()
+        .timeout(DELIVERY_TIMEOUT)
+        .build()
+        .expect("the HTTP client has a valid configuration");
+    let config = Arc::new(config);
+
+    tokio::spawn(async move {
+        loop {
+            let event = match changes.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("Webhooks fell behind and skipped {} changes", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            };
+            let body = Bytes::from(serde_json::to_vec(&event).expect("events serialize to JSON"));
+            let signature = sign(config.secret.as_bytes(), &body);
+            for url in &config.urls {
+                tokio::spawn(deliver(
+                    client.clone(),
+                    Arc::clone(&config),
+                    url.clone(),
+                    body.clone(),
+                    signature.clone(),
+                ));
+            }
+        }
+    })
+}
+
+// Posts the change to `url` until it is taken or the attempts run out
+async fn deliver(
+    client: reqwest::Client,
+    config: Arc<WebhookConfig>,
+    url: String,
+    body: Bytes,
+    signature: String,
+) {
+    let mut backoff = config.initial_backoff;
+    for attempt in 1..=config.max_attempts {
+        let outcome = client
+            .post(&url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, "dataset")
+            .header(SIGNATURE_HEADER, &signature)
+            .body(body.clone())
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status);
+        match outcome {
+            Ok(_) => return,
+            Err(error) if attempt < config.max_attempts => {
+                tracing::warn!(
+                    "Webhook delivery to {} failed (attempt {} of {}), retrying in {:?}: {}",
+                    url,
+                    attempt,
+                    config.max_attempts,
+                    backoff,
+                    error
+                );
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            Err(error) => tracing::error!(
+                "Giving up on webhook delivery to {} after {} attempts: {}",
+                url,
+                attempt,
+                error
+            ),
+        }
+    }
+}
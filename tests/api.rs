@@ -0,0 +1,5942 @@
+//! The HTTP API, exercised through the router the library exports.
+
+use axum::{
+    body::{Body, Bytes},
+    extract::connect_info::MockConnectInfo,
+    http::{HeaderMap, HeaderValue, Request, Response, StatusCode},
+    response::IntoResponse,
+    routing::post,
+    Router,
+};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use http_body_util::BodyExt;
+use rusty_currency::{
+    admin_auth::{self, BasicAuth},
+    api_keys::ApiKeys,
+    audit::{AuditAction, AuditLog, AuditRecord},
+    breaker, builtin,
+    client_ip::{self, TrustedProxies},
+    codes::{Alpha2, CurrencyCode},
+    create_app, create_app_with, create_app_with_state, events, export, geoip, input,
+    log_level::LogLevel,
+    lookup, rate_snapshot, rates, reload, remote, revision, routes, security_headers, snapshot,
+    source::{
+        BuiltinLoader, CountryDataSource, DatasetLoader, LoadError, MutableSource, StaticSource,
+    },
+    webhooks, Alias, AppConfig, AppState, CodesResponse, CountryRecord, CountryResponse, Dataset,
+    DatasetEvent, ErrorEnvelope, HealthResponse, StatsResponse, ValidateResponse,
+    MAX_COUNTRIES_PER_QUERY,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tower::ServiceExt;
+
+const ADMIN_TOKEN: &str = "test-admin-token";
+const ADMIN_AUTHORIZATION: &str = "Bearer test-admin-token";
+
+fn admin_config() -> AppConfig {
+    AppConfig {
+        admin_token: Some(ADMIN_TOKEN.to_string()),
+        ..AppConfig::default()
+    }
+}
+
+fn admin_app() -> Router {
+    admin_app_with_dataset(builtin::dataset())
+}
+
+fn admin_app_with_dataset(dataset: Dataset) -> Router {
+    create_app_with(admin_config(), Arc::new(MutableSource::new(dataset)))
+}
+
+#[tokio::test]
+async fn test_get_country_single() {
+    let app = create_app();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/getCountry?based=japan")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+
+    assert_eq!(country_response.results.len(), 1);
+    assert_eq!(country_response.results[0].country, "japan");
+    assert_eq!(country_response.results[0].flag, "🇯🇵");
+    assert_eq!(country_response.results[0].currency_code, "JPY");
+    assert_eq!(country_response.results[0].phone_code, "+81");
+}
+
+#[tokio::test]
+async fn test_get_country_multiple() {
+    let app = create_app();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/getCountry?based=japan,korea")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+
+    assert_eq!(country_response.results.len(), 2);
+    assert_eq!(country_response.results[0].country, "japan");
+    assert_eq!(country_response.results[0].flag, "🇯🇵");
+    assert_eq!(country_response.results[0].currency_code, "JPY");
+    assert_eq!(country_response.results[0].phone_code, "+81");
+    assert_eq!(country_response.results[1].country, "korea");
+    assert_eq!(country_response.results[1].flag, "🇰🇷");
+    assert_eq!(country_response.results[1].currency_code, "KRW");
+    assert_eq!(country_response.results[1].phone_code, "+82");
+}
+
+#[tokio::test]
+async fn test_get_country_case_insensitive() {
+    let app = create_app();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/getCountry?based=JAPAN")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+
+    assert_eq!(country_response.results.len(), 1);
+    assert_eq!(country_response.results[0].country, "JAPAN");
+    assert_eq!(country_response.results[0].flag, "🇯🇵");
+    assert_eq!(country_response.results[0].currency_code, "JPY");
+    assert_eq!(country_response.results[0].phone_code, "+81");
+}
+
+#[tokio::test]
+async fn test_get_country_unknown() {
+    let app = create_app();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/getCountry?based=unknown")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+
+    assert_eq!(country_response.results.len(), 0);
+}
+
+#[tokio::test]
+async fn test_get_country_with_spaces() {
+    let app = create_app();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/getCountry?based=japan,%20korea,%20usa")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+
+    assert_eq!(country_response.results.len(), 3);
+    assert_eq!(country_response.results[0].country, "japan");
+    assert_eq!(country_response.results[1].country, "korea");
+    assert_eq!(country_response.results[2].country, "usa");
+}
+
+#[tokio::test]
+async fn test_get_country_mixed_valid_invalid() {
+    let app = create_app();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/getCountry?based=japan,unknown,korea")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+
+    assert_eq!(country_response.results.len(), 2);
+    assert_eq!(country_response.results[0].country, "japan");
+    assert_eq!(country_response.results[1].country, "korea");
+}
+
+#[tokio::test]
+async fn test_get_country_all_supported() {
+    let app = create_app();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/getCountry?based=usa,uk,germany")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+
+    assert_eq!(country_response.results.len(), 3);
+    assert_eq!(country_response.results[0].flag, "🇺🇸");
+    assert_eq!(country_response.results[0].currency_code, "USD");
+    assert_eq!(country_response.results[0].phone_code, "+1");
+    assert_eq!(country_response.results[1].flag, "🇬🇧");
+    assert_eq!(country_response.results[1].currency_code, "GBP");
+    assert_eq!(country_response.results[1].phone_code, "+44");
+    assert_eq!(country_response.results[2].flag, "🇩🇪");
+    assert_eq!(country_response.results[2].currency_code, "EUR");
+    assert_eq!(country_response.results[2].phone_code, "+49");
+}
+
+#[tokio::test]
+async fn test_get_country_comprehensive_coverage() {
+    let app = create_app();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/getCountry?based=afghanistan,argentina,egypt,fiji,iceland,nigeria,thailand")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+
+    assert_eq!(country_response.results.len(), 7);
+
+    assert_eq!(country_response.results[0].country, "afghanistan");
+    assert_eq!(country_response.results[0].flag, "🇦🇫");
+    assert_eq!(country_response.results[0].currency_code, "AFN");
+    assert_eq!(country_response.results[0].phone_code, "+93");
+
+    assert_eq!(country_response.results[1].country, "argentina");
+    assert_eq!(country_response.results[1].flag, "🇦🇷");
+    assert_eq!(country_response.results[1].currency_code, "ARS");
+
+    assert_eq!(country_response.results[2].country, "egypt");
+    assert_eq!(country_response.results[2].flag, "🇪🇬");
+    assert_eq!(country_response.results[2].currency_code, "EGP");
+    assert_eq!(country_response.results[2].phone_code, "+20");
+
+    assert_eq!(country_response.results[3].country, "fiji");
+    assert_eq!(country_response.results[3].flag, "🇫🇯");
+    assert_eq!(country_response.results[3].currency_code, "FJD");
+    assert_eq!(country_response.results[3].phone_code, "+679");
+
+    assert_eq!(country_response.results[4].country, "iceland");
+    assert_eq!(country_response.results[4].flag, "🇮🇸");
+    assert_eq!(country_response.results[4].currency_code, "ISK");
+    assert_eq!(country_response.results[4].phone_code, "+354");
+
+    assert_eq!(country_response.results[5].country, "nigeria");
+    assert_eq!(country_response.results[5].flag, "🇳🇬");
+    assert_eq!(country_response.results[5].currency_code, "NGN");
+    assert_eq!(country_response.results[5].phone_code, "+234");
+
+    assert_eq!(country_response.results[6].country, "thailand");
+    assert_eq!(country_response.results[6].flag, "🇹🇭");
+    assert_eq!(country_response.results[6].currency_code, "THB");
+    assert_eq!(country_response.results[6].phone_code, "+66");
+}
+
+#[tokio::test]
+async fn test_get_country_african_countries() {
+    let app = create_app();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/getCountry?based=kenya,morocco,ghana,ethiopia")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+
+    assert_eq!(country_response.results.len(), 4);
+    assert_eq!(country_response.results[0].flag, "🇰🇪");
+    assert_eq!(country_response.results[0].currency_code, "KES");
+    assert_eq!(country_response.results[0].phone_code, "+254");
+    assert_eq!(country_response.results[1].flag, "🇲🇦");
+    assert_eq!(country_response.results[1].currency_code, "MAD");
+    assert_eq!(country_response.results[1].phone_code, "+212");
+}
+
+#[tokio::test]
+async fn test_get_country_aliases() {
+    let app = create_app();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/getCountry?based=uae,czechia,burma,vatican")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+
+    assert_eq!(country_response.results.len(), 4);
+    assert_eq!(country_response.results[0].flag, "🇦🇪"); // UAE
+    assert_eq!(country_response.results[0].currency_code, "AED");
+    assert_eq!(country_response.results[0].phone_code, "+971");
+    assert_eq!(country_response.results[1].flag, "🇨🇿"); // Czechia
+    assert_eq!(country_response.results[1].currency_code, "CZK");
+    assert_eq!(country_response.results[1].phone_code, "+420");
+    assert_eq!(country_response.results[2].flag, "🇲🇲"); // Burma/Myanmar
+    assert_eq!(country_response.results[2].currency_code, "MMK");
+    assert_eq!(country_response.results[2].phone_code, "+95");
+    assert_eq!(country_response.results[3].flag, "🇻🇦"); // Vatican
+    assert_eq!(country_response.results[3].currency_code, "EUR");
+    assert_eq!(country_response.results[3].phone_code, "+3906698");
+}
+
+#[tokio::test]
+async fn test_get_country_all_parameter() {
+    let app = create_app();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/getCountry?based=all")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body_str = String::from_utf8(body.to_vec()).unwrap();
+    let country_response: CountryResponse = serde_json::from_str(&body_str).unwrap();
+
+    assert_eq!(
+        country_response.results.len(),
+        builtin::dataset().names().count()
+    );
+    assert!(country_response
+        .results
+        .iter()
+        .any(|country| country.country == "japan" && country.phone_code == "+81"));
+}
+
+#[tokio::test]
+async fn test_get_country_normalizes_each_name_once() {
+    let app = create_app();
+
+    let response = get_countries(
+        &app,
+        "%C2%A0C%C3%94TE%20D%E2%80%99IVOIRE%20,United%C2%A0%20States",
+    )
+    .await;
+
+    assert_eq!(response.results.len(), 2);
+    assert_eq!(response.results[0].country, "C\u{d4}TE D\u{2019}IVOIRE");
+    assert_eq!(response.results[0].currency_code, "XOF");
+    assert_eq!(response.results[1].phone_code, "+1");
+}
+
+#[tokio::test]
+async fn test_get_country_response_bytes() {
+    let response = create_app()
+        .oneshot(
+            Request::builder()
+                .uri("/getCountry?based=Japan,atlantis")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.headers()["content-type"], "application/json");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(
+        body,
+        r#"{"results":[{"country":"Japan","canonicalName":"japan","localizedName":"Japan","flag":"🇯🇵","currencyCode":"JPY","phoneCode":"+81","nameStatus":"current"}]}"#
+    );
+}
+
+#[tokio::test]
+async fn test_request_id_generated_when_missing() {
+    let app = create_app();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/healthz")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let request_id = response.headers()["x-request-id"].to_str().unwrap();
+    assert!(uuid::Uuid::parse_str(request_id).is_ok());
+}
+
+#[tokio::test]
+async fn test_unknown_path_returns_json_404() {
+    let app = create_app();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/nope?based=japan")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let envelope: ErrorEnvelope = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(envelope.error.code, "NOT_FOUND");
+    assert_eq!(envelope.error.details.unwrap()["path"], "/nope");
+    assert!(envelope.error.request_id.is_some());
+}
+
+#[tokio::test]
+async fn test_unsupported_method_returns_json_405_with_allow() {
+    let app = create_app();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("DELETE")
+                .uri("/getCountry?based=japan")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    let allow = response.headers()["allow"].to_str().unwrap().to_string();
+    assert!(allow.split(',').any(|method| method.trim() == "GET"));
+
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let envelope: ErrorEnvelope = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(envelope.error.code, "METHOD_NOT_ALLOWED");
+    assert_eq!(envelope.error.details.unwrap()["method"], "DELETE");
+}
+
+#[tokio::test]
+async fn test_fallbacks_leave_existing_routes_unaffected() {
+    for uri in ["/getCountry?based=japan", "/healthz", "/version"] {
+        let response = create_app()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        assert!(response.headers().get("allow").is_none(), "{}", uri);
+    }
+}
+
+async fn get_error(uri: &str) -> (StatusCode, ErrorEnvelope) {
+    let (status, _, body) = get_raw(&create_app(), uri).await;
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn test_get_country_without_names_is_an_empty_query() {
+    for uri in [
+        "/getCountry",
+        "/getCountry?based=",
+        "/getCountry?based=%20,%20,",
+    ] {
+        let (status, envelope) = get_error(uri).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(envelope.error.code, "EMPTY_QUERY", "{}", uri);
+        assert!(envelope.error.details.is_none(), "{}", uri);
+        assert!(envelope.error.request_id.is_some(), "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn test_get_country_rejects_too_many_names() {
+    let based = vec!["japan"; MAX_COUNTRIES_PER_QUERY + 1].join(",");
+
+    let (status, envelope) = get_error(&format!("/getCountry?based={}", based)).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(envelope.error.code, "TOO_MANY_COUNTRIES");
+    let details = envelope.error.details.unwrap();
+    assert_eq!(details["requested"], MAX_COUNTRIES_PER_QUERY + 1);
+    assert_eq!(details["limit"], MAX_COUNTRIES_PER_QUERY);
+}
+
+#[tokio::test]
+async fn test_get_country_accepts_the_most_names_allowed() {
+    let based = vec!["japan"; MAX_COUNTRIES_PER_QUERY].join(",");
+    let app = create_app();
+
+    let response = get_countries(&app, &based).await;
+
+    assert_eq!(response.results.len(), MAX_COUNTRIES_PER_QUERY);
+}
+
+#[tokio::test]
+async fn test_get_country_rejects_a_query_over_the_length_limit() {
+    let based = format!("japan,{}", "x".repeat(input::MAX_QUERY_BYTES));
+
+    let (status, envelope) = get_error(&format!("/getCountry?based={}", based)).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(envelope.error.code, "QUERY_TOO_LONG");
+    let details = envelope.error.details.unwrap();
+    assert_eq!(details["length"], based.len());
+    assert_eq!(details["limit"], input::MAX_QUERY_BYTES);
+}
+
+#[tokio::test]
+async fn test_get_country_rejects_an_entry_over_the_length_limit() {
+    let long = "a".repeat(input::MAX_ENTRY_CHARS + 1);
+
+    let (status, envelope) = get_error(&format!("/getCountry?based=japan,{},france", long)).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(envelope.error.code, "ENTRY_TOO_LONG");
+    let details = envelope.error.details.unwrap();
+    assert_eq!(details["entry"], long);
+    assert_eq!(details["length"], input::MAX_ENTRY_CHARS + 1);
+    assert_eq!(details["limit"], input::MAX_ENTRY_CHARS);
+}
+
+#[tokio::test]
+async fn test_get_country_accepts_an_entry_at_the_length_limit() {
+    // Characters are counted, not bytes, and the spaces around an entry are not
+    let longest = "日".repeat(input::MAX_ENTRY_CHARS);
+    let app = create_app();
+
+    let (status, body) =
+        get_json(&app, &format!("/getCountry?based=japan,%20{}%20", longest)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(result_countries(&body["results"]), ["japan"]);
+
+    let (status, _) = get_json(
+        &app,
+        "/getCountry?based=united%20kingdom%20of%20great%20britain%20and%20northern%20ireland",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_get_country_cleans_pasted_entries() {
+    let app = create_app();
+
+    let (status, body) = get_json(&app, "/getCountry?based=%22Japan%22,(Korea)").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(result_countries(&body["results"]), ["Japan", "Korea"]);
+    assert_eq!(body["results"][1]["currencyCode"], "KRW");
+
+    let (_, body) = get_json(&app, "/getCountry?based=France.,%22japan%22&strict=true").await;
+    assert_eq!(result_countries(&body["results"]), ["France", "japan"]);
+    let (_, body) = get_json(&app, "/validate?based=%5BGermany%5D").await;
+    assert_eq!(body["results"][0]["input"], "Germany");
+    assert_eq!(body["results"][0]["valid"], true);
+}
+
+#[tokio::test]
+async fn test_get_country_rejects_control_characters() {
+    for (encoded, entry, character) in [
+        ("japan%00", "japan\0", "U+0000"),
+        ("new%0Azealand", "new\nzealand", "U+000A"),
+        ("fran%C2%85ce", "fran\u{85}ce", "U+0085"),
+        ("%09japan", "\tjapan", "U+0009"),
+    ] {
+        let uri = format!("/getCountry?based=germany,{}", encoded);
+        let (status, envelope) = get_error(&uri).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(envelope.error.code, "CONTROL_CHARACTER", "{}", uri);
+        let details = envelope.error.details.unwrap();
+        assert_eq!(details["entry"], entry, "{}", uri);
+        assert_eq!(details["character"], character, "{}", uri);
+    }
+
+    let (status, envelope) = get_error("/validate?based=japan%0D").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(envelope.error.code, "CONTROL_CHARACTER");
+}
+
+fn geo_app() -> Router {
+    Router::new()
+        .nest("/geo", routes())
+        .with_state(AppState::new(builtin::dataset()))
+}
+
+#[tokio::test]
+async fn test_routes_nest_under_a_prefix() {
+    let response = geo_app()
+        .oneshot(
+            Request::builder()
+                .uri("/geo/getCountry?based=japan")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let country_response: CountryResponse = serde_json::from_slice(&body).unwrap();
+    assert_eq!(country_response.results[0].currency_code, "JPY");
+
+    let response = geo_app()
+        .oneshot(
+            Request::builder()
+                .uri("/getCountry?based=japan")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_nested_fallbacks_report_the_prefixed_path() {
+    let response = geo_app()
+        .oneshot(
+            Request::builder()
+                .uri("/geo/nope")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let envelope: ErrorEnvelope = serde_json::from_slice(&body).unwrap();
+    assert_eq!(envelope.error.details.unwrap()["path"], "/geo/nope");
+
+    let response = geo_app()
+        .oneshot(
+            Request::builder()
+                .method("DELETE")
+                .uri("/geo/countries")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let envelope: ErrorEnvelope = serde_json::from_slice(&body).unwrap();
+    assert_eq!(envelope.error.details.unwrap()["path"], "/geo/countries");
+}
+
+#[tokio::test]
+async fn test_routes_leave_out_admin_endpoints() {
+    let response = geo_app()
+        .oneshot(
+            Request::builder()
+                .uri("/geo/admin/export")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+async fn send_json(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    body: serde_json::Value,
+) -> (StatusCode, serde_json::Value) {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("authorization", ADMIN_AUTHORIZATION)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+async fn get_countries(app: &Router, based: &str) -> CountryResponse<'static> {
+    let (status, _, body) = get_raw(app, &format!("/getCountry?based={}", based)).await;
+    assert_eq!(status, StatusCode::OK);
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_admin_add_country_then_query() {
+    let app = admin_app();
+    assert!(get_countries(&app, "wakanda").await.results.is_empty());
+
+    let (status, body) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "Wakanda",
+            "aliases": ["Kingdom of Wakanda"],
+            "flag": "🇼🇰",
+            "currencyCode": "WKD",
+            "phoneCode": "+999"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(body["name"], "wakanda");
+    assert_eq!(body["aliases"][0], "kingdom of wakanda");
+
+    let country_response = get_countries(&app, "wakanda,kingdom%20of%20wakanda").await;
+    assert_eq!(country_response.results.len(), 2);
+    assert_eq!(country_response.results[0].flag, "🇼🇰");
+    assert_eq!(country_response.results[0].currency_code, "WKD");
+    assert_eq!(country_response.results[1].phone_code, "+999");
+}
+
+#[tokio::test]
+async fn test_admin_add_country_conflict() {
+    let app = admin_app();
+
+    let (status, body) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "Nippon Koku",
+            "aliases": ["Japan"],
+            "flag": "🇯🇵",
+            "currencyCode": "JPY",
+            "phoneCode": "+81"
+        }),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"]["code"], "COUNTRY_CONFLICT");
+    assert_eq!(body["error"]["details"]["name"], "japan");
+    assert!(get_countries(&app, "nippon%20koku")
+        .await
+        .results
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_admin_add_country_validation() {
+    let app = admin_app();
+
+    for flag in ["JP", "🇯", "🇯🇵🇰🇷", "🏳️"] {
+        let (status, body) = send_json(
+            &app,
+            "POST",
+            "/admin/countries",
+            serde_json::json!({
+                "name": "atlantis",
+                "flag": flag,
+                "currencyCode": "ATL",
+                "phoneCode": "+998"
+            }),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", flag);
+        assert_eq!(body["error"]["code"], "VALIDATION_FAILED");
+        assert_eq!(body["error"]["details"]["field"], "flag");
+    }
+
+    let (status, body) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({ "name": "atlantis", "flag": "🇦🇹" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["code"], "INVALID_BODY");
+
+    assert!(get_countries(&app, "atlantis").await.results.is_empty());
+}
+
+// An admin GET, sending `authorization` if given; `200` once the credentials are accepted
+async fn admin_status_with(
+    app: &Router,
+    authorization: Option<&str>,
+) -> (StatusCode, HeaderMap, Bytes) {
+    let headers: Vec<_> = authorization
+        .map(|authorization| ("authorization", authorization))
+        .into_iter()
+        .collect();
+    get_raw_with(app, "/admin/export", &headers).await
+}
+
+#[tokio::test]
+async fn test_admin_requires_the_token() {
+    for authorization in [None, Some("Bearer wrong-token"), Some("test-admin-token")] {
+        let (status, headers, body) = admin_status_with(&admin_app(), authorization).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{:?}", authorization);
+        assert_eq!(headers["www-authenticate"], "Bearer");
+        assert_eq!(error_code(&body), "UNAUTHORIZED");
+    }
+
+    let (status, _, _) = admin_status_with(&admin_app(), Some(ADMIN_AUTHORIZATION)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_admin_routes_not_served_without_a_token() {
+    let (status, _, _) = admin_status_with(&create_app(), Some(ADMIN_AUTHORIZATION)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+const ADMIN_USER: &str = "ops";
+const ADMIN_PASSWORD: &str = "correct horse battery staple";
+
+fn basic_authorization(username: &str, password: &str) -> String {
+    let credentials = format!("{}:{}", username, password);
+    format!(
+        "Basic {}",
+        base64::engine::general_purpose::STANDARD.encode(credentials)
+    )
+}
+
+fn basic_admin_config(token: Option<&str>) -> AppConfig {
+    let hash = admin_auth::hash_password(ADMIN_PASSWORD);
+    AppConfig {
+        admin_token: token.map(String::from),
+        admin_basic: Some(BasicAuth::new(ADMIN_USER.to_string(), hash).unwrap()),
+        ..AppConfig::default()
+    }
+}
+
+fn challenges(headers: &HeaderMap) -> Vec<&str> {
+    headers
+        .get_all("www-authenticate")
+        .iter()
+        .map(|challenge| challenge.to_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_admin_basic_auth() {
+    let app = create_app_with(
+        basic_admin_config(None),
+        Arc::new(MutableSource::new(builtin::dataset())),
+    );
+    let wrong_password = basic_authorization(ADMIN_USER, "hunter2");
+    let wrong_user = basic_authorization("root", ADMIN_PASSWORD);
+
+    for authorization in [
+        None,
+        Some(wrong_password.as_str()),
+        Some(wrong_user.as_str()),
+        Some("Basic not-base64!"),
+        // Bearer tokens are not accepted without `admin_token`
+        Some(ADMIN_AUTHORIZATION),
+    ] {
+        let (status, headers, body) = admin_status_with(&app, authorization).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{:?}", authorization);
+        assert_eq!(
+            challenges(&headers),
+            ["Basic realm=\"rustincountry admin\", charset=\"UTF-8\""]
+        );
+        assert_eq!(error_code(&body), "UNAUTHORIZED");
+    }
+
+    let authorization = basic_authorization(ADMIN_USER, ADMIN_PASSWORD);
+    let (status, _, _) = admin_status_with(&app, Some(&authorization)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_admin_accepts_the_bearer_token_alongside_basic_auth() {
+    let app = create_app_with(
+        basic_admin_config(Some(ADMIN_TOKEN)),
+        Arc::new(MutableSource::new(builtin::dataset())),
+    );
+
+    let (status, headers, _) = admin_status_with(&app, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        challenges(&headers),
+        [
+            "Basic realm=\"rustincountry admin\", charset=\"UTF-8\"",
+            "Bearer"
+        ]
+    );
+    let (status, _, _) = admin_status_with(&app, Some("Bearer wrong-token")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _, _) = admin_status_with(&app, Some(ADMIN_AUTHORIZATION)).await;
+    assert_eq!(status, StatusCode::OK);
+    let authorization = basic_authorization(ADMIN_USER, ADMIN_PASSWORD);
+    let (status, _, _) = admin_status_with(&app, Some(&authorization)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_admin_auth_schemes_match_in_any_case() {
+    let app = create_app_with(
+        basic_admin_config(Some(ADMIN_TOKEN)),
+        Arc::new(MutableSource::new(builtin::dataset())),
+    );
+    let basic = basic_authorization(ADMIN_USER, ADMIN_PASSWORD);
+    let (_, credentials) = basic.split_once(' ').unwrap();
+
+    for authorization in [
+        format!("bearer {}", ADMIN_TOKEN),
+        format!("BEARER {}", ADMIN_TOKEN),
+        format!("basic {}", credentials),
+        format!("BaSiC {}", credentials),
+    ] {
+        let (status, _, _) = admin_status_with(&app, Some(&authorization)).await;
+        assert_eq!(status, StatusCode::OK, "{}", authorization);
+    }
+    // The scheme has to be whole, not a prefix of one
+    let (status, _, _) = admin_status_with(&app, Some(&format!("Bearers {}", ADMIN_TOKEN))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_admin_credentials_do_nothing_outside_admin() {
+    let app = create_app_with(
+        AppConfig {
+            api_keys: Some(ApiKeys::load(API_KEYS_FIXTURE.as_ref()).unwrap()),
+            ..basic_admin_config(Some(ADMIN_TOKEN))
+        },
+        Arc::new(MutableSource::new(builtin::dataset())),
+    );
+    let get = |authorization: String| {
+        let app = app.clone();
+        async move {
+            let (status, _, body) = get_raw_with(
+                &app,
+                "/getCountry?based=japan",
+                &[("authorization", &authorization)],
+            )
+            .await;
+            (status, body)
+        }
+    };
+
+    let (status, _, body) = get_raw(&app, "/getCountry?based=japan").await;
+    assert_eq!(status, StatusCode::OK);
+    let plain = (status, body);
+    assert_eq!(
+        get(basic_authorization(ADMIN_USER, ADMIN_PASSWORD)).await,
+        plain
+    );
+    assert_eq!(get(ADMIN_AUTHORIZATION.to_string()).await, plain);
+    // Wrong admin credentials are not checked outside /admin either
+    assert_eq!(get(basic_authorization(ADMIN_USER, "hunter2")).await, plain);
+
+    // An API key is no admin credential
+    let (status, _, _) =
+        get_raw_with(&app, "/admin/export", &[("x-api-key", "k_partner_0001")]).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[test]
+fn test_admin_password_hash_must_be_argon2() {
+    let bcrypt = "$2b$12$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW";
+    assert!(admin_auth::parse_password_hash(bcrypt).is_err());
+    assert!(admin_auth::parse_password_hash("hunter2").is_err());
+    let scrypt =
+        "$scrypt$ln=16,r=8,p=1$aM15713r3Xsvxbi31lqr1Q$nFNh2CVHVjNldFVKDHDlm4CbdRSCdEBsjjJxD+iCs5E";
+    assert!(matches!(
+        BasicAuth::new(ADMIN_USER.to_string(), scrypt.to_string()),
+        Err(admin_auth::AdminAuthError::NotArgon2(_))
+    ));
+    let hash = admin_auth::hash_password(ADMIN_PASSWORD);
+    assert_eq!(admin_auth::parse_password_hash(&hash), Ok(hash));
+}
+async fn count_countries(app: &Router) -> usize {
+    let (status, _, body) = get_raw(app, "/countries").await;
+    assert_eq!(status, StatusCode::OK);
+    let country_response: CountryResponse = serde_json::from_slice(&body).unwrap();
+
+    country_response.results.len()
+}
+
+#[tokio::test]
+async fn test_list_countries_one_entry_per_country() {
+    let app = create_app();
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/countries")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let country_response: CountryResponse = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(
+        country_response.results.len(),
+        builtin::BUILTIN_COUNTRIES.len()
+    );
+    assert!(country_response
+        .results
+        .iter()
+        .any(|country| country.country == "united states"));
+    assert!(!country_response
+        .results
+        .iter()
+        .any(|country| country.country == "usa"));
+}
+
+#[tokio::test]
+async fn test_admin_add_update_delete_sequence() {
+    let app = admin_app();
+    let baseline = count_countries(&app).await;
+
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "wakanda",
+            "aliases": ["kingdom of wakanda"],
+            "flag": "🇼🇰",
+            "currencyCode": "WKD",
+            "phoneCode": "+999"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(count_countries(&app).await, baseline + 1);
+
+    let (status, body) = send_json(
+        &app,
+        "PUT",
+        "/admin/countries/Kingdom%20of%20Wakanda",
+        serde_json::json!({
+            "name": "wakanda",
+            "aliases": ["birnin zana"],
+            "flag": "🇼🇰",
+            "currencyCode": "VBN",
+            "phoneCode": "+998"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["currencyCode"], "VBN");
+
+    let country_response =
+        get_countries(&app, "wakanda,birnin%20zana,kingdom%20of%20wakanda").await;
+    assert_eq!(country_response.results.len(), 2);
+    assert_eq!(country_response.results[0].currency_code, "VBN");
+    assert_eq!(country_response.results[1].phone_code, "+998");
+    assert_eq!(count_countries(&app).await, baseline + 1);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("DELETE")
+                .uri("/admin/countries/birnin%20zana")
+                .header("authorization", ADMIN_AUTHORIZATION)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    assert!(get_countries(&app, "wakanda,birnin%20zana")
+        .await
+        .results
+        .is_empty());
+    assert_eq!(count_countries(&app).await, baseline);
+}
+
+#[tokio::test]
+async fn test_admin_update_and_delete_unknown_country() {
+    let app = admin_app();
+
+    let (status, body) = send_json(
+        &app,
+        "PUT",
+        "/admin/countries/narnia",
+        serde_json::json!({
+            "name": "narnia",
+            "flag": "🇳🇦",
+            "currencyCode": "NAR",
+            "phoneCode": "+997"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"]["code"], "COUNTRY_NOT_FOUND");
+    assert_eq!(
+        body["error"]["details"]["names"],
+        serde_json::json!(["narnia"])
+    );
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("DELETE")
+                .uri("/admin/countries/narnia")
+                .header("authorization", ADMIN_AUTHORIZATION)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_admin_update_conflict_keeps_original() {
+    let app = admin_app();
+
+    let (status, body) = send_json(
+        &app,
+        "PUT",
+        "/admin/countries/japan",
+        serde_json::json!({
+            "name": "japan",
+            "aliases": ["korea"],
+            "flag": "🇯🇵",
+            "currencyCode": "JPY",
+            "phoneCode": "+81"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"]["details"]["name"], "korea");
+
+    let country_response = get_countries(&app, "japan,korea").await;
+    assert_eq!(country_response.results[0].currency_code, "JPY");
+    assert_eq!(country_response.results[1].currency_code, "KRW");
+}
+
+async fn get_metrics(app: &Router) -> String {
+    let (status, _, body) = get_raw(app, "/metrics").await;
+    assert_eq!(status, StatusCode::OK);
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+fn cached_app(capacity: usize) -> Router {
+    create_app_with(
+        AppConfig {
+            response_cache: capacity,
+            ..admin_config()
+        },
+        Arc::new(MutableSource::new(builtin::dataset())),
+    )
+}
+
+// (hits, misses) as reported on /metrics
+async fn cache_counts(app: &Router) -> (u64, u64) {
+    let metrics = get_metrics(app).await;
+    let count = |result: &str| {
+        let prefix = format!(
+            "rustincountry_response_cache_requests_total{{result=\"{}\"}} ",
+            result
+        );
+        metrics
+            .lines()
+            .find_map(|line| line.strip_prefix(&prefix))
+            .unwrap()
+            .parse()
+            .unwrap()
+    };
+    (count("hit"), count("miss"))
+}
+
+async fn get_country_body(app: &Router, based: &str) -> axum::body::Bytes {
+    let (status, headers, body) = get_raw(app, &format!("/getCountry?based={}", based)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["content-type"], "application/json");
+    body
+}
+
+#[tokio::test]
+async fn test_response_cache_serves_repeated_query() {
+    let app = cached_app(8);
+
+    let first = get_country_body(&app, "japan").await;
+    assert_eq!(cache_counts(&app).await, (0, 1));
+    let second = get_country_body(&app, "japan").await;
+    assert_eq!(cache_counts(&app).await, (1, 1));
+    assert_eq!(first, second);
+}
+
+#[tokio::test]
+async fn test_response_cache_disabled_by_default() {
+    let app = create_app();
+
+    get_country_body(&app, "japan").await;
+    get_country_body(&app, "japan").await;
+    assert_eq!(cache_counts(&app).await, (0, 0));
+}
+
+#[tokio::test]
+async fn test_response_cache_invalidated_by_admin_change() {
+    let app = cached_app(8);
+    assert!(get_countries(&app, "wakanda").await.results.is_empty());
+    assert!(get_countries(&app, "wakanda").await.results.is_empty());
+    assert_eq!(cache_counts(&app).await, (1, 1));
+
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "Wakanda",
+            "flag": "🇼🇰",
+            "currencyCode": "WKD",
+            "phoneCode": "+999"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    assert_eq!(get_countries(&app, "wakanda").await.results.len(), 1);
+    assert_eq!(cache_counts(&app).await, (1, 2));
+}
+
+#[tokio::test]
+async fn test_response_cache_key_canonicalization() {
+    let app = cached_app(8);
+    get_country_body(&app, "japan,korea").await;
+
+    // Whitespace around names does not change the body
+    get_country_body(&app, "%20japan%20,%20korea%20").await;
+    assert_eq!(cache_counts(&app).await, (1, 1));
+    // Order and spelling are echoed back, so they are separate entries
+    get_country_body(&app, "korea,japan").await;
+    get_country_body(&app, "Japan,korea").await;
+    assert_eq!(cache_counts(&app).await, (1, 3));
+    // So is the language of localizedName
+    get_country_body(&app, "japan,korea&lang=ja").await;
+    get_country_body(&app, "japan,korea&lang=JA-jp").await;
+    assert_eq!(cache_counts(&app).await, (2, 4));
+}
+
+#[tokio::test]
+async fn test_response_cache_evicts_least_recently_used() {
+    let app = cached_app(2);
+    get_country_body(&app, "japan").await;
+    get_country_body(&app, "france").await;
+    get_country_body(&app, "japan").await;
+    get_country_body(&app, "peru").await;
+    assert_eq!(cache_counts(&app).await, (1, 3));
+
+    get_country_body(&app, "japan").await;
+    get_country_body(&app, "france").await;
+    assert_eq!(cache_counts(&app).await, (2, 4));
+}
+
+// (ETag, body) of /countries
+async fn get_catalog(app: &Router, query: &str) -> (String, axum::body::Bytes) {
+    let (status, headers, body) = get_raw(app, &format!("/countries{}", query)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["content-type"], "application/json");
+    let etag = headers["etag"].to_str().unwrap().to_string();
+
+    (etag, body)
+}
+
+#[tokio::test]
+async fn test_list_countries_serves_memoized_body() {
+    let app = create_app();
+
+    let (first_etag, first) = get_catalog(&app, "").await;
+    let (second_etag, second) = get_catalog(&app, "").await;
+
+    assert_eq!(first, second);
+    assert_eq!(first_etag, second_etag);
+    // Same bytes as serializing the catalog afresh
+    let dataset = builtin::dataset();
+    let results = lookup::canonical_countries(&dataset, "en");
+    assert_eq!(
+        first,
+        serde_json::to_vec(&CountryResponse {
+            results,
+            unmatched: Vec::new(),
+            truncated: false,
+        })
+        .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_list_countries_memoized_per_language() {
+    let app = create_app();
+
+    let (en_etag, en) = get_catalog(&app, "").await;
+    let (ja_etag, ja) = get_catalog(&app, "?lang=ja").await;
+
+    assert_ne!(en, ja);
+    // A cache must not answer an English client with the Japanese body
+    assert_ne!(en_etag, ja_etag);
+    assert_eq!(get_catalog(&app, "?lang=ja").await.1, ja);
+    assert_eq!(get_catalog(&app, "?lang=en").await.1, en);
+}
+
+#[tokio::test]
+async fn test_list_countries_answers_a_matching_if_none_match_with_304() {
+    let app = create_app();
+    let (etag, _) = get_catalog(&app, "").await;
+
+    for if_none_match in [
+        etag.clone(),
+        format!("W/{}", etag),
+        format!("\"x\", {}", etag),
+    ] {
+        let (status, headers, body) =
+            get_raw_with(&app, "/countries", &[("if-none-match", &if_none_match)]).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        assert_eq!(headers["etag"], etag.as_str());
+        assert!(body.is_empty());
+    }
+
+    // The English tag does not match the Japanese body
+    let (status, _, _) =
+        get_raw_with(&app, "/countries?lang=ja", &[("if-none-match", &etag)]).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_list_countries_etag_depends_on_the_data_not_the_version() {
+    let app = admin_app();
+    let (etag, body) = get_catalog(&app, "").await;
+
+    let wakanda = serde_json::json!({
+        "name": "Wakanda",
+        "flag": "🇼🇰",
+        "currencyCode": "WKD",
+        "phoneCode": "+999"
+    });
+    let (status, _) = send_json(&app, "POST", "/admin/countries", wakanda).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = send_json(
+        &app,
+        "DELETE",
+        "/admin/countries/wakanda",
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // Two changes later the data is the same again, as on a freshly started replica
+    let (after_etag, after_body) = get_catalog(&app, "").await;
+    assert_eq!(after_body, body);
+    assert_eq!(after_etag, etag);
+    assert_eq!(get_catalog(&create_app(), "").await.0, etag);
+}
+
+#[tokio::test]
+async fn test_list_endpoints_let_caches_serve_stale_copies_while_revalidating() {
+    let app = create_app();
+
+    for path in ["/countries", "/codes", "/codes?format=csv"] {
+        let (status, headers, _) = get_raw(&app, path).await;
+        assert_eq!(status, StatusCode::OK, "{}", path);
+        assert_eq!(
+            headers["cache-control"], "public, max-age=60, stale-while-revalidate=300",
+            "{}",
+            path
+        );
+        let etag = headers["etag"].to_str().unwrap().to_string();
+
+        // A revalidation keeps the copy fresh for another max-age
+        let (status, headers, body) = get_raw_with(&app, path, &[("if-none-match", &etag)]).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED, "{}", path);
+        assert_eq!(headers["etag"], etag.as_str());
+        assert_eq!(
+            headers["cache-control"],
+            "public, max-age=60, stale-while-revalidate=300"
+        );
+        assert!(body.is_empty());
+    }
+}
+
+#[tokio::test]
+async fn test_list_cache_control_is_configurable() {
+    let state =
+        AppState::new(builtin::dataset()).with_list_caching(rusty_currency::catalog::ListCaching {
+            max_age: Duration::from_secs(5),
+            stale_while_revalidate: Duration::from_secs(3600),
+        });
+    let app = create_app_with_state(state, &AppConfig::default());
+
+    for path in ["/countries", "/codes"] {
+        let (_, headers, _) = get_raw(&app, path).await;
+        assert_eq!(
+            headers["cache-control"],
+            "public, max-age=5, stale-while-revalidate=3600"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_codes_etag_is_the_memoized_body_hash() {
+    let app = create_app();
+
+    let (_, headers, first) = get_raw(&app, "/codes").await;
+    let etag = headers["etag"].to_str().unwrap().to_string();
+    let (_, headers, second) = get_raw(&app, "/codes").await;
+    assert_eq!(headers["etag"], etag.as_str());
+    assert_eq!(second, first);
+    let body: CodesResponse = serde_json::from_slice(&first).unwrap();
+    assert!(!body.codes.is_empty());
+
+    // Each format is a body of its own
+    let (status, headers, _) =
+        get_raw_with(&app, "/codes?format=csv", &[("if-none-match", &etag)]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(headers["etag"], etag.as_str());
+}
+
+#[tokio::test]
+async fn test_list_etags_change_when_the_data_does() {
+    let app = admin_app();
+    let mut etags = Vec::new();
+    for path in ["/countries", "/codes"] {
+        let (_, headers, _) = get_raw(&app, path).await;
+        etags.push(headers["etag"].to_str().unwrap().to_string());
+    }
+
+    let wakanda = serde_json::json!({
+        "name": "Wakanda",
+        "flag": "🇼🇰",
+        "currencyCode": "WKD",
+        "phoneCode": "+999"
+    });
+    let (status, _) = send_json(&app, "POST", "/admin/countries", wakanda).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // A cache revalidating its stale copy gets the new list instead of a 304
+    for (path, stale) in ["/countries", "/codes"].into_iter().zip(&etags) {
+        let (status, headers, body) = get_raw_with(&app, path, &[("if-none-match", stale)]).await;
+        assert_eq!(status, StatusCode::OK, "{}", path);
+        assert_ne!(headers["etag"], stale.as_str(), "{}", path);
+        assert!(
+            String::from_utf8_lossy(&body)
+                .to_lowercase()
+                .contains("wakanda"),
+            "{}",
+            path
+        );
+    }
+}
+
+// (Content type, ETag, body) of /countries in another format
+async fn get_catalog_as(app: &Router, query: &str) -> (String, String, String) {
+    let (status, headers, body) = get_raw(app, &format!("/countries{}", query)).await;
+    assert_eq!(status, StatusCode::OK);
+    let header = |name: &str| headers[name].to_str().unwrap().to_string();
+    let (content_type, etag) = (header("content-type"), header("etag"));
+    (
+        content_type,
+        etag,
+        String::from_utf8(body.to_vec()).unwrap(),
+    )
+}
+
+#[tokio::test]
+async fn test_list_countries_as_csv_and_ndjson() {
+    let app = create_app();
+    let (json_etag, _) = get_catalog(&app, "").await;
+    let count = builtin::dataset().len();
+
+    let (content_type, csv_etag, csv) = get_catalog_as(&app, "?format=csv&lang=ja").await;
+    assert_eq!(content_type, "text/csv; charset=utf-8");
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "country,localizedName,flag,currencyCode,phoneCode"
+    );
+    assert_eq!(lines.len(), count + 1);
+    assert!(lines.contains(&"japan,日本,🇯🇵,JPY,+81"));
+
+    let (content_type, ndjson_etag, ndjson) = get_catalog_as(&app, "?format=ndjson").await;
+    assert_eq!(content_type, "application/x-ndjson");
+    let countries: Vec<lookup::CountryInfo> = ndjson
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(countries.len(), count);
+    assert!(countries.iter().any(|info| info.country == "japan"));
+
+    assert_ne!(csv_etag, json_etag);
+    assert_ne!(ndjson_etag, json_etag);
+    assert_eq!(get_catalog_as(&app, "?format=csv&lang=ja").await.2, csv);
+}
+
+#[tokio::test]
+async fn test_list_countries_rejects_an_unknown_format() {
+    let (status, envelope) = get_error("/countries?format=xml").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(envelope.error.code, "INVALID_PARAMETER");
+}
+
+#[tokio::test]
+async fn test_list_countries_rejects_a_conflicting_lang() {
+    let (status, envelope) = get_error("/countries?lang=en&lang=ja").await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(envelope.error.code, "CONFLICTING_PARAMETER");
+    assert_eq!(envelope.error.details.unwrap()["parameter"], "lang");
+    assert!(envelope.error.message.contains("lang"));
+    assert!(envelope.error.request_id.is_some());
+}
+
+#[tokio::test]
+async fn test_conflicting_parameters() {
+    for (uri, parameter) in [
+        ("/getCountry?based=japan&strict=true&strict=false", "strict"),
+        ("/getCountry?based=japan&lang=ja&lang=de", "lang"),
+        (
+            "/getCountry?based=japan&flagFormat=emoji&flagFormat=none",
+            "flagFormat",
+        ),
+        ("/byCurrency?code=EUR&code=USD", "code"),
+    ] {
+        let (status, envelope) = get_error(uri).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(envelope.error.code, "CONFLICTING_PARAMETER", "{}", uri);
+        assert_eq!(
+            envelope.error.details.unwrap()["parameter"],
+            parameter,
+            "{}",
+            uri
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_repeated_parameters_that_agree_or_list() {
+    let app = create_app();
+
+    // The same value twice is no conflict
+    let (status, body) = get_json(&app, "/getCountry?based=japan&lang=ja&lang=ja").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["results"][0]["localizedName"], "日本");
+
+    // Repeated lists add up, in the order given
+    let (status, body) = get_json(&app, "/getCountry?based=japan&based=korea,france").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        result_countries(&body["results"]),
+        ["japan", "korea", "france"]
+    );
+    let (_, body) = get_json(
+        &app,
+        "/getCountry?based=japan&include=geo&include=currencies",
+    )
+    .await;
+    assert!(body["results"][0]["latlng"].is_array());
+    assert!(body["results"][0]["currencies"].is_array());
+}
+
+#[tokio::test]
+async fn test_list_countries_body_and_etag_change_after_admin_add() {
+    let app = admin_app();
+    let (etag, body) = get_catalog(&app, "").await;
+
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "Wakanda",
+            "flag": "🇼🇰",
+            "currencyCode": "WKD",
+            "phoneCode": "+999"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (new_etag, new_body) = get_catalog(&app, "").await;
+    assert_ne!(new_etag, etag);
+    assert_ne!(new_body, body);
+    let catalog: CountryResponse = serde_json::from_slice(&new_body).unwrap();
+    assert!(catalog
+        .results
+        .iter()
+        .any(|country| country.country == "wakanda"));
+}
+
+async fn get_export(app: &Router, query: &str) -> (StatusCode, String, String) {
+    let (status, headers, body) = get_raw_with(
+        app,
+        &format!("/admin/export{}", query),
+        &[("authorization", ADMIN_AUTHORIZATION)],
+    )
+    .await;
+    let content_type = headers["content-type"].to_str().unwrap().to_string();
+
+    (
+        status,
+        content_type,
+        String::from_utf8(body.to_vec()).unwrap(),
+    )
+}
+
+#[tokio::test]
+async fn test_export_csv() {
+    let mut dataset = Dataset::default();
+    dataset
+        .insert(CountryRecord {
+            name: String::from("korea, republic of"),
+            aliases: vec![Alias::current("south korea"), Alias::current("rok")],
+            localized_names: BTreeMap::new(),
+            flag: String::from("🇰🇷"),
+            currency_code: CurrencyCode::new("KRW").unwrap(),
+            phone_code: String::from("+82"),
+            bounding_box: None,
+            tld: None,
+            continent: None,
+            languages: Vec::new(),
+            timezones: Vec::new(),
+            latlng: None,
+            borders: Vec::new(),
+        })
+        .unwrap();
+    let app = admin_app_with_dataset(dataset);
+
+    let (status, content_type, body) = get_export(&app, "?format=csv").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type, "text/csv; charset=utf-8");
+    assert_eq!(
+        body,
+        "name,aliases,flag,currencyCode,phoneCode\n\"korea, republic of\",south korea|rok,🇰🇷,KRW,+82\n"
+    );
+}
+
+#[tokio::test]
+async fn test_export_yaml() {
+    let app = admin_app();
+
+    let (status, content_type, body) = get_export(&app, "?format=yaml").await;
+    let (_, _, json) = get_export(&app, "").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content_type, "application/yaml");
+    let yaml: serde_json::Value = serde_yaml::from_str(&body).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(yaml, json);
+}
+
+#[tokio::test]
+async fn test_checksum_hashes_the_json_export() {
+    let app = admin_app();
+
+    let (status, body) = get_json(&app, "/checksum").await;
+    let (_, _, export) = get_export(&app, "").await;
+    let (_, health) = get_json(&app, "/healthz?verbose=true").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["algorithm"], "sha256");
+    assert_eq!(body["checksum"], format!("{:x}", Sha256::digest(&export)));
+    assert_eq!(body["checksum"], export::checksum(&builtin::dataset()));
+    assert_eq!(body["checksum"], health["datasetChecksum"]);
+    assert_eq!(body["countries"], builtin::dataset().len());
+    assert_eq!(body["datasetVersion"], 1);
+}
+
+#[tokio::test]
+async fn test_checksum_compares_instances() {
+    let japan_in = |currency: &str| {
+        let mut dataset = builtin::dataset();
+        let mut japan = dataset.get("japan").unwrap().clone();
+        japan.currency_code = CurrencyCode::new(currency).unwrap();
+        dataset.replace("japan", japan).unwrap();
+        dataset
+    };
+    let checksum = |dataset: Dataset| async move {
+        let app = create_app_with_state(AppState::new(dataset), &AppConfig::default());
+        get_json(&app, "/checksum").await.1["checksum"].clone()
+    };
+
+    // The same data hashes the same, however it was arrived at
+    assert_eq!(
+        checksum(japan_in("JPY")).await,
+        checksum(builtin::dataset()).await
+    );
+    assert_ne!(
+        checksum(japan_in("JPZ")).await,
+        checksum(builtin::dataset()).await
+    );
+}
+
+#[tokio::test]
+async fn test_checksum_follows_admin_changes() {
+    let app = admin_app();
+    let (_, before) = get_json(&app, "/checksum").await;
+
+    let response = send_admin(
+        &app,
+        "PUT",
+        "/admin/countries/japan",
+        serde_json::json!({
+            "name": "Japan",
+            "flag": "🇯🇵",
+            "currencyCode": "JPZ",
+            "phoneCode": "+81"
+        }),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let (_, after) = get_json(&app, "/checksum").await;
+
+    assert_ne!(after["checksum"], before["checksum"]);
+    assert_eq!(after["datasetVersion"], 2);
+    let (_, again) = get_json(&app, "/checksum").await;
+    assert_eq!(again, after);
+}
+
+#[tokio::test]
+async fn test_export_unsupported_format() {
+    let app = admin_app();
+
+    let (status, _, body) = get_export(&app, "?format=xml").await;
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "INVALID_PARAMETER");
+    assert_eq!(body["error"]["details"]["parameter"], "format");
+    assert!(body["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("\"xml\""));
+    assert!(body["error"]["requestId"].is_string());
+}
+
+// Levels of the events logged with the target `rustincountry::probe`
+#[derive(Clone, Default)]
+struct CapturedProbes(Arc<Mutex<Vec<tracing::Level>>>);
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for CapturedProbes {
+    fn on_event(&self, event: &tracing::Event<'_>, _: tracing_subscriber::layer::Context<'_, S>) {
+        if event.metadata().target() == "rustincountry::probe" {
+            self.0.lock().unwrap().push(*event.metadata().level());
+        }
+    }
+}
+
+fn log_probes() {
+    tracing::info!(target: "rustincountry::probe", "info probe");
+    tracing::debug!(target: "rustincountry::probe", "debug probe");
+}
+
+#[tokio::test]
+async fn test_admin_log_level_changes_what_is_logged() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let (log_level, filter) = LogLevel::new("info,rustincountry::unmatched=off").unwrap();
+    let captured = CapturedProbes::default();
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(captured.clone());
+    let _guard = tracing::subscriber::set_default(subscriber);
+    let state = AppState::new(builtin::dataset()).with_log_level(Arc::new(log_level));
+    let app = create_app_with_state(state, &admin_config());
+
+    let (status, body) = send_json(&app, "GET", "/admin/loglevel", serde_json::json!({})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, serde_json::json!({ "level": "info" }));
+    log_probes();
+    assert_eq!(*captured.0.lock().unwrap(), [tracing::Level::INFO]);
+
+    let (status, body) = send_json(
+        &app,
+        "PUT",
+        "/admin/loglevel",
+        serde_json::json!({ "level": "debug" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        serde_json::json!({ "previous": "info", "level": "debug" })
+    );
+    captured.0.lock().unwrap().clear();
+    log_probes();
+    assert_eq!(
+        *captured.0.lock().unwrap(),
+        [tracing::Level::INFO, tracing::Level::DEBUG]
+    );
+    // Targets the startup filter names keep their level
+    assert!(!tracing::enabled!(target: "rustincountry::unmatched", tracing::Level::INFO));
+
+    let (status, body) = send_json(
+        &app,
+        "PUT",
+        "/admin/loglevel",
+        serde_json::json!({ "level": "info" }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body,
+        serde_json::json!({ "previous": "debug", "level": "info" })
+    );
+    captured.0.lock().unwrap().clear();
+    log_probes();
+    assert_eq!(*captured.0.lock().unwrap(), [tracing::Level::INFO]);
+}
+
+#[tokio::test]
+async fn test_admin_log_level_rejects_unknown_levels() {
+    let (log_level, _filter) = LogLevel::new("warn").unwrap();
+    let log_level = Arc::new(log_level);
+    let state = AppState::new(builtin::dataset()).with_log_level(log_level.clone());
+    let app = create_app_with_state(state, &admin_config());
+
+    for level in [serde_json::json!("verbose"), serde_json::json!("off")] {
+        let (status, body) = send_json(
+            &app,
+            "PUT",
+            "/admin/loglevel",
+            serde_json::json!({ "level": level }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", level);
+        assert_eq!(body["error"]["code"], "INVALID_BODY");
+    }
+    assert_eq!(log_level.current().to_string(), "warn");
+}
+
+#[tokio::test]
+async fn test_admin_log_level_needs_the_token_and_a_handle() {
+    let (log_level, _filter) = LogLevel::new("info").unwrap();
+    let state = AppState::new(builtin::dataset()).with_log_level(Arc::new(log_level));
+    let app = create_app_with_state(state, &admin_config());
+    for method in ["GET", "PUT"] {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method(method)
+                    .uri("/admin/loglevel")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"level":"debug"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", method);
+    }
+
+    let (status, body) = send_json(
+        &admin_app(),
+        "GET",
+        "/admin/loglevel",
+        serde_json::json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["error"]["code"], "LOG_LEVEL_UNAVAILABLE");
+}
+
+async fn dataset_version_of(app: &Router, uri: &str) -> (u64, String) {
+    let (status, headers, _) = get_raw(app, uri).await;
+    assert_eq!(status, StatusCode::OK);
+    let version = headers[revision::DATASET_VERSION_HEADER]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let modified = headers[revision::DATASET_MODIFIED_HEADER]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    (version, modified)
+}
+
+#[tokio::test]
+async fn test_identical_requests_carry_same_dataset_version() {
+    let app = create_app();
+
+    let first = dataset_version_of(&app, "/getCountry?based=japan").await;
+    let second = dataset_version_of(&app, "/getCountry?based=japan").await;
+
+    assert_eq!(first, second);
+    assert_eq!(first.0, 1);
+    assert!(httpdate::parse_http_date(&first.1).is_ok());
+    assert_eq!(dataset_version_of(&app, "/countries").await, first);
+}
+
+#[tokio::test]
+async fn test_dataset_version_bumps_after_admin_add() {
+    let app = admin_app();
+    let (before, _) = dataset_version_of(&app, "/getCountry?based=japan").await;
+
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "atlantis",
+            "flag": "🇦🇹",
+            "currencyCode": "ATL",
+            "phoneCode": "+999"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (after, _) = dataset_version_of(&app, "/getCountry?based=japan").await;
+    assert_eq!(after, before + 1);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/healthz")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let health: HealthResponse = serde_json::from_slice(&body).unwrap();
+    assert_eq!(health.dataset_version, after);
+}
+
+#[tokio::test]
+async fn test_lang_ja_localizes_names() {
+    let app = create_app();
+
+    let country_response = get_countries(&app, "japan,usa&lang=ja").await;
+
+    assert_eq!(country_response.results[0].localized_name, "日本");
+    assert_eq!(country_response.results[1].localized_name, "アメリカ合衆国");
+    assert_eq!(country_response.results[1].country, "usa");
+    assert_eq!(country_response.results[1].currency_code, "USD");
+}
+
+#[tokio::test]
+async fn test_lang_de_localizes_names() {
+    let app = create_app();
+
+    let country_response = get_countries(&app, "germany,Japan&lang=de-AT").await;
+
+    assert_eq!(country_response.results[0].localized_name, "Deutschland");
+    assert_eq!(country_response.results[1].localized_name, "Japan");
+    assert_eq!(country_response.results[1].flag, "🇯🇵");
+}
+
+#[tokio::test]
+async fn test_unknown_lang_falls_back_to_english() {
+    let app = create_app();
+
+    let default = get_countries(&app, "south%20korea").await;
+    let unknown = get_countries(&app, "south%20korea&lang=tlh").await;
+
+    assert_eq!(default.results[0].localized_name, "South Korea");
+    assert_eq!(unknown.results[0].localized_name, "South Korea");
+}
+
+#[tokio::test]
+async fn test_lookup_is_language_agnostic() {
+    let app = create_app();
+
+    for lang in ["en", "ja", "de", "fr"] {
+        let country_response = get_countries(&app, &format!("France,uk&lang={}", lang)).await;
+        assert_eq!(country_response.results.len(), 2);
+        assert_eq!(country_response.results[0].country, "France");
+        assert_eq!(country_response.results[0].currency_code, "EUR");
+        assert_eq!(country_response.results[1].phone_code, "+44");
+    }
+    assert!(get_countries(&app, "%E6%97%A5%E6%9C%AC&lang=ja")
+        .await
+        .results
+        .is_empty());
+}
+
+// A loader defined outside the crate, loading one country that is not built in
+struct FakeLoader;
+
+impl DatasetLoader for FakeLoader {
+    fn describe(&self) -> String {
+        String::from("a fake source")
+    }
+
+    fn load(&self) -> Result<Dataset, LoadError> {
+        let mut dataset = Dataset::default();
+        for record in self.records()? {
+            dataset.insert(record.normalized()).unwrap();
+        }
+        Ok(dataset)
+    }
+
+    fn records(&self) -> Result<Vec<CountryRecord>, LoadError> {
+        Ok(vec![CountryRecord {
+            name: String::from("Atlantis"),
+            aliases: vec![Alias::current("the lost city")],
+            localized_names: BTreeMap::new(),
+            flag: String::from("🇦🇹"),
+            currency_code: CurrencyCode::new("ATL").unwrap(),
+            phone_code: String::from("+999"),
+            bounding_box: None,
+            tld: None,
+            continent: None,
+            languages: Vec::new(),
+            timezones: Vec::new(),
+            latlng: None,
+            borders: Vec::new(),
+        }])
+    }
+}
+
+#[tokio::test]
+async fn test_custom_loader_is_served() {
+    let source: &dyn DatasetLoader = &FakeLoader;
+    let app = create_app_with_state(AppState::new(source.load().unwrap()), &AppConfig::default());
+
+    let response = get_countries(&app, "the%20lost%20city,japan").await;
+
+    assert_eq!(response.results.len(), 1);
+    assert_eq!(response.results[0].country, "the lost city");
+    assert_eq!(response.results[0].currency_code, "ATL");
+    assert_eq!(response.results[0].phone_code, "+999");
+    assert_eq!(count_countries(&app).await, 1);
+}
+
+// A backend defined outside the crate, at whatever version the test sets
+struct TinySource {
+    dataset: Arc<Dataset>,
+    version: AtomicU64,
+}
+
+impl CountryDataSource for TinySource {
+    fn all(&self) -> Arc<Dataset> {
+        Arc::clone(&self.dataset)
+    }
+
+    fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
+}
+
+#[tokio::test]
+async fn test_custom_source_is_served() {
+    let source = Arc::new(TinySource {
+        dataset: Arc::new(FakeLoader.load().unwrap()),
+        version: AtomicU64::new(7),
+    });
+    let state = AppState::from_source(source.clone());
+    let app = create_app_with_state(state, &AppConfig::default());
+
+    let response = get_countries(&app, "the%20lost%20city,japan").await;
+    assert_eq!(response.results.len(), 1);
+    assert_eq!(response.results[0].currency_code, "ATL");
+    assert_eq!(count_countries(&app).await, 1);
+    let (version, _) = dataset_version_of(&app, "/getCountry?based=atlantis").await;
+    assert_eq!(version, 7);
+
+    source.version.store(8, Ordering::Relaxed);
+    let (version, _) = dataset_version_of(&app, "/countries").await;
+    assert_eq!(version, 8);
+}
+
+#[tokio::test]
+async fn test_admin_edits_of_a_static_source_are_refused() {
+    let state = AppState::from_source(Arc::new(StaticSource::new(builtin::dataset())));
+    let app = create_app_with_state(state, &admin_config());
+
+    let (status, body) = send_json(
+        &app,
+        "DELETE",
+        "/admin/countries/japan",
+        serde_json::Value::Null,
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"]["code"], "READ_ONLY_DATASET");
+    assert_eq!(get_countries(&app, "japan").await.results.len(), 1);
+}
+
+fn record(name: &str, flag: &str, currency_code: &str, phone_code: &str) -> CountryRecord {
+    CountryRecord {
+        name: name.to_string(),
+        aliases: Vec::new(),
+        localized_names: BTreeMap::new(),
+        flag: flag.to_string(),
+        currency_code: CurrencyCode::new(currency_code).unwrap(),
+        phone_code: phone_code.to_string(),
+        bounding_box: None,
+        tld: None,
+        continent: None,
+        languages: Vec::new(),
+        timezones: Vec::new(),
+        latlng: None,
+        borders: Vec::new(),
+    }
+}
+
+async fn status_of(app: &Router, method: &str, uri: &str) -> StatusCode {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("authorization", ADMIN_AUTHORIZATION)
+                .header("content-type", "application/json")
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn test_admin_routes_disabled_by_default() {
+    let app = create_app();
+
+    assert_eq!(
+        status_of(&app, "POST", "/admin/countries").await,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        status_of(&app, "GET", "/admin/export").await,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        status_of(&app, "DELETE", "/admin/countries/japan").await,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        status_of(&admin_app(), "GET", "/admin/export").await,
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn test_create_app_with_two_country_dataset() {
+    let mut dataset = Dataset::default();
+    dataset.insert(record("japan", "🇯🇵", "JPY", "+81")).unwrap();
+    dataset.insert(record("korea", "🇰🇷", "KRW", "+82")).unwrap();
+    let app = create_app_with(AppConfig::default(), Arc::new(StaticSource::new(dataset)));
+
+    let response = get_countries(&app, "japan,france,korea").await;
+
+    let currencies: Vec<_> = response
+        .results
+        .iter()
+        .map(|country| country.currency_code)
+        .collect();
+    assert_eq!(currencies, ["JPY", "KRW"]);
+    assert_eq!(count_countries(&app).await, 2);
+}
+
+#[tokio::test]
+async fn test_rate_limit_rejects_excess_requests() {
+    let app = create_app_with(
+        AppConfig {
+            rate_limit: NonZeroU32::new(2),
+            ..AppConfig::default()
+        },
+        Arc::new(StaticSource::new(builtin::dataset())),
+    );
+
+    assert_eq!(
+        status_of(&app, "GET", "/getCountry?based=japan").await,
+        StatusCode::OK
+    );
+    assert_eq!(status_of(&app, "GET", "/countries").await, StatusCode::OK);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/getCountry?based=japan")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["retry-after"], "1");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let envelope: ErrorEnvelope = serde_json::from_slice(&body).unwrap();
+    assert_eq!(envelope.error.code, "RATE_LIMITED");
+
+    // Health checks are never limited
+    assert_eq!(status_of(&app, "GET", "/healthz").await, StatusCode::OK);
+}
+
+async fn allowed_origin(app: &Router, origin: &str) -> Option<String> {
+    let (_, headers, _) = get_raw_with(app, "/getCountry?based=japan", &[("origin", origin)]).await;
+    headers
+        .get("access-control-allow-origin")
+        .map(|value| value.to_str().unwrap().to_string())
+}
+
+#[tokio::test]
+async fn test_cors_origins() {
+    let origin = "https://example.com";
+    assert_eq!(allowed_origin(&create_app(), origin).await, None);
+
+    let app = create_app_with(
+        AppConfig {
+            cors_origins: vec![HeaderValue::from_static("https://example.com")],
+            ..AppConfig::default()
+        },
+        Arc::new(StaticSource::new(builtin::dataset())),
+    );
+    assert_eq!(allowed_origin(&app, origin).await.as_deref(), Some(origin));
+    assert_eq!(allowed_origin(&app, "https://other.example").await, None);
+
+    let app = create_app_with(
+        AppConfig {
+            cors_origins: vec![HeaderValue::from_static("*")],
+            ..AppConfig::default()
+        },
+        Arc::new(StaticSource::new(builtin::dataset())),
+    );
+    assert_eq!(allowed_origin(&app, origin).await.as_deref(), Some("*"));
+}
+
+async fn get_openapi() -> serde_json::Value {
+    let (status, _, body) = get_raw(&create_app(), "/openapi.json").await;
+    assert_eq!(status, StatusCode::OK);
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_openapi_documents_get_country() {
+    let document = get_openapi().await;
+
+    let operation = &document["paths"]["/getCountry"]["get"];
+    let parameters = operation["parameters"].as_array().unwrap();
+    let based = parameters
+        .iter()
+        .find(|parameter| parameter["name"] == "based")
+        .unwrap();
+    assert_eq!(based["in"], "query");
+    assert_eq!(based["required"], true);
+    assert!(operation["responses"]["200"]["content"]["application/json"].is_object());
+    assert!(operation["responses"]["400"].is_object());
+    assert!(document["components"]["schemas"]["CountryInfo"].is_object());
+    assert!(document["components"]["schemas"]["ErrorEnvelope"].is_object());
+}
+
+#[tokio::test]
+async fn test_openapi_documents_every_route() {
+    let document = get_openapi().await;
+    let paths = document["paths"].as_object().unwrap();
+
+    let routes = [
+        ("get", "/getCountry"),
+        ("get", "/countries"),
+        ("get", "/openapi.json"),
+        ("get", "/healthz"),
+        ("get", "/version"),
+        ("get", "/checksum"),
+        ("get", "/metrics"),
+        ("post", "/admin/countries"),
+        ("put", "/admin/countries/{name}"),
+        ("delete", "/admin/countries/{name}"),
+        ("get", "/admin/export"),
+        ("get", "/admin/loglevel"),
+        ("put", "/admin/loglevel"),
+    ];
+    for (method, path) in routes {
+        assert!(paths[path][method].is_object(), "{} {}", method, path);
+    }
+}
+
+// Every documented operation reaches a handler rather than the 404 or 405 fallback
+#[tokio::test]
+async fn test_openapi_documents_only_routed_paths() {
+    let document = get_openapi().await;
+    let app = admin_app();
+
+    for (path, operations) in document["paths"].as_object().unwrap() {
+        let uri = path.replace("{name}", "japan");
+        for method in operations.as_object().unwrap().keys() {
+            let status = status_of(&app, &method.to_uppercase(), &uri).await;
+            assert_ne!(status, StatusCode::NOT_FOUND, "{} {}", method, path);
+            assert_ne!(
+                status,
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{} {}",
+                method,
+                path
+            );
+        }
+    }
+}
+
+fn docs_app(docs: bool) -> Router {
+    create_app_with(
+        AppConfig {
+            docs,
+            ..AppConfig::default()
+        },
+        Arc::new(StaticSource::new(builtin::dataset())),
+    )
+}
+
+// The status, headers and body of a GET of `uri`; the other `get_` helpers build on it
+async fn get_raw(app: &Router, uri: &str) -> (StatusCode, HeaderMap, Bytes) {
+    get_raw_with(app, uri, &[]).await
+}
+
+// `get_raw`, sending `headers` with the request
+async fn get_raw_with(
+    app: &Router,
+    uri: &str,
+    headers: &[(&str, &str)],
+) -> (StatusCode, HeaderMap, Bytes) {
+    let mut request = Request::builder().uri(uri);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let response = app
+        .clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, headers, body)
+}
+
+async fn get_page(app: &Router, uri: &str) -> (StatusCode, String, String) {
+    let (status, headers, body) = get_raw(app, uri).await;
+    let content_type = headers
+        .get("content-type")
+        .map(|value| value.to_str().unwrap().to_string())
+        .unwrap_or_default();
+    (
+        status,
+        content_type,
+        String::from_utf8_lossy(&body).into_owned(),
+    )
+}
+
+#[tokio::test]
+async fn test_docs_pages_render_the_spec() {
+    let app = docs_app(true);
+
+    let (status, content_type, body) = get_page(&app, "/docs").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(content_type.starts_with("text/html"), "{}", content_type);
+    assert!(body.contains(r#"url: "openapi.json""#));
+    assert!(body.contains(r#"src="docs/swagger-ui-bundle.js""#));
+    assert!(!body.contains("https://"));
+
+    let (status, content_type, body) = get_page(&app, "/redoc").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(content_type.starts_with("text/html"), "{}", content_type);
+    assert!(body.contains(r#"spec-url="openapi.json""#));
+    assert!(!body.contains("https://"));
+}
+
+#[tokio::test]
+async fn test_docs_assets_are_served_with_their_content_types() {
+    let app = docs_app(true);
+
+    for (uri, expected) in [
+        (
+            "/docs/swagger-ui-bundle.js",
+            "text/javascript; charset=utf-8",
+        ),
+        ("/docs/swagger-ui.css", "text/css; charset=utf-8"),
+        (
+            "/redoc/redoc.standalone.js",
+            "text/javascript; charset=utf-8",
+        ),
+    ] {
+        let (status, content_type, body) = get_page(&app, uri).await;
+        assert_eq!(status, StatusCode::OK, "{}", uri);
+        assert_eq!(content_type, expected, "{}", uri);
+        assert!(!body.is_empty(), "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn test_docs_not_served_when_disabled() {
+    let app = docs_app(false);
+
+    for uri in ["/docs", "/docs/swagger-ui-bundle.js", "/redoc"] {
+        let (status, _, _) = get_page(&app, uri).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{}", uri);
+    }
+}
+
+async fn get_headers(app: &Router, uri: &str) -> HeaderMap {
+    let (status, headers, _) = get_raw(app, uri).await;
+    assert!(status.is_success(), "{}", uri);
+    headers
+}
+
+#[tokio::test]
+async fn test_security_headers_default_on_json_and_html() {
+    let app = docs_app(true);
+
+    let json = get_headers(&app, "/getCountry?based=japan").await;
+    assert_eq!(json["x-content-type-options"], "nosniff");
+    assert_eq!(json["referrer-policy"], "no-referrer");
+    assert!(!json.contains_key("content-security-policy"));
+    assert!(!json.contains_key("permissions-policy"));
+
+    let html = get_headers(&app, "/docs").await;
+    assert_eq!(html["x-content-type-options"], "nosniff");
+    assert_eq!(html["referrer-policy"], "no-referrer");
+    assert_eq!(
+        html["permissions-policy"],
+        security_headers::DEFAULT_PERMISSIONS_POLICY
+    );
+    let policy = html["content-security-policy"].to_str().unwrap();
+    assert!(policy.starts_with("default-src 'none'; script-src 'self' 'sha256-"));
+    assert!(policy.contains("frame-ancestors 'none'"));
+
+    // The hash lets the Swagger UI page's own inline script run
+    let (_, _, page) = get_page(&app, "/docs").await;
+    let (_, script) = page.rsplit_once("<script>").unwrap();
+    let (script, _) = script.split_once("</script>").unwrap();
+    let hash = base64::engine::general_purpose::STANDARD.encode(Sha256::digest(script));
+    assert!(policy.contains(&format!("'sha256-{}'", hash)), "{}", policy);
+
+    assert!(get_headers(&app, "/redoc")
+        .await
+        .contains_key("content-security-policy"));
+}
+
+#[tokio::test]
+async fn test_security_headers_can_be_overridden_one_at_a_time() {
+    let app = create_app_with_state(
+        AppState::new(builtin::dataset()),
+        &AppConfig {
+            docs: true,
+            security_headers: security_headers::SecurityHeaders {
+                referrer_policy: Some(HeaderValue::from_static("strict-origin")),
+                permissions_policy: None,
+                ..security_headers::SecurityHeaders::default()
+            },
+            ..AppConfig::default()
+        },
+    );
+
+    let html = get_headers(&app, "/docs").await;
+    assert_eq!(html["referrer-policy"], "strict-origin");
+    assert!(!html.contains_key("permissions-policy"));
+    assert_eq!(html["x-content-type-options"], "nosniff");
+    assert_eq!(
+        html["content-security-policy"],
+        security_headers::SecurityHeaders::default()
+            .content_security_policy
+            .unwrap()
+    );
+
+    let off = create_app_with_state(
+        AppState::new(builtin::dataset()),
+        &AppConfig {
+            security_headers: security_headers::SecurityHeaders::off(),
+            ..AppConfig::default()
+        },
+    );
+    let json = get_headers(&off, "/getCountry?based=japan").await;
+    assert!(!json.contains_key("x-content-type-options"));
+    assert!(!json.contains_key("referrer-policy"));
+
+    assert_eq!(
+        security_headers::parse_setting("OFF"),
+        Ok(security_headers::HeaderSetting(None))
+    );
+    assert!(security_headers::parse_setting("no-referrer\n").is_err());
+}
+
+fn rated_app(provider: Arc<rates::StaticRateProvider>) -> Router {
+    let state = AppState::new(builtin::dataset())
+        .with_rates(rates::RateCache::new(provider, rates::DEFAULT_TTL));
+    create_app_with_state(state, &AppConfig::default())
+}
+
+async fn get_json(app: &Router, uri: &str) -> (StatusCode, serde_json::Value) {
+    let (status, _, body) = get_raw(app, uri).await;
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn test_get_country_with_rates() {
+    let provider = Arc::new(rates::StaticRateProvider::new([
+        (CurrencyCode::new("JPY").unwrap(), 0.0067),
+        (CurrencyCode::new("EUR").unwrap(), 1.08),
+    ]));
+    let app = rated_app(provider.clone());
+
+    let (status, body) = get_json(&app, "/getCountry?based=japan,germany,france&rates=true").await;
+
+    assert_eq!(status, StatusCode::OK);
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results[0]["currencyCode"], "JPY");
+    assert_eq!(results[0]["exchangeRateToUsd"], 0.0067);
+    assert_eq!(results[1]["exchangeRateToUsd"], 1.08);
+    assert_eq!(results[2]["exchangeRateToUsd"], 1.08);
+    assert!(results
+        .iter()
+        .all(|result| result.get("rateError").is_none()));
+    // Germany and France share one lookup
+    assert_eq!(provider.calls(), 2);
+
+    get_json(&app, "/getCountry?based=japan&rates=true").await;
+    assert_eq!(provider.calls(), 2);
+}
+
+#[tokio::test]
+async fn test_get_country_without_rates_leaves_them_out() {
+    let provider = Arc::new(rates::StaticRateProvider::new([(
+        CurrencyCode::new("JPY").unwrap(),
+        0.0067,
+    )]));
+    let app = rated_app(provider.clone());
+
+    let (_, body) = get_json(&app, "/getCountry?based=japan").await;
+
+    assert!(body["results"][0].get("exchangeRateToUsd").is_none());
+    assert_eq!(provider.calls(), 0);
+}
+
+#[tokio::test]
+async fn test_get_country_rates_degrade_when_upstream_fails() {
+    let app = rated_app(Arc::new(rates::StaticRateProvider::failing(
+        "connection refused",
+    )));
+
+    let (status, body) = get_json(&app, "/getCountry?based=japan,usa&rates=true").await;
+
+    assert_eq!(status, StatusCode::OK);
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    for result in results {
+        assert!(result["exchangeRateToUsd"].is_null());
+        assert_eq!(
+            result["rateError"],
+            "The rate provider failed: connection refused"
+        );
+    }
+    assert_eq!(results[0]["flag"], "🇯🇵");
+}
+
+#[tokio::test]
+async fn test_get_country_rates_say_where_they_came_from() {
+    let provider = Arc::new(rates::StaticRateProvider::new([(
+        CurrencyCode::new("JPY").unwrap(),
+        0.0067,
+    )]));
+    let (_, body) = get_json(&rated_app(provider), "/getCountry?based=japan&rates=true").await;
+    let live = &body["results"][0];
+    assert_eq!(live["rateSource"], "live");
+    let as_of: chrono::DateTime<chrono::Utc> = live["rateAsOf"].as_str().unwrap().parse().unwrap();
+    assert!(chrono::Utc::now() - as_of < chrono::Duration::minutes(1));
+
+    let snapshot = rate_snapshot::RateSnapshot::builtin();
+    let failing = rates::RateCache::new(
+        Arc::new(rates::StaticRateProvider::failing("connection refused")),
+        rates::DEFAULT_TTL,
+    )
+    .with_snapshot(snapshot);
+    for rates in [failing, rates::RateCache::offline(snapshot)] {
+        let app = create_app_with_state(
+            AppState::new(builtin::dataset()).with_rates(rates),
+            &AppConfig::default(),
+        );
+        let (status, body) = get_json(&app, "/getCountry?based=japan&rates=true").await;
+        assert_eq!(status, StatusCode::OK);
+        let result = &body["results"][0];
+        assert_eq!(result["rateSource"], "snapshot");
+        assert_eq!(result["rateAsOf"], "2025-01-02T00:00:00Z");
+        assert!(result["exchangeRateToUsd"].as_f64().unwrap() > 0.0);
+        assert!(result.get("rateError").is_none());
+    }
+
+    // Without a rate there is nothing to date
+    let (_, body) = get_json(&create_app(), "/getCountry?based=japan&rates=true").await;
+    assert!(body["results"][0].get("rateSource").is_none());
+    assert!(body["results"][0].get("rateAsOf").is_none());
+}
+
+#[tokio::test]
+async fn test_get_country_rates_without_a_provider_are_null() {
+    let (status, body) = get_json(&create_app(), "/getCountry?based=japan&rates=true").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["results"][0]["exchangeRateToUsd"].is_null());
+    assert_eq!(
+        body["results"][0]["rateError"],
+        "No exchange rate provider is configured"
+    );
+}
+
+#[tokio::test]
+async fn test_get_country_base_shape_leaves_groups_out() {
+    let (status, body) = get_json(&create_app(), "/getCountry?based=France").await;
+
+    assert_eq!(status, StatusCode::OK);
+    let result = body["results"][0].as_object().unwrap();
+    for key in [
+        "country",
+        "canonicalName",
+        "flag",
+        "currencyCode",
+        "localizedName",
+    ] {
+        assert!(result.contains_key(key), "{}", key);
+    }
+    assert_eq!(result["country"], "France");
+    assert_eq!(result["canonicalName"], "france");
+    for key in [
+        "timezones",
+        "latlng",
+        "borders",
+        "currencies",
+        "translations",
+        "exchangeRateToUsd",
+    ] {
+        assert!(!result.contains_key(key), "{}", key);
+    }
+}
+
+#[tokio::test]
+async fn test_get_country_include_geo() {
+    let (status, body) = get_json(&create_app(), "/getCountry?based=france,🇯🇵&include=geo").await;
+
+    assert_eq!(status, StatusCode::OK);
+    let france = &body["results"][0];
+    assert_eq!(france["latlng"], serde_json::json!([46.0, 2.0]));
+    let borders = france["borders"].as_array().unwrap();
+    for neighbour in ["BE", "DE", "ES", "IT"] {
+        assert!(borders.contains(&neighbour.into()), "{}", neighbour);
+    }
+    assert_eq!(france["timezones"], serde_json::json!(["Europe/Paris"]));
+    assert!(france.get("translations").is_none());
+
+    // Islands have no borders, and results for flags are expanded too
+    let japan = &body["results"][1];
+    assert_eq!(japan["resolvedFrom"], "flag");
+    assert_eq!(japan["borders"], serde_json::json!([]));
+    assert_eq!(japan["timezones"], serde_json::json!(["Asia/Tokyo"]));
+}
+
+#[tokio::test]
+async fn test_get_country_include_currencies_and_translations() {
+    let (_, body) = get_json(
+        &create_app(),
+        "/getCountry?based=japan&include=currencies,%20TRANSLATIONS",
+    )
+    .await;
+
+    let japan = &body["results"][0];
+    assert_eq!(
+        japan["currencies"],
+        serde_json::json!([{"code": "JPY", "symbols": ["¥"]}])
+    );
+    assert_eq!(japan["translations"]["ja"], "日本");
+    assert_eq!(japan["translations"]["en"], "Japan");
+    assert!(japan.get("latlng").is_none());
+}
+
+#[tokio::test]
+async fn test_get_country_include_rates_is_rates_true() {
+    let (status, body) = get_json(&create_app(), "/getCountry?based=japan&include=rates").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["results"][0]["exchangeRateToUsd"].is_null());
+    assert_eq!(
+        body["results"][0]["rateError"],
+        "No exchange rate provider is configured"
+    );
+}
+
+#[tokio::test]
+async fn test_get_country_include_is_part_of_the_response_cache_key() {
+    let app = create_app();
+
+    let (_, plain) = get_json(&app, "/getCountry?based=france").await;
+    let (_, geo) = get_json(&app, "/getCountry?based=france&include=geo").await;
+
+    assert!(plain["results"][0].get("borders").is_none());
+    assert!(geo["results"][0]["borders"].is_array());
+}
+
+#[tokio::test]
+async fn test_get_country_unknown_include() {
+    let (status, envelope) = get_error("/getCountry?based=france&include=geo,weather").await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(envelope.error.code, "INVALID_PARAMETER");
+    let details = envelope.error.details.unwrap();
+    assert_eq!(details["parameter"], "include");
+    assert!(
+        envelope
+            .error
+            .message
+            .contains("valid ones are geo, currencies, translations, rates"),
+        "{}",
+        envelope.error.message
+    );
+}
+
+#[tokio::test]
+async fn test_get_country_flag_formats() {
+    let app = create_app();
+
+    for (query, flag) in [
+        ("", Some("🇯🇵")),
+        ("&flagFormat=emoji", Some("🇯🇵")),
+        ("&flagFormat=codepoints", Some("U+1F1EF U+1F1F5")),
+        ("&flagFormat=none", None),
+    ] {
+        // Twice, so the second answer comes from the response cache
+        for _ in 0..2 {
+            let (status, body) = get_json(&app, &format!("/getCountry?based=japan{}", query)).await;
+
+            assert_eq!(status, StatusCode::OK, "{}", query);
+            let result = body["results"][0].as_object().unwrap();
+            assert_eq!(
+                result.get("flag").and_then(|flag| flag.as_str()),
+                flag,
+                "{}",
+                query
+            );
+            assert_eq!(result["currencyCode"], "JPY", "{}", query);
+        }
+    }
+
+    let (_, body) = get_json(&app, "/getCountry?based=🇯🇵&flagFormat=codepoints").await;
+    assert_eq!(body["results"][0]["country"], "🇯🇵");
+    assert_eq!(body["results"][0]["flag"], "U+1F1EF U+1F1F5");
+}
+
+#[tokio::test]
+async fn test_list_countries_flag_formats() {
+    let app = create_app();
+    let japan = |body: &serde_json::Value| {
+        body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|result| result["country"] == "japan")
+            .unwrap()
+            .clone()
+    };
+
+    let (_, emoji) = get_json(&app, "/countries").await;
+    assert_eq!(japan(&emoji)["flag"], "🇯🇵");
+    let (_, codepoints) = get_json(&app, "/countries?flagFormat=codepoints").await;
+    assert_eq!(japan(&codepoints)["flag"], "U+1F1EF U+1F1F5");
+    let (_, none) = get_json(&app, "/countries?flagFormat=none").await;
+    assert!(none["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|result| result.get("flag").is_none()));
+    // Each format is memoized apart
+    assert_eq!(get_json(&app, "/countries").await.1, emoji);
+
+    let (_, _, csv) = get_page(&app, "/countries?format=csv&flagFormat=codepoints").await;
+    assert!(
+        csv.contains("japan,Japan,U+1F1EF U+1F1F5,JPY,+81"),
+        "{}",
+        csv
+    );
+}
+
+#[tokio::test]
+async fn test_flag_format_applies_to_every_country_endpoint() {
+    for uri in [
+        "/byCurrency?code=JPY&flagFormat=codepoints",
+        "/byTld?tld=jp&flagFormat=codepoints",
+        "/byCallingCode?number=81&flagFormat=codepoints",
+        "/byTimezone?zone=Asia/Tokyo&flagFormat=codepoints",
+        "/byLanguage?name=ja&flagFormat=codepoints",
+    ] {
+        let (status, body) = get_json(&create_app(), uri).await;
+
+        assert_eq!(status, StatusCode::OK, "{}", uri);
+        assert_eq!(body["results"][0]["flag"], "U+1F1EF U+1F1F5", "{}", uri);
+    }
+
+    let (_, body) = get_json(&create_app(), "/byCurrency?symbol=¥&flagFormat=none").await;
+    for currency in body["currencies"].as_array().unwrap() {
+        for country in currency["countries"].as_array().unwrap() {
+            assert!(country.get("flag").is_none());
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_unknown_flag_format() {
+    for uri in [
+        "/getCountry?based=japan&flagFormat=png",
+        "/countries?flagFormat=Emoji",
+        "/byTld?tld=jp&flagFormat=",
+    ] {
+        let (status, envelope) = get_error(uri).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(envelope.error.code, "INVALID_PARAMETER", "{}", uri);
+        assert_eq!(
+            envelope.error.details.unwrap()["parameter"],
+            "flagFormat",
+            "{}",
+            uri
+        );
+    }
+}
+
+fn result_countries(results: &serde_json::Value) -> Vec<&str> {
+    results
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["country"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_get_country_order_alpha_sorts_by_canonical_name() {
+    let app = create_app();
+
+    // Sorted by what they were called, these would be FRANCE, Holland, japan, USA
+    let (status, body) = get_json(
+        &app,
+        "/getCountry?based=USA,Holland,japan,FRANCE&order=alpha",
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        result_countries(&body["results"]),
+        ["FRANCE", "japan", "Holland", "USA"]
+    );
+    let canonical: Vec<_> = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["canonicalName"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        canonical,
+        ["france", "japan", "netherlands", "united states"]
+    );
+}
+
+#[tokio::test]
+async fn test_get_country_default_order_is_input_order() {
+    let app = create_app();
+
+    let (_, default) = get_json(&app, "/getCountry?based=USA,Holland,japan,FRANCE").await;
+    let (_, input) = get_json(
+        &app,
+        "/getCountry?based=USA,Holland,japan,FRANCE&order=input",
+    )
+    .await;
+
+    assert_eq!(
+        result_countries(&default["results"]),
+        ["USA", "Holland", "japan", "FRANCE"]
+    );
+    assert_eq!(default, input);
+    assert!(default.get("groups").is_none());
+}
+
+#[tokio::test]
+async fn test_get_country_group_by_currency() {
+    let app = create_app();
+
+    let (status, body) = get_json(
+        &app,
+        "/getCountry?based=germany,france,japan&groupBy=currency",
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.get("results").is_none());
+    let groups = body["groups"].as_array().unwrap();
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0]["key"], "EUR");
+    assert_eq!(
+        result_countries(&groups[0]["results"]),
+        ["germany", "france"]
+    );
+    assert_eq!(groups[1]["key"], "JPY");
+    assert_eq!(result_countries(&groups[1]["results"]), ["japan"]);
+
+    // Ordering applies within each group
+    let (_, body) = get_json(
+        &app,
+        "/getCountry?based=germany,france,japan&groupBy=currency&order=alpha",
+    )
+    .await;
+    assert_eq!(body["groups"][0]["key"], "EUR");
+    assert_eq!(
+        result_countries(&body["groups"][0]["results"]),
+        ["france", "germany"]
+    );
+}
+
+#[tokio::test]
+async fn test_get_country_group_by_continent() {
+    let (_, body) = get_json(
+        &create_app(),
+        "/getCountry?based=japan,germany,china,🇦🇦&groupBy=continent",
+    )
+    .await;
+
+    let groups = body["groups"].as_array().unwrap();
+    assert_eq!(groups[0]["key"], "asia");
+    assert_eq!(result_countries(&groups[0]["results"]), ["japan", "china"]);
+    assert_eq!(groups[1]["key"], "europe");
+    assert_eq!(body["unmatched"][0]["input"], "🇦🇦");
+}
+
+#[tokio::test]
+async fn test_get_country_group_by_currency_with_rates() {
+    let (_, body) = get_json(
+        &create_app(),
+        "/getCountry?based=japan,germany&groupBy=currency&rates=true",
+    )
+    .await;
+
+    assert_eq!(body["groups"][0]["key"], "JPY");
+    assert!(body["groups"][0]["results"][0]["exchangeRateToUsd"].is_null());
+    assert_eq!(body["groups"][1]["results"][0]["country"], "germany");
+}
+
+#[tokio::test]
+async fn test_get_country_order_and_group_errors() {
+    for (uri, parameter) in [
+        ("/getCountry?based=japan&order=random", "order"),
+        ("/getCountry?based=japan&order=", "order"),
+        ("/getCountry?based=japan&groupBy=language", "groupBy"),
+    ] {
+        let (status, envelope) = get_error(uri).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(envelope.error.code, "INVALID_PARAMETER", "{}", uri);
+        assert_eq!(
+            envelope.error.details.unwrap()["parameter"],
+            parameter,
+            "{}",
+            uri
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_get_country_only_currency_code() {
+    let app = create_app();
+
+    let (status, body) = get_json(
+        &app,
+        "/getCountry?based=japan,korea,germany,france,Japan&only=currencyCode",
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.get("results").is_none());
+    assert_eq!(
+        body["values"],
+        serde_json::json!(["JPY", "KRW", "EUR", "EUR", "JPY"])
+    );
+
+    let (_, body) = get_json(
+        &app,
+        "/getCountry?based=japan,korea,germany,france,Japan&only=currencyCode&distinct=true",
+    )
+    .await;
+    assert_eq!(body["values"], serde_json::json!(["JPY", "KRW", "EUR"]));
+}
+
+#[tokio::test]
+async fn test_get_country_only_flag() {
+    let app = create_app();
+
+    let (_, body) = get_json(&app, "/getCountry?based=japan,atlantis,korea&only=flag").await;
+    assert_eq!(body["values"], serde_json::json!(["🇯🇵", "🇰🇷"]));
+
+    let (_, body) = get_json(
+        &app,
+        "/getCountry?based=korea,japan&only=flag&order=alpha&flagFormat=codepoints",
+    )
+    .await;
+    assert_eq!(
+        body["values"],
+        serde_json::json!(["U+1F1EF U+1F1F5", "U+1F1F0 U+1F1F7"])
+    );
+}
+
+#[tokio::test]
+async fn test_get_country_only_errors() {
+    for (uri, parameter) in [
+        ("/getCountry?based=japan&only=timezones", "only"),
+        ("/getCountry?based=japan&only=borders", "only"),
+        ("/getCountry?based=japan&only=capital", "only"),
+        ("/getCountry?based=japan&only=", "only"),
+        (
+            "/getCountry?based=japan&only=flag&groupBy=currency",
+            "groupBy",
+        ),
+    ] {
+        let (status, envelope) = get_error(uri).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(envelope.error.code, "INVALID_PARAMETER", "{}", uri);
+        assert_eq!(
+            envelope.error.details.unwrap()["parameter"],
+            parameter,
+            "{}",
+            uri
+        );
+    }
+
+    let (_, envelope) = get_error("/getCountry?based=japan&only=timezones").await;
+    assert!(
+        envelope.error.message.contains("not a single value"),
+        "{}",
+        envelope.error.message
+    );
+}
+
+#[tokio::test]
+async fn test_get_country_allow_partial_false_with_an_unknown_name() {
+    let app = create_app();
+
+    let (status, body) = get_json(
+        &app,
+        "/getCountry?based=japan,atlantis,🇦🇦,france&allowPartial=false",
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["results"], serde_json::json!([]));
+    let unmatched = body["unmatched"].as_array().unwrap();
+    assert_eq!(unmatched.len(), 2);
+    assert_eq!(unmatched[0]["input"], "atlantis");
+    assert_eq!(unmatched[0]["reason"], "No served country has this name");
+    assert_eq!(unmatched[1]["input"], "🇦🇦");
+    assert_eq!(unmatched[1]["reason"], "No served country has the code AA");
+
+    // The default still answers the rest, leaving unknown names out
+    let (_, body) = get_json(&app, "/getCountry?based=japan,atlantis,🇦🇦,france").await;
+    assert_eq!(result_countries(&body["results"]), ["japan", "france"]);
+    assert_eq!(body["unmatched"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_get_country_allow_partial_false_with_every_name_known() {
+    let app = create_app();
+
+    let (status, body) = get_json(&app, "/getCountry?based=japan,🇫🇷,usa&allowPartial=false").await;
+    let (_, partial) = get_json(&app, "/getCountry?based=japan,🇫🇷,usa").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(result_countries(&body["results"]), ["japan", "🇫🇷", "usa"]);
+    assert!(body.get("unmatched").is_none());
+    assert_eq!(body, partial);
+}
+
+#[tokio::test]
+async fn test_get_country_strict_wins_over_allow_partial() {
+    for query in [
+        "based=japan,atlantis,narnia&strict=true",
+        "based=japan,atlantis,narnia&strict=true&allowPartial=false",
+        "based=japan,atlantis,narnia&allowPartial=false&strict=true&only=flag",
+    ] {
+        let (status, envelope) = get_error(&format!("/getCountry?{}", query)).await;
+
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", query);
+        assert_eq!(envelope.error.code, "UNMATCHED_ENTRIES", "{}", query);
+        assert_eq!(
+            envelope.error.details.unwrap()["entries"],
+            serde_json::json!(["atlantis", "narnia"]),
+            "{}",
+            query
+        );
+    }
+
+    let (status, body) = get_json(
+        &create_app(),
+        "/getCountry?based=japan&strict=true&allowPartial=false",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(result_countries(&body["results"]), ["japan"]);
+}
+
+#[tokio::test]
+async fn test_get_country_max_results_truncates_a_continent_query() {
+    let (status, body) = get_json(
+        &create_app(),
+        "/getCountry?based=japan,germany,china,france&groupBy=continent&maxResults=3",
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["truncated"], true);
+    let groups = body["groups"].as_array().unwrap();
+    assert_eq!(groups[0]["key"], "asia");
+    assert_eq!(result_countries(&groups[0]["results"]), ["japan", "china"]);
+    assert_eq!(groups[1]["key"], "europe");
+    assert_eq!(result_countries(&groups[1]["results"]), ["germany"]);
+}
+
+#[tokio::test]
+async fn test_get_country_max_results_under_the_cap() {
+    let app = create_app();
+    for uri in [
+        "/getCountry?based=japan,germany&maxResults=2",
+        "/getCountry?based=japan,germany&maxResults=10",
+        "/getCountry?based=japan,germany&maxResults=5&groupBy=continent",
+        "/getCountry?based=japan,germany&maxResults=5&only=currencyCode",
+    ] {
+        let (status, body) = get_json(&app, uri).await;
+
+        assert_eq!(status, StatusCode::OK, "{}", uri);
+        assert!(body.get("truncated").is_none(), "{}", uri);
+    }
+
+    let (_, body) = get_json(&app, "/getCountry?based=japan,germany,france&maxResults=2").await;
+    assert_eq!(result_countries(&body["results"]), ["japan", "germany"]);
+    assert_eq!(body["truncated"], true);
+    // Sorted before the cap, so it keeps the first names alphabetically
+    let (_, body) = get_json(
+        &app,
+        "/getCountry?based=japan,germany,france&maxResults=2&order=alpha",
+    )
+    .await;
+    assert_eq!(result_countries(&body["results"]), ["france", "germany"]);
+    let (_, body) = get_json(
+        &app,
+        "/getCountry?based=japan,germany,france&maxResults=1&only=country",
+    )
+    .await;
+    assert_eq!(body["values"], serde_json::json!(["japan"]));
+    assert_eq!(body["truncated"], true);
+}
+
+#[tokio::test]
+async fn test_list_countries_max_results() {
+    let app = create_app();
+
+    let (status, body) = get_json(&app, "/countries?maxResults=3").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["results"].as_array().unwrap().len(), 3);
+    assert_eq!(body["truncated"], true);
+
+    let (_, all) = get_json(&app, "/countries").await;
+    assert!(all.get("truncated").is_none());
+    // Capped bodies are memoized apart from the full catalog
+    let count = all["results"].as_array().unwrap().len();
+    let (_, body) = get_json(&app, &format!("/countries?maxResults={}", count)).await;
+    assert_eq!(body, all);
+}
+
+#[tokio::test]
+async fn test_max_results_out_of_range() {
+    for uri in [
+        "/getCountry?based=japan&maxResults=0",
+        "/getCountry?based=japan&maxResults=-1",
+        "/getCountry?based=japan&maxResults=ten",
+        "/getCountry?based=japan&maxResults=1001",
+        "/countries?maxResults=0",
+        "/countries?maxResults=1001",
+    ] {
+        let (status, envelope) = get_error(uri).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(envelope.error.code, "INVALID_PARAMETER", "{}", uri);
+        assert_eq!(
+            envelope.error.details.unwrap()["parameter"],
+            "maxResults",
+            "{}",
+            uri
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_max_results_ceiling_is_configurable() {
+    let state = AppState::new(builtin::dataset()).with_max_results(2);
+    let app = create_app_with_state(state, &AppConfig::default());
+
+    let (status, _) = get_json(&app, "/getCountry?based=japan&maxResults=2").await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = get_json(&app, "/getCountry?based=japan&maxResults=3").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["details"]["parameter"], "maxResults");
+}
+
+fn geoip_app() -> Router {
+    let resolver = geoip::StaticResolver::new([
+        ("8.8.8.8".parse().unwrap(), Alpha2::new("US").unwrap()),
+        ("2001:200::1".parse().unwrap(), Alpha2::new("JP").unwrap()),
+    ]);
+    let state = AppState::new(builtin::dataset()).with_geoip(Arc::new(resolver));
+    create_app_with_state(state, &AppConfig::default())
+}
+
+#[tokio::test]
+async fn test_get_country_by_ip() {
+    let (status, body) = get_json(
+        &geoip_app(),
+        "/getCountry?based=ip:8.8.8.8,germany,ip:2001:200::1",
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0]["country"], "ip:8.8.8.8");
+    assert_eq!(results[0]["currencyCode"], "USD");
+    assert_eq!(results[0]["resolvedFrom"], "ip");
+    assert_eq!(results[1]["currencyCode"], "EUR");
+    assert!(results[1].get("resolvedFrom").is_none());
+    assert_eq!(results[2]["flag"], "🇯🇵");
+}
+
+#[tokio::test]
+async fn test_get_country_mixes_names_and_bare_ips() {
+    let (status, body) = get_json(
+        &geoip_app(),
+        "/getCountry?based=japan,8.8.8.8,atlantis,germany",
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    let results = body["results"].as_array().unwrap();
+    let countries: Vec<_> = results.iter().map(|result| &result["country"]).collect();
+    assert_eq!(countries, ["japan", "8.8.8.8", "germany"]);
+    assert_eq!(results[1]["currencyCode"], "USD");
+    assert_eq!(results[1]["resolvedFrom"], "ip");
+    assert!(results[0].get("resolvedFrom").is_none());
+    assert!(body.get("unmatched").is_none());
+}
+
+#[tokio::test]
+async fn test_get_country_by_bare_ipv6() {
+    let (status, body) = get_json(&geoip_app(), "/getCountry?based=2001:200::1&lang=ja").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["results"][0]["country"], "2001:200::1");
+    assert_eq!(body["results"][0]["localizedName"], "日本");
+    assert_eq!(body["results"][0]["resolvedFrom"], "ip");
+}
+
+#[tokio::test]
+async fn test_get_country_lists_bare_ips_it_cannot_locate() {
+    let (status, body) =
+        get_json(&geoip_app(), "/getCountry?based=9.9.9.9,japan,192.168.1.10").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["results"].as_array().unwrap().len(), 1);
+    assert_eq!(body["unmatched"][0]["input"], "9.9.9.9");
+    assert_eq!(
+        body["unmatched"][0]["reason"],
+        "The GeoIP database has no country for 9.9.9.9"
+    );
+    assert_eq!(body["unmatched"][1]["input"], "192.168.1.10");
+}
+
+#[tokio::test]
+async fn test_get_country_by_bare_ip_without_database() {
+    let app = create_app();
+
+    for query in ["based=japan,8.8.8.8", "based=japan,8.8.8.8&rates=true"] {
+        let (status, body) = get_json(&app, &format!("/getCountry?{}", query)).await;
+
+        assert_eq!(status, StatusCode::OK, "{}", query);
+        assert_eq!(body["results"].as_array().unwrap().len(), 1, "{}", query);
+        assert_eq!(body["results"][0]["country"], "japan");
+        assert_eq!(
+            body["unmatched"],
+            serde_json::json!([{
+                "input": "8.8.8.8",
+                "reason": "No GeoIP database is configured"
+            }]),
+            "{}",
+            query
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_get_country_by_private_ip_fails() {
+    let (status, body) = get_json(&geoip_app(), "/getCountry?based=japan,ip:192.168.1.10").await;
+
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["code"], "PRIVATE_IP");
+    assert_eq!(body["error"]["details"]["input"], "ip:192.168.1.10");
+}
+
+#[tokio::test]
+async fn test_get_country_by_malformed_ip_fails() {
+    let (status, body) = get_json(&geoip_app(), "/getCountry?based=ip:8.8.8.888").await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "INVALID_IP");
+    assert_eq!(
+        body["error"]["message"],
+        "\"8.8.8.888\" is not an IP address"
+    );
+}
+
+#[tokio::test]
+async fn test_get_country_by_unknown_ip_fails() {
+    let (status, body) = get_json(&geoip_app(), "/getCountry?based=ip:9.9.9.9").await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"]["code"], "IP_NOT_LOCATED");
+}
+
+#[tokio::test]
+async fn test_get_country_by_ip_without_database() {
+    let app = create_app();
+
+    let (status, body) = get_json(&app, "/getCountry?based=ip:8.8.8.8").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["error"]["code"], "GEOIP_UNAVAILABLE");
+
+    // Names are looked up as ever
+    let (status, body) = get_json(&app, "/getCountry?based=japan").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["results"][0]["currencyCode"], "JPY");
+}
+
+#[tokio::test]
+async fn test_whoami() {
+    let app = geoip_app().layer(MockConnectInfo(SocketAddr::from(([8, 8, 8, 8], 443))));
+
+    let (status, body) = get_json(&app, "/whoami?lang=ja").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["ip"], "8.8.8.8");
+    assert_eq!(body["country"]["country"], "united states");
+    assert_eq!(body["country"]["localizedName"], "アメリカ合衆国");
+}
+
+#[tokio::test]
+async fn test_whoami_without_database() {
+    let app = create_app().layer(MockConnectInfo(SocketAddr::from(([8, 8, 8, 8], 443))));
+
+    let (status, body) = get_json(&app, "/whoami").await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["error"]["code"], "GEOIP_UNAVAILABLE");
+    assert_eq!(body["error"]["details"]["input"], "8.8.8.8");
+}
+
+// The GeoIP app behind a load balancer at 10.0.0.5, trusting 10.0.0.0/8
+fn proxied_geoip_app(peer: [u8; 4]) -> Router {
+    let resolver = geoip::StaticResolver::new([
+        ("8.8.8.8".parse().unwrap(), Alpha2::new("US").unwrap()),
+        ("2001:200::1".parse().unwrap(), Alpha2::new("JP").unwrap()),
+    ]);
+    let proxies = TrustedProxies::new([client_ip::parse_network("10.0.0.0/8").unwrap()]);
+    let state = AppState::new(builtin::dataset())
+        .with_geoip(Arc::new(resolver))
+        .with_trusted_proxies(proxies);
+    create_app_with_state(state, &AppConfig::default())
+        .layer(MockConnectInfo(SocketAddr::from((peer, 443))))
+}
+
+async fn whoami_with(app: &Router, name: &str, value: &str) -> (StatusCode, serde_json::Value) {
+    let (status, _, body) = get_raw_with(app, "/whoami", &[(name, value)]).await;
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn test_whoami_behind_a_trusted_proxy_reads_the_forwarded_chain() {
+    let app = proxied_geoip_app([10, 0, 0, 5]);
+
+    // The client wrote 1.2.3.4 itself; 8.8.8.8 is who connected to the inner proxy 10.1.1.1
+    let (status, body) = whoami_with(&app, "x-forwarded-for", "1.2.3.4, 8.8.8.8, 10.1.1.1").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["ip"], "8.8.8.8");
+    assert_eq!(body["country"]["country"], "united states");
+
+    let (status, body) = whoami_with(
+        &app,
+        "forwarded",
+        r#"for=1.2.3.4, for="[2001:200::1]:4711";proto=https, for=10.1.1.1"#,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["ip"], "2001:200::1");
+    assert_eq!(body["country"]["country"], "japan");
+}
+
+#[tokio::test]
+async fn test_whoami_ignores_forwarded_headers_from_untrusted_peers() {
+    let app = proxied_geoip_app([8, 8, 8, 8]);
+
+    for (name, value) in [
+        ("x-forwarded-for", "2001:200::1"),
+        ("forwarded", "for=\"[2001:200::1]\""),
+    ] {
+        let (status, body) = whoami_with(&app, name, value).await;
+        assert_eq!(status, StatusCode::OK, "{}", name);
+        assert_eq!(body["ip"], "8.8.8.8", "{}", name);
+        assert_eq!(body["country"]["country"], "united states", "{}", name);
+    }
+}
+
+#[tokio::test]
+async fn test_whoami_stops_at_malformed_forwarded_entries() {
+    let app = proxied_geoip_app([10, 0, 0, 5]);
+
+    // Nothing left of a malformed entry can be trusted, so the client is the hop after it
+    for (name, value, client) in [
+        (
+            "x-forwarded-for",
+            "8.8.8.8, not-an-ip, 10.1.1.1",
+            "10.1.1.1",
+        ),
+        ("x-forwarded-for", "8.8.8.8,,", "10.0.0.5"),
+        ("x-forwarded-for", "", "10.0.0.5"),
+        ("forwarded", "for=8.8.8.8, for=_hidden", "10.0.0.5"),
+        ("forwarded", "for=8.8.8.8, proto=https", "10.0.0.5"),
+        ("forwarded", "for=\"8.8.8.8", "10.0.0.5"),
+    ] {
+        let (status, body) = whoami_with(&app, name, value).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", value);
+        assert_eq!(body["error"]["code"], "PRIVATE_IP", "{}", value);
+        assert_eq!(body["error"]["details"]["input"], client, "{}", value);
+    }
+}
+
+async fn get_json_with_language(
+    app: &Router,
+    uri: &str,
+    accept_language: &str,
+) -> serde_json::Value {
+    let (status, headers, body) =
+        get_raw_with(app, uri, &[("accept-language", accept_language)]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["vary"], "accept-language");
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_accept_language_localizes_names() {
+    let app = create_app();
+
+    let body = get_json_with_language(&app, "/getCountry?based=japan,usa", "ja").await;
+    assert_eq!(body["results"][0]["localizedName"], "日本");
+    assert_eq!(body["results"][1]["localizedName"], "アメリカ合衆国");
+
+    let body =
+        get_json_with_language(&app, "/getCountry?based=japan", "ko, de;q=0.9, ja;q=0.8").await;
+    assert_eq!(body["results"][0]["localizedName"], "Japan");
+    assert_eq!(body["results"][0]["country"], "japan");
+
+    let body = get_json_with_language(&app, "/countries", "fr-FR").await;
+    let germany = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|result| result["country"] == "germany")
+        .unwrap();
+    assert_eq!(germany["localizedName"], "Allemagne");
+}
+
+#[tokio::test]
+async fn test_lang_param_overrides_accept_language() {
+    let app = create_app();
+
+    let body = get_json_with_language(&app, "/getCountry?based=japan&lang=de", "ja").await;
+    assert_eq!(body["results"][0]["localizedName"], "Japan");
+
+    let body = get_json_with_language(&app, "/getCountry?based=germany&lang=en", "ja").await;
+    assert_eq!(body["results"][0]["localizedName"], "Germany");
+}
+
+#[tokio::test]
+async fn test_garbage_accept_language_falls_back_to_english() {
+    let body = get_json_with_language(&create_app(), "/getCountry?based=japan", ";;q=,*x").await;
+
+    assert_eq!(body["results"][0]["localizedName"], "Japan");
+}
+
+#[tokio::test]
+async fn test_accept_language_is_part_of_the_response_cache_key() {
+    let app = create_app_with(
+        AppConfig {
+            response_cache: 10,
+            ..AppConfig::default()
+        },
+        Arc::new(MutableSource::new(builtin::dataset())),
+    );
+
+    let ja = get_json_with_language(&app, "/getCountry?based=japan", "ja").await;
+    let en = get_json_with_language(&app, "/getCountry?based=japan", "en").await;
+
+    assert_eq!(ja["results"][0]["localizedName"], "日本");
+    assert_eq!(en["results"][0]["localizedName"], "Japan");
+}
+
+// The next event of an SSE body, as its `field: value` lines
+async fn next_event(body: &mut Body) -> Vec<(String, String)> {
+    let mut event = String::new();
+    while !event.ends_with("\n\n") {
+        let frame = tokio::time::timeout(events::KEEP_ALIVE_INTERVAL * 2, body.frame())
+            .await
+            .expect("no event arrived in time")
+            .expect("the stream ended")
+            .unwrap();
+        event.push_str(std::str::from_utf8(&frame.into_data().unwrap()).unwrap());
+    }
+    event
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(field, value)| (field.to_string(), value.trim_start().to_string()))
+        .collect()
+}
+
+async fn subscribe_to_events(app: &Router) -> Body {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/events")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "text/event-stream");
+    response.into_body()
+}
+
+fn dataset_event(fields: &[(String, String)]) -> DatasetEvent {
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_else(|| panic!("no {} in {:?}", name, fields))
+    };
+    assert_eq!(field("event"), "dataset");
+    let event: DatasetEvent = serde_json::from_str(field("data")).unwrap();
+    assert_eq!(field("id"), event.dataset_version.to_string());
+    event
+}
+
+#[tokio::test]
+async fn test_events_stream_admin_changes() {
+    let app = admin_app();
+    let mut events = subscribe_to_events(&app).await;
+
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "Wakanda",
+            "flag": "🇼🇰",
+            "currencyCode": "WKD",
+            "phoneCode": "+999"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let event = dataset_event(&next_event(&mut events).await);
+    assert_eq!(event.dataset_version, 2);
+    assert_eq!(event.added, ["wakanda"]);
+    assert!(event.removed.is_empty());
+    assert!(event.changed.is_empty());
+
+    let (status, _) = send_json(
+        &app,
+        "PUT",
+        "/admin/countries/wakanda",
+        serde_json::json!({
+            "name": "Wakanda",
+            "flag": "🇼🇰",
+            "currencyCode": "WKD",
+            "phoneCode": "+998"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let event = dataset_event(&next_event(&mut events).await);
+    assert_eq!(event.dataset_version, 3);
+    assert_eq!(event.changed, ["wakanda"]);
+
+    let (status, _) = send_json(
+        &app,
+        "DELETE",
+        "/admin/countries/japan",
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let event = dataset_event(&next_event(&mut events).await);
+    assert_eq!(event.dataset_version, 4);
+    assert_eq!(event.removed, ["japan"]);
+}
+
+#[tokio::test]
+async fn test_events_failed_edit_sends_nothing() {
+    let app = admin_app();
+    let mut events = subscribe_to_events(&app).await;
+
+    let (status, _) = send_json(
+        &app,
+        "DELETE",
+        "/admin/countries/atlantis",
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send_json(
+        &app,
+        "DELETE",
+        "/admin/countries/japan",
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // The first event is the successful delete
+    let event = dataset_event(&next_event(&mut events).await);
+    assert_eq!(event.dataset_version, 2);
+    assert_eq!(event.removed, ["japan"]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_events_keep_alive() {
+    let app = create_app();
+    let mut events = subscribe_to_events(&app).await;
+
+    let started = tokio::time::Instant::now();
+    let fields = next_event(&mut events).await;
+
+    assert_eq!(fields, [(String::new(), String::from("keep-alive"))]);
+    assert_eq!(started.elapsed(), events::KEEP_ALIVE_INTERVAL);
+}
+
+const WEBHOOK_SECRET: &str = "test-webhook-secret";
+
+// A webhook target that answers with `statuses` in turn and 200 after them, and hands
+// over every request it gets
+async fn webhook_target(
+    statuses: Vec<StatusCode>,
+) -> (String, mpsc::UnboundedReceiver<(HeaderMap, Bytes)>) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let statuses = Arc::new(Mutex::new(statuses.into_iter()));
+    let target = Router::new().route(
+        "/hook",
+        post(move |headers: HeaderMap, body: Bytes| {
+            let (sender, statuses) = (sender.clone(), statuses.clone());
+            async move {
+                sender.send((headers, body)).unwrap();
+                let status = statuses.lock().unwrap().next();
+                status.unwrap_or(StatusCode::OK)
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, target).await });
+    (url, receiver)
+}
+
+fn webhook_app(url: String, max_attempts: u32) -> Router {
+    let state = AppState::new(builtin::dataset());
+    webhooks::spawn(
+        &state,
+        webhooks::WebhookConfig {
+            max_attempts,
+            initial_backoff: Duration::from_millis(10),
+            ..webhooks::WebhookConfig::new(vec![url], WEBHOOK_SECRET.to_string())
+        },
+    );
+    create_app_with_state(state, &admin_config())
+}
+
+async fn add_wakanda(app: &Router) {
+    let (status, _) = send_json(
+        app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "Wakanda",
+            "flag": "🇼🇰",
+            "currencyCode": "WKD",
+            "phoneCode": "+999"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+}
+
+async fn next_delivery(
+    deliveries: &mut mpsc::UnboundedReceiver<(HeaderMap, Bytes)>,
+) -> (HeaderMap, Bytes) {
+    tokio::time::timeout(Duration::from_secs(5), deliveries.recv())
+        .await
+        .expect("no webhook arrived in time")
+        .unwrap()
+}
+
+// Checks the signature header the way a webhook target would
+fn assert_signed(headers: &HeaderMap, body: &[u8]) {
+    let signature = headers[webhooks::SIGNATURE_HEADER].to_str().unwrap();
+    let hex = signature.strip_prefix("sha256=").unwrap();
+    let digest: Vec<u8> = (0..hex.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(&hex[at..at + 2], 16).unwrap())
+        .collect();
+    Hmac::<Sha256>::new_from_slice(WEBHOOK_SECRET.as_bytes())
+        .unwrap()
+        .chain_update(body)
+        .verify_slice(&digest)
+        .expect("the signature verifies");
+}
+
+#[tokio::test]
+async fn test_webhook_delivers_signed_change() {
+    let (url, mut deliveries) = webhook_target(Vec::new()).await;
+    let app = webhook_app(url, webhooks::DEFAULT_MAX_ATTEMPTS);
+
+    add_wakanda(&app).await;
+
+    let (headers, body) = next_delivery(&mut deliveries).await;
+    assert_eq!(headers["content-type"], "application/json");
+    assert_eq!(headers[webhooks::EVENT_HEADER], "dataset");
+    assert_signed(&headers, &body);
+    let event: DatasetEvent = serde_json::from_slice(&body).unwrap();
+    assert_eq!(event.dataset_version, 2);
+    assert_eq!(event.added, ["wakanda"]);
+    assert!(event.removed.is_empty());
+    assert!(event.changed.is_empty());
+}
+
+#[tokio::test]
+async fn test_webhook_retries_until_accepted() {
+    let failure = StatusCode::INTERNAL_SERVER_ERROR;
+    let (url, mut deliveries) = webhook_target(vec![failure, failure]).await;
+    let app = webhook_app(url, webhooks::DEFAULT_MAX_ATTEMPTS);
+
+    add_wakanda(&app).await;
+
+    let (_, first) = next_delivery(&mut deliveries).await;
+    for _ in 0..2 {
+        let (headers, body) = next_delivery(&mut deliveries).await;
+        assert_eq!(body, first);
+        assert_signed(&headers, &body);
+    }
+    // The third attempt was taken, so there is no fourth
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(deliveries.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn test_webhook_gives_up_after_max_attempts() {
+    let failure = StatusCode::INTERNAL_SERVER_ERROR;
+    let (url, mut deliveries) = webhook_target(vec![failure; 10]).await;
+    let app = webhook_app(url, 3);
+
+    add_wakanda(&app).await;
+
+    for _ in 0..3 {
+        next_delivery(&mut deliveries).await;
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(deliveries.try_recv().is_err());
+}
+
+// A fresh state directory for each test, so parallel tests do not share snapshots
+fn state_dir(test: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "rusty_currency_state_{}_{}",
+        test,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+// Starts the server the way `--state-dir` does, restoring the snapshot in `dir`
+fn snapshot_app(dir: &std::path::Path) -> Router {
+    let snapshots = snapshot::SnapshotStore::new(dir).unwrap();
+    let dataset = snapshots.restore(builtin::dataset(), None);
+    let state = AppState::new(dataset).with_snapshots(snapshots);
+    create_app_with_state(state, &admin_config())
+}
+
+async fn wakanda_results(app: &Router) -> usize {
+    let (status, body) = get_json(app, "/getCountry?based=wakanda").await;
+    assert_eq!(status, StatusCode::OK);
+    body["results"].as_array().unwrap().len()
+}
+
+#[tokio::test]
+async fn test_admin_changes_survive_restart() {
+    let dir = state_dir("restart");
+    let app = snapshot_app(&dir);
+    add_wakanda(&app).await;
+    let (status, _) = send_json(
+        &app,
+        "DELETE",
+        "/admin/countries/japan",
+        serde_json::json!({}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    drop(app);
+
+    let restarted = snapshot_app(&dir);
+    assert_eq!(wakanda_results(&restarted).await, 1);
+    let (_, body) = get_json(&restarted, "/getCountry?based=japan,germany").await;
+    assert_eq!(body["results"].as_array().unwrap().len(), 1);
+    assert!(!dir.join("countries.snapshot.json.tmp").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_corrupt_snapshot_is_skipped() {
+    let dir = state_dir("corrupt");
+    add_wakanda(&snapshot_app(&dir)).await;
+
+    // A flipped currency code still parses, but no longer matches the checksum
+    let path = dir.join(snapshot::SNAPSHOT_FILE);
+    let snapshot = std::fs::read_to_string(&path).unwrap();
+    assert!(snapshot.contains("\"checksum\": \"sha256:"));
+    std::fs::write(&path, snapshot.replace("WKD", "WKX")).unwrap();
+    assert!(matches!(
+        snapshot::SnapshotStore::new(&dir).unwrap().read(),
+        Err(snapshot::SnapshotError::Corrupt { .. })
+    ));
+    assert_eq!(wakanda_results(&snapshot_app(&dir)).await, 0);
+
+    std::fs::write(&path, &snapshot[..snapshot.len() / 2]).unwrap();
+    assert_eq!(wakanda_results(&snapshot_app(&dir)).await, 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn test_snapshot_older_than_data_is_ignored() {
+    let dir = state_dir("older");
+    add_wakanda(&snapshot_app(&dir)).await;
+
+    let snapshots = snapshot::SnapshotStore::new(&dir).unwrap();
+    let saved_at = snapshots.read().unwrap().unwrap().saved_at;
+    let newer = saved_at + Duration::from_secs(1);
+    let dataset = snapshots.restore(builtin::dataset(), Some(newer));
+    assert!(dataset.get("wakanda").is_none());
+    let dataset = snapshots.restore(builtin::dataset(), Some(saved_at - Duration::from_secs(1)));
+    assert!(dataset.get("wakanda").is_some());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// Serves `document` at /countries.json with an ETag, answering 500 while it is None.
+// Counts the downloads that were not answered `304 Not Modified`.
+async fn data_server(
+    document: Arc<Mutex<Option<String>>>,
+) -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+    let downloads = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counted = downloads.clone();
+    let server = Router::new().route(
+        "/countries.json",
+        axum::routing::get(move |headers: HeaderMap| {
+            let (document, downloads) = (document.clone(), counted.clone());
+            async move {
+                let Some(document) = document.lock().unwrap().clone() else {
+                    return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+                };
+                let etag = format!("\"{:x}\"", Sha256::digest(&document));
+                if headers
+                    .get("if-none-match")
+                    .and_then(|tag| tag.to_str().ok())
+                    == Some(&etag)
+                {
+                    return StatusCode::NOT_MODIFIED.into_response();
+                }
+                downloads.fetch_add(1, Ordering::Relaxed);
+                ([("etag", etag)], document).into_response()
+            }
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/countries.json", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, server).await });
+    (url, downloads)
+}
+
+// Japan alone, plus Wakanda when `with_wakanda` is set, as a data file
+fn remote_document(with_wakanda: bool) -> String {
+    let mut dataset = Dataset::default();
+    dataset
+        .insert(builtin::dataset().get("japan").unwrap().clone())
+        .unwrap();
+    if with_wakanda {
+        dataset
+            .insert(CountryRecord {
+                name: String::from("wakanda"),
+                aliases: Vec::new(),
+                localized_names: BTreeMap::new(),
+                flag: String::from("🇼🇰"),
+                currency_code: CurrencyCode::new("WKD").unwrap(),
+                phone_code: String::from("+999"),
+                bounding_box: None,
+                tld: None,
+                continent: None,
+                languages: Vec::new(),
+                timezones: Vec::new(),
+                latlng: None,
+                borders: Vec::new(),
+            })
+            .unwrap();
+    }
+    export::to_json(&dataset)
+}
+
+// Downloads the document and serves it, as `--data-url` does at startup
+async fn remote_app(url: &str) -> (AppState, remote::RemoteDataset, Router) {
+    let remote = remote::RemoteDataset::new(url);
+    let dataset = remote.fetch().await.unwrap().unwrap();
+    let state = AppState::new(dataset);
+    remote::record_success(&state);
+    let app = create_app_with_state(state.clone(), &AppConfig::default());
+    (state, remote, app)
+}
+
+#[tokio::test]
+async fn test_data_url_refresh_swaps_in_new_data() {
+    let document = Arc::new(Mutex::new(Some(remote_document(false))));
+    let (url, downloads) = data_server(document.clone()).await;
+    let (state, remote, app) = remote_app(&url).await;
+    assert_eq!(wakanda_results(&app).await, 0);
+
+    let refreshed = remote::refresh(&state, &remote).await.unwrap();
+    assert!(matches!(refreshed, remote::Refreshed::Unchanged));
+    assert_eq!(downloads.load(Ordering::Relaxed), 1);
+
+    *document.lock().unwrap() = Some(remote_document(true));
+    match remote::refresh(&state, &remote).await.unwrap() {
+        remote::Refreshed::Updated(diff) => assert_eq!(diff.added, ["wakanda"]),
+        unchanged => panic!("expected an update, got {:?}", unchanged),
+    }
+    assert_eq!(wakanda_results(&app).await, 1);
+    assert_eq!(downloads.load(Ordering::Relaxed), 2);
+
+    let metrics = get_metrics(&app).await;
+    assert!(metrics.contains("rustincountry_dataset_refreshes_total{outcome=\"updated\"} 1"));
+    assert!(metrics.contains("rustincountry_dataset_refreshes_total{outcome=\"unchanged\"} 1"));
+    let (_, health) = get_json(&app, "/healthz").await;
+    assert!(health["lastRefresh"].as_str().unwrap().ends_with(" GMT"));
+}
+
+#[tokio::test]
+async fn test_data_url_failure_keeps_current_data() {
+    let document = Arc::new(Mutex::new(Some(remote_document(false))));
+    let (url, _) = data_server(document.clone()).await;
+    let (state, remote, app) = remote_app(&url).await;
+
+    *document.lock().unwrap() = None;
+    assert!(matches!(
+        remote::refresh(&state, &remote).await,
+        Err(remote::RemoteError::Status(
+            StatusCode::INTERNAL_SERVER_ERROR
+        ))
+    ));
+    *document.lock().unwrap() = Some(String::from("{\"countries\": [{\"name\": \"wakanda\"}]}"));
+    assert!(matches!(
+        remote::refresh(&state, &remote).await,
+        Err(remote::RemoteError::Invalid(_))
+    ));
+
+    let (status, body) = get_json(&app, "/getCountry?based=japan").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["results"][0]["currencyCode"], "JPY");
+    let metrics = get_metrics(&app).await;
+    assert!(metrics.contains("rustincountry_dataset_refreshes_total{outcome=\"failure\"} 2"));
+}
+
+#[tokio::test]
+async fn test_data_url_refreshes_on_the_interval() {
+    let document = Arc::new(Mutex::new(Some(remote_document(false))));
+    let (url, _) = data_server(document.clone()).await;
+    let (state, remote, app) = remote_app(&url).await;
+    remote::spawn_refresh(state, remote, Duration::from_millis(20));
+
+    *document.lock().unwrap() = Some(remote_document(true));
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while wakanda_results(&app).await == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the new data was not swapped in");
+}
+
+#[test]
+fn test_parse_refresh_interval() {
+    assert_eq!(remote::parse_interval("15m"), Ok(Duration::from_secs(900)));
+    assert_eq!(remote::parse_interval("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(remote::parse_interval("1h"), Ok(Duration::from_secs(3600)));
+    for invalid in ["", "m", "0s", "15 minutes", "-1m"] {
+        assert!(remote::parse_interval(invalid).is_err(), "{:?}", invalid);
+    }
+}
+
+fn health_app(state: AppState) -> Router {
+    create_app_with_state(state, &AppConfig::default())
+}
+
+#[tokio::test]
+async fn test_verbose_health_with_every_component_ok() {
+    let provider = Arc::new(rates::StaticRateProvider::new([]));
+    let state = AppState::new(builtin::dataset())
+        .with_geoip(Arc::new(geoip::StaticResolver::default()))
+        .with_rates(rates::RateCache::new(provider, rates::DEFAULT_TTL));
+    let app = health_app(state);
+
+    let (status, body) = get_json(&app, "/healthz?verbose=true").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ok");
+    assert!(body["uptimeSeconds"].is_u64());
+    assert_eq!(body["datasetEntries"], builtin::dataset().len());
+    assert_eq!(
+        body["datasetChecksum"],
+        export::checksum(&builtin::dataset())
+    );
+    assert!(body.get("lastReload").is_none());
+    let components = body["components"].as_object().unwrap();
+    assert_eq!(
+        components.keys().collect::<Vec<_>>(),
+        ["dataset", "geoip", "rates"]
+    );
+    for (name, component) in components {
+        assert_eq!(component["status"], "ok", "{}", name);
+        assert_eq!(component["required"], name == "dataset", "{}", name);
+    }
+    let health: HealthResponse = serde_json::from_value(body).unwrap();
+    assert_eq!(health.details.unwrap().components.len(), 3);
+
+    let (_, plain) = get_json(&app, "/healthz").await;
+    assert!(plain.get("components").is_none());
+    let health: HealthResponse = serde_json::from_value(plain).unwrap();
+    assert!(health.details.is_none());
+}
+
+#[tokio::test]
+async fn test_verbose_health_is_degraded_by_failing_rate_provider() {
+    let provider = Arc::new(rates::StaticRateProvider::failing("connection refused"));
+    let state = AppState::new(builtin::dataset())
+        .with_rates(rates::RateCache::new(provider, rates::DEFAULT_TTL));
+
+    let (status, body) = get_json(&health_app(state), "/healthz?verbose=true").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["components"]["dataset"]["status"], "ok");
+    let rates = &body["components"]["rates"];
+    assert_eq!(rates["status"], "degraded");
+    assert_eq!(rates["required"], false);
+    assert!(rates["message"]
+        .as_str()
+        .unwrap()
+        .contains("connection refused"));
+}
+
+#[tokio::test]
+async fn test_open_rate_breaker_degrades_health_without_asking_the_provider() {
+    let provider = Arc::new(rates::StaticRateProvider::failing("connection refused"));
+    let rates = rates::RateCache::new(provider.clone(), rates::DEFAULT_TTL).with_breaker(
+        breaker::CircuitBreaker::new(NonZeroU32::new(2).unwrap(), Duration::from_secs(30)),
+    );
+    let app = health_app(AppState::new(builtin::dataset()).with_rates(rates));
+
+    let (_, body) = get_json(&app, "/healthz?verbose=true").await;
+    assert_eq!(body["rates"]["breaker"], "closed");
+
+    // Two currencies, two failures: the breaker opens
+    let (status, body) = get_json(&app, "/getCountry?based=japan,germany&rates=true").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["results"][1]["exchangeRateToUsd"].is_null());
+    let calls = provider.calls();
+    assert_eq!(calls, 3);
+
+    let (status, body) = get_json(&app, "/getCountry?based=usa&rates=true").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body["results"][0]["rateError"],
+        "The rate provider is failing, so it is not being asked for now"
+    );
+    let (_, body) = get_json(&app, "/healthz?verbose=true").await;
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["rates"]["breaker"], "open");
+    assert_eq!(body["rates"]["consecutiveFailures"], 2);
+    assert!(body["components"]["rates"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("not asked for another 30s"));
+    assert_eq!(provider.calls(), calls);
+
+    let metrics = get_metrics(&app).await;
+    assert!(metrics.contains("rustincountry_rate_breaker_state{state=\"open\"} 1\n"));
+    assert!(metrics.contains("rustincountry_rate_breaker_state{state=\"closed\"} 0\n"));
+    assert!(metrics.contains("rustincountry_rate_breaker_short_circuited_total 1\n"));
+}
+
+#[tokio::test]
+async fn test_verbose_health_reports_failed_reload() {
+    let document = Arc::new(Mutex::new(Some(remote_document(false))));
+    let (url, _) = data_server(document.clone()).await;
+    let (state, remote, app) = remote_app(&url).await;
+    *document.lock().unwrap() = None;
+    remote::refresh(&state, &remote).await.unwrap_err();
+
+    let (status, body) = get_json(&app, "/healthz?verbose=true").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "degraded");
+    assert_eq!(body["components"]["reload"]["status"], "degraded");
+    assert_eq!(body["lastReload"]["outcome"], "failure");
+    assert!(body["lastReload"]["time"]
+        .as_str()
+        .unwrap()
+        .ends_with(" GMT"));
+}
+
+#[tokio::test]
+async fn test_verbose_health_is_down_without_data() {
+    let app = health_app(AppState::new(Dataset::default()));
+
+    let (status, body) = get_json(&app, "/healthz?verbose=true").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["status"], "down");
+    assert_eq!(body["components"]["dataset"]["status"], "down");
+    // Liveness alone does not look at the data
+    assert_eq!(get_json(&app, "/healthz").await.0, StatusCode::OK);
+}
+
+// The canonical names a successful GET of `uri` lists in its `results`, in order
+async fn listed_countries(app: &Router, uri: &str) -> Vec<String> {
+    let (status, body) = get_json(app, uri).await;
+    assert_eq!(status, StatusCode::OK, "{}: {}", uri, body);
+    body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["country"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_by_calling_code_shared_prefix() {
+    let countries = listed_countries(&create_app(), "/byCallingCode?number=%2B1").await;
+
+    assert!(countries.contains(&String::from("united states")));
+    assert!(countries.contains(&String::from("canada")));
+    assert!(!countries.contains(&String::from("bahamas")));
+    let mut sorted = countries.clone();
+    sorted.sort();
+    assert_eq!(countries, sorted);
+}
+
+#[tokio::test]
+async fn test_by_calling_code_formatted_number() {
+    let app = create_app();
+
+    assert_eq!(
+        listed_countries(&app, "/byCallingCode?number=%2B81-90-1234-5678").await,
+        ["japan"]
+    );
+    // A literal `+` decodes to a space, which is ignored like the `+` itself
+    assert_eq!(
+        listed_countries(&app, "/byCallingCode?number=+81%20(90)%201234.5678").await,
+        ["japan"]
+    );
+    // The longest code wins over the `+1` it starts with
+    assert_eq!(
+        listed_countries(&app, "/byCallingCode?number=%2B1-242-555-0100").await,
+        ["bahamas"]
+    );
+}
+
+#[tokio::test]
+async fn test_by_calling_code_bare_code() {
+    let app = create_app();
+
+    assert_eq!(
+        listed_countries(&app, "/byCallingCode?number=81").await,
+        ["japan"]
+    );
+    assert_eq!(
+        listed_countries(&app, "/byCallingCode?number=%2B44")
+            .await
+            .len(),
+        4
+    );
+    assert!(listed_countries(&app, "/byCallingCode?number=%2B999")
+        .await
+        .is_empty());
+}
+
+#[tokio::test]
+async fn test_by_calling_code_rejects_junk() {
+    for uri in [
+        "/byCallingCode",
+        "/byCallingCode?number=",
+        "/byCallingCode?number=%2B--",
+        "/byCallingCode?number=call%20me",
+        "/byCallingCode?number=%2B81%2B90",
+        "/byCallingCode?number=1234567890123456",
+    ] {
+        let (status, envelope) = get_error(uri).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(envelope.error.code, "INVALID_PARAMETER", "{}", uri);
+        assert_eq!(
+            envelope.error.details.unwrap()["parameter"],
+            "number",
+            "{}",
+            uri
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_by_calling_code_follows_admin_changes() {
+    let app = admin_app();
+    assert!(listed_countries(&app, "/byCallingCode?number=%2B999")
+        .await
+        .is_empty());
+
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "Wakanda",
+            "flag": "🇼🇰",
+            "currencyCode": "WKD",
+            "phoneCode": "+999"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(
+        listed_countries(&app, "/byCallingCode?number=%2B999-1").await,
+        ["wakanda"]
+    );
+
+    let (status, _) = send_json(
+        &app,
+        "DELETE",
+        "/admin/countries/canada",
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let countries = listed_countries(&app, "/byCallingCode?number=%2B1").await;
+    assert!(countries.contains(&String::from("united states")));
+    assert!(!countries.contains(&String::from("canada")));
+}
+
+#[tokio::test]
+async fn test_by_coordinates_tokyo() {
+    let app = create_app();
+
+    let countries = listed_countries(&app, "/byCoordinates?lat=35.68&lng=139.69").await;
+    assert_eq!(countries.first().map(String::as_str), Some("japan"));
+
+    let (_, body) = get_json(&app, "/byCoordinates?lat=35.68&lng=139.69&lang=ja").await;
+    assert_eq!(body["results"][0]["localizedName"], "日本");
+    assert_eq!(body["results"][0]["currencyCode"], "JPY");
+}
+
+#[tokio::test]
+async fn test_by_coordinates_smallest_box_first() {
+    let app = create_app();
+
+    // Vatican City and Lesotho lie inside the boxes of the countries around them
+    assert_eq!(
+        listed_countries(&app, "/byCoordinates?lat=41.9029&lng=12.4534").await,
+        ["vatican city", "italy"]
+    );
+    assert_eq!(
+        listed_countries(&app, "/byCoordinates?lat=-29.31&lng=27.48").await,
+        ["lesotho", "south africa"]
+    );
+}
+
+#[tokio::test]
+async fn test_by_coordinates_ocean() {
+    let app = create_app();
+
+    assert!(listed_countries(&app, "/byCoordinates?lat=-30&lng=-120")
+        .await
+        .is_empty());
+    // Russia's box crosses the antimeridian, from the Baltic to the Bering Strait
+    assert!(listed_countries(&app, "/byCoordinates?lat=66&lng=-170")
+        .await
+        .contains(&String::from("russia")));
+}
+
+#[tokio::test]
+async fn test_by_coordinates_rejects_invalid_points() {
+    for (uri, parameter) in [
+        ("/byCoordinates?lng=139.69", "lat"),
+        ("/byCoordinates?lat=&lng=139.69", "lat"),
+        ("/byCoordinates?lat=north&lng=139.69", "lat"),
+        ("/byCoordinates?lat=90.5&lng=139.69", "lat"),
+        ("/byCoordinates?lat=-91&lng=139.69", "lat"),
+        ("/byCoordinates?lat=NaN&lng=139.69", "lat"),
+        ("/byCoordinates?lat=35.68", "lng"),
+        ("/byCoordinates?lat=35.68&lng=180.01", "lng"),
+        ("/byCoordinates?lat=35.68&lng=-200", "lng"),
+        ("/byCoordinates?lat=35.68&lng=inf", "lng"),
+    ] {
+        let (status, envelope) = get_error(uri).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(envelope.error.code, "INVALID_PARAMETER", "{}", uri);
+        assert_eq!(
+            envelope.error.details.unwrap()["parameter"],
+            parameter,
+            "{}",
+            uri
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_by_coordinates_uses_admin_boxes() {
+    let app = admin_app();
+    assert!(listed_countries(&app, "/byCoordinates?lat=-30&lng=-120")
+        .await
+        .is_empty());
+
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "Wakanda",
+            "flag": "🇼🇰",
+            "currencyCode": "WKD",
+            "phoneCode": "+999",
+            "boundingBox": {"minLat": -31, "minLng": -121, "maxLat": -29, "maxLng": -119}
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(
+        listed_countries(&app, "/byCoordinates?lat=-30&lng=-120").await,
+        ["wakanda"]
+    );
+
+    let (status, body) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "Atlantis",
+            "flag": "🇦🇹",
+            "currencyCode": "EUR",
+            "phoneCode": "+998",
+            "boundingBox": {"minLat": 10, "minLng": 0, "maxLat": -10, "maxLng": 5}
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", body);
+    assert_eq!(body["error"]["details"]["field"], "boundingBox");
+}
+
+// Every byte of `value` percent-encoded, for query parameters outside ASCII
+fn percent_encoded(value: &str) -> String {
+    value.bytes().map(|byte| format!("%{:02X}", byte)).collect()
+}
+
+async fn get_by_symbol(app: &Router, symbol: &str) -> serde_json::Value {
+    let uri = format!("/byCurrency?symbol={}", percent_encoded(symbol));
+    let (status, body) = get_json(app, &uri).await;
+    assert_eq!(status, StatusCode::OK, "{}: {}", uri, body);
+    body
+}
+
+fn symbol_currencies(body: &serde_json::Value) -> Vec<&str> {
+    body["currencies"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|currency| currency["currencyCode"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_by_currency_code() {
+    let (status, body) = get_json(&create_app(), "/byCurrency?code=JPY").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["results"].as_array().unwrap().len(), 1);
+    assert_eq!(body["results"][0]["country"], "japan");
+    assert!(body.get("ambiguous").is_none());
+}
+
+#[tokio::test]
+async fn test_by_currency_yen_symbol_is_ambiguous() {
+    let body = get_by_symbol(&create_app(), "¥").await;
+
+    assert_eq!(body["symbol"], "¥");
+    assert_eq!(body["ambiguous"], true);
+    assert_eq!(symbol_currencies(&body), ["CNY", "JPY"]);
+    assert_eq!(body["currencies"][0]["countries"][0]["country"], "china");
+    assert_eq!(body["currencies"][1]["countries"][0]["country"], "japan");
+}
+
+#[tokio::test]
+async fn test_by_currency_dollar_symbol_is_ambiguous() {
+    let body = get_by_symbol(&create_app(), "$").await;
+
+    assert_eq!(body["ambiguous"], true);
+    let currencies = symbol_currencies(&body);
+    for code in ["AUD", "CAD", "MXN", "SGD", "USD"] {
+        assert!(currencies.contains(&code), "{}", code);
+    }
+    let usd = body["currencies"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|currency| currency["currencyCode"] == "USD")
+        .unwrap();
+    assert!(usd["countries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|country| country["country"] == "united states"));
+
+    // A prefixed dollar names one currency
+    let body = get_by_symbol(&create_app(), "us$").await;
+    assert_eq!(body["ambiguous"], false);
+    assert_eq!(symbol_currencies(&body), ["USD"]);
+}
+
+#[tokio::test]
+async fn test_by_currency_euro_symbol_is_unambiguous() {
+    let body = get_by_symbol(&create_app(), "€").await;
+
+    assert_eq!(body["ambiguous"], false);
+    assert_eq!(symbol_currencies(&body), ["EUR"]);
+    let countries = body["currencies"][0]["countries"].as_array().unwrap();
+    assert!(countries
+        .iter()
+        .any(|country| country["country"] == "germany"));
+}
+
+#[tokio::test]
+async fn test_by_currency_unknown_symbol() {
+    let body = get_by_symbol(&create_app(), "¤").await;
+
+    assert_eq!(body["ambiguous"], false);
+    assert!(symbol_currencies(&body).is_empty());
+}
+
+#[tokio::test]
+async fn test_by_currency_symbol_counts_only_served_currencies() {
+    let app = admin_app();
+    let (status, _) = send_json(
+        &app,
+        "DELETE",
+        "/admin/countries/china",
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let body = get_by_symbol(&app, "¥").await;
+    assert_eq!(body["ambiguous"], false);
+    assert_eq!(symbol_currencies(&body), ["JPY"]);
+}
+
+#[tokio::test]
+async fn test_by_currency_rejects_invalid_queries() {
+    for (uri, parameter) in [
+        ("/byCurrency", "code"),
+        ("/byCurrency?lang=ja", "code"),
+        ("/byCurrency?code=yen", "code"),
+        ("/byCurrency?code=JPY&symbol=%C2%A5", "symbol"),
+    ] {
+        let (status, envelope) = get_error(uri).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(envelope.error.code, "INVALID_PARAMETER", "{}", uri);
+        assert_eq!(
+            envelope.error.details.unwrap()["parameter"],
+            parameter,
+            "{}",
+            uri
+        );
+    }
+}
+
+async fn get_by_based(app: &Router, based: &str) -> serde_json::Value {
+    let uri = format!("/getCountry?based={}", percent_encoded(based));
+    let (status, body) = get_json(app, &uri).await;
+    assert_eq!(status, StatusCode::OK, "{}: {}", based, body);
+    body
+}
+
+#[tokio::test]
+async fn test_get_country_by_flag() {
+    let body = get_by_based(&create_app(), "🇯🇵").await;
+
+    assert_eq!(body["results"].as_array().unwrap().len(), 1);
+    let result = &body["results"][0];
+    assert_eq!(result["country"], "🇯🇵");
+    assert_eq!(result["localizedName"], "Japan");
+    assert_eq!(result["currencyCode"], "JPY");
+    assert_eq!(result["resolvedFrom"], "flag");
+    assert!(body.get("unmatched").is_none());
+}
+
+#[tokio::test]
+async fn test_get_country_by_flags_and_names() {
+    let body = get_by_based(&create_app(), "🇩🇪, japan,atlantis,🇧🇷").await;
+
+    let countries: Vec<_> = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["country"].as_str().unwrap())
+        .collect();
+    assert_eq!(countries, ["🇩🇪", "japan", "🇧🇷"]);
+    assert_eq!(body["results"][1].get("resolvedFrom"), None);
+    assert_eq!(body["results"][2]["currencyCode"], "BRL");
+
+    // Flags also mix with addresses
+    let body = get_by_based(&geoip_app(), "2001:200::1,🇩🇪").await;
+    assert_eq!(body["results"][0]["resolvedFrom"], "ip");
+    assert_eq!(body["results"][1]["resolvedFrom"], "flag");
+    assert_eq!(body["results"][1]["currencyCode"], "EUR");
+}
+
+#[tokio::test]
+async fn test_get_country_by_flag_with_variation_selector() {
+    let body = get_by_based(&create_app(), "🇯🇵\u{FE0F}").await;
+
+    assert_eq!(body["results"][0]["country"], "🇯🇵\u{FE0F}");
+    assert_eq!(body["results"][0]["currencyCode"], "JPY");
+}
+
+#[tokio::test]
+async fn test_get_country_lists_flags_it_cannot_resolve() {
+    let app = admin_app();
+    let (status, _) = send_json(
+        &app,
+        "DELETE",
+        "/admin/countries/japan",
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // A lone regional indicator, three of them, and a flag for a removed country
+    let body = get_by_based(&app, "🇯,germany,🇯🇵🇯,🇯🇵").await;
+
+    assert_eq!(body["results"].as_array().unwrap().len(), 1);
+    assert_eq!(body["results"][0]["country"], "germany");
+    assert_eq!(
+        body["unmatched"],
+        serde_json::json!([
+            {"input": "🇯", "reason": "Not a flag: a flag is two regional indicator symbols"},
+            {"input": "🇯🇵🇯", "reason": "Not a flag: a flag is two regional indicator symbols"},
+            {"input": "🇯🇵", "reason": "No served country has the code JP"}
+        ])
+    );
+}
+
+#[tokio::test]
+async fn test_get_country_reports_name_status() {
+    let body = get_by_based(&create_app(), "Holland,burma,czech republic,japan,usa").await;
+
+    let results = body["results"].as_array().unwrap();
+    assert_eq!(results.len(), 5);
+    assert_eq!(results[0]["country"], "Holland");
+    assert_eq!(results[0]["localizedName"], "Netherlands");
+    assert_eq!(results[0]["currencyCode"], "EUR");
+    assert_eq!(results[0]["nameStatus"], "colloquial");
+    assert_eq!(results[1]["localizedName"], "Myanmar");
+    assert_eq!(results[1]["nameStatus"], "historical");
+    assert_eq!(results[2]["currencyCode"], "CZK");
+    assert_eq!(results[2]["nameStatus"], "historical");
+    // Canonical names and current aliases alike
+    assert_eq!(results[3]["nameStatus"], "current");
+    assert_eq!(results[4]["nameStatus"], "current");
+}
+
+#[tokio::test]
+async fn test_name_status_of_flags_and_listings_is_current() {
+    let body = get_by_based(&create_app(), "🇳🇱").await;
+    assert_eq!(body["results"][0]["nameStatus"], "current");
+
+    let body = get_by_based(&create_app(), "all").await;
+    let swaziland = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|result| result["country"] == "swaziland")
+        .unwrap();
+    assert_eq!(swaziland["nameStatus"], "historical");
+}
+
+#[tokio::test]
+async fn test_admin_aliases_take_a_kind() {
+    let app = admin_app();
+    let (status, body) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "atlantis",
+            "aliases": ["poseidonis", {"name": "the lost city", "kind": "historical"}],
+            "flag": "🇦🇹",
+            "currencyCode": "ATL",
+            "phoneCode": "+999"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    // Current aliases stay plain strings
+    assert_eq!(
+        body["aliases"],
+        serde_json::json!(["poseidonis", {"name": "the lost city", "kind": "historical"}])
+    );
+
+    let body = get_by_based(&app, "poseidonis,the lost city").await;
+    assert_eq!(body["results"][0]["nameStatus"], "current");
+    assert_eq!(body["results"][1]["nameStatus"], "historical");
+
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "lemuria",
+            "aliases": [{"name": "mu", "kind": "legendary"}],
+            "flag": "🇱🇲",
+            "currencyCode": "LMU",
+            "phoneCode": "+998"
+        }),
+    )
+    .await;
+    assert!(status.is_client_error());
+}
+
+#[tokio::test]
+async fn test_by_tld() {
+    let app = create_app();
+
+    assert_eq!(listed_countries(&app, "/byTld?tld=.jp").await, ["japan"]);
+    assert_eq!(listed_countries(&app, "/byTld?tld=jp").await, ["japan"]);
+    assert_eq!(listed_countries(&app, "/byTld?tld=.JP").await, ["japan"]);
+    assert_eq!(
+        listed_countries(&app, "/byTld?tld=%20.De%20").await,
+        ["germany"]
+    );
+
+    let (_, body) = get_json(&app, "/byTld?tld=.jp&lang=ja").await;
+    assert_eq!(body["results"][0]["localizedName"], "日本");
+    assert_eq!(body["results"][0]["currencyCode"], "JPY");
+}
+
+#[tokio::test]
+async fn test_by_tld_uk_is_the_united_kingdom() {
+    let app = create_app();
+
+    assert_eq!(
+        listed_countries(&app, "/byTld?tld=.uk").await,
+        ["united kingdom"]
+    );
+    assert_eq!(
+        listed_countries(&app, "/byTld?tld=UK").await,
+        ["united kingdom"]
+    );
+    // The alpha-2 code is not the TLD
+    assert!(listed_countries(&app, "/byTld?tld=.gb").await.is_empty());
+}
+
+#[tokio::test]
+async fn test_by_tld_generic_and_unknown_are_empty() {
+    let app = create_app();
+
+    for uri in [
+        "/byTld?tld=.com",
+        "/byTld?tld=org",
+        "/byTld?tld=.zz",
+        "/byTld?tld=.co.jp",
+    ] {
+        assert!(listed_countries(&app, uri).await.is_empty(), "{}", uri);
+    }
+
+    for uri in ["/byTld", "/byTld?tld=", "/byTld?tld=.", "/byTld?tld=%20"] {
+        let (status, envelope) = get_error(uri).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(envelope.error.code, "INVALID_PARAMETER", "{}", uri);
+        assert_eq!(
+            envelope.error.details.unwrap()["parameter"],
+            "tld",
+            "{}",
+            uri
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_by_tld_follows_admin_changes() {
+    let app = admin_app();
+    assert!(listed_countries(&app, "/byTld?tld=.atl").await.is_empty());
+
+    let (status, body) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "atlantis",
+            "flag": "🇦🇹",
+            "currencyCode": "ATL",
+            "phoneCode": "+999",
+            "tld": ".ATL"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    assert_eq!(body["tld"], ".atl");
+    assert_eq!(listed_countries(&app, "/byTld?tld=atl").await, ["atlantis"]);
+
+    let (status, _) = send_json(
+        &app,
+        "DELETE",
+        "/admin/countries/japan",
+        serde_json::Value::Null,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(listed_countries(&app, "/byTld?tld=.jp").await.is_empty());
+
+    let (status, body) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "lemuria",
+            "flag": "🇱🇲",
+            "currencyCode": "LMU",
+            "phoneCode": "+998",
+            "tld": ".lemuria.example"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["details"]["field"], "tld");
+}
+
+#[tokio::test]
+async fn test_by_language_french_is_official_in_several_countries() {
+    let app = create_app();
+
+    let french = listed_countries(&app, "/byLanguage?name=french").await;
+    for country in ["france", "canada", "switzerland", "belgium"] {
+        assert!(french.contains(&country.to_string()), "{}", country);
+    }
+    assert!(!french.contains(&String::from("japan")));
+    let mut sorted = french.clone();
+    sorted.sort();
+    assert_eq!(french, sorted);
+
+    assert_eq!(
+        listed_countries(&app, "/byLanguage?name=FRENCH").await,
+        french
+    );
+    assert_eq!(listed_countries(&app, "/byLanguage?name=fr").await, french);
+    assert_eq!(
+        listed_countries(&app, "/byLanguage?name=%20French%20").await,
+        french
+    );
+
+    let (_, body) = get_json(&app, "/byLanguage?name=japanese&lang=ja").await;
+    assert_eq!(body["results"][0]["country"], "japan");
+    assert_eq!(body["results"][0]["localizedName"], "日本");
+}
+
+#[tokio::test]
+async fn test_by_language_native_names() {
+    let app = create_app();
+
+    let french = listed_countries(&app, "/byLanguage?name=french").await;
+    assert_eq!(
+        listed_countries(&app, "/byLanguage?name=fran%C3%A7ais").await,
+        french
+    );
+    assert_eq!(
+        listed_countries(&app, "/byLanguage?name=Fran%C3%A7ais").await,
+        french
+    );
+    let german = listed_countries(&app, "/byLanguage?name=german").await;
+    assert_eq!(
+        listed_countries(&app, "/byLanguage?name=deutsch").await,
+        german
+    );
+}
+
+#[tokio::test]
+async fn test_by_language_unknown_is_empty() {
+    let app = create_app();
+
+    for uri in [
+        "/byLanguage?name=klingon",
+        "/byLanguage?name=xx",
+        "/byLanguage?name=zzz",
+        "/byLanguage?name=english%20please",
+    ] {
+        assert!(listed_countries(&app, uri).await.is_empty(), "{}", uri);
+    }
+
+    for uri in ["/byLanguage", "/byLanguage?name=", "/byLanguage?name=%20"] {
+        let (status, envelope) = get_error(uri).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(envelope.error.code, "INVALID_PARAMETER", "{}", uri);
+        assert_eq!(
+            envelope.error.details.unwrap()["parameter"],
+            "name",
+            "{}",
+            uri
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_by_language_list() {
+    let app = create_app();
+
+    let (status, body) = get_json(&app, "/byLanguage?list=true").await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let languages = body["languages"].as_array().unwrap();
+    let codes: Vec<&str> = languages
+        .iter()
+        .map(|language| language["code"].as_str().unwrap())
+        .collect();
+    let mut sorted = codes.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(codes, sorted);
+
+    let french = languages
+        .iter()
+        .find(|language| language["code"] == "fr")
+        .unwrap();
+    assert_eq!(french["name"], "French");
+    assert_eq!(
+        french["countries"],
+        listed_countries(&app, "/byLanguage?name=french")
+            .await
+            .len()
+    );
+    let japanese = languages
+        .iter()
+        .find(|language| language["code"] == "ja")
+        .unwrap();
+    assert_eq!(japanese["countries"], 1);
+
+    // `name` is ignored in list mode
+    let (_, named) = get_json(&app, "/byLanguage?list=true&name=french").await;
+    assert_eq!(named, body);
+}
+
+#[tokio::test]
+async fn test_by_language_follows_admin_changes() {
+    let app = admin_app();
+    assert!(listed_countries(&app, "/byLanguage?name=atl")
+        .await
+        .is_empty());
+
+    let (status, body) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "atlantis",
+            "flag": "🇦🇹",
+            "currencyCode": "ATL",
+            "phoneCode": "+999",
+            "languages": ["FR", "atl"]
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    assert_eq!(body["languages"], serde_json::json!(["fr", "atl"]));
+    assert!(listed_countries(&app, "/byLanguage?name=french")
+        .await
+        .contains(&String::from("atlantis")));
+    assert_eq!(
+        listed_countries(&app, "/byLanguage?name=ATL").await,
+        ["atlantis"]
+    );
+
+    let (_, list) = get_json(&app, "/byLanguage?list=true").await;
+    let atlantean = list["languages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|language| language["code"] == "atl")
+        .unwrap();
+    assert_eq!(atlantean["countries"], 1);
+    assert!(atlantean.get("name").is_none());
+
+    let (status, body) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "lemuria",
+            "flag": "🇱🇲",
+            "currencyCode": "LMU",
+            "phoneCode": "+998",
+            "languages": ["lemurian"]
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["details"]["field"], "languages");
+}
+
+#[tokio::test]
+async fn test_by_timezone_exact_zone() {
+    let app = create_app();
+
+    assert_eq!(
+        listed_countries(&app, "/byTimezone?zone=Europe/Paris").await,
+        ["france"]
+    );
+    assert_eq!(
+        listed_countries(&app, "/byTimezone?zone=europe%2Fparis").await,
+        ["france"]
+    );
+    assert_eq!(
+        listed_countries(&app, "/byTimezone?zone=America/Chicago").await,
+        ["united states"]
+    );
+    // A real zone no country lists as its own
+    assert!(listed_countries(&app, "/byTimezone?zone=Etc/UTC")
+        .await
+        .is_empty());
+
+    let (_, body) = get_json(&app, "/byTimezone?zone=Asia/Tokyo&lang=ja").await;
+    assert_eq!(body["results"][0]["country"], "japan");
+    assert_eq!(body["results"][0]["localizedName"], "日本");
+}
+
+#[tokio::test]
+async fn test_by_timezone_offset() {
+    let app = create_app();
+
+    // Neither keeps daylight saving time, so this holds whenever the test runs
+    let nine = listed_countries(&app, "/byTimezone?offset=%2B09:00").await;
+    for country in ["japan", "south korea"] {
+        assert!(nine.contains(&country.to_string()), "{}", country);
+    }
+    assert!(!nine.contains(&String::from("china")));
+    assert_eq!(
+        listed_countries(&app, "/byTimezone?offset=09:00").await,
+        nine
+    );
+    assert_eq!(
+        listed_countries(&app, "/byTimezone?offset=+09:00").await,
+        nine
+    );
+    assert_eq!(
+        listed_countries(&app, "/byTimezone?offset=%2B0900").await,
+        nine
+    );
+
+    let eight = listed_countries(&app, "/byTimezone?offset=%2B08").await;
+    assert!(eight.contains(&String::from("china")));
+    assert!(!eight.contains(&String::from("japan")));
+}
+
+#[tokio::test]
+async fn test_by_timezone_errors() {
+    for (uri, parameter) in [
+        ("/byTimezone", "zone"),
+        ("/byTimezone?zone=Mars/Olympus_Mons", "zone"),
+        ("/byTimezone?zone=", "zone"),
+        ("/byTimezone?offset=%2B9", "offset"),
+        ("/byTimezone?offset=%2B09:60", "offset"),
+        ("/byTimezone?offset=%2B15:00", "offset"),
+        ("/byTimezone?offset=JST", "offset"),
+        ("/byTimezone?zone=Asia/Tokyo&offset=%2B09:00", "offset"),
+    ] {
+        let (status, envelope) = get_error(uri).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+        assert_eq!(envelope.error.code, "INVALID_PARAMETER", "{}", uri);
+        assert_eq!(
+            envelope.error.details.unwrap()["parameter"],
+            parameter,
+            "{}",
+            uri
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_by_timezone_follows_admin_changes() {
+    let app = admin_app();
+
+    let (status, body) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "atlantis",
+            "flag": "🇦🇹",
+            "currencyCode": "ATL",
+            "phoneCode": "+999",
+            "timezones": ["atlantic/azores", "Europe/Paris"]
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    assert_eq!(
+        body["timezones"],
+        serde_json::json!(["Atlantic/Azores", "Europe/Paris"])
+    );
+    assert_eq!(
+        listed_countries(&app, "/byTimezone?zone=Europe/Paris").await,
+        ["atlantis", "france"]
+    );
+
+    let (status, body) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "lemuria",
+            "flag": "🇱🇲",
+            "currencyCode": "LMU",
+            "phoneCode": "+998",
+            "timezones": ["Pacific/Lemuria"]
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["details"]["field"], "timezones");
+}
+
+async fn resolve(app: &Router, inputs: serde_json::Value) -> Vec<serde_json::Value> {
+    let (status, body) = send_json(
+        app,
+        "POST",
+        "/resolve",
+        serde_json::json!({ "inputs": inputs }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body["results"].as_array().unwrap().clone()
+}
+
+// The canonical names of one `/resolve` entry's results
+fn resolved_countries(resolved: &serde_json::Value) -> Vec<&str> {
+    resolved["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["country"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_resolve_one_input_of_each_kind() {
+    let inputs = serde_json::json!([
+        "JP",
+        "united kingdom",
+        ".fr",
+        "+82",
+        "🇩🇪",
+        "8.8.8.8",
+        "no such place",
+    ]);
+    let resolved = resolve(&geoip_app(), inputs.clone()).await;
+
+    assert_eq!(resolved.len(), 7);
+    let expected = [
+        ("alphaCode", vec!["japan"]),
+        ("name", vec!["united kingdom"]),
+        ("tld", vec!["france"]),
+        ("callingCode", vec!["south korea"]),
+        ("flag", vec!["germany"]),
+        ("ip", vec!["united states"]),
+        ("unknown", vec![]),
+    ];
+    for (position, (kind, countries)) in expected.into_iter().enumerate() {
+        let entry = &resolved[position];
+        assert_eq!(entry["input"], inputs[position]);
+        assert_eq!(entry["kind"], kind, "{}", entry);
+        assert_eq!(resolved_countries(entry), countries, "{}", entry);
+        assert!(entry.get("reason").is_none(), "{}", entry);
+    }
+    assert_eq!(resolved[0]["results"][0]["currencyCode"], "JPY");
+    assert_eq!(resolved[4]["results"][0]["flag"], "🇩🇪");
+}
+
+#[tokio::test]
+async fn test_resolve_lists_ambiguous_matches_fully() {
+    let resolved = resolve(&create_app(), serde_json::json!(["+1", ".uk", "$"])).await;
+
+    assert_eq!(resolved[0]["kind"], "callingCode");
+    assert_eq!(
+        resolved_countries(&resolved[0]),
+        [
+            "canada",
+            "united states",
+            "united states minor outlying islands"
+        ]
+    );
+    assert_eq!(resolved_countries(&resolved[1]), ["united kingdom"]);
+    assert_eq!(resolved[2]["kind"], "unknown");
+    assert_eq!(resolved[2]["results"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn test_resolve_short_inputs_as_codes_or_names() {
+    let resolved = resolve(
+        &create_app(),
+        serde_json::json!(["JPN", "uk", "jp", "UAE", "holland", "XX"]),
+    )
+    .await;
+
+    let kinds: Vec<_> = resolved.iter().map(|entry| entry["kind"].clone()).collect();
+    assert_eq!(
+        kinds,
+        ["alphaCode", "name", "alphaCode", "name", "name", "unknown"]
+    );
+    assert_eq!(resolved_countries(&resolved[0]), ["japan"]);
+    assert_eq!(resolved_countries(&resolved[1]), ["united kingdom"]);
+    assert_eq!(resolved_countries(&resolved[2]), ["japan"]);
+    assert_eq!(resolved_countries(&resolved[3]), ["united arab emirates"]);
+    // Results are under canonical names, with the status of the name sent
+    assert_eq!(resolved_countries(&resolved[4]), ["netherlands"]);
+    assert_eq!(resolved[4]["results"][0]["nameStatus"], "colloquial");
+}
+
+#[tokio::test]
+async fn test_resolve_explains_unresolvable_addresses_and_codes() {
+    let resolved = resolve(
+        &create_app(),
+        serde_json::json!(["8.8.8.8", "ip:nonsense", "+81+90"]),
+    )
+    .await;
+
+    assert_eq!(resolved[0]["kind"], "ip");
+    assert_eq!(resolved[0]["results"], serde_json::json!([]));
+    assert_eq!(resolved[0]["reason"], "No GeoIP database is configured");
+    assert_eq!(resolved[1]["kind"], "ip");
+    assert!(resolved[1]["reason"].is_string());
+    assert_eq!(resolved[2]["kind"], "callingCode");
+    assert!(resolved[2]["reason"].is_string());
+}
+
+#[tokio::test]
+async fn test_resolve_rejects_empty_and_malformed_batches() {
+    let app = create_app();
+
+    let (status, body) =
+        send_json(&app, "POST", "/resolve", serde_json::json!({"inputs": []})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "EMPTY_QUERY");
+
+    let inputs = vec!["japan"; 301];
+    let (status, body) = send_json(
+        &app,
+        "POST",
+        "/resolve",
+        serde_json::json!({ "inputs": inputs }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "TOO_MANY_COUNTRIES");
+
+    let (status, body) = send_json(
+        &app,
+        "POST",
+        "/resolve",
+        serde_json::json!({"inputs": ["japan", "france\u{0}"]}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "CONTROL_CHARACTER");
+    assert_eq!(body["error"]["details"]["entry"], "france\u{0}");
+
+    let (status, body) = send_json(
+        &app,
+        "POST",
+        "/resolve",
+        serde_json::json!({"input": ["japan"]}),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["code"], "INVALID_BODY");
+}
+
+async fn get_stats(app: &Router) -> StatsResponse {
+    let (status, _, body) = get_raw(app, "/stats").await;
+    assert_eq!(status, StatusCode::OK);
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_stats_counts_every_country_once() {
+    let stats = get_stats(&create_app()).await;
+
+    assert_eq!(stats.countries, builtin::BUILTIN_COUNTRIES.len());
+    assert_eq!(stats.continents.values().sum::<usize>(), stats.countries);
+    assert_eq!(stats.currencies.values().sum::<usize>(), stats.countries);
+    // Every built-in country has a continent
+    assert!(!stats.continents.contains_key("unknown"));
+    assert_eq!(stats.continents["antarctica"], 4);
+    assert!(stats.currencies["EUR"] > 20);
+    assert!(stats.aliases > 0);
+}
+
+#[tokio::test]
+async fn test_stats_follow_admin_edits() {
+    let app = admin_app();
+    let before = get_stats(&app).await;
+
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "Atlantis",
+            "aliases": ["Sunken Kingdom", "Poseidonis"],
+            "flag": "🇦🇽",
+            "currencyCode": "ATL",
+            "phoneCode": "+998",
+            "continent": "europe"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let after = get_stats(&app).await;
+    assert_eq!(after.countries, before.countries + 1);
+    assert_eq!(after.aliases, before.aliases + 2);
+    assert_eq!(after.continents["europe"], before.continents["europe"] + 1);
+    assert_eq!(after.currencies["ATL"], 1);
+    assert_eq!(after.continents.values().sum::<usize>(), after.countries);
+
+    // A country added without a continent is counted, as unknown
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "Lemuria",
+            "flag": "🇦🇽",
+            "currencyCode": "ATL",
+            "phoneCode": "+997"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let after = get_stats(&app).await;
+    assert_eq!(after.continents["unknown"], 1);
+    assert_eq!(after.currencies["ATL"], 2);
+    assert_eq!(after.continents.values().sum::<usize>(), after.countries);
+}
+
+async fn validate(app: &Router, uri: &str) -> ValidateResponse<'static> {
+    let (status, _, body) = get_raw(app, uri).await;
+    assert_eq!(status, StatusCode::OK);
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_validate_mixed_valid_invalid() {
+    let app = create_app();
+    let validated = validate(
+        &app,
+        "/validate?based=japan,%20narnia%20,UK,%F0%9F%87%AB%F0%9F%87%B7,,korea",
+    )
+    .await;
+
+    let inputs: Vec<_> = validated.results.iter().map(|v| &*v.input).collect();
+    assert_eq!(inputs, ["japan", "narnia", "UK", "🇫🇷", "korea"]);
+    let valid: Vec<_> = validated.results.iter().map(|v| v.valid).collect();
+    assert_eq!(valid, [true, false, true, true, true]);
+    assert_eq!(
+        validated.results[0].canonical_name.as_deref(),
+        Some("Japan")
+    );
+    assert_eq!(validated.results[1].canonical_name, None);
+    assert_eq!(
+        validated.results[2].canonical_name.as_deref(),
+        Some("United Kingdom")
+    );
+
+    // Only the verdict is sent, not the country
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/validate?based=japan,narnia")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(
+        std::str::from_utf8(&body).unwrap(),
+        r#"{"results":[{"input":"japan","valid":true,"canonicalName":"Japan"},{"input":"narnia","valid":false}]}"#
+    );
+}
+
+#[tokio::test]
+async fn test_validate_agrees_with_get_country() {
+    let app = create_app();
+    let based = "japan,unknown,korea,czech%20republic,%F0%9F%87%BD%F0%9F%87%BD,holland";
+    let validated = validate(&app, &format!("/validate?based={}", based)).await;
+    let found = get_countries(&app, based).await;
+
+    let valid: Vec<_> = validated
+        .results
+        .iter()
+        .filter(|v| v.valid)
+        .map(|v| v.canonical_name.as_deref().unwrap())
+        .collect();
+    let answered: Vec<_> = found
+        .results
+        .iter()
+        .map(|info| &*info.localized_name)
+        .collect();
+    assert_eq!(valid, answered);
+}
+
+#[tokio::test]
+async fn test_validate_localizes_and_limits_like_get_country() {
+    let app = create_app();
+    let validated = validate(&app, "/validate?based=japan&lang=ja").await;
+    assert_eq!(validated.results[0].canonical_name.as_deref(), Some("日本"));
+
+    let (status, envelope) = get_error("/validate?based=%20,%20").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(envelope.error.code, "EMPTY_QUERY");
+}
+
+#[tokio::test]
+async fn test_flag_svg() {
+    let app = create_app();
+
+    for uri in ["/flag/japan.svg", "/flag/Japan.SVG"] {
+        let (status, headers, body) = get_raw(&app, uri).await;
+        assert_eq!(status, StatusCode::OK, "{}", uri);
+        assert_eq!(headers["content-type"], "image/svg+xml");
+        assert!(headers["cache-control"]
+            .to_str()
+            .unwrap()
+            .contains("max-age="));
+        assert!(std::str::from_utf8(&body).unwrap().starts_with("<svg"));
+    }
+
+    let (status, _, _) = get_raw(&app, "/flag/united%20kingdom.svg").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_flag_of_unknown_country_is_not_found() {
+    let app = create_app();
+
+    let (status, _, body) = get_raw(&app, "/flag/narnia.svg").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let envelope: ErrorEnvelope = serde_json::from_slice(&body).unwrap();
+    assert_eq!(envelope.error.code, "COUNTRY_NOT_FOUND");
+
+    let (status, _, _) = get_raw(&app, "/flag/japan.gif").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_flag_of_custom_country_without_image() {
+    let app = admin_app();
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "Wakanda",
+            "flag": "🇼🇰",
+            "currencyCode": "WKD",
+            "phoneCode": "+999"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, _, body) = get_raw(&app, "/flag/wakanda.svg").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let envelope: ErrorEnvelope = serde_json::from_slice(&body).unwrap();
+    assert_eq!(envelope.error.code, "FLAG_NOT_FOUND");
+}
+
+#[cfg(feature = "flag-png")]
+#[tokio::test]
+async fn test_flag_png_sizes() {
+    let app = create_app();
+
+    for (uri, width) in [
+        ("/flag/japan.png", 64),
+        ("/flag/japan.png?size=32", 32),
+        ("/flag/france.png?size=200", 200),
+    ] {
+        let (status, headers, body) = get_raw(&app, uri).await;
+        assert_eq!(status, StatusCode::OK, "{}", uri);
+        assert_eq!(headers["content-type"], "image/png");
+        assert_eq!(&body[1..4], b"PNG");
+        // IHDR: width then height, big-endian, after the signature and chunk header
+        let dimension =
+            |offset: usize| u32::from_be_bytes(body[offset..offset + 4].try_into().unwrap());
+        assert_eq!(dimension(16), width, "{}", uri);
+        // The vendored flags are square
+        assert_eq!(dimension(20), width, "{}", uri);
+    }
+
+    for size in ["0", "8", "4096"] {
+        let (status, _, _) = get_raw(&app, &format!("/flag/japan.png?size={}", size)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", size);
+    }
+}
+
+#[cfg(not(feature = "flag-png"))]
+#[tokio::test]
+async fn test_flag_png_needs_the_feature() {
+    let (status, _, _) = get_raw(&create_app(), "/flag/japan.png").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_codes_list_every_country_once() {
+    let (status, _, body) = get_raw(&create_app(), "/codes").await;
+    assert_eq!(status, StatusCode::OK);
+    let table: CodesResponse = serde_json::from_slice(&body).unwrap();
+
+    let mut countries: Vec<_> = table.codes.iter().map(|entry| &*entry.country).collect();
+    countries.sort_unstable();
+    let mut expected: Vec<_> = builtin::BUILTIN_COUNTRIES
+        .iter()
+        .map(|country| country.name)
+        .collect();
+    expected.sort_unstable();
+    assert_eq!(countries, expected);
+
+    let alpha2: Vec<_> = table.codes.iter().map(|entry| entry.alpha2).collect();
+    assert!(
+        alpha2.windows(2).all(|pair| pair[0] < pair[1]),
+        "sorted and unique"
+    );
+    let mut alpha3: Vec<_> = table
+        .codes
+        .iter()
+        .map(|entry| entry.alpha3.clone().unwrap())
+        .collect();
+    alpha3.sort_unstable();
+    alpha3.dedup();
+    assert_eq!(alpha3.len(), table.codes.len());
+    let mut numeric: Vec<_> = table
+        .codes
+        .iter()
+        .map(|entry| entry.numeric.clone().unwrap())
+        .collect();
+    numeric.sort_unstable();
+    numeric.dedup();
+    assert_eq!(numeric.len(), table.codes.len());
+
+    let japan = table
+        .codes
+        .iter()
+        .find(|entry| entry.country == "japan")
+        .unwrap();
+    assert_eq!(japan.alpha2, "JP");
+    assert_eq!(japan.alpha3.as_deref(), Some("JPN"));
+    assert_eq!(japan.numeric.as_deref(), Some("392"));
+}
+
+#[tokio::test]
+async fn test_codes_csv() {
+    let (status, headers, body) = get_raw(&create_app(), "/codes?format=csv").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["content-type"], "text/csv; charset=utf-8");
+
+    let csv = std::str::from_utf8(&body).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("country,alpha2,alpha3,numeric"));
+    assert_eq!(lines.next(), Some("andorra,AD,AND,020"));
+    assert_eq!(lines.count(), builtin::BUILTIN_COUNTRIES.len() - 1);
+
+    let (status, _, _) = get_raw(&create_app(), "/codes?format=xml").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_codes_of_custom_countries() {
+    let app = admin_app();
+    let (status, _) = send_json(
+        &app,
+        "POST",
+        "/admin/countries",
+        serde_json::json!({
+            "name": "Wakanda",
+            "flag": "🇼🇰",
+            "currencyCode": "WKD",
+            "phoneCode": "+999"
+        }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, _, body) = get_raw(&app, "/codes").await;
+    assert_eq!(status, StatusCode::OK);
+    let table: CodesResponse = serde_json::from_slice(&body).unwrap();
+    let wakanda = table
+        .codes
+        .iter()
+        .find(|entry| entry.country == "wakanda")
+        .unwrap();
+    assert_eq!(wakanda.alpha2, "WK");
+    assert_eq!(wakanda.alpha3, None);
+    assert_eq!(wakanda.numeric, None);
+
+    let (status, _, body) = get_raw(&app, "/codes?format=csv").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(std::str::from_utf8(&body)
+        .unwrap()
+        .contains("\nwakanda,WK,,\n"));
+}
+
+const API_KEYS_FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/api_keys.toml");
+
+fn api_keys_app() -> Router {
+    create_app_with(
+        AppConfig {
+            api_keys: Some(ApiKeys::load(API_KEYS_FIXTURE.as_ref()).unwrap()),
+            ..AppConfig::default()
+        },
+        Arc::new(StaticSource::new(builtin::dataset())),
+    )
+}
+
+async fn get_with_key(app: &Router, key: Option<&str>) -> (StatusCode, HeaderMap, Bytes) {
+    let headers: Vec<_> = key.map(|key| ("x-api-key", key)).into_iter().collect();
+    get_raw_with(app, "/getCountry?based=japan", &headers).await
+}
+
+fn error_code(body: &[u8]) -> String {
+    let envelope: ErrorEnvelope = serde_json::from_slice(body).unwrap();
+    envelope.error.code
+}
+
+#[tokio::test]
+async fn test_api_keys_are_limited_by_their_tier() {
+    let app = api_keys_app();
+
+    // Each response counts down the key's own window
+    for remaining in ["4", "3", "2", "1", "0"] {
+        let (status, headers, _) = get_with_key(&app, Some("k_partner_0001")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["x-ratelimit-limit"], "5");
+        assert_eq!(headers["x-ratelimit-remaining"], remaining);
+        let reset: u64 = headers["x-ratelimit-reset"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=60).contains(&reset), "{}", reset);
+    }
+    let (status, headers, body) = get_with_key(&app, Some("k_partner_0001")).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(headers["x-ratelimit-remaining"], "0");
+    assert!(headers.contains_key("retry-after"));
+    assert_eq!(error_code(&body), "RATE_LIMITED");
+
+    // The free key has its own, smaller window, untouched by the partner's
+    for remaining in ["1", "0"] {
+        let (status, headers, _) = get_with_key(&app, Some("k_free_0001")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["x-ratelimit-limit"], "2");
+        assert_eq!(headers["x-ratelimit-remaining"], remaining);
+    }
+    let (status, _, body) = get_with_key(&app, Some("k_free_0001")).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(error_code(&body), "RATE_LIMITED");
+
+    let (status, _, body) = get_with_key(&app, Some("k_unknown")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(error_code(&body), "INVALID_API_KEY");
+
+    // Without an anonymous tier, requests without a key are not limited by key
+    let (status, headers, _) = get_with_key(&app, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!headers.contains_key("x-ratelimit-limit"));
+}
+
+#[tokio::test]
+async fn test_api_key_quota_exhausted() {
+    let app = api_keys_app();
+
+    for _ in 0..3 {
+        let (status, _, _) = get_with_key(&app, Some("k_trial_0001")).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (status, headers, body) = get_with_key(&app, Some("k_trial_0001")).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = headers["retry-after"].to_str().unwrap().parse().unwrap();
+    assert!((1..=86_400).contains(&retry_after), "{}", retry_after);
+    assert_eq!(error_code(&body), "QUOTA_EXHAUSTED");
+
+    // Health checks are never counted
+    let (status, _, _) = get_raw_with(&app, "/healthz", &[("x-api-key", "k_trial_0001")]).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_requests_without_a_key_are_limited_by_address() {
+    let mut keys = std::fs::read_to_string(API_KEYS_FIXTURE).unwrap();
+    keys.push_str("\n[tiers.anonymous]\nrequests-per-minute = 1\n");
+    let path = std::env::temp_dir().join(format!("api-keys-{}.toml", std::process::id()));
+    std::fs::write(&path, keys).unwrap();
+    let loaded = ApiKeys::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    let app = create_app_with(
+        AppConfig {
+            api_keys: Some(loaded.unwrap()),
+            ..AppConfig::default()
+        },
+        Arc::new(StaticSource::new(builtin::dataset())),
+    );
+    let from = |address: [u8; 4]| {
+        app.clone()
+            .layer(MockConnectInfo(SocketAddr::from((address, 443))))
+    };
+
+    let (status, headers, _) = get_with_key(&from([198, 51, 100, 1]), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["x-ratelimit-limit"], "1");
+    let (status, _, _) = get_with_key(&from([198, 51, 100, 1]), None).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    let (status, _, _) = get_with_key(&from([198, 51, 100, 2]), None).await;
+    assert_eq!(status, StatusCode::OK);
+    // A key is counted apart from the address it is sent from
+    let (status, _, _) = get_with_key(&from([198, 51, 100, 1]), Some("k_free_0001")).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+fn audit_log_path(test: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "rusty_currency_audit_{}_{}.jsonl",
+        test,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn audit_records(path: &std::path::Path) -> Vec<AuditRecord> {
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+async fn send_admin(
+    app: &Router,
+    method: &str,
+    uri: &str,
+    body: serde_json::Value,
+) -> Response<Body> {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("authorization", ADMIN_AUTHORIZATION)
+                .header("content-type", "application/json")
+                .header("x-request-id", format!("{} {}", method, uri))
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_audit_log_records_admin_changes_and_reloads_in_order() {
+    let path = audit_log_path("order");
+    let state = AppState::new(builtin::dataset()).with_audit_log(AuditLog::open(&path).unwrap());
+    let app = create_app_with_state(state.clone(), &admin_config());
+    let wakanda = |currency: &str| {
+        serde_json::json!({
+            "name": "Wakanda",
+            "flag": "🇼🇰",
+            "currencyCode": currency,
+            "phoneCode": "+999"
+        })
+    };
+
+    let response = send_admin(&app, "POST", "/admin/countries", wakanda("WKD")).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = send_admin(&app, "PUT", "/admin/countries/wakanda", wakanda("VBN")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = send_admin(
+        &app,
+        "DELETE",
+        "/admin/countries/japan",
+        serde_json::json!({}),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    // A change that fails is not recorded
+    let response = send_admin(
+        &app,
+        "DELETE",
+        "/admin/countries/atlantis",
+        serde_json::json!({}),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    reload::reload_dataset(&state, &BuiltinLoader).unwrap();
+
+    let records = audit_records(&path);
+    std::fs::remove_file(&path).unwrap();
+    let actions: Vec<_> = records.iter().map(|record| record.action).collect();
+    assert_eq!(
+        actions,
+        [
+            AuditAction::Add,
+            AuditAction::Update,
+            AuditAction::Delete,
+            AuditAction::Reload
+        ]
+    );
+    assert!(records
+        .windows(2)
+        .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+
+    let added = &records[0];
+    assert_eq!(added.principal, "admin");
+    assert_eq!(added.request_id.as_deref(), Some("POST /admin/countries"));
+    assert_eq!(added.country.as_deref(), Some("wakanda"));
+    assert_eq!(added.before, None);
+    assert_eq!(added.after.as_ref().unwrap().currency_code, "WKD");
+
+    let updated = &records[1];
+    assert_eq!(
+        updated.request_id.as_deref(),
+        Some("PUT /admin/countries/wakanda")
+    );
+    assert_eq!(updated.before.as_ref().unwrap().currency_code, "WKD");
+    assert_eq!(updated.after.as_ref().unwrap().currency_code, "VBN");
+
+    let deleted = &records[2];
+    assert_eq!(deleted.country.as_deref(), Some("japan"));
+    assert_eq!(deleted.before.as_ref().unwrap().currency_code, "JPY");
+    assert_eq!(deleted.after, None);
+
+    let reloaded = &records[3];
+    assert_eq!(reloaded.principal, "server");
+    assert_eq!(reloaded.request_id, None);
+    assert_eq!(
+        reloaded.source.as_deref(),
+        Some(BuiltinLoader.describe().as_str())
+    );
+    let changes = reloaded.changes.as_ref().unwrap();
+    assert_eq!(changes.added, ["japan"]);
+    assert_eq!(changes.removed, ["wakanda"]);
+    assert!(changes.changed.is_empty());
+}
+
+#[tokio::test]
+async fn test_audit_log_records_the_basic_auth_username() {
+    let path = audit_log_path("basic");
+    let state = AppState::new(builtin::dataset()).with_audit_log(AuditLog::open(&path).unwrap());
+    let app = create_app_with_state(state, &basic_admin_config(Some(ADMIN_TOKEN)));
+    let delete = |name: &str, authorization: String| {
+        Request::builder()
+            .method("DELETE")
+            .uri(format!("/admin/countries/{}", name))
+            .header("authorization", authorization)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let basic = basic_authorization(ADMIN_USER, ADMIN_PASSWORD);
+    let response = app.clone().oneshot(delete("japan", basic)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let bearer = ADMIN_AUTHORIZATION.to_string();
+    let response = app.oneshot(delete("france", bearer)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let records = audit_records(&path);
+    std::fs::remove_file(&path).unwrap();
+    let principals: Vec<_> = records
+        .iter()
+        .map(|record| record.principal.as_str())
+        .collect();
+    assert_eq!(principals, [ADMIN_USER, "admin"]);
+}
+
+// Writes to /dev/full always fail, as they would on a full or broken disk
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn test_admin_change_fails_when_the_audit_log_cannot_be_written() {
+    let audit_log = AuditLog::open(std::path::Path::new("/dev/full")).unwrap();
+    let state = AppState::new(builtin::dataset()).with_audit_log(audit_log);
+    let app = create_app_with_state(state.clone(), &admin_config());
+
+    let response = send_admin(
+        &app,
+        "DELETE",
+        "/admin/countries/japan",
+        serde_json::json!({}),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(error_code(&body), "AUDIT_LOG_FAILED");
+    let (_, body) = get_json(&app, "/getCountry?based=japan").await;
+    assert_eq!(body["results"].as_array().unwrap().len(), 1);
+
+    let error = reload::reload_dataset(&state, &BuiltinLoader).unwrap_err();
+    assert!(matches!(error, LoadError::Audit(_)), "{}", error);
+}
+
+#[test]
+fn test_audit_log_cannot_open_an_unwritable_path() {
+    let path = std::env::temp_dir()
+        .join("rusty_currency_no_such_directory")
+        .join("audit.jsonl");
+    let error = AuditLog::open(&path).unwrap_err();
+    assert!(error.to_string().contains("audit.jsonl"), "{}", error);
+}
@@ -0,0 +1,230 @@
+//! The `rusty_currency` binary's offline commands, run as a separate process.
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+fn rusty_currency(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rusty_currency"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_lookup_exits_zero_when_every_name_matches() {
+    let output = rusty_currency(&["lookup", "japan", "united states"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("COUNTRY"));
+    assert_eq!(stdout.lines().count(), 3);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_lookup_exits_one_and_lists_unmatched_names() {
+    let output = rusty_currency(&["lookup", "--json", "japan", "atlantis"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    let body: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(body["results"][0]["currencyCode"], "JPY");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Unknown country: atlantis\n"
+    );
+}
+
+#[test]
+fn test_lookup_requires_a_name() {
+    let output = rusty_currency(&["lookup"]);
+
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn test_dump_prints_the_builtin_dataset() {
+    let output = rusty_currency(&["dump", "--format", "yaml"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let body: serde_json::Value = serde_yaml::from_slice(&output.stdout).unwrap();
+    assert!(body["countries"].as_array().unwrap().len() >= 240);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_dump_exits_one_when_the_data_file_is_missing() {
+    let output = rusty_currency(&["dump", "--data-file", "/nonexistent/countries.json"]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(!output.stderr.is_empty());
+}
+
+fn completions(shell: &str) -> String {
+    let output = rusty_currency(&["completions", shell]);
+    assert_eq!(output.status.code(), Some(0), "{}", shell);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_completions_cover_every_subcommand() {
+    let script = completions("bash");
+
+    for subcommand in [
+        "serve",
+        "lookup",
+        "dump",
+        "completions",
+        "hash-password",
+        "migrate",
+    ] {
+        assert!(
+            script.contains(subcommand),
+            "{} is not completed",
+            subcommand
+        );
+    }
+    assert!(script.contains("--data-file"));
+}
+
+#[test]
+fn test_completions_are_generated_for_each_shell() {
+    for shell in ["bash", "zsh", "fish"] {
+        assert!(completions(shell).contains("rusty_currency"), "{}", shell);
+    }
+    assert_eq!(
+        rusty_currency(&["completions", "tcsh"]).status.code(),
+        Some(2)
+    );
+}
+
+#[test]
+fn test_completions_offer_country_names_with_spaces_escaped() {
+    assert!(completions("zsh").contains(r"united\ states\:"));
+    let fish = completions("fish");
+    assert!(fish.contains(r"united\ states\t"));
+    assert!(fish.contains(r"cocos\ \(keeling\)\ islands"));
+
+    // Only offered: names outside the built-in dataset are still looked up
+    let output = rusty_currency(&["lookup", "atlantis"]);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_help_lists_the_environment_variable_of_each_flag() {
+    let output = rusty_currency(&["serve", "--help"]);
+    let help = String::from_utf8(output.stdout).unwrap();
+
+    for (flag, variable) in [
+        ("--data-file", "RUSTINCOUNTRY_DATA_FILE"),
+        ("--data-url", "RUSTINCOUNTRY_DATA_URL"),
+        ("--refresh-interval", "RUSTINCOUNTRY_REFRESH_INTERVAL"),
+        ("--enable-admin", "RUSTINCOUNTRY_ENABLE_ADMIN"),
+        ("--admin-token", "RUSTINCOUNTRY_ADMIN_TOKEN"),
+        ("--state-dir", "RUSTINCOUNTRY_STATE_DIR"),
+        ("--response-cache", "RUSTINCOUNTRY_RESPONSE_CACHE"),
+        ("--rate-limit", "RUSTINCOUNTRY_RATE_LIMIT"),
+        ("--rates-url", "RUSTINCOUNTRY_RATES_URL"),
+        ("--rates-ttl", "RUSTINCOUNTRY_RATES_TTL"),
+        ("--webhook-secret", "RUSTINCOUNTRY_WEBHOOK_SECRET"),
+        ("--geoip-db", "RUSTINCOUNTRY_GEOIP_DB"),
+        ("--disable-docs", "RUSTINCOUNTRY_DISABLE_DOCS"),
+    ] {
+        // Each flag is followed by its description, which ends with the variable
+        let entry = help
+            .split("\n      --")
+            .find(|entry| entry.starts_with(&flag[2..]))
+            .unwrap_or_else(|| panic!("{} is not in the help", flag));
+        assert!(
+            entry.contains(&format!("[env: {}", variable)),
+            "{} does not mention {}",
+            flag,
+            variable
+        );
+    }
+}
+
+// The binary only exists with the default `server` feature; this fails to build if the
+// feature split ever leaves it out
+#[test]
+fn test_server_binary_is_built_with_default_features() {
+    let output = rusty_currency(&["--help"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("serve"));
+    assert!(stdout.contains("--listen"));
+}
+
+#[test]
+fn test_serve_reads_options_from_a_config_file() {
+    let path =
+        std::env::temp_dir().join(format!("rusty_currency_config_{}.toml", std::process::id()));
+    std::fs::write(&path, "check = true\ncolour = \"blue\"\n").unwrap();
+
+    let output = rusty_currency(&["--config", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("name no option: colour"), "{}", stdout);
+}
+
+#[test]
+fn test_serve_exits_one_on_an_invalid_config_file() {
+    let path = std::env::temp_dir().join(format!(
+        "rusty_currency_bad_config_{}.toml",
+        std::process::id()
+    ));
+    std::fs::write(&path, "rate-limit = \"lots\"\n").unwrap();
+
+    let output = rusty_currency(&["--config", path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("Error: Invalid config file"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("'lots'"), "{}", stderr);
+}
+
+#[test]
+fn test_serve_self_test_reports_every_check_passing() {
+    let output = rusty_currency(&["serve", "--self-test"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("PASS  single lookup"));
+    assert!(stdout.ends_with("6 checks, 6 passed, 0 failed\n"));
+}
+
+#[test]
+fn test_hash_password_prints_an_argon2_hash_of_stdin() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rusty_currency"))
+        .arg("hash-password")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"correct horse\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let hash = stdout.trim_end();
+    assert!(hash.starts_with("$argon2id$"), "{}", hash);
+    let basic =
+        rusty_currency::admin_auth::BasicAuth::new(String::from("ops"), hash.to_string()).unwrap();
+    assert!(basic.verify("ops", "correct horse"));
+}
@@ -0,0 +1,90 @@
+//! The typed client against the real router, served on an ephemeral local port.
+
+use reqwest::StatusCode;
+use rusty_currency::{
+    builtin,
+    client::{Client, ClientError},
+    source::MutableSource,
+    test_util::{spawn_test_server, TestServer},
+    AppConfig, Dataset,
+};
+
+// Serves the built-in data in the background until the returned server is dropped
+async fn serve() -> TestServer {
+    spawn_test_server(AppConfig::default(), MutableSource::new(Dataset::builtin())).await
+}
+
+#[tokio::test]
+async fn test_get_countries() {
+    let server = serve().await;
+    let client = Client::new(server.url());
+
+    let response = client
+        .get_countries(&["japan", "united states", "atlantis"])
+        .await
+        .unwrap();
+
+    let codes: Vec<_> = response
+        .results
+        .iter()
+        .map(|info| info.currency_code.as_str())
+        .collect();
+    assert_eq!(codes, ["JPY", "USD"]);
+    assert_eq!(response.results[1].country, "united states");
+}
+
+#[tokio::test]
+async fn test_list_all() {
+    let server = serve().await;
+    let client = Client::new(&format!("{}/", server.url()));
+
+    let response = client.list_all().await.unwrap();
+
+    assert_eq!(response.results.len(), builtin::dataset().len());
+    assert!(response
+        .results
+        .iter()
+        .any(|info| info.country == "japan" && info.currency_code == "JPY"));
+}
+
+#[tokio::test]
+async fn test_health() {
+    let server = serve().await;
+    let client = Client::new(server.url());
+
+    let health = client.health().await.unwrap();
+
+    assert_eq!(health.status, "ok");
+}
+
+#[tokio::test]
+async fn test_non_success_status_carries_the_error_envelope() {
+    let server = serve().await;
+    let client = Client::new(&format!("{}/geo", server.url()));
+
+    let error = client.health().await.unwrap_err();
+
+    match error {
+        ClientError::Status {
+            status,
+            error: Some(envelope),
+        } => {
+            assert_eq!(status, StatusCode::NOT_FOUND);
+            assert_eq!(envelope.error.code, "NOT_FOUND");
+            assert_eq!(envelope.error.details.unwrap()["path"], "/geo/healthz");
+        }
+        other => panic!("expected a 404 with an error envelope, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_connection_failure_is_a_transport_error() {
+    // Nothing listens on the port once the server has shut down
+    let server = serve().await;
+    let client = Client::new(server.url());
+    server.shutdown().await.unwrap();
+
+    let error = client.get_countries(&["japan"]).await.unwrap_err();
+
+    assert!(matches!(error, ClientError::Transport(_)), "{:?}", error);
+}
@@ -0,0 +1,58 @@
+//! The C functions called as a C caller would, through raw pointers. Also run under
+//! Miri to check the allocation and free paths:
+//! `cargo +nightly miri test --no-default-features --features ffi --test ffi`
+
+use rusty_currency::ffi::{ric_free, ric_lookup_json};
+use std::{
+    ffi::{c_char, CStr},
+    ptr,
+};
+
+// The JSON for `name`, freed with `ric_free` before returning
+fn lookup(name: &CStr) -> Option<serde_json::Value> {
+    unsafe {
+        let json = ric_lookup_json(name.as_ptr());
+        if json.is_null() {
+            return None;
+        }
+        let value = serde_json::from_slice(CStr::from_ptr(json).to_bytes()).unwrap();
+        ric_free(json);
+        Some(value)
+    }
+}
+
+#[test]
+fn test_lookup_json_returns_the_country() {
+    let info = lookup(c"Japan").unwrap();
+
+    assert_eq!(info["country"], "Japan");
+    assert_eq!(info["currencyCode"], "JPY");
+    assert_eq!(info["phoneCode"], "+81");
+}
+
+#[test]
+fn test_lookup_json_normalizes_the_name() {
+    let info = lookup(c"  UNITED   states ").unwrap();
+
+    assert_eq!(info["currencyCode"], "USD");
+}
+
+#[test]
+fn test_lookup_json_returns_null_for_unknown_country() {
+    assert_eq!(lookup(c"Atlantis"), None);
+}
+
+#[test]
+fn test_lookup_json_returns_null_for_invalid_utf8() {
+    assert_eq!(lookup(c"Jap\xffan"), None);
+}
+
+#[test]
+fn test_lookup_json_returns_null_for_null_name() {
+    assert!(unsafe { ric_lookup_json(ptr::null()) }.is_null());
+}
+
+#[test]
+fn test_free_accepts_null() {
+    unsafe { ric_free(ptr::null_mut::<c_char>()) };
+}
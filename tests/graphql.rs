@@ -0,0 +1,136 @@
+//! The GraphQL schema, executed directly against the built-in dataset.
+
+use async_graphql::{value, Request, Response};
+use rusty_currency::{builtin, graphql, AppState};
+
+async fn execute(query: &str) -> Response {
+    graphql::schema()
+        .execute(Request::new(query).data(AppState::new(builtin::dataset())))
+        .await
+}
+
+#[tokio::test]
+async fn test_country() {
+    let response = execute(
+        r#"{
+            country(name: " Japan ") {
+                name
+                canonicalName
+                localizedName(lang: "ja")
+                flag
+                phoneCode
+                currency { code }
+                geo { alpha2 alpha3 numeric }
+            }
+        }"#,
+    )
+    .await;
+
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        value!({
+            "country": {
+                "name": "Japan",
+                "canonicalName": "japan",
+                "localizedName": "日本",
+                "flag": "🇯🇵",
+                "phoneCode": "+81",
+                "currency": { "code": "JPY" },
+                "geo": { "alpha2": "JP", "alpha3": "JPN", "numeric": "392" },
+            }
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_country_unknown_is_null() {
+    let response = execute(r#"{ country(name: "atlantis") { flag } }"#).await;
+
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(response.data, value!({ "country": null }));
+}
+
+#[tokio::test]
+async fn test_countries_keeps_order_and_skips_unknown() {
+    let response =
+        execute(r#"{ countries(names: ["usa", "atlantis", "germany"]) { currency { code } } }"#)
+            .await;
+
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        value!({
+            "countries": [
+                { "currency": { "code": "USD" } },
+                { "currency": { "code": "EUR" } },
+            ]
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_countries_without_names_fails() {
+    let response = execute(r#"{ countries(names: [" "]) { flag } }"#).await;
+
+    assert_eq!(response.errors.len(), 1);
+}
+
+#[tokio::test]
+async fn test_all_countries_filtered() {
+    let response =
+        execute(r#"{ allCountries(filter: { currencyCode: "CHF" }) { name currency { code } } }"#)
+            .await;
+
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        value!({
+            "allCountries": [
+                { "name": "liechtenstein", "currency": { "code": "CHF" } },
+                { "name": "switzerland", "currency": { "code": "CHF" } },
+            ]
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_all_countries_unfiltered_lists_every_country() {
+    let response = execute("{ allCountries { name } }").await;
+
+    let data = response.data.into_json().unwrap();
+    assert_eq!(
+        data["allCountries"].as_array().unwrap().len(),
+        builtin::dataset().len()
+    );
+}
+
+#[tokio::test]
+async fn test_all_countries_rejects_malformed_currency_code() {
+    let response = execute(r#"{ allCountries(filter: { currencyCode: "euro" }) { name } }"#).await;
+
+    assert_eq!(response.errors.len(), 1);
+}
+
+#[tokio::test]
+async fn test_currency_countries() {
+    let response =
+        execute(r#"{ country(name: "ecuador") { currency { code countries { name } } } }"#).await;
+
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let data = response.data.into_json().unwrap();
+    let countries = data["country"]["currency"]["countries"].as_array().unwrap();
+    assert!(countries
+        .iter()
+        .any(|country| country["name"] == "united states"));
+}
+
+#[tokio::test]
+async fn test_deep_nesting_is_rejected() {
+    let response = execute(
+        r#"{ allCountries { currency { countries { currency { countries { name } } } } } }"#,
+    )
+    .await;
+
+    assert_eq!(response.errors.len(), 1);
+}
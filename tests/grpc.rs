@@ -0,0 +1,192 @@
+//! The gRPC service through a tonic client, against a server on an ephemeral local port.
+
+use rusty_currency::{
+    builtin, create_app,
+    grpc::{
+        self,
+        proto::{
+            country_service_client::CountryServiceClient, GetByCurrencyRequest, GetCountryRequest,
+            ListCountriesRequest,
+        },
+    },
+    run_server_with_grpc, AppState,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use tonic::{transport::Channel, Code};
+
+// Serves the built-in dataset in the background and connects a client to it
+async fn connect() -> CountryServiceClient<Channel> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(grpc::serve(listener, AppState::new(builtin::dataset())));
+    CountryServiceClient::connect(format!("http://{}", address))
+        .await
+        .unwrap()
+}
+
+fn get_country(names: &[&str]) -> GetCountryRequest {
+    GetCountryRequest {
+        names: names.iter().map(|name| name.to_string()).collect(),
+        lang: String::new(),
+    }
+}
+
+#[tokio::test]
+async fn test_get_country_single() {
+    let mut client = connect().await;
+
+    let results = client
+        .get_country(get_country(&["japan"]))
+        .await
+        .unwrap()
+        .into_inner()
+        .results;
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].country, "japan");
+    assert_eq!(results[0].localized_name, "Japan");
+    assert_eq!(results[0].flag, "🇯🇵");
+    assert_eq!(results[0].currency_code, "JPY");
+    assert_eq!(results[0].phone_code, "+81");
+}
+
+#[tokio::test]
+async fn test_get_country_batch_keeps_order_and_skips_unknown() {
+    let mut client = connect().await;
+
+    let results = client
+        .get_country(get_country(&["usa", "atlantis", " Germany "]))
+        .await
+        .unwrap()
+        .into_inner()
+        .results;
+
+    let codes: Vec<_> = results
+        .iter()
+        .map(|country| &*country.currency_code)
+        .collect();
+    assert_eq!(codes, ["USD", "EUR"]);
+    assert_eq!(results[1].country, "Germany");
+}
+
+#[tokio::test]
+async fn test_get_country_unknown_is_empty() {
+    let mut client = connect().await;
+
+    let results = client
+        .get_country(get_country(&["atlantis"]))
+        .await
+        .unwrap()
+        .into_inner()
+        .results;
+
+    assert!(results.is_empty());
+}
+
+#[tokio::test]
+async fn test_get_country_without_names_is_invalid() {
+    let mut client = connect().await;
+
+    let status = client.get_country(get_country(&[" "])).await.unwrap_err();
+
+    assert_eq!(status.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_get_country_localized() {
+    let mut client = connect().await;
+
+    let results = client
+        .get_country(GetCountryRequest {
+            names: vec![String::from("germany")],
+            lang: String::from("de"),
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .results;
+
+    assert_eq!(results[0].localized_name, "Deutschland");
+}
+
+#[tokio::test]
+async fn test_list_countries() {
+    let mut client = connect().await;
+
+    let results = client
+        .list_countries(ListCountriesRequest::default())
+        .await
+        .unwrap()
+        .into_inner()
+        .results;
+
+    assert_eq!(results.len(), builtin::dataset().len());
+    assert!(results
+        .windows(2)
+        .all(|pair| pair[0].country < pair[1].country));
+}
+
+#[tokio::test]
+async fn test_get_by_currency() {
+    let mut client = connect().await;
+
+    let results = client
+        .get_by_currency(GetByCurrencyRequest {
+            currency_code: String::from("EUR"),
+            lang: String::new(),
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .results;
+
+    assert!(results.iter().any(|country| country.country == "germany"));
+    assert!(results.iter().all(|country| country.currency_code == "EUR"));
+}
+
+#[tokio::test]
+async fn test_get_by_currency_rejects_malformed_code() {
+    let mut client = connect().await;
+
+    let status = client
+        .get_by_currency(GetByCurrencyRequest {
+            currency_code: String::from("euro"),
+            lang: String::new(),
+        })
+        .await
+        .unwrap_err();
+
+    assert_eq!(status.code(), Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_run_server_serves_http_and_grpc_together() {
+    let http = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let grpc = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let (http_address, grpc_address) = (http.local_addr().unwrap(), grpc.local_addr().unwrap());
+    let state = AppState::new(builtin::dataset());
+    tokio::spawn(run_server_with_grpc(vec![http], create_app(), grpc, state));
+
+    let mut client = CountryServiceClient::connect(format!("http://{}", grpc_address))
+        .await
+        .unwrap();
+    let results = client
+        .get_country(get_country(&["japan"]))
+        .await
+        .unwrap()
+        .into_inner()
+        .results;
+    assert_eq!(results[0].currency_code, "JPY");
+
+    let mut stream = tokio::net::TcpStream::connect(http_address).await.unwrap();
+    stream
+        .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+}
@@ -0,0 +1,113 @@
+//! The Redis cache, against an address nothing listens on and, when `REDIS_URL` is set,
+//! a real server: `REDIS_URL=redis://127.0.0.1/ cargo test --features redis -- --ignored`.
+
+use rusty_currency::{
+    builtin,
+    cache::{Cache, MemoryCache, RedisCache},
+    codes::CurrencyCode,
+    health,
+    rates::{RateCache, StaticRateProvider},
+    AppState, HealthStatus,
+};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// Port 1 refuses connections, as a Redis that is down would
+const UNREACHABLE: &str = "redis://127.0.0.1:1/";
+
+fn jpy() -> CurrencyCode {
+    CurrencyCode::new("JPY").unwrap()
+}
+
+fn redis_url() -> String {
+    std::env::var("REDIS_URL").expect("REDIS_URL names the Redis server to test against")
+}
+
+// A key prefix no other run uses, so runs do not see each other's entries
+fn unique_prefix() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    format!("rustincountry-test:{}:", nanos)
+}
+
+#[tokio::test]
+async fn unreachable_redis_falls_back_to_memory() {
+    let redis = RedisCache::connect(UNREACHABLE, "test:", Arc::new(MemoryCache::new(8))).unwrap();
+    assert!(!redis.ping().await);
+
+    redis
+        .set("key", "value".into(), Duration::from_secs(60))
+        .await;
+    assert_eq!(redis.get("key").await.as_deref(), Some(&b"value"[..]));
+    assert_eq!(redis.get("other").await, None);
+}
+
+#[tokio::test]
+async fn rates_are_still_cached_without_redis() {
+    let provider = Arc::new(StaticRateProvider::new([(jpy(), 0.0067)]));
+    let store = RedisCache::connect(UNREACHABLE, "test:", Arc::new(MemoryCache::new(8))).unwrap();
+    let rates =
+        RateCache::new(provider.clone(), Duration::from_secs(60)).with_store(Arc::new(store));
+
+    assert_eq!(rates.rate_to_usd(jpy()).await, Ok(0.0067));
+    assert_eq!(rates.rate_to_usd(jpy()).await, Ok(0.0067));
+    assert_eq!(provider.calls(), 1);
+}
+
+#[tokio::test]
+async fn unreachable_redis_degrades_health() {
+    let redis = RedisCache::connect(UNREACHABLE, "test:", Arc::new(MemoryCache::new(0))).unwrap();
+    let state = AppState::new(builtin::dataset()).with_dependency("redis", Arc::new(redis));
+
+    let (status, details) = health::report(&state).await;
+    assert_eq!(status, HealthStatus::Degraded);
+    let redis = &details.components["redis"];
+    assert_eq!(redis.status, HealthStatus::Degraded);
+    assert!(!redis.required);
+}
+
+#[tokio::test]
+async fn malformed_url_is_rejected() {
+    assert!(RedisCache::connect("not a url", "test:", Arc::new(MemoryCache::new(8))).is_err());
+}
+
+#[tokio::test]
+#[ignore = "needs a Redis server at REDIS_URL"]
+async fn replicas_share_entries_through_redis() {
+    let prefix = unique_prefix();
+    let connect = || RedisCache::connect(&redis_url(), &prefix, Arc::new(MemoryCache::new(0)));
+    let (first, second) = (connect().unwrap(), connect().unwrap());
+    assert!(first.ping().await);
+
+    first
+        .set("key", "value".into(), Duration::from_secs(60))
+        .await;
+    assert_eq!(second.get("key").await.as_deref(), Some(&b"value"[..]));
+
+    first
+        .set("short", "value".into(), Duration::from_millis(50))
+        .await;
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(second.get("short").await, None);
+}
+
+#[tokio::test]
+#[ignore = "needs a Redis server at REDIS_URL"]
+async fn replicas_share_rates_through_redis() {
+    let prefix = unique_prefix();
+    let provider = Arc::new(StaticRateProvider::new([(jpy(), 0.0067)]));
+    let replica = || {
+        let store =
+            RedisCache::connect(&redis_url(), &prefix, Arc::new(MemoryCache::new(0))).unwrap();
+        RateCache::new(provider.clone(), Duration::from_secs(60)).with_store(Arc::new(store))
+    };
+    let (first, second) = (replica(), replica());
+
+    assert_eq!(first.rate_to_usd(jpy()).await, Ok(0.0067));
+    assert_eq!(second.rate_to_usd(jpy()).await, Ok(0.0067));
+    assert_eq!(provider.calls(), 1);
+}
@@ -0,0 +1,106 @@
+//! `test_util::spawn_test_server`, hit over real TCP with reqwest as a downstream crate would.
+
+use reqwest::StatusCode;
+use rusty_currency::{
+    shutdown::DrainOutcome,
+    source::{MutableSource, StaticSource},
+    test_util::spawn_test_server,
+    AppConfig, CountryResponse, Dataset,
+};
+use std::time::Duration;
+
+#[tokio::test]
+async fn test_spawn_test_server_serves_the_source() {
+    let mut dataset = Dataset::builtin();
+    dataset.remove("japan").unwrap();
+    let server = spawn_test_server(AppConfig::default(), StaticSource::new(dataset)).await;
+
+    assert!(server.address().ip().is_loopback());
+    assert_ne!(server.address().port(), 0);
+    assert_eq!(server.url(), format!("http://{}", server.address()));
+    let response: CountryResponse =
+        reqwest::get(format!("{}/getCountry?based=japan,france", server.url()))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+    let countries: Vec<_> = response
+        .results
+        .iter()
+        .map(|info| info.country.as_ref())
+        .collect();
+    assert_eq!(countries, ["france"]);
+}
+
+#[tokio::test]
+async fn test_spawn_test_server_applies_the_config() {
+    let config = AppConfig {
+        admin_token: Some(String::from("test-admin-token")),
+        ..AppConfig::default()
+    };
+    let server = spawn_test_server(config, MutableSource::new(Dataset::builtin())).await;
+    let client = reqwest::Client::new();
+    let export = format!("{}/admin/export", server.url());
+
+    let response = client.get(&export).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client
+        .get(&export)
+        .bearer_auth("test-admin-token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_test_servers_run_side_by_side() {
+    let first =
+        spawn_test_server(AppConfig::default(), StaticSource::new(Dataset::builtin())).await;
+    let second =
+        spawn_test_server(AppConfig::default(), StaticSource::new(Dataset::builtin())).await;
+
+    assert_ne!(first.address(), second.address());
+    for server in [&first, &second] {
+        let response = reqwest::get(format!("{}/healthz", server.url()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn test_test_server_shutdown_drains_and_closes_the_port() {
+    let server =
+        spawn_test_server(AppConfig::default(), StaticSource::new(Dataset::builtin())).await;
+    let url = format!("{}/healthz", server.url());
+    assert!(reqwest::get(&url).await.is_ok());
+
+    assert_eq!(server.shutdown().await.unwrap(), DrainOutcome::Drained);
+
+    assert!(reqwest::get(&url).await.unwrap_err().is_connect());
+}
+
+#[tokio::test]
+async fn test_dropping_a_test_server_closes_the_port() {
+    let server =
+        spawn_test_server(AppConfig::default(), StaticSource::new(Dataset::builtin())).await;
+    let url = format!("{}/healthz", server.url());
+    assert!(reqwest::get(&url).await.is_ok());
+
+    drop(server);
+
+    // The server task is cancelled the next time the runtime gets to it
+    let client = reqwest::Client::builder()
+        .pool_max_idle_per_host(0)
+        .build()
+        .unwrap();
+    for _ in 0..50 {
+        match client.get(&url).send().await {
+            Err(error) if error.is_connect() => return,
+            _ => tokio::time::sleep(Duration::from_millis(10)).await,
+        }
+    }
+    panic!("{} still answers after the server was dropped", url);
+}
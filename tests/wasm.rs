@@ -0,0 +1,37 @@
+//! Runs in a headless JavaScript engine:
+//! `cargo test --target wasm32-unknown-unknown --no-default-features --test wasm`
+//! with `wasm-bindgen-test-runner` as the target's runner.
+
+#![cfg(target_arch = "wasm32")]
+
+use js_sys::JSON;
+use rusty_currency::wasm::{lookup, lookup_many};
+use wasm_bindgen_test::wasm_bindgen_test;
+
+fn stringify(value: &wasm_bindgen::JsValue) -> String {
+    JSON::stringify(value).unwrap().into()
+}
+
+#[wasm_bindgen_test]
+fn looks_up_a_country() {
+    let info = stringify(&lookup("Japan"));
+    assert!(info.contains(r#""country":"Japan""#), "{}", info);
+    assert!(info.contains(r#""currencyCode":"JPY""#), "{}", info);
+}
+
+#[wasm_bindgen_test]
+fn unknown_country_is_null() {
+    assert!(lookup("Atlantis").is_null());
+}
+
+#[wasm_bindgen_test]
+fn looks_up_many_countries() {
+    let body = stringify(&lookup_many("Japan,Atlantis,France"));
+    assert!(
+        body.starts_with(r#"{"results":[{"country":"Japan""#),
+        "{}",
+        body
+    );
+    assert!(body.contains(r#""currencyCode":"EUR""#), "{}", body);
+    assert!(!body.contains("Atlantis"), "{}", body);
+}